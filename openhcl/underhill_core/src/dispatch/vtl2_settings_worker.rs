@@ -1343,6 +1343,7 @@ async fn make_nvme_disk_config(
         nsid: namespace.nsid,
         disk: disk_type,
         read_only: false,
+        zoned: None,
     })
 }
 
@@ -1469,6 +1470,7 @@ async fn make_nvme_controller_config(
             namespaces,
             max_io_queues: 64,
             msix_count: 64,
+            disk_pool: Vec::new(),
             requests: None,
         }
         .into_resource(),
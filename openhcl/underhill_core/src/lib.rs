@@ -429,6 +429,7 @@ async fn launch_workers(
                         } else {
                             debug_worker_defs::TargetArch::Aarch64
                         },
+                        inspect: None,
                     },
                 )
                 .await?,
@@ -374,6 +374,15 @@ unsafe fn submit(
         // SAFETY: caller guarantees the SQE references valid memory.
         unsafe { self.current_driver().initiator().submit(sqe) }
     }
+
+    unsafe fn submit_fixed<'a>(
+        &'a self,
+        sqe: pal_async::io_uring::Entry,
+        buf: &'a pal_async::io_uring::FixedBuffer,
+    ) -> impl Future<Output = io::Result<i32>> + Send + 'a {
+        // SAFETY: caller guarantees the SQE references valid memory.
+        unsafe { self.current_driver().initiator().submit_fixed(sqe, buf) }
+    }
 }
 
 /// The state for the thread pool thread for the currently running CPU.
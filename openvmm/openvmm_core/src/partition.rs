@@ -17,6 +17,7 @@
 use hvdef::Vtl;
 use inspect::Inspect;
 use inspect::InspectMut;
+use memory_range::MemoryRange;
 use pci_core::msi::SignalMsi;
 use std::convert::Infallible;
 use std::sync::Arc;
@@ -103,6 +104,19 @@ fn into_doorbell_registration(
     /// Returns whether partition reset is supported.
     fn supports_reset(&self) -> bool;
 
+    /// Starts or stops tracking guest writes to the RAM in `range`.
+    ///
+    /// Fails if the hypervisor cannot track guest writes.
+    fn set_dirty_log(&self, range: MemoryRange, enable: bool) -> anyhow::Result<()>;
+
+    /// Fetches and clears the dirty bitmap for `range`, as described by
+    /// [`virt::DirtyLog::get_and_clear_dirty_bitmap`].
+    fn get_and_clear_dirty_bitmap(
+        &self,
+        range: MemoryRange,
+        bitmap: &mut [u64],
+    ) -> anyhow::Result<()>;
+
     /// Returns the reference time source.
     fn reference_time_source(&self) -> Option<ReferenceTimeSource>;
 
@@ -247,6 +261,31 @@ fn supports_reset(&self) -> bool {
         self.supports_reset().is_some()
     }
 
+    fn set_dirty_log(&self, range: MemoryRange, enable: bool) -> anyhow::Result<()> {
+        let dirty_log = self
+            .supports_dirty_log()
+            .context("hypervisor does not support dirty page tracking")?;
+        let result = if enable {
+            dirty_log.enable_dirty_log(range)
+        } else {
+            dirty_log.disable_dirty_log(range)
+        };
+        result.with_context(|| format!("failed to update dirty log for {range}"))?;
+        Ok(())
+    }
+
+    fn get_and_clear_dirty_bitmap(
+        &self,
+        range: MemoryRange,
+        bitmap: &mut [u64],
+    ) -> anyhow::Result<()> {
+        self.supports_dirty_log()
+            .context("hypervisor does not support dirty page tracking")?
+            .get_and_clear_dirty_bitmap(range, bitmap)
+            .with_context(|| format!("failed to get dirty bitmap for {range}"))?;
+        Ok(())
+    }
+
     fn reference_time_source(&self) -> Option<ReferenceTimeSource> {
         self.reference_time_source()
     }
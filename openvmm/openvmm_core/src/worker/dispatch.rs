@@ -44,6 +44,10 @@
 use guestmem::GuestMemory;
 use hvdef::HV_PAGE_SIZE;
 use hvdef::Vtl;
+use hyperv_ic_resources::heartbeat::HeartbeatRpc;
+use hyperv_ic_resources::heartbeat::HeartbeatStatus;
+use hyperv_ic_resources::vss::VssResult;
+use hyperv_ic_resources::vss::VssRpc;
 use hypervisor_resources::HypervisorKind;
 use ide_resources::GuestMedia;
 use ide_resources::IdeDeviceConfig;
@@ -58,6 +62,8 @@
 use mesh::error::RemoteError;
 use mesh::payload::Protobuf;
 use mesh::payload::message::ProtobufMessage;
+use mesh::rpc::Rpc;
+use mesh::rpc::RpcSend;
 use mesh_worker::Worker;
 use mesh_worker::WorkerId;
 use mesh_worker::WorkerRpc;
@@ -112,6 +118,7 @@
 use virtio::VirtioMmioDevice;
 use virtio::VirtioPciDevice;
 use virtio::resolve::VirtioResolveInput;
+use virtio_resources::balloon::BalloonRequest;
 use vm_loader::InitialLoad;
 use vm_loader::initial_regs::initial_regs;
 use vm_resource::IntoResource;
@@ -205,6 +212,9 @@ fn from_config(config: Config) -> Self {
             vga_firmware: config.vga_firmware,
             vtl2_gfx: config.vtl2_gfx,
             virtio_devices: config.virtio_devices,
+            balloon_control: config.balloon_control,
+            vss_ic: config.vss_ic,
+            heartbeat_ic: config.heartbeat_ic,
             vmbus: config.vmbus,
             vtl2_vmbus: config.vtl2_vmbus,
             #[cfg(all(windows, feature = "virt_whp"))]
@@ -249,6 +259,9 @@ pub struct Manifest {
     vga_firmware: Option<RomFileLocation>,
     vtl2_gfx: bool,
     virtio_devices: Vec<(VirtioBus, Resource<VirtioDeviceHandle>)>,
+    balloon_control: Option<mesh::Sender<BalloonRequest>>,
+    vss_ic: Option<mesh::Sender<VssRpc>>,
+    heartbeat_ic: Option<mesh::Sender<HeartbeatRpc>>,
     vmbus: Option<VmbusConfig>,
     vtl2_vmbus: Option<VmbusConfig>,
     #[cfg(all(windows, feature = "virt_whp"))]
@@ -801,6 +814,9 @@ struct LoadedVmInner {
     #[cfg_attr(not(guest_arch = "x86_64"), expect(dead_code))]
     pci_legacy_interrupts: Vec<((u8, Option<u8>), u32)>,
     firmware_event_send: Option<mesh::Sender<get_resources::ged::FirmwareEvent>>,
+    balloon_control: Option<mesh::Sender<BalloonRequest>>,
+    vss_ic: Option<mesh::Sender<VssRpc>>,
+    heartbeat_ic: Option<mesh::Sender<HeartbeatRpc>>,
 
     load_mode: LoadMode,
     igvm_file: Option<IgvmFile>,
@@ -2589,6 +2605,7 @@ struct PortInfo {
                 vmm_core::device_builder::build_pcie_device(
                     vmm_core::device_builder::PciDeviceResolveContext {
                         driver_source,
+                        vp_count: processor_topology.vp_count(),
                         resolver,
                         resource: dev_cfg.resource,
                         doorbell_registration: partition
@@ -2774,6 +2791,7 @@ fn make_ids(
                     vmm_core::device_builder::build_vpci_device(
                         vmm_core::device_builder::PciDeviceResolveContext {
                             driver_source: &driver_source,
+                            vp_count: processor_topology.vp_count(),
                             resolver: &resolver,
                             resource: dev_cfg.resource,
                             doorbell_registration: partition
@@ -2915,6 +2933,7 @@ fn make_ids(
                     device,
                     VirtioResolveInput {
                         driver_source: &driver_source,
+                        vp_count: processor_topology.vp_count(),
                     },
                 )
                 .await?;
@@ -3067,6 +3086,9 @@ fn make_ids(
                 chipset_cfg: cfg.chipset,
                 chipset_capabilities: cfg.chipset_capabilities,
                 firmware_event_send: cfg.firmware_event_send,
+                balloon_control: cfg.balloon_control,
+                vss_ic: cfg.vss_ic,
+                heartbeat_ic: cfg.heartbeat_ic,
                 load_mode: cfg.load_mode,
                 virtio_mmio_region,
                 virtio_mmio_irq,
@@ -3646,7 +3668,22 @@ enum Event {
                         rpc.handle(async |()| self.inner.partition_unit.clear_halt().await)
                             .await
                     }
-                    VmRpc::Resume(rpc) => rpc.handle(async |()| self.resume().await).await,
+                    VmRpc::Resume(rpc) => {
+                        rpc.handle(async |()| {
+                            let resumed = self.resume().await;
+                            // A guest frozen for an application consistent
+                            // snapshot must not stay frozen once it runs
+                            // again. The IC ignores this if the guest is not
+                            // frozen.
+                            if resumed {
+                                if let Some(vss_ic) = &self.inner.vss_ic {
+                                    vss_ic.send(VssRpc::Thaw(Rpc::detached(())));
+                                }
+                            }
+                            resumed
+                        })
+                        .await
+                    }
                     VmRpc::Pause(rpc) => rpc.handle(async |()| self.pause().await).await,
                     VmRpc::Save(rpc) => {
                         rpc.handle_failable(async |()| self.save().await.map(ProtobufMessage::new))
@@ -3814,6 +3851,7 @@ enum Event {
                                                 dma_target: &pcie_ctx.dma_target,
                                                 register_mmio,
                                                 driver_source: &self.inner.driver_source,
+                                                vp_count: self.inner.processor_topology.vp_count(),
                                                 doorbell_registration: self.inner.partition.clone().into_doorbell_registration(Vtl::Vtl0),
                                                 shared_mem_mapper: None,
                                             },
@@ -3890,6 +3928,45 @@ enum Event {
                         rpc.handle_failable(async |file| self.dump_state(file).await)
                             .await
                     }
+                    VmRpc::SetDirtyLog(rpc) => {
+                        rpc.handle_failable_sync(|enable| self.set_dirty_log(enable))
+                    }
+                    VmRpc::GetDirtyBitmap(rpc) => {
+                        rpc.handle_failable_sync(|()| self.get_dirty_bitmap())
+                    }
+                    VmRpc::SetBalloonTarget(rpc) => {
+                        rpc.handle_failable(async |target| {
+                            self.balloon_control()?
+                                .call(BalloonRequest::SetTarget, target)
+                                .await
+                                .context("balloon device failed")
+                        })
+                        .await
+                    }
+                    VmRpc::QueryBalloon(rpc) => {
+                        rpc.handle_failable(async |()| {
+                            self.balloon_control()?
+                                .call(BalloonRequest::Query, ())
+                                .await
+                                .context("balloon device failed")
+                        })
+                        .await
+                    }
+                    // Forward these to the ICs rather than waiting on them
+                    // here: the guest may take a while to respond, and VM
+                    // state changes must not be blocked meanwhile.
+                    VmRpc::FreezeGuest(rpc) => match &self.inner.vss_ic {
+                        Some(vss_ic) => vss_ic.send(VssRpc::Freeze(rpc)),
+                        None => rpc.complete(VssResult::NotReady),
+                    },
+                    VmRpc::ThawGuest(rpc) => match &self.inner.vss_ic {
+                        Some(vss_ic) => vss_ic.send(VssRpc::Thaw(rpc)),
+                        None => rpc.complete(VssResult::NotReady),
+                    },
+                    VmRpc::GetHeartbeat(rpc) => match &self.inner.heartbeat_ic {
+                        Some(heartbeat_ic) => heartbeat_ic.send(HeartbeatRpc::GetStatus(rpc)),
+                        None => rpc.complete(HeartbeatStatus::NoContact),
+                    },
                 },
                 Event::Halt(Err(_)) => break,
                 Event::Halt(Ok(reason)) => {
@@ -3904,6 +3981,45 @@ enum Event {
         }
     }
 
+    fn balloon_control(&self) -> anyhow::Result<&mesh::Sender<BalloonRequest>> {
+        self.inner
+            .balloon_control
+            .as_ref()
+            .context("no balloon device")
+    }
+
+    fn set_dirty_log(&self, enable: bool) -> anyhow::Result<()> {
+        for range in self.inner.mem_layout.ram() {
+            self.inner.partition.set_dirty_log(range.range, enable)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the dirty bitmap for RAM, indexed by page offset within RAM.
+    fn get_dirty_bitmap(&self) -> anyhow::Result<Vec<u64>> {
+        let ram = self.inner.mem_layout.ram();
+        let page_count = ram.iter().map(|r| r.range.page_count_4k()).sum::<u64>();
+        let mut bitmap = vec![0u64; page_count.div_ceil(64) as usize];
+        let mut range_bitmap = Vec::new();
+        let mut first_page = 0;
+        for range in ram {
+            let range_pages = range.range.page_count_4k();
+            range_bitmap.clear();
+            range_bitmap.resize(range_pages.div_ceil(64) as usize, 0);
+            self.inner
+                .partition
+                .get_and_clear_dirty_bitmap(range.range, &mut range_bitmap)?;
+            for page in 0..range_pages {
+                if range_bitmap[(page / 64) as usize] & (1 << (page % 64)) != 0 {
+                    let n = first_page + page;
+                    bitmap[(n / 64) as usize] |= 1 << (n % 64);
+                }
+            }
+            first_page += range_pages;
+        }
+        Ok(bitmap)
+    }
+
     fn start_reload_igvm(&mut self, file: &File) -> anyhow::Result<()> {
         // Clear any previously staged IGVM file.
         self.inner.next_igvm_file = None;
@@ -4039,6 +4155,9 @@ async fn serialize(
             vga_firmware: None,     // TODO
             vtl2_gfx: false,        // TODO
             virtio_devices: vec![], // TODO
+            balloon_control: None,  // TODO
+            vss_ic: None,           // TODO
+            heartbeat_ic: None,     // TODO
             #[cfg(all(windows, feature = "virt_whp"))]
             vpci_resources: vec![], // TODO
             vmgs: None,             // TODO
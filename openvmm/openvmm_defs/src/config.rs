@@ -45,6 +45,12 @@ pub struct Config {
     pub vga_firmware: Option<RomFileLocation>,
     pub vtl2_gfx: bool,
     pub virtio_devices: Vec<(VirtioBus, Resource<VirtioDeviceHandle>)>,
+    /// Control channel for the virtio balloon device, if there is one.
+    pub balloon_control: Option<mesh::Sender<virtio_resources::balloon::BalloonRequest>>,
+    /// Control channel for the VSS IC, if there is one.
+    pub vss_ic: Option<mesh::Sender<hyperv_ic_resources::vss::VssRpc>>,
+    /// Control channel for the heartbeat IC, if there is one.
+    pub heartbeat_ic: Option<mesh::Sender<hyperv_ic_resources::heartbeat::HeartbeatRpc>>,
     #[cfg(windows)]
     pub vpci_resources: Vec<virt_whp::device::DeviceHandle>,
     pub vmgs: Option<VmgsResource>,
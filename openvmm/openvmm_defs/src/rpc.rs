@@ -5,6 +5,8 @@
 
 use crate::config::DeviceVtl;
 use guid::Guid;
+use hyperv_ic_resources::heartbeat::HeartbeatStatus;
+use hyperv_ic_resources::vss::VssResult;
 use mesh::CancelContext;
 use mesh::MeshPayload;
 use mesh::error::RemoteError;
@@ -13,6 +15,7 @@
 use mesh::rpc::Rpc;
 use std::fmt;
 use std::fs::File;
+use virtio_resources::balloon::BalloonStatus;
 use vm_resource::Resource;
 use vm_resource::kind::PciDeviceHandleKind;
 use vm_resource::kind::VmbusDeviceHandleKind;
@@ -47,6 +50,34 @@ pub enum VmRpc {
     /// handle to write to (typically a temporary file that gets renamed
     /// into place on success).
     DumpState(FailableRpc<File, ()>),
+    /// Starts (`true`) or stops (`false`) tracking guest processor writes to
+    /// RAM.
+    SetDirtyLog(FailableRpc<bool, ()>),
+    /// Fetches and clears the set of RAM pages written by guest processors
+    /// since tracking started or since the last call.
+    ///
+    /// The result has one bit per 4KiB page, indexed by the page's offset
+    /// within RAM: the guest RAM ranges are laid out back to back in address
+    /// order, matching the layout of the memory backing file. Writes by the
+    /// host, such as device DMA, are not reported.
+    GetDirtyBitmap(FailableRpc<(), Vec<u64>>),
+    /// Sets the target size of the virtio balloon, in bytes.
+    SetBalloonTarget(FailableRpc<u64, ()>),
+    /// Gets the state of the virtio balloon.
+    QueryBalloon(FailableRpc<(), BalloonStatus>),
+    /// Asks the guest to freeze its filesystems via the VSS IC.
+    ///
+    /// The guest stays frozen until [`VmRpc::ThawGuest`]. Completes with
+    /// [`VssResult::NotReady`] if there is no VSS IC or the guest has not
+    /// connected to it.
+    FreezeGuest(Rpc<(), VssResult>),
+    /// Asks the guest to thaw its filesystems after [`VmRpc::FreezeGuest`].
+    ThawGuest(Rpc<(), VssResult>),
+    /// Gets the guest's liveness, as reported by the heartbeat IC.
+    ///
+    /// This is only answered once the guest has opened the heartbeat channel,
+    /// so callers should use a timeout.
+    GetHeartbeat(Rpc<(), HeartbeatStatus>),
 }
 
 #[derive(Debug, MeshPayload, thiserror::Error)]
@@ -83,6 +114,13 @@ fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
             VmRpc::AddPcieDevice(_) => "AddPcieDevice",
             VmRpc::RemovePcieDevice(_) => "RemovePcieDevice",
             VmRpc::DumpState(_) => "DumpState",
+            VmRpc::SetDirtyLog(_) => "SetDirtyLog",
+            VmRpc::GetDirtyBitmap(_) => "GetDirtyBitmap",
+            VmRpc::SetBalloonTarget(_) => "SetBalloonTarget",
+            VmRpc::QueryBalloon(_) => "QueryBalloon",
+            VmRpc::FreezeGuest(_) => "FreezeGuest",
+            VmRpc::ThawGuest(_) => "ThawGuest",
+            VmRpc::GetHeartbeat(_) => "GetHeartbeat",
         };
         f.pad(s)
     }
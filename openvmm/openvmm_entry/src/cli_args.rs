@@ -22,12 +22,18 @@
 use clap::Parser;
 use clap::ValueEnum;
 use cxl_spec::spec::CfmwsWindowRestrictions;
+use disk_backend_resources::NbdAddress;
 use guid::Guid;
+use net_backend_resources::consomme::EgressPolicyConfig;
+use net_backend_resources::consomme::EgressRuleConfig;
+use net_backend_resources::consomme::HostPortProtocol;
+use net_backend_resources::impair::ImpairmentConfig;
 use openvmm_defs::config::DEFAULT_PCAT_BOOT_ORDER;
 use openvmm_defs::config::DeviceVtl;
 use openvmm_defs::config::PcatBootDevice;
 use openvmm_defs::config::Vtl2BaseAddressType;
 use openvmm_defs::config::X2ApicConfig;
+use openvmm_helpers::migrate::MigrationAddr;
 use std::ffi::OsString;
 use std::net::SocketAddr;
 use std::path::PathBuf;
@@ -247,8 +253,10 @@ pub struct Options {
     )]
     pub deprecated_memory_backing_file: Option<PathBuf>,
 
-    /// Restore VM from a snapshot directory (implies file-backed memory from
-    /// the snapshot's memory.bin). Cannot be used with --memory-backing-file.
+    /// Restore VM from a snapshot directory. Snapshots that link memory.bin
+    /// run from it directly; snapshots with copied memory are rebuilt into
+    /// the file given by --memory file=<path>, which must not exist yet.
+    /// Cannot be used with --memory-backing-file.
     #[clap(
         long,
         value_name = "DIR",
@@ -256,6 +264,16 @@ pub struct Options {
     )]
     pub restore_snapshot: Option<PathBuf>,
 
+    /// Wait for an incoming live migration on ADDR (a Unix socket path or
+    /// tcp:<ip>:<port>) and run the migrated VM. Guest RAM is received into
+    /// the memory backing file, which must not already exist.
+    #[clap(
+        long,
+        value_name = "ADDR",
+        conflicts_with_all = ["restore_snapshot", "numa"]
+    )]
+    pub migrate_listen: Option<MigrationAddr>,
+
     /// use private anonymous memory for guest RAM
     #[clap(long = "private-memory", hide = true, conflicts_with_all = ["deprecated_memory_backing_file", "restore_snapshot", "numa"])]
     pub deprecated_private_memory: bool,
@@ -366,11 +384,15 @@ pub struct Options {
     `file:<path>[;direct][;create=<len>]`   file-backed disk
         <path>: path to file
         `;direct`: bypass the OS page cache
+    `qcow2:<path>`                 qcow2 image, including its backing chain
     `sql:<path>[;create=<len>]`    SQLite-backed disk (dev/test)
     `sqldiff:<path>[;create]:<disk>` SQLite diff layer on a backing disk
     `autocache:<key>:<disk>`       auto-cached SQLite layer (use `autocache::<disk>` to omit key; needs OPENVMM_AUTO_CACHE_PATH)
     `blob:<type>:<url>`            HTTP blob (read-only)
         <type>: `flat` or `vhd1`
+    `nbd://<host>[:<port>][/<export>]`  NBD export over TCP
+    `nbd+unix:///[<export>]?socket=<path>` NBD export over a Unix socket
+    `nbd-export:<path>:<disk>`     serve the disk read-only over NBD on Unix socket <path>
     `crypt:<cipher>:<key_file>:<disk>` encrypted disk wrapper
         <cipher>: `xts-aes-256`
     `prwrap:<disk>`                persistent reservations wrapper
@@ -408,11 +430,15 @@ pub struct Options {
     `file:<path>[;direct][;create=<len>]`   file-backed disk
         <path>: path to file
         `;direct`: bypass the OS page cache
+    `qcow2:<path>`                 qcow2 image, including its backing chain
     `sql:<path>[;create=<len>]`    SQLite-backed disk (dev/test)
     `sqldiff:<path>[;create]:<disk>` SQLite diff layer on a backing disk
     `autocache:<key>:<disk>`       auto-cached SQLite layer (use `autocache::<disk>` to omit key; needs OPENVMM_AUTO_CACHE_PATH)
     `blob:<type>:<url>`            HTTP blob (read-only)
         <type>: `flat` or `vhd1`
+    `nbd://<host>[:<port>][/<export>]`  NBD export over TCP
+    `nbd+unix:///[<export>]?socket=<path>` NBD export over a Unix socket
+    `nbd-export:<path>:<disk>`     serve the disk read-only over NBD on Unix socket <path>
     `crypt:<cipher>:<key_file>:<disk>` encrypted disk wrapper
         <cipher>: `xts-aes-256`
     `prwrap:<disk>`                persistent reservations wrapper
@@ -475,6 +501,32 @@ pub struct Options {
     #[clap(long = "vmbus-scsi")]
     pub vmbus_scsi: Vec<ScsiControllerCli>,
 
+    /// create a named virtio-scsi controller
+    #[clap(long_help = r#"
+Create a named virtio-scsi controller in VTL0, using the same SCSI disk and
+DVD emulation as the VMBus SCSI controller.
+
+syntax: id=<name>,pcie_port=<port> | id=<name>,vpci[=<guid>][,queues=<N>]
+
+The controller name can be referenced by `--disk` with the `on=<name>`
+option to attach disks to this controller. Use a `prwrap:` disk for
+persistent reservations.
+
+options:
+    `id=<name>`                    controller name (required)
+    `pcie_port=<port>`             present on PCIe under the specified port
+    `vpci[=<guid>]`                present via VPCI; optional instance GUID
+    `queues=<N>`                   number of request queues (default 1, max 16)
+
+Exactly one of `pcie_port` or `vpci` must be specified.
+
+Examples:
+    --virtio-scsi id=vscsi0,pcie_port=p0
+    --virtio-scsi id=vscsi1,vpci,queues=4
+"#)]
+    #[clap(long = "virtio-scsi")]
+    pub virtio_scsi: Vec<VirtioScsiControllerCli>,
+
     /// register an OpenHCL-managed storage controller (relay target)
     #[clap(long_help = r#"
 Register an OpenHCL-managed storage controller that can be used as a
@@ -512,12 +564,14 @@ pub struct Options {
     `file:<path>[;direct]`                  file-backed disk
         <path>: path to file
         `;direct`: bypass the OS page cache
+    `qcow2:<path>`                 qcow2 image, including its backing chain
 
 flags:
     `ro`                           open disk as read-only
 
 options:
     `pcie_port=<name>`             present the disk using pcie under the specified port
+    `queues=<N>`                   number of request queues (default 1, max 64)
 "#)]
     #[clap(long = "virtio-blk")]
     pub virtio_blk: Vec<DiskCli>,
@@ -558,7 +612,8 @@ pub struct Options {
     #[clap(long)]
     pub nic: bool,
 
-    /// expose a virtual NIC with the given backend (consomme | dio | tap | none)
+    /// expose a virtual NIC with the given backend (consomme | dio | tap |
+    /// switch | none)
     ///
     /// Prefix with `uh:` to add this NIC via Mana emulation through OpenHCL,
     /// `vtl2:` to assign this NIC to VTL2, or `pcie_port=<port_name>:` to
@@ -569,6 +624,32 @@ pub struct Options {
     ///   --net consomme:hostfwd=tcp:127.0.0.1:8080-:80
     ///   --net consomme:hostfwd=tcp:\[::1\]:8080-:80
     ///   --net consomme:10.0.0.0/24,hostfwd=tcp::22-:22,hostfwd=udp::5000-:5000
+    ///
+    /// For consomme, serve a host directory over TFTP on the gateway with
+    /// `tftp=<dir>`, and advertise a network boot file from it over DHCP with
+    /// `bootfile=<name>`:
+    ///   --net consomme:tftp=/srv/tftp,bootfile=efi/bootx64.efi
+    ///
+    /// For consomme, filter guest-initiated TCP and UDP flows with
+    /// `allow=<rule>` and `deny=<rule>`, evaluated in order, and
+    /// `egress=allow|deny` for flows matching no rule (default allow). Rules
+    /// are `[tcp:|udp:]<network>[:<port>[-<port>]]`, where the network is an
+    /// address with an optional prefix length, bracketed for IPv6, or `*`:
+    ///   --net consomme:egress=deny,allow=tcp:140.82.112.0/20:443,allow=udp:*:53
+    ///
+    /// For switch, attach to the software Ethernet switch at a Unix socket
+    /// path, hosting it in this process if no switch is listening there.
+    /// Add `vlan=<id>` to make the port an access port on that VLAN:
+    ///   --net switch:/tmp/lab.sock
+    ///   --net switch:/tmp/lab.sock,vlan=10
+    ///
+    /// Prefix with `impair=<options>:` to impair the NIC's traffic in each
+    /// direction. Options are comma-separated `delay=<time>`,
+    /// `jitter=<time>`, `loss=<percent>`, `dup=<percent>`,
+    /// `reorder=<percent>`, `rate=<bits/s>`, and `seed=<n>`. Times take a
+    /// `us`, `ms`, or `s` suffix and rates an optional `k`, `m`, or `g`:
+    ///   --net impair=delay=50ms,jitter=10ms,loss=1:consomme
+    ///   --net impair=rate=10m,seed=42:switch:/tmp/lab.sock
     #[clap(long)]
     pub net: Vec<NicConfigCli>,
 
@@ -763,6 +844,36 @@ pub struct Options {
     #[clap(long, value_name = "PORT", requires("virtio_rng"))]
     pub virtio_rng_pcie_port: Option<String>,
 
+    /// add a virtio memory balloon device, resizable with the `balloon`
+    /// interactive command
+    #[clap(long)]
+    pub virtio_balloon: bool,
+
+    /// add the virtio-balloon device under either the PCI or MMIO bus, or whatever the hypervisor supports (pci | mmio | vpci | auto)
+    #[clap(long, value_name = "BUS", default_value = "auto")]
+    pub virtio_balloon_bus: VirtioBusCli,
+
+    /// attach the virtio-balloon device to the specified PCIe port (overrides --virtio-balloon-bus)
+    #[clap(long, value_name = "PORT", requires("virtio_balloon"))]
+    pub virtio_balloon_pcie_port: Option<String>,
+
+    /// add a 2D virtio GPU, displayed over VNC
+    #[clap(long, conflicts_with_all = ["gfx", "pcat"])]
+    pub virtio_gpu: bool,
+
+    /// add the virtio-gpu device under either the PCI or MMIO bus, or whatever the hypervisor supports (pci | mmio | vpci | auto)
+    #[clap(long, value_name = "BUS", default_value = "auto")]
+    pub virtio_gpu_bus: VirtioBusCli,
+
+    /// add virtio input devices that receive keyboard and pointer input
+    /// from the VNC server (keyboard | mouse | tablet), comma separated
+    #[clap(long, value_name = "DEVICES", value_delimiter = ',')]
+    pub virtio_input: Vec<VirtioInputCli>,
+
+    /// add the virtio-input devices under either the PCI or MMIO bus, or whatever the hypervisor supports (pci | mmio | vpci | auto)
+    #[clap(long, value_name = "BUS", default_value = "auto")]
+    pub virtio_input_bus: VirtioBusCli,
+
     /// virtio console device backed by a serial backend (/dev/hvc0 in guest)
     ///
     /// Accepts serial config (console | stderr | listen=\<path\> |
@@ -795,11 +906,16 @@ pub struct Options {
     pub virtio_vsock_vhost_cid: Option<u32>,
 
     /// expose a virtio network with the given backend (dio | vmnic | tap |
-    /// none)
+    /// switch | none)
     ///
     /// Prefix with `uh:` to add this NIC via Mana emulation through OpenHCL,
     /// `vtl2:` to assign this NIC to VTL2, or `pcie_port=<port_name>:` to
     /// expose the NIC over emulated PCIe at the specified port.
+    ///
+    /// On Linux, `tap:<name>:vhost` moves the datapath into the kernel's
+    /// vhost-net driver. This requires `/dev/vhost-net` and shared file-backed
+    /// guest RAM, and supports only one queue pair; requesting more with
+    /// `queues=` fails.
     #[clap(long)]
     pub virtio_net: Vec<NicConfigCli>,
 
@@ -997,10 +1113,14 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:<path>[;create=<len>]`   file-backed disk
         <path>: path to file
+    `qcow2:<path>`                 qcow2 image, including its backing chain
     `sql:<path>[;create=<len>]`    SQLite-backed disk (dev/test)
     `sqldiff:<path>[;create]:<disk>` SQLite diff layer on a backing disk
     `blob:<type>:<url>`            HTTP blob (read-only)
         <type>: `flat` or `vhd1`
+    `nbd://<host>[:<port>][/<export>]`  NBD export over TCP
+    `nbd+unix:///[<export>]?socket=<path>` NBD export over a Unix socket
+    `nbd-export:<path>:<disk>`     serve the disk read-only over NBD on Unix socket <path>
     `crypt:<cipher>:<key_file>:<disk>` encrypted disk wrapper
         <cipher>: `xts-aes-256`
 
@@ -1029,10 +1149,14 @@ pub struct Options {
         <disk>: lower disk, e.g.: `file:base.img`
     `file:<path>[;create=<len>]`   file-backed disk
         <path>: path to file
+    `qcow2:<path>`                 qcow2 image, including its backing chain
     `sql:<path>[;create=<len>]`    SQLite-backed disk (dev/test)
     `sqldiff:<path>[;create]:<disk>` SQLite diff layer on a backing disk
     `blob:<type>:<url>`            HTTP blob (read-only)
         <type>: `flat` or `vhd1`
+    `nbd://<host>[:<port>][/<export>]`  NBD export over TCP
+    `nbd+unix:///[<export>]?socket=<path>` NBD export over a Unix socket
+    `nbd-export:<path>:<disk>`     serve the disk read-only over NBD on Unix socket <path>
     `crypt:<cipher>:<key_file>:<disk>` encrypted disk wrapper
         <cipher>: `xts-aes-256`
 
@@ -1373,8 +1497,8 @@ pub fn validate_memory_options(&self) -> anyhow::Result<()> {
         if self.memory.file.is_some() && self.deprecated_memory_backing_file.is_some() {
             anyhow::bail!("--memory file=... conflicts with --memory-backing-file");
         }
-        if self.memory.file.is_some() && self.restore_snapshot.is_some() {
-            anyhow::bail!("--memory file=... conflicts with --restore-snapshot");
+        if self.migrate_listen.is_some() && self.memory_backing_file().is_none() {
+            anyhow::bail!("--migrate-listen requires --memory file=<path>");
         }
         if self.memory.shared == Some(true) && self.deprecated_private_memory {
             anyhow::bail!("--memory shared=on conflicts with --private-memory");
@@ -1497,6 +1621,16 @@ pub enum VirtioBusCli {
     Vpci,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, clap::ValueEnum)]
+pub enum VirtioInputCli {
+    /// A keyboard.
+    Keyboard,
+    /// A mouse that reports relative motion.
+    Mouse,
+    /// A tablet that reports absolute pointer positions.
+    Tablet,
+}
+
 fn parse_virtio_vsock_bus(value: &str) -> Result<VirtioBusCli, String> {
     match VirtioBusCli::from_str(value, true) {
         Ok(bus @ (VirtioBusCli::Mmio | VirtioBusCli::Pci)) => Ok(bus),
@@ -1557,7 +1691,7 @@ pub enum SecureBootTemplateCli {
     UefiCa,
 }
 
-fn parse_memory(s: &str) -> anyhow::Result<u64> {
+pub(crate) fn parse_memory(s: &str) -> anyhow::Result<u64> {
     if s == "VMGS_DEFAULT" {
         Ok(vmgs_format::VMGS_DEFAULT_CAPACITY)
     } else {
@@ -1691,6 +1825,10 @@ pub enum DiskCliKind {
         create_with_len: Option<u64>,
         direct: bool,
     },
+    // qcow2:<path>
+    Qcow2 {
+        path: PathBuf,
+    },
     // blob:<type>:<url>
     Blob {
         kind: BlobKind,
@@ -1707,6 +1845,16 @@ pub enum DiskCliKind {
         delay_ms: u64,
         disk: Box<DiskCliKind>,
     },
+    // nbd://<host>[:<port>][/<export>] or nbd+unix:///[<export>]?socket=<path>
+    Nbd {
+        address: NbdAddress,
+        export: String,
+    },
+    // nbd-export:<socket_path>:<kind>
+    NbdExport {
+        path: PathBuf,
+        disk: Box<DiskCliKind>,
+    },
 }
 
 #[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
@@ -1770,6 +1918,40 @@ fn parse_autocache(
     }
 }
 
+/// Parses the part of an NBD URI after the scheme, following the NBD URI
+/// specification.
+fn parse_nbd_uri(scheme: &str, arg: &str) -> anyhow::Result<DiskCliKind> {
+    // The IANA-assigned NBD port.
+    const DEFAULT_PORT: u16 = 10809;
+
+    let rest = arg.strip_prefix("//").context("expected //")?;
+    let (authority, rest) = rest.split_at(rest.find(['/', '?']).unwrap_or(rest.len()));
+    let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+    let export = path.strip_prefix('/').unwrap_or(path).to_owned();
+    let address = if scheme == "nbd+unix" {
+        if !authority.is_empty() {
+            anyhow::bail!("nbd+unix URIs must not have a host");
+        }
+        let socket = query
+            .split('&')
+            .find_map(|param| param.strip_prefix("socket="))
+            .context("expected ?socket=<path>")?;
+        NbdAddress::Unix(socket.to_owned())
+    } else {
+        if authority.is_empty() {
+            anyhow::bail!("expected host");
+        }
+        // Don't mistake the colons in a bracketed IPv6 address for a port.
+        let host_end = authority.rfind(']').unwrap_or(0);
+        if authority[host_end..].contains(':') {
+            NbdAddress::Tcp(authority.to_owned())
+        } else {
+            NbdAddress::Tcp(format!("{authority}:{DEFAULT_PORT}"))
+        }
+    };
+    Ok(DiskCliKind::Nbd { address, export })
+}
+
 impl FromStr for DiskCliKind {
     type Err = anyhow::Error;
 
@@ -1843,6 +2025,7 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
                         direct,
                     }
                 }
+                "qcow2" => DiskCliKind::Qcow2 { path: arg.into() },
                 "blob" => {
                     let (blob_kind, url) = arg.split_once(':').context("expected kind:url")?;
                     let blob_kind = match blob_kind {
@@ -1855,6 +2038,14 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
                         url: url.to_string(),
                     }
                 }
+                "nbd" | "nbd+unix" => parse_nbd_uri(kind, arg)?,
+                "nbd-export" => {
+                    let (path, kind) = arg.split_once(':').context("expected path:kind")?;
+                    DiskCliKind::NbdExport {
+                        path: path.into(),
+                        disk: Box::new(kind.parse()?),
+                    }
+                }
                 "crypt" => {
                     let (cipher, (key, kind)) = arg
                         .split_once(':')
@@ -1990,7 +2181,7 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
 /// VNC server configuration options.
 #[derive(clap::Args)]
 pub struct VncCli {
-    /// Listen for VNC connections. Implied by --gfx.
+    /// Listen for VNC connections. Implied by --gfx and --virtio-gpu.
     #[clap(long)]
     pub vnc: bool,
 
@@ -2027,6 +2218,7 @@ pub struct DiskCli {
     pub nsid: Option<u32>,
     pub lun: Option<u8>,
     pub relay: Option<(String, Option<u32>)>,
+    pub queues: Option<u16>,
 }
 
 #[derive(Copy, Clone)]
@@ -2081,6 +2273,7 @@ struct DiskArgs {
     nsid: Option<u32>,
     lun: Option<u8>,
     relay: Option<RelayTarget>,
+    queues: Option<u16>,
 }
 
 impl FromStr for DiskCli {
@@ -2103,6 +2296,7 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
         let nsid = args.nsid;
         let lun = args.lun;
         let relay = args.relay.map(|r| (r.name, r.location));
+        let queues = args.queues;
 
         if underhill.is_some() && vtl != DeviceVtl::Vtl0 {
             anyhow::bail!("`uh` or `uh-nvme` is incompatible with `vtl2`");
@@ -2146,6 +2340,10 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
             anyhow::bail!("`relay` is incompatible with `uh` and `uh-nvme`");
         }
 
+        if queues == Some(0) {
+            anyhow::bail!("`queues` must be at least 1");
+        }
+
         Ok(DiskCli {
             vtl,
             kind: args.kind,
@@ -2157,11 +2355,12 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
             nsid,
             lun,
             relay,
+            queues,
         })
     }
 }
 
-/// The transport for a named NVMe controller.
+/// The transport for a named NVMe or virtio-scsi controller.
 #[derive(Clone, Debug, PartialEq, vmm_cli::KeyValueGroup)]
 pub enum NvmeControllerTransport {
     /// Present via PCIe on the specified root port.
@@ -2198,6 +2397,18 @@ pub struct ScsiControllerCli {
     pub vtl: DeviceVtl,
 }
 
+/// CLI arguments for a named virtio-scsi controller.
+#[derive(Clone, Debug, vmm_cli::KeyValueArgs)]
+pub struct VirtioScsiControllerCli {
+    /// Controller name, referenced by `--disk on=<name>`.
+    pub id: String,
+    /// Transport configuration.
+    #[kv(flatten)]
+    pub transport: NvmeControllerTransport,
+    /// Number of request queues.
+    pub queues: Option<u16>,
+}
+
 /// Protocol type for an OpenHCL-managed controller.
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum OpenhclControllerType {
@@ -2489,12 +2700,20 @@ pub enum EndpointConfigCli {
     Consomme {
         cidr: Option<String>,
         host_fwd: Vec<HostPortConfigCli>,
+        tftp: Option<String>,
+        boot_file: Option<String>,
+        egress: Option<EgressPolicyConfig>,
     },
     Dio {
         id: Option<String>,
     },
     Tap {
         name: String,
+        vhost: bool,
+    },
+    Switch {
+        path: String,
+        vlan: Option<u16>,
     },
 }
 
@@ -2551,6 +2770,123 @@ fn parse_hostfwd(s: &str) -> Result<HostPortConfigCli, String> {
     })
 }
 
+/// Parses an `egress=`, `allow=` or `deny=` option into `egress`, creating
+/// an allow-all policy if there is none yet. Returns `false` if `opt` is some
+/// other option.
+fn parse_egress_option(egress: &mut Option<EgressPolicyConfig>, opt: &str) -> Result<bool, String> {
+    let policy = || EgressPolicyConfig {
+        rules: Vec::new(),
+        default_allow: true,
+    };
+    if let Some(action) = opt.strip_prefix("egress=") {
+        egress.get_or_insert_with(policy).default_allow = match action {
+            "allow" => true,
+            "deny" => false,
+            _ => return Err(format!("invalid egress action '{action}'")),
+        };
+    } else if let Some(rule) = opt.strip_prefix("allow=") {
+        let rule = parse_egress_rule(true, rule)?;
+        egress.get_or_insert_with(policy).rules.push(rule);
+    } else if let Some(rule) = opt.strip_prefix("deny=") {
+        let rule = parse_egress_rule(false, rule)?;
+        egress.get_or_insert_with(policy).rules.push(rule);
+    } else {
+        return Ok(false);
+    }
+    Ok(true)
+}
+
+/// Parses a comma-separated egress policy, using the `egress=`, `allow=` and
+/// `deny=` options of `--net consomme`.
+pub(crate) fn parse_egress_policy(s: &str) -> Result<EgressPolicyConfig, String> {
+    let mut egress = None;
+    for opt in s.split(',').filter(|s| !s.is_empty()) {
+        if !parse_egress_option(&mut egress, opt)? {
+            return Err(format!("unexpected egress option '{opt}'"));
+        }
+    }
+    Ok(egress.unwrap_or(EgressPolicyConfig {
+        rules: Vec::new(),
+        default_allow: true,
+    }))
+}
+
+fn parse_egress_rule(allow: bool, s: &str) -> Result<EgressRuleConfig, String> {
+    // Format: [proto:]network[:port[-port]]
+    // Examples: "tcp:10.0.0.0/8:443", "udp:*:53", "[2001:db8::/32]:1000-2000"
+    let (protocol, rest) = if let Some(rest) = s.strip_prefix("tcp:") {
+        (Some(HostPortProtocol::Tcp), rest)
+    } else if let Some(rest) = s.strip_prefix("udp:") {
+        (Some(HostPortProtocol::Udp), rest)
+    } else {
+        (None, s)
+    };
+
+    let (network, ports) = if let Some(rest) = rest.strip_prefix('[') {
+        let (network, ports) = rest
+            .split_once(']')
+            .ok_or_else(|| format!("expected '[network]' in egress rule '{s}'"))?;
+        let ports = if ports.is_empty() {
+            None
+        } else {
+            Some(
+                ports
+                    .strip_prefix(':')
+                    .ok_or_else(|| format!("expected '[network]:port' in egress rule '{s}'"))?,
+            )
+        };
+        (network, ports)
+    } else {
+        match rest.split_once(':') {
+            Some((network, ports)) => (network, Some(ports)),
+            None => (rest, None),
+        }
+    };
+
+    let destination = if network == "*" {
+        None
+    } else {
+        let (addr, prefix_len) = match network.split_once('/') {
+            Some((addr, prefix_len)) => (addr, Some(prefix_len)),
+            None => (network, None),
+        };
+        let addr: std::net::IpAddr = addr
+            .parse()
+            .map_err(|e| format!("invalid address '{addr}': {e}"))?;
+        let max_prefix_len = if addr.is_ipv4() { 32 } else { 128 };
+        let prefix_len = match prefix_len {
+            Some(len) => len
+                .parse::<u8>()
+                .ok()
+                .filter(|&len| len <= max_prefix_len)
+                .ok_or_else(|| format!("invalid prefix length '{len}'"))?,
+            None => max_prefix_len,
+        };
+        Some((addr.into(), prefix_len))
+    };
+
+    let ports = ports
+        .map(|ports| {
+            let (start, end) = ports.split_once('-').unwrap_or((ports, ports));
+            let start: u16 = start
+                .parse()
+                .map_err(|_| format!("invalid port '{start}'"))?;
+            let end: u16 = end.parse().map_err(|_| format!("invalid port '{end}'"))?;
+            if start > end {
+                return Err(format!("invalid port range '{ports}'"));
+            }
+            Ok((start, end))
+        })
+        .transpose()?;
+
+    Ok(EgressRuleConfig {
+        allow,
+        protocol,
+        destination,
+        ports,
+    })
+}
+
 /// Parse an address-port pair in one of these forms:
 /// - `[ipv6addr]:port`
 /// - `addr:port`
@@ -2599,23 +2935,72 @@ fn from_str(s: &str) -> Result<Self, Self::Err> {
                 let remaining = rest.join(":");
                 let mut cidr = None;
                 let mut host_fwd = Vec::new();
+                let mut tftp = None;
+                let mut boot_file = None;
+                let mut egress: Option<EgressPolicyConfig> = None;
                 for opt in remaining.split(',').filter(|s| !s.is_empty()) {
+                    if parse_egress_option(&mut egress, opt)? {
+                        continue;
+                    }
                     if let Some(fwd) = opt.strip_prefix("hostfwd=") {
                         host_fwd.push(parse_hostfwd(fwd)?);
+                    } else if let Some(dir) = opt.strip_prefix("tftp=") {
+                        tftp = Some(dir.to_owned());
+                    } else if let Some(name) = opt.strip_prefix("bootfile=") {
+                        boot_file = Some(name.to_owned());
                     } else if cidr.is_none() {
                         cidr = Some(opt.to_owned());
                     } else {
                         return Err(format!("unexpected consomme option '{opt}'"));
                     }
                 }
-                EndpointConfigCli::Consomme { cidr, host_fwd }
+                if boot_file.is_some() && tftp.is_none() {
+                    return Err("consomme bootfile= requires tftp=".to_owned());
+                }
+                EndpointConfigCli::Consomme {
+                    cidr,
+                    host_fwd,
+                    tftp,
+                    boot_file,
+                    egress,
+                }
             }
             ["dio", s @ ..] => EndpointConfigCli::Dio {
                 id: s.first().map(|s| (*s).to_owned()),
             },
             ["tap", name] => EndpointConfigCli::Tap {
                 name: (*name).to_owned(),
+                vhost: false,
+            },
+            ["tap", name, "vhost"] => EndpointConfigCli::Tap {
+                name: (*name).to_owned(),
+                vhost: true,
             },
+            ["switch", rest @ ..] => {
+                let remaining = rest.join(":");
+                let mut opts = remaining.split(',');
+                let path = opts.next().unwrap_or_default();
+                if path.is_empty() {
+                    return Err("switch requires a socket path".into());
+                }
+                let mut vlan = None;
+                for opt in opts {
+                    if let Some(id) = opt.strip_prefix("vlan=") {
+                        let id = id
+                            .parse()
+                            .ok()
+                            .filter(|id| (1..=4094).contains(id))
+                            .ok_or_else(|| format!("invalid vlan id '{id}'"))?;
+                        vlan = Some(id);
+                    } else {
+                        return Err(format!("unexpected switch option '{opt}'"));
+                    }
+                }
+                EndpointConfigCli::Switch {
+                    path: path.to_owned(),
+                    vlan,
+                }
+            }
             _ => return Err("invalid network backend".into()),
         };
 
@@ -2630,6 +3015,7 @@ pub struct NicConfigCli {
     pub max_queues: Option<u16>,
     pub underhill: bool,
     pub pcie_port: Option<String>,
+    pub impairment: Option<ImpairmentConfig>,
 }
 
 impl FromStr for NicConfigCli {
@@ -2640,6 +3026,7 @@ fn from_str(mut s: &str) -> Result<Self, Self::Err> {
         let mut max_queues = None;
         let mut underhill = false;
         let mut pcie_port = None;
+        let mut impairment = None;
         while let Some((opt, rest)) = s.split_once(':') {
             if let Some((opt, val)) = opt.split_once('=') {
                 match opt {
@@ -2652,6 +3039,9 @@ fn from_str(mut s: &str) -> Result<Self, Self::Err> {
                         }
                         pcie_port = Some(val.to_string());
                     }
+                    "impair" => {
+                        impairment = Some(parse_impairment(val)?);
+                    }
                     _ => break,
                 }
             } else {
@@ -2681,10 +3071,63 @@ fn from_str(mut s: &str) -> Result<Self, Self::Err> {
             max_queues,
             underhill,
             pcie_port,
+            impairment,
         })
     }
 }
 
+/// Parses comma-separated network impairment options, such as
+/// `delay=50ms,jitter=10ms,loss=1.5,rate=10m`.
+fn parse_impairment(s: &str) -> Result<ImpairmentConfig, String> {
+    fn duration(v: &str) -> Option<std::time::Duration> {
+        let (n, scale) = if let Some(n) = v.strip_suffix("us") {
+            (n, 1)
+        } else if let Some(n) = v.strip_suffix("ms") {
+            (n, 1000)
+        } else {
+            (v.strip_suffix('s')?, 1_000_000)
+        };
+        let n: u64 = n.parse().ok()?;
+        Some(std::time::Duration::from_micros(n.checked_mul(scale)?))
+    }
+
+    fn ppm(v: &str) -> Option<u32> {
+        let percent: f64 = v.strip_suffix('%').unwrap_or(v).parse().ok()?;
+        (0.0..=100.0)
+            .contains(&percent)
+            .then(|| (percent * 10_000.0).round() as u32)
+    }
+
+    fn rate(v: &str) -> Option<u64> {
+        let (n, scale) = match v.as_bytes().last()? {
+            b'k' | b'K' => (&v[..v.len() - 1], 1_000),
+            b'm' | b'M' => (&v[..v.len() - 1], 1_000_000),
+            b'g' | b'G' => (&v[..v.len() - 1], 1_000_000_000),
+            _ => (v, 1),
+        };
+        n.parse::<u64>().ok()?.checked_mul(scale)
+    }
+
+    let mut config = ImpairmentConfig::default();
+    for opt in s.split(',') {
+        let (name, v) = opt
+            .split_once('=')
+            .ok_or_else(|| format!("expected <option>=<value>, got '{opt}'"))?;
+        let invalid = || format!("invalid value for impairment option '{name}': '{v}'");
+        match name {
+            "delay" => config.delay = duration(v).ok_or_else(invalid)?,
+            "jitter" => config.jitter = duration(v).ok_or_else(invalid)?,
+            "loss" => config.loss_ppm = ppm(v).ok_or_else(invalid)?,
+            "dup" => config.duplicate_ppm = ppm(v).ok_or_else(invalid)?,
+            "reorder" => config.reorder_ppm = ppm(v).ok_or_else(invalid)?,
+            "rate" => config.rate = rate(v).ok_or_else(invalid)?,
+            "seed" => config.seed = v.parse().map_err(|_| invalid())?,
+            _ => return Err(format!("unknown impairment option '{name}'")),
+        }
+    }
+    Ok(config)
+}
+
 #[derive(Debug, Error)]
 #[error("unknown VTL2 relocation type: {0}")]
 pub struct UnknownVtl2RelocationType(String);
@@ -3437,6 +3880,7 @@ fn from_str(s: &str) -> anyhow::Result<Self> {
 mod tests {
     use super::*;
 
+    use net_backend_resources::consomme::HostIpAddress;
     use std::path::Path;
     use test_with_tracing::test;
 
@@ -3548,6 +3992,22 @@ fn test_parse_file_opts() {
         assert!(DiskCliKind::from_str("sql:db.sqlite;direct").is_err());
     }
 
+    #[test]
+    fn test_parse_qcow2() {
+        let disk = DiskCliKind::from_str("qcow2:/images/disk.qcow2").unwrap();
+        assert!(matches!(
+            &disk,
+            DiskCliKind::Qcow2 { path } if path == Path::new("/images/disk.qcow2")
+        ));
+
+        let disk = DiskCliKind::from_str("memdiff:qcow2:disk.qcow2").unwrap();
+        assert!(matches!(
+            &disk,
+            DiskCliKind::MemoryDiff(inner)
+                if matches!(&**inner, DiskCliKind::Qcow2 { path } if path == Path::new("disk.qcow2"))
+        ));
+    }
+
     #[test]
     fn test_parse_memory_disk() {
         let s = "mem:1G";
@@ -3588,6 +4048,17 @@ fn test_parse_pcie_disk() {
         assert!(DiskCli::from_str("file:disk.vhd,pcie_port=p0,uh-nvme").is_err());
     }
 
+    #[test]
+    fn test_parse_disk_queues() {
+        assert_eq!(DiskCli::from_str("mem:1G").unwrap().queues, None);
+        assert_eq!(
+            DiskCli::from_str("mem:1G,queues=4").unwrap().queues,
+            Some(4)
+        );
+        assert!(DiskCli::from_str("mem:1G,queues=0").is_err());
+        assert!(DiskCli::from_str("mem:1G,queues=x").is_err());
+    }
+
     #[test]
     fn test_parse_memory_diff_disk() {
         let s = "memdiff:file:base.img";
@@ -3719,6 +4190,51 @@ fn test_parse_autocache_sqlite_disk() {
         );
     }
 
+    #[test]
+    fn test_parse_nbd_disk() {
+        assert_eq!(
+            DiskCliKind::from_str("nbd://localhost").unwrap(),
+            DiskCliKind::Nbd {
+                address: NbdAddress::Tcp("localhost:10809".into()),
+                export: String::new(),
+            }
+        );
+        assert_eq!(
+            DiskCliKind::from_str("nbd://10.0.0.1:1234/disk0").unwrap(),
+            DiskCliKind::Nbd {
+                address: NbdAddress::Tcp("10.0.0.1:1234".into()),
+                export: "disk0".into(),
+            }
+        );
+        assert_eq!(
+            DiskCliKind::from_str("nbd://[::1]/disk0").unwrap(),
+            DiskCliKind::Nbd {
+                address: NbdAddress::Tcp("[::1]:10809".into()),
+                export: "disk0".into(),
+            }
+        );
+        assert_eq!(
+            DiskCliKind::from_str("nbd+unix:///disk0?socket=/tmp/nbd.sock").unwrap(),
+            DiskCliKind::Nbd {
+                address: NbdAddress::Unix("/tmp/nbd.sock".into()),
+                export: "disk0".into(),
+            }
+        );
+        assert_eq!(
+            DiskCliKind::from_str("nbd-export:/tmp/nbd.sock:mem:1G").unwrap(),
+            DiskCliKind::NbdExport {
+                path: "/tmp/nbd.sock".into(),
+                disk: Box::new(DiskCliKind::Memory(1024 * 1024 * 1024)),
+            }
+        );
+
+        assert!(DiskCliKind::from_str("nbd:localhost").is_err());
+        assert!(DiskCliKind::from_str("nbd:///disk0").is_err());
+        assert!(DiskCliKind::from_str("nbd+unix:///disk0").is_err());
+        assert!(DiskCliKind::from_str("nbd+unix://host/disk0?socket=/tmp/nbd.sock").is_err());
+        assert!(DiskCliKind::from_str("nbd-export:/tmp/nbd.sock").is_err());
+    }
+
     #[test]
     fn test_parse_disk_errors() {
         assert!(DiskCliKind::from_str("invalid:").is_err());
@@ -3876,6 +4392,7 @@ fn test_endpoint_config_from_str() {
             EndpointConfigCli::Consomme {
                 cidr: None,
                 host_fwd,
+                ..
             } => assert!(host_fwd.is_empty()),
             _ => panic!("Expected Consomme variant without cidr"),
         }
@@ -3885,6 +4402,7 @@ fn test_endpoint_config_from_str() {
             EndpointConfigCli::Consomme {
                 cidr: Some(cidr),
                 host_fwd,
+                ..
             } => {
                 assert_eq!(cidr, "192.168.0.0/24");
                 assert!(host_fwd.is_empty());
@@ -3894,7 +4412,7 @@ fn test_endpoint_config_from_str() {
 
         // Test consomme with hostfwd
         match EndpointConfigCli::from_str("consomme:hostfwd=udp:127.0.0.1:5000-:5000").unwrap() {
-            EndpointConfigCli::Consomme { cidr, host_fwd } => {
+            EndpointConfigCli::Consomme { cidr, host_fwd, .. } => {
                 assert!(cidr.is_none());
                 assert_eq!(host_fwd.len(), 1);
                 assert_eq!(host_fwd[0].protocol, HostPortProtocolCli::Udp);
@@ -3910,7 +4428,7 @@ fn test_endpoint_config_from_str() {
 
         // Test consomme with cidr and hostfwd
         match EndpointConfigCli::from_str("consomme:10.0.0.0/24,hostfwd=tcp::2222-:22").unwrap() {
-            EndpointConfigCli::Consomme { cidr, host_fwd } => {
+            EndpointConfigCli::Consomme { cidr, host_fwd, .. } => {
                 assert_eq!(cidr.as_deref(), Some("10.0.0.0/24"));
                 assert_eq!(host_fwd.len(), 1);
                 assert_eq!(host_fwd[0].protocol, HostPortProtocolCli::Tcp);
@@ -3924,7 +4442,7 @@ fn test_endpoint_config_from_str() {
         match EndpointConfigCli::from_str("consomme:hostfwd=tcp::2222-:22,hostfwd=tcp::3389-:3389")
             .unwrap()
         {
-            EndpointConfigCli::Consomme { cidr, host_fwd } => {
+            EndpointConfigCli::Consomme { cidr, host_fwd, .. } => {
                 assert!(cidr.is_none());
                 assert_eq!(host_fwd.len(), 2);
                 assert_eq!(host_fwd[0].host_port, 2222);
@@ -3937,7 +4455,7 @@ fn test_endpoint_config_from_str() {
 
         // Test consomme with different host and guest ports
         match EndpointConfigCli::from_str("consomme:hostfwd=tcp:127.0.0.1:8080-:80").unwrap() {
-            EndpointConfigCli::Consomme { cidr, host_fwd } => {
+            EndpointConfigCli::Consomme { cidr, host_fwd, .. } => {
                 assert!(cidr.is_none());
                 assert_eq!(host_fwd.len(), 1);
                 assert_eq!(host_fwd[0].protocol, HostPortProtocolCli::Tcp);
@@ -3953,7 +4471,7 @@ fn test_endpoint_config_from_str() {
 
         // Test consomme with guest address (accepted but ignored by backend)
         match EndpointConfigCli::from_str("consomme:hostfwd=tcp::8080-10.0.0.2:80").unwrap() {
-            EndpointConfigCli::Consomme { cidr, host_fwd } => {
+            EndpointConfigCli::Consomme { cidr, host_fwd, .. } => {
                 assert!(cidr.is_none());
                 assert_eq!(host_fwd[0].host_port, 8080);
                 assert_eq!(host_fwd[0].guest_port, 80);
@@ -3963,7 +4481,7 @@ fn test_endpoint_config_from_str() {
 
         // Test consomme with IPv6 host address (bracketed)
         match EndpointConfigCli::from_str("consomme:hostfwd=tcp:[::1]:8080-:80").unwrap() {
-            EndpointConfigCli::Consomme { cidr, host_fwd } => {
+            EndpointConfigCli::Consomme { cidr, host_fwd, .. } => {
                 assert!(cidr.is_none());
                 assert_eq!(host_fwd.len(), 1);
                 assert_eq!(host_fwd[0].protocol, HostPortProtocolCli::Tcp);
@@ -3979,7 +4497,7 @@ fn test_endpoint_config_from_str() {
 
         // Test consomme with IPv6 guest address (bracketed)
         match EndpointConfigCli::from_str("consomme:hostfwd=tcp::8080-[::1]:80").unwrap() {
-            EndpointConfigCli::Consomme { cidr, host_fwd } => {
+            EndpointConfigCli::Consomme { cidr, host_fwd, .. } => {
                 assert!(cidr.is_none());
                 assert_eq!(host_fwd[0].host_port, 8080);
                 assert_eq!(host_fwd[0].guest_port, 80);
@@ -3987,6 +4505,78 @@ fn test_endpoint_config_from_str() {
             _ => panic!("Expected Consomme variant with IPv6 guest address"),
         }
 
+        // Test consomme with tftp and bootfile
+        match EndpointConfigCli::from_str("consomme:10.0.0.0/24,tftp=/srv/tftp,bootfile=pxelinux.0")
+            .unwrap()
+        {
+            EndpointConfigCli::Consomme {
+                cidr,
+                tftp,
+                boot_file,
+                ..
+            } => {
+                assert_eq!(cidr.as_deref(), Some("10.0.0.0/24"));
+                assert_eq!(tftp.as_deref(), Some("/srv/tftp"));
+                assert_eq!(boot_file.as_deref(), Some("pxelinux.0"));
+            }
+            _ => panic!("Expected Consomme variant with tftp"),
+        }
+        assert!(EndpointConfigCli::from_str("consomme:bootfile=pxelinux.0").is_err());
+
+        // Test consomme with an egress policy
+        let network = |addr: &str, len| {
+            let addr: std::net::IpAddr = addr.parse().unwrap();
+            Some((HostIpAddress::from(addr), len))
+        };
+        let policy = "consomme:egress=deny,allow=tcp:10.0.0.0/8:443,\
+                      deny=[2001:db8::/32]:1000-2000,allow=udp:*:53";
+        match EndpointConfigCli::from_str(policy).unwrap() {
+            EndpointConfigCli::Consomme {
+                egress: Some(egress),
+                ..
+            } => {
+                assert!(!egress.default_allow);
+                assert_eq!(
+                    egress.rules,
+                    [
+                        EgressRuleConfig {
+                            allow: true,
+                            protocol: Some(HostPortProtocol::Tcp),
+                            destination: network("10.0.0.0", 8),
+                            ports: Some((443, 443)),
+                        },
+                        EgressRuleConfig {
+                            allow: false,
+                            protocol: None,
+                            destination: network("2001:db8::", 32),
+                            ports: Some((1000, 2000)),
+                        },
+                        EgressRuleConfig {
+                            allow: true,
+                            protocol: Some(HostPortProtocol::Udp),
+                            destination: None,
+                            ports: Some((53, 53)),
+                        },
+                    ]
+                );
+            }
+            _ => panic!("Expected Consomme variant with egress policy"),
+        }
+        match EndpointConfigCli::from_str("consomme:deny=192.0.2.1").unwrap() {
+            EndpointConfigCli::Consomme {
+                egress: Some(egress),
+                ..
+            } => {
+                assert!(egress.default_allow);
+                assert_eq!(egress.rules[0].destination, network("192.0.2.1", 32));
+                assert_eq!(egress.rules[0].ports, None);
+            }
+            _ => panic!("Expected Consomme variant with egress policy"),
+        }
+        assert!(EndpointConfigCli::from_str("consomme:allow=10.0.0.0/33").is_err());
+        assert!(EndpointConfigCli::from_str("consomme:allow=*:2000-1000").is_err());
+        assert!(EndpointConfigCli::from_str("consomme:egress=maybe").is_err());
+
         // Test dio without id
         match EndpointConfigCli::from_str("dio").unwrap() {
             EndpointConfigCli::Dio { id: None } => (),
@@ -4003,16 +4593,74 @@ fn test_endpoint_config_from_str() {
 
         // Test tap
         match EndpointConfigCli::from_str("tap:tap0").unwrap() {
-            EndpointConfigCli::Tap { name } => {
+            EndpointConfigCli::Tap { name, vhost } => {
+                assert_eq!(name, "tap0");
+                assert!(!vhost);
+            }
+            _ => panic!("Expected Tap variant"),
+        }
+
+        // Test tap with vhost-net
+        match EndpointConfigCli::from_str("tap:tap0:vhost").unwrap() {
+            EndpointConfigCli::Tap { name, vhost } => {
                 assert_eq!(name, "tap0");
+                assert!(vhost);
             }
             _ => panic!("Expected Tap variant"),
         }
+        assert!(EndpointConfigCli::from_str("tap:tap0:bogus").is_err());
+
+        // Test switch
+        assert_eq!(
+            EndpointConfigCli::from_str("switch:/tmp/switch.sock").unwrap(),
+            EndpointConfigCli::Switch {
+                path: "/tmp/switch.sock".into(),
+                vlan: None,
+            }
+        );
+        assert_eq!(
+            EndpointConfigCli::from_str("switch:/tmp/switch.sock,vlan=10").unwrap(),
+            EndpointConfigCli::Switch {
+                path: "/tmp/switch.sock".into(),
+                vlan: Some(10),
+            }
+        );
+        assert!(EndpointConfigCli::from_str("switch:").is_err());
+        assert!(EndpointConfigCli::from_str("switch:/tmp/switch.sock,vlan=0").is_err());
+        assert!(EndpointConfigCli::from_str("switch:/tmp/switch.sock,bogus").is_err());
 
         // Test error case
         assert!(EndpointConfigCli::from_str("invalid").is_err());
     }
 
+    #[test]
+    fn test_parse_egress_policy() {
+        assert_eq!(
+            parse_egress_policy("egress=deny,allow=tcp:10.0.0.0/8:443").unwrap(),
+            EgressPolicyConfig {
+                rules: vec![EgressRuleConfig {
+                    allow: true,
+                    protocol: Some(HostPortProtocol::Tcp),
+                    destination: Some((
+                        HostIpAddress::from("10.0.0.0".parse::<std::net::IpAddr>().unwrap()),
+                        8
+                    )),
+                    ports: Some((443, 443)),
+                }],
+                default_allow: false,
+            }
+        );
+        assert_eq!(
+            parse_egress_policy("").unwrap(),
+            EgressPolicyConfig {
+                rules: Vec::new(),
+                default_allow: true,
+            }
+        );
+        assert!(parse_egress_policy("egress=deny,hostfwd=tcp::22-:22").is_err());
+        assert!(parse_egress_policy("egress=maybe").is_err());
+    }
+
     #[test]
     fn test_nic_config_from_str() {
         use openvmm_defs::config::DeviceVtl;
@@ -4055,6 +4703,40 @@ fn test_nic_config_from_str() {
         assert!(NicConfigCli::from_str("uh:pcie_port=rp0:none").is_err());
         assert!(NicConfigCli::from_str("pcie_port=:none").is_err());
         assert!(NicConfigCli::from_str("pcie_port:none").is_err());
+
+        // Test with impairments
+        let config = NicConfigCli::from_str(
+            "impair=delay=50ms,jitter=500us,loss=1.5,dup=0.1%,reorder=2,rate=10m,seed=7:consomme",
+        )
+        .unwrap();
+        assert_eq!(
+            config.impairment.unwrap(),
+            ImpairmentConfig {
+                delay: std::time::Duration::from_millis(50),
+                jitter: std::time::Duration::from_micros(500),
+                loss_ppm: 15_000,
+                duplicate_ppm: 1_000,
+                reorder_ppm: 20_000,
+                rate: 10_000_000,
+                seed: 7,
+            }
+        );
+        assert!(matches!(
+            config.endpoint,
+            EndpointConfigCli::Consomme { .. }
+        ));
+        let config = NicConfigCli::from_str("pcie_port=rp0:impair=delay=1s:none").unwrap();
+        assert_eq!(config.pcie_port.unwrap(), "rp0");
+        assert_eq!(
+            config.impairment.unwrap().delay,
+            std::time::Duration::from_secs(1)
+        );
+        assert!(NicConfigCli::from_str("none").unwrap().impairment.is_none());
+        assert!(NicConfigCli::from_str("impair=delay=50:none").is_err());
+        assert!(NicConfigCli::from_str("impair=loss=101:none").is_err());
+        assert!(NicConfigCli::from_str("impair=rate=fast:none").is_err());
+        assert!(NicConfigCli::from_str("impair=bogus=1:none").is_err());
+        assert!(NicConfigCli::from_str("impair=delay:none").is_err());
     }
 
     #[test]
@@ -4931,6 +5613,39 @@ fn test_memory_options_reject_conflicting_legacy_aliases() {
         assert!(opt.validate_memory_options().is_err());
     }
 
+    #[test]
+    fn test_migrate_listen() {
+        let opt = Options::try_parse_from([
+            "openvmm",
+            "--memory",
+            "file=/tmp/mem.bin",
+            "--migrate-listen",
+            "tcp:127.0.0.1:7000",
+        ])
+        .unwrap();
+        opt.validate_memory_options().unwrap();
+        assert_eq!(
+            opt.migrate_listen,
+            Some(MigrationAddr::Tcp("127.0.0.1:7000".parse().unwrap()))
+        );
+
+        // Guest RAM must be received into a backing file.
+        let opt =
+            Options::try_parse_from(["openvmm", "--migrate-listen", "/tmp/migrate.sock"]).unwrap();
+        assert!(opt.validate_memory_options().is_err());
+
+        assert!(
+            Options::try_parse_from([
+                "openvmm",
+                "--migrate-listen",
+                "/tmp/migrate.sock",
+                "--restore-snapshot",
+                "/tmp/snap",
+            ])
+            .is_err()
+        );
+    }
+
     #[test]
     fn test_isolation_options_reject_snp_uefi() {
         let opt = Options::try_parse_from(["openvmm", "--isolation", "snp", "--uefi"]).unwrap();
@@ -5306,6 +6021,25 @@ fn test_scsi_controller_cli_errors() {
         assert!(ScsiControllerCli::from_str("id=scsi0,sub_channels=abc").is_err());
     }
 
+    #[test]
+    fn test_virtio_scsi_controller_cli() {
+        let c = VirtioScsiControllerCli::from_str("id=vscsi0,pcie_port=p0").unwrap();
+        assert_eq!(c.id, "vscsi0");
+        assert_eq!(c.transport, NvmeControllerTransport::Pcie("p0".into()));
+        assert_eq!(c.queues, None);
+
+        let c = VirtioScsiControllerCli::from_str("id=vscsi1,vpci,queues=4").unwrap();
+        assert!(matches!(c.transport, NvmeControllerTransport::Vpci(None)));
+        assert_eq!(c.queues, Some(4));
+
+        // Missing transport.
+        assert!(VirtioScsiControllerCli::from_str("id=vscsi0").is_err());
+        // Invalid queues.
+        assert!(VirtioScsiControllerCli::from_str("id=vscsi0,vpci,queues=abc").is_err());
+        // VTL2 is not supported.
+        assert!(VirtioScsiControllerCli::from_str("id=vscsi0,vpci,vtl2").is_err());
+    }
+
     #[test]
     fn test_disk_cli_relay() {
         let d = DiskCli::from_str("file:disk.vhd,on=src,relay=tgt").unwrap();
@@ -36,11 +36,14 @@
 use cli_args::SerialConfigCli;
 use cli_args::UefiConsoleModeCli;
 use cli_args::VirtioBusCli;
+use cli_args::VirtioInputCli;
 use cli_args::VmgsCli;
 use crash_dump::spawn_dump_handler;
 use cxl_spec::test::CxlTestDeviceHandle;
 use disk_backend_resources::DelayDiskHandle;
 use disk_backend_resources::DiskLayerDescription;
+use disk_backend_resources::NbdDiskHandle;
+use disk_backend_resources::NbdExportDiskHandle;
 use disk_backend_resources::layer::DiskLayerHandle;
 use disk_backend_resources::layer::RamDiskLayerHandle;
 use disk_backend_resources::layer::SqliteAutoCacheDiskLayerHandle;
@@ -93,6 +96,10 @@
 use openvmm_helpers::disk::OpenDiskOptions;
 use openvmm_helpers::disk::create_disk_type;
 use openvmm_helpers::disk::open_disk_type;
+use openvmm_helpers::disk::open_qcow2_disk;
+use openvmm_helpers::migrate::MigrationAddr;
+use openvmm_helpers::migrate::MigrationStream;
+use openvmm_helpers::migrate::MigrationTarget;
 use pal_async::DefaultDriver;
 use pal_async::DefaultPool;
 use pal_async::socket::PolledSocket;
@@ -194,6 +201,30 @@ struct VmResources {
     dirty_rect_recv: Option<mesh::Receiver<Vec<video_core::DirtyRect>>>,
     #[cfg(windows)]
     switch_ports: Vec<vmswitch::kernel::SwitchPort>,
+    /// Software Ethernet switches attached to by NICs, by socket path.
+    switches: BTreeMap<PathBuf, SwitchAttachment>,
+    /// Runs the switches hosted by this process and their connections.
+    switch_driver: Option<DefaultDriver>,
+}
+
+/// A software Ethernet switch that this process's NICs are attached to.
+enum SwitchAttachment {
+    /// The switch is hosted by this process and served to others.
+    Local {
+        switch: net_switch::Switch,
+        _server: Task<()>,
+    },
+    /// The switch is hosted by another process.
+    Remote(net_switch::socket::SwitchClient),
+}
+
+impl SwitchAttachment {
+    fn sender(&self) -> mesh::Sender<net_backend_resources::switch::SwitchRequest> {
+        match self {
+            SwitchAttachment::Local { switch, .. } => switch.sender(),
+            SwitchAttachment::Remote(client) => client.sender(),
+        }
+    }
 }
 
 struct ConsoleState<'a> {
@@ -488,6 +519,19 @@ async fn vm_config_from_command_line(
         storage.add_scsi_controller(ctrl.id.clone(), ctrl.vtl, instance_id, ctrl.sub_channels)?;
     }
 
+    for ctrl in &opt.virtio_scsi {
+        let transport = match &ctrl.transport {
+            cli_args::NvmeControllerTransport::Pcie(port) => {
+                storage_builder::NvmeControllerTransport::Pcie(port.clone())
+            }
+            cli_args::NvmeControllerTransport::Vpci(guid) => {
+                let guid = guid.unwrap_or_else(|| storage_builder::deterministic_guid(&ctrl.id));
+                storage_builder::NvmeControllerTransport::Vpci(guid)
+            }
+        };
+        storage.add_virtio_scsi_controller(ctrl.id.clone(), transport, ctrl.queues)?;
+    }
+
     for ctrl in &opt.openhcl_controller {
         let controller_type = match ctrl.controller_type {
             cli_args::OpenhclControllerType::Scsi => storage_builder::OpenhclControllerType::Scsi,
@@ -510,8 +554,12 @@ async fn vm_config_from_command_line(
         nsid,
         lun,
         ref relay,
+        queues,
     } in &opt.disk
     {
+        if queues.is_some() {
+            anyhow::bail!("`queues` is only supported with --virtio-blk");
+        }
         if controller.is_none() && underhill.is_none() && relay.is_none() {
             tracing::warn!(
                 "--disk without `on` is deprecated; \
@@ -614,8 +662,12 @@ async fn vm_config_from_command_line(
         nsid: _,
         lun: _,
         relay: _,
+        queues,
     } in &opt.nvme
     {
+        if queues.is_some() {
+            anyhow::bail!("`queues` is only supported with --virtio-blk");
+        }
         let target = if let Some(port) = pcie_port {
             storage_builder::DiskLocation::Named {
                 controller: port.clone(),
@@ -641,6 +693,7 @@ async fn vm_config_from_command_line(
         nsid: _,
         lun: _,
         relay: _,
+        queues,
     } in &opt.virtio_blk
     {
         if underhill.is_some() {
@@ -651,7 +704,7 @@ async fn vm_config_from_command_line(
                 vtl,
                 None,
                 None,
-                storage_builder::DiskLocation::VirtioBlk(pcie_port.clone()),
+                storage_builder::DiskLocation::VirtioBlk(pcie_port.clone(), queues),
                 kind,
                 is_dvd,
                 read_only,
@@ -711,10 +764,14 @@ async fn vm_config_from_command_line(
                 endpoint: EndpointConfigCli::Consomme {
                     cidr: None,
                     host_fwd: Vec::new(),
+                    tftp: None,
+                    boot_file: None,
+                    egress: None,
                 },
                 max_queues: None,
                 underhill: false,
                 pcie_port: None,
+                impairment: None,
             },
             &mut nic_index,
             &mut resources,
@@ -1120,7 +1177,7 @@ async fn vm_config_from_command_line(
         None
     };
 
-    let framebuffer = if opt.gfx || opt.vtl2_gfx || opt.vnc.vnc || opt.pcat {
+    let framebuffer = if opt.gfx || opt.vtl2_gfx || opt.vnc.vnc || opt.pcat || opt.virtio_gpu {
         let vram = alloc_shared_memory(FRAMEBUFFER_SIZE, "vram")?;
         let (fb, fba) =
             framebuffer::framebuffer(vram, FRAMEBUFFER_SIZE, 0).context("creating framebuffer")?;
@@ -1243,9 +1300,10 @@ async fn vm_config_from_command_line(
         .build()
         .context("failed to build chipset configuration")?;
 
-    if opt.restore_snapshot.is_some() {
-        // Snapshot restore: skip firmware loading entirely. Device state and
-        // memory come from the snapshot directory.
+    if opt.restore_snapshot.is_some() || opt.migrate_listen.is_some() {
+        // Snapshot restore or incoming migration: skip firmware loading
+        // entirely. Device state and memory come from the snapshot directory
+        // or the migration source.
         load_mode = LoadMode::None;
         with_hv = true;
     } else if let Some(path) = &opt.igvm {
@@ -1640,11 +1698,17 @@ async fn vm_config_from_command_line(
         None
     };
 
+    let mut vss_ic = None;
+    let mut heartbeat_ic = None;
     if with_hv && !opt.no_vmbus {
         let (shutdown_send, shutdown_recv) = mesh::channel();
         resources.shutdown_ic = Some(shutdown_send);
         let (kvp_send, kvp_recv) = mesh::channel();
         resources.kvp_ic = Some(kvp_send);
+        let (vss_send, vss_recv) = mesh::channel();
+        vss_ic = Some(vss_send);
+        let (heartbeat_send, heartbeat_recv) = mesh::channel();
+        heartbeat_ic = Some(heartbeat_send);
         vmbus_devices.extend(
             [
                 hyperv_ic_resources::shutdown::ShutdownIcHandle {
@@ -1653,6 +1717,11 @@ async fn vm_config_from_command_line(
                 .into_resource(),
                 hyperv_ic_resources::kvp::KvpIcHandle { recv: kvp_recv }.into_resource(),
                 hyperv_ic_resources::timesync::TimesyncIcHandle.into_resource(),
+                hyperv_ic_resources::vss::VssIcHandle { recv: vss_recv }.into_resource(),
+                hyperv_ic_resources::heartbeat::HeartbeatIcHandle {
+                    recv: heartbeat_recv,
+                }
+                .into_resource(),
             ]
             .map(|r| (DeviceVtl::Vtl0, r)),
         );
@@ -1797,6 +1866,67 @@ async fn vm_config_from_command_line(
         }
     }
 
+    let mut balloon_control = None;
+    if opt.virtio_balloon {
+        let (send, recv) = mesh::channel();
+        balloon_control = Some(send);
+        let resource: Resource<VirtioDeviceHandle> =
+            virtio_resources::balloon::VirtioBalloonHandle {
+                deflate_on_oom: true,
+                free_page_reporting: true,
+                control: recv,
+            }
+            .into_resource();
+        if let Some(pcie_port) = &opt.virtio_balloon_pcie_port {
+            pcie_devices.push(PcieDeviceConfig {
+                port_name: pcie_port.clone(),
+                resource: VirtioPciDeviceHandle(resource).into_resource(),
+            });
+        } else {
+            add_virtio_device(opt.virtio_balloon_bus, resource);
+        }
+    }
+
+    if opt.virtio_gpu {
+        // Channel for the GPU to report dirty rectangles to the VNC worker.
+        let (dirt_send, dirt_recv) = mesh::channel();
+        resources.dirty_rect_recv = Some(dirt_recv);
+        let resource: Resource<VirtioDeviceHandle> = virtio_resources::gpu::VirtioGpuHandle {
+            framebuffer: SharedFramebufferHandle.into_resource(),
+            dirt_send: Some(dirt_send),
+        }
+        .into_resource();
+        add_virtio_device(opt.virtio_gpu_bus, resource);
+    }
+
+    for kind in [
+        VirtioInputCli::Keyboard,
+        VirtioInputCli::Mouse,
+        VirtioInputCli::Tablet,
+    ] {
+        if !opt.virtio_input.contains(&kind) {
+            continue;
+        }
+        // Above PS/2 and synthetic input, so that the virtio devices receive
+        // input once their driver is running. The tablet is above the mouse
+        // since it tracks the host pointer exactly.
+        let resource: Resource<VirtioDeviceHandle> = match kind {
+            VirtioInputCli::Keyboard => virtio_resources::input::VirtioKeyboardHandle {
+                source: MultiplexedInputHandle { elevation: 2 }.into_resource(),
+            }
+            .into_resource(),
+            VirtioInputCli::Mouse => virtio_resources::input::VirtioMouseHandle {
+                source: MultiplexedInputHandle { elevation: 2 }.into_resource(),
+            }
+            .into_resource(),
+            VirtioInputCli::Tablet => virtio_resources::input::VirtioTabletHandle {
+                source: MultiplexedInputHandle { elevation: 3 }.into_resource(),
+            }
+            .into_resource(),
+        };
+        add_virtio_device(opt.virtio_input_bus, resource);
+    }
+
     if let Some(backend) = virtio_console_backend {
         let resource: Resource<VirtioDeviceHandle> =
             virtio_resources::console::VirtioConsoleHandle { backend }.into_resource();
@@ -2011,6 +2141,9 @@ async fn vm_config_from_command_line(
         vga_firmware,
         vtl2_gfx: opt.vtl2_gfx,
         virtio_devices,
+        balloon_control,
+        vss_ic,
+        heartbeat_ic,
         vmbus: (with_hv && !opt.no_vmbus).then_some(VmbusConfig {
             vsock_listener: vtl0_vsock_listener,
             vsock_path: opt.vmbus_vsock_path.clone(),
@@ -2115,6 +2248,65 @@ fn cleanup_socket(path: &Path) {
     }
 }
 
+/// Returns a sender for attaching ports to the software Ethernet switch at
+/// `path`, connecting to the process serving it or, if none is, hosting it in
+/// this process.
+fn attach_switch(
+    resources: &mut VmResources,
+    path: &Path,
+) -> anyhow::Result<mesh::Sender<net_backend_resources::switch::SwitchRequest>> {
+    if let Some(switch) = resources.switches.get(path) {
+        return Ok(switch.sender());
+    }
+
+    let driver = resources
+        .switch_driver
+        .get_or_insert_with(|| DefaultPool::spawn_on_thread("switch").1)
+        .clone();
+    let switch = match unix_socket::UnixStream::connect(path) {
+        Ok(stream) => SwitchAttachment::Remote(
+            net_switch::socket::SwitchClient::new(&driver, stream)
+                .context("failed to connect to switch")?,
+        ),
+        Err(err)
+            if matches!(
+                err.kind(),
+                std::io::ErrorKind::NotFound | std::io::ErrorKind::ConnectionRefused
+            ) =>
+        {
+            // Nothing is serving the switch, so host it here, replacing any
+            // stale socket left by a previous host.
+            cleanup_socket(path);
+            let listener = unix_socket::UnixListener::bind(path)
+                .with_context(|| format!("failed to bind switch socket {}", path.display()))?;
+            let switch = net_switch::Switch::new(&driver);
+            let server = driver.spawn("switch-server", {
+                let driver = driver.clone();
+                let sender = switch.sender();
+                async move {
+                    if let Err(err) = net_switch::socket::serve(&driver, listener, sender).await {
+                        tracing::error!(
+                            error = &err as &dyn std::error::Error,
+                            "switch server failed"
+                        );
+                    }
+                }
+            });
+            SwitchAttachment::Local {
+                switch,
+                _server: server,
+            }
+        }
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("failed to connect to switch at {}", path.display()));
+        }
+    };
+    let sender = switch.sender();
+    resources.switches.insert(path.to_owned(), switch);
+    Ok(sender)
+}
+
 #[cfg(windows)]
 fn new_switch_port(
     switch_id: Option<&str>,
@@ -2148,7 +2340,13 @@ fn parse_endpoint(
 ) -> anyhow::Result<NicConfig> {
     let _ = resources;
     let endpoint = match &cli_cfg.endpoint {
-        EndpointConfigCli::Consomme { cidr, host_fwd } => {
+        EndpointConfigCli::Consomme {
+            cidr,
+            host_fwd,
+            tftp,
+            boot_file,
+            egress,
+        } => {
             let ports = host_fwd
                 .iter()
                 .map(|fwd| {
@@ -2180,10 +2378,18 @@ fn parse_endpoint(
                 cidr: cidr.clone(),
                 ports,
                 recv,
+                tftp_root: tftp.clone(),
+                boot_file: boot_file.clone(),
+                egress_policy: egress.clone(),
             }
             .into_resource()
         }
         EndpointConfigCli::None => net_backend_resources::null::NullHandle.into_resource(),
+        EndpointConfigCli::Switch { path, vlan } => net_backend_resources::switch::SwitchHandle {
+            switch: attach_switch(resources, Path::new(path))?,
+            vlan: *vlan,
+        }
+        .into_resource(),
         EndpointConfigCli::Dio { id } => {
             #[cfg(windows)]
             {
@@ -2204,22 +2410,44 @@ fn parse_endpoint(
                 bail!("cannot use dio on non-windows platforms")
             }
         }
-        EndpointConfigCli::Tap { name } => {
+        EndpointConfigCli::Tap { name, vhost } => {
             #[cfg(target_os = "linux")]
             {
                 let fd = net_tap::tap::open_tap(name)
                     .with_context(|| format!("failed to open TAP device '{name}'"))?;
-                net_backend_resources::tap::TapHandle { fd }.into_resource()
+                let vhost = if *vhost {
+                    Some(
+                        std::fs::OpenOptions::new()
+                            .read(true)
+                            .write(true)
+                            .open("/dev/vhost-net")
+                            .context("failed to open /dev/vhost-net")?
+                            .into(),
+                    )
+                } else {
+                    None
+                };
+                net_backend_resources::tap::TapHandle { fd, vhost }.into_resource()
             }
 
             #[cfg(not(target_os = "linux"))]
             {
-                let _ = name;
+                let _ = (name, vhost);
                 bail!("TAP backend is only supported on Linux")
             }
         }
     };
 
+    let endpoint = if let Some(config) = &cli_cfg.impairment {
+        net_backend_resources::impair::ImpairedHandle {
+            endpoint,
+            config: config.clone(),
+        }
+        .into_resource()
+    } else {
+        endpoint
+    };
+
     // Pick a random MAC address.
     let mut mac_address = [0x00, 0x15, 0x5D, 0, 0, 0];
     getrandom::fill(&mut mac_address[3..]).expect("rng failure");
@@ -2344,6 +2572,9 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
                 .await
                 .with_context(|| format!("failed to open {}", path.display()))?
             })),
+            DiskCliKind::Qcow2 { path } => {
+                layers.push(LayerOrDisk::Disk(open_qcow2_disk(path, read_only)?))
+            }
             DiskCliKind::Blob { kind, url } => {
                 layers.push(disk(disk_backend_resources::BlobDiskHandle {
                     url: url.to_owned(),
@@ -2374,6 +2605,21 @@ fn disk<T: IntoResource<DiskHandleKind>>(disk: T) -> LayerOrDisk {
                 delay: CellUpdater::new(Duration::from_millis(*delay_ms)).cell(),
                 disk: disk_open(inner, read_only).await?,
             })),
+            DiskCliKind::Nbd { address, export } => layers.push(disk(NbdDiskHandle {
+                address: address.clone(),
+                export_name: export.clone(),
+            })),
+            DiskCliKind::NbdExport { path, disk: inner } => {
+                cleanup_socket(path);
+                let listener = unix_socket::UnixListener::bind(path)
+                    .with_context(|| format!("failed to bind to nbd socket {}", path.display()))?;
+                layers.push(disk(NbdExportDiskHandle {
+                    disk: disk_open(inner, read_only).await?,
+                    listener: listener.into(),
+                    export_name: String::new(),
+                    read_only: true,
+                }))
+            }
             DiskCliKind::Crypt {
                 disk: inner,
                 cipher,
@@ -2477,10 +2723,16 @@ pub(crate) fn system_page_size() -> u32 {
 };
 
 /// Open a snapshot directory and validate it against the current VM config.
-/// Returns the shared memory fd (from memory.bin) and the saved device state.
-fn prepare_snapshot_restore(
+/// Returns the shared memory fd and the saved device state.
+///
+/// Snapshots that link the memory backing file run directly from their
+/// memory.bin. Snapshots that store memory pages are rebuilt into the memory
+/// backing file, which must not already exist.
+pub(crate) fn prepare_snapshot_restore(
     snapshot_dir: &Path,
-    opt: &Options,
+    memory_size: u64,
+    processors: u32,
+    memory_backing_file: Option<&Path>,
 ) -> anyhow::Result<(
     openvmm_defs::worker::SharedMemoryFd,
     mesh::payload::message::ProtobufMessage,
@@ -2491,16 +2743,41 @@ fn prepare_snapshot_restore(
     openvmm_helpers::snapshot::validate_manifest(
         &manifest,
         GUEST_ARCH,
-        opt.memory_size(),
-        opt.processors,
+        memory_size,
+        processors,
         system_page_size(),
     )?;
 
-    // Open memory.bin (existing file, no create, no resize).
-    let memory_file = fs_err::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(snapshot_dir.join("memory.bin"))?;
+    let memory_file = if openvmm_helpers::snapshot::has_memory_pages(snapshot_dir) {
+        let memory_path = memory_backing_file.context(
+            "restoring a snapshot with copied memory requires a memory backing file (--memory file=<path>) to hold guest memory",
+        )?;
+        let snapshot_memory = openvmm_helpers::snapshot::SnapshotMemory::open(snapshot_dir)?;
+        let memory_file = fs_err::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(memory_path)
+            .context("failed to create memory backing file for snapshot restore")?;
+        memory_file
+            .set_len(snapshot_memory.memory_size())
+            .context("failed to set memory backing file size")?;
+        snapshot_memory
+            .write_to(memory_file.file())
+            .context("failed to restore snapshot memory")?;
+        memory_file
+    } else {
+        if memory_backing_file.is_some() {
+            anyhow::bail!(
+                "a memory backing file (--memory file=...) cannot be used to restore a snapshot that links its memory.bin"
+            );
+        }
+        // Open memory.bin (existing file, no create, no resize).
+        fs_err::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(snapshot_dir.join("memory.bin"))?
+    };
 
     // Validate file size matches expected memory size.
     let file_size = memory_file.metadata()?.len();
@@ -2523,6 +2800,64 @@ fn prepare_snapshot_restore(
     Ok((shared_memory_fd, state_msg))
 }
 
+/// Wait for an incoming live migration and receive the VM's memory and device
+/// state. Returns the shared memory fd, the saved device state, and the
+/// migration target, which must be completed once the VM is running.
+async fn receive_migration(
+    addr: &MigrationAddr,
+    opt: &Options,
+) -> anyhow::Result<(
+    openvmm_defs::worker::SharedMemoryFd,
+    mesh::payload::message::ProtobufMessage,
+    MigrationTarget<MigrationStream>,
+)> {
+    let memory_path = opt
+        .memory_backing_file()
+        .context("--migrate-listen requires a memory backing file")?;
+
+    // Create a new file so that guest RAM starts out zeroed; the source does
+    // not send zero pages.
+    let memory_file = fs_err::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(memory_path)
+        .context("failed to create memory backing file for incoming migration")?;
+    memory_file
+        .set_len(opt.memory_size())
+        .context("failed to set memory backing file size")?;
+    let memory_file: std::fs::File = memory_file.into();
+
+    let listener = addr
+        .listen()
+        .with_context(|| format!("failed to listen on {addr}"))?;
+    tracing::info!(%addr, "waiting for incoming migration");
+
+    let (memory_size, vp_count) = (opt.memory_size(), opt.processors);
+    let (memory_file, state_bytes, target) = blocking::unblock(move || {
+        let stream = listener.accept()?;
+        let mut target = MigrationTarget::accept(stream, |manifest| {
+            openvmm_helpers::snapshot::validate_manifest(
+                manifest,
+                GUEST_ARCH,
+                memory_size,
+                vp_count,
+                system_page_size(),
+            )
+        })?;
+        let state_bytes = target.receive(&memory_file)?;
+        anyhow::Ok((memory_file, state_bytes, target))
+    })
+    .await
+    .context("failed to receive migration")?;
+
+    let shared_memory_fd = openvmm_helpers::shared_memory::file_to_shared_memory_fd(memory_file)?;
+    let state_msg: mesh::payload::message::ProtobufMessage = mesh::payload::decode(&state_bytes)
+        .context("failed to decode saved state from migration")?;
+
+    Ok((shared_memory_fd, state_msg, target))
+}
+
 fn do_main(pidfile_guard: &mut Option<pidfile::Pidfile>) -> anyhow::Result<i32> {
     #[cfg(windows)]
     pal::windows::disable_hard_error_dialog();
@@ -2629,7 +2964,7 @@ async fn run_control_inner(
     let (mut vm_config, mut resources) = vm_config_from_command_line(driver, mesh, &opt).await?;
 
     let mut vnc_worker = None;
-    if opt.gfx || opt.vnc.vnc {
+    if opt.gfx || opt.vnc.vnc || opt.virtio_gpu {
         // Parse the listen address. Try as a full SocketAddr (host:port) first;
         // fall back to a bare IP, using the configured port.
         let addr: std::net::SocketAddr = if let Ok(sa) =
@@ -2710,12 +3045,15 @@ async fn run_control_inner(
     }
 
     // spin up the debug worker
+    let mut gdb_inspect = None;
     let gdb_worker = if let Some(port) = opt.gdb {
         let listener = TcpListener::bind(format!("127.0.0.1:{}", port))
             .with_context(|| format!("binding to gdb port {}", port))?;
 
         let (req_tx, req_rx) = mesh::channel();
         vm_config.debugger_rpc = Some(req_rx);
+        let (inspect_tx, inspect_rx) = mesh::channel();
+        gdb_inspect = Some(inspect_rx);
 
         let gdb_host = mesh
             .make_host("gdb", None)
@@ -2735,6 +3073,7 @@ async fn run_control_inner(
                         } else {
                             debug_worker_defs::TargetArch::Aarch64
                         },
+                        inspect: Some(inspect_tx),
                     },
                 )
                 .await
@@ -2747,11 +3086,21 @@ async fn run_control_inner(
     // spin up the VM
     let (vm_rpc, rpc_recv) = mesh::channel();
     let (notify_send, notify_recv) = mesh::channel();
+    let mut incoming_migration = None;
     let vm_worker = {
         let vm_host = mesh.make_host("vm", opt.log_file.clone()).await?;
 
         let (shared_memory, saved_state) = if let Some(snapshot_dir) = &opt.restore_snapshot {
-            let (fd, state_msg) = prepare_snapshot_restore(snapshot_dir, &opt)?;
+            let (fd, state_msg) = prepare_snapshot_restore(
+                snapshot_dir,
+                opt.memory_size(),
+                opt.processors,
+                opt.memory_backing_file().map(|p| p.as_path()),
+            )?;
+            (Some(fd), Some(state_msg))
+        } else if let Some(addr) = &opt.migrate_listen {
+            let (fd, state_msg, target) = receive_migration(addr, &opt).await?;
+            incoming_migration = Some(target);
             (Some(fd), Some(state_msg))
         } else {
             let shared_memory = opt
@@ -2791,6 +3140,15 @@ async fn run_control_inner(
         vm_rpc.call(VmRpc::Resume, ()).await?;
     }
 
+    if let Some(target) = incoming_migration {
+        // Tell the source that the VM is running here. If restoring failed
+        // above, the source sees the connection drop and resumes its VM.
+        blocking::unblock(move || target.complete())
+            .await
+            .context("failed to complete incoming migration")?;
+        tracing::info!("incoming migration complete");
+    }
+
     let paravisor_diag = Arc::new(diag_client::DiagClient::from_dialer(
         driver.clone(),
         DiagDialer {
@@ -2822,6 +3180,7 @@ async fn run_control_inner(
         vm_worker,
         vnc_worker,
         gdb_worker,
+        gdb_inspect,
         diag_inspector: Some(diag_inspector),
         vtl2_settings: resources.vtl2_settings,
         ged_rpc: resources.ged_rpc.clone(),
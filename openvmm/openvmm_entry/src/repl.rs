@@ -14,6 +14,7 @@
 use crate::vm_controller::InspectTarget;
 use crate::vm_controller::RemoveVtl0ScsiDiskByNvmeNsidParams;
 use crate::vm_controller::RemoveVtl0ScsiDiskParams;
+use crate::vm_controller::SaveSnapshotParams;
 use crate::vm_controller::ServiceVtl2Params;
 use crate::vm_controller::VmControllerEvent;
 use crate::vm_controller::VmControllerRpc;
@@ -36,6 +37,8 @@
 use mesh::rpc::RpcError;
 use mesh::rpc::RpcSend;
 use net_backend_resources::consomme::ConsommeRequest;
+use net_backend_resources::consomme::EgressPolicyConfig;
+use net_backend_resources::consomme::HostIpAddress;
 use net_backend_resources::consomme::HostPort;
 use net_backend_resources::consomme::HostPortConfig;
 use net_backend_resources::consomme::HostPortProtocol;
@@ -56,6 +59,9 @@
 #[cfg(unix)]
 use std::io::IsTerminal;
 use std::io::Read;
+use std::net::IpAddr;
+use std::net::Ipv6Addr;
+use std::net::SocketAddr;
 use std::path::PathBuf;
 use std::pin::pin;
 use std::sync::Arc;
@@ -65,6 +71,7 @@
 use storvsp_resources::ScsiDeviceAndPath;
 use storvsp_resources::ScsiPath;
 use tracing_helpers::AnyhowValueExt;
+use virtio_resources::balloon::BalloonStatus;
 use vm_resource::IntoResource;
 use vm_resource::Resource;
 
@@ -82,6 +89,41 @@ fn maybe_with_radix_u64(s: &str) -> Result<u64, String> {
     u64::from_str_radix(&s[prefix_len..], radix).map_err(|e| format!("{e}"))
 }
 
+fn print_balloon_status(status: &BalloonStatus) {
+    const MB: u64 = 1024 * 1024;
+    println!("target:   {} MB", status.target / MB);
+    println!("actual:   {} MB", status.actual / MB);
+    println!("reported: {} MB", status.reported / MB);
+    let Some(stats) = &status.stats else {
+        println!("no guest statistics");
+        return;
+    };
+    let memory = [
+        ("free", stats.free_memory),
+        ("total", stats.total_memory),
+        ("available", stats.available_memory),
+        ("disk caches", stats.disk_caches),
+        ("swapped in", stats.swap_in),
+        ("swapped out", stats.swap_out),
+    ];
+    for (name, value) in memory {
+        if let Some(value) = value {
+            println!("{name}: {} MB", value / MB);
+        }
+    }
+    let counts = [
+        ("major faults", stats.major_faults),
+        ("minor faults", stats.minor_faults),
+        ("hugetlb allocations", stats.hugetlb_allocations),
+        ("hugetlb failures", stats.hugetlb_failures),
+    ];
+    for (name, value) in counts {
+        if let Some(value) = value {
+            println!("{name}: {value}");
+        }
+    }
+}
+
 #[derive(Parser)]
 #[clap(
     name = "openvmm",
@@ -110,10 +152,35 @@ enum InteractiveCommand {
     Resume,
 
     /// Save a snapshot to a directory (requires --memory-backing-file).
+    ///
+    /// By default the snapshot links the memory backing file, and the VM
+    /// stays paused afterwards. With --copy, --parent or --compress, guest
+    /// memory is copied into the snapshot instead, and the VM keeps running.
     #[clap(visible_alias = "snap")]
     SaveSnapshot {
         /// Directory to write the snapshot to.
         dir: PathBuf,
+        /// Copy the non-zero pages of guest memory into the snapshot.
+        #[clap(long)]
+        copy: bool,
+        /// Only copy the pages that differ from this earlier snapshot, which
+        /// must also have been saved with copied memory. Implies --copy.
+        #[clap(long, value_name = "DIR")]
+        parent: Option<PathBuf>,
+        /// Compress the copied pages. Implies --copy.
+        #[clap(long)]
+        compress: bool,
+        /// Freeze the guest's filesystems via the VSS integration component
+        /// first, so that the snapshot is application consistent.
+        #[clap(long)]
+        app_consistent: bool,
+    },
+
+    /// Live migrate the VM to another OpenVMM process started with
+    /// --migrate-listen (requires --memory-backing-file).
+    Migrate {
+        /// The destination address: a Unix socket path or tcp:<ip>:<port>.
+        addr: String,
     },
 
     /// Dump VM state (VP registers + memory) to a .vmrs file for WinDbg.
@@ -325,6 +392,9 @@ enum InteractiveCommand {
         /// The protocol to forward (tcp or udp).
         #[clap(long, default_value = "tcp")]
         protocol: PortProtocolArg,
+        /// The host address to listen on. Defaults to all IPv4 addresses.
+        #[clap(long)]
+        address: Option<IpAddr>,
         /// The host port to listen on.
         host_port: u16,
         /// The guest port to forward to.
@@ -336,9 +406,42 @@ enum InteractiveCommand {
         /// The protocol of the port to unbind (tcp or udp).
         #[clap(long, default_value = "tcp")]
         protocol: PortProtocolArg,
+        /// Unbind the IPv6 forward instead of the IPv4 one.
+        #[clap(long)]
+        ipv6: bool,
         /// The guest port to unbind.
         guest_port: u16,
     },
+
+    /// List the ports forwarded to the guest (consomme).
+    ListPorts,
+
+    /// Replace the egress policy for flows initiated by the guest (consomme).
+    ///
+    /// The policy takes the `egress=`, `allow=` and `deny=` options of
+    /// `--net consomme`, separated by commas, e.g.
+    /// `egress=deny,allow=tcp:10.0.0.0/8:443`. With no policy, all flows are
+    /// allowed. Open TCP connections that the new policy denies are reset.
+    SetEgress {
+        /// The new policy.
+        #[clap(value_parser = crate::cli_args::parse_egress_policy)]
+        policy: Option<EgressPolicyConfig>,
+    },
+
+    /// Show the virtio balloon's state, or set its target size.
+    ///
+    /// The target is the amount of memory the guest should give back to the
+    /// host, e.g. `2G`. With no target, the balloon's state and the guest's
+    /// latest memory statistics are shown.
+    Balloon {
+        /// The new balloon size.
+        #[clap(value_parser = crate::cli_args::parse_memory)]
+        target: Option<u64>,
+    },
+
+    /// Show the guest's liveness, as reported by the heartbeat integration
+    /// component.
+    Heartbeat,
 }
 
 /// Subcommands for managing VTL2 settings.
@@ -601,6 +704,7 @@ pub(crate) async fn run_repl(
     let mut pulse_save_restore_interval: Option<Duration> = None;
     let mut pending_shutdown = None;
     let mut snapshot_saved = false;
+    let mut migrated = false;
 
     enum StateChange {
         Pause(bool),
@@ -791,6 +895,9 @@ enum Event {
                     VmControllerEvent::GuestHalt(reason) => {
                         tracing::info!(reason = reason.as_str(), "guest halted");
                     }
+                    VmControllerEvent::GuestCrashed(_) => {
+                        // The controller logs the halt reason.
+                    }
                     VmControllerEvent::ExitRequested { code } => break code,
                 }
                 continue;
@@ -844,6 +951,10 @@ fn state_change<U: 'static + Send>(
                     eprintln!(
                         "error: cannot resume after snapshot save — resuming would corrupt the snapshot. Use 'shutdown' to exit."
                     );
+                } else if migrated {
+                    eprintln!(
+                        "error: cannot resume after migration — the VM is running on the destination. Use 'shutdown' to exit."
+                    );
                 } else {
                     state_change(
                         driver,
@@ -863,16 +974,32 @@ fn state_change<U: 'static + Send>(
                     StateChange::Reset,
                 );
             }
-            InteractiveCommand::SaveSnapshot { dir } => {
+            InteractiveCommand::SaveSnapshot {
+                dir,
+                copy,
+                parent,
+                compress,
+                app_consistent,
+            } => {
+                let copy_memory = copy || parent.is_some() || compress;
                 match vm_controller
                     .call(
                         VmControllerRpc::SaveSnapshot,
-                        dir.to_string_lossy().into_owned(),
+                        SaveSnapshotParams {
+                            dir: dir.to_string_lossy().into_owned(),
+                            copy_memory,
+                            parent: parent.map(|p| p.to_string_lossy().into_owned()),
+                            compress,
+                            app_consistent,
+                        },
                     )
                     .await
                     .map_err(anyhow::Error::from)
                     .and_then(|r| Ok(r?))
                 {
+                    Ok(()) if copy_memory => {
+                        tracing::info!(dir = %dir.display(), "snapshot saved");
+                    }
                     Ok(()) => {
                         snapshot_saved = true;
                         tracing::info!(
@@ -887,6 +1014,27 @@ fn state_change<U: 'static + Send>(
                     }
                 }
             }
+            InteractiveCommand::Migrate { addr } => {
+                match vm_controller
+                    .call(VmControllerRpc::Migrate, addr.clone())
+                    .await
+                    .map_err(anyhow::Error::from)
+                    .and_then(|r| Ok(r?))
+                {
+                    Ok(()) => {
+                        migrated = true;
+                        tracing::info!(
+                            %addr,
+                            "migration complete; VM is paused. \
+                             Resume is blocked because the VM is running on the destination. \
+                             Use 'shutdown' to exit."
+                        );
+                    }
+                    Err(err) => {
+                        eprintln!("error: migrate failed: {err:#}");
+                    }
+                }
+            }
             InteractiveCommand::DumpState { path } => {
                 match vm_controller
                     .call(
@@ -1191,6 +1339,7 @@ fn state_change<U: 'static + Send>(
                         nsid,
                         read_only,
                         disk: disk_type,
+                        zoned: None,
                     };
 
                     nvme.call_failable(NvmeControllerRequest::AddNamespace, ns)
@@ -1519,6 +1668,7 @@ fn state_change<U: 'static + Send>(
             }
             InteractiveCommand::BindPort {
                 protocol,
+                address,
                 host_port,
                 guest_port,
             } => {
@@ -1526,7 +1676,7 @@ fn state_change<U: 'static + Send>(
                     let rpc = consomme_rpc.as_ref().context("no consomme device")?;
                     let cfg = HostPortConfig {
                         protocol: protocol.to_host_port_protocol(),
-                        host_address: None,
+                        host_address: address.map(HostIpAddress::from),
                         host_port: HostPort::Fixed(host_port),
                         guest_port,
                     };
@@ -1544,13 +1694,15 @@ fn state_change<U: 'static + Send>(
             }
             InteractiveCommand::UnbindPort {
                 protocol,
+                ipv6,
                 guest_port,
             } => {
                 let action = async {
                     let rpc = consomme_rpc.as_ref().context("no consomme device")?;
                     let cfg = HostPortConfig {
                         protocol: protocol.to_host_port_protocol(),
-                        host_address: None,
+                        // Only the address family is used to find the forward.
+                        host_address: ipv6.then_some(HostIpAddress::Ipv6(Ipv6Addr::UNSPECIFIED)),
                         host_port: HostPort::Fixed(0),
                         guest_port,
                     };
@@ -1566,6 +1718,71 @@ fn state_change<U: 'static + Send>(
                     }
                 }
             }
+            InteractiveCommand::ListPorts => {
+                let action = async {
+                    let rpc = consomme_rpc.as_ref().context("no consomme device")?;
+                    anyhow::Ok(rpc.call(ConsommeRequest::List, ()).await?)
+                };
+                match action.await {
+                    Ok(forwards) if forwards.is_empty() => println!("no forwarded ports"),
+                    Ok(forwards) => {
+                        for forward in forwards {
+                            let protocol = match forward.protocol {
+                                HostPortProtocol::Tcp => "tcp",
+                                HostPortProtocol::Udp => "udp",
+                            };
+                            let host_addr =
+                                SocketAddr::new(forward.host_address.into(), forward.host_port);
+                            println!("{protocol} {host_addr} -> {}", forward.guest_port);
+                        }
+                    }
+                    Err(err) => eprintln!("error: {err:#}"),
+                }
+            }
+            InteractiveCommand::SetEgress { policy } => {
+                let action = async {
+                    let rpc = consomme_rpc.as_ref().context("no consomme device")?;
+                    let policy = policy.unwrap_or(EgressPolicyConfig {
+                        rules: Vec::new(),
+                        default_allow: true,
+                    });
+                    rpc.call_failable(ConsommeRequest::SetEgressPolicy, policy)
+                        .await?;
+                    anyhow::Ok(())
+                };
+                match action.await {
+                    Ok(()) => tracing::info!("egress policy updated"),
+                    Err(error) => {
+                        tracing::error!(error = error.as_error(), "error updating egress policy");
+                    }
+                }
+            }
+            InteractiveCommand::Balloon { target } => {
+                if let Some(target) = target {
+                    match vm_rpc.call_failable(VmRpc::SetBalloonTarget, target).await {
+                        Ok(()) => tracing::info!(target, "balloon target set"),
+                        Err(err) => eprintln!("error: {err:#}"),
+                    }
+                } else {
+                    match vm_rpc.call_failable(VmRpc::QueryBalloon, ()).await {
+                        Ok(status) => print_balloon_status(&status),
+                        Err(err) => eprintln!("error: {err:#}"),
+                    }
+                }
+            }
+            InteractiveCommand::Heartbeat => {
+                // The heartbeat IC only answers once the guest has opened its
+                // channel.
+                match CancelContext::new()
+                    .with_timeout(Duration::from_secs(1))
+                    .until_cancelled(vm_rpc.call(VmRpc::GetHeartbeat, ()))
+                    .await
+                {
+                    Ok(Ok(status)) => println!("{status:?}"),
+                    Ok(Err(err)) => eprintln!("error: {err:#}"),
+                    Err(_) => println!("NoContact (guest has not connected)"),
+                }
+            }
             InteractiveCommand::Input { .. } | InteractiveCommand::InputMode => unreachable!(),
         }
     };
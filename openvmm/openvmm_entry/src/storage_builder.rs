@@ -29,6 +29,7 @@
 use storvsp_resources::StorvspIdeDeviceHandle;
 use virtio_resources::VirtioPciDeviceHandle;
 use virtio_resources::blk::VirtioBlkHandle;
+use virtio_resources::scsi::VirtioScsiHandle;
 use vm_resource::IntoResource;
 use vm_resource::Resource;
 use vm_resource::kind::DiskHandleKind;
@@ -67,7 +68,7 @@ pub(super) fn deterministic_guid(name: &str) -> Guid {
     }
 }
 
-/// Transport for a named NVMe controller.
+/// Transport for a named NVMe or virtio-scsi controller.
 #[derive(Clone, PartialEq)]
 pub enum NvmeControllerTransport {
     /// PCIe under a specific root port name.
@@ -91,10 +92,27 @@ struct ScsiControllerEntry {
     devices: Vec<ScsiDeviceAndPath>,
 }
 
-/// A named controller entry (NVMe or SCSI).
+struct VirtioScsiControllerEntry {
+    transport: NvmeControllerTransport,
+    num_queues: Option<u16>,
+    devices: Vec<ScsiDeviceAndPath>,
+}
+
+/// A named controller entry (NVMe, SCSI, or virtio-scsi).
 enum ControllerEntry {
     Nvme(NvmeControllerEntry),
     Scsi(ScsiControllerEntry),
+    VirtioScsi(VirtioScsiControllerEntry),
+}
+
+impl ControllerEntry {
+    fn kind(&self) -> &'static str {
+        match self {
+            ControllerEntry::Nvme(_) => "NVMe",
+            ControllerEntry::Scsi(_) => "SCSI",
+            ControllerEntry::VirtioScsi(_) => "virtio-scsi",
+        }
+    }
 }
 
 /// Protocol type for an OpenHCL-managed controller.
@@ -139,6 +157,7 @@ pub(super) struct StorageBuilder {
 struct VirtioBlkDisk {
     disk: Resource<DiskHandleKind>,
     read_only: bool,
+    num_queues: Option<u16>,
 }
 
 #[derive(Clone)]
@@ -148,13 +167,16 @@ pub enum DiskLocation {
     Scsi(Option<u8>),
     /// Implicit VTL0/VTL2 VPCI NVMe controller.
     Nvme(Option<u32>),
-    /// Named controller (NVMe or SCSI, resolved by name at add time).
+    /// Named controller (NVMe, SCSI, or virtio-scsi, resolved by name at add
+    /// time).
     Named {
         controller: String,
         nsid: Option<u32>,
         lun: Option<u8>,
     },
-    VirtioBlk(Option<String>),
+    /// Dedicated virtio-blk device, on the given PCIe port if any, with the
+    /// given number of request queues.
+    VirtioBlk(Option<String>, Option<u16>),
 }
 
 impl From<UnderhillDiskSource> for DiskLocation {
@@ -225,10 +247,7 @@ pub fn add_nvme_controller(
         requests: Option<mesh::Receiver<NvmeControllerRequest>>,
     ) -> anyhow::Result<()> {
         if let Some(existing) = self.controllers.get(&name) {
-            let kind = match existing {
-                ControllerEntry::Nvme(_) => "NVMe",
-                ControllerEntry::Scsi(_) => "SCSI",
-            };
+            let kind = existing.kind();
             anyhow::bail!(
                 "cannot add NVMe controller '{name}': name already used by a {kind} controller"
             );
@@ -254,10 +273,7 @@ pub fn add_scsi_controller(
         sub_channels: u16,
     ) -> anyhow::Result<()> {
         if let Some(existing) = self.controllers.get(&name) {
-            let kind = match existing {
-                ControllerEntry::Nvme(_) => "NVMe",
-                ControllerEntry::Scsi(_) => "SCSI",
-            };
+            let kind = existing.kind();
             anyhow::bail!(
                 "cannot add SCSI controller '{name}': name already used by a {kind} controller"
             );
@@ -274,6 +290,31 @@ pub fn add_scsi_controller(
         Ok(())
     }
 
+    /// Register a named virtio-scsi controller. These are only offered to
+    /// VTL0.
+    pub fn add_virtio_scsi_controller(
+        &mut self,
+        name: String,
+        transport: NvmeControllerTransport,
+        num_queues: Option<u16>,
+    ) -> anyhow::Result<()> {
+        if let Some(existing) = self.controllers.get(&name) {
+            let kind = existing.kind();
+            anyhow::bail!(
+                "cannot add virtio-scsi controller '{name}': name already used by a {kind} controller"
+            );
+        }
+        self.controllers.insert(
+            name,
+            ControllerEntry::VirtioScsi(VirtioScsiControllerEntry {
+                transport,
+                num_queues,
+                devices: Vec::new(),
+            }),
+        );
+        Ok(())
+    }
+
     /// Register an OpenHCL-managed controller (relay target).
     pub fn add_openhcl_controller(
         &mut self,
@@ -442,6 +483,7 @@ async fn add_inner(
                     nsid,
                     disk,
                     read_only,
+                    zoned: None,
                 });
                 Some(nsid)
             }
@@ -467,6 +509,7 @@ async fn add_inner(
                         nsid,
                         disk,
                         read_only,
+                        zoned: None,
                     });
                     Some(nsid)
                 }
@@ -502,18 +545,61 @@ async fn add_inner(
                     });
                     Some(lun.into())
                 }
+                Some(ControllerEntry::VirtioScsi(scsi)) => {
+                    if nsid.is_some() {
+                        anyhow::bail!(
+                            "`nsid` is not valid for virtio-scsi controller '{controller}'"
+                        );
+                    }
+                    if vtl != DeviceVtl::Vtl0 {
+                        anyhow::bail!("virtio-scsi only supported for VTL0");
+                    }
+                    let device = if is_dvd {
+                        SimpleScsiDvdHandle {
+                            media: Some(disk),
+                            requests: None,
+                        }
+                        .into_resource()
+                    } else {
+                        SimpleScsiDiskHandle {
+                            disk,
+                            read_only,
+                            parameters: Default::default(),
+                        }
+                        .into_resource()
+                    };
+                    let lun = lun.unwrap_or(scsi.devices.len() as u8);
+                    if scsi.devices.iter().any(|d| d.path.lun == lun) {
+                        anyhow::bail!(
+                            "duplicate LUN {lun} on virtio-scsi controller '{controller}'"
+                        );
+                    }
+                    scsi.devices.push(ScsiDeviceAndPath {
+                        path: ScsiPath {
+                            path: 0,
+                            target: 0,
+                            lun,
+                        },
+                        device,
+                    });
+                    None
+                }
                 None => {
                     anyhow::bail!("unknown controller: '{controller}'");
                 }
             },
-            DiskLocation::VirtioBlk(pcie_port) => {
+            DiskLocation::VirtioBlk(pcie_port, num_queues) => {
                 if vtl != DeviceVtl::Vtl0 {
                     anyhow::bail!("virtio-blk only supported for VTL0");
                 }
                 if is_dvd {
                     anyhow::bail!("dvd not supported with virtio-blk");
                 }
-                let vblk = VirtioBlkDisk { disk, read_only };
+                let vblk = VirtioBlkDisk {
+                    disk,
+                    read_only,
+                    num_queues,
+                };
                 if let Some(port) = pcie_port {
                     self.pcie_virtio_blk_disks.push((port, vblk));
                 } else {
@@ -557,6 +643,9 @@ async fn add_relay(
                     vtl2_settings_proto::physical_device::DeviceType::Vscsi,
                     scsi.instance_id,
                 ),
+                Some(ControllerEntry::VirtioScsi(_)) => {
+                    anyhow::bail!("OpenHCL relay does not support virtio-scsi source controllers");
+                }
                 None => {
                     anyhow::bail!("unknown source controller: '{controller}'");
                 }
@@ -654,7 +743,7 @@ async fn add_underhill(
                     NVME_VTL0_INSTANCE_ID
                 },
             ),
-            DiskLocation::VirtioBlk(_) => {
+            DiskLocation::VirtioBlk(..) => {
                 anyhow::bail!("OpenHCL relay not supported with virtio-blk")
             }
             DiskLocation::Named { .. } => {
@@ -674,7 +763,7 @@ async fn add_underhill(
                 let nsid = nsid.unwrap_or(self.underhill_nvme_luns.len() as u32 + 1);
                 (&mut self.underhill_nvme_luns, nsid)
             }
-            DiskLocation::VirtioBlk(_) => {
+            DiskLocation::VirtioBlk(..) => {
                 anyhow::bail!("OpenHCL relay not supported with virtio-blk")
             }
             DiskLocation::Named { .. } => {
@@ -779,6 +868,33 @@ pub fn build_config(
                         .into_resource(),
                     ));
                 }
+                ControllerEntry::VirtioScsi(ctrl) => {
+                    let resource = VirtioPciDeviceHandle(
+                        VirtioScsiHandle {
+                            devices: ctrl.devices,
+                            requests: None,
+                            num_queues: ctrl.num_queues,
+                        }
+                        .into_resource(),
+                    )
+                    .into_resource();
+                    match ctrl.transport {
+                        NvmeControllerTransport::Pcie(port_name) => {
+                            config.pcie_devices.push(PcieDeviceConfig {
+                                port_name,
+                                resource,
+                            });
+                        }
+                        NvmeControllerTransport::Vpci(instance_id) => {
+                            config.vpci_devices.push(VpciDeviceConfig {
+                                vtl: DeviceVtl::Vtl0,
+                                instance_id,
+                                resource,
+                                vnode: None,
+                            });
+                        }
+                    }
+                }
                 ControllerEntry::Nvme(ctrl) => {
                     let subsystem_id = deterministic_guid(&name);
                     match ctrl.transport {
@@ -790,6 +906,7 @@ pub fn build_config(
                                     namespaces: ctrl.namespaces,
                                     max_io_queues: 64,
                                     msix_count: 64,
+                                    disk_pool: Vec::new(),
                                     requests: ctrl.requests,
                                 }
                                 .into_resource(),
@@ -804,6 +921,7 @@ pub fn build_config(
                                     namespaces: ctrl.namespaces,
                                     max_io_queues: 64,
                                     msix_count: 64,
+                                    disk_pool: Vec::new(),
                                     requests: ctrl.requests,
                                 }
                                 .into_resource(),
@@ -834,6 +952,7 @@ pub fn build_config(
                     namespaces: std::mem::take(&mut self.vtl0_nvme_namespaces),
                     max_io_queues: 64,
                     msix_count: 64,
+                    disk_pool: Vec::new(),
                     requests: None,
                 }
                 .into_resource(),
@@ -872,6 +991,7 @@ pub fn build_config(
                     namespaces: std::mem::take(&mut self.vtl2_nvme_namespaces),
                     max_io_queues: 64,
                     msix_count: 64,
+                    disk_pool: Vec::new(),
                     requests: Some(recv),
                 }
                 .into_resource(),
@@ -893,6 +1013,7 @@ pub fn build_config(
                     VirtioBlkHandle {
                         disk: vblk.disk,
                         read_only: vblk.read_only,
+                        num_queues: vblk.num_queues,
                     }
                     .into_resource(),
                 )
@@ -908,6 +1029,7 @@ pub fn build_config(
                     VirtioBlkHandle {
                         disk: vblk.disk,
                         read_only: vblk.read_only,
+                        num_queues: vblk.num_queues,
                     }
                     .into_resource(),
                 )
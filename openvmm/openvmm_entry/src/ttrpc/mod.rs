@@ -21,18 +21,25 @@ struct FdRegistry {}
 
 use crate::cli_args::GuestPowerAction;
 use crate::meshworker::VmmMesh;
+use crate::serial_io::anonymous_serial_pair;
 use crate::serial_io::bind_serial;
 use crate::serial_io::connect_serial;
 use crate::vm_controller::GuestPowerActions;
 use crate::vm_controller::InspectTarget;
+use crate::vm_controller::SaveSnapshotParams;
 use crate::vm_controller::VmController;
 use crate::vm_controller::VmControllerEvent;
 use crate::vm_controller::VmControllerRpc;
 use anyhow::Context;
 use anyhow::anyhow;
 use anyhow::bail;
+use futures::AsyncRead;
+use futures::AsyncReadExt;
+use futures::AsyncWrite;
+use futures::AsyncWriteExt;
 use futures::FutureExt;
 use futures::StreamExt;
+use futures_concurrency::future::Race;
 use guid::Guid;
 use inspect::InspectionBuilder;
 use inspect_proto::InspectResponse2;
@@ -42,8 +49,10 @@ struct FdRegistry {}
 use mesh::CancelReason;
 use mesh::MeshPayload;
 use mesh::error::RemoteError;
+use mesh::rpc::FailableRpc;
 use mesh::rpc::RpcSend;
 use mesh_rpc::service::Code;
+use mesh_rpc::service::RequestStream;
 use mesh_rpc::service::Status;
 use mesh_worker::Worker;
 use mesh_worker::WorkerId;
@@ -87,6 +96,8 @@ struct FdRegistry {}
 use scsidisk_resources::SimpleScsiDiskHandle;
 use std::fs::File;
 use std::future::Future;
+use std::path::Path;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use storvsp_resources::ScsiControllerHandle;
@@ -322,6 +333,7 @@ fn run(self, recv: mesh::Receiver<WorkerRpc<Self::State>>) -> anyhow::Result<()>
                 controller_task: None,
                 wait_vm_response: None,
                 lifecycle: VmLifecycle::Uninitialized,
+                vm_watchers: Vec::new(),
                 rpc_tasks: Vec::new(),
                 transport: self.transport,
                 registry: FdRegistry::default(),
@@ -340,6 +352,9 @@ async fn run(
     ) -> anyhow::Result<()> {
         let mut server = mesh_rpc::Server::new();
         let mut vm_service_recv = server.add_service::<vmservice::Vm>();
+        // ttrpc connections reject the streaming methods, so this service is
+        // only usable over gRPC.
+        let mut streaming_service_recv = server.add_service::<vmservice::VmStreaming>();
         let mut inspect_service_recv = server.add_service::<InspectService>();
 
         let transport = self.transport;
@@ -384,6 +399,7 @@ async fn run(
 
             enum Action {
                 VmService(Box<Option<(mesh::CancelContext, vmservice::Vm)>>),
+                StreamingService(Option<(mesh::CancelContext, vmservice::VmStreaming)>),
                 InspectService(Option<(mesh::CancelContext, InspectService)>),
                 WorkerRpc(Result<WorkerRpc<()>, mesh::RecvError>),
                 ControllerEvent(Option<VmControllerEvent>),
@@ -392,6 +408,7 @@ enum Action {
 
             let action = futures::select! { // merge semantics
                 m = vm_service_recv.next() => Action::VmService(Box::new(m)),
+                m = streaming_service_recv.next() => Action::StreamingService(m),
                 m = inspect_service_recv.next() => Action::InspectService(m),
                 r = recv.recv().fuse() => Action::WorkerRpc(r),
                 e = ctrl_fut.fuse() => Action::ControllerEvent(e),
@@ -416,6 +433,13 @@ enum Action {
                         break false;
                     }
                 },
+                Action::StreamingService(Some((_ctx, message))) => {
+                    self.handle_streaming(message);
+                }
+                Action::StreamingService(None) => {
+                    tracing::debug!("no more ttrpc requests");
+                    break false;
+                }
                 Action::InspectService(Some((ctx, message))) => {
                     self.handle_inspect(ctx, message).await;
                 }
@@ -499,6 +523,14 @@ struct Vm {
     worker_rpc: mesh::Sender<VmRpc>,
     scsi_rpc: Option<mesh::Sender<ScsiControllerRequest>>,
     consomme_rpc: Option<mesh::Sender<ConsommeRequest>>,
+    /// Consoles for the serial ports configured with `attach`, indexed by port.
+    serial_consoles: [Option<mesh::Sender<SerialConsoleRequest>>; 4],
+    processor_count: u32,
+}
+
+enum SerialConsoleRequest {
+    Attach(mesh::Sender<Result<vmservice::SerialOutput, Status>>),
+    Write(FailableRpc<Vec<u8>, ()>),
 }
 
 enum VmLifecycle {
@@ -527,6 +559,8 @@ struct VmService {
     controller_task: Option<Task<()>>,
     wait_vm_response: Option<(mesh::CancelContext, mesh::OneshotSender<Result<(), Status>>)>,
     lifecycle: VmLifecycle,
+    /// Streams from `WatchVM` calls.
+    vm_watchers: Vec<mesh::Sender<Result<vmservice::VmEvent, Status>>>,
     rpc_tasks: Vec<Task<()>>,
     transport: ResolvedTransport,
     /// Registry of file descriptors passed in over the fd-passing protocol,
@@ -567,7 +601,7 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
         tracing::debug!(?request, "request");
         match request {
             vmservice::Vm::CreateVm(request, response) => {
-                response.send(map_grpc(self.create_vm(request).await))
+                response.send(map_grpc(self.create_vm(request, None).await))
             }
             vmservice::Vm::TeardownVm((), response) => {
                 response.send(map_grpc(self.teardown_vm().await))
@@ -624,10 +658,87 @@ async fn handle(&mut self, ctx: mesh::CancelContext, request: vmservice::Vm) ->
                 let r = self.remove_pcie_device(request);
                 self.start_rpc(response, r);
             }
+            vmservice::Vm::SaveSnapshot(request, response) => {
+                response.send(map_grpc(self.save_snapshot(request).await));
+            }
+            vmservice::Vm::RestoreSnapshot(request, response) => {
+                response.send(map_grpc(self.restore_snapshot(request).await));
+            }
+            vmservice::Vm::DumpState(request, response) => {
+                let r = self.dump_state(request);
+                self.start_rpc(response, r);
+            }
+            vmservice::Vm::InjectNmi(request, response) => {
+                let r = self.inject_nmi(request);
+                self.start_rpc(response, r);
+            }
+            vmservice::Vm::InspectVm(request, response) => {
+                let inspect = self.inspect(
+                    ctx,
+                    inspect_proto::InspectRequest {
+                        path: request.path,
+                        depth: request.depth,
+                    },
+                );
+                self.start_rpc(
+                    response,
+                    Ok(async move {
+                        let InspectResponse2 { result } = inspect.await?;
+                        Ok(vmservice::InspectVmResponse {
+                            json: result.json().to_string(),
+                        })
+                    }),
+                );
+            }
         }
         HandleAction::None
     }
 
+    fn handle_streaming(&mut self, request: vmservice::VmStreaming) {
+        tracing::debug!(?request, "streaming request");
+        match request {
+            vmservice::VmStreaming::AttachSerial(requests, response) => match self.vm.as_ref() {
+                Some(vm) => {
+                    // The port is not known until the first request arrives,
+                    // so wait for it off the main loop.
+                    self.driver
+                        .spawn(
+                            "ttrpc-attach-serial",
+                            attach_serial(vm.serial_consoles.clone(), requests, response),
+                        )
+                        .detach();
+                }
+                None => response.send(Err(grpc_error(anyhow!("VM not created yet")))),
+            },
+            vmservice::VmStreaming::WatchVm((), response) => {
+                response.send(Ok(vmservice::VmEvent {
+                    kind: Some(vmservice::vm_event::Kind::StateChanged(
+                        vmservice::VmState::from(&self.lifecycle).into(),
+                    )),
+                }));
+                self.vm_watchers.push(response);
+            }
+        }
+    }
+
+    fn notify_watchers(&mut self, kind: vmservice::vm_event::Kind) {
+        self.vm_watchers.retain(|watcher| !watcher.is_closed());
+        for watcher in &self.vm_watchers {
+            watcher.send(Ok(vmservice::VmEvent {
+                kind: Some(kind.clone()),
+            }));
+        }
+    }
+
+    fn set_lifecycle(&mut self, lifecycle: VmLifecycle) {
+        let old_state = vmservice::VmState::from(&self.lifecycle);
+        self.lifecycle = lifecycle;
+        let new_state = vmservice::VmState::from(&self.lifecycle);
+        if new_state != old_state {
+            self.notify_watchers(vmservice::vm_event::Kind::StateChanged(new_state.into()));
+        }
+    }
+
     async fn handle_inspect(&mut self, ctx: mesh::CancelContext, request: InspectService) {
         match request {
             InspectService::Inspect(request, response) => {
@@ -686,7 +797,13 @@ fn update(
         }
     }
 
-    async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Result<()> {
+    /// Creates the VM, restoring its state from the snapshot in `restore_dir`
+    /// if provided.
+    async fn create_vm(
+        &mut self,
+        request: vmservice::CreateVmRequest,
+        restore_dir: Option<&Path>,
+    ) -> anyhow::Result<()> {
         let mut req_config = request.config.context("missing configuration")?;
 
         if self.vm.is_some() {
@@ -701,14 +818,35 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
         // needs to know whether any are present to decide whether to enable its
         // serial console.
         let mut ports = [(); 4].map(|_| None);
+        let mut serial_consoles = [(); 4].map(|_| None);
         for port in req_config.serial_config.iter().flat_map(|c| &c.ports) {
             let pc = ports
                 .get_mut(port.port as usize)
                 .context("invalid serial port")?;
-            let (serial_fn, action) = open_socket_backend(port.connect);
-            *pc = Some(serial_fn(port.socket_path.as_ref()).with_context(|| {
-                format!("failed to {} serial socket: {}", action, port.socket_path)
-            })?);
+            *pc = Some(if port.attach {
+                if !port.socket_path.is_empty() {
+                    bail!(
+                        "serial port {} cannot both be attachable and use a socket",
+                        port.port
+                    );
+                }
+                let (backend, serial) = anonymous_serial_pair(&self.driver)
+                    .context("failed to create serial console pipe")?;
+                let (send, recv) = mesh::channel();
+                self.driver
+                    .spawn(
+                        format!("serial-console-{}", port.port),
+                        run_serial_console(serial, recv),
+                    )
+                    .detach();
+                serial_consoles[port.port as usize] = Some(send);
+                backend
+            } else {
+                let (serial_fn, action) = open_socket_backend(port.connect);
+                serial_fn(port.socket_path.as_ref()).with_context(|| {
+                    format!("failed to {} serial socket: {}", action, port.socket_path)
+                })?
+            });
         }
         let any_serial_configured = ports.iter().any(|port| port.is_some());
         let com1_configured = ports[0].is_some();
@@ -871,6 +1009,13 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             (numa, mem_size)
         };
 
+        let memory_backing_file = req_config
+            .memory_config
+            .as_ref()
+            .map(|c| &c.memory_backing_file)
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+
         let config_proc_count = req_config
             .processor_config
             .as_ref()
@@ -885,6 +1030,25 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             BuiltPcieTopology::default()
         };
 
+        let ics = req_config.integration_components.take().unwrap_or_default();
+        let mut vmbus_devices = Vec::new();
+        let vss_ic = ics.vss.then(|| {
+            let (send, recv) = mesh::channel();
+            vmbus_devices.push((
+                DeviceVtl::Vtl0,
+                hyperv_ic_resources::vss::VssIcHandle { recv }.into_resource(),
+            ));
+            send
+        });
+        let heartbeat_ic = ics.heartbeat.then(|| {
+            let (send, recv) = mesh::channel();
+            vmbus_devices.push((
+                DeviceVtl::Vtl0,
+                hyperv_ic_resources::heartbeat::HeartbeatIcHandle { recv }.into_resource(),
+            ));
+            send
+        });
+
         let mut config = Config {
             // TODO: devices, other stuff
             load_mode,
@@ -915,9 +1079,12 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             vga_firmware: None,
             vtl2_gfx: false,
             virtio_devices: vec![],
+            balloon_control: None,
+            vss_ic,
+            heartbeat_ic,
             vmbus: Some(VmbusConfig::default()),
             vtl2_vmbus: None,
-            vmbus_devices: vec![],
+            vmbus_devices,
             #[cfg(windows)]
             vpci_resources: vec![],
             vmgs: None,
@@ -1053,6 +1220,24 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             config.vmbus.as_mut().unwrap().vsock_path = Some(hvsocket_config.path);
         }
 
+        let (shared_memory, saved_state) = if let Some(restore_dir) = restore_dir {
+            let (fd, state) = crate::prepare_snapshot_restore(
+                restore_dir,
+                config_mem_size,
+                config_proc_count,
+                memory_backing_file.as_deref(),
+            )?;
+            (Some(fd), Some(state))
+        } else {
+            let shared_memory = memory_backing_file
+                .as_deref()
+                .map(|path| {
+                    openvmm_helpers::shared_memory::open_memory_backing_file(path, config_mem_size)
+                })
+                .transpose()?;
+            (shared_memory, None)
+        };
+
         let (send, recv) = mesh::channel();
         let (notify_send, notify_recv) = mesh::channel();
 
@@ -1069,8 +1254,8 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
                 VmWorkerParameters {
                     hypervisor: openvmm_helpers::hypervisor::choose_hypervisor()?,
                     cfg: config,
-                    saved_state: None,
-                    shared_memory: None,
+                    saved_state,
+                    shared_memory,
                     rpc: recv,
                     notify: notify_send,
                 },
@@ -1090,13 +1275,14 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             vm_worker: worker,
             vnc_worker: None,
             gdb_worker: None,
+            gdb_inspect: None,
             diag_inspector: None,
             vtl2_settings: None,
             ged_rpc: None,
             vm_rpc: send.clone(),
             paravisor_diag: None,
             igvm_path: None,
-            memory_backing_file: None,
+            memory_backing_file,
             memory,
             processors,
             log_file: None,
@@ -1117,11 +1303,72 @@ async fn create_vm(&mut self, request: vmservice::CreateVmRequest) -> anyhow::Re
             scsi_rpc,
             consomme_rpc,
             worker_rpc: send,
+            serial_consoles,
+            processor_count: config_proc_count,
         }));
-        self.lifecycle = VmLifecycle::Paused;
+        self.set_lifecycle(VmLifecycle::Paused);
+        Ok(())
+    }
+
+    async fn restore_snapshot(
+        &mut self,
+        request: vmservice::RestoreSnapshotRequest,
+    ) -> anyhow::Result<()> {
+        let create = request.create.context("missing create request")?;
+        tracing::info!(dir = request.dir, "restoring VM from snapshot");
+        self.create_vm(create, Some(Path::new(&request.dir))).await
+    }
+
+    async fn save_snapshot(
+        &mut self,
+        request: vmservice::SaveSnapshotRequest,
+    ) -> anyhow::Result<()> {
+        let controller = self.vm_controller.as_ref().context("VM not created yet")?;
+        let copy_memory = request.copy_memory;
+        controller
+            .call(
+                VmControllerRpc::SaveSnapshot,
+                SaveSnapshotParams {
+                    dir: request.dir,
+                    copy_memory,
+                    parent: request.parent,
+                    compress: request.compress,
+                    app_consistent: request.app_consistent,
+                },
+            )
+            .await
+            .context("controller closed")??;
+        // A snapshot that links guest memory leaves the VM paused.
+        if !copy_memory && !matches!(self.lifecycle, VmLifecycle::Halted(_)) {
+            self.set_lifecycle(VmLifecycle::Paused);
+        }
         Ok(())
     }
 
+    fn dump_state(
+        &self,
+        request: vmservice::DumpStateRequest,
+    ) -> anyhow::Result<impl Future<Output = anyhow::Result<()>> + use<>> {
+        let recv = self
+            .vm_controller
+            .as_ref()
+            .context("VM not created yet")?
+            .call(VmControllerRpc::DumpState, request.path);
+        Ok(async move { Ok(recv.await.context("controller closed")??) })
+    }
+
+    fn inject_nmi(
+        &self,
+        request: vmservice::InjectNmiRequest,
+    ) -> anyhow::Result<impl Future<Output = anyhow::Result<()>> + use<>> {
+        let vm = self.vm.as_ref().context("VM not created yet")?;
+        if request.vp_index >= vm.processor_count {
+            bail!("invalid vp index {}", request.vp_index);
+        }
+        let recv = vm.worker_rpc.call(VmRpc::Nmi, request.vp_index);
+        Ok(async move { recv.await.context("nmi failed") })
+    }
+
     async fn teardown_vm(&mut self) -> anyhow::Result<()> {
         let controller = self.vm_controller.take().context("vm not created")?;
         // Drop the VM's device RPC channels before waiting on the controller.
@@ -1136,7 +1383,7 @@ async fn teardown_vm(&mut self) -> anyhow::Result<()> {
             task.await;
         }
         self.vm_controller_events.take();
-        self.lifecycle = VmLifecycle::Uninitialized;
+        self.set_lifecycle(VmLifecycle::Uninitialized);
         if let Some((_, response)) = self.wait_vm_response.take() {
             response.send(Err(grpc_error(anyhow!("VM torn down"))));
         }
@@ -1194,7 +1441,7 @@ async fn pause_vm(&mut self) -> anyhow::Result<()> {
             .map(drop)
             .context("pause failed")?;
         if !matches!(self.lifecycle, VmLifecycle::Halted(_)) {
-            self.lifecycle = VmLifecycle::Paused;
+            self.set_lifecycle(VmLifecycle::Paused);
         }
         Ok(())
     }
@@ -1207,7 +1454,7 @@ async fn resume_vm(&mut self) -> anyhow::Result<()> {
             .map(drop)
             .context("resume failed")?;
         if !matches!(self.lifecycle, VmLifecycle::Halted(_)) {
-            self.lifecycle = VmLifecycle::Running;
+            self.set_lifecycle(VmLifecycle::Running);
         }
         Ok(())
     }
@@ -1216,7 +1463,8 @@ fn handle_controller_event(&mut self, event: VmControllerEvent) {
         match event {
             VmControllerEvent::GuestHalt(reason) => {
                 tracing::info!(%reason, "guest halted (via controller)");
-                self.lifecycle = VmLifecycle::Halted(reason);
+                self.notify_watchers(vmservice::vm_event::Kind::Halted(reason.clone()));
+                self.set_lifecycle(VmLifecycle::Halted(reason));
                 if let Some((_, response)) = self.wait_vm_response.take() {
                     response.send(Ok(()));
                 }
@@ -1245,7 +1493,11 @@ fn handle_controller_event(&mut self, event: VmControllerEvent) {
                 // task will be awaited during final cleanup.
                 self.vm.take();
                 self.vm_controller.take();
-                self.lifecycle = VmLifecycle::Uninitialized;
+                self.set_lifecycle(VmLifecycle::Uninitialized);
+            }
+            VmControllerEvent::GuestCrashed(reason) => {
+                tracing::info!(%reason, "guest crashed (via controller)");
+                self.notify_watchers(vmservice::vm_event::Kind::GuestCrashed(reason));
             }
             VmControllerEvent::VncWorkerStopped { error } => {
                 if let Some(err) = &error {
@@ -1412,6 +1664,95 @@ fn open_socket_backend(
     }
 }
 
+/// Attaches an `AttachSerial` client to one of `consoles`.
+///
+/// The port is selected by the first request. The client's output stream is
+/// registered with the console, and the data of each request is then written
+/// to the port until the client closes its request stream.
+async fn attach_serial(
+    consoles: [Option<mesh::Sender<SerialConsoleRequest>>; 4],
+    mut requests: RequestStream<vmservice::AttachSerialRequest>,
+    response: mesh::Sender<Result<vmservice::SerialOutput, Status>>,
+) {
+    let request = match requests.next().await {
+        Some(Ok(request)) => request,
+        Some(Err(status)) => return response.send(Err(status)),
+        None => return,
+    };
+    let Some(console) = consoles.get(request.port as usize).and_then(Option::as_ref) else {
+        return response.send(Err(grpc_error(anyhow!(
+            "serial port {} is not attachable",
+            request.port
+        ))));
+    };
+    console.send(SerialConsoleRequest::Attach(response.clone()));
+    let mut data = request.data;
+    loop {
+        if !data.is_empty()
+            && let Err(err) = console
+                .call_failable(SerialConsoleRequest::Write, data)
+                .await
+        {
+            return response.send(Err(grpc_error(err.into())));
+        }
+        data = match requests.next().await {
+            Some(Ok(request)) => request.data,
+            Some(Err(status)) => return response.send(Err(status)),
+            None => break,
+        };
+    }
+}
+
+/// Relays a serial port to the clients attached with `AttachSerial`, and
+/// writes their input, until the VM closes the port or the VM is torn down.
+async fn run_serial_console(
+    serial: impl AsyncRead + AsyncWrite,
+    mut recv: mesh::Receiver<SerialConsoleRequest>,
+) {
+    let (mut serial_read, mut serial_write) = serial.split();
+    let mut clients = Vec::<mesh::Sender<Result<vmservice::SerialOutput, Status>>>::new();
+    let mut buf = vec![0; 4096];
+    loop {
+        enum Event {
+            Read(std::io::Result<usize>),
+            Request(Option<SerialConsoleRequest>),
+        }
+
+        let event = (
+            serial_read.read(&mut buf).map(Event::Read),
+            recv.next().map(Event::Request),
+        )
+            .race()
+            .await;
+
+        match event {
+            Event::Read(Ok(0)) | Event::Request(None) => break,
+            Event::Read(Ok(n)) => {
+                // Output is dropped while no client is attached.
+                clients.retain(|client| !client.is_closed());
+                for client in &clients {
+                    client.send(Ok(vmservice::SerialOutput {
+                        data: buf[..n].to_vec(),
+                    }));
+                }
+            }
+            Event::Read(Err(err)) => {
+                tracing::error!(
+                    error = &err as &dyn std::error::Error,
+                    "serial console read failed"
+                );
+                break;
+            }
+            Event::Request(Some(SerialConsoleRequest::Attach(client))) => clients.push(client),
+            Event::Request(Some(SerialConsoleRequest::Write(rpc))) => {
+                let (data, rpc) = rpc.split();
+                let r = serial_write.write_all(&data).await;
+                rpc.complete(r.map_err(RemoteError::new));
+            }
+        }
+    }
+}
+
 /// Convert a ttrpc `PortConfig` (untrusted input) into a `HostPortConfig`,
 /// validating the protocol and port ranges. The host port is always treated as
 /// a fixed port; the unbind path ignores it.
@@ -1475,6 +1816,9 @@ fn parse_nic_config(
                 .map(parse_port_config)
                 .collect::<anyhow::Result<_>>()?,
             recv,
+            tftp_root: None,
+            boot_file: None,
+            egress_policy: None,
         }
         .into_resource(),
         _ => anyhow::bail!("unsupported backend"),
@@ -1876,6 +2220,7 @@ async fn build_nvme_controller(
             nsid,
             read_only,
             disk,
+            zoned: None,
         });
     }
     Ok(nvme_resources::NvmeControllerHandle {
@@ -1883,6 +2228,7 @@ async fn build_nvme_controller(
         msix_count: 64,
         max_io_queues: 64,
         namespaces,
+        disk_pool: Vec::new(),
         requests: None,
     }
     .into_resource())
@@ -1900,7 +2246,12 @@ async fn build_virtio_device(
         Kind::Blk(vmservice::VirtioBlk { backend, read_only }) => {
             let disk =
                 build_disk_backend(backend.context("missing blk backend")?, read_only).await?;
-            virtio_resources::blk::VirtioBlkHandle { disk, read_only }.into_resource()
+            virtio_resources::blk::VirtioBlkHandle {
+                disk,
+                read_only,
+                num_queues: None,
+            }
+            .into_resource()
         }
         Kind::Net(vmservice::VirtioNet {
             max_queues,
@@ -1975,6 +2326,9 @@ fn build_nic_backend(
                     .map(parse_port_config)
                     .collect::<anyhow::Result<_>>()?,
                 recv: None,
+                tftp_root: None,
+                boot_file: None,
+                egress_policy: None,
             }
             .into_resource()
         }
@@ -2010,7 +2364,7 @@ fn build_tap_backend(
             .resolve(&fd_name)
             .with_context(|| format!("failed to resolve tap fd '{fd_name}'"))?,
     };
-    Ok(net_backend_resources::tap::TapHandle { fd }.into_resource())
+    Ok(net_backend_resources::tap::TapHandle { fd, vhost: None }.into_resource())
 }
 
 /// Builds a serial backend resource from the proto `SerialBackend`.
@@ -2093,9 +2447,202 @@ fn build_vhost_user_device(
 #[cfg(all(test, target_os = "linux"))]
 mod tests {
     use super::*;
+    use mesh_rpc::service::RequestSender;
+    use mesh_rpc::service::request_channel;
+    use pal_async::async_test;
     use vfio_assigned_device_resources::BarAddressConfig;
     use vmservice::vfio_bar_address::Source;
 
+    fn test_service(driver: DefaultDriver) -> VmService {
+        VmService {
+            driver,
+            vm: None,
+            vm_controller: None,
+            vm_controller_events: None,
+            controller_task: None,
+            wait_vm_response: None,
+            lifecycle: VmLifecycle::Uninitialized,
+            vm_watchers: Vec::new(),
+            rpc_tasks: Vec::new(),
+            transport: ResolvedTransport::Auto,
+            registry: FdRegistry::default(),
+        }
+    }
+
+    fn test_vm(serial_consoles: [Option<mesh::Sender<SerialConsoleRequest>>; 4]) -> Arc<Vm> {
+        Arc::new(Vm {
+            worker_rpc: mesh::channel().0,
+            scsi_rpc: None,
+            consomme_rpc: None,
+            serial_consoles,
+            processor_count: 2,
+        })
+    }
+
+    async fn call<T: 'static + MeshPayload + Send>(
+        service: &mut VmService,
+        request: impl FnOnce(mesh::OneshotSender<Result<T, Status>>) -> vmservice::Vm,
+    ) -> Result<T, Status> {
+        let (send, recv) = mesh::oneshot();
+        service
+            .handle(mesh::CancelContext::new(), request(send))
+            .await;
+        recv.await.unwrap()
+    }
+
+    fn attach_serial_request(
+        service: &mut VmService,
+    ) -> (
+        RequestSender<vmservice::AttachSerialRequest>,
+        mesh::Receiver<Result<vmservice::SerialOutput, Status>>,
+    ) {
+        let (requests, recv) = request_channel();
+        let (send, responses) = mesh::channel();
+        service.handle_streaming(vmservice::VmStreaming::AttachSerial(recv, send));
+        (requests, responses)
+    }
+
+    #[async_test]
+    async fn vm_rpcs_require_vm(driver: DefaultDriver) {
+        let mut service = test_service(driver);
+
+        let status = call(&mut service, |r| {
+            vmservice::Vm::SaveSnapshot(Default::default(), r)
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(status.message, "VM not created yet");
+
+        let status = call(&mut service, |r| {
+            vmservice::Vm::DumpState(Default::default(), r)
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(status.message, "VM not created yet");
+
+        let status = call(&mut service, |r| {
+            vmservice::Vm::InjectNmi(Default::default(), r)
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(status.message, "VM not created yet");
+
+        let (_requests, mut responses) = attach_serial_request(&mut service);
+        let status = responses.next().await.unwrap().unwrap_err();
+        assert_eq!(status.message, "VM not created yet");
+    }
+
+    #[async_test]
+    async fn restore_snapshot_requires_create(driver: DefaultDriver) {
+        let mut service = test_service(driver);
+        let status = call(&mut service, |r| {
+            vmservice::Vm::RestoreSnapshot(
+                vmservice::RestoreSnapshotRequest {
+                    create: None,
+                    dir: "snapshot".to_owned(),
+                },
+                r,
+            )
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(status.message, "missing create request");
+        assert!(service.vm.is_none());
+    }
+
+    #[async_test]
+    async fn inject_nmi_rejects_invalid_vp(driver: DefaultDriver) {
+        let mut service = test_service(driver);
+        service.vm = Some(test_vm(Default::default()));
+        let status = call(&mut service, |r| {
+            vmservice::Vm::InjectNmi(vmservice::InjectNmiRequest { vp_index: 2 }, r)
+        })
+        .await
+        .unwrap_err();
+        assert_eq!(status.message, "invalid vp index 2");
+    }
+
+    #[async_test]
+    async fn attach_serial_rejects_unattachable_port(driver: DefaultDriver) {
+        let mut service = test_service(driver);
+        let (console, _console_recv) = mesh::channel();
+        service.vm = Some(test_vm([Some(console), None, None, None]));
+
+        for port in [1, 4] {
+            let (requests, mut responses) = attach_serial_request(&mut service);
+            requests.send(vmservice::AttachSerialRequest {
+                port,
+                data: Vec::new(),
+            });
+            let status = responses.next().await.unwrap().unwrap_err();
+            assert_eq!(
+                status.message,
+                format!("serial port {port} is not attachable")
+            );
+        }
+    }
+
+    #[async_test]
+    async fn attach_serial_forwards_input(driver: DefaultDriver) {
+        let mut service = test_service(driver);
+        let (console, mut console_recv) = mesh::channel();
+        service.vm = Some(test_vm([Some(console), None, None, None]));
+
+        let (requests, _responses) = attach_serial_request(&mut service);
+        requests.send(vmservice::AttachSerialRequest {
+            port: 0,
+            data: b"abc".to_vec(),
+        });
+        // The port of later requests is ignored.
+        requests.send(vmservice::AttachSerialRequest {
+            port: 1,
+            data: b"def".to_vec(),
+        });
+
+        let Some(SerialConsoleRequest::Attach(_)) = console_recv.next().await else {
+            panic!("expected attach");
+        };
+        for expected in [b"abc", b"def"] {
+            let Some(SerialConsoleRequest::Write(rpc)) = console_recv.next().await else {
+                panic!("expected write");
+            };
+            let (data, rpc) = rpc.split();
+            assert_eq!(data, expected);
+            rpc.complete(Ok(()));
+        }
+    }
+
+    #[async_test]
+    async fn watch_vm_sends_current_state(driver: DefaultDriver) {
+        let mut service = test_service(driver);
+        let (send, mut events) = mesh::channel();
+        service.handle_streaming(vmservice::VmStreaming::WatchVm((), send));
+        let event = events.next().await.unwrap().unwrap();
+        assert_eq!(
+            event.kind,
+            Some(vmservice::vm_event::Kind::StateChanged(
+                vmservice::VmState::Uninitialized.into()
+            ))
+        );
+        assert_eq!(service.vm_watchers.len(), 1);
+    }
+
+    #[async_test]
+    async fn inspect_vm_without_vm(driver: DefaultDriver) {
+        let mut service = test_service(driver);
+        call(&mut service, |r| {
+            vmservice::Vm::InspectVm(
+                vmservice::InspectVmRequest {
+                    path: String::new(),
+                    depth: 1,
+                },
+                r,
+            )
+        })
+        .await
+        .unwrap();
+    }
+
     fn vfio_bar_address(bar_index: u32, source: Option<Source>) -> vmservice::VfioBarAddress {
         vmservice::VfioBarAddress { bar_index, source }
     }
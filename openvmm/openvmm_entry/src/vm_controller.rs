@@ -13,19 +13,41 @@
 use futures_concurrency::stream::Merge;
 use get_resources::ged::GuestServicingFlags;
 use guid::Guid;
+use hyperv_ic_resources::vss::VssResult;
 use inspect::InspectMut;
+use mesh::CancelContext;
 use mesh::rpc::Rpc;
 use mesh::rpc::RpcSend;
 use mesh_worker::WorkerEvent;
 use mesh_worker::WorkerHandle;
 use openvmm_defs::rpc::VmRpc;
+use openvmm_helpers::migrate::CompleteError;
+use openvmm_helpers::migrate::MigrationAddr;
+use openvmm_helpers::migrate::MigrationSource;
 use std::path::Path;
 use std::path::PathBuf;
 use std::pin::pin;
 use std::sync::Arc;
+use std::time::Duration;
 use std::time::Instant;
 use vmm_core_defs::HaltReason;
 
+/// The maximum number of memory copy rounds run while the VM keeps running
+/// during a live migration.
+const MAX_PRECOPY_ROUNDS: u32 = 30;
+
+/// Pre-copy stops once a round sends no more than this many pages.
+const PRECOPY_STOP_PAGES: u64 = 256;
+
+/// How long to wait for the guest to freeze its filesystems before an
+/// application consistent snapshot.
+const FREEZE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// How long to wait for the guest to thaw its filesystems. This is shorter
+/// than [`FREEZE_TIMEOUT`] since it also bounds the error paths, where the VM
+/// may not be running.
+const THAW_TIMEOUT: Duration = Duration::from_secs(10);
+
 /// Inspection target: host-side workers or the paravisor.
 #[derive(Clone, Copy, mesh::MeshPayload)]
 pub enum InspectTarget {
@@ -56,7 +78,10 @@ pub enum VmControllerRpc {
         Rpc<RemoveVtl0ScsiDiskByNvmeNsidParams, Result<Option<u32>, mesh::error::RemoteError>>,
     ),
     /// Save a VM snapshot to a directory.
-    SaveSnapshot(Rpc<String, Result<(), mesh::error::RemoteError>>),
+    SaveSnapshot(Rpc<SaveSnapshotParams, Result<(), mesh::error::RemoteError>>),
+    /// Live migrate the VM to another OpenVMM process listening at an address
+    /// (a Unix socket path or `tcp:<ip>:<port>`).
+    Migrate(Rpc<String, Result<(), mesh::error::RemoteError>>),
     /// Dump VM state (VP registers + memory) to a `.vmrs` file.
     DumpState(Rpc<String, Result<(), mesh::error::RemoteError>>),
     /// Service (update) the VTL2 firmware.
@@ -65,6 +90,24 @@ pub enum VmControllerRpc {
     Quit,
 }
 
+#[derive(mesh::MeshPayload)]
+pub struct SaveSnapshotParams {
+    /// The directory to write the snapshot to.
+    pub dir: String,
+    /// Store guest memory as pages in the snapshot rather than linking the
+    /// memory backing file, so that the VM can keep running.
+    pub copy_memory: bool,
+    /// The snapshot to store memory as a diff against. Requires
+    /// `copy_memory`.
+    pub parent: Option<String>,
+    /// Compress the memory pages. Requires `copy_memory`.
+    pub compress: bool,
+    /// Freeze the guest's filesystems via the VSS IC before saving, so that
+    /// the snapshot is application consistent. Fails if the guest cannot be
+    /// frozen.
+    pub app_consistent: bool,
+}
+
 #[derive(mesh::MeshPayload)]
 pub struct AddVtl0ScsiDiskParams {
     pub controller_guid: Guid,
@@ -104,6 +147,10 @@ pub enum VmControllerEvent {
     VncWorkerStopped { error: Option<String> },
     /// The guest halted.
     GuestHalt(String),
+    /// The guest crashed. This is sent before the crash power action is
+    /// applied, so it is followed by [`Self::GuestHalt`] if the VM is left
+    /// halted.
+    GuestCrashed(String),
     /// The controller requests that the process exit with this code, because the
     /// guest drove a power event the user opted into exiting on.
     ExitRequested { code: i32 },
@@ -115,6 +162,8 @@ pub struct VmController {
     pub(crate) vm_worker: WorkerHandle,
     pub(crate) vnc_worker: Option<WorkerHandle>,
     pub(crate) gdb_worker: Option<WorkerHandle>,
+    /// Inspect requests from the gdbstub `monitor inspect` command.
+    pub(crate) gdb_inspect: Option<mesh::Receiver<inspect::Deferred>>,
     pub(crate) diag_inspector: Option<DiagInspector>,
     pub(crate) vtl2_settings: Option<vtl2_settings_proto::Vtl2Settings>,
     pub(crate) ged_rpc: Option<mesh::Sender<get_resources::ged::GuestEmulationRequest>>,
@@ -182,6 +231,7 @@ enum Event {
             Worker(WorkerEvent),
             VncWorker(WorkerEvent),
             Halt(HaltReason),
+            GdbInspect(inspect::Deferred),
         }
 
         let mut quit = false;
@@ -203,8 +253,11 @@ enum Event {
                     .flatten()
                     .map(Event::VncWorker);
                 let halt = (&mut notify_recv).map(Event::Halt);
+                let gdb_inspect = futures::stream::iter(self.gdb_inspect.as_mut())
+                    .flatten()
+                    .map(Event::GdbInspect);
 
-                (rpc.into_stream(), vm, vnc, halt)
+                (rpc.into_stream(), vm, vnc, halt, gdb_inspect)
                     .merge()
                     .next()
                     .await
@@ -274,12 +327,16 @@ enum Event {
                         tracing::info!("vnc worker restarted");
                     }
                 },
+                Event::GdbInspect(deferred) => {
+                    self.handle_inspect(InspectTarget::Host, deferred);
+                }
                 Event::Halt(reason) => {
                     tracing::info!(?reason, "guest halted");
                     // On a guest crash, write a `.vmrs` dump (if configured)
                     // before applying the crash action, since a `Reset` action
                     // would wipe the guest state we want to capture.
                     if matches!(&reason, HaltReason::TripleFault { .. }) {
+                        event_send.send(VmControllerEvent::GuestCrashed(format!("{reason:?}")));
                         if let Some(path) = self.crash_dump_path.clone() {
                             tracing::info!(path = %path.display(), "dumping VM state on guest crash");
                             match self.handle_dump_state(&path).await {
@@ -395,8 +452,13 @@ async fn handle_rpc(&mut self, rpc: VmControllerRpc, quit: &mut bool) {
                 req.complete(result.map_err(mesh::error::RemoteError::new));
             }
             VmControllerRpc::SaveSnapshot(req) => {
-                let (dir, req) = req.split();
-                let result = self.handle_save_snapshot(Path::new(&dir)).await;
+                let (params, req) = req.split();
+                let result = self.handle_save_snapshot(params).await;
+                req.complete(result.map_err(mesh::error::RemoteError::new));
+            }
+            VmControllerRpc::Migrate(req) => {
+                let (addr, req) = req.split();
+                let result = self.handle_migrate(&addr).await;
                 req.complete(result.map_err(mesh::error::RemoteError::new));
             }
             VmControllerRpc::DumpState(req) => {
@@ -459,36 +521,183 @@ fn handle_inspect(&mut self, target: InspectTarget, deferred: inspect::Deferred)
         deferred.inspect(obj);
     }
 
-    async fn handle_save_snapshot(&self, dir: &Path) -> anyhow::Result<()> {
+    async fn handle_save_snapshot(&self, params: SaveSnapshotParams) -> anyhow::Result<()> {
         let memory_file_path = self
             .memory_backing_file
             .as_ref()
             .context("save-snapshot requires --memory-backing-file")?;
+        if params.copy_memory {
+            return self
+                .handle_save_snapshot_pages(params, memory_file_path)
+                .await;
+        }
+        anyhow::ensure!(
+            params.parent.is_none() && !params.compress,
+            "incremental and compressed snapshots must copy memory"
+        );
+        let dir = Path::new(&params.dir);
 
-        // Pause the VM.
-        self.vm_rpc
-            .call(VmRpc::Pause, ())
-            .await
-            .context("failed to pause VM")?;
+        if params.app_consistent {
+            self.freeze_guest().await?;
+        }
 
-        // Get device state via existing VmRpc::Save.
-        let saved_state_msg = self
-            .vm_rpc
-            .call_failable(VmRpc::Save, ())
-            .await
-            .context("failed to save state")?;
+        let result = async {
+            // Pause the VM.
+            self.vm_rpc
+                .call(VmRpc::Pause, ())
+                .await
+                .context("failed to pause VM")?;
+
+            // Get device state via existing VmRpc::Save.
+            let saved_state_msg = self
+                .vm_rpc
+                .call_failable(VmRpc::Save, ())
+                .await
+                .context("failed to save state")?;
+
+            // Serialize the ProtobufMessage to bytes for writing to disk.
+            let saved_state_bytes = mesh::payload::encode(saved_state_msg);
+
+            // Fsync the memory backing file.
+            let memory_file = fs_err::File::open(memory_file_path)?;
+            memory_file
+                .sync_all()
+                .context("failed to fsync memory backing file")?;
+
+            // Write snapshot directory.
+            openvmm_helpers::snapshot::write_snapshot(
+                dir,
+                &self.snapshot_manifest(),
+                &saved_state_bytes,
+                memory_file_path,
+            )
+        }
+        .await;
+
+        match result {
+            // VM stays paused. Do NOT resume.
+            //
+            // An app consistent guest stays frozen along with it: the VSS IC
+            // thaws the guest once it reconnects after the snapshot is
+            // restored, and the worker thaws it if this VM is resumed instead.
+            Ok(()) => Ok(()),
+            // No snapshot was taken, so put the guest back the way it was
+            // before the freeze. It can only thaw once the VM is running.
+            Err(err) if params.app_consistent => {
+                let thaw = async {
+                    self.vm_rpc
+                        .call(VmRpc::Resume, ())
+                        .await
+                        .context("failed to resume VM")?;
+                    self.thaw_guest().await
+                }
+                .await;
+                first_error(Err(err), thaw)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Saves a snapshot that copies guest memory into the snapshot directory,
+    /// then resumes the VM if it was running.
+    async fn handle_save_snapshot_pages(
+        &self,
+        params: SaveSnapshotParams,
+        memory_file_path: &Path,
+    ) -> anyhow::Result<()> {
+        let memory: std::fs::File = fs_err::File::open(memory_file_path)?.into();
+        let manifest = self.snapshot_manifest();
+        let app_consistent = params.app_consistent;
+
+        if app_consistent {
+            self.freeze_guest().await?;
+        }
+
+        let result = async {
+            let was_running = self
+                .vm_rpc
+                .call(VmRpc::Pause, ())
+                .await
+                .context("failed to pause VM")?;
+
+            let result = async {
+                let saved_state = self
+                    .vm_rpc
+                    .call_failable(VmRpc::Save, ())
+                    .await
+                    .context("failed to save state")?;
+                let saved_state = mesh::payload::encode(saved_state);
+                blocking::unblock(move || {
+                    openvmm_helpers::snapshot::write_snapshot_pages(
+                        Path::new(&params.dir),
+                        &manifest,
+                        &saved_state,
+                        &memory,
+                        params.parent.as_deref().map(Path::new),
+                        params.compress,
+                    )
+                })
+                .await
+            }
+            .await;
+
+            if was_running {
+                self.vm_rpc
+                    .call(VmRpc::Resume, ())
+                    .await
+                    .context("failed to resume VM")?;
+            }
+            result
+        }
+        .await;
+
+        // Thaw the guest on every path once it has been frozen, whether or not
+        // the save succeeded.
+        let result = if app_consistent {
+            first_error(result, self.thaw_guest().await)
+        } else {
+            result
+        };
+        let pages = result?;
+        tracing::info!(pages, "snapshot memory saved");
+        Ok(())
+    }
 
-        // Serialize the ProtobufMessage to bytes for writing to disk.
-        let saved_state_bytes = mesh::payload::encode(saved_state_msg);
+    /// Asks the guest to freeze its filesystems via the VSS IC.
+    async fn freeze_guest(&self) -> anyhow::Result<()> {
+        let result = CancelContext::new()
+            .with_timeout(FREEZE_TIMEOUT)
+            .until_cancelled(self.vm_rpc.call(VmRpc::FreezeGuest, ()))
+            .await
+            .context("timed out freezing guest filesystems")?
+            .context("failed to freeze guest filesystems")?;
+        match result {
+            VssResult::Ok => Ok(()),
+            VssResult::NotReady => anyhow::bail!("guest vss ic is not ready"),
+            VssResult::AlreadyInProgress => anyhow::bail!("guest vss request already in progress"),
+            VssResult::Failed(status) => {
+                anyhow::bail!("guest failed to freeze filesystems: {status:#x}")
+            }
+        }
+    }
 
-        // Fsync the memory backing file.
-        let memory_file = fs_err::File::open(memory_file_path)?;
-        memory_file
-            .sync_all()
-            .context("failed to fsync memory backing file")?;
+    /// Asks the guest to thaw its filesystems after [`Self::freeze_guest`].
+    async fn thaw_guest(&self) -> anyhow::Result<()> {
+        let result = CancelContext::new()
+            .with_timeout(THAW_TIMEOUT)
+            .until_cancelled(self.vm_rpc.call(VmRpc::ThawGuest, ()))
+            .await
+            .context("timed out thawing guest filesystems")?
+            .context("failed to thaw guest filesystems")?;
+        anyhow::ensure!(
+            result == VssResult::Ok,
+            "guest failed to thaw filesystems: {result:?}"
+        );
+        Ok(())
+    }
 
-        // Build manifest.
-        let manifest = openvmm_helpers::snapshot::SnapshotManifest {
+    fn snapshot_manifest(&self) -> openvmm_helpers::snapshot::SnapshotManifest {
+        openvmm_helpers::snapshot::SnapshotManifest {
             version: openvmm_helpers::snapshot::MANIFEST_VERSION,
             created_at: std::time::SystemTime::now().into(),
             openvmm_version: env!("CARGO_PKG_VERSION").to_string(),
@@ -496,18 +705,142 @@ async fn handle_save_snapshot(&self, dir: &Path) -> anyhow::Result<()> {
             vp_count: self.processors,
             page_size: crate::system_page_size(),
             architecture: crate::GUEST_ARCH.to_string(),
+            parent: None,
+        }
+    }
+
+    async fn handle_migrate(&self, addr: &str) -> anyhow::Result<()> {
+        let addr: MigrationAddr = addr.parse().context("invalid migration address")?;
+        let memory_file_path = self
+            .memory_backing_file
+            .as_ref()
+            .context("migrate requires --memory-backing-file")?;
+        let memory: std::fs::File = fs_err::File::open(memory_file_path)?.into();
+        let manifest = self.snapshot_manifest();
+
+        // Track guest writes so that later pre-copy rounds only need to examine
+        // the pages written since the previous round. If the hypervisor cannot
+        // track them, each round hashes all of memory instead.
+        let dirty_log = match self.vm_rpc.call_failable(VmRpc::SetDirtyLog, true).await {
+            Ok(()) => true,
+            Err(err) => {
+                tracing::info!(
+                    error = &err as &dyn std::error::Error,
+                    "dirty page tracking unavailable, scanning all memory each round"
+                );
+                // Tracking may have been enabled for some RAM ranges.
+                let _ = self.vm_rpc.call_failable(VmRpc::SetDirtyLog, false).await;
+                false
+            }
         };
 
-        // Write snapshot directory.
-        openvmm_helpers::snapshot::write_snapshot(
-            dir,
-            &manifest,
-            &saved_state_bytes,
-            memory_file_path,
-        )?;
+        let result = async {
+            tracing::info!(%addr, "starting live migration");
+            let mut source = blocking::unblock(move || {
+                let stream = addr
+                    .connect()
+                    .with_context(|| format!("failed to connect to {addr}"))?;
+                MigrationSource::new(stream, memory, &manifest)
+            })
+            .await?;
 
-        // VM stays paused. Do NOT resume.
-        Ok(())
+            // Copy memory while the VM keeps running, until few enough pages
+            // change between rounds.
+            for round in 0..MAX_PRECOPY_ROUNDS {
+                let dirty = if dirty_log && round > 0 {
+                    Some(
+                        self.vm_rpc
+                            .call_failable(VmRpc::GetDirtyBitmap, ())
+                            .await
+                            .context("failed to get dirty bitmap")?,
+                    )
+                } else {
+                    None
+                };
+                let pages;
+                (source, pages) = blocking::unblock(move || {
+                    let pages = match dirty {
+                        Some(dirty) => source.send_dirty_pages(&dirty)?,
+                        None => source.send_changed_pages()?,
+                    };
+                    anyhow::Ok((source, pages))
+                })
+                .await?;
+                tracing::info!(round, pages, "migration pre-copy round complete");
+                if pages <= PRECOPY_STOP_PAGES {
+                    break;
+                }
+            }
+
+            // Pause the VM, then send the pages written since the last round
+            // and the device state.
+            let was_running = self
+                .vm_rpc
+                .call(VmRpc::Pause, ())
+                .await
+                .context("failed to pause VM")?;
+
+            let result = async {
+                let dirty = if dirty_log {
+                    Some(
+                        self.vm_rpc
+                            .call_failable(VmRpc::GetDirtyBitmap, ())
+                            .await
+                            .context("failed to get dirty bitmap")
+                            .map_err(CompleteError::NotStarted)?,
+                    )
+                } else {
+                    None
+                };
+                let saved_state = self
+                    .vm_rpc
+                    .call_failable(VmRpc::Save, ())
+                    .await
+                    .context("failed to save state")
+                    .map_err(CompleteError::NotStarted)?;
+                let saved_state = mesh::payload::encode(saved_state);
+                blocking::unblock(move || source.complete(dirty.as_deref(), &saved_state)).await
+            }
+            .await;
+            anyhow::Ok((result, was_running))
+        }
+        .await;
+
+        if dirty_log && let Err(err) = self.vm_rpc.call_failable(VmRpc::SetDirtyLog, false).await {
+            tracing::warn!(
+                error = &err as &dyn std::error::Error,
+                "failed to disable dirty page tracking"
+            );
+        }
+
+        let (result, was_running) = result?;
+        match result {
+            Ok(pages) => {
+                // The VM now runs on the destination. It stays paused here.
+                tracing::info!(pages, "live migration complete");
+                Ok(())
+            }
+            Err(CompleteError::NotStarted(err)) => {
+                // The destination did not take over the VM, so keep running
+                // it here.
+                if was_running {
+                    if let Err(err) = self.vm_rpc.call(VmRpc::Resume, ()).await {
+                        tracing::error!(
+                            error = &err as &dyn std::error::Error,
+                            "failed to resume VM after failed migration"
+                        );
+                    }
+                }
+                Err(err)
+            }
+            Err(err @ CompleteError::Unknown(_)) => {
+                // The VM may be running on the destination, so resuming it
+                // here could run it twice.
+                Err(anyhow::Error::from(err).context(
+                    "VM left paused; check whether it is running on the destination before resuming",
+                ))
+            }
+        }
     }
 
     async fn handle_dump_state(&self, path: &Path) -> anyhow::Result<()> {
@@ -698,3 +1031,19 @@ async fn handle_remove_vtl0_scsi_disk_by_nvme_nsid(
         Ok(removed_lun)
     }
 }
+
+/// Returns the first error of `result` and `cleanup`, logging a cleanup error
+/// that is superseded.
+fn first_error<T>(result: anyhow::Result<T>, cleanup: anyhow::Result<()>) -> anyhow::Result<T> {
+    match (result, cleanup) {
+        (Ok(value), cleanup) => cleanup.map(|()| value),
+        (Err(err), Ok(())) => Err(err),
+        (Err(err), Err(cleanup_err)) => {
+            tracing::warn!(
+                error = cleanup_err.as_ref() as &dyn std::error::Error,
+                "cleanup after failed snapshot also failed"
+            );
+            Err(err)
+        }
+    }
+}
@@ -40,6 +40,9 @@ pub struct OpenDiskOptions {
 /// If the file ends with .vhdx, the kernel-mode VHD parser is used on
 /// Windows. On Linux, the pure-Rust VHDX parser is used, with automatic
 /// parent-locator walking for differencing chains.
+///
+/// If the file ends with .qcow2, it is opened with the pure-Rust qcow2 parser,
+/// along with its backing chain.
 pub async fn open_disk_type(
     path: &Path,
     options: OpenDiskOptions,
@@ -100,6 +103,10 @@ pub async fn open_disk_type(
                 disklayer_vhdx::chain::open_vhdx_chain(path, read_only).await?
             }
         }
+        Some("qcow2") => {
+            ensure_no_direct(".qcow2")?;
+            open_qcow2_disk(path, read_only)?
+        }
         Some("iso") if !read_only => {
             anyhow::bail!("iso file cannot be opened as read/write")
         }
@@ -119,6 +126,13 @@ pub async fn open_disk_type(
     })
 }
 
+/// Opens the resources needed for using the qcow2 image at `path`, including
+/// its backing chain.
+pub fn open_qcow2_disk(path: &Path, read_only: bool) -> anyhow::Result<Resource<DiskHandleKind>> {
+    disk_qcow2::chain::open_qcow2_chain(path, read_only)
+        .with_context(|| disk_open_error(path, "failed to open"))
+}
+
 /// Create and open the resources needed for using a disk from a file at `path`.
 pub fn create_disk_type(
     path: &Path,
@@ -145,6 +159,31 @@ pub fn create_disk_type(
         Some("vhdx") => {
             anyhow::bail!("creating vhdx not supported")
         }
+        Some("qcow2") => {
+            if options.direct {
+                anyhow::bail!("direct I/O is not supported for qcow2 files");
+            }
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .truncate(true)
+                .read(true)
+                .write(true)
+                .open(path)
+                .with_context(|| disk_open_error(path, "failed to create"))?;
+
+            disk_qcow2::create::create(
+                &file,
+                &disk_qcow2::create::CreateParams {
+                    size,
+                    ..Default::default()
+                },
+            )?;
+            Resource::new(disk_backend_resources::Qcow2DiskHandle {
+                file,
+                read_only: false,
+                backing: None,
+            })
+        }
         Some("iso") => {
             anyhow::bail!("creating iso not supported")
         }
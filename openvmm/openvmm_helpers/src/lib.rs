@@ -6,7 +6,9 @@
 #![forbid(unsafe_code)]
 
 pub mod disk;
+mod fileio;
 pub mod hypervisor;
+pub mod migrate;
 pub mod shared_memory;
 pub mod snapshot;
 pub mod underhill;
@@ -0,0 +1,782 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Pre-copy live migration of a VM between two OpenVMM processes.
+//!
+//! The source connects to the destination over a Unix socket or TCP and sends
+//! a [`SnapshotManifest`] describing the VM. Once the destination accepts it,
+//! the source copies guest RAM from the memory backing file in rounds while the
+//! VM keeps running, each round sending only the pages that changed since they
+//! were last sent. The caller then pauses the VM and calls
+//! [`MigrationSource::complete`], which sends the remaining changed pages and
+//! the device saved state, and waits for the destination to report that the VM
+//! is running there.
+//!
+//! Changed pages are found by comparing the SHA-256 hash of each page against
+//! the hash of the data last sent for it. Since the guest cannot construct
+//! colliding pages, a matching hash proves that the destination's copy is
+//! current. If the hypervisor tracks guest writes,
+//! [`MigrationSource::send_dirty_pages`] limits a round to the pages written
+//! since the previous one, and the final round sends the pages in the final
+//! bitmap without hashing them. The final round still hashes the other pages to
+//! find writes missing from the bitmap, but only sends the ones that changed.
+//!
+//! Wire format (all integers are little endian):
+//!
+//! - source: `OVMMMIG1`, a `u32` length and the protobuf-encoded manifest
+//! - destination: a status
+//! - source: any number of page batches: `1u8`, a `u32` count, then `count`
+//!   entries of a `u64` page index followed by the page data
+//! - source: `2u8`, a `u64` length and the saved state bytes
+//! - destination: a status
+//!
+//! A status is a `u8`, zero for success, followed on error by a `u32` length
+//! and a UTF-8 error message.
+
+use crate::fileio::read_exact_at;
+use crate::fileio::write_all_at;
+use crate::snapshot::SnapshotManifest;
+use anyhow::Context;
+use crypto::sha_256::sha_256;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::net::TcpListener;
+use std::net::TcpStream;
+use std::path::PathBuf;
+use std::str::FromStr;
+use thiserror::Error;
+use unix_socket::UnixListener;
+use unix_socket::UnixStream;
+
+/// The bytes that start a migration stream.
+const MAGIC: [u8; 8] = *b"OVMMMIG1";
+/// A batch of pages.
+const MSG_PAGES: u8 = 1;
+/// The device saved state, which ends the stream.
+const MSG_STATE: u8 = 2;
+/// The destination accepted the previous message.
+const STATUS_OK: u8 = 0;
+/// The destination failed; an error message follows.
+const STATUS_ERROR: u8 = 1;
+
+/// The granularity of the bitmap passed to
+/// [`MigrationSource::send_dirty_pages`].
+const DIRTY_PAGE_SIZE: usize = 4096;
+
+const MAX_MANIFEST_SIZE: u32 = 1 << 20;
+const MAX_STATE_SIZE: u64 = 1 << 30;
+const MAX_ERROR_SIZE: u32 = 64 << 10;
+/// The maximum number of pages in a batch.
+const MAX_BATCH_PAGES: usize = 256;
+/// The amount of guest RAM read from the memory file at a time.
+const READ_CHUNK_SIZE: usize = 1 << 20;
+
+/// The address of a migration destination: a Unix socket path, or
+/// `tcp:<ip>:<port>`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MigrationAddr {
+    /// A Unix socket path.
+    Unix(PathBuf),
+    /// A TCP socket address.
+    Tcp(SocketAddr),
+}
+
+impl FromStr for MigrationAddr {
+    type Err = std::net::AddrParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(tcp) = s.strip_prefix("tcp:") {
+            Ok(Self::Tcp(tcp.parse()?))
+        } else {
+            Ok(Self::Unix(s.into()))
+        }
+    }
+}
+
+impl fmt::Display for MigrationAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unix(path) => write!(f, "{}", path.display()),
+            Self::Tcp(addr) => write!(f, "tcp:{addr}"),
+        }
+    }
+}
+
+impl MigrationAddr {
+    /// Connects to a destination listening on this address.
+    pub fn connect(&self) -> io::Result<MigrationStream> {
+        let inner = match self {
+            Self::Unix(path) => StreamInner::Unix(UnixStream::connect(path)?),
+            Self::Tcp(addr) => {
+                let stream = TcpStream::connect(addr)?;
+                stream.set_nodelay(true)?;
+                StreamInner::Tcp(stream)
+            }
+        };
+        Ok(MigrationStream(inner))
+    }
+
+    /// Listens for an incoming migration on this address.
+    pub fn listen(&self) -> io::Result<MigrationListener> {
+        let inner = match self {
+            Self::Unix(path) => ListenerInner::Unix(UnixListener::bind(path)?, path.clone()),
+            Self::Tcp(addr) => ListenerInner::Tcp(TcpListener::bind(addr)?),
+        };
+        Ok(MigrationListener(inner))
+    }
+}
+
+/// A listener for an incoming migration, from [`MigrationAddr::listen`].
+pub struct MigrationListener(ListenerInner);
+
+enum ListenerInner {
+    Unix(UnixListener, PathBuf),
+    Tcp(TcpListener),
+}
+
+impl MigrationListener {
+    /// Waits for the source to connect.
+    ///
+    /// Only one migration is accepted per listener. For Unix sockets, the
+    /// socket file is removed once the source connects.
+    pub fn accept(self) -> io::Result<MigrationStream> {
+        let inner = match self.0 {
+            ListenerInner::Unix(listener, path) => {
+                let (stream, _) = listener.accept()?;
+                let _ = std::fs::remove_file(path);
+                StreamInner::Unix(stream)
+            }
+            ListenerInner::Tcp(listener) => {
+                let (stream, _) = listener.accept()?;
+                stream.set_nodelay(true)?;
+                StreamInner::Tcp(stream)
+            }
+        };
+        Ok(MigrationStream(inner))
+    }
+}
+
+/// A connected migration stream.
+pub struct MigrationStream(StreamInner);
+
+enum StreamInner {
+    Unix(UnixStream),
+    Tcp(TcpStream),
+}
+
+impl Read for MigrationStream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            StreamInner::Unix(s) => s.read(buf),
+            StreamInner::Tcp(s) => s.read(buf),
+        }
+    }
+}
+
+impl Write for MigrationStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            StreamInner::Unix(s) => s.write(buf),
+            StreamInner::Tcp(s) => s.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            StreamInner::Unix(s) => s.flush(),
+            StreamInner::Tcp(s) => s.flush(),
+        }
+    }
+}
+
+/// An error from [`MigrationSource::complete`].
+#[derive(Debug, Error)]
+pub enum CompleteError {
+    /// The VM was not started on the destination, so it can keep running on
+    /// the source.
+    #[error("migration failed")]
+    NotStarted(#[source] anyhow::Error),
+    /// The connection failed after the device saved state was sent, so the VM
+    /// may be running on the destination.
+    #[error("lost the destination after sending the device state")]
+    Unknown(#[source] anyhow::Error),
+}
+
+/// The sending side of a migration.
+pub struct MigrationSource<S> {
+    stream: S,
+    memory: File,
+    page_size: usize,
+    /// The SHA-256 hash of the data last sent for each page.
+    hashes: Vec<[u8; 32]>,
+}
+
+/// What to do with a page in [`MigrationSource::send_pages`].
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum PageAction {
+    /// The page is known not to have changed.
+    Skip,
+    /// Send the page if its hash changed.
+    Compare,
+    /// Send the page without comparing it.
+    Send,
+}
+
+impl<S: Read + Write> MigrationSource<S> {
+    /// Starts a migration of the VM described by `manifest`, whose guest RAM
+    /// is backed by `memory`.
+    ///
+    /// Fails if the destination rejects the manifest.
+    pub fn new(mut stream: S, memory: File, manifest: &SnapshotManifest) -> anyhow::Result<Self> {
+        let page_size = manifest.page_size as usize;
+        anyhow::ensure!(
+            page_size.is_power_of_two()
+                && manifest.memory_size_bytes.is_multiple_of(page_size as u64),
+            "memory size {:#x} is not a multiple of the page size {:#x}",
+            manifest.memory_size_bytes,
+            page_size
+        );
+        let len = memory.metadata()?.len();
+        anyhow::ensure!(
+            len == manifest.memory_size_bytes,
+            "memory backing file has size {len} bytes, expected {} bytes",
+            manifest.memory_size_bytes
+        );
+
+        let manifest = mesh::payload::encode(manifest.clone());
+        stream.write_all(&MAGIC)?;
+        stream.write_all(&(manifest.len() as u32).to_le_bytes())?;
+        stream.write_all(&manifest)?;
+        stream.flush()?;
+        read_status(&mut stream)?.context("destination rejected the migration")?;
+
+        // The destination's memory starts out zeroed, so zero pages never need
+        // to be sent.
+        let zero_hash = sha_256(&vec![0u8; page_size]);
+        Ok(Self {
+            stream,
+            memory,
+            page_size,
+            hashes: vec![zero_hash; (len / page_size as u64) as usize],
+        })
+    }
+
+    /// Sends all pages that changed since they were last sent, returning the
+    /// number of pages sent.
+    ///
+    /// This can be called while the VM is running; pages written by the guest
+    /// during the call are sent by a later call.
+    pub fn send_changed_pages(&mut self) -> anyhow::Result<u64> {
+        self.send_pages(|_| PageAction::Compare)
+    }
+
+    /// Like [`Self::send_changed_pages`], but only examines the pages marked
+    /// in `dirty`, a bitmap with one bit per 4KiB of memory, least significant
+    /// bit first. Memory beyond the end of the bitmap is treated as dirty.
+    ///
+    /// This avoids reading and hashing all of memory each round. Writes that
+    /// are missing from the bitmap, such as device DMA when the bitmap comes
+    /// from the hypervisor, are still sent by [`Self::complete`], which
+    /// examines every page.
+    pub fn send_dirty_pages(&mut self, dirty: &[u64]) -> anyhow::Result<u64> {
+        let page_size = self.page_size;
+        self.send_pages(|page| {
+            if is_dirty(dirty, page, page_size) {
+                PageAction::Compare
+            } else {
+                PageAction::Skip
+            }
+        })
+    }
+
+    /// Sends the pages that `action` selects, returning the number of pages
+    /// sent.
+    fn send_pages(&mut self, action: impl Fn(usize) -> PageAction) -> anyhow::Result<u64> {
+        let pages_per_chunk = (READ_CHUNK_SIZE / self.page_size).max(1);
+        let mut chunk = vec![0; pages_per_chunk * self.page_size];
+        let mut batch = PageBatch::new(self.page_size);
+        let mut sent = 0;
+        for first in (0..self.hashes.len()).step_by(pages_per_chunk) {
+            let count = pages_per_chunk.min(self.hashes.len() - first);
+            if (first..first + count).all(|page| action(page) == PageAction::Skip) {
+                continue;
+            }
+            let chunk = &mut chunk[..count * self.page_size];
+            read_exact_at(&self.memory, chunk, (first * self.page_size) as u64)
+                .context("failed to read guest memory")?;
+            for (i, page) in chunk.chunks_exact(self.page_size).enumerate() {
+                let index = first + i;
+                let send = match action(index) {
+                    PageAction::Skip => false,
+                    PageAction::Compare => {
+                        let hash = sha_256(page);
+                        let changed = hash != self.hashes[index];
+                        self.hashes[index] = hash;
+                        changed
+                    }
+                    PageAction::Send => true,
+                };
+                if send {
+                    batch.push(index as u64, page);
+                    sent += 1;
+                    if batch.len() == MAX_BATCH_PAGES {
+                        batch.send(&mut self.stream)?;
+                    }
+                }
+            }
+        }
+        batch.send(&mut self.stream)?;
+        self.stream.flush()?;
+        Ok(sent)
+    }
+
+    /// Completes the migration by sending the remaining changed pages and the
+    /// device saved state, then waiting for the destination to restore and
+    /// start the VM. Returns the number of pages sent.
+    ///
+    /// `dirty` is the bitmap of pages written since the last round, in the
+    /// format of [`Self::send_dirty_pages`], if the hypervisor tracks writes.
+    /// Those pages are sent without being hashed; every other page is hashed
+    /// and sent only if it changed.
+    ///
+    /// The VM must be paused before calling this. If this fails with
+    /// [`CompleteError::Unknown`], the VM must stay paused, since it may
+    /// already be running on the destination.
+    pub fn complete(
+        mut self,
+        dirty: Option<&[u64]>,
+        saved_state: &[u8],
+    ) -> Result<u64, CompleteError> {
+        let page_size = self.page_size;
+        let sent = self
+            .send_pages(|page| match dirty {
+                Some(dirty) if is_dirty(dirty, page, page_size) => PageAction::Send,
+                _ => PageAction::Compare,
+            })
+            .map_err(CompleteError::NotStarted)?;
+        self.send_state(saved_state)
+            .map_err(|err| CompleteError::Unknown(err.into()))?
+            .context("destination failed to restore the VM")
+            .map_err(CompleteError::NotStarted)?;
+        Ok(sent)
+    }
+
+    /// Sends the device saved state and reads the destination's status.
+    fn send_state(&mut self, saved_state: &[u8]) -> io::Result<anyhow::Result<()>> {
+        self.stream.write_all(&[MSG_STATE])?;
+        self.stream
+            .write_all(&(saved_state.len() as u64).to_le_bytes())?;
+        self.stream.write_all(saved_state)?;
+        self.stream.flush()?;
+        read_status(&mut self.stream)
+    }
+}
+
+/// Returns whether `page` is marked in `dirty`, a bitmap with one bit per
+/// [`DIRTY_PAGE_SIZE`] bytes. Memory beyond the end of the bitmap is dirty.
+fn is_dirty(dirty: &[u64], page: usize, page_size: usize) -> bool {
+    let start = page * page_size / DIRTY_PAGE_SIZE;
+    let end = ((page + 1) * page_size).div_ceil(DIRTY_PAGE_SIZE);
+    (start..end).any(|n| dirty.get(n / 64).is_none_or(|w| w & (1 << (n % 64)) != 0))
+}
+
+/// A batch of pages being built for sending.
+struct PageBatch {
+    buf: Vec<u8>,
+    count: u32,
+}
+
+impl PageBatch {
+    fn new(page_size: usize) -> Self {
+        Self {
+            buf: Vec::with_capacity(5 + MAX_BATCH_PAGES * (8 + page_size)),
+            count: 0,
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.count as usize
+    }
+
+    fn push(&mut self, index: u64, data: &[u8]) {
+        if self.count == 0 {
+            self.buf.push(MSG_PAGES);
+            self.buf.extend_from_slice(&[0; 4]);
+        }
+        self.buf.extend_from_slice(&index.to_le_bytes());
+        self.buf.extend_from_slice(data);
+        self.count += 1;
+    }
+
+    fn send(&mut self, stream: &mut impl Write) -> io::Result<()> {
+        if self.count == 0 {
+            return Ok(());
+        }
+        self.buf[1..5].copy_from_slice(&self.count.to_le_bytes());
+        stream.write_all(&self.buf)?;
+        self.buf.clear();
+        self.count = 0;
+        Ok(())
+    }
+}
+
+/// The receiving side of a migration.
+pub struct MigrationTarget<S> {
+    stream: S,
+    manifest: SnapshotManifest,
+}
+
+impl<S: Read + Write> MigrationTarget<S> {
+    /// Accepts a migration from the source connected on `stream`.
+    ///
+    /// `validate` checks that the source's manifest is compatible with the
+    /// VM configured here; if it fails, the error is reported to the source.
+    pub fn accept(
+        mut stream: S,
+        validate: impl FnOnce(&SnapshotManifest) -> anyhow::Result<()>,
+    ) -> anyhow::Result<Self> {
+        let mut magic = [0; 8];
+        stream.read_exact(&mut magic)?;
+        anyhow::ensure!(magic == MAGIC, "not a migration stream");
+        let len = read_u32(&mut stream)?;
+        anyhow::ensure!(
+            len <= MAX_MANIFEST_SIZE,
+            "migration manifest too large: {len} bytes"
+        );
+        let mut manifest = vec![0; len as usize];
+        stream.read_exact(&mut manifest)?;
+        let manifest: SnapshotManifest =
+            mesh::payload::decode(&manifest).context("failed to decode migration manifest")?;
+        let result = if manifest.page_size.is_power_of_two() {
+            validate(&manifest)
+        } else {
+            Err(anyhow::anyhow!(
+                "invalid page size {:#x}",
+                manifest.page_size
+            ))
+        };
+        if let Err(err) = result {
+            write_error(&mut stream, &err)?;
+            return Err(err);
+        }
+        stream.write_all(&[STATUS_OK])?;
+        stream.flush()?;
+        Ok(Self { stream, manifest })
+    }
+
+    /// Returns the source's manifest.
+    pub fn manifest(&self) -> &SnapshotManifest {
+        &self.manifest
+    }
+
+    /// Receives guest RAM into `memory` until the source sends the device
+    /// saved state, which is returned.
+    ///
+    /// `memory` must be sized to the guest RAM size and initially zeroed.
+    pub fn receive(&mut self, memory: &File) -> anyhow::Result<Vec<u8>> {
+        let page_size = self.manifest.page_size as usize;
+        let page_count = self.manifest.memory_size_bytes / page_size as u64;
+        let mut page = vec![0; page_size];
+        loop {
+            let mut msg = [0];
+            self.stream.read_exact(&mut msg)?;
+            match msg[0] {
+                MSG_PAGES => {
+                    let count = read_u32(&mut self.stream)?;
+                    for _ in 0..count {
+                        let index = read_u64(&mut self.stream)?;
+                        anyhow::ensure!(index < page_count, "page index {index} out of range");
+                        self.stream.read_exact(&mut page)?;
+                        write_all_at(memory, &page, index * page_size as u64)
+                            .context("failed to write guest memory")?;
+                    }
+                }
+                MSG_STATE => {
+                    let len = read_u64(&mut self.stream)?;
+                    anyhow::ensure!(
+                        len <= MAX_STATE_SIZE,
+                        "migration saved state too large: {len} bytes"
+                    );
+                    let mut state = vec![0; len as usize];
+                    self.stream.read_exact(&mut state)?;
+                    return Ok(state);
+                }
+                msg => anyhow::bail!("unknown migration message {msg:#x}"),
+            }
+        }
+    }
+
+    /// Reports to the source that the VM was restored and the migration
+    /// succeeded.
+    pub fn complete(mut self) -> io::Result<()> {
+        self.stream.write_all(&[STATUS_OK])?;
+        self.stream.flush()
+    }
+
+    /// Reports to the source that the VM could not be restored.
+    pub fn fail(mut self, err: &anyhow::Error) -> io::Result<()> {
+        write_error(&mut self.stream, err)
+    }
+}
+
+fn read_u32(stream: &mut impl Read) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    stream.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_u64(stream: &mut impl Read) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    stream.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+/// Reads a status from the destination, returning the error that the
+/// destination reported, if any.
+fn read_status(stream: &mut impl Read) -> io::Result<anyhow::Result<()>> {
+    let mut status = [0];
+    stream.read_exact(&mut status)?;
+    match status[0] {
+        STATUS_OK => Ok(Ok(())),
+        STATUS_ERROR => {
+            let len = read_u32(stream)?.min(MAX_ERROR_SIZE);
+            let mut msg = vec![0; len as usize];
+            stream.read_exact(&mut msg)?;
+            Ok(Err(anyhow::anyhow!("{}", String::from_utf8_lossy(&msg))))
+        }
+        status => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown migration status {status:#x}"),
+        )),
+    }
+}
+
+fn write_error(stream: &mut impl Write, err: &anyhow::Error) -> io::Result<()> {
+    let msg = format!("{err:#}");
+    let msg = &msg.as_bytes()[..msg.len().min(MAX_ERROR_SIZE as usize)];
+    stream.write_all(&[STATUS_ERROR])?;
+    stream.write_all(&(msg.len() as u32).to_le_bytes())?;
+    stream.write_all(msg)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snapshot::MANIFEST_VERSION;
+    use mesh::payload::Timestamp;
+    use std::thread;
+
+    const PAGE_SIZE: u32 = 4096;
+    const MEMORY_SIZE: u64 = 64 * PAGE_SIZE as u64;
+
+    fn test_manifest() -> SnapshotManifest {
+        SnapshotManifest {
+            version: MANIFEST_VERSION,
+            created_at: Timestamp {
+                seconds: 1234567890,
+                nanos: 0,
+            },
+            openvmm_version: "test-0.1.0".to_string(),
+            memory_size_bytes: MEMORY_SIZE,
+            vp_count: 2,
+            page_size: PAGE_SIZE,
+            architecture: "x86_64".to_string(),
+            parent: None,
+        }
+    }
+
+    fn zeroed_memory() -> File {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len(MEMORY_SIZE).unwrap();
+        file
+    }
+
+    fn fill_page(file: &File, index: u64, value: u8) {
+        write_all_at(file, &[value; PAGE_SIZE as usize], index * PAGE_SIZE as u64).unwrap();
+    }
+
+    fn read_all(file: &File) -> Vec<u8> {
+        let mut buf = vec![0; MEMORY_SIZE as usize];
+        read_exact_at(file, &mut buf, 0).unwrap();
+        buf
+    }
+
+    fn listen() -> (TcpListener, MigrationAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = MigrationAddr::Tcp(listener.local_addr().unwrap());
+        (listener, addr)
+    }
+
+    #[test]
+    fn parse_addr() {
+        assert_eq!(
+            "tcp:127.0.0.1:1234".parse::<MigrationAddr>().unwrap(),
+            MigrationAddr::Tcp("127.0.0.1:1234".parse().unwrap())
+        );
+        assert_eq!(
+            "/tmp/migrate.sock".parse::<MigrationAddr>().unwrap(),
+            MigrationAddr::Unix("/tmp/migrate.sock".into())
+        );
+        assert!("tcp:localhost".parse::<MigrationAddr>().is_err());
+    }
+
+    #[test]
+    fn migrate_loopback() {
+        let (listener, addr) = listen();
+        let target = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut target = MigrationTarget::accept(stream, |_| Ok(())).unwrap();
+            let memory = zeroed_memory();
+            let state = target.receive(&memory).unwrap();
+            target.complete().unwrap();
+            (read_all(&memory), state)
+        });
+
+        let memory = zeroed_memory();
+        for i in (0..64).step_by(3) {
+            fill_page(&memory, i, i as u8 + 1);
+        }
+        let mut source = MigrationSource::new(
+            addr.connect().unwrap(),
+            memory.try_clone().unwrap(),
+            &test_manifest(),
+        )
+        .unwrap();
+        // Zero pages are skipped.
+        assert_eq!(source.send_changed_pages().unwrap(), 22);
+        assert_eq!(source.send_changed_pages().unwrap(), 0);
+
+        // Simulate guest writes between rounds, including zeroing a page that
+        // was already sent.
+        fill_page(&memory, 1, 0xaa);
+        fill_page(&memory, 3, 0);
+        assert_eq!(source.send_changed_pages().unwrap(), 2);
+
+        // Only the page changed since the last round is sent while paused.
+        fill_page(&memory, 63, 0x55);
+        assert_eq!(source.complete(None, b"device-state").unwrap(), 1);
+
+        let (target_memory, state) = target.join().unwrap();
+        assert_eq!(state, b"device-state");
+        assert!(target_memory == read_all(&memory));
+    }
+
+    #[test]
+    fn migrate_dirty_bitmap() {
+        let (listener, addr) = listen();
+        let target = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut target = MigrationTarget::accept(stream, |_| Ok(())).unwrap();
+            let memory = zeroed_memory();
+            target.receive(&memory).unwrap();
+            target.complete().unwrap();
+            read_all(&memory)
+        });
+
+        let memory = zeroed_memory();
+        let mut source = MigrationSource::new(
+            addr.connect().unwrap(),
+            memory.try_clone().unwrap(),
+            &test_manifest(),
+        )
+        .unwrap();
+        assert_eq!(source.send_changed_pages().unwrap(), 0);
+
+        // Only pages marked dirty are examined.
+        fill_page(&memory, 2, 0x11);
+        fill_page(&memory, 5, 0x22);
+        assert_eq!(source.send_dirty_pages(&[1 << 2]).unwrap(), 1);
+        // Marked pages that did not change are not sent again.
+        assert_eq!(source.send_dirty_pages(&[(1 << 2) | (1 << 3)]).unwrap(), 0);
+        // Memory past the end of the bitmap is examined.
+        fill_page(&memory, 63, 0x33);
+        assert_eq!(source.send_dirty_pages(&[]).unwrap(), 2);
+
+        // The final round sends the pages in the final bitmap, whether or not
+        // they changed, and finds the writes missing from it.
+        fill_page(&memory, 7, 0x44);
+        fill_page(&memory, 8, 0x55);
+        assert_eq!(
+            source.complete(Some(&[(1 << 2) | (1 << 8)]), b"").unwrap(),
+            3
+        );
+        assert!(target.join().unwrap() == read_all(&memory));
+    }
+
+    #[test]
+    fn manifest_rejected() {
+        let (listener, addr) = listen();
+        let target = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            MigrationTarget::accept(stream, |_| anyhow::bail!("VP count mismatch")).is_err()
+        });
+
+        let err = MigrationSource::new(addr.connect().unwrap(), zeroed_memory(), &test_manifest())
+            .err()
+            .unwrap();
+        assert!(
+            format!("{err:#}").contains("VP count mismatch"),
+            "unexpected error: {err:#}"
+        );
+        assert!(target.join().unwrap());
+    }
+
+    #[test]
+    fn restore_failed() {
+        let (listener, addr) = listen();
+        let target = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut target = MigrationTarget::accept(stream, |_| Ok(())).unwrap();
+            target.receive(&zeroed_memory()).unwrap();
+            target.fail(&anyhow::anyhow!("bad state")).unwrap();
+        });
+
+        let source =
+            MigrationSource::new(addr.connect().unwrap(), zeroed_memory(), &test_manifest())
+                .unwrap();
+        let err = match source.complete(None, b"state") {
+            Err(CompleteError::NotStarted(err)) => err,
+            r => panic!("unexpected result: {r:?}"),
+        };
+        assert!(
+            format!("{err:#}").contains("bad state"),
+            "unexpected error: {err:#}"
+        );
+        target.join().unwrap();
+    }
+
+    #[test]
+    fn restore_status_lost() {
+        let (listener, addr) = listen();
+        let target = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut target = MigrationTarget::accept(stream, |_| Ok(())).unwrap();
+            // Receive the state, then drop the connection as if the status
+            // was lost after the VM started here.
+            target.receive(&zeroed_memory()).unwrap()
+        });
+
+        let source =
+            MigrationSource::new(addr.connect().unwrap(), zeroed_memory(), &test_manifest())
+                .unwrap();
+        let err = source.complete(None, b"state").unwrap_err();
+        assert!(
+            matches!(err, CompleteError::Unknown(_)),
+            "unexpected error: {err:#}"
+        );
+        assert_eq!(target.join().unwrap(), b"state");
+    }
+
+    #[test]
+    fn memory_size_mismatch() {
+        let (_listener, addr) = listen();
+        let memory = tempfile::tempfile().unwrap();
+        memory.set_len(MEMORY_SIZE / 2).unwrap();
+        assert!(MigrationSource::new(addr.connect().unwrap(), memory, &test_manifest()).is_err());
+    }
+}
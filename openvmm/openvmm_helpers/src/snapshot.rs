@@ -2,15 +2,66 @@
 // Licensed under the MIT License.
 
 //! Snapshot manifest types and I/O functions for saving/restoring VM snapshots.
-
+//!
+//! A snapshot stores guest memory in one of two ways:
+//!
+//! - `memory.bin`, a hard link to the VM's memory backing file. This is free to
+//!   create, but the VM cannot run again without changing the snapshot.
+//! - `memory.pages`, a copy of guest memory made up of individual, optionally
+//!   compressed pages. Zero pages are not stored, and a snapshot with a parent
+//!   only stores the pages that differ from the parent's memory.
+//!
+//! Page file format (all integers are little endian):
+//!
+//! - header: `OVMMPAGE`, a `u32` version, a `u32` page size, a `u64` memory
+//!   size and a `u32` flags field, padded to 32 bytes
+//! - any number of records: a `u64` page index, a `u32` length and the page
+//!   data. A length of zero means a zero page, a length equal to the page size
+//!   means uncompressed data, and anything else means raw deflate data.
+//!
+//! Records later in the file override earlier ones for the same page.
+
+use crate::fileio::read_exact_at;
+use crate::fileio::write_all_at;
 use anyhow::Context;
+use flate2::Compression;
+use flate2::read::DeflateDecoder;
+use flate2::write::DeflateEncoder;
 use mesh::payload::Protobuf;
 use mesh::payload::Timestamp;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::Read;
+use std::io::Write;
+use std::path::Component;
 use std::path::Path;
+use std::path::PathBuf;
 
 /// Current manifest format version. Bump when making incompatible changes.
 pub const MANIFEST_VERSION: u32 = 1;
 
+/// The guest memory file of a snapshot that links the memory backing file.
+const MEMORY_BIN: &str = "memory.bin";
+/// The guest memory file of a snapshot that stores individual pages.
+const MEMORY_PAGES: &str = "memory.pages";
+
+/// The bytes that start a page file.
+const PAGE_FILE_MAGIC: [u8; 8] = *b"OVMMPAGE";
+const PAGE_FILE_VERSION: u32 = 1;
+const PAGE_FILE_HEADER_SIZE: usize = 32;
+/// Page file flag: records may hold deflate-compressed pages.
+const PAGE_FILE_FLAG_COMPRESSED: u32 = 1;
+/// The size of a page record header.
+const PAGE_RECORD_HEADER_SIZE: usize = 12;
+
+/// The maximum number of snapshots in a chain.
+const MAX_CHAIN_DEPTH: usize = 256;
+/// The amount of guest memory to read at a time.
+const READ_CHUNK_SIZE: usize = 1 << 20;
+
 /// Manifest describing a VM snapshot.
 #[derive(Clone, Protobuf)]
 #[mesh(package = "openvmm.snapshot")]
@@ -36,6 +87,10 @@ pub struct SnapshotManifest {
     /// Architecture string ("x86_64" or "aarch64").
     #[mesh(7)]
     pub architecture: String,
+    /// The directory of the snapshot that this snapshot's memory pages are a
+    /// diff against, relative to this snapshot's directory.
+    #[mesh(8)]
+    pub parent: Option<String>,
 }
 
 /// Write a snapshot to the given directory.
@@ -60,7 +115,7 @@ pub fn write_snapshot(
     fs_err::write(dir.join("state.bin"), saved_state_bytes)?;
 
     // Handle memory.bin: hard-link from the backing file.
-    let memory_bin_path = dir.join("memory.bin");
+    let memory_bin_path = dir.join(MEMORY_BIN);
     let canonical_source = fs_err::canonicalize(memory_file_path)?;
 
     // Check whether source and target are already the same file (e.g.,
@@ -100,22 +155,402 @@ pub fn write_snapshot(
         }
     }
 
+    // Memory pages left by an earlier snapshot in this directory would take
+    // precedence over memory.bin.
+    remove_if_exists(&dir.join(MEMORY_PAGES))?;
+
     Ok(())
 }
 
+/// Write a snapshot that stores guest memory as individual pages in
+/// `memory.pages` instead of linking the memory backing file.
+///
+/// Zero pages are not stored. If `parent` is set, only the pages that differ
+/// from the memory of the snapshot in that directory are stored, and the
+/// parent must stay in place to restore this snapshot. If `compress` is set,
+/// pages are deflate-compressed.
+///
+/// `memory` is the memory backing file, which must not change until this
+/// returns. The snapshot does not reference it afterwards, so the VM can keep
+/// running.
+///
+/// Returns the number of pages stored.
+pub fn write_snapshot_pages(
+    dir: &Path,
+    manifest: &SnapshotManifest,
+    saved_state_bytes: &[u8],
+    memory: &File,
+    parent: Option<&Path>,
+    compress: bool,
+) -> anyhow::Result<u64> {
+    let page_size = manifest.page_size as usize;
+    let memory_size = manifest.memory_size_bytes;
+    anyhow::ensure!(
+        page_size.is_power_of_two() && memory_size.is_multiple_of(page_size as u64),
+        "memory size {memory_size:#x} is not a multiple of the page size {page_size:#x}",
+    );
+    let len = memory.metadata()?.len();
+    anyhow::ensure!(
+        len == memory_size,
+        "memory backing file has size {len} bytes, expected {memory_size} bytes",
+    );
+
+    fs_err::create_dir_all(dir)?;
+    let dir = fs_err::canonicalize(dir)?;
+
+    let mut manifest = manifest.clone();
+    manifest.parent = None;
+    let parent = if let Some(parent) = parent {
+        let parent_memory = SnapshotMemory::open(parent)
+            .with_context(|| format!("failed to open parent snapshot {}", parent.display()))?;
+        anyhow::ensure!(
+            parent_memory.memory_size == memory_size && parent_memory.page_size == page_size,
+            "parent snapshot memory size or page size doesn't match",
+        );
+        anyhow::ensure!(
+            !parent_memory.dirs.contains(&dir),
+            "cannot overwrite a snapshot in the parent chain",
+        );
+        let parent_dir = relative_path(&dir, parent_memory.dirs.last().unwrap());
+        manifest.parent = Some(
+            parent_dir
+                .into_os_string()
+                .into_string()
+                .ok()
+                .context("parent snapshot path is not valid UTF-8")?,
+        );
+        Some(parent_memory)
+    } else {
+        None
+    };
+
+    // Remove the manifest first so that an interrupted save does not leave
+    // something that looks like a complete snapshot.
+    remove_if_exists(&dir.join("manifest.bin"))?;
+
+    let file = fs_err::File::create(dir.join(MEMORY_PAGES))?;
+    let mut writer = PageFileWriter::new(BufWriter::new(file), page_size, memory_size, compress)?;
+    let page_count = (memory_size / page_size as u64) as usize;
+    let pages_per_chunk = (READ_CHUNK_SIZE / page_size).max(1);
+    let mut chunk = vec![0; pages_per_chunk * page_size];
+    let mut parent_page = vec![0; page_size];
+    for first in (0..page_count).step_by(pages_per_chunk) {
+        let count = pages_per_chunk.min(page_count - first);
+        let chunk = &mut chunk[..count * page_size];
+        read_exact_at(memory, chunk, (first * page_size) as u64)
+            .context("failed to read guest memory")?;
+        for (i, page) in chunk.chunks_exact(page_size).enumerate() {
+            let index = first + i;
+            let unchanged = if let Some(parent) = &parent {
+                parent.read_page(index, &mut parent_page)?;
+                *page == *parent_page
+            } else {
+                is_zero(page)
+            };
+            if !unchanged {
+                writer.write_page(index as u64, page)?;
+            }
+        }
+    }
+    let pages = writer.finish()?;
+
+    fs_err::write(dir.join("state.bin"), saved_state_bytes)?;
+    remove_if_exists(&dir.join(MEMORY_BIN))?;
+    fs_err::write(dir.join("manifest.bin"), mesh::payload::encode(manifest))?;
+    Ok(pages)
+}
+
 /// Read a snapshot from the given directory.
 ///
 /// Returns the decoded manifest and the raw saved-state bytes.
-/// The caller is responsible for opening `memory.bin` separately.
+/// The caller is responsible for opening the memory separately: either
+/// `memory.bin`, or [`SnapshotMemory`] if [`has_memory_pages`] returns true.
 pub fn read_snapshot(dir: &Path) -> anyhow::Result<(SnapshotManifest, Vec<u8>)> {
+    let manifest = read_manifest(dir)?;
+    let state_bytes = fs_err::read(dir.join("state.bin")).context("failed to read state.bin")?;
+
+    Ok((manifest, state_bytes))
+}
+
+fn read_manifest(dir: &Path) -> anyhow::Result<SnapshotManifest> {
     let manifest_bytes =
         fs_err::read(dir.join("manifest.bin")).context("failed to read manifest.bin")?;
-    let manifest: SnapshotManifest =
-        mesh::payload::decode(&manifest_bytes).context("failed to decode snapshot manifest")?;
+    mesh::payload::decode(&manifest_bytes).context("failed to decode snapshot manifest")
+}
 
-    let state_bytes = fs_err::read(dir.join("state.bin")).context("failed to read state.bin")?;
+/// Returns whether the snapshot in `dir` stores guest memory as pages, to be
+/// read with [`SnapshotMemory`], rather than in `memory.bin`.
+pub fn has_memory_pages(dir: &Path) -> bool {
+    dir.join(MEMORY_PAGES).exists()
+}
 
-    Ok((manifest, state_bytes))
+/// The guest memory of a snapshot stored as pages, including the pages
+/// inherited from its parents.
+pub struct SnapshotMemory {
+    page_size: usize,
+    memory_size: u64,
+    /// The canonical directories of the snapshots in the chain, from the base
+    /// to the leaf.
+    dirs: Vec<PathBuf>,
+    /// The page files of the snapshots in the chain, from the base to the
+    /// leaf.
+    layers: Vec<File>,
+    /// The location of each page's data, or `None` for zero pages.
+    pages: Vec<Option<PageLocation>>,
+}
+
+#[derive(Copy, Clone)]
+struct PageLocation {
+    layer: u32,
+    len: u32,
+    offset: u64,
+}
+
+impl SnapshotMemory {
+    /// Opens the memory of the snapshot in `dir` and its parent chain.
+    pub fn open(dir: &Path) -> anyhow::Result<Self> {
+        // Walk the chain from the leaf to the base.
+        let mut chain = Vec::new();
+        let mut dir = fs_err::canonicalize(dir)?;
+        loop {
+            anyhow::ensure!(
+                chain.len() < MAX_CHAIN_DEPTH,
+                "snapshot chain exceeds maximum depth of {MAX_CHAIN_DEPTH}"
+            );
+            let manifest = read_manifest(&dir)
+                .with_context(|| format!("failed to read snapshot {}", dir.display()))?;
+            let parent = manifest
+                .parent
+                .as_ref()
+                .map(|parent| fs_err::canonicalize(dir.join(parent)))
+                .transpose()?;
+            chain.push((dir, manifest));
+            match parent {
+                Some(parent) => dir = parent,
+                None => break,
+            }
+        }
+        chain.reverse();
+
+        let leaf = &chain.last().unwrap().1;
+        let page_size = leaf.page_size as usize;
+        let memory_size = leaf.memory_size_bytes;
+        anyhow::ensure!(
+            page_size.is_power_of_two() && memory_size.is_multiple_of(page_size as u64),
+            "memory size {memory_size:#x} is not a multiple of the page size {page_size:#x}",
+        );
+
+        let mut pages = vec![None; (memory_size / page_size as u64) as usize];
+        let mut dirs = Vec::new();
+        let mut layers = Vec::new();
+        for (layer, (dir, manifest)) in chain.into_iter().enumerate() {
+            anyhow::ensure!(
+                manifest.page_size as usize == page_size
+                    && manifest.memory_size_bytes == memory_size,
+                "snapshot {} memory size or page size doesn't match its child",
+                dir.display(),
+            );
+            let path = dir.join(MEMORY_PAGES);
+            let file: File = fs_err::File::open(&path)
+                .with_context(|| format!("snapshot {} does not store memory pages", dir.display()))?
+                .into();
+            read_page_index(&file, layer as u32, page_size, memory_size, &mut pages)
+                .with_context(|| format!("failed to read {}", path.display()))?;
+            layers.push(file);
+            dirs.push(dir);
+        }
+
+        Ok(Self {
+            page_size,
+            memory_size,
+            dirs,
+            layers,
+            pages,
+        })
+    }
+
+    /// Returns the size of guest memory in bytes.
+    pub fn memory_size(&self) -> u64 {
+        self.memory_size
+    }
+
+    /// Writes the non-zero pages of guest memory to `memory`, which must
+    /// initially be zeroed.
+    pub fn write_to(&self, memory: &File) -> anyhow::Result<()> {
+        let mut page = vec![0; self.page_size];
+        for (index, location) in self.pages.iter().enumerate() {
+            if location.is_some() {
+                self.read_page(index, &mut page)?;
+                write_all_at(memory, &page, (index * self.page_size) as u64)
+                    .context("failed to write guest memory")?;
+            }
+        }
+        Ok(())
+    }
+
+    fn read_page(&self, index: usize, buf: &mut [u8]) -> anyhow::Result<()> {
+        let Some(location) = self.pages[index] else {
+            buf.fill(0);
+            return Ok(());
+        };
+        let file = &self.layers[location.layer as usize];
+        if location.len as usize == self.page_size {
+            read_exact_at(file, buf, location.offset)?;
+        } else {
+            let mut data = vec![0; location.len as usize];
+            read_exact_at(file, &mut data, location.offset)?;
+            DeflateDecoder::new(data.as_slice())
+                .read_exact(buf)
+                .with_context(|| format!("failed to decompress page {index}"))?;
+        }
+        Ok(())
+    }
+}
+
+/// Reads the records of a page file into `pages`.
+fn read_page_index(
+    file: &File,
+    layer: u32,
+    page_size: usize,
+    memory_size: u64,
+    pages: &mut [Option<PageLocation>],
+) -> anyhow::Result<()> {
+    let mut reader = BufReader::new(file);
+    let mut header = [0; PAGE_FILE_HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+    anyhow::ensure!(header[..8] == PAGE_FILE_MAGIC, "not a page file");
+    let version = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    anyhow::ensure!(
+        version == PAGE_FILE_VERSION,
+        "page file version {version} is not supported"
+    );
+    let file_page_size = u32::from_le_bytes(header[12..16].try_into().unwrap());
+    let file_memory_size = u64::from_le_bytes(header[16..24].try_into().unwrap());
+    anyhow::ensure!(
+        file_page_size as usize == page_size && file_memory_size == memory_size,
+        "page file memory size or page size doesn't match the manifest"
+    );
+    let flags = u32::from_le_bytes(header[24..28].try_into().unwrap());
+    anyhow::ensure!(
+        flags & !PAGE_FILE_FLAG_COMPRESSED == 0,
+        "unsupported page file flags {flags:#x}"
+    );
+    let compressed = flags & PAGE_FILE_FLAG_COMPRESSED != 0;
+
+    let mut offset = PAGE_FILE_HEADER_SIZE as u64;
+    while !reader.fill_buf()?.is_empty() {
+        let mut record = [0; PAGE_RECORD_HEADER_SIZE];
+        reader.read_exact(&mut record)?;
+        let index = u64::from_le_bytes(record[..8].try_into().unwrap());
+        let len = u32::from_le_bytes(record[8..].try_into().unwrap());
+        let page = pages
+            .get_mut(index as usize)
+            .with_context(|| format!("page index {index} is out of range"))?;
+        anyhow::ensure!(
+            len == 0 || len as usize == page_size || (compressed && (len as usize) < page_size),
+            "page {index} has invalid length {len}"
+        );
+        offset += PAGE_RECORD_HEADER_SIZE as u64;
+        *page = (len != 0).then_some(PageLocation { layer, len, offset });
+        reader.seek_relative(len.into())?;
+        offset += u64::from(len);
+    }
+    let file_len = file.metadata()?.len();
+    anyhow::ensure!(offset == file_len, "page file is truncated");
+    Ok(())
+}
+
+/// Writes the records of a page file.
+struct PageFileWriter {
+    writer: BufWriter<fs_err::File>,
+    compress: bool,
+    buf: Vec<u8>,
+    pages: u64,
+}
+
+impl PageFileWriter {
+    fn new(
+        mut writer: BufWriter<fs_err::File>,
+        page_size: usize,
+        memory_size: u64,
+        compress: bool,
+    ) -> io::Result<Self> {
+        let mut header = [0; PAGE_FILE_HEADER_SIZE];
+        header[..8].copy_from_slice(&PAGE_FILE_MAGIC);
+        header[8..12].copy_from_slice(&PAGE_FILE_VERSION.to_le_bytes());
+        header[12..16].copy_from_slice(&(page_size as u32).to_le_bytes());
+        header[16..24].copy_from_slice(&memory_size.to_le_bytes());
+        let flags = if compress {
+            PAGE_FILE_FLAG_COMPRESSED
+        } else {
+            0
+        };
+        header[24..28].copy_from_slice(&flags.to_le_bytes());
+        writer.write_all(&header)?;
+        Ok(Self {
+            writer,
+            compress,
+            buf: Vec::new(),
+            pages: 0,
+        })
+    }
+
+    fn write_page(&mut self, index: u64, page: &[u8]) -> io::Result<()> {
+        let data = if is_zero(page) {
+            &[][..]
+        } else if self.compress {
+            self.buf.clear();
+            let mut encoder = DeflateEncoder::new(&mut self.buf, Compression::fast());
+            encoder.write_all(page)?;
+            encoder.finish()?;
+            // Store pages that do not compress as is.
+            if self.buf.len() < page.len() {
+                &self.buf
+            } else {
+                page
+            }
+        } else {
+            page
+        };
+        self.writer.write_all(&index.to_le_bytes())?;
+        self.writer.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.writer.write_all(data)?;
+        self.pages += 1;
+        Ok(())
+    }
+
+    /// Flushes the file to disk, returning the number of pages written.
+    fn finish(self) -> io::Result<u64> {
+        let file = self.writer.into_inner().map_err(|err| err.into_error())?;
+        file.sync_all()?;
+        Ok(self.pages)
+    }
+}
+
+fn is_zero(page: &[u8]) -> bool {
+    page.iter().all(|&b| b == 0)
+}
+
+fn remove_if_exists(path: &Path) -> io::Result<()> {
+    match fs_err::remove_file(path) {
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        r => r,
+    }
+}
+
+/// Returns the path to `to` relative to the directory `from`. Both paths must
+/// be canonical.
+fn relative_path(from: &Path, to: &Path) -> PathBuf {
+    let from = from.components().collect::<Vec<_>>();
+    let to = to.components().collect::<Vec<_>>();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+    if common == 0 {
+        // There is no relative path between different Windows drives.
+        return to.iter().collect();
+    }
+    std::iter::repeat_n(Component::ParentDir, from.len() - common)
+        .chain(to[common..].iter().copied())
+        .collect()
 }
 
 /// Validate that a snapshot manifest is compatible with the running VM config.
@@ -190,6 +625,7 @@ fn test_manifest() -> SnapshotManifest {
             vp_count: 2,
             page_size: 4096,
             architecture: "x86_64".to_string(),
+            parent: None,
         }
     }
 
@@ -259,6 +695,243 @@ fn read_snapshot_missing_file() {
         assert!(result.is_err());
     }
 
+    const PAGE_SIZE: usize = 4096;
+    const PAGE_COUNT: usize = 16;
+
+    fn pages_manifest() -> SnapshotManifest {
+        SnapshotManifest {
+            memory_size_bytes: (PAGE_COUNT * PAGE_SIZE) as u64,
+            ..test_manifest()
+        }
+    }
+
+    fn new_memory() -> File {
+        let file = tempfile::tempfile().unwrap();
+        file.set_len((PAGE_COUNT * PAGE_SIZE) as u64).unwrap();
+        file
+    }
+
+    fn fill_page(memory: &File, index: usize, value: u8) {
+        write_all_at(memory, &[value; PAGE_SIZE], (index * PAGE_SIZE) as u64).unwrap();
+    }
+
+    /// Fills a page with data that does not compress.
+    fn fill_page_random(memory: &File, index: usize) {
+        let mut x = 0x2545f4914f6cdd1du64 ^ index as u64;
+        let page = (0..PAGE_SIZE / 8)
+            .flat_map(|_| {
+                x ^= x << 13;
+                x ^= x >> 7;
+                x ^= x << 17;
+                x.to_le_bytes()
+            })
+            .collect::<Vec<_>>();
+        write_all_at(memory, &page, (index * PAGE_SIZE) as u64).unwrap();
+    }
+
+    fn read_memory(memory: &File) -> Vec<u8> {
+        let mut buf = vec![0; PAGE_COUNT * PAGE_SIZE];
+        read_exact_at(memory, &mut buf, 0).unwrap();
+        buf
+    }
+
+    fn restore_memory(dir: &Path) -> Vec<u8> {
+        let snapshot_memory = SnapshotMemory::open(dir).unwrap();
+        let memory = tempfile::tempfile().unwrap();
+        memory.set_len(snapshot_memory.memory_size()).unwrap();
+        snapshot_memory.write_to(&memory).unwrap();
+        read_memory(&memory)
+    }
+
+    #[test]
+    fn pages_roundtrip() {
+        for compress in [false, true] {
+            let dir = tempfile::tempdir().unwrap();
+            let memory = new_memory();
+            fill_page(&memory, 1, 0xaa);
+            fill_page_random(&memory, 5);
+
+            // Zero pages are skipped.
+            let pages = write_snapshot_pages(
+                dir.path(),
+                &pages_manifest(),
+                b"state",
+                &memory,
+                None,
+                compress,
+            )
+            .unwrap();
+            assert_eq!(pages, 2);
+            assert!(has_memory_pages(dir.path()));
+            assert!(!dir.path().join(MEMORY_BIN).exists());
+
+            let (manifest, state) = read_snapshot(dir.path()).unwrap();
+            assert_eq!(manifest.parent, None);
+            assert_eq!(state, b"state");
+            assert!(restore_memory(dir.path()) == read_memory(&memory));
+
+            let uncompressed_len =
+                (PAGE_FILE_HEADER_SIZE + 2 * (PAGE_RECORD_HEADER_SIZE + PAGE_SIZE)) as u64;
+            let len = std::fs::metadata(dir.path().join(MEMORY_PAGES))
+                .unwrap()
+                .len();
+            assert_eq!(len < uncompressed_len, compress);
+        }
+    }
+
+    #[test]
+    fn incremental_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_dir = dir.path().join("base");
+        let child_dir = dir.path().join("child");
+        let grandchild_dir = dir.path().join("grandchild");
+
+        let memory = new_memory();
+        for i in 1..4 {
+            fill_page(&memory, i, i as u8);
+        }
+        let base = read_memory(&memory);
+        write_snapshot_pages(&base_dir, &pages_manifest(), b"", &memory, None, false).unwrap();
+
+        // Unchanged memory produces an empty diff.
+        let pages = write_snapshot_pages(
+            &child_dir,
+            &pages_manifest(),
+            b"",
+            &memory,
+            Some(&base_dir),
+            false,
+        )
+        .unwrap();
+        assert_eq!(pages, 0);
+
+        // Changed pages, including a page that became zero, are stored.
+        fill_page(&memory, 2, 0x22);
+        fill_page(&memory, 3, 0);
+        fill_page(&memory, 7, 0x77);
+        let child = read_memory(&memory);
+        let pages = write_snapshot_pages(
+            &child_dir,
+            &pages_manifest(),
+            b"",
+            &memory,
+            Some(&base_dir),
+            false,
+        )
+        .unwrap();
+        assert_eq!(pages, 3);
+        let (manifest, _) = read_snapshot(&child_dir).unwrap();
+        assert_eq!(
+            manifest.parent.as_deref().map(Path::new),
+            Some(Path::new("../base"))
+        );
+
+        fill_page(&memory, 1, 0x11);
+        let pages = write_snapshot_pages(
+            &grandchild_dir,
+            &pages_manifest(),
+            b"",
+            &memory,
+            Some(&child_dir),
+            true,
+        )
+        .unwrap();
+        assert_eq!(pages, 1);
+
+        assert!(restore_memory(&base_dir) == base);
+        assert!(restore_memory(&child_dir) == child);
+        assert!(restore_memory(&grandchild_dir) == read_memory(&memory));
+    }
+
+    #[test]
+    fn overwrite_parent_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        let base_dir = dir.path().join("base");
+        let child_dir = dir.path().join("child");
+        let memory = new_memory();
+        write_snapshot_pages(&base_dir, &pages_manifest(), b"", &memory, None, false).unwrap();
+        write_snapshot_pages(
+            &child_dir,
+            &pages_manifest(),
+            b"",
+            &memory,
+            Some(&base_dir),
+            false,
+        )
+        .unwrap();
+
+        for (target, parent) in [(&base_dir, &base_dir), (&base_dir, &child_dir)] {
+            let err =
+                write_snapshot_pages(target, &pages_manifest(), b"", &memory, Some(parent), false)
+                    .unwrap_err();
+            assert!(
+                err.to_string().contains("parent chain"),
+                "unexpected error: {err}"
+            );
+        }
+        // The chain is still intact.
+        restore_memory(&child_dir);
+    }
+
+    #[test]
+    fn parent_without_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let mem_path = dir.path().join("memory.bin");
+        std::fs::write(&mem_path, vec![0; PAGE_COUNT * PAGE_SIZE]).unwrap();
+        let base_dir = dir.path().join("base");
+        write_snapshot(&base_dir, &pages_manifest(), b"", &mem_path).unwrap();
+
+        assert!(
+            write_snapshot_pages(
+                &dir.path().join("child"),
+                &pages_manifest(),
+                b"",
+                &new_memory(),
+                Some(&base_dir),
+                false,
+            )
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn truncated_page_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let memory = new_memory();
+        fill_page(&memory, 0, 1);
+        write_snapshot_pages(dir.path(), &pages_manifest(), b"", &memory, None, false).unwrap();
+
+        let path = dir.path().join(MEMORY_PAGES);
+        let len = std::fs::metadata(&path).unwrap().len();
+        File::options()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(len - 1)
+            .unwrap();
+        assert!(SnapshotMemory::open(dir.path()).is_err());
+    }
+
+    #[test]
+    fn write_snapshot_replaces_pages() {
+        let dir = tempfile::tempdir().unwrap();
+        let snap_dir = dir.path().join("snap");
+        write_snapshot_pages(
+            &snap_dir,
+            &pages_manifest(),
+            b"",
+            &new_memory(),
+            None,
+            false,
+        )
+        .unwrap();
+
+        let mem_path = dir.path().join("memory.bin");
+        std::fs::write(&mem_path, b"MEM").unwrap();
+        write_snapshot(&snap_dir, &pages_manifest(), b"", &mem_path).unwrap();
+        assert!(!has_memory_pages(&snap_dir));
+    }
+
     #[test]
     fn validate_manifest_ok() {
         let manifest = test_manifest();
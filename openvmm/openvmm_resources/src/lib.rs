@@ -59,6 +59,8 @@
     net_backend::null::NullResolver,
     #[cfg(feature = "net_consomme")]
     net_consomme::resolver::ConsommeResolver,
+    net_impair::resolver::ImpairedResolver,
+    net_switch::resolver::SwitchResolver,
     #[cfg(all(feature = "net_tap", target_os = "linux"))]
     net_tap::resolver::TapResolver,
     #[cfg(windows)]
@@ -73,7 +75,10 @@
     disk_blockdevice::resolver::StaticBlockDeviceResolver,
     disk_prwrap::DiskWithReservationsResolver,
     disk_delay::resolver::DelayDiskResolver,
+    disk_nbd::resolver::NbdDiskResolver,
+    disk_nbd::resolver::NbdExportDiskResolver,
     disk_vhd1::Vhd1Resolver,
+    disk_qcow2::resolver::Qcow2DiskResolver,
     #[cfg(windows)]
     disk_vhdmp::VhdmpDiskResolver,
     #[cfg(feature = "disk_blob")]
@@ -84,6 +89,7 @@
     #[cfg(feature = "disklayer_sqlite")]
     disklayer_sqlite::resolver::SqliteDiskLayerResolver,
     disklayer_vhdx::resolver::VhdxDiskLayerResolver,
+    disk_qcow2::resolver::Qcow2DiskLayerResolver,
 
     // PCI devices
     cxl_spec::test::resolver::CxlTestDeviceResolver,
@@ -96,8 +102,11 @@
     scsidisk::resolver::SimpleScsiResolver,
 
     // Virtio devices
+    virtio_balloon::resolver::VirtioBalloonResolver,
     virtio_blk::resolver::VirtioBlkResolver,
     virtio_console::resolver::VirtioConsoleResolver,
+    virtio_gpu::resolver::VirtioGpuResolver,
+    virtio_input::resolver::VirtioInputResolver,
     #[cfg(any(windows, target_os = "linux"))]
     virtiofs::resolver::VirtioFsResolver,
     #[cfg(any(windows, target_os = "linux"))]
@@ -105,6 +114,7 @@
     virtio_net::resolver::VirtioNetResolver,
     virtio_pmem::resolver::VirtioPmemResolver,
     virtio_rng::resolver::VirtioRngResolver,
+    virtio_scsi::resolver::VirtioScsiResolver,
     #[cfg(target_os = "linux")]
     vhost_user_frontend::resolver::VhostUserFrontendResolver,
     virtio_vsock::resolver::VirtioVsockResolver,
@@ -115,9 +125,11 @@
     guest_crash_device::resolver::GuestCrashDeviceResolver,
     guest_emulation_device::resolver::GuestEmulationDeviceResolver,
     guest_emulation_log::resolver::GuestEmulationLogResolver,
+    hyperv_ic::resolver::HeartbeatIcResolver,
     hyperv_ic::resolver::KvpIcResolver,
     hyperv_ic::resolver::ShutdownIcResolver,
     hyperv_ic::resolver::TimesyncIcResolver,
+    hyperv_ic::resolver::VssIcResolver,
     netvsp::resolver::NetvspResolver,
     storvsp::resolver::StorvspResolver,
     storvsp::resolver::StorvspIdeResolver,
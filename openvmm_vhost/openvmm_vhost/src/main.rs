@@ -102,6 +102,7 @@ pub fn main() -> anyhow::Result<()> {
                     let virtio_handle = VirtioBlkHandle {
                         disk: Resource::new(FileDiskHandle(file)),
                         read_only: *read_only,
+                        num_queues: None,
                     };
 
                     let resolved = resolver
@@ -109,6 +110,10 @@ pub fn main() -> anyhow::Result<()> {
                             Resource::new(virtio_handle),
                             VirtioResolveInput {
                                 driver_source: &driver_source,
+                                // The frontend's VP count is not known here,
+                                // and the single driver backend ignores target
+                                // VPs anyway.
+                                vp_count: 1,
                             },
                         )
                         .await
@@ -191,6 +191,7 @@ async fn setup(
                                     virtio_resources::blk::VirtioBlkHandle {
                                         disk: FileDiskHandle(erofs_file.into()).into_resource(),
                                         read_only: true,
+                                        num_queues: None,
                                     }
                                     .into_resource(),
                                 )
@@ -203,6 +204,7 @@ async fn setup(
                                     virtio_resources::blk::VirtioBlkHandle {
                                         disk,
                                         read_only: false,
+                                        num_queues: None,
                                     }
                                     .into_resource(),
                                 )
@@ -231,6 +233,7 @@ async fn setup(
                                         virtio_resources::blk::VirtioBlkHandle {
                                             disk: FileDiskHandle(erofs_file.into()).into_resource(),
                                             read_only: true,
+                                            num_queues: None,
                                         }
                                         .into_resource(),
                                     )
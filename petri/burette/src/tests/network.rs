@@ -244,6 +244,7 @@ async fn setup(
                                     virtio_resources::blk::VirtioBlkHandle {
                                         disk: FileDiskHandle(erofs_file.into()).into_resource(),
                                         read_only: true,
+                                        num_queues: None,
                                     }
                                     .into_resource(),
                                 )
@@ -489,7 +490,11 @@ mod tap {
 
     /// Add a VMBus synthnic backed by a TAP fd to the VM config.
     fn add_tap_nic(config: &mut openvmm_defs::config::Config, tap_fd: std::os::fd::OwnedFd) {
-        let endpoint = net_backend_resources::tap::TapHandle { fd: tap_fd }.into_resource();
+        let endpoint = net_backend_resources::tap::TapHandle {
+            fd: tap_fd,
+            vhost: None,
+        }
+        .into_resource();
         const TAP_NETVSP_INSTANCE: guid::Guid = guid::guid!("a1b2c3d4-e5f6-7890-abcd-ef1234567890");
 
         config.vmbus_devices.push((
@@ -506,7 +511,11 @@ fn add_tap_nic(config: &mut openvmm_defs::config::Config, tap_fd: std::os::fd::O
 
     /// Add a virtio-net NIC backed by a TAP fd to the VM config (PCIe).
     fn add_virtio_tap_nic(config: &mut openvmm_defs::config::Config, tap_fd: std::os::fd::OwnedFd) {
-        let endpoint = net_backend_resources::tap::TapHandle { fd: tap_fd }.into_resource();
+        let endpoint = net_backend_resources::tap::TapHandle {
+            fd: tap_fd,
+            vhost: None,
+        }
+        .into_resource();
 
         config.pcie_devices.push(PcieDeviceConfig {
             port_name: "s0rc0rp1".into(),
@@ -552,6 +561,7 @@ pub(super) fn configure_builder(
                         virtio_resources::blk::VirtioBlkHandle {
                             disk: FileDiskHandle(erofs_file.into()).into_resource(),
                             read_only: true,
+                            num_queues: None,
                         }
                         .into_resource(),
                     )
@@ -6,7 +6,9 @@
 use anyhow::Context;
 use futures::AsyncRead;
 use futures::AsyncWrite;
+use futures::AsyncWriteExt;
 use futures::future::FutureExt;
+use futures_concurrency::future::Join;
 use futures_concurrency::future::Race;
 use mesh_remote::PointToPointMesh;
 use pal_async::DefaultDriver;
@@ -17,6 +19,9 @@
 use pipette_protocol::PipetteBootstrap;
 use pipette_protocol::PipetteRequest;
 use socket2::Socket;
+use std::net::IpAddr;
+use std::net::Ipv4Addr;
+use std::net::SocketAddr;
 use std::time::Duration;
 use std::time::SystemTime;
 use unicycle::FuturesUnordered;
@@ -258,9 +263,83 @@ async fn handle_request(
         PipetteRequest::Mount(rpc) => {
             rpc.handle_failable_sync(|_| anyhow::bail!("mount not supported on this platform"))
         }
+        PipetteRequest::ConnectTcp(rpc) => {
+            rpc.handle_failable(async |request| connect_tcp(driver, request).await)
+                .await
+        }
+        PipetteRequest::ReadDir(rpc) => rpc.handle_failable_sync(read_dir),
+        PipetteRequest::Stat(rpc) => rpc.handle_failable_sync(stat),
     }
 }
 
+async fn connect_tcp(
+    driver: &DefaultDriver,
+    request: pipette_protocol::ConnectTcpRequest,
+) -> anyhow::Result<()> {
+    let pipette_protocol::ConnectTcpRequest {
+        address,
+        port,
+        mut receiver,
+        mut sender,
+    } = request;
+    let address = match address {
+        Some(address) => address
+            .parse()
+            .with_context(|| format!("invalid address: {address}"))?,
+        None => IpAddr::V4(Ipv4Addr::LOCALHOST),
+    };
+    let address = SocketAddr::new(address, port);
+    tracing::debug!(%address, "connect tcp request");
+
+    let socket = PolledSocket::connect_tcp(driver, address)
+        .await
+        .with_context(|| format!("failed to connect to {address}"))?;
+
+    // Relay the connection in the background so that the request completes
+    // once the connection is established.
+    driver
+        .spawn(format!("tcp-tunnel-{address}"), async move {
+            let (mut socket_read, mut socket_write) = socket.split();
+            let outbound = async {
+                let r = futures::io::copy(&mut receiver, &mut socket_write).await;
+                let _ = socket_write.close().await;
+                r
+            };
+            let inbound = async {
+                let r = futures::io::copy(&mut socket_read, &mut sender).await;
+                drop(sender);
+                r
+            };
+            let (outbound, inbound) = (outbound, inbound).join().await;
+            tracing::debug!(
+                %address,
+                sent = outbound.ok(),
+                received = inbound.ok(),
+                "tcp tunnel closed"
+            );
+        })
+        .detach();
+    Ok(())
+}
+
+fn read_dir(path: String) -> anyhow::Result<Vec<pipette_protocol::DirEntry>> {
+    tracing::debug!(path, "read dir request");
+    fs_err::read_dir(path)?
+        .map(|entry| -> anyhow::Result<_> {
+            let entry = entry?;
+            Ok(pipette_protocol::DirEntry {
+                name: entry.file_name().to_string_lossy().into_owned(),
+                metadata: entry.metadata()?.into(),
+            })
+        })
+        .collect()
+}
+
+fn stat(path: String) -> anyhow::Result<pipette_protocol::FileMetadata> {
+    tracing::debug!(path, "stat request");
+    Ok(fs_err::metadata(path)?.into())
+}
+
 async fn read_file(mut request: pipette_protocol::ReadFileRequest) -> anyhow::Result<u64> {
     tracing::debug!(path = request.path, "Beginning file read request");
     let file = fs_err::File::open(request.path)?;
@@ -297,3 +376,111 @@ pub async fn send(&self, filename: &str) -> anyhow::Result<()> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::AsyncReadExt;
+    use pal_async::async_test;
+    use pipette_protocol::FileType;
+
+    #[test]
+    fn read_dir_lists_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        fs_err::write(dir.path().join("file"), b"abc").unwrap();
+        fs_err::create_dir(dir.path().join("subdir")).unwrap();
+
+        let mut entries = read_dir(dir.path().to_str().unwrap().to_owned()).unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].name, "file");
+        assert_eq!(entries[0].metadata.file_type, FileType::File);
+        assert_eq!(entries[0].metadata.len, 3);
+        assert_eq!(entries[1].name, "subdir");
+        assert_eq!(entries[1].metadata.file_type, FileType::Dir);
+    }
+
+    #[test]
+    fn read_dir_missing_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing");
+        read_dir(path.to_str().unwrap().to_owned()).unwrap_err();
+    }
+
+    #[test]
+    fn stat_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("file");
+        fs_err::write(&path, b"hello").unwrap();
+
+        let metadata = stat(path.to_str().unwrap().to_owned()).unwrap();
+        assert_eq!(metadata.file_type, FileType::File);
+        assert_eq!(metadata.len, 5);
+        assert!(metadata.modified.is_some());
+    }
+
+    #[test]
+    fn stat_missing_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("missing");
+        let err = stat(path.to_str().unwrap().to_owned()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<std::io::Error>().unwrap().kind(),
+            std::io::ErrorKind::NotFound
+        );
+    }
+
+    #[async_test]
+    async fn connect_tcp_relays_data(driver: DefaultDriver) {
+        let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let (receiver, mut send_data) = mesh::pipe::pipe();
+        let (mut recv_data, sender) = mesh::pipe::pipe();
+        connect_tcp(
+            &driver,
+            pipette_protocol::ConnectTcpRequest {
+                address: None,
+                port,
+                receiver,
+                sender,
+            },
+        )
+        .await
+        .unwrap();
+
+        // The connection is already established, so this does not block.
+        let (stream, _) = listener.accept().unwrap();
+        let mut stream = PolledSocket::new(&driver, stream).unwrap();
+
+        // Closing the pipe shuts down the guest's side of the connection.
+        send_data.write_all(b"ping").await.unwrap();
+        drop(send_data);
+        let mut data = Vec::new();
+        stream.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"ping");
+
+        stream.write_all(b"pong").await.unwrap();
+        stream.close().await.unwrap();
+        let mut data = Vec::new();
+        recv_data.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"pong");
+    }
+
+    #[async_test]
+    async fn connect_tcp_invalid_address(driver: DefaultDriver) {
+        let (receiver, _send_data) = mesh::pipe::pipe();
+        let (_recv_data, sender) = mesh::pipe::pipe();
+        let err = connect_tcp(
+            &driver,
+            pipette_protocol::ConnectTcpRequest {
+                address: Some("not an address".to_owned()),
+                port: 80,
+                receiver,
+                sender,
+            },
+        )
+        .await
+        .unwrap_err();
+        assert!(err.to_string().contains("invalid address"));
+    }
+}
@@ -3,16 +3,21 @@
 
 //! Handler for the execute request.
 
-// UNSAFETY: Required for libc calls (chroot, chdir, setsid, ioctl) in pre_exec on Linux.
+// UNSAFETY: Required for libc calls (chroot, chdir, setsid, ioctl) in pre_exec,
+// and for kill, on Linux.
 #![cfg_attr(target_os = "linux", expect(unsafe_code))]
 
 #[cfg(target_os = "linux")]
 use std::os::unix::process::CommandExt;
 use std::process::Stdio;
 
+use futures::FutureExt;
+use futures::StreamExt;
+use futures_concurrency::future::Race;
 use pal_async::pipe::PolledPipe;
 use pal_async::process::PolledChild;
 use pal_async::task::Spawn;
+use pipette_protocol::Signal;
 
 pub fn handle_execute(
     driver: &pal_async::DefaultDriver,
@@ -164,19 +169,75 @@ pub fn handle_execute(
         .expect("process was just spawned, driver must be able to wait on it");
     let pid = polled_child.get().id();
     let (send, recv) = mesh::oneshot();
+    let (signal_send, mut signal_recv) = mesh::channel();
 
     driver
         .spawn("child_wait", async move {
-            let exit_status = polled_child
-                .wait()
-                .await
-                .expect("waiting on a spawned child should not fail");
+            // The child is not reaped until it is waited on, so its pid cannot
+            // be reused while signals are being handled.
+            let exit_status = loop {
+                enum Event {
+                    Exited(std::io::Result<std::process::ExitStatus>),
+                    Signal(Option<mesh::rpc::FailableRpc<Signal, ()>>),
+                }
+
+                let event = (
+                    polled_child.wait().map(Event::Exited),
+                    signal_recv.next().map(Event::Signal),
+                )
+                    .race()
+                    .await;
+
+                match event {
+                    Event::Exited(r) => {
+                        break r.expect("waiting on a spawned child should not fail");
+                    }
+                    Event::Signal(Some(rpc)) => rpc.handle_failable_sync(|signal| {
+                        tracing::debug!(pid, ?signal, "signal request");
+                        send_signal(polled_child.get_mut(), signal)
+                    }),
+                    Event::Signal(None) => {
+                        break polled_child
+                            .wait()
+                            .await
+                            .expect("waiting on a spawned child should not fail");
+                    }
+                }
+            };
             let status = convert_exit_status(exit_status);
             tracing::debug!(pid, ?status, "process exited");
             send.send(status);
         })
         .detach();
-    Ok(pipette_protocol::ExecuteResponse { pid, result: recv })
+    Ok(pipette_protocol::ExecuteResponse {
+        pid,
+        result: recv,
+        signal: signal_send,
+    })
+}
+
+#[cfg(target_os = "linux")]
+fn send_signal(child: &mut std::process::Child, signal: Signal) -> anyhow::Result<()> {
+    let signal = match signal {
+        Signal::Kill => libc::SIGKILL,
+        Signal::Terminate => libc::SIGTERM,
+        Signal::Interrupt => libc::SIGINT,
+        Signal::Raw(signal) => signal,
+    };
+    // SAFETY: the child has not been reaped, so its pid still refers to the
+    // child process.
+    if unsafe { libc::kill(child.id() as i32, signal) } != 0 {
+        return Err(std::io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send_signal(child: &mut std::process::Child, signal: Signal) -> anyhow::Result<()> {
+    match signal {
+        Signal::Kill => Ok(child.kill()?),
+        signal => anyhow::bail!("signal {signal:?} is only supported on Linux"),
+    }
 }
 
 async fn relay(
@@ -198,3 +259,68 @@ fn convert_exit_status(exit_status: std::process::ExitStatus) -> pipette_protoco
 
     pipette_protocol::ExitStatus::Unknown
 }
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+    use mesh::rpc::RpcSend;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use pipette_protocol::ExitStatus;
+
+    fn execute(
+        driver: &DefaultDriver,
+        program: &str,
+        args: &[&str],
+    ) -> pipette_protocol::ExecuteResponse {
+        handle_execute(
+            driver,
+            pipette_protocol::ExecuteRequest {
+                program: program.to_owned(),
+                args: args.iter().map(|&arg| arg.to_owned()).collect(),
+                ..Default::default()
+            },
+        )
+        .unwrap()
+    }
+
+    #[async_test]
+    async fn kill_child(driver: DefaultDriver) {
+        let response = execute(&driver, "sleep", &["100"]);
+        response
+            .signal
+            .call_failable(std::convert::identity, Signal::Kill)
+            .await
+            .unwrap();
+        assert_eq!(
+            response.result.await.unwrap(),
+            ExitStatus::Signal(libc::SIGKILL)
+        );
+    }
+
+    #[async_test]
+    async fn raw_signal_child(driver: DefaultDriver) {
+        let response = execute(&driver, "sleep", &["100"]);
+        response
+            .signal
+            .call_failable(std::convert::identity, Signal::Raw(libc::SIGUSR1))
+            .await
+            .unwrap();
+        assert_eq!(
+            response.result.await.unwrap(),
+            ExitStatus::Signal(libc::SIGUSR1)
+        );
+    }
+
+    #[async_test]
+    async fn signal_after_exit(driver: DefaultDriver) {
+        let response = execute(&driver, "true", &[]);
+        assert_eq!(response.result.await.unwrap(), ExitStatus::Normal(0));
+        // The signal channel is closed once the child has been reaped.
+        response
+            .signal
+            .call_failable(std::convert::identity, Signal::Kill)
+            .await
+            .unwrap_err();
+    }
+}
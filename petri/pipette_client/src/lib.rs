@@ -8,7 +8,11 @@
 pub mod process;
 mod send;
 pub mod shell;
+pub mod tunnel;
 
+pub use pipette_protocol::DirEntry;
+pub use pipette_protocol::FileMetadata;
+pub use pipette_protocol::FileType;
 pub use pipette_protocol::PIPETTE_PORT;
 pub use pipette_protocol::PIPETTE_READY_MARKER;
 
@@ -36,9 +40,11 @@
 use pipette_protocol::WriteFileRequest;
 use shell::UnixShell;
 use shell::WindowsShell;
+use std::net::SocketAddr;
 use std::path::Path;
 use std::path::PathBuf;
 use std::time::Duration;
+use tunnel::TcpTunnel;
 
 /// A client to a running `pipette` instance inside a VM.
 pub struct PipetteClient {
@@ -279,6 +285,60 @@ pub async fn write_file(
         Ok(())
     }
 
+    /// Lists the entries of a directory in the guest.
+    pub async fn read_dir(&self, path: impl AsRef<str>) -> anyhow::Result<Vec<DirEntry>> {
+        self.send
+            .call_failable(PipetteRequest::ReadDir, path.as_ref().to_owned())
+            .await
+            .with_context(|| format!("failed to read directory {}", path.as_ref()))
+    }
+
+    /// Gets the metadata of a file in the guest, following symlinks.
+    pub async fn stat(&self, path: impl AsRef<str>) -> anyhow::Result<FileMetadata> {
+        self.send
+            .call_failable(PipetteRequest::Stat, path.as_ref().to_owned())
+            .await
+            .with_context(|| format!("failed to stat {}", path.as_ref()))
+    }
+
+    /// Opens a TCP connection to `port` on the guest's loopback address.
+    ///
+    /// The connection is made by the agent and tunneled over the pipette
+    /// connection, so it works regardless of the guest's network
+    /// configuration.
+    pub async fn connect_tcp(&self, port: u16) -> anyhow::Result<TcpTunnel> {
+        self.connect_tcp_inner(None, port).await
+    }
+
+    /// Opens a TCP connection from the guest to `addr`, tunneled over the
+    /// pipette connection.
+    pub async fn connect_tcp_to(&self, addr: SocketAddr) -> anyhow::Result<TcpTunnel> {
+        self.connect_tcp_inner(Some(addr.ip().to_string()), addr.port())
+            .await
+    }
+
+    async fn connect_tcp_inner(
+        &self,
+        address: Option<String>,
+        port: u16,
+    ) -> anyhow::Result<TcpTunnel> {
+        let (receiver, send_pipe) = mesh::pipe::pipe();
+        let (recv_pipe, sender) = mesh::pipe::pipe();
+        self.send
+            .call_failable(
+                PipetteRequest::ConnectTcp,
+                pipette_protocol::ConnectTcpRequest {
+                    address,
+                    port,
+                    receiver,
+                    sender,
+                },
+            )
+            .await
+            .with_context(|| format!("failed to connect to guest port {port}"))?;
+        Ok(TcpTunnel::new(recv_pipe, send_pipe))
+    }
+
     /// Waits for the agent to exit.
     pub async fn wait(self) -> Result<(), mesh::RecvError> {
         self.watch.await
@@ -11,10 +11,15 @@
 use futures_concurrency::future::Join;
 use mesh::pipe::ReadPipe;
 use mesh::pipe::WritePipe;
+use mesh::rpc::FailableRpc;
+use mesh::rpc::RpcError;
+use mesh::rpc::RpcSend;
 use pipette_protocol::EnvPair;
 use pipette_protocol::PipetteRequest;
 use std::fmt;
 
+pub use pipette_protocol::Signal;
+
 /// A builder for launching a command inside the guest.
 ///
 /// This has a similar API to [`std::process::Command`].
@@ -213,6 +218,7 @@ async fn spawn_inner(
             stderr: stderr_read,
             pid: response.pid,
             result: Ok(response.result),
+            signal: response.signal,
         })
     }
 }
@@ -321,6 +327,7 @@ pub struct Child {
     pub stderr: Option<ReadPipe>,
     pid: u32,
     result: Result<mesh::OneshotReceiver<pipette_protocol::ExitStatus>, ExitStatus>,
+    signal: mesh::Sender<FailableRpc<Signal, ()>>,
 }
 
 impl Child {
@@ -329,6 +336,25 @@ pub fn id(&self) -> u32 {
         self.pid
     }
 
+    /// Sends a signal to the child.
+    ///
+    /// Fails if the child has already exited.
+    pub async fn signal(&self, signal: Signal) -> anyhow::Result<()> {
+        self.signal
+            .call_failable(|rpc| rpc, signal)
+            .await
+            .with_context(|| format!("failed to send {signal:?} to process {}", self.pid))
+    }
+
+    /// Forcibly terminates the child. Succeeds if the child has already
+    /// exited, like [`std::process::Child::kill`].
+    pub async fn kill(&self) -> anyhow::Result<()> {
+        match self.signal.call_failable(|rpc| rpc, Signal::Kill).await {
+            Ok(()) | Err(RpcError::Channel(_)) => Ok(()),
+            Err(err) => Err(err).with_context(|| format!("failed to kill process {}", self.pid)),
+        }
+    }
+
     /// Waits for the child to exit, returning the exit status.
     pub async fn wait(&mut self) -> Result<ExitStatus, mesh::RecvError> {
         match &mut self.result {
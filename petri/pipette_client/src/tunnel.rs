@@ -0,0 +1,64 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! TCP connections tunneled over the pipette connection.
+
+use futures::AsyncRead;
+use futures::AsyncWrite;
+use mesh::pipe::ReadPipe;
+use mesh::pipe::WritePipe;
+use std::io;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
+
+/// A TCP connection made by the agent inside the guest, returned by
+/// [`PipetteClient::connect_tcp`](crate::PipetteClient::connect_tcp).
+///
+/// Closing the tunnel for writing shuts down the sending side of the guest
+/// connection. Reads return EOF once the guest connection's receiving side is
+/// shut down.
+pub struct TcpTunnel {
+    read: ReadPipe,
+    write: WritePipe,
+}
+
+impl TcpTunnel {
+    pub(crate) fn new(read: ReadPipe, write: WritePipe) -> Self {
+        Self { read, write }
+    }
+
+    /// Splits the tunnel into its receiving and sending pipes, which can be
+    /// used concurrently.
+    pub fn into_split(self) -> (ReadPipe, WritePipe) {
+        (self.read, self.write)
+    }
+}
+
+impl AsyncRead for TcpTunnel {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().read).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for TcpTunnel {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.get_mut().write).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().write).poll_flush(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.get_mut().write).poll_close(cx)
+    }
+}
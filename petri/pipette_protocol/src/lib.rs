@@ -58,6 +58,12 @@ pub enum PipetteRequest {
     KernelCrash(FailableRpc<(), ()>),
     /// Mounts a filesystem (Linux only).
     Mount(FailableRpc<MountRequest, ()>),
+    /// Opens a TCP connection from the guest, relaying its data over pipes.
+    ConnectTcp(FailableRpc<ConnectTcpRequest, ()>),
+    /// Lists the entries of a directory.
+    ReadDir(FailableRpc<String, Vec<DirEntry>>),
+    /// Gets the metadata of a file, following symlinks.
+    Stat(FailableRpc<String, FileMetadata>),
 }
 
 /// A request to execute a command inside the guest.
@@ -126,10 +132,27 @@ pub struct ExecuteResponse {
     pub pid: u32,
     /// The process result channel. Receives the exit status of the process.
     pub result: mesh::OneshotReceiver<ExitStatus>,
+    /// The channel for sending signals to the process. Closed once the process
+    /// exits.
+    pub signal: mesh::Sender<FailableRpc<Signal, ()>>,
+}
+
+/// A signal to send to a process.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, MeshPayload)]
+pub enum Signal {
+    /// Forcibly terminates the process (`SIGKILL` on Linux,
+    /// `TerminateProcess` on Windows).
+    Kill,
+    /// Requests that the process terminate (`SIGTERM`, Linux only).
+    Terminate,
+    /// Interrupts the process (`SIGINT`, Linux only).
+    Interrupt,
+    /// Sends the given signal number (Linux only).
+    Raw(i32),
 }
 
 /// The exit status of a process.
-#[derive(Debug, MeshPayload, Clone)]
+#[derive(Debug, MeshPayload, Clone, PartialEq, Eq)]
 pub enum ExitStatus {
     /// The process exited normally with the given exit code.
     Normal(i32),
@@ -201,6 +224,77 @@ pub struct MountRequest {
     pub mkdir_target: bool,
 }
 
+/// A request to open a TCP connection from the guest.
+#[derive(MeshPayload)]
+pub struct ConnectTcpRequest {
+    /// The IP address to connect to, or `None` for the loopback address.
+    pub address: Option<String>,
+    /// The port to connect to.
+    pub port: u16,
+    /// The receiver of the data to send on the connection. The guest shuts
+    /// down the sending side of the connection when this pipe is closed.
+    pub receiver: ReadPipe,
+    /// The sender for the data received on the connection. Closed when the
+    /// connection's receiving side is shut down.
+    pub sender: WritePipe,
+}
+
+/// An entry in a directory listing.
+#[derive(MeshPayload, Clone, Debug, PartialEq, Eq)]
+pub struct DirEntry {
+    /// The file name of the entry.
+    pub name: String,
+    /// The metadata of the entry. Symlinks are not followed.
+    pub metadata: FileMetadata,
+}
+
+/// The metadata of a file.
+#[derive(MeshPayload, Clone, Debug, PartialEq, Eq)]
+pub struct FileMetadata {
+    /// The type of the file.
+    pub file_type: FileType,
+    /// The size of the file in bytes.
+    pub len: u64,
+    /// The last modification time, if available.
+    pub modified: Option<Timestamp>,
+    /// Whether the file is read-only.
+    pub readonly: bool,
+}
+
+/// The type of a file.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, MeshPayload)]
+pub enum FileType {
+    /// A regular file.
+    File,
+    /// A directory.
+    Dir,
+    /// A symbolic link.
+    Symlink,
+    /// Some other type of file, such as a device or socket.
+    Other,
+}
+
+impl From<std::fs::Metadata> for FileMetadata {
+    fn from(metadata: std::fs::Metadata) -> Self {
+        let file_type = metadata.file_type();
+        let file_type = if file_type.is_symlink() {
+            FileType::Symlink
+        } else if file_type.is_dir() {
+            FileType::Dir
+        } else if file_type.is_file() {
+            FileType::File
+        } else {
+            FileType::Other
+        };
+        Self {
+            file_type,
+            len: metadata.len(),
+            modified: metadata.modified().ok().map(Into::into),
+            readonly: metadata.permissions().readonly(),
+        }
+    }
+}
+
 /// A file that the guest client wishes to be logged on the host for diagnostic purposes.
 #[derive(MeshPayload)]
 pub struct DiagnosticFile {
@@ -209,3 +303,129 @@ pub struct DiagnosticFile {
     /// The receiver of the contents of the file.
     pub receiver: ReadPipe,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::AsyncReadExt;
+    use futures::AsyncWriteExt;
+    use mesh::rpc::RpcSend;
+    use pal_async::async_test;
+
+    /// Serializes and deserializes `value`, as happens when it is sent
+    /// between the host and the guest.
+    fn round_trip<T: 'static + MeshPayload + Send>(value: T) -> T {
+        mesh::OwnedMessage::serialized(mesh::OwnedMessage::new(value).serialize())
+            .parse()
+            .unwrap()
+    }
+
+    #[test]
+    fn signal_round_trip() {
+        for signal in [
+            Signal::Kill,
+            Signal::Terminate,
+            Signal::Interrupt,
+            Signal::Raw(10),
+            Signal::Raw(-1),
+        ] {
+            assert_eq!(round_trip(signal), signal);
+        }
+    }
+
+    #[test]
+    fn exit_status_round_trip() {
+        for status in [
+            ExitStatus::Normal(0),
+            ExitStatus::Normal(-2),
+            ExitStatus::Signal(9),
+            ExitStatus::Unknown,
+        ] {
+            assert_eq!(round_trip(status.clone()), status);
+        }
+    }
+
+    #[test]
+    fn dir_entry_round_trip() {
+        for entry in [
+            DirEntry {
+                name: "file.txt".to_owned(),
+                metadata: FileMetadata {
+                    file_type: FileType::File,
+                    len: 1234,
+                    modified: Some(Timestamp {
+                        seconds: 1_700_000_000,
+                        nanos: 5,
+                    }),
+                    readonly: true,
+                },
+            },
+            DirEntry {
+                name: "dir".to_owned(),
+                metadata: FileMetadata {
+                    file_type: FileType::Dir,
+                    len: 0,
+                    modified: None,
+                    readonly: false,
+                },
+            },
+        ] {
+            assert_eq!(round_trip(entry.clone()), entry);
+        }
+    }
+
+    #[async_test]
+    async fn execute_response_round_trip() {
+        let (result_send, result_recv) = mesh::oneshot();
+        let (signal_send, mut signal_recv) = mesh::channel();
+        let response = round_trip(ExecuteResponse {
+            pid: 42,
+            result: result_recv,
+            signal: signal_send,
+        });
+        assert_eq!(response.pid, 42);
+
+        // The signal channel still reaches the agent's side.
+        let call = response
+            .signal
+            .call_failable(std::convert::identity, Signal::Raw(10));
+        let rpc = signal_recv.recv().await.unwrap();
+        assert_eq!(*rpc.input(), Signal::Raw(10));
+        rpc.complete(Ok(()));
+        call.await.unwrap();
+
+        result_send.send(ExitStatus::Signal(9));
+        assert_eq!(response.result.await.unwrap(), ExitStatus::Signal(9));
+    }
+
+    #[async_test]
+    async fn connect_tcp_request_round_trip() {
+        let (receiver, mut send_data) = mesh::pipe::pipe();
+        let (mut recv_data, sender) = mesh::pipe::pipe();
+        let ConnectTcpRequest {
+            address,
+            port,
+            mut receiver,
+            mut sender,
+        } = round_trip(ConnectTcpRequest {
+            address: Some("10.0.0.2".to_owned()),
+            port: 8080,
+            receiver,
+            sender,
+        });
+        assert_eq!(address.as_deref(), Some("10.0.0.2"));
+        assert_eq!(port, 8080);
+
+        send_data.write_all(b"request").await.unwrap();
+        drop(send_data);
+        let mut data = Vec::new();
+        receiver.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"request");
+
+        sender.write_all(b"response").await.unwrap();
+        drop(sender);
+        let mut data = Vec::new();
+        recv_data.read_to_end(&mut data).await.unwrap();
+        assert_eq!(data, b"response");
+    }
+}
@@ -254,7 +254,9 @@ pub async fn new(
                         nsid,
                         read_only: false,
                         disk,
+                        zoned: None,
                     }],
+                    disk_pool: Vec::new(),
                     requests: None,
                 }
                 .into_resource(),
@@ -276,6 +278,7 @@ pub async fn new(
                     VirtioBlkHandle {
                         disk,
                         read_only: false,
+                        num_queues: None,
                     }
                     .into_resource(),
                 )
@@ -705,6 +708,9 @@ pub async fn new(
             input: mesh::Receiver::new(),
             vtl2_gfx: false,
             virtio_devices: vec![],
+            balloon_control: None,
+            vss_ic: None,
+            heartbeat_ic: None,
             #[cfg(windows)]
             vpci_resources: vec![],
             debugger_rpc: None,
@@ -1481,6 +1487,7 @@ async fn vmbus_storage_controllers_to_openvmm(
                             nsid: *nsid,
                             read_only: false,
                             disk: petri_disk_to_openvmm(disk).await?,
+                            zoned: None,
                         });
                     } else {
                         todo!("dvd ({}) or empty ({})", *is_dvd, disk.is_none())
@@ -1495,6 +1502,7 @@ async fn vmbus_storage_controllers_to_openvmm(
                         max_io_queues: 64,
                         msix_count: 64,
                         namespaces,
+                        disk_pool: Vec::new(),
                         requests: None,
                     }
                     .into_resource(),
@@ -1526,6 +1534,7 @@ async fn vmbus_storage_controllers_to_openvmm(
                             VirtioBlkHandle {
                                 disk: petri_disk_to_openvmm(disk).await?,
                                 read_only: false,
+                                num_queues: None,
                             }
                             .into_resource(),
                         )
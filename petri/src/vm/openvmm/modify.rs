@@ -92,6 +92,9 @@ pub fn with_nic(mut self) -> Self {
             cidr: None,
             ports: Vec::new(),
             recv: None,
+            tftp_root: None,
+            boot_file: None,
+            egress_policy: None,
         }
         .into_resource();
         if let Some(vtl2_settings) = self.runtime_config.vtl2_settings.as_mut() {
@@ -138,6 +141,9 @@ pub fn with_pcie_nic(mut self, port_name: &str, mac_address: MacAddress) -> Self
             cidr: None,
             ports: Vec::new(),
             recv: None,
+            tftp_root: None,
+            boot_file: None,
+            egress_policy: None,
         }
         .into_resource();
         self.config.pcie_devices.push(PcieDeviceConfig {
@@ -170,7 +176,9 @@ pub fn with_pcie_nvme(mut self, port_name: &str, subsystem_id: Guid) -> Self {
                     })
                     .into_resource(),
                     read_only: false,
+                    zoned: None,
                 }],
+                disk_pool: Vec::new(),
                 requests: None,
             }
             .into_resource(),
@@ -188,6 +196,9 @@ pub fn with_virtio_nic(mut self, port_name: &str, mac_address: MacAddress) -> Se
             cidr: None,
             ports: Vec::new(),
             recv: None,
+            tftp_root: None,
+            boot_file: None,
+            egress_policy: None,
         }
         .into_resource();
 
@@ -227,6 +238,9 @@ pub fn with_tcp_pipette_nic(mut self, port_name: &str, mac_address: MacAddress)
                 guest_port: pipette_client::PIPETTE_PORT as u16,
             }],
             recv: None,
+            tftp_root: None,
+            boot_file: None,
+            egress_policy: None,
         }
         .into_resource();
         self.config.pcie_devices.push(PcieDeviceConfig {
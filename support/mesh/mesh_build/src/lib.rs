@@ -81,19 +81,122 @@ fn generate(&mut self, service: prost_build::Service, buf: &mut String) {
             .iter()
             .map(|m| self.lookup_type(&m.input_type))
             .collect();
+        // Client-streaming methods receive their requests over a channel,
+        // with the stream ending when the client finishes sending requests.
+        let request_args: Vec<_> = service
+            .methods
+            .iter()
+            .zip(&request_types)
+            .map(|(m, ty)| {
+                if m.client_streaming {
+                    quote::quote!(::mesh_rpc::service::RequestStream<#ty>)
+                } else {
+                    quote::quote!(#ty)
+                }
+            })
+            .collect();
         let response_types: Vec<_> = service
             .methods
             .iter()
             .map(|m| self.lookup_type(&m.output_type))
             .collect();
+        // Server-streaming methods respond over a channel instead of a
+        // oneshot, with the stream ending when the channel is closed.
+        let response_senders: Vec<_> = service
+            .methods
+            .iter()
+            .zip(&response_types)
+            .map(|(m, ty)| {
+                let result =
+                    quote::quote!(::core::result::Result<#ty, ::mesh_rpc::service::Status>);
+                if m.server_streaming {
+                    quote::quote!(::mesh::Sender<#result>)
+                } else {
+                    quote::quote!(::mesh::OneshotSender<#result>)
+                }
+            })
+            .collect();
+        let streaming_method_names: Vec<_> = service
+            .methods
+            .iter()
+            .filter(|m| m.server_streaming)
+            .map(|m| &m.proto_name)
+            .collect();
+        let client_streaming_method_names: Vec<_> = service
+            .methods
+            .iter()
+            .filter(|m| m.client_streaming)
+            .map(|m| &m.proto_name)
+            .collect();
+        // The request stream is passed as a separate port, so client-streaming
+        // methods encode an empty request.
+        let encode_arms: Vec<_> = service
+            .methods
+            .iter()
+            .zip(&request_types)
+            .map(|(m, ty)| {
+                if m.client_streaming {
+                    quote::quote! {
+                        writer.bytes(&[]);
+                        (port.into(), Some(req.into()))
+                    }
+                } else {
+                    quote::quote! {
+                        <<#ty as ::mesh::payload::DefaultEncoding>::Encoding as ::mesh::payload::FieldEncode<_, _>>::write_field(req, writer);
+                        (port.into(), None)
+                    }
+                }
+            })
+            .collect();
+        let compute_size_arms: Vec<_> = service
+            .methods
+            .iter()
+            .zip(&request_types)
+            .map(|(m, ty)| {
+                if m.client_streaming {
+                    quote::quote! {
+                        let _ = req;
+                        sizer.bytes(0);
+                    }
+                } else {
+                    quote::quote! {
+                        <<#ty as ::mesh::payload::DefaultEncoding>::Encoding as ::mesh::payload::FieldEncode::<_, ::mesh::resource::Resource>>::compute_field_size(
+                            req,
+                            sizer);
+                    }
+                }
+            })
+            .collect();
+        let decode_arms: Vec<_> = service
+            .methods
+            .iter()
+            .zip(&method_idents)
+            .map(|(m, method_ident)| {
+                if m.client_streaming {
+                    quote::quote! {
+                        match requests {
+                            Some(requests) => Ok(#ident::#method_ident(requests.into(), port.into())),
+                            None => Err((::mesh_rpc::service::ServiceRpcError::MissingRequestStream, port)),
+                        }
+                    }
+                } else {
+                    quote::quote! {
+                        match mesh::payload::decode(data) {
+                            Ok(req) => Ok(#ident::#method_ident(req, port.into())),
+                            Err(e) => Err((::mesh_rpc::service::ServiceRpcError::InvalidInput(e), port)),
+                        }
+                    }
+                }
+            })
+            .collect();
 
         *buf += &quote::quote! {
             #[derive(Debug)]
             pub enum #ident {
                 #(
                     #method_idents(
-                        #request_types,
-                        ::mesh::OneshotSender<::core::result::Result<#response_types, ::mesh_rpc::service::Status>>,
+                        #request_args,
+                        #response_senders,
                     ),
                 )*
             }
@@ -120,15 +223,22 @@ fn method(&self) -> &'static str {
                     }
                 }
 
+                fn is_server_streaming(method: &str) -> bool {
+                    [#(#streaming_method_names),*].contains(&method)
+                }
+
+                fn is_client_streaming(method: &str) -> bool {
+                    [#(#client_streaming_method_names),*].contains(&method)
+                }
+
                 fn encode(
                     self,
                     writer: ::mesh::payload::protobuf::FieldWriter<'_, '_, ::mesh::resource::Resource>,
-                ) -> ::mesh::local_node::Port {
+                ) -> (::mesh::local_node::Port, Option<::mesh::local_node::Port>) {
                     match self {
                         #(
                             #ident::#method_idents(req, port) => {
-                                <<#request_types as ::mesh::payload::DefaultEncoding>::Encoding as ::mesh::payload::FieldEncode<_, _>>::write_field(req, writer);
-                                port.into()
+                                #encode_arms
                             }
                         )*
                     }
@@ -138,26 +248,25 @@ fn compute_size(&mut self, sizer: ::mesh::payload::protobuf::FieldSizer<'_>) {
                     match self {
                         #(
                             #ident::#method_idents(req, _) => {
-                                <<#request_types as ::mesh::payload::DefaultEncoding>::Encoding as ::mesh::payload::FieldEncode::<_, ::mesh::resource::Resource>>::compute_field_size(
-                                    req,
-                                    sizer);
+                                #compute_size_arms
                             }
                         )*
                     }
                 }
 
+                // Each of `requests` and `data` is unused if no method or every
+                // method is client-streaming.
+                #[allow(unused_variables)]
                 fn decode(
                     method: &str,
                     port: ::mesh::local_node::Port,
+                    requests: Option<::mesh::local_node::Port>,
                     data: &[u8],
                 ) -> Result<Self, (::mesh_rpc::service::ServiceRpcError, ::mesh::local_node::Port)> {
                     match method {
                         #(
                             #method_names => {
-                                match mesh::payload::decode(data) {
-                                    Ok(req) => Ok(#ident::#method_idents(req, port.into())),
-                                    Err(e) => Err((::mesh_rpc::service::ServiceRpcError::InvalidInput(e), port)),
-                                }
+                                #decode_arms
                             }
                         )*
                         _ => Err((::mesh_rpc::service::ServiceRpcError::UnknownMethod, port)),
@@ -47,6 +47,22 @@ fn server(path: &str) -> anyhow::Result<()> {
                     }));
                 }
                 items::Example::Method2(_req, _response) => {}
+                items::Example::Method3(req, response) => {
+                    for index in 0..req.count {
+                        response.send(Ok(items::Method3Response { index }));
+                    }
+                }
+                items::Example::Method4(mut requests, response) => {
+                    std::thread::spawn(move || {
+                        block_on(async {
+                            while let Some(req) = requests.next().await {
+                                response.send(req.map(|req| items::Method4Response {
+                                    value: req.value + "_echo",
+                                }));
+                            }
+                        })
+                    });
+                }
             }
         }
         drop(recv);
@@ -1,7 +1,7 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-//! TTRPC client.
+//! ttrpc and gRPC client.
 
 use crate::message::MESSAGE_TYPE_REQUEST;
 use crate::message::MESSAGE_TYPE_RESPONSE;
@@ -23,6 +23,7 @@
 use futures::AsyncReadExt;
 use futures::AsyncWrite;
 use futures::FutureExt;
+use futures::Stream;
 use futures::StreamExt;
 use futures_concurrency::future::Race;
 use mesh::Deadline;
@@ -45,7 +46,10 @@
 use std::time::Duration;
 use unix_socket::UnixStream;
 
-/// A TTRPC client connection.
+/// An RPC client connection.
+///
+/// By default, the client uses the ttrpc protocol. Use
+/// [`ClientBuilder::grpc`] to use gRPC instead.
 pub struct Client {
     send: mesh::Sender<mesh::OwnedMessage>,
     task: Task<()>,
@@ -56,9 +60,20 @@ struct ClientRequest<T> {
     service: String,
     deadline: Option<EncodeAs<Deadline, Timestamp>>,
     wait_ready: bool,
+    stream: bool,
     rpc: T,
 }
 
+impl ClientRequest<GenericRpc> {
+    fn fail(self, status: Status) {
+        if self.stream {
+            self.rpc.respond_stream_status(status);
+        } else {
+            self.rpc.respond_status(status);
+        }
+    }
+}
+
 /// Dials a connection to a server.
 pub trait Dial: 'static + Send {
     /// A bidirectional byte stream connection to the server.
@@ -114,6 +129,16 @@ async fn dial(&mut self) -> std::io::Result<Self::Stream> {
 /// A builder for [`Client`].
 pub struct ClientBuilder {
     retry_timeout: Duration,
+    protocol: Protocol,
+}
+
+#[derive(Clone)]
+enum Protocol {
+    Ttrpc,
+    #[cfg(feature = "grpc")]
+    Grpc {
+        authority: String,
+    },
 }
 
 impl ClientBuilder {
@@ -122,6 +147,7 @@ pub fn new() -> Self {
         Self {
             // Use the gRPC default.
             retry_timeout: Duration::from_secs(20),
+            protocol: Protocol::Ttrpc,
         }
     }
 
@@ -131,6 +157,19 @@ pub fn retry_timeout(&mut self, timeout: Duration) -> &mut Self {
         self
     }
 
+    /// Uses the gRPC protocol (over HTTP/2) instead of ttrpc.
+    ///
+    /// `authority` is sent as the `:authority` pseudo-header of each request.
+    /// Servers that do not host multiple services typically ignore it, so
+    /// `localhost` is usually sufficient.
+    #[cfg(feature = "grpc")]
+    pub fn grpc(&mut self, authority: impl Into<String>) -> &mut Self {
+        self.protocol = Protocol::Grpc {
+            authority: authority.into(),
+        };
+        self
+    }
+
     /// Builds a new client from a dialier.
     pub fn build(&self, driver: &(impl Driver + Spawn), dialer: impl Dial) -> Client {
         let (send, recv) = mesh::channel();
@@ -142,8 +181,9 @@ pub fn build(&self, driver: &(impl Driver + Spawn), dialer: impl Dial) -> Client
             rpc_recv: Some(recv),
             last_failure: None,
             failure_timeout: self.retry_timeout,
+            protocol: self.protocol.clone(),
         };
-        let task = driver.spawn("ttrpc client", worker.run());
+        let task = driver.spawn("rpc client", worker.run());
         Client {
             // Erase the type of the sender.
             send: mesh::local_node::Port::from(send).into(),
@@ -190,6 +230,18 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
     }
 }
 
+/// A stream of responses from a server-streaming RPC call.
+///
+/// The stream ends after the server completes the call. If the call fails,
+/// the final item is the error.
+pub struct CallStream<T>(mesh::Receiver<Result<T, Status>>);
+
+impl<T> std::fmt::Debug for CallStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("CallStream").field(&self.0).finish()
+    }
+}
+
 impl CallBuilder<'_> {
     /// Sets the timeout for the RPC.
     ///
@@ -235,11 +287,39 @@ pub fn start<F, R, T, U>(&self, rpc: F, input: T) -> Call<U>
                 deadline: self.deadline.map(Into::into),
                 rpc: DecodedRpc::Rpc(rpc(input, send)),
                 wait_ready: self.wait_ready,
+                stream: false,
             }));
 
         Call(recv)
     }
 
+    /// Starts a server-streaming RPC.
+    ///
+    /// Responses are returned by the returned stream as they arrive. Only the
+    /// gRPC protocol supports streaming; over ttrpc, the call fails with
+    /// [`Code::Unimplemented`].
+    #[must_use]
+    pub fn start_stream<F, R, T, U>(&self, rpc: F, input: T) -> CallStream<U>
+    where
+        F: FnOnce(T, mesh::Sender<Result<U, Status>>) -> R,
+        R: ServiceRpc,
+        U: 'static + MeshPayload + Send,
+    {
+        let (send, recv) = mesh::channel();
+
+        self.client
+            .send
+            .send(mesh::OwnedMessage::new(ClientRequest {
+                service: R::NAME.to_string(),
+                deadline: self.deadline.map(Into::into),
+                rpc: DecodedRpc::Rpc(rpc(input, send)),
+                wait_ready: self.wait_ready,
+                stream: true,
+            }));
+
+        CallStream(recv)
+    }
+
     /// Used to send unknown requests for testing.
     #[cfg(test)]
     pub(crate) fn start_raw(&self, service: &str, method: &str, data: Vec<u8>) -> Call<Vec<u8>> {
@@ -254,8 +334,10 @@ pub(crate) fn start_raw(&self, service: &str, method: &str, data: Vec<u8>) -> Ca
                     method: method.to_string(),
                     data,
                     port: send.into(),
+                    requests: None,
                 },
                 wait_ready: self.wait_ready,
+                stream: false,
             }));
 
         Call(recv)
@@ -277,6 +359,17 @@ fn poll(
     }
 }
 
+impl<T: 'static + Send> Stream for CallStream<T> {
+    type Item = Result<T, Status>;
+
+    fn poll_next(
+        self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.get_mut().0.poll_next_unpin(cx)
+    }
+}
+
 struct ClientWorker<T> {
     dialer: T,
     timer: PolledTimer,
@@ -285,6 +378,7 @@ struct ClientWorker<T> {
     rpc_recv: Option<mesh::Receiver<ClientRequest<GenericRpc>>>,
     last_failure: Option<Instant>,
     failure_timeout: Duration,
+    protocol: Protocol,
 }
 
 impl<T: Dial> ClientWorker<T> {
@@ -294,7 +388,14 @@ async fn run(mut self) {
                 None => break,
                 Some(Ok(stream)) => {
                     tracing::debug!("connection established");
-                    self.run_connection(stream).await.inspect_err(|err| {
+                    let r = match self.protocol.clone() {
+                        Protocol::Ttrpc => self.run_connection(stream).await,
+                        #[cfg(feature = "grpc")]
+                        Protocol::Grpc { authority } => {
+                            self.run_connection_grpc(stream, &authority).await
+                        }
+                    };
+                    r.inspect_err(|err| {
                         tracing::debug!(
                             error = err.as_ref() as &dyn std::error::Error,
                             "connection failed"
@@ -315,7 +416,7 @@ async fn run(mut self) {
                         if req.wait_ready {
                             return Some(req);
                         }
-                        req.rpc.respond_status(status.clone());
+                        req.fail(status.clone());
                         None
                     })
                     .collect();
@@ -389,7 +490,7 @@ enum Event<T> {
                         .filter_map(|req| {
                             if let Some(deadline) = req.deadline {
                                 if *deadline <= now {
-                                    req.rpc.respond_status(Status {
+                                    req.fail(Status {
                                         code: Code::DeadlineExceeded as i32,
                                         message: "deadline exceeded".to_string(),
                                         details: Vec::new(),
@@ -447,6 +548,22 @@ async fn run_connection(&mut self, stream: T::Stream) -> anyhow::Result<()> {
                 let Some(request) = request else {
                     break;
                 };
+                if request.stream {
+                    request.fail(Status {
+                        code: Code::Unimplemented as i32,
+                        message: "streaming responses are not supported over ttrpc".to_string(),
+                        details: Vec::new(),
+                    });
+                    continue;
+                }
+                if request.rpc.requests.is_some() {
+                    request.fail(Status {
+                        code: Code::Unimplemented as i32,
+                        message: "streaming requests are not supported over ttrpc".to_string(),
+                        details: Vec::new(),
+                    });
+                    continue;
+                }
                 responses
                     .lock()
                     .insert(next_stream_id, request.rpc.port.into());
@@ -496,6 +613,464 @@ fn handle_message(message: ReadResult) -> Result<Vec<u8>, Status> {
     }
 }
 
+#[cfg(feature = "grpc")]
+mod grpc {
+    use super::ClientRequest;
+    use super::ClientWorker;
+    use super::Dial;
+    use crate::rpc::status_from_err;
+    use crate::service::Code;
+    use crate::service::GenericRpc;
+    use crate::service::Status;
+    use anyhow::Context as _;
+    use futures::AsyncRead;
+    use futures::AsyncWrite;
+    use futures::FutureExt;
+    use futures::StreamExt;
+    use futures::stream::FuturesUnordered;
+    use futures_concurrency::future::Race;
+    use h2::SendStream;
+    use h2::client::ResponseFuture;
+    use h2::client::SendRequest;
+    use http::HeaderMap;
+    use mesh::Deadline;
+    use mesh::local_node::Port;
+    use prost::bytes::Bytes;
+    use std::future::pending;
+    use std::pin::Pin;
+    use std::pin::pin;
+    use std::task::ready;
+
+    /// The largest value allowed in a `grpc-timeout` header.
+    const MAX_TIMEOUT_VALUE: u128 = 99_999_999;
+
+    /// The largest response message accepted, matching the default receive
+    /// limit of other gRPC implementations.
+    const MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+    /// Servers may send `grpc-status-details-bin` with or without padding.
+    const STATUS_DETAILS_ENGINE: base64::engine::GeneralPurpose =
+        base64::engine::GeneralPurpose::new(
+            &base64::alphabet::STANDARD,
+            base64::engine::GeneralPurposeConfig::new()
+                .with_decode_padding_mode(base64::engine::DecodePaddingMode::Indifferent),
+        );
+
+    struct Wrap<T>(Pin<Box<T>>);
+
+    impl<T: AsyncRead> tokio::io::AsyncRead for Wrap<T> {
+        fn poll_read(
+            self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            let n = ready!(
+                self.get_mut()
+                    .0
+                    .as_mut()
+                    .poll_read(cx, buf.initialize_unfilled())
+            )?;
+            buf.advance(n);
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    impl<T: AsyncWrite> tokio::io::AsyncWrite for Wrap<T> {
+        fn poll_write(
+            self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<Result<usize, std::io::Error>> {
+            self.get_mut().0.as_mut().poll_write(cx, buf)
+        }
+
+        fn poll_flush(
+            self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            self.get_mut().0.as_mut().poll_flush(cx)
+        }
+
+        fn poll_shutdown(
+            self: Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<Result<(), std::io::Error>> {
+            self.get_mut().0.as_mut().poll_close(cx)
+        }
+    }
+
+    impl<T: Dial> ClientWorker<T> {
+        pub(super) async fn run_connection_grpc(
+            &mut self,
+            stream: T::Stream,
+            authority: &str,
+        ) -> anyhow::Result<()> {
+            let (mut send_request, conn) = h2::client::handshake(Wrap(Box::pin(stream)))
+                .await
+                .context("failed http2 handshake")?;
+            let mut conn = pin!(conn);
+
+            let mut responses = FuturesUnordered::new();
+            loop {
+                enum Event {
+                    Request(Result<Option<ClientRequest<GenericRpc>>, h2::Error>),
+                    Response(()),
+                    Closed(Result<(), h2::Error>),
+                }
+
+                let next = async {
+                    // Wait for the server to accept another stream before
+                    // taking the next request.
+                    std::future::poll_fn(|cx| send_request.poll_ready(cx)).await?;
+                    let request = if let Some(req) = self.waiting.pop_front() {
+                        Some(req)
+                    } else if let Some(recv) = &mut self.rpc_recv {
+                        recv.next().await
+                    } else {
+                        None
+                    };
+                    Ok(request)
+                };
+                let response = async {
+                    if responses.is_empty() {
+                        pending().await
+                    } else {
+                        responses.next().await;
+                    }
+                };
+
+                let event = (
+                    next.map(Event::Request),
+                    response.map(Event::Response),
+                    conn.as_mut().map(Event::Closed),
+                )
+                    .race()
+                    .await;
+
+                match event {
+                    Event::Request(r) => {
+                        let Some(request) = r.context("http2 connection failed")? else {
+                            break;
+                        };
+                        let ClientRequest {
+                            service,
+                            deadline,
+                            wait_ready: _,
+                            stream,
+                            rpc,
+                        } = request;
+                        let GenericRpc {
+                            method,
+                            data,
+                            port,
+                            requests,
+                        } = rpc;
+                        let mut responder = Responder::new(port, stream);
+                        let requests = requests.map(mesh::Receiver::<Vec<u8>>::from);
+                        match start_request(
+                            &mut send_request,
+                            authority,
+                            &service,
+                            &method,
+                            requests.is_none().then_some(data),
+                            deadline.map(|d| *d),
+                        ) {
+                            Ok((response, body)) => responses.push(handle_response(
+                                response,
+                                requests.map(|requests| (body, requests)),
+                                responder,
+                            )),
+                            Err(err) => {
+                                responder.complete(Err(status_from_err(Code::Internal, err)))
+                            }
+                        }
+                    }
+                    Event::Response(()) => {}
+                    Event::Closed(r) => {
+                        r.context("http2 connection failed")?;
+                        break;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    /// Starts a request, sending `data` as its only message. If `data` is
+    /// `None`, the request body is left open for streaming requests.
+    fn start_request(
+        send_request: &mut SendRequest<Bytes>,
+        authority: &str,
+        service: &str,
+        method: &str,
+        data: Option<Vec<u8>>,
+        deadline: Option<Deadline>,
+    ) -> anyhow::Result<(ResponseFuture, SendStream<Bytes>)> {
+        tracing::debug!(service, method, "rpc request");
+
+        let uri = http::Uri::builder()
+            .scheme("http")
+            .authority(authority)
+            .path_and_query(format!("/{service}/{method}"))
+            .build()?;
+
+        let mut request = http::Request::builder()
+            .method(http::Method::POST)
+            .uri(uri)
+            .header("content-type", "application/grpc+proto")
+            .header("te", "trailers");
+
+        if let Some(deadline) = deadline {
+            let timeout = (deadline - Deadline::now()).as_millis();
+            request = request.header(
+                "grpc-timeout",
+                format!("{}m", timeout.min(MAX_TIMEOUT_VALUE)),
+            );
+        }
+
+        let (response, mut body) = send_request.send_request(request.body(())?, false)?;
+        if let Some(data) = data {
+            body.send_data(frame_message(data), true)?;
+        }
+        Ok((response, body))
+    }
+
+    fn frame_message(data: Vec<u8>) -> Bytes {
+        let mut buf = Vec::with_capacity(5 + data.len());
+        buf.push(0);
+        buf.extend(&(data.len() as u32).to_be_bytes());
+        buf.extend(data);
+        buf.into()
+    }
+
+    /// Delivers responses to the caller.
+    ///
+    /// If dropped before completion, such as when the connection fails, the
+    /// call fails with [`Code::Unavailable`].
+    struct Responder(Option<ResponsePort>);
+
+    enum ResponsePort {
+        Unary {
+            send: mesh::OneshotSender<mesh::OwnedMessage>,
+            message: Option<Vec<u8>>,
+        },
+        Stream(mesh::Sender<mesh::OwnedMessage>),
+    }
+
+    impl Responder {
+        fn new(port: Port, stream: bool) -> Self {
+            Self(Some(if stream {
+                ResponsePort::Stream(port.into())
+            } else {
+                ResponsePort::Unary {
+                    send: port.into(),
+                    message: None,
+                }
+            }))
+        }
+
+        fn message(&mut self, data: Vec<u8>) {
+            match self.0.as_mut() {
+                Some(ResponsePort::Unary { message, .. }) => {
+                    // Unary methods have a single response message; ignore
+                    // any extras.
+                    if message.is_none() {
+                        *message = Some(data);
+                    }
+                }
+                Some(ResponsePort::Stream(send)) => {
+                    send.send(mesh::OwnedMessage::new(Ok::<_, Status>(data)));
+                }
+                None => {}
+            }
+        }
+
+        fn complete(&mut self, result: Result<(), Status>) {
+            match self.0.take() {
+                Some(ResponsePort::Unary { send, message }) => {
+                    let result = result.and_then(|()| {
+                        message.ok_or_else(|| {
+                            status_from_err(Code::Internal, anyhow::anyhow!("missing response"))
+                        })
+                    });
+                    send.send(mesh::OwnedMessage::new(result));
+                }
+                Some(ResponsePort::Stream(send)) => {
+                    // The stream ends when the sender is dropped.
+                    if let Err(status) = result {
+                        send.send(mesh::OwnedMessage::new(Err::<Vec<u8>, _>(status)));
+                    }
+                }
+                None => {}
+            }
+        }
+    }
+
+    impl Drop for Responder {
+        fn drop(&mut self) {
+            self.complete(Err(status_from_err(
+                Code::Unavailable,
+                anyhow::anyhow!("connection closed"),
+            )));
+        }
+    }
+
+    async fn handle_response(
+        response: ResponseFuture,
+        requests: Option<(SendStream<Bytes>, mesh::Receiver<Vec<u8>>)>,
+        mut responder: Responder,
+    ) {
+        // Send the requests of a client-streaming call until the caller drops
+        // its sender or the server completes the call.
+        let send_requests = async {
+            if let Some((mut body, mut requests)) = requests {
+                while let Some(data) = requests.next().await {
+                    if body.send_data(frame_message(data), false).is_err() {
+                        // The stream was reset. The response reports why.
+                        break;
+                    }
+                }
+                let _ = body.send_data(Bytes::new(), true);
+            }
+            pending::<Result<(), Status>>().await
+        };
+        let result = (read_response(response, &mut responder), send_requests)
+            .race()
+            .await;
+        match &result {
+            Ok(()) => tracing::debug!("rpc success"),
+            Err(status) => tracing::debug!(?status, "rpc error"),
+        }
+        responder.complete(result);
+    }
+
+    async fn read_response(
+        response: ResponseFuture,
+        responder: &mut Responder,
+    ) -> Result<(), Status> {
+        let response = response
+            .await
+            .map_err(|err| status_from_err(Code::Unavailable, err))?;
+
+        let (head, mut body) = response.into_parts();
+        if head.status != http::StatusCode::OK {
+            return Err(status_from_err(
+                code_from_http_status(head.status),
+                anyhow::anyhow!("http status {}", head.status),
+            ));
+        }
+
+        // A response without messages can carry its status in the headers
+        // instead of the trailers.
+        if let Some(result) = parse_status(&head.headers) {
+            return result;
+        }
+
+        let mut buf = Vec::new();
+        while let Some(data) = body.data().await {
+            let data = data.map_err(|err| status_from_err(Code::Unavailable, err))?;
+            body.flow_control().release_capacity(data.len()).unwrap();
+            buf.extend(&data);
+            while let Some(message) = take_message(&mut buf)? {
+                responder.message(message);
+            }
+        }
+        if !buf.is_empty() {
+            return Err(status_from_err(
+                Code::Internal,
+                anyhow::anyhow!("truncated response message"),
+            ));
+        }
+
+        let trailers = body
+            .trailers()
+            .await
+            .map_err(|err| status_from_err(Code::Unavailable, err))?;
+
+        trailers.as_ref().and_then(parse_status).unwrap_or_else(|| {
+            Err(status_from_err(
+                Code::Internal,
+                anyhow::anyhow!("missing grpc-status"),
+            ))
+        })
+    }
+
+    /// Removes the next complete length-prefixed message from `buf`.
+    ///
+    /// Fails with [`Code::ResourceExhausted`] as soon as the header of a
+    /// message larger than [`MAX_MESSAGE_SIZE`] arrives, rather than buffering
+    /// it.
+    pub(super) fn take_message(buf: &mut Vec<u8>) -> Result<Option<Vec<u8>>, Status> {
+        let Some(hdr) = buf.get(0..5) else {
+            return Ok(None);
+        };
+        if hdr[0] != 0 {
+            // Compression was not advertised as supported, so the server
+            // should not send compressed messages.
+            return Err(status_from_err(
+                Code::Internal,
+                anyhow::anyhow!("unexpected compressed message"),
+            ));
+        }
+        let len = u32::from_be_bytes(hdr[1..5].try_into().unwrap()) as usize;
+        if len > MAX_MESSAGE_SIZE {
+            return Err(status_from_err(
+                Code::ResourceExhausted,
+                anyhow::anyhow!("response message length {len} exceeds maximum {MAX_MESSAGE_SIZE}"),
+            ));
+        }
+        if buf.len() < 5 + len {
+            return Ok(None);
+        }
+        let message = buf[5..5 + len].to_vec();
+        buf.drain(..5 + len);
+        Ok(Some(message))
+    }
+
+    fn parse_status(headers: &HeaderMap) -> Option<Result<(), Status>> {
+        let code = headers.get("grpc-status")?;
+        let Some(code) = code.to_str().ok().and_then(|c| c.parse::<i32>().ok()) else {
+            return Some(Err(status_from_err(
+                Code::Internal,
+                anyhow::anyhow!("invalid grpc-status"),
+            )));
+        };
+        if code == Code::Ok as i32 {
+            return Some(Ok(()));
+        }
+
+        // Prefer the full status, which includes any details.
+        let status = headers
+            .get("grpc-status-details-bin")
+            .and_then(|v| base64::Engine::decode(&STATUS_DETAILS_ENGINE, v.as_bytes()).ok())
+            .and_then(|v| <Status as prost::Message>::decode(v.as_slice()).ok())
+            .unwrap_or_else(|| Status {
+                code,
+                message: headers
+                    .get("grpc-message")
+                    .and_then(|v| v.to_str().ok())
+                    .and_then(|v| urlencoding::decode(v).ok())
+                    .map(|v| v.into_owned())
+                    .unwrap_or_default(),
+                details: Vec::new(),
+            });
+
+        Some(Err(status))
+    }
+
+    /// Maps an HTTP status to a gRPC code, as described in the gRPC spec.
+    fn code_from_http_status(status: http::StatusCode) -> Code {
+        match status.as_u16() {
+            400 => Code::Internal,
+            401 => Code::Unauthenticated,
+            403 => Code::PermissionDenied,
+            404 => Code::Unimplemented,
+            429 | 502 | 503 | 504 => Code::Unavailable,
+            _ => Code::Unknown,
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::Client;
@@ -573,4 +1148,23 @@ async fn test_delayed_connect(driver: DefaultDriver) {
 
         assert_eq!(err.code, Code::DeadlineExceeded as i32);
     }
+
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn test_grpc_message_too_large() {
+        // Only the header has arrived, but it is enough to reject the message.
+        let mut buf = vec![0];
+        buf.extend((4 * 1024 * 1024 + 1u32).to_be_bytes());
+        let err = super::grpc::take_message(&mut buf).unwrap_err();
+        assert_eq!(err.code, Code::ResourceExhausted as i32);
+
+        let mut buf = vec![0];
+        buf.extend(3u32.to_be_bytes());
+        buf.extend(b"abc");
+        assert_eq!(
+            super::grpc::take_message(&mut buf).unwrap().as_deref(),
+            Some(&b"abc"[..])
+        );
+        assert!(buf.is_empty());
+    }
 }
@@ -8,8 +8,8 @@
 //! interop well with mesh channels, allowing gRPC to be easily used with a
 //! mesh-based application.
 //!
-//! Currently, the server supports the gRPC and ttrpc protocols, while the
-//! client only supports the ttrpc protocol.
+//! Both the server and the client support the gRPC and ttrpc protocols. Methods
+//! with streaming requests or responses are only supported over gRPC.
 //!
 //! # Usage
 //!
@@ -42,7 +42,14 @@
 /// A ttrpc server.
 #[derive(Debug, Default)]
 pub struct Server {
-    services: HashMap<&'static str, mesh::Sender<(CancelContext, GenericRpc)>>,
+    services: HashMap<&'static str, Service>,
+}
+
+#[derive(Debug)]
+struct Service {
+    send: mesh::Sender<(CancelContext, GenericRpc)>,
+    is_server_streaming: fn(&str) -> bool,
+    is_client_streaming: fn(&str) -> bool,
 }
 
 /// A receiver for RPC requests for a given service.
@@ -73,7 +80,8 @@ fn poll_next(
             match rpc {
                 DecodedRpc::Rpc(rpc) => return Some((ctx, rpc)).into(),
                 DecodedRpc::Err { rpc, err } => {
-                    rpc.fail(err);
+                    let streaming = T::is_server_streaming(&rpc.method);
+                    rpc.fail(err, streaming);
                 }
             }
         }
@@ -88,7 +96,7 @@ fn is_terminated(&self) -> bool {
 }
 
 impl GenericRpc {
-    fn fail(self, err: ServiceRpcError) {
+    fn fail(self, err: ServiceRpcError, streaming: bool) {
         let status = match err {
             ServiceRpcError::UnknownMethod => Status {
                 code: Code::Unimplemented.into(),
@@ -96,8 +104,17 @@ fn fail(self, err: ServiceRpcError) {
                 details: Vec::new(),
             },
             ServiceRpcError::InvalidInput(error) => status_from_err(Code::InvalidArgument, error),
+            ServiceRpcError::MissingRequestStream => Status {
+                code: Code::InvalidArgument.into(),
+                message: format!("missing request stream for method {}", self.method),
+                details: Vec::new(),
+            },
         };
-        self.respond_status(status);
+        if streaming {
+            self.respond_stream_status(status);
+        } else {
+            self.respond_status(status);
+        }
     }
 }
 
@@ -112,7 +129,14 @@ pub fn new() -> Self {
     /// Adds or updates a channel for receiving service requests.
     pub fn add_service<T: ServiceRpc>(&mut self) -> RpcReceiver<T> {
         let (send, recv) = mesh::channel();
-        self.services.insert(T::NAME, Port::from(send).into());
+        self.services.insert(
+            T::NAME,
+            Service {
+                send: Port::from(send).into(),
+                is_server_streaming: T::is_server_streaming,
+                is_client_streaming: T::is_client_streaming,
+            },
+        );
         RpcReceiver(recv)
     }
 
@@ -183,6 +207,18 @@ pub async fn serve_connection(
                         )
                     })?;
 
+                    if (service.is_server_streaming)(&request.method)
+                        || (service.is_client_streaming)(&request.method)
+                    {
+                        return Err(status_from_err(
+                            Code::Unimplemented,
+                            anyhow::anyhow!(
+                                "streaming method {} is not supported over ttrpc",
+                                request.method
+                            ),
+                        ));
+                    }
+
                     let ctx = if request.timeout_nano == 0 {
                         ctx.clone()
                     } else {
@@ -190,12 +226,13 @@ pub async fn serve_connection(
                     };
 
                     Ok(move |port| {
-                        service.send((
+                        service.send.send((
                             ctx,
                             GenericRpc {
                                 method: request.method,
                                 data: request.payload,
                                 port,
+                                requests: None,
                             },
                         ));
                     })
@@ -301,8 +338,10 @@ mod grpc {
     use futures::AsyncWrite;
     use futures::FutureExt;
     use futures::StreamExt;
+    use futures_concurrency::future::Race;
     use futures_concurrency::stream::Merge;
     use h2::RecvStream;
+    use h2::SendStream;
     use h2::server::SendResponse;
     use http::HeaderMap;
     use http::HeaderValue;
@@ -312,6 +351,7 @@ mod grpc {
     use pal_async::socket::Listener;
     use pal_async::socket::PolledSocket;
     use prost::bytes::Bytes;
+    use std::future::pending;
     use std::io::Read;
     use std::io::Write;
     use std::pin::Pin;
@@ -520,18 +560,14 @@ async fn handle_request(
             // No returning HTTP status code errors after this point.
             let mut resp = resp.send_response(response.body(())?, false)?;
 
-            let result = self.invoke_rpc(service, method, body, ctx).await?;
+            let result = self
+                .invoke_rpc(service, method, body, ctx, &mut resp)
+                .await?;
 
             let mut trailers = HeaderMap::new();
             match result {
-                Ok(data) => {
+                Ok(()) => {
                     tracing::debug!(service, method, "rpc success");
-
-                    let mut buf = Vec::with_capacity(5 + data.len());
-                    buf.push(0);
-                    buf.extend(&(data.len() as u32).to_be_bytes());
-                    buf.extend(data);
-                    resp.send_data(buf.into(), false)?;
                     trailers.insert("grpc-status", const { HeaderValue::from_static("0") });
                 }
                 Err(status) => {
@@ -566,7 +602,8 @@ async fn invoke_rpc(
             method: &str,
             mut body: RecvStream,
             ctx: CancelContext,
-        ) -> Result<Result<Vec<u8>, Status>, RequestError> {
+            resp: &mut SendStream<Bytes>,
+        ) -> Result<Result<(), Status>, RequestError> {
             let Some(service) = self.services.get(service) else {
                 return Ok(Err(Status {
                     code: Code::Unimplemented.into(),
@@ -575,50 +612,118 @@ async fn invoke_rpc(
                 }));
             };
 
-            // For now, only non-stream RPCs are supported, so read the first
-            // message and ignore the rest.
-            //
-            // FUTURE: change the `GenericRpc` type to include channels for
-            // streams.
-
             let mut buf = Vec::new();
+            let (data, requests) = if (service.is_client_streaming)(method) {
+                // The requests are forwarded to the service as they arrive.
+                (Vec::new(), Some(mesh::channel()))
+            } else {
+                // Read the single request message and ignore the rest.
+                let data = read_request_message(&mut body, &mut buf)
+                    .await?
+                    .ok_or(RequestError::InvalidHeader)?;
+                (data, None)
+            };
+            let (requests_send, requests_recv) = requests.unzip();
 
-            // Read data frames until the header is complete.
-            while buf.len() < 5 {
-                let data = body.data().await.ok_or(RequestError::InvalidHeader)??;
-                buf.extend(&data);
-                body.flow_control().release_capacity(data.len()).unwrap();
-            }
-            let hdr = buf.get(0..5).ok_or(RequestError::InvalidHeader)?;
-            if hdr[0] != 0 {
-                // Compression was not advertised as supported, so the client
-                // should not send compressed messages.
-                return Err(RequestError::InvalidHeader);
-            }
-            let len = u32::from_be_bytes(hdr[1..5].try_into().unwrap()) as usize;
-
-            buf.drain(..5);
-            while buf.len() < len {
-                let data = body.data().await.ok_or(RequestError::InvalidHeader)??;
-                buf.extend(&data);
-                body.flow_control().release_capacity(data.len()).unwrap();
-            }
-
-            let (send, recv) = mesh::oneshot();
+            let forward_requests = async {
+                if let Some(send) = requests_send {
+                    while let Some(data) = read_request_message(&mut body, &mut buf).await? {
+                        send.send(data);
+                    }
+                    // The request stream ends when `send` is dropped.
+                }
+                pending::<Result<Result<(), Status>, RequestError>>().await
+            };
 
-            let rpc = GenericRpc {
-                method: method.to_owned(),
-                data: buf,
-                port: send.into(),
+            let respond = async {
+                let requests = requests_recv.map(|recv: mesh::Receiver<Vec<u8>>| recv.into());
+                if (service.is_server_streaming)(method) {
+                    let (send, mut recv) = mesh::channel::<Result<Vec<u8>, Status>>();
+                    service.send.send((
+                        ctx,
+                        GenericRpc {
+                            method: method.to_owned(),
+                            data,
+                            port: send.into(),
+                            requests,
+                        },
+                    ));
+                    // The stream completes successfully when the service
+                    // closes the channel.
+                    while let Some(result) = recv.next().await {
+                        match result {
+                            Ok(data) => send_message(resp, data)?,
+                            Err(status) => return Ok(Err(status)),
+                        }
+                    }
+                } else {
+                    let (send, recv) = mesh::oneshot();
+                    service.send.send((
+                        ctx,
+                        GenericRpc {
+                            method: method.to_owned(),
+                            data,
+                            port: send.into(),
+                            requests,
+                        },
+                    ));
+                    match recv
+                        .await
+                        .unwrap_or_else(|err| Err(status_from_err(Code::Internal, err)))
+                    {
+                        Ok(data) => send_message(resp, data)?,
+                        Err(status) => return Ok(Err(status)),
+                    }
+                }
+                Ok::<_, RequestError>(Ok(()))
             };
 
-            service.send((ctx, rpc));
+            (forward_requests, respond).race().await
+        }
+    }
 
-            Ok(recv
-                .await
-                .unwrap_or_else(|err| Err(status_from_err(Code::Internal, err))))
+    /// Reads the next length-prefixed message from `body`, keeping any data
+    /// received past the end of the message in `buf`.
+    ///
+    /// Returns `None` at the end of the stream.
+    async fn read_request_message(
+        body: &mut RecvStream,
+        buf: &mut Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, RequestError> {
+        loop {
+            if let Some(hdr) = buf.get(0..5) {
+                if hdr[0] != 0 {
+                    // Compression was not advertised as supported, so the
+                    // client should not send compressed messages.
+                    return Err(RequestError::InvalidHeader);
+                }
+                let len = u32::from_be_bytes(hdr[1..5].try_into().unwrap()) as usize;
+                if buf.len() >= 5 + len {
+                    let message = buf[5..5 + len].to_vec();
+                    buf.drain(..5 + len);
+                    return Ok(Some(message));
+                }
+            }
+            let Some(data) = body.data().await else {
+                // The stream must not end in the middle of a message.
+                if !buf.is_empty() {
+                    return Err(RequestError::InvalidHeader);
+                }
+                return Ok(None);
+            };
+            let data = data?;
+            buf.extend(&data);
+            body.flow_control().release_capacity(data.len()).unwrap();
         }
     }
+
+    fn send_message(resp: &mut SendStream<Bytes>, data: Vec<u8>) -> Result<(), h2::Error> {
+        let mut buf = Vec::with_capacity(5 + data.len());
+        buf.push(0);
+        buf.extend(&(data.len() as u32).to_be_bytes());
+        buf.extend(data);
+        resp.send_data(buf.into(), false)
+    }
 }
 
 #[cfg(test)]
@@ -628,6 +733,7 @@ mod tests {
     use crate::client::ExistingConnection;
     use crate::service::Code;
     use crate::service::ServiceRpc;
+    use crate::service::request_channel;
     use futures::StreamExt;
     use futures::executor::block_on;
     use pal_async::DefaultPool;
@@ -678,6 +784,27 @@ fn client_server() {
 
                 assert_eq!(status.code, Code::Unimplemented as i32);
 
+                let status = client
+                    .call()
+                    .start_stream(items::Example::Method3, items::Method3Request { count: 1 })
+                    .next()
+                    .await
+                    .unwrap()
+                    .unwrap_err();
+
+                assert_eq!(status.code, Code::Unimplemented as i32);
+
+                let (_requests, recv) = request_channel::<items::Method4Request>();
+                let status = client
+                    .call()
+                    .start_stream(items::Example::Method4, recv)
+                    .next()
+                    .await
+                    .unwrap()
+                    .unwrap_err();
+
+                assert_eq!(status.code, Code::Unimplemented as i32);
+
                 client.shutdown().await;
             })
         });
@@ -702,4 +829,131 @@ fn client_server() {
         client_thread.join().unwrap();
         server_thread.join().unwrap().unwrap();
     }
+
+    #[cfg(feature = "grpc")]
+    #[test]
+    fn grpc_client_server() {
+        use crate::client::ClientBuilder;
+
+        let (c, s) = unix_socket::UnixStream::pair().unwrap();
+        let mut server = Server::new();
+        let mut recv = server.add_service::<items::Example>();
+        let server_thread = std::thread::spawn(move || {
+            block_with_io(async |driver| {
+                server
+                    .serve_connection_grpc(PolledSocket::new(&driver, s)?)
+                    .await
+            })
+        });
+
+        let client_thread = std::thread::spawn(move || {
+            DefaultPool::run_with(async |driver| {
+                let client = ClientBuilder::new().grpc("localhost").build(
+                    &driver,
+                    ExistingConnection::new(PolledSocket::new(&driver, c).unwrap()),
+                );
+                let response = client
+                    .call()
+                    .start(
+                        items::Example::Method1,
+                        items::Method1Request {
+                            foo: "abc".to_string(),
+                            bar: "def".to_string(),
+                        },
+                    )
+                    .await
+                    .unwrap();
+
+                assert_eq!(&response.foo, "abc123");
+                assert_eq!(&response.bar, "def456");
+
+                let responses = client
+                    .call()
+                    .start_stream(items::Example::Method3, items::Method3Request { count: 3 })
+                    .collect::<Vec<_>>()
+                    .await;
+
+                let indexes = responses
+                    .into_iter()
+                    .map(|r| r.unwrap().index)
+                    .collect::<Vec<_>>();
+                assert_eq!(indexes, [0, 1, 2]);
+
+                let responses = client
+                    .call()
+                    .start_stream(
+                        items::Example::Method3,
+                        items::Method3Request { count: 100 },
+                    )
+                    .collect::<Vec<_>>()
+                    .await;
+
+                assert_eq!(responses.len(), 1);
+                let status = responses.into_iter().next().unwrap().unwrap_err();
+                assert_eq!(status.code, Code::InvalidArgument as i32);
+                assert_eq!(status.message, "too many");
+
+                // Each request is answered before the next one is sent, so
+                // the call must stream in both directions at once.
+                let (requests, recv) = request_channel();
+                let mut responses = client.call().start_stream(items::Example::Method4, recv);
+                for value in ["abc", "def"] {
+                    requests.send(items::Method4Request {
+                        value: value.to_string(),
+                    });
+                    let response = responses.next().await.unwrap().unwrap();
+                    assert_eq!(response.value, format!("{value}!"));
+                }
+                drop(requests);
+                assert!(responses.next().await.is_none());
+
+                let status = client
+                    .call()
+                    .start_raw(items::Example::NAME, "unknown", Vec::new())
+                    .await
+                    .unwrap_err();
+
+                assert_eq!(status.code, Code::Unimplemented as i32);
+
+                client.shutdown().await;
+            })
+        });
+
+        block_on(async {
+            while let Some((_, req)) = recv.next().await {
+                match req {
+                    items::Example::Method1(input, resp) => {
+                        resp.send(Ok(items::Method1Response {
+                            foo: input.foo + "123",
+                            bar: input.bar + "456",
+                        }));
+                    }
+                    items::Example::Method3(input, resp) => {
+                        if input.count > 10 {
+                            resp.send(Err(crate::service::Status {
+                                code: Code::InvalidArgument as i32,
+                                message: "too many".to_string(),
+                                details: Vec::new(),
+                            }));
+                        } else {
+                            for index in 0..input.count {
+                                resp.send(Ok(items::Method3Response { index }));
+                            }
+                        }
+                    }
+                    items::Example::Method4(mut requests, resp) => {
+                        while let Some(input) = requests.next().await {
+                            resp.send(input.map(|input| items::Method4Response {
+                                value: input.value + "!",
+                            }));
+                        }
+                    }
+                    _ => panic!("{:?}", req),
+                }
+            }
+        });
+
+        client_thread.join().unwrap();
+        server_thread.join().unwrap().unwrap();
+    }
 }
@@ -3,12 +3,15 @@
 
 //! Protobuf service support for mesh types.
 
+use futures::Stream;
+use futures::StreamExt;
 pub use grpc::Code;
 pub use grpc::Status;
 use mesh::local_node::Port;
 use mesh::payload::DefaultEncoding;
 use mesh::payload::MessageDecode;
 use mesh::payload::MessageEncode;
+use mesh::payload::Protobuf;
 use mesh::payload::Result;
 use mesh::payload::encoding::MessageEncoding;
 use mesh::payload::protobuf::FieldSizer;
@@ -17,6 +20,10 @@
 use mesh::payload::protobuf::MessageSizer;
 use mesh::payload::protobuf::MessageWriter;
 use mesh::resource::Resource;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::Context;
+use std::task::Poll;
 
 #[expect(clippy::allow_attributes)]
 mod grpc {
@@ -47,6 +54,9 @@ pub(crate) struct GenericRpc {
     pub data: Vec<u8>,
     #[mesh(3)]
     pub port: Port, // TODO: transparent mesh::OneshotSender<std::result::Result<Vec<u8>, Status>>,
+    /// The request stream of a client-streaming method.
+    #[mesh(4)]
+    pub requests: Option<Port>, // TODO: transparent mesh::Receiver<Vec<u8>>,
 }
 
 impl GenericRpc {
@@ -58,6 +68,13 @@ pub(crate) fn respond_status(self, status: Status) {
 
         sender.send(Err(status));
     }
+
+    pub(crate) fn respond_stream_status(self, status: Status) {
+        let sender =
+            mesh::Sender::<std::result::Result<std::convert::Infallible, Status>>::from(self.port);
+
+        sender.send(Err(status));
+    }
 }
 
 /// A generic RPC value, using borrows instead of owning types.
@@ -69,6 +86,8 @@ struct GenericRpcView<'a> {
     data: &'a [u8],
     #[mesh(3)]
     port: Port,
+    #[mesh(4)]
+    requests: Option<Port>,
 }
 
 /// Trait for service-specific RPC requests.
@@ -79,16 +98,29 @@ pub trait ServiceRpc: 'static + Send + Sized {
     /// The method name.
     fn method(&self) -> &'static str;
 
-    /// Encode the request into a field.
-    fn encode(self, writer: FieldWriter<'_, '_, Resource>) -> Port;
+    /// Returns whether `method` streams its responses back over a channel
+    /// rather than sending a single response.
+    fn is_server_streaming(method: &str) -> bool;
+
+    /// Returns whether `method` receives its requests as a [`RequestStream`]
+    /// rather than as a single request.
+    fn is_client_streaming(method: &str) -> bool;
+
+    /// Encode the request into a field, returning the response port and, for
+    /// client-streaming methods, the request stream port.
+    fn encode(self, writer: FieldWriter<'_, '_, Resource>) -> (Port, Option<Port>);
 
     /// Compute the field size of the request.
     fn compute_size(&mut self, sizer: FieldSizer<'_>);
 
     /// Decode the request from a field.
+    ///
+    /// For client-streaming methods, `data` is ignored and the requests are
+    /// read from `requests`.
     fn decode(
         method: &str,
         port: Port,
+        requests: Option<Port>,
         data: &[u8],
     ) -> std::result::Result<Self, (ServiceRpcError, Port)>;
 }
@@ -99,6 +131,75 @@ pub enum ServiceRpcError {
     UnknownMethod,
     /// The input could not be decoded.
     InvalidInput(mesh::payload::Error),
+    /// A client-streaming method was called without a request stream.
+    MissingRequestStream,
+}
+
+/// Returns a new channel for the requests of a client-streaming method.
+///
+/// Pass the [`RequestStream`] to the method, and send requests with the
+/// [`RequestSender`]. The stream ends when the sender is dropped.
+pub fn request_channel<T>() -> (RequestSender<T>, RequestStream<T>) {
+    let (send, recv) = mesh::channel();
+    (
+        RequestSender(send, PhantomData),
+        RequestStream(recv, PhantomData),
+    )
+}
+
+/// The sending half of a client-streaming method's requests, returned by
+/// [`request_channel`].
+pub struct RequestSender<T>(mesh::Sender<Vec<u8>>, PhantomData<fn(T)>);
+
+impl<T: Protobuf> RequestSender<T> {
+    /// Sends a request.
+    pub fn send(&self, request: T) {
+        self.0.send(mesh::payload::encode(request));
+    }
+}
+
+impl<T> std::fmt::Debug for RequestSender<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RequestSender").field(&self.0).finish()
+    }
+}
+
+/// The requests of a client-streaming method.
+///
+/// The requests are received in their encoded form and decoded as they are
+/// read, so that the transport does not need to know their type. The stream
+/// ends when the client finishes sending requests. A request that fails to
+/// decode is returned as an error.
+pub struct RequestStream<T>(mesh::Receiver<Vec<u8>>, PhantomData<fn() -> T>);
+
+impl<T> std::fmt::Debug for RequestStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("RequestStream").field(&self.0).finish()
+    }
+}
+
+impl<T> From<Port> for RequestStream<T> {
+    fn from(port: Port) -> Self {
+        Self(port.into(), PhantomData)
+    }
+}
+
+impl<T> From<RequestStream<T>> for Port {
+    fn from(stream: RequestStream<T>) -> Self {
+        stream.0.into()
+    }
+}
+
+impl<T: Protobuf> Stream for RequestStream<T> {
+    type Item = std::result::Result<T, Status>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let data = std::task::ready!(self.get_mut().0.poll_next_unpin(cx));
+        Poll::Ready(data.map(|data| {
+            mesh::payload::decode(&data)
+                .map_err(|err| crate::rpc::status_from_err(Code::InvalidArgument, err))
+        }))
+    }
 }
 
 #[doc(hidden)]
@@ -121,8 +222,11 @@ fn write_message(item: DecodedRpc<T>, mut writer: MessageWriter<'_, '_, Resource
         match item {
             DecodedRpc::Rpc(rpc) => {
                 writer.field(1).bytes(rpc.method().as_bytes());
-                let port = rpc.encode(writer.field(2));
+                let (port, requests) = rpc.encode(writer.field(2));
                 writer.field(3).resource(Resource::Port(port));
+                if let Some(requests) = requests {
+                    writer.field(4).resource(Resource::Port(requests));
+                }
             }
             DecodedRpc::Err { rpc, err: _ } => {
                 <GenericRpc as DefaultEncoding>::Encoding::write_message(rpc, writer)
@@ -136,6 +240,9 @@ fn compute_message_size(item: &mut DecodedRpc<T>, mut sizer: MessageSizer<'_>) {
                 sizer.field(1).bytes(rpc.method().len());
                 rpc.compute_size(sizer.field(2));
                 sizer.field(3).resource();
+                if T::is_client_streaming(rpc.method()) {
+                    sizer.field(4).resource();
+                }
             }
             DecodedRpc::Err { rpc, err: _ } => {
                 <GenericRpc as DefaultEncoding>::Encoding::compute_message_size(rpc, sizer)
@@ -152,13 +259,14 @@ fn read_message(
         mesh::payload::inplace_none!(v: GenericRpcView<'_>);
         <GenericRpcView<'_> as DefaultEncoding>::Encoding::read_message(&mut v, reader)?;
         let v = v.take().expect("should be constructed");
-        let rpc = match T::decode(v.method, v.port, v.data) {
+        let rpc = match T::decode(v.method, v.port, v.requests, v.data) {
             Ok(rpc) => DecodedRpc::Rpc(rpc),
             Err((err, port)) => {
                 let rpc = GenericRpc {
                     method: v.method.to_string(),
                     data: v.data.to_vec(),
                     port,
+                    requests: None,
                 };
                 DecodedRpc::Err { rpc, err }
             }
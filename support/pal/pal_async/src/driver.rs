@@ -136,6 +136,22 @@ unsafe fn io_uring_submit(
         &self,
         sqe: crate::io_uring::Entry,
     ) -> std::pin::Pin<Box<dyn Future<Output = io::Result<i32>> + Send + '_>>;
+
+    /// Submits an io-uring SQE that references `buf` as a fixed buffer.
+    ///
+    /// See [`IoUringSubmit::submit_fixed`](crate::io_uring::IoUringSubmit::submit_fixed).
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as [`io_uring_submit`](Self::io_uring_submit)
+    /// apply. In addition, `buf` must be the only fixed buffer referenced by
+    /// the SQE.
+    #[cfg(target_os = "linux")]
+    unsafe fn io_uring_submit_fixed<'a>(
+        &'a self,
+        sqe: crate::io_uring::Entry,
+        buf: &'a crate::io_uring::FixedBuffer,
+    ) -> std::pin::Pin<Box<dyn Future<Output = io::Result<i32>> + Send + 'a>>;
 }
 
 #[cfg(target_os = "macos")]
@@ -222,6 +238,24 @@ unsafe fn io_uring_submit(
             .await
         })
     }
+
+    unsafe fn io_uring_submit_fixed<'a>(
+        &'a self,
+        sqe: crate::io_uring::Entry,
+        buf: &'a crate::io_uring::FixedBuffer,
+    ) -> std::pin::Pin<Box<dyn Future<Output = io::Result<i32>> + Send + 'a>> {
+        use crate::io_uring::IoUringSubmit as _;
+
+        Box::pin(async move {
+            // SAFETY: caller guarantees contract
+            unsafe {
+                self.io_uring_submitter()
+                    .ok_or(io::ErrorKind::Unsupported)?
+                    .submit_fixed(sqe, buf)
+            }
+            .await
+        })
+    }
 }
 
 #[cfg(windows)]
@@ -288,6 +322,16 @@ unsafe fn io_uring_submit(
         // SAFETY: caller guarantees contract
         unsafe { self.as_ref().io_uring_submit(sqe) }
     }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn io_uring_submit_fixed<'a>(
+        &'a self,
+        sqe: crate::io_uring::Entry,
+        buf: &'a crate::io_uring::FixedBuffer,
+    ) -> std::pin::Pin<Box<dyn Future<Output = io::Result<i32>> + Send + 'a>> {
+        // SAFETY: caller guarantees contract
+        unsafe { self.as_ref().io_uring_submit_fixed(sqe, buf) }
+    }
 }
 
 #[cfg(windows)]
@@ -349,6 +393,16 @@ unsafe fn io_uring_submit(
         // SAFETY: caller guarantees contract
         unsafe { self.as_ref().io_uring_submit(sqe) }
     }
+
+    #[cfg(target_os = "linux")]
+    unsafe fn io_uring_submit_fixed<'a>(
+        &'a self,
+        sqe: crate::io_uring::Entry,
+        buf: &'a crate::io_uring::FixedBuffer,
+    ) -> std::pin::Pin<Box<dyn Future<Output = io::Result<i32>> + Send + 'a>> {
+        // SAFETY: caller guarantees contract
+        unsafe { self.as_ref().io_uring_submit_fixed(sqe, buf) }
+    }
 }
 
 #[cfg(windows)]
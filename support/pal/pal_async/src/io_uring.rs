@@ -3,14 +3,22 @@
 
 //! io-uring submission trait.
 
-// UNSAFETY: The `IoUringSubmit` trait has an unsafe method for submitting SQEs.
+// UNSAFETY: The `IoUringSubmit` trait has an unsafe method for submitting SQEs,
+// and fixed buffers are manually allocated and registered with the kernel.
 #![expect(unsafe_code)]
 
 pub use squeue::Entry;
 
+use io_uring::IoUring;
 use io_uring::squeue;
+use parking_lot::Mutex;
+use slab::Slab;
+use std::alloc::Layout;
 use std::future::Future;
 use std::io;
+use std::ptr::NonNull;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
 
 /// Component trait for drivers that optionally support io-uring submission.
 ///
@@ -40,6 +48,14 @@ fn probe(&self, _opcode: u8) -> bool {
     unsafe fn submit(&self, _sqe: Entry) -> impl Future<Output = io::Result<i32>> + Send + '_ {
         (match *self {}) as std::future::Pending<_>
     }
+
+    unsafe fn submit_fixed<'a>(
+        &'a self,
+        _sqe: Entry,
+        _buf: &'a FixedBuffer,
+    ) -> impl Future<Output = io::Result<i32>> + Send + 'a {
+        (match *self {}) as std::future::Pending<_>
+    }
 }
 
 /// Trait for submitting io-uring operations.
@@ -92,4 +108,182 @@ pub trait IoUringSubmit: Send + Sync {
     /// }
     /// ```
     unsafe fn submit(&self, sqe: Entry) -> impl Future<Output = io::Result<i32>> + Send + '_;
+
+    /// Submits an io-uring SQE that references `buf` as a fixed buffer, i.e. a
+    /// `ReadFixed` or `WriteFixed` with `buf_index` set to
+    /// [`FixedBuffer::index`].
+    ///
+    /// `buf` is registered with the ring that the SQE is submitted to, if it
+    /// is not already. If the ring does not support registered buffers or
+    /// registration fails, the returned future fails with
+    /// [`io::ErrorKind::Unsupported`] without submitting the SQE.
+    ///
+    /// # Safety
+    ///
+    /// The same requirements as [`submit`](Self::submit) apply. In addition,
+    /// `buf` must be the only fixed buffer referenced by the SQE.
+    unsafe fn submit_fixed<'a>(
+        &'a self,
+        sqe: Entry,
+        buf: &'a FixedBuffer,
+    ) -> impl Future<Output = io::Result<i32>> + Send + 'a;
+}
+
+/// The maximum number of [`FixedBuffer`]s that can exist at once in the
+/// process. Each ring reserves a sparse registered buffer table of this size.
+pub const MAX_FIXED_BUFFERS: u32 = 1024;
+
+/// The allocated indexes in the process-wide fixed buffer table.
+static FIXED_BUFFER_INDEXES: Mutex<Slab<()>> = Mutex::new(Slab::new());
+
+/// Source of unique fixed buffer IDs, used by rings to detect when an index
+/// has been reused by a different buffer.
+static NEXT_FIXED_BUFFER_ID: AtomicU64 = AtomicU64::new(1);
+
+/// A page-aligned buffer for use with io-uring's `ReadFixed` and `WriteFixed`
+/// operations.
+///
+/// Each buffer is assigned an index in a process-wide table. Rings register
+/// the buffer at that same index on demand (see
+/// [`IoUringSubmit::submit_fixed`]), so the index is valid no matter which
+/// ring an IO is issued to.
+pub struct FixedBuffer {
+    ptr: NonNull<u8>,
+    len: usize,
+    index: u16,
+    id: u64,
+}
+
+// SAFETY: the buffer memory is exclusively owned, so it can be sent and shared
+// like a `Box<[u8]>`.
+unsafe impl Send for FixedBuffer {}
+// SAFETY: see above.
+unsafe impl Sync for FixedBuffer {}
+
+impl std::fmt::Debug for FixedBuffer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("FixedBuffer")
+            .field("len", &self.len)
+            .field("index", &self.index)
+            .finish()
+    }
+}
+
+impl FixedBuffer {
+    /// Allocates a zeroed fixed buffer of `len` bytes.
+    ///
+    /// Returns `None` if [`MAX_FIXED_BUFFERS`] buffers already exist.
+    pub fn new(len: usize) -> Option<Self> {
+        assert!(len > 0);
+        let index = {
+            let mut indexes = FIXED_BUFFER_INDEXES.lock();
+            if indexes.len() >= MAX_FIXED_BUFFERS as usize {
+                return None;
+            }
+            indexes.insert(())
+        };
+        let layout = Self::layout(len);
+        // SAFETY: the layout has a non-zero size.
+        let ptr = unsafe { std::alloc::alloc_zeroed(layout) };
+        let Some(ptr) = NonNull::new(ptr) else {
+            std::alloc::handle_alloc_error(layout)
+        };
+        Some(Self {
+            ptr,
+            len,
+            index: index as u16,
+            id: NEXT_FIXED_BUFFER_ID.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+
+    fn layout(len: usize) -> Layout {
+        Layout::from_size_align(len, 4096).unwrap()
+    }
+
+    /// The index to use as the SQE's `buf_index`.
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    /// Returns a pointer to the buffer, for use in building an SQE.
+    pub fn as_mut_ptr(&self) -> *mut u8 {
+        self.ptr.as_ptr()
+    }
+
+    /// Returns the buffer contents.
+    pub fn as_slice(&self) -> &[u8] {
+        // SAFETY: the buffer is valid for `len` bytes and initialized.
+        unsafe { std::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+
+    /// Returns the buffer contents, mutably.
+    pub fn as_mut_slice(&mut self) -> &mut [u8] {
+        // SAFETY: the buffer is valid for `len` bytes, initialized, and
+        // exclusively borrowed.
+        unsafe { std::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl Drop for FixedBuffer {
+    fn drop(&mut self) {
+        // Rings may still have the buffer registered at this index. This is
+        // harmless: the kernel holds its own references to the pinned pages,
+        // and a ring replaces the registration before the index is used for
+        // a different buffer, since the IDs will not match.
+        //
+        // SAFETY: the memory was allocated in `new` with the same layout.
+        unsafe { std::alloc::dealloc(self.ptr.as_ptr(), Self::layout(self.len)) };
+        FIXED_BUFFER_INDEXES.lock().remove(self.index.into());
+    }
+}
+
+/// Per-ring registration state for [`FixedBuffer`]s, for use by
+/// [`IoUringSubmit`] implementations.
+#[derive(Debug)]
+pub struct FixedBufferTable {
+    /// The ID of the buffer registered at each index, or `None` if the ring
+    /// has no registered buffer table.
+    registered: Option<Mutex<Vec<u64>>>,
+}
+
+impl FixedBufferTable {
+    /// Reserves a sparse registered buffer table of [`MAX_FIXED_BUFFERS`]
+    /// entries on `ring`.
+    ///
+    /// If the kernel does not support sparse buffer tables, the returned table
+    /// reports all buffers as unsupported.
+    pub fn new(ring: &IoUring) -> Self {
+        let registered = ring
+            .submitter()
+            .register_buffers_sparse(MAX_FIXED_BUFFERS)
+            .ok()
+            .map(|()| Mutex::new(vec![0; MAX_FIXED_BUFFERS as usize]));
+        Self { registered }
+    }
+
+    /// Registers `buf` with `ring` at [`FixedBuffer::index`], if it is not
+    /// already registered there.
+    ///
+    /// `ring` must be the ring this table was created for.
+    pub fn register(&self, ring: &IoUring, buf: &FixedBuffer) -> io::Result<()> {
+        let registered = self.registered.as_ref().ok_or(io::ErrorKind::Unsupported)?;
+        let mut registered = registered.lock();
+        let id = &mut registered[buf.index as usize];
+        if *id != buf.id {
+            let iovec = libc::iovec {
+                iov_base: buf.ptr.as_ptr().cast(),
+                iov_len: buf.len,
+            };
+            // SAFETY: the buffer is valid until it is dropped, and the kernel
+            // takes its own references to the pinned pages, so a stale
+            // registration never refers to freed memory.
+            unsafe {
+                ring.submitter()
+                    .register_buffers_update(buf.index.into(), &[iovec], None)
+            }
+            .map_err(|err| io::Error::new(io::ErrorKind::Unsupported, err))?;
+            *id = buf.id;
+        }
+        Ok(())
+    }
 }
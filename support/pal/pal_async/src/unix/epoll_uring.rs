@@ -24,6 +24,8 @@
 // intrusive completion tracking, and Pin projections.
 #![expect(unsafe_code)]
 
+use crate::io_uring::FixedBuffer;
+use crate::io_uring::FixedBufferTable;
 use crate::io_uring::IoUringSubmit;
 use crate::waker::WakerList;
 use io_uring::IoUring;
@@ -118,6 +120,9 @@ pub struct EpollIoUring {
     /// mutability). `probe()` and `submit()` use the submitter, which is
     /// a thread-safe syscall wrapper.
     ring: IoUring,
+    /// Registration state for fixed buffers. Registration uses the
+    /// submitter, so it is thread-safe.
+    fixed_buffers: FixedBufferTable,
     /// Wake event fd to signal the epoll thread. Set during creation.
     wake_fd: i32,
 }
@@ -158,6 +163,7 @@ pub(crate) fn new(epoll_fd: i32, wake_fd: i32) -> io::Result<Self> {
         Ok(Self {
             remote_queue: Mutex::new(VecDeque::new()),
             has_remote: AtomicBool::new(false),
+            fixed_buffers: FixedBufferTable::new(&ring),
             ring,
             wake_fd,
         })
@@ -375,6 +381,20 @@ unsafe fn submit(
             state: IoFutureState::Init { ring: self, sqe },
         }
     }
+
+    unsafe fn submit_fixed<'a>(
+        &'a self,
+        sqe: squeue::Entry,
+        buf: &'a FixedBuffer,
+    ) -> impl Future<Output = io::Result<i32>> + Send + 'a {
+        let registered = self.fixed_buffers.register(&self.ring, buf);
+        async move {
+            registered?;
+            // SAFETY: the caller guarantees the SQE only references memory
+            // that is valid for the lifetime of the returned future.
+            unsafe { self.submit(sqe) }.await
+        }
+    }
 }
 
 /// Future returned by [`EpollIoUring::submit`].
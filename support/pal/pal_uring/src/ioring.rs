@@ -10,6 +10,8 @@
 use pal::unix::SyscallResult;
 use pal::unix::affinity::CpuSet;
 use pal::unix::while_eintr;
+use pal_async::io_uring::FixedBuffer;
+use pal_async::io_uring::FixedBufferTable;
 use parking_lot::Mutex;
 use slab::Slab;
 use smallbox::smallbox;
@@ -69,6 +71,7 @@ struct RingInner {
     ring: IoUring,
     state: Mutex<RingState>,
     pending_io_count: AtomicUsize,
+    fixed_buffers: FixedBufferTable,
 }
 
 struct RingState {
@@ -163,13 +166,16 @@ impl IoRing {
     ///   number of outstanding I/Os, rather it's the maximum number of I/Os that the IoRing client
     ///   can allow to batch (either in the submission or completion paths).
     pub fn new(size: u32) -> Result<(IoRing, IoCompletionRing), io::Error> {
+        let ring = IoUring::builder().build(size)?;
+        let fixed_buffers = FixedBufferTable::new(&ring);
         let inner = Arc::new(RingInner {
-            ring: IoUring::builder().build(size)?,
+            ring,
             state: Mutex::new(RingState {
                 iocbs: Slab::new(),
                 queue: VecDeque::with_capacity(size as usize),
             }),
             pending_io_count: AtomicUsize::new(0),
+            fixed_buffers,
         });
 
         let this = IoRing {
@@ -368,6 +374,11 @@ pub fn probe(&self, opcode: u8) -> bool {
         probe.is_supported(opcode)
     }
 
+    /// Registers `buf` with this `IoRing`, if it is not already registered.
+    pub fn register_fixed_buffer(&self, buf: &FixedBuffer) -> io::Result<()> {
+        self.inner.fixed_buffers.register(&self.inner.ring, buf)
+    }
+
     /// Polls an IO for completion.
     ///
     /// If the IO is completed, returns the status and associated memory object.
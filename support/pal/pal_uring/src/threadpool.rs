@@ -21,6 +21,7 @@
 use io_uring::opcode;
 use io_uring::squeue;
 use loan_cell::LoanCell;
+use pal_async::io_uring::FixedBuffer;
 use pal_async::task::Runnable;
 use pal_async::task::Schedule;
 use pal_async::task::Scheduler;
@@ -382,6 +383,12 @@ pub fn probe(&self, opcode: u8) -> bool {
         self.client.worker.io_ring.probe(opcode)
     }
 
+    /// Registers `buf` with the ring, if it is not already registered, so that
+    /// it can be used by `ReadFixed` and `WriteFixed` IOs.
+    pub fn register_fixed_buffer(&self, buf: &FixedBuffer) -> io::Result<()> {
+        self.client.worker.io_ring.register_fixed_buffer(buf)
+    }
+
     /// Issues an IO described by `f`, referencing IO memory in `io_mem`.
     ///
     /// The submission queue entry for the IO is provided by `f` so that the IO
@@ -340,6 +340,17 @@ fn probe(&self, opcode: u8) -> bool {
         // valid for the lifetime of the returned future.
         unsafe { self.issue_io((), |_| sqe).await.0 }
     }
+
+    async unsafe fn submit_fixed(
+        &self,
+        sqe: squeue::Entry,
+        buf: &pal_async::io_uring::FixedBuffer,
+    ) -> io::Result<i32> {
+        self.register_fixed_buffer(buf)?;
+        // SAFETY: the caller guarantees the SQE only references memory that is
+        // valid for the lifetime of the returned future.
+        unsafe { self.issue_io((), |_| sqe).await.0 }
+    }
 }
 
 impl pal_async::io_uring::IoUringDriver for IoInitiator {
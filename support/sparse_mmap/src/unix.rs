@@ -500,6 +500,41 @@ fn new_memfd(_name: &str) -> io::Result<File> {
     }
 }
 
+/// Releases the host pages backing `address..address + len`.
+///
+/// Only host pages that lie entirely within the range are released. For
+/// shared memory and file mappings, the pages are removed from the backing
+/// object as well, so that memory is reclaimed even if it is mapped
+/// elsewhere. The range stays mapped; the next access sees zeroes.
+///
+/// # Safety
+/// The caller must ensure that the range is mapped and that nothing relies on
+/// its current contents.
+pub unsafe fn discard(address: *mut u8, len: usize) -> io::Result<()> {
+    let page_size = page_size();
+    let start = (address as usize).next_multiple_of(page_size);
+    let end = (address as usize + len) & !(page_size - 1);
+    if start >= end {
+        return Ok(());
+    }
+    let (address, len) = (start as *mut c_void, end - start);
+    // MADV_REMOVE punches a hole in the backing object, which is what frees
+    // memfd- and file-backed RAM. It fails with EINVAL for private anonymous
+    // mappings, where MADV_DONTNEED already releases the pages.
+    #[cfg(target_os = "linux")]
+    {
+        if unsafe { libc::madvise(address, len, libc::MADV_REMOVE) } == 0 {
+            return Ok(());
+        }
+        let err = Error::last_os_error();
+        if err.raw_os_error() != Some(libc::EINVAL) {
+            return Err(err);
+        }
+    }
+    unsafe { libc::madvise(address, len, libc::MADV_DONTNEED).syscall_result()? };
+    Ok(())
+}
+
 /// Allocates a mappable shared memory object of `size` bytes.
 ///
 /// `name` labels the memfd so it appears as `/memfd:<name>` in
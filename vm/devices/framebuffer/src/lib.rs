@@ -488,6 +488,9 @@ async fn unmap(&mut self) {
     async fn set_format(&mut self, format: FramebufferFormat) {
         self.set_format(format);
     }
+    fn memory(&self) -> io::Result<GuestMemory> {
+        FramebufferLocalControl::memory(self)
+    }
 }
 
 impl ResolveResource<FramebufferHandleKind, SharedFramebufferHandle> for FramebufferLocalControl {
@@ -0,0 +1,309 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The heartbeat IC.
+
+use crate::common::IcPipe;
+use crate::common::NegotiateState;
+use crate::common::Versions;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::stream::once;
+use futures_concurrency::stream::Merge;
+use guestmem::GuestMemory;
+use hyperv_ic_protocol::Status;
+use hyperv_ic_protocol::heartbeat as proto;
+use hyperv_ic_resources::heartbeat::HeartbeatRpc;
+use hyperv_ic_resources::heartbeat::HeartbeatStatus;
+use inspect::Inspect;
+use inspect::InspectMut;
+use pal_async::driver::Driver;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use std::pin::pin;
+use std::time::Duration;
+use task_control::Cancelled;
+use task_control::StopTask;
+use vmbus_channel::RawAsyncChannel;
+use vmbus_channel::bus::ChannelType;
+use vmbus_channel::bus::OfferParams;
+use vmbus_channel::channel::ChannelOpenError;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
+use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
+use vmbus_channel::simple::SimpleVmbusDevice;
+use vmcore::save_restore::NoSavedState;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+const HEARTBEAT_VERSIONS: &[hyperv_ic_protocol::Version] =
+    &[proto::HEARTBEAT_VERSION_1, proto::HEARTBEAT_VERSION_3];
+
+/// Send a heartbeat every second.
+const HEARTBEAT_PERIOD: Duration = Duration::from_secs(1);
+
+/// The guest has lost communication if it has not responded to a heartbeat
+/// for this long.
+const HEARTBEAT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Heartbeat IC device.
+#[derive(InspectMut)]
+pub struct HeartbeatIc {
+    #[inspect(skip)]
+    timer: PolledTimer,
+    #[inspect(skip)]
+    recv: mesh::Receiver<HeartbeatRpc>,
+}
+
+#[doc(hidden)]
+#[derive(InspectMut)]
+pub struct HeartbeatChannel {
+    #[inspect(mut)]
+    pipe: IcPipe,
+    state: ChannelState,
+    sequence_number: u64,
+    #[inspect(debug)]
+    application_state: proto::ApplicationState,
+    #[inspect(skip)]
+    last_response: Option<Instant>,
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ChannelState {
+    Negotiate(#[inspect(flatten)] NegotiateState),
+    Ready {
+        versions: Versions,
+        state: ReadyState,
+    },
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ReadyState {
+    SleepUntilNextHeartbeat {
+        #[inspect(skip)]
+        next_heartbeat: Instant,
+    },
+    SendHeartbeat,
+    WaitForResponse,
+}
+
+impl HeartbeatIc {
+    /// Returns a new heartbeat IC, using `recv` to receive status requests.
+    pub fn new(driver: &(impl Driver + ?Sized), recv: mesh::Receiver<HeartbeatRpc>) -> Self {
+        Self {
+            timer: PolledTimer::new(driver),
+            recv,
+        }
+    }
+}
+
+#[async_trait]
+impl SimpleVmbusDevice for HeartbeatIc {
+    type SavedState = NoSavedState;
+    type Runner = HeartbeatChannel;
+
+    fn offer(&self) -> OfferParams {
+        OfferParams {
+            interface_name: "heartbeat_ic".to_owned(),
+            instance_id: proto::INSTANCE_ID,
+            interface_id: proto::INTERFACE_ID,
+            channel_type: ChannelType::Pipe {
+                message_mode: true,
+                user_defined: Default::default(),
+                pipe_flags: Default::default(),
+            },
+            ..Default::default()
+        }
+    }
+
+    fn inspect(&mut self, req: inspect::Request<'_>, runner: Option<&mut Self::Runner>) {
+        req.respond().merge(self).merge(runner);
+    }
+
+    fn open(
+        &mut self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+        _guest_memory: GuestMemory,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        HeartbeatChannel::new(channel)
+    }
+
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        runner: &mut Self::Runner,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async {
+            match runner.process(self).await {
+                Ok(()) => {}
+                Err(err) => {
+                    tracing::error!(
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "heartbeat ic error"
+                    )
+                }
+            }
+        })
+        .await
+    }
+
+    fn supports_save_restore(
+        &mut self,
+    ) -> Option<
+        &mut dyn SaveRestoreSimpleVmbusDevice<SavedState = Self::SavedState, Runner = Self::Runner>,
+    > {
+        Some(self)
+    }
+}
+
+impl HeartbeatChannel {
+    fn new(channel: RawAsyncChannel<GpadlRingMem>) -> Result<Self, ChannelOpenError> {
+        let pipe = IcPipe::new(channel)?;
+        Ok(Self {
+            pipe,
+            state: ChannelState::Negotiate(NegotiateState::default()),
+            sequence_number: 0,
+            application_state: proto::ApplicationState::UNKNOWN,
+            last_response: None,
+        })
+    }
+
+    fn status(&self) -> HeartbeatStatus {
+        let Some(last_response) = self.last_response else {
+            return HeartbeatStatus::NoContact;
+        };
+        if Instant::now() - last_response > HEARTBEAT_TIMEOUT {
+            return HeartbeatStatus::LostCommunication;
+        }
+        match self.application_state {
+            proto::ApplicationState::CRITICAL | proto::ApplicationState::STOPPED => {
+                HeartbeatStatus::ApplicationCritical
+            }
+            _ => HeartbeatStatus::Ok,
+        }
+    }
+
+    async fn process(&mut self, ic: &mut HeartbeatIc) -> anyhow::Result<()> {
+        enum Event {
+            StateMachine(anyhow::Result<()>),
+            Request(HeartbeatRpc),
+        }
+
+        loop {
+            let event = pin!(
+                (
+                    once(
+                        self.process_state_machine(&mut ic.timer)
+                            .map(Event::StateMachine)
+                    ),
+                    (&mut ic.recv).map(Event::Request),
+                )
+                    .merge()
+            )
+            .next()
+            .await
+            .unwrap();
+            match event {
+                Event::StateMachine(r) => {
+                    r?;
+                }
+                Event::Request(req) => match req {
+                    HeartbeatRpc::GetStatus(rpc) => rpc.complete(self.status()),
+                },
+            }
+        }
+    }
+
+    async fn process_state_machine(&mut self, timer: &mut PolledTimer) -> anyhow::Result<()> {
+        match self.state {
+            ChannelState::Negotiate(ref mut state) => {
+                if let Some(versions) = self.pipe.negotiate(state, HEARTBEAT_VERSIONS).await? {
+                    tracelimit::info_ratelimited!(
+                        framework = %versions.framework_version,
+                        version = %versions.message_version,
+                        "heartbeat versions negotiated"
+                    );
+                    // Count the negotiation as the first sign of life, so that
+                    // the guest is not reported as lost before it has had a
+                    // chance to answer the first heartbeat.
+                    self.last_response = Some(Instant::now());
+                    self.state = ChannelState::Ready {
+                        versions,
+                        state: ReadyState::SendHeartbeat,
+                    };
+                }
+            }
+            ChannelState::Ready {
+                ref versions,
+                ref mut state,
+            } => match *state {
+                ReadyState::SleepUntilNextHeartbeat { next_heartbeat } => {
+                    timer.sleep_until(next_heartbeat).await;
+                    *state = ReadyState::SendHeartbeat;
+                }
+                ReadyState::SendHeartbeat => {
+                    let message = proto::HeartbeatMessage {
+                        sequence_number: self.sequence_number,
+                        application_state: proto::ApplicationState::UNKNOWN,
+                        reserved: [0; 4],
+                    };
+                    self.pipe
+                        .write_message(
+                            versions,
+                            hyperv_ic_protocol::MessageType::HEARTBEAT,
+                            hyperv_ic_protocol::HeaderFlags::new()
+                                .with_request(true)
+                                .with_transaction(true),
+                            message.as_bytes(),
+                        )
+                        .await?;
+                    *state = ReadyState::WaitForResponse;
+                }
+                ReadyState::WaitForResponse => {
+                    let (status, buf) = self.pipe.read_response().await?;
+                    if status != Status::SUCCESS {
+                        anyhow::bail!("heartbeat failed with status {:#x}", status.0);
+                    }
+                    let (message, _) = proto::HeartbeatMessage::read_from_prefix(buf)
+                        .ok()
+                        .context("missing heartbeat message")?;
+                    // The guest acknowledges a heartbeat by incrementing its
+                    // sequence number.
+                    if message.sequence_number != self.sequence_number.wrapping_add(1) {
+                        tracelimit::warn_ratelimited!(
+                            expected = self.sequence_number.wrapping_add(1),
+                            actual = message.sequence_number,
+                            "unexpected heartbeat sequence number"
+                        );
+                    }
+                    self.sequence_number = message.sequence_number;
+                    self.application_state = message.application_state;
+                    self.last_response = Some(Instant::now());
+                    *state = ReadyState::SleepUntilNextHeartbeat {
+                        next_heartbeat: Instant::now() + HEARTBEAT_PERIOD,
+                    };
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+// Like the timesync IC, no state is saved. On restore, the channel starts over
+// with version negotiation, and the guest's liveness is tracked afresh.
+impl SaveRestoreSimpleVmbusDevice for HeartbeatIc {
+    fn save_open(&mut self, _runner: &Self::Runner) -> Self::SavedState {
+        NoSavedState
+    }
+
+    fn restore_open(
+        &mut self,
+        NoSavedState: Self::SavedState,
+        channel: RawAsyncChannel<GpadlRingMem>,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        HeartbeatChannel::new(channel)
+    }
+}
@@ -0,0 +1,320 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The VSS IC, used to quiesce the guest's filesystems for application
+//! consistent snapshots.
+
+use crate::common::IcPipe;
+use crate::common::NegotiateState;
+use crate::common::Versions;
+use async_trait::async_trait;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::stream::once;
+use futures_concurrency::stream::Merge;
+use hyperv_ic_protocol::Status;
+use hyperv_ic_protocol::vss as proto;
+use hyperv_ic_resources::vss::VssResult;
+use hyperv_ic_resources::vss::VssRpc;
+use inspect::Inspect;
+use inspect::InspectMut;
+use mesh::rpc::Rpc;
+use std::pin::pin;
+use task_control::Cancelled;
+use task_control::StopTask;
+use vmbus_channel::RawAsyncChannel;
+use vmbus_channel::bus::ChannelType;
+use vmbus_channel::bus::OfferParams;
+use vmbus_channel::channel::ChannelOpenError;
+use vmbus_channel::gpadl_ring::GpadlRingMem;
+use vmbus_channel::simple::SaveRestoreSimpleVmbusDevice;
+use vmbus_channel::simple::SimpleVmbusDevice;
+use zerocopy::IntoBytes;
+
+/// Freeze and thaw require message version 5.0.
+const VSS_VERSIONS: &[hyperv_ic_protocol::Version] = &[proto::VSS_VERSION_WINBLUE];
+
+/// A VSS IC device.
+#[derive(InspectMut)]
+pub struct VssIc {
+    #[inspect(skip)]
+    recv: mesh::Receiver<VssRpc>,
+}
+
+#[doc(hidden)]
+#[derive(InspectMut)]
+pub struct VssChannel {
+    #[inspect(mut)]
+    pipe: IcPipe,
+    state: ChannelState,
+    /// Whether the guest's filesystems are currently frozen.
+    frozen: bool,
+    #[inspect(with = "|x| x.len()")]
+    pending_requests: Vec<Rpc<(), VssResult>>,
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ChannelState {
+    Negotiate(#[inspect(flatten)] NegotiateState),
+    Ready {
+        versions: Versions,
+        state: ReadyState,
+    },
+}
+
+#[derive(Inspect)]
+#[inspect(external_tag)]
+enum ReadyState {
+    Ready,
+    SendRequest(#[inspect(debug)] proto::Operation),
+    WaitResponse(#[inspect(debug)] proto::Operation),
+}
+
+impl VssIc {
+    /// Returns a new VSS IC, using `recv` to receive freeze and thaw requests.
+    pub fn new(recv: mesh::Receiver<VssRpc>) -> Self {
+        Self { recv }
+    }
+}
+
+impl VssChannel {
+    fn new(
+        channel: RawAsyncChannel<GpadlRingMem>,
+        frozen: bool,
+    ) -> Result<VssChannel, ChannelOpenError> {
+        let pipe = IcPipe::new(channel)?;
+        Ok(Self {
+            pipe,
+            state: ChannelState::Negotiate(NegotiateState::default()),
+            frozen,
+            pending_requests: Vec::new(),
+        })
+    }
+
+    async fn process(&mut self, ic: &mut VssIc) -> anyhow::Result<()> {
+        enum Event {
+            StateMachine(anyhow::Result<()>),
+            Request(VssRpc),
+        }
+
+        loop {
+            let event = pin!(
+                (
+                    once(self.process_state_machine().map(Event::StateMachine)),
+                    (&mut ic.recv).map(Event::Request),
+                )
+                    .merge()
+            )
+            .next()
+            .await
+            .unwrap();
+            match event {
+                Event::StateMachine(r) => {
+                    r?;
+                }
+                Event::Request(req) => {
+                    let (operation, rpc) = match req {
+                        VssRpc::Freeze(rpc) => (proto::Operation::FREEZE_APPLICATIONS, rpc),
+                        VssRpc::Thaw(rpc) => (proto::Operation::THAW_APPLICATIONS, rpc),
+                    };
+                    match self.state {
+                        ChannelState::Negotiate(_) => rpc.complete(VssResult::NotReady),
+                        ChannelState::Ready { ref mut state, .. } => match *state {
+                            ReadyState::Ready => {
+                                // Freeze and thaw are idempotent, so there is
+                                // no need to bother the guest if it is already
+                                // in the requested state.
+                                if self.frozen
+                                    == (operation == proto::Operation::FREEZE_APPLICATIONS)
+                                {
+                                    rpc.complete(VssResult::Ok);
+                                } else {
+                                    self.pending_requests.push(rpc);
+                                    *state = ReadyState::SendRequest(operation);
+                                }
+                            }
+                            // Join a request for the same operation that is
+                            // already in flight.
+                            ReadyState::SendRequest(current)
+                            | ReadyState::WaitResponse(current)
+                                if current == operation =>
+                            {
+                                self.pending_requests.push(rpc);
+                            }
+                            ReadyState::SendRequest(_) | ReadyState::WaitResponse(_) => {
+                                rpc.complete(VssResult::AlreadyInProgress)
+                            }
+                        },
+                    }
+                }
+            }
+        }
+    }
+
+    async fn process_state_machine(&mut self) -> anyhow::Result<()> {
+        match self.state {
+            ChannelState::Negotiate(ref mut state) => {
+                if let Some(versions) = self.pipe.negotiate(state, VSS_VERSIONS).await? {
+                    // If the guest was frozen when the VM was saved, then it
+                    // has been restored from an application consistent
+                    // snapshot. Thaw it now that it is listening again.
+                    let state = if self.frozen {
+                        ReadyState::SendRequest(proto::Operation::THAW_APPLICATIONS)
+                    } else {
+                        ReadyState::Ready
+                    };
+                    self.state = ChannelState::Ready { versions, state };
+                }
+            }
+            ChannelState::Ready {
+                ref mut state,
+                ref versions,
+            } => match *state {
+                ReadyState::Ready => std::future::pending().await,
+                ReadyState::SendRequest(operation) => {
+                    let message = proto::VssMessage {
+                        header: proto::VssHeader {
+                            operation,
+                            reserved: [0; 7],
+                        },
+                        reserved: [0; 7],
+                        data: [0; 24],
+                    };
+
+                    self.pipe
+                        .write_message(
+                            versions,
+                            hyperv_ic_protocol::MessageType::VSS,
+                            hyperv_ic_protocol::HeaderFlags::new()
+                                .with_transaction(true)
+                                .with_request(true),
+                            message.as_bytes(),
+                        )
+                        .await?;
+
+                    *state = ReadyState::WaitResponse(operation);
+                }
+                ReadyState::WaitResponse(operation) => {
+                    let (status, _) = self.pipe.read_response().await?;
+                    let result = if status == Status::SUCCESS {
+                        self.frozen = operation == proto::Operation::FREEZE_APPLICATIONS;
+                        VssResult::Ok
+                    } else {
+                        tracelimit::warn_ratelimited!(
+                            ?operation,
+                            status = status.0,
+                            "vss request failed"
+                        );
+                        VssResult::Failed(status.0)
+                    };
+                    for rpc in self.pending_requests.drain(..) {
+                        rpc.complete(result.clone());
+                    }
+                    *state = ReadyState::Ready;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SimpleVmbusDevice for VssIc {
+    type SavedState = save_restore::state::SavedState;
+    type Runner = VssChannel;
+
+    fn offer(&self) -> OfferParams {
+        OfferParams {
+            interface_name: "vss_ic".to_owned(),
+            instance_id: proto::INSTANCE_ID,
+            interface_id: proto::INTERFACE_ID,
+            channel_type: ChannelType::Pipe {
+                message_mode: true,
+                user_defined: Default::default(),
+                pipe_flags: Default::default(),
+            },
+            ..Default::default()
+        }
+    }
+
+    fn inspect(&mut self, req: inspect::Request<'_>, runner: Option<&mut Self::Runner>) {
+        req.respond().merge(self).merge(runner);
+    }
+
+    fn open(
+        &mut self,
+        channel: RawAsyncChannel<GpadlRingMem>,
+        _guest_memory: guestmem::GuestMemory,
+    ) -> Result<Self::Runner, ChannelOpenError> {
+        VssChannel::new(channel, false)
+    }
+
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        runner: &mut Self::Runner,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async {
+            match runner.process(self).await {
+                Ok(()) => {}
+                Err(err) => {
+                    tracing::error!(
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "vss ic error"
+                    )
+                }
+            }
+        })
+        .await
+    }
+
+    fn supports_save_restore(
+        &mut self,
+    ) -> Option<
+        &mut dyn SaveRestoreSimpleVmbusDevice<SavedState = Self::SavedState, Runner = Self::Runner>,
+    > {
+        Some(self)
+    }
+}
+
+mod save_restore {
+    use super::*;
+
+    pub mod state {
+        use mesh::payload::Protobuf;
+        use vmcore::save_restore::SavedStateRoot;
+
+        #[derive(Protobuf, SavedStateRoot)]
+        #[mesh(package = "vss_ic")]
+        pub struct SavedState {
+            /// Whether the guest's filesystems were frozen at save time.
+            #[mesh(1)]
+            pub frozen: bool,
+        }
+    }
+
+    // The channel always renegotiates after a restore. Any request in flight
+    // at save time is dropped, since its requester does not survive the
+    // restore; only the frozen state is kept, so that the guest can be thawed.
+    impl SaveRestoreSimpleVmbusDevice for VssIc {
+        fn save_open(&mut self, runner: &Self::Runner) -> state::SavedState {
+            let frozen = match runner.state {
+                ChannelState::Ready {
+                    state: ReadyState::SendRequest(operation) | ReadyState::WaitResponse(operation),
+                    ..
+                } => operation == proto::Operation::FREEZE_APPLICATIONS || runner.frozen,
+                _ => runner.frozen,
+            };
+            state::SavedState { frozen }
+        }
+
+        fn restore_open(
+            &mut self,
+            saved_state: Self::SavedState,
+            channel: RawAsyncChannel<GpadlRingMem>,
+        ) -> Result<Self::Runner, ChannelOpenError> {
+            VssChannel::new(channel, saved_state.frozen)
+        }
+    }
+}
@@ -2,12 +2,25 @@
 // Licensed under the MIT License.
 
 //! Heartbeat component protocol.
+
+use crate::Version;
+use guid::Guid;
 use open_enum::open_enum;
 use zerocopy::FromBytes;
 use zerocopy::Immutable;
 use zerocopy::IntoBytes;
 use zerocopy::KnownLayout;
 
+/// The unique vmbus interface ID of the heartbeat IC.
+pub const INTERFACE_ID: Guid = guid::guid!("57164f39-9115-4e78-ab55-382f3bd5422d");
+/// The unique vmbus instance ID of the heartbeat IC.
+pub const INSTANCE_ID: Guid = guid::guid!("fd149e91-82e0-4a7d-afa6-2a4166cbd7c0");
+
+/// Version 1.0.
+pub const HEARTBEAT_VERSION_1: Version = Version::new(1, 0);
+/// Version 3.0.
+pub const HEARTBEAT_VERSION_3: Version = Version::new(3, 0);
+
 /// Heartbeat message from guest to host.
 #[repr(C)]
 #[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
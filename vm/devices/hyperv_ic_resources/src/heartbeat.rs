@@ -0,0 +1,44 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource definitions for the heartbeat IC.
+
+use mesh::MeshPayload;
+use mesh::rpc::Rpc;
+use vm_resource::ResourceId;
+use vm_resource::kind::VmbusDeviceHandleKind;
+
+/// A handle to a heartbeat IC.
+#[derive(MeshPayload)]
+pub struct HeartbeatIcHandle {
+    /// The channel by which to receive heartbeat requests.
+    pub recv: mesh::Receiver<HeartbeatRpc>,
+}
+
+impl ResourceId<VmbusDeviceHandleKind> for HeartbeatIcHandle {
+    const ID: &'static str = "heartbeat_ic";
+}
+
+/// An RPC request to the heartbeat IC.
+#[derive(MeshPayload)]
+pub enum HeartbeatRpc {
+    /// Get the guest's heartbeat status.
+    ///
+    /// This is only answered while the guest has the heartbeat channel open,
+    /// so callers should use a timeout.
+    GetStatus(Rpc<(), HeartbeatStatus>),
+}
+
+/// The health of the guest, as reported by the heartbeat IC.
+#[derive(MeshPayload, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeartbeatStatus {
+    /// The guest has not finished connecting to the heartbeat IC.
+    NoContact,
+    /// The guest is responding to heartbeats.
+    Ok,
+    /// The guest is responding to heartbeats, but reports that an application
+    /// is in a critical state.
+    ApplicationCritical,
+    /// The guest has stopped responding to heartbeats.
+    LostCommunication,
+}
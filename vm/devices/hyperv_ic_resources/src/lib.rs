@@ -5,6 +5,8 @@
 
 #![forbid(unsafe_code)]
 
+pub mod heartbeat;
 pub mod kvp;
 pub mod shutdown;
 pub mod timesync;
+pub mod vss;
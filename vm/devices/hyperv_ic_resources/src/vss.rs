@@ -0,0 +1,48 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource definitions for the VSS IC.
+
+use mesh::MeshPayload;
+use mesh::rpc::Rpc;
+use vm_resource::ResourceId;
+use vm_resource::kind::VmbusDeviceHandleKind;
+
+/// A handle to a VSS IC.
+#[derive(MeshPayload)]
+pub struct VssIcHandle {
+    /// The channel by which to receive VSS requests.
+    pub recv: mesh::Receiver<VssRpc>,
+}
+
+impl ResourceId<VmbusDeviceHandleKind> for VssIcHandle {
+    const ID: &'static str = "vss_ic";
+}
+
+/// An RPC request to the VSS IC.
+#[derive(MeshPayload)]
+pub enum VssRpc {
+    /// Ask the guest to flush and freeze its filesystems.
+    ///
+    /// Completes once the guest has frozen its filesystems. The guest stays
+    /// frozen until [`VssRpc::Thaw`] is sent.
+    Freeze(Rpc<(), VssResult>),
+    /// Ask the guest to thaw its filesystems after a freeze.
+    ///
+    /// Completes immediately with [`VssResult::Ok`] if the guest is not
+    /// frozen, and joins any thaw that is already in progress.
+    Thaw(Rpc<(), VssResult>),
+}
+
+/// The result of a VSS request.
+#[derive(MeshPayload, Debug, Clone, PartialEq)]
+pub enum VssResult {
+    /// The guest completed the request.
+    Ok,
+    /// The IC is not ready to send VSS requests.
+    NotReady,
+    /// Another VSS request is already in progress.
+    AlreadyInProgress,
+    /// The guest failed the request with the given error code.
+    Failed(u32),
+}
@@ -39,6 +39,8 @@
 pub mod null;
 pub mod resolve;
 pub mod tests;
+#[cfg(target_os = "linux")]
+pub mod vhost;
 
 use async_trait::async_trait;
 use bitfield_struct::bitfield;
@@ -143,6 +145,15 @@ fn link_speed(&self) -> u64 {
         // can overwrite.
         10 * 1000 * 1000 * 1000
     }
+
+    /// Takes the endpoint's kernel virtio-net datapath, if it has one.
+    ///
+    /// A virtio-net frontend that takes the datapath hands its data virtqueues
+    /// to the kernel and must not call [`get_queues`](Self::get_queues).
+    #[cfg(target_os = "linux")]
+    fn take_vhost_net(&mut self) -> Option<Box<dyn vhost::VhostNet>> {
+        None
+    }
 }
 
 /// Multi-queue related support.
@@ -173,7 +184,29 @@ pub struct TxOffloadSupport {
 pub struct RssConfig<'a> {
     pub key: &'a [u8],
     pub indirection_table: &'a [u16],
-    pub flags: u32, // TODO
+    pub flags: RssFlags,
+}
+
+/// The packet types whose headers feed the RSS hash.
+///
+/// If none are set, the backend chooses which packet types to hash.
+#[bitfield(u32)]
+#[derive(PartialEq, Eq)]
+pub struct RssFlags {
+    /// Hash the source and destination addresses of IPv4 packets.
+    pub ipv4: bool,
+    /// Also hash the ports of IPv4 TCP packets.
+    pub tcp_ipv4: bool,
+    /// Also hash the ports of IPv4 UDP packets.
+    pub udp_ipv4: bool,
+    /// Hash the source and destination addresses of IPv6 packets.
+    pub ipv6: bool,
+    /// Also hash the ports of IPv6 TCP packets.
+    pub tcp_ipv6: bool,
+    /// Also hash the ports of IPv6 UDP packets.
+    pub udp_ipv6: bool,
+    #[bits(26)]
+    _reserved: u32,
 }
 
 #[derive(Error, Debug)]
@@ -0,0 +1,80 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Kernel (Linux vhost-net) datapaths for virtio-net frontends.
+//!
+//! An endpoint backed by a kernel network device can offer a [`VhostNet`]
+//! datapath via [`Endpoint::take_vhost_net`](crate::Endpoint::take_vhost_net).
+//! A virtio-net frontend that takes it hands its receive and transmit
+//! virtqueues to the kernel, which then moves packets between guest memory and
+//! the device without the VMM in the per-packet path. The frontend keeps
+//! ownership of the virtio transport, configuration space, and guest memory
+//! mappings.
+//!
+//! A datapath handles a single queue pair, with no control queue. Frontends
+//! reject configurations that request more queue pairs.
+
+use std::os::fd::BorrowedFd;
+
+/// The virtio-net receive queue index within a queue pair.
+pub const RX_QUEUE: u16 = 0;
+/// The virtio-net transmit queue index within a queue pair.
+pub const TX_QUEUE: u16 = 1;
+
+/// A region of guest RAM mapped into the VMM process.
+#[derive(Debug, Copy, Clone)]
+pub struct VhostMemoryRegion {
+    /// The guest physical address of the region.
+    pub guest_address: u64,
+    /// The size of the region in bytes.
+    pub size: u64,
+    /// The VMM virtual address the region is mapped at.
+    pub host_address: u64,
+}
+
+/// The configuration of a virtqueue handed to the kernel.
+///
+/// Ring addresses are VMM virtual addresses within the regions passed to
+/// [`VhostNet::set_memory`].
+pub struct VhostQueue<'a> {
+    /// The queue size.
+    pub size: u16,
+    /// The initial ring state, in `VHOST_SET_VRING_BASE` encoding.
+    pub base: u32,
+    /// The descriptor table address.
+    pub desc_addr: u64,
+    /// The available (driver) ring address.
+    pub avail_addr: u64,
+    /// The used (device) ring address.
+    pub used_addr: u64,
+    /// The event signaled when the guest notifies the queue.
+    pub kick: BorrowedFd<'a>,
+    /// The event the kernel signals to interrupt the guest.
+    pub call: BorrowedFd<'a>,
+}
+
+/// A kernel virtio-net datapath for a single queue pair.
+pub trait VhostNet: Send + Sync {
+    /// Returns the virtio feature bits (ring layout, notification, and
+    /// version features) the kernel datapath supports.
+    fn features(&self) -> u64;
+
+    /// Sets the features negotiated with the guest.
+    ///
+    /// `features` is the full set of negotiated virtio-net features. The
+    /// implementation passes the datapath features to the kernel and
+    /// configures the device's receive offloads to match the guest's.
+    fn set_features(&mut self, features: u64) -> anyhow::Result<()>;
+
+    /// Sets the guest memory layout used to translate ring and buffer
+    /// addresses.
+    fn set_memory(&mut self, regions: &[VhostMemoryRegion]) -> anyhow::Result<()>;
+
+    /// Starts the kernel processing queue `index` ([`RX_QUEUE`] or
+    /// [`TX_QUEUE`]).
+    fn start_queue(&mut self, index: u16, queue: VhostQueue<'_>) -> anyhow::Result<()>;
+
+    /// Stops the kernel processing queue `index`, returning its ring state in
+    /// `VHOST_GET_VRING_BASE` encoding.
+    fn stop_queue(&mut self, index: u16) -> anyhow::Result<u32>;
+}
@@ -32,7 +32,7 @@ pub mod consomme {
     use vm_resource::kind::NetEndpointHandleKind;
 
     /// Protocol for host port forwarding.
-    #[derive(Clone, Debug, MeshPayload)]
+    #[derive(Clone, Debug, PartialEq, Eq, MeshPayload)]
     pub enum HostPortProtocol {
         /// TCP protocol.
         Tcp,
@@ -41,7 +41,7 @@ pub enum HostPortProtocol {
     }
 
     /// An IP address, suitable for serialization via mesh.
-    #[derive(Clone, Debug, MeshPayload)]
+    #[derive(Clone, Debug, PartialEq, Eq, MeshPayload)]
     pub enum HostIpAddress {
         /// IPv4 address.
         Ipv4(std::net::Ipv4Addr),
@@ -90,13 +90,55 @@ pub struct HostPortConfig {
         pub guest_port: u16,
     }
 
-    /// A runtime request to bind or unbind a port on a running Consomme endpoint.
+    /// A port currently forwarded from the host into the guest.
+    #[derive(Debug, MeshPayload)]
+    pub struct PortForwardInfo {
+        /// The forwarded protocol.
+        pub protocol: HostPortProtocol,
+        /// The host IP address the forward is listening on.
+        pub host_address: HostIpAddress,
+        /// The host port the forward is listening on.
+        pub host_port: u16,
+        /// The guest port traffic is forwarded to.
+        pub guest_port: u16,
+    }
+
+    /// A rule in a Consomme egress policy.
+    #[derive(Clone, Debug, PartialEq, Eq, MeshPayload)]
+    pub struct EgressRuleConfig {
+        /// Whether matching flows are allowed. Otherwise, they are denied.
+        pub allow: bool,
+        /// The protocol to match, or `None` for both TCP and UDP.
+        pub protocol: Option<HostPortProtocol>,
+        /// The destination network address and prefix length to match, or
+        /// `None` for any destination.
+        pub destination: Option<(HostIpAddress, u8)>,
+        /// The inclusive range of destination ports to match, or `None` for
+        /// any port.
+        pub ports: Option<(u16, u16)>,
+    }
+
+    /// Policy for TCP and UDP flows initiated by the guest.
+    #[derive(Clone, Debug, PartialEq, Eq, MeshPayload)]
+    pub struct EgressPolicyConfig {
+        /// Rules, evaluated in order. The first matching rule applies.
+        pub rules: Vec<EgressRuleConfig>,
+        /// Whether flows that match no rule are allowed.
+        pub default_allow: bool,
+    }
+
+    /// A runtime request to a running Consomme endpoint.
     #[derive(MeshPayload)]
     pub enum ConsommeRequest {
         /// Bind a host port to forward traffic to the guest.
         Bind(mesh::rpc::FailableRpc<HostPortConfig, ()>),
         /// Unbind a previously forwarded port.
         Unbind(mesh::rpc::FailableRpc<HostPortConfig, ()>),
+        /// List the currently forwarded ports.
+        List(mesh::rpc::Rpc<(), Vec<PortForwardInfo>>),
+        /// Replace the egress policy. Open TCP connections initiated by the
+        /// guest that the new policy denies are reset.
+        SetEgressPolicy(mesh::rpc::FailableRpc<EgressPolicyConfig, ()>),
     }
 
     /// Handle to a Consomme network endpoint.
@@ -108,6 +150,14 @@ pub struct ConsommeHandle {
         pub ports: Vec<HostPortConfig>,
         /// Optional channel for runtime port bind/unbind after the endpoint starts.
         pub recv: Option<mesh::Receiver<ConsommeRequest>>,
+        /// Host directory to serve read-only over TFTP on the gateway address,
+        /// for network boot.
+        pub tftp_root: Option<String>,
+        /// Boot file name to advertise over DHCP, relative to `tftp_root`.
+        pub boot_file: Option<String>,
+        /// Policy for flows initiated by the guest, or `None` to allow all
+        /// flows.
+        pub egress_policy: Option<EgressPolicyConfig>,
     }
 
     impl ResourceId<NetEndpointHandleKind> for ConsommeHandle {
@@ -115,7 +165,99 @@ impl ResourceId<NetEndpointHandleKind> for ConsommeHandle {
     }
 }
 
+/// Software Ethernet switch backend.
+pub mod switch {
+    use mesh::MeshPayload;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::NetEndpointHandleKind;
+
+    /// A request to a software Ethernet switch.
+    #[derive(MeshPayload)]
+    pub enum SwitchRequest {
+        /// Attaches a new port to the switch. Completes once the port is
+        /// attached, so that frames sent afterwards are forwarded to it.
+        Connect(mesh::rpc::FailableRpc<SwitchPortConnection, ()>),
+    }
+
+    /// The channels for a port on a software Ethernet switch.
+    ///
+    /// Frames are complete Ethernet frames, without an FCS. The port is
+    /// detached when `from_port` is closed.
+    #[derive(MeshPayload)]
+    pub struct SwitchPortConnection {
+        /// The access VLAN of the port. Frames from the port are placed on
+        /// this VLAN and are delivered to it untagged. If `None`, the port is a
+        /// trunk port: untagged frames are on the untagged network, and
+        /// 802.1Q-tagged frames keep their tags.
+        pub vlan: Option<u16>,
+        /// Frames sent by the port.
+        pub from_port: mesh::Receiver<Vec<u8>>,
+        /// Frames delivered to the port.
+        pub to_port: mesh::Sender<Vec<u8>>,
+    }
+
+    /// Handle to an endpoint attached to a software Ethernet switch.
+    #[derive(MeshPayload)]
+    pub struct SwitchHandle {
+        /// The switch to attach to.
+        pub switch: mesh::Sender<SwitchRequest>,
+        /// The access VLAN of the port, or `None` for a trunk port. See
+        /// [`SwitchPortConnection::vlan`].
+        pub vlan: Option<u16>,
+    }
+
+    impl ResourceId<NetEndpointHandleKind> for SwitchHandle {
+        const ID: &'static str = "switch";
+    }
+}
+
 /// Windows vmswitch DirectIO backend.
+/// Network impairment resources.
+pub mod impair {
+    use mesh::MeshPayload;
+    use std::time::Duration;
+    use vm_resource::Resource;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::NetEndpointHandleKind;
+
+    /// Impairments applied to the packets of a network endpoint.
+    ///
+    /// Each impairment applies independently to each direction of each queue.
+    /// Probabilities are in parts per million.
+    #[derive(Clone, Debug, Default, PartialEq, Eq, MeshPayload)]
+    pub struct ImpairmentConfig {
+        /// The delay added to each packet.
+        pub delay: Duration,
+        /// The maximum random variation of the delay, in either direction.
+        pub jitter: Duration,
+        /// The probability that a packet is dropped.
+        pub loss_ppm: u32,
+        /// The probability that a packet is delivered twice.
+        pub duplicate_ppm: u32,
+        /// The probability that a packet skips the delay, overtaking packets
+        /// that are still delayed.
+        pub reorder_ppm: u32,
+        /// The bandwidth cap in bits per second, or zero for no cap.
+        pub rate: u64,
+        /// The seed for the random choices, so that runs are reproducible.
+        pub seed: u64,
+    }
+
+    /// Handle to an endpoint that impairs the packets of another endpoint.
+    #[derive(MeshPayload)]
+    pub struct ImpairedHandle {
+        /// The endpoint to impair.
+        pub endpoint: Resource<NetEndpointHandleKind>,
+        /// The initial impairments. These can be changed at runtime through
+        /// inspect.
+        pub config: ImpairmentConfig,
+    }
+
+    impl ResourceId<NetEndpointHandleKind> for ImpairedHandle {
+        const ID: &'static str = "impair";
+    }
+}
+
 pub mod dio {
     use guid::Guid;
     use mesh::MeshPayload;
@@ -156,6 +298,10 @@ pub struct TapHandle {
         /// A pre-opened TAP file descriptor, configured with
         /// `IFF_TAP | IFF_NO_PI | IFF_VNET_HDR`.
         pub fd: std::os::fd::OwnedFd,
+        /// An optional pre-opened `/dev/vhost-net` fd. When set and the
+        /// frontend is virtio-net, the frontend hands its virtqueues to the
+        /// kernel instead of processing packets in userspace.
+        pub vhost: Option<std::os::fd::OwnedFd>,
     }
 
     impl ResourceId<NetEndpointHandleKind> for TapHandle {
@@ -10,6 +10,7 @@
 use smoltcp::phy::ChecksumCapabilities;
 use smoltcp::wire::DHCP_MAX_DNS_SERVER_COUNT;
 use smoltcp::wire::DhcpMessageType;
+use smoltcp::wire::DhcpOption;
 use smoltcp::wire::DhcpPacket;
 use smoltcp::wire::DhcpRepr;
 use smoltcp::wire::EthernetAddress;
@@ -28,6 +29,9 @@
 pub const DHCP_CLIENT: u16 = 68;
 // RFC 1542 section 2.1 requires every BOOTP message to be at least 300 octets.
 const BOOTP_MIN_MESSAGE_LEN: usize = 300;
+// The BOOTP `file` field, which older PXE clients read instead of option 67.
+const BOOTP_FILE: std::ops::Range<usize> = 108..236;
+const DHCP_OPT_BOOTFILE_NAME: u8 = 67;
 
 impl<T: Client> Access<'_, T> {
     pub(crate) fn handle_dhcp(&mut self, payload: &[u8]) -> Result<(), DropReason> {
@@ -94,6 +98,22 @@ pub(crate) fn handle_dhcp(&mut self, payload: &[u8]) -> Result<(), DropReason> {
                 .take(DHCP_MAX_DNS_SERVER_COUNT),
         );
 
+        // Point network boot clients at the TFTP server on the gateway.
+        let params = &self.inner.state.params;
+        let boot_server = if params.tftp_root.is_some() {
+            gateway_ip
+        } else {
+            Ipv4Address::UNSPECIFIED
+        };
+        let boot_file = your_ip
+            .and(params.boot_file.as_deref())
+            .map(str::as_bytes)
+            .filter(|name| name.len() <= u8::MAX.into());
+        let boot_file_option = boot_file.map(|name| DhcpOption {
+            kind: DHCP_OPT_BOOTFILE_NAME,
+            data: name,
+        });
+
         let resp_dhcp = if let Some(your_ip) = your_ip {
             DhcpRepr {
                 message_type,
@@ -102,7 +122,7 @@ pub(crate) fn handle_dhcp(&mut self, payload: &[u8]) -> Result<(), DropReason> {
                 client_hardware_address: dhcp_req.client_hardware_address,
                 client_ip: response_client_ip,
                 your_ip,
-                server_ip: Ipv4Address::UNSPECIFIED,
+                server_ip: boot_server,
                 router: Some(gateway_ip),
                 subnet_mask: Some(self.inner.state.params.net_mask),
                 relay_agent_ip: Ipv4Address::UNSPECIFIED,
@@ -116,7 +136,7 @@ pub(crate) fn handle_dhcp(&mut self, payload: &[u8]) -> Result<(), DropReason> {
                 lease_duration: Some(86400),
                 renew_duration: None,
                 rebind_duration: None,
-                additional_options: &[],
+                additional_options: boot_file_option.as_slice(),
             }
         } else {
             DhcpRepr {
@@ -185,8 +205,14 @@ pub(crate) fn handle_dhcp(&mut self, payload: &[u8]) -> Result<(), DropReason> {
             &IpAddress::Ipv4(resp_ipv4.dst_addr),
             resp_dhcp_len,
             |udp_payload| {
-                let mut resp_dhcp_packet = DhcpPacket::new_unchecked(udp_payload);
+                let mut resp_dhcp_packet = DhcpPacket::new_unchecked(&mut *udp_payload);
                 dhcp_emit_result = resp_dhcp.emit(&mut resp_dhcp_packet);
+                // The field must be NUL terminated.
+                if let Some(name) = boot_file
+                    && name.len() < BOOTP_FILE.len()
+                {
+                    udp_payload[BOOTP_FILE][..name.len()].copy_from_slice(name);
+                }
             },
             &ChecksumCapabilities::default(),
         );
@@ -294,11 +320,20 @@ struct Reply {
     }
 
     fn capture(driver: DefaultDriver, request: &[u8]) -> Vec<(Vec<u8>, ChecksumState)> {
+        capture_with(driver, request, |_| {})
+    }
+
+    fn capture_with(
+        driver: DefaultDriver,
+        request: &[u8],
+        configure: impl FnOnce(&mut ConsommeParams),
+    ) -> Vec<(Vec<u8>, ChecksumState)> {
         let mut params = ConsommeParams::new().unwrap();
         params.net_mask = NET_MASK;
         params.gateway_ip = GATEWAY_IP;
         params.client_ip = CLIENT_IP;
         params.client_mac = CLIENT_MAC;
+        configure(&mut params);
         let mut consomme = Consomme::new(params);
         let mut client = CaptureClient {
             driver,
@@ -471,4 +506,33 @@ async fn ignores_request_from_unsupported_relay(driver: DefaultDriver) {
 
         assert!(capture(driver, &request).is_empty());
     }
+
+    #[pal_async::async_test]
+    async fn advertises_boot_server_and_file(driver: DefaultDriver) {
+        let frames = capture_with(
+            driver,
+            &request(DhcpMessageType::Discover, false, None),
+            |params| {
+                params.tftp_root = Some("/srv/tftp".into());
+                params.boot_file = Some("pxelinux.0".into());
+            },
+        );
+
+        let (frame, _) = frames.last().expect("DHCP reply");
+        let ethernet = EthernetFrame::new_checked(frame.as_slice()).unwrap();
+        let ipv4 = Ipv4Packet::new_checked(ethernet.payload()).unwrap();
+        let udp = UdpPacket::new_checked(ipv4.payload()).unwrap();
+        let dhcp = DhcpPacket::new_checked(udp.payload()).unwrap();
+        let dhcp_repr = DhcpRepr::parse(&dhcp).unwrap();
+        assert_eq!(dhcp_repr.message_type, DhcpMessageType::Offer);
+        assert_eq!(dhcp_repr.server_ip, GATEWAY_IP);
+
+        let file = &udp.payload()[BOOTP_FILE];
+        assert_eq!(&file[..11], b"pxelinux.0\0");
+        let option = dhcp
+            .options()
+            .find(|option| option.kind == DHCP_OPT_BOOTFILE_NAME)
+            .expect("boot file option");
+        assert_eq!(option.data, b"pxelinux.0");
+    }
 }
@@ -7,7 +7,8 @@
 //! This module implements a subset of RFC 8415 (DHCPv6) to compliment our NDP
 //! implementation for SLAAC.  
 //! We only support the Information Request message type, to configure DNS
-//! servers for clients that have autoconfigured their own addresses via SLAAC.
+//! servers for clients that have autoconfigured their own addresses via SLAAC,
+//! along with the network boot file URL from RFC 5970.
 
 use super::Access;
 use super::Client;
@@ -57,6 +58,7 @@ pub enum OptionCode: u16 {
         CLIENT_ID = 1,
         SERVER_ID = 2,
         DNS_SERVERS = 23,
+        BOOTFILE_URL = 59,
     }
 }
 
@@ -67,6 +69,7 @@ struct Message {
     client_id: Option<Vec<u8>>,
     server_id: Option<Vec<u8>>,
     dns_servers: Option<Vec<std::net::Ipv6Addr>>,
+    bootfile_url: Option<String>,
 }
 
 #[derive(Debug, Error)]
@@ -77,6 +80,8 @@ enum DhcpV6Error {
     MalformedOption(usize),
     #[error("invalid DNS Server option length {0:#x}")]
     InvalidDnsServerOption(usize),
+    #[error("invalid Boot File URL option at offset {0:#x}")]
+    InvalidBootfileUrlOption(usize),
 }
 
 #[repr(C)]
@@ -101,6 +106,7 @@ fn new(msg_type: MessageType) -> Self {
             client_id: None,
             server_id: None,
             dns_servers: None,
+            bootfile_url: None,
         }
     }
 
@@ -114,6 +120,7 @@ fn decode(message_bytes: &[u8]) -> Result<Self, DhcpV6Error> {
         let mut client_id = None;
         let mut server_id = None;
         let mut dns_servers = None;
+        let mut bootfile_url = None;
 
         while unparsed_bytes.len() >= size_of::<DhcpV6Option>() {
             let option_offset = message_bytes.len() - unparsed_bytes.len();
@@ -153,6 +160,11 @@ fn decode(message_bytes: &[u8]) -> Result<Self, DhcpV6Error> {
                     }
                     dns_servers = Some(servers);
                 }
+                OptionCode::BOOTFILE_URL => {
+                    let url = std::str::from_utf8(option_value)
+                        .map_err(|_| DhcpV6Error::InvalidBootfileUrlOption(option_offset))?;
+                    bootfile_url = Some(url.to_owned());
+                }
                 _ => {
                     // Skip unknown options
                 }
@@ -165,6 +177,7 @@ fn decode(message_bytes: &[u8]) -> Result<Self, DhcpV6Error> {
             client_id,
             server_id,
             dns_servers,
+            bootfile_url,
         })
     }
 
@@ -196,6 +209,12 @@ fn encode(&self) -> Vec<u8> {
             }
         }
 
+        if let Some(url) = &self.bootfile_url {
+            buffer.extend_from_slice(&OptionCode::BOOTFILE_URL.0.to_be_bytes());
+            buffer.extend_from_slice(&(url.len() as u16).to_be_bytes());
+            buffer.extend_from_slice(url.as_bytes());
+        }
+
         buffer
     }
 }
@@ -235,6 +254,17 @@ pub(crate) fn handle_dhcpv6(
                     reply.dns_servers = Some(dns_servers);
                 }
 
+                // Point network boot clients at the TFTP server on the gateway.
+                let params = &self.inner.state.params;
+                if params.tftp_root.is_some()
+                    && let Some(boot_file) = &params.boot_file
+                {
+                    reply.bootfile_url = Some(format!(
+                        "tftp://[{}]/{}",
+                        params.gateway_link_local_ipv6, boot_file
+                    ));
+                }
+
                 let dhcpv6_buffer = reply.encode();
 
                 let resp_udp = UdpRepr {
@@ -361,5 +391,17 @@ fn test_message_encode() {
 
         let servers = decoded.dns_servers.as_ref().expect("DnsServers not found");
         assert_eq!(servers, &dns_servers);
+        assert_eq!(decoded.bootfile_url, None);
+    }
+
+    #[test]
+    fn test_bootfile_url_round_trip() {
+        const URL: &str = "tftp://[fe80::5055:aff:fe00:102]/efi/bootx64.efi";
+
+        let mut msg = Message::new(MessageType::REPLY);
+        msg.bootfile_url = Some(URL.to_owned());
+
+        let decoded = Message::decode(&msg.encode()).expect("Failed to decode encoded message");
+        assert_eq!(decoded.bootfile_url.as_deref(), Some(URL));
     }
 }
@@ -0,0 +1,284 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Policy for flows initiated by the guest.
+
+use crate::Access;
+use crate::Client;
+use crate::ConsommeState;
+use crate::InvalidCidr;
+use inspect::Inspect;
+use inspect_counters::Counter;
+use std::fmt;
+use std::fmt::Display;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::ops::RangeInclusive;
+
+/// The transport protocol of a guest flow.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum FlowProtocol {
+    /// TCP.
+    Tcp,
+    /// UDP.
+    Udp,
+}
+
+impl Display for FlowProtocol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            FlowProtocol::Tcp => "tcp",
+            FlowProtocol::Udp => "udp",
+        })
+    }
+}
+
+/// What happens to a guest flow.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EgressAction {
+    /// The flow is forwarded to the host network.
+    Allow,
+    /// The flow is dropped. TCP connections are reset.
+    Deny,
+}
+
+impl Display for EgressAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(match self {
+            EgressAction::Allow => "allow",
+            EgressAction::Deny => "deny",
+        })
+    }
+}
+
+/// An IPv4 or IPv6 network.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct IpNetwork {
+    address: IpAddr,
+    prefix_len: u8,
+}
+
+impl IpNetwork {
+    /// Returns the network containing `address` with the given prefix length.
+    pub fn new(address: IpAddr, prefix_len: u8) -> Result<Self, InvalidCidr> {
+        let max = if address.is_ipv4() { 32 } else { 128 };
+        if prefix_len > max {
+            return Err(InvalidCidr);
+        }
+        Ok(Self {
+            address,
+            prefix_len,
+        })
+    }
+
+    /// Returns whether `addr` is in the network.
+    pub fn contains(&self, addr: &IpAddr) -> bool {
+        match (self.address, addr) {
+            (IpAddr::V4(network), IpAddr::V4(addr)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                network.to_bits() & mask == addr.to_bits() & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(addr)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix_len))
+                    .unwrap_or(0);
+                network.to_bits() & mask == addr.to_bits() & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Display for IpNetwork {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.address, self.prefix_len)
+    }
+}
+
+/// A rule matching guest flows by destination.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EgressRule {
+    /// What happens to matching flows.
+    pub action: EgressAction,
+    /// The protocol to match, or `None` for both TCP and UDP.
+    pub protocol: Option<FlowProtocol>,
+    /// The destination network to match, or `None` for any destination.
+    pub destination: Option<IpNetwork>,
+    /// The destination ports to match, or `None` for any port.
+    pub ports: Option<RangeInclusive<u16>>,
+}
+
+impl EgressRule {
+    fn matches(&self, protocol: FlowProtocol, dst: &SocketAddr) -> bool {
+        self.protocol.is_none_or(|p| p == protocol)
+            && self.destination.is_none_or(|n| n.contains(&dst.ip()))
+            && self.ports.as_ref().is_none_or(|p| p.contains(&dst.port()))
+    }
+}
+
+impl Display for EgressRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.action)?;
+        if let Some(protocol) = self.protocol {
+            write!(f, " {protocol}")?;
+        }
+        match self.destination {
+            Some(destination) => write!(f, " to {destination}")?,
+            None => write!(f, " to any")?,
+        }
+        if let Some(ports) = &self.ports {
+            write!(f, " port {}-{}", ports.start(), ports.end())?;
+        }
+        Ok(())
+    }
+}
+
+/// Policy for TCP connections and UDP datagrams initiated by the guest.
+///
+/// The policy is checked when a TCP connection is opened and for each UDP
+/// datagram, against the destination on the host network. Traffic to the
+/// gateway's own services (DHCP, DNS, TFTP) and port-forwarded connections
+/// from the host are not affected. When the policy is replaced with
+/// [`Access::set_egress_policy`], open TCP connections that the new policy
+/// denies are reset.
+#[derive(Debug, Clone, PartialEq, Eq, Inspect)]
+pub struct EgressPolicy {
+    /// Rules, evaluated in order. The first matching rule applies.
+    #[inspect(with = "|x| inspect::iter_by_index(x).map_value(inspect::AsDisplay)")]
+    pub rules: Vec<EgressRule>,
+    /// What happens to flows that match no rule.
+    #[inspect(display)]
+    pub default_action: EgressAction,
+}
+
+impl Default for EgressPolicy {
+    fn default() -> Self {
+        Self::allow_all()
+    }
+}
+
+impl EgressPolicy {
+    /// Returns a policy that allows all flows.
+    pub fn allow_all() -> Self {
+        Self {
+            rules: Vec::new(),
+            default_action: EgressAction::Allow,
+        }
+    }
+
+    /// Returns the action for a flow to `dst`.
+    pub fn evaluate(&self, protocol: FlowProtocol, dst: &SocketAddr) -> EgressAction {
+        self.rules
+            .iter()
+            .find(|rule| rule.matches(protocol, dst))
+            .map_or(self.default_action, |rule| rule.action)
+    }
+}
+
+#[derive(Inspect, Default)]
+pub(crate) struct EgressStats {
+    tcp_denied: Counter,
+    udp_denied: Counter,
+    pub(crate) tcp_reset: Counter,
+}
+
+impl ConsommeState {
+    /// Returns whether the egress policy allows a guest flow to `dst`, logging
+    /// and counting denied flows.
+    pub(crate) fn egress_allowed(&mut self, protocol: FlowProtocol, dst: &SocketAddr) -> bool {
+        match self.params.egress_policy.evaluate(protocol, dst) {
+            EgressAction::Allow => true,
+            EgressAction::Deny => {
+                match protocol {
+                    FlowProtocol::Tcp => self.egress_stats.tcp_denied.increment(),
+                    FlowProtocol::Udp => self.egress_stats.udp_denied.increment(),
+                }
+                tracelimit::info_ratelimited!(%protocol, %dst, "guest flow denied by egress policy");
+                false
+            }
+        }
+    }
+}
+
+impl<T: Client> Access<'_, T> {
+    /// Replaces the egress policy, resetting the open TCP connections
+    /// initiated by the guest that the new policy denies.
+    pub fn set_egress_policy(&mut self, policy: EgressPolicy) {
+        self.inner.state.params.egress_policy = policy;
+        self.reset_denied_tcp_connections();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(s: &str) -> IpNetwork {
+        let (addr, len) = s.split_once('/').unwrap();
+        IpNetwork::new(addr.parse().unwrap(), len.parse().unwrap()).unwrap()
+    }
+
+    fn addr(s: &str) -> SocketAddr {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn network_contains() {
+        assert!(network("10.0.0.0/8").contains(&"10.1.2.3".parse().unwrap()));
+        assert!(!network("10.0.0.0/8").contains(&"11.0.0.1".parse().unwrap()));
+        assert!(network("0.0.0.0/0").contains(&"1.2.3.4".parse().unwrap()));
+        assert!(!network("0.0.0.0/0").contains(&"::1".parse().unwrap()));
+        assert!(network("2001:db8::/32").contains(&"2001:db8::1".parse().unwrap()));
+        assert!(network("2001:db8::1/128").contains(&"2001:db8::1".parse().unwrap()));
+        assert!(!network("2001:db8::1/128").contains(&"2001:db8::2".parse().unwrap()));
+        assert!(IpNetwork::new("10.0.0.0".parse().unwrap(), 33).is_err());
+    }
+
+    #[test]
+    fn first_matching_rule_applies() {
+        let policy = EgressPolicy {
+            rules: vec![
+                EgressRule {
+                    action: EgressAction::Deny,
+                    protocol: None,
+                    destination: Some(network("10.1.0.0/16")),
+                    ports: None,
+                },
+                EgressRule {
+                    action: EgressAction::Allow,
+                    protocol: Some(FlowProtocol::Tcp),
+                    destination: Some(network("10.0.0.0/8")),
+                    ports: Some(443..=443),
+                },
+                EgressRule {
+                    action: EgressAction::Allow,
+                    protocol: Some(FlowProtocol::Udp),
+                    destination: None,
+                    ports: Some(53..=53),
+                },
+            ],
+            default_action: EgressAction::Deny,
+        };
+
+        let tcp = |dst| policy.evaluate(FlowProtocol::Tcp, &addr(dst));
+        let udp = |dst| policy.evaluate(FlowProtocol::Udp, &addr(dst));
+        assert_eq!(tcp("10.2.0.1:443"), EgressAction::Allow);
+        assert_eq!(tcp("10.1.0.1:443"), EgressAction::Deny);
+        assert_eq!(tcp("10.2.0.1:80"), EgressAction::Deny);
+        assert_eq!(udp("10.2.0.1:443"), EgressAction::Deny);
+        assert_eq!(udp("8.8.8.8:53"), EgressAction::Allow);
+        assert_eq!(tcp("8.8.8.8:53"), EgressAction::Deny);
+        assert_eq!(udp("[2001:4860::8888]:53"), EgressAction::Allow);
+    }
+
+    #[test]
+    fn default_allows_all() {
+        let policy = EgressPolicy::default();
+        assert_eq!(
+            policy.evaluate(FlowProtocol::Tcp, &addr("1.2.3.4:22")),
+            EgressAction::Allow
+        );
+    }
+}
@@ -12,7 +12,8 @@
 //! essentially causing this stack to act as a NAT implementation, providing
 //! guest OS networking by leveraging the host's network stack.
 //!
-//! This implementation includes a small DHCP server for address assignment.
+//! This implementation includes a small DHCP server for address assignment,
+//! and optionally a read-only TFTP server for network boot.
 
 mod arp;
 mod dhcp;
@@ -21,10 +22,12 @@
 #[cfg_attr(windows, path = "dns_windows.rs")]
 mod dns;
 mod dns_resolver;
+mod egress;
 mod icmp;
 mod local_addr_map;
 mod ndp;
 mod tcp;
+mod tftp;
 mod udp;
 
 mod unix;
@@ -42,6 +45,11 @@
 
 pub use dns_resolver::StaticDnsRecord;
 pub use dns_resolver::StaticDnsRecordError;
+pub use egress::EgressAction;
+pub use egress::EgressPolicy;
+pub use egress::EgressRule;
+pub use egress::FlowProtocol;
+pub use egress::IpNetwork;
 use inspect::Inspect;
 use inspect::InspectMut;
 use pal_async::driver::Driver;
@@ -67,6 +75,7 @@
 use std::net::SocketAddr;
 use std::net::SocketAddrV4;
 use std::net::SocketAddrV6;
+use std::path::PathBuf;
 use std::task::Context;
 use std::time::Duration;
 use thiserror::Error;
@@ -119,6 +128,7 @@ pub struct Consomme {
     udp: udp::Udp,
     icmp: icmp::Icmp,
     dns: dns_resolver::DnsResolver,
+    tftp: tftp::Tftp,
     host_has_ipv6: bool,
 }
 
@@ -128,6 +138,7 @@ struct ConsommeState {
     #[inspect(skip)]
     buffer: Box<[u8]>,
     local_addr_map: local_addr_map::LocalAddrMap,
+    egress_stats: egress::EgressStats,
 }
 
 /// Dynamic networking properties of a consomme endpoint.
@@ -189,6 +200,15 @@ pub struct ConsommeParams {
     pub tcp_rx_buffer: TcpBufferBounds,
     /// Per-connection TCP transmit ring buffer bounds (host-to-guest).
     pub tcp_tx_buffer: TcpBufferBounds,
+    /// Host directory served read-only over TFTP on the gateway address. If
+    /// set, DHCP advertises the gateway as the boot server.
+    #[inspect(with = "|x| x.as_ref().map(|p| p.display().to_string())")]
+    pub tftp_root: Option<PathBuf>,
+    /// Boot file name advertised to network boot clients over DHCP, relative
+    /// to `tftp_root`.
+    pub boot_file: Option<String>,
+    /// Policy for flows initiated by the guest.
+    pub egress_policy: EgressPolicy,
 }
 
 /// Bounds for a per-connection TCP ring buffer.
@@ -252,6 +272,9 @@ pub fn new() -> Result<Self, Error> {
             allow_host_local_access: false,
             tcp_rx_buffer: DEFAULT_TCP_BUFFER_BOUNDS,
             tcp_tx_buffer: DEFAULT_TCP_BUFFER_BOUNDS,
+            tftp_root: None,
+            boot_file: None,
+            egress_policy: EgressPolicy::allow_all(),
         })
     }
 
@@ -689,6 +712,20 @@ pub enum DropReason {
     /// or link-local when host-local access is disabled).
     #[error("destination address not allowed")]
     DestinationNotAllowed,
+    /// The flow was denied by the egress policy.
+    #[error("denied by egress policy")]
+    EgressDenied,
+}
+
+/// A port forwarded from the host to the guest.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortForward {
+    /// The forwarded protocol.
+    pub protocol: FlowProtocol,
+    /// The host address the forward is listening on.
+    pub host_addr: SocketAddr,
+    /// The guest port traffic is forwarded to.
+    pub guest_port: u16,
 }
 
 /// An error from a port bind or unbind operation.
@@ -834,11 +871,13 @@ pub fn new(mut params: ConsommeParams) -> Self {
                 params,
                 buffer: Box::new([0; 65536]),
                 local_addr_map: local_addr_map::LocalAddrMap::new(),
+                egress_stats: Default::default(),
             },
             tcp: tcp::Tcp::new(tcp_rx_buffer, tcp_tx_buffer),
             udp: udp::Udp::new(timeout),
             icmp: icmp::Icmp::new(),
             dns,
+            tftp: tftp::Tftp::new(),
             host_has_ipv6,
         }
     }
@@ -898,6 +937,18 @@ pub fn create_virtual_address(&mut self, destination: IpAddr) -> Option<IpAddr>
         }
     }
 
+    /// Returns the ports currently forwarded from the host to the guest,
+    /// ordered by guest port.
+    pub fn port_forwards(&self) -> Vec<PortForward> {
+        let mut forwards = self
+            .tcp
+            .port_forwards()
+            .chain(self.udp.port_forwards())
+            .collect::<Vec<_>>();
+        forwards.sort_by_key(|f| (f.guest_port, f.protocol, f.host_addr));
+        forwards
+    }
+
     /// Pairs the client with this instance to operate on the consomme instance.
     pub fn access<'a, T: Client>(&'a mut self, client: &'a mut T) -> Access<'a, T> {
         Access {
@@ -10,9 +10,12 @@
 use super::DropReason;
 use crate::ChecksumState;
 use crate::ConsommeState;
+use crate::EgressAction;
+use crate::FlowProtocol;
 use crate::FourTuple;
 use crate::IpAddresses;
 use crate::IpVersion;
+use crate::PortForward;
 use crate::PortForwardKey;
 use crate::dns_resolver::DnsResolver;
 use crate::dns_resolver::dns_tcp::DnsTcpFrameAssembler;
@@ -146,6 +149,14 @@ pub fn new(rx_buffer: crate::TcpBufferBounds, tx_buffer: crate::TcpBufferBounds)
             aggregate_stats: TcpAggregateStats::default(),
         }
     }
+
+    pub(crate) fn port_forwards(&self) -> impl Iterator<Item = PortForward> + '_ {
+        self.listeners.iter().map(|(key, listener)| PortForward {
+            protocol: FlowProtocol::Tcp,
+            host_addr: listener.host_addr,
+            guest_port: key.guest_port,
+        })
+    }
 }
 
 #[derive(Inspect)]
@@ -291,6 +302,10 @@ struct TcpConnectionInner {
     #[inspect(skip)]
     last_close_reason: ConnectionCloseReason,
     stats: TcpConnStats,
+    /// The host destination checked against the egress policy, for
+    /// connections initiated by the guest.
+    #[inspect(with = "|x| x.map(inspect::AsDisplay)")]
+    egress_destination: Option<SocketAddr>,
 }
 
 /// Why a connection was closed, for aggregate stats categorization.
@@ -366,7 +381,8 @@ fn inspect_seq(seq: &TcpSeqNumber) -> inspect::AsHex<u32> {
 struct TcpListener {
     #[inspect(skip)]
     socket: PolledSocket<Socket>,
-    host_port: u16,
+    #[inspect(display)]
+    host_addr: SocketAddr,
 }
 
 #[derive(Debug, PartialEq, Eq, Inspect)]
@@ -656,6 +672,15 @@ pub(crate) fn handle_tcp(
                         // Resolve virtual mapped addresses back to real host
                         // addresses before establishing the connection.
                         let resolved_dst = sender.state.resolve_destination(&sender.ft.dst);
+                        if !sender
+                            .state
+                            .egress_allowed(FlowProtocol::Tcp, &resolved_dst)
+                        {
+                            // Refuse the connection so that the guest fails
+                            // fast rather than retrying the SYN.
+                            sender.rst(TcpSeqNumber(0), Some(tcp.seq_number + tcp.segment_len()));
+                            return Err(DropReason::EgressDenied);
+                        }
                         // If this is directed to a local port owned by the guest, use the
                         // appropriate host port substitution.
                         let is_local_address = sender.state.params.is_local_address(&resolved_dst);
@@ -666,7 +691,7 @@ pub(crate) fn handle_tcp(
                         {
                             FourTuple {
                                 src: sender.ft.src,
-                                dst: SocketAddr::new(resolved_dst.ip(), listener.host_port),
+                                dst: SocketAddr::new(resolved_dst.ip(), listener.host_addr.port()),
                             }
                         } else if resolved_dst != sender.ft.dst {
                             FourTuple {
@@ -681,13 +706,15 @@ pub(crate) fn handle_tcp(
                             client: sender.client,
                             state: sender.state,
                         };
-                        TcpConnection::new(
+                        let mut conn = TcpConnection::new(
                             &mut sender,
                             &tcp,
                             &self.inner.tcp.connection_params,
                             is_local_address,
                             inspect_static_dns,
-                        )?
+                        )?;
+                        conn.inner.egress_destination = Some(resolved_dst);
+                        conn
                     };
                     e.insert(conn);
                     self.inner
@@ -736,6 +763,40 @@ pub fn unbind_tcp_port(&mut self, family: IpVersion, port: u16) -> Result<(), Bi
         }
     }
 
+    /// Resets the connections initiated by the guest that the current egress
+    /// policy denies.
+    pub(crate) fn reset_denied_tcp_connections(&mut self) {
+        self.inner.tcp.connections.retain(|ft, conn| {
+            let Some(dst) = conn.inner.egress_destination else {
+                return true;
+            };
+            if self
+                .inner
+                .state
+                .params
+                .egress_policy
+                .evaluate(FlowProtocol::Tcp, &dst)
+                == EgressAction::Allow
+            {
+                return true;
+            }
+            let mut sender = Sender {
+                ft,
+                state: &mut self.inner.state,
+                client: self.client,
+            };
+            sender.rst(conn.inner.tx_send, Some(conn.inner.rx_seq));
+            conn.inner.stats.rsts_tx.increment();
+            sender.state.egress_stats.tcp_reset.increment();
+            tracelimit::info_ratelimited!(%dst, "tcp connection reset by egress policy");
+            self.inner
+                .tcp
+                .aggregate_stats
+                .record_close(conn.inner.last_close_reason);
+            false
+        });
+    }
+
     fn socket_local_addr(socket: &Socket) -> Result<SocketAddr, BindError> {
         socket
             .local_addr()
@@ -922,6 +983,7 @@ fn new_base(params: &ConnectionParams) -> TcpConnectionInner {
             tx_fin_buffered: false,
             last_close_reason: ConnectionCloseReason::LocalError,
             stats: TcpConnStats::default(),
+            egress_destination: None,
         }
     }
 
@@ -2038,12 +2100,7 @@ impl TcpListener {
     /// The socket must already be bound to an address. This method will call
     /// `listen` on it.
     pub fn from_socket(driver: &dyn Driver, socket: Socket) -> Result<Self, BindError> {
-        let Some(host_port) = socket
-            .local_addr()
-            .map_err(BindError::Io)?
-            .as_socket()
-            .map(|addr| addr.port())
-        else {
+        let Some(host_addr) = socket.local_addr().map_err(BindError::Io)?.as_socket() else {
             return Err(BindError::Io(io::Error::other(
                 "socket local address is invalid",
             )));
@@ -2056,7 +2113,7 @@ pub fn from_socket(driver: &dyn Driver, socket: Socket) -> Result<Self, BindErro
             );
             return Err(BindError::Io(err));
         }
-        Ok(Self { socket, host_port })
+        Ok(Self { socket, host_addr })
     }
 
     fn poll_listener(
@@ -7,6 +7,11 @@
 use crate::Client;
 use crate::Consomme;
 use crate::ConsommeParams;
+use crate::EgressAction;
+use crate::EgressPolicy;
+use crate::EgressRule;
+use crate::FlowProtocol;
+use crate::IpNetwork;
 use crate::IpVersion;
 use crate::PortForwardKey;
 use crate::StaticDnsRecord;
@@ -985,6 +990,45 @@ async fn test_tcp_host_fin(driver: DefaultDriver) {
     assert_eq!(tcp.control, TcpControl::Fin);
 }
 
+/// Test that replacing the egress policy resets the open connections that the
+/// new policy denies, and leaves the others alone.
+#[pal_async::async_test]
+async fn test_tcp_egress_policy_update_resets_denied(driver: DefaultDriver) {
+    let mut h = TcpTestHarness::connect(driver).await;
+    let ft = h.four_tuple();
+    let deny = |network: &str| EgressPolicy {
+        rules: vec![EgressRule {
+            action: EgressAction::Deny,
+            protocol: Some(FlowProtocol::Tcp),
+            destination: Some(IpNetwork::new(network.parse().unwrap(), 8).unwrap()),
+            ports: None,
+        }],
+        default_action: EgressAction::Allow,
+    };
+
+    h.clear_guest_packets();
+    h.consomme
+        .access(&mut h.client)
+        .set_egress_policy(deny("10.0.0.0"));
+    assert!(h.consomme.tcp.connections.contains_key(&ft));
+    assert!(h.client.received_packets.lock().is_empty());
+
+    h.consomme
+        .access(&mut h.client)
+        .set_egress_policy(deny("127.0.0.0"));
+    assert!(!h.consomme.tcp.connections.contains_key(&ft));
+    let reset =
+        h.client.received_packets.lock().iter().any(|p| {
+            TcpTestHarness::is_tcp_packet(p).is_some_and(|t| t.control == TcpControl::Rst)
+        });
+    assert!(reset, "guest should be sent a RST");
+
+    // The host side of the connection is closed too.
+    let mut out = Vec::new();
+    h.poll_until_host_eof(&mut out).await;
+    assert!(out.is_empty());
+}
+
 /// Test that a duplicate (retransmitted) segment is handled gracefully
 /// and doesn't corrupt the data stream.
 #[pal_async::async_test]
@@ -1312,6 +1356,33 @@ async fn test_tcp_bind_duplicate_port(driver: DefaultDriver) {
     );
 }
 
+/// Test that bound TCP ports are listed as port forwards until unbound.
+#[pal_async::async_test]
+async fn test_tcp_port_forwards_listed(driver: DefaultDriver) {
+    let mut consomme = Consomme::new(ConsommeParams::new().unwrap());
+    let mut client = TestClient::new(driver);
+
+    let socket = Socket::new(Domain::IPV4, Type::STREAM, None).unwrap();
+    socket
+        .bind(&SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0).into())
+        .unwrap();
+    let host_addr: SocketAddr = socket.local_addr().unwrap().as_socket().unwrap();
+
+    let mut access = consomme.access(&mut client);
+    access.bind_tcp_port(socket, 2222).unwrap();
+    assert_eq!(
+        access.get().port_forwards(),
+        [PortForward {
+            protocol: FlowProtocol::Tcp,
+            host_addr,
+            guest_port: 2222,
+        }]
+    );
+
+    access.unbind_tcp_port(IpVersion::Ipv4, 2222).unwrap();
+    assert!(access.get().port_forwards().is_empty());
+}
+
 /// Test that the same guest TCP port can be bound separately for IPv4 and IPv6.
 #[pal_async::async_test]
 async fn test_tcp_bind_same_port_different_families(driver: DefaultDriver) {
@@ -345,6 +345,76 @@ async fn ipv4_normal_destination_not_blocked(driver: DefaultDriver) {
     );
 }
 
+/// Verify that the egress policy denies TCP connections, and that policy
+/// updates take effect for new connections.
+#[pal_async::async_test]
+async fn egress_policy_denies_tcp(driver: DefaultDriver) {
+    let mut params = ConsommeParams::new().unwrap();
+    params.egress_policy = EgressPolicy {
+        rules: vec![EgressRule {
+            action: EgressAction::Allow,
+            protocol: Some(FlowProtocol::Tcp),
+            destination: Some(IpNetwork::new("192.0.2.0".parse().unwrap(), 24).unwrap()),
+            ports: Some(80..=80),
+        }],
+        default_action: EgressAction::Deny,
+    };
+    let mut consomme = Consomme::new(params);
+    let mut client = TestClient::new(driver);
+    let mut buf = vec![0u8; 1514];
+
+    let guest_mac = consomme.params_mut().client_mac;
+    let gateway_mac = consomme.params_mut().gateway_mac;
+    let guest_ip = consomme.params_mut().client_ip;
+
+    let len = build_ipv4_syn(
+        &mut buf,
+        guest_mac,
+        gateway_mac,
+        guest_ip,
+        Ipv4Address::new(198, 51, 100, 1),
+    );
+    let result = consomme
+        .access(&mut client)
+        .send(&buf[..len], &ChecksumState::NONE);
+    assert!(
+        matches!(result, Err(DropReason::EgressDenied)),
+        "flow outside the allowlist should be denied, got {result:?}"
+    );
+
+    let len = build_ipv4_syn(
+        &mut buf,
+        guest_mac,
+        gateway_mac,
+        guest_ip,
+        Ipv4Address::new(192, 0, 2, 1),
+    );
+    let result = consomme
+        .access(&mut client)
+        .send(&buf[..len], &ChecksumState::NONE);
+    // Should not be EgressDenied (may fail for other reasons).
+    assert!(
+        !matches!(result, Err(DropReason::EgressDenied)),
+        "flow in the allowlist should not be denied, got {result:?}"
+    );
+
+    consomme.params_mut().egress_policy.rules.clear();
+    let len = build_ipv4_syn(
+        &mut buf,
+        guest_mac,
+        gateway_mac,
+        guest_ip,
+        Ipv4Address::new(192, 0, 2, 2),
+    );
+    let result = consomme
+        .access(&mut client)
+        .send(&buf[..len], &ChecksumState::NONE);
+    assert!(
+        matches!(result, Err(DropReason::EgressDenied)),
+        "flow should be denied after the policy update, got {result:?}"
+    );
+}
+
 #[test]
 fn test_is_same_ipv6_subnet_basic() {
     let a = Ipv6Address::new(0x2001, 0x0db8, 0x0001, 0, 0, 0, 0, 1);
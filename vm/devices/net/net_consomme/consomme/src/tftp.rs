@@ -0,0 +1,699 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A read-only TFTP server (RFC 1350) on the gateway address.
+//!
+//! Together with the boot file advertised over DHCP, this lets guests network
+//! boot from a host directory. The block size (RFC 2348) and transfer size
+//! (RFC 2349) options are supported, since firmware PXE clients rely on them.
+//!
+//! The server never retransmits on a timer. Instead, it resends its last
+//! packet when the guest retransmits its previous acknowledgment. Packets from
+//! the gateway are delivered directly to the guest, so this is sufficient.
+
+use super::Access;
+use super::Client;
+use super::DropReason;
+use crate::ChecksumState;
+use crate::ConsommeState;
+use crate::udp::build_udp_packet;
+use inspect::Inspect;
+use inspect_counters::Counter;
+use smoltcp::wire::ETHERNET_HEADER_LEN;
+use smoltcp::wire::EthernetAddress;
+use smoltcp::wire::EthernetFrame;
+use smoltcp::wire::IPV4_HEADER_LEN;
+use smoltcp::wire::IPV6_HEADER_LEN;
+use smoltcp::wire::UDP_HEADER_LEN;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Read;
+use std::net::IpAddr;
+use std::net::SocketAddr;
+use std::path::Component;
+use std::path::Path;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::Instant;
+
+pub const TFTP_PORT: u16 = 69;
+
+/// The block size used when the client does not negotiate one.
+const DEFAULT_BLOCK_SIZE: usize = 512;
+/// The block size limits from RFC 2348.
+const MIN_BLOCK_SIZE: usize = 8;
+const MAX_BLOCK_SIZE: usize = 65464;
+/// The length of the opcode and block number preceding the data in a DATA
+/// packet.
+const DATA_HEADER_LEN: usize = 4;
+const MAX_TRANSFERS: usize = 16;
+/// How long a transfer can be idle before it is abandoned to make room for
+/// new ones.
+const TRANSFER_TIMEOUT: Duration = Duration::from_secs(60);
+/// The first port used as the server's transfer ID.
+const FIRST_SERVER_PORT: u16 = 49152;
+
+open_enum::open_enum! {
+    /// TFTP opcodes.
+    enum Opcode: u16 {
+        RRQ = 1,
+        WRQ = 2,
+        DATA = 3,
+        ACK = 4,
+        ERROR = 5,
+        OACK = 6,
+    }
+}
+
+open_enum::open_enum! {
+    /// TFTP error codes.
+    enum ErrorCode: u16 {
+        NOT_DEFINED = 0,
+        FILE_NOT_FOUND = 1,
+        ACCESS_VIOLATION = 2,
+        ILLEGAL_OPERATION = 4,
+    }
+}
+
+pub(crate) struct Tftp {
+    transfers: HashMap<SocketAddr, Transfer>,
+    next_port: u16,
+    stats: Stats,
+}
+
+#[derive(Inspect, Default)]
+struct Stats {
+    requests: Counter,
+    completed: Counter,
+    failed: Counter,
+}
+
+impl Inspect for Tftp {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        let mut resp = req.respond();
+        resp.merge(&self.stats);
+        for (addr, transfer) in &self.transfers {
+            resp.field(&addr.to_string(), transfer);
+        }
+    }
+}
+
+impl Tftp {
+    pub fn new() -> Self {
+        Self {
+            transfers: HashMap::new(),
+            next_port: FIRST_SERVER_PORT,
+            stats: Default::default(),
+        }
+    }
+
+    fn allocate_port(&mut self) -> u16 {
+        loop {
+            let port = self.next_port;
+            self.next_port = self.next_port.checked_add(1).unwrap_or(FIRST_SERVER_PORT);
+            if !self.transfers.values().any(|t| t.server_port == port) {
+                break port;
+            }
+        }
+    }
+}
+
+#[derive(Inspect)]
+struct Transfer {
+    #[inspect(with = "|x| x.display().to_string()")]
+    path: PathBuf,
+    server_port: u16,
+    #[inspect(skip)]
+    file: File,
+    block_size: usize,
+    /// The number of the last block sent, or zero if the options are still
+    /// being acknowledged.
+    block: u16,
+    /// Whether the last block sent was the final one.
+    done: bool,
+    /// The last packet sent, for retransmission.
+    #[inspect(skip)]
+    sent: Vec<u8>,
+    #[inspect(debug)]
+    last_activity: Instant,
+}
+
+impl Transfer {
+    fn send_next_block(&mut self) -> std::io::Result<()> {
+        let mut data = Vec::with_capacity(self.block_size);
+        self.file
+            .by_ref()
+            .take(self.block_size as u64)
+            .read_to_end(&mut data)?;
+        // Block numbers wrap for files of more than 65535 blocks, which most
+        // clients accept.
+        self.block = self.block.wrapping_add(1);
+        self.done = data.len() < self.block_size;
+        self.sent = data_packet(self.block, &data);
+        Ok(())
+    }
+}
+
+/// A failed request, reported to the client in an ERROR packet.
+struct TftpError(ErrorCode, &'static str);
+
+struct ReadRequest<'a> {
+    filename: &'a str,
+    mode: &'a str,
+    options: Vec<(&'a str, &'a str)>,
+}
+
+impl<'a> ReadRequest<'a> {
+    fn parse(payload: &'a [u8]) -> Result<Self, TftpError> {
+        let malformed = || TftpError(ErrorCode::ILLEGAL_OPERATION, "malformed request");
+        let fields = payload
+            .strip_suffix(&[0])
+            .ok_or_else(malformed)?
+            .split(|&b| b == 0)
+            .map(std::str::from_utf8)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| malformed())?;
+        let &[filename, mode, ref options @ ..] = fields.as_slice() else {
+            return Err(malformed());
+        };
+        Ok(Self {
+            filename,
+            mode,
+            options: options
+                .chunks_exact(2)
+                .map(|option| (option[0], option[1]))
+                .collect(),
+        })
+    }
+}
+
+/// Resolves `filename` to a file within `root`.
+fn resolve_path(root: &Path, filename: &str) -> Result<PathBuf, TftpError> {
+    // Boot file names written for Windows deployment servers use
+    // backslashes.
+    let filename = filename.replace('\\', "/");
+    let relative = Path::new(filename.trim_start_matches('/'));
+    if !relative
+        .components()
+        .all(|c| matches!(c, Component::Normal(_)))
+    {
+        return Err(TftpError(ErrorCode::ACCESS_VIOLATION, "invalid path"));
+    }
+    let root = root
+        .canonicalize()
+        .map_err(|_| TftpError(ErrorCode::NOT_DEFINED, "server root unavailable"))?;
+    let path = root
+        .join(relative)
+        .canonicalize()
+        .map_err(|_| TftpError(ErrorCode::FILE_NOT_FOUND, "file not found"))?;
+    // Don't follow symlinks out of the root.
+    if !path.starts_with(&root) {
+        return Err(TftpError(ErrorCode::ACCESS_VIOLATION, "invalid path"));
+    }
+    Ok(path)
+}
+
+fn data_packet(block: u16, data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(DATA_HEADER_LEN + data.len());
+    packet.extend_from_slice(&Opcode::DATA.0.to_be_bytes());
+    packet.extend_from_slice(&block.to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+fn error_packet(code: ErrorCode, message: &str) -> Vec<u8> {
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&Opcode::ERROR.0.to_be_bytes());
+    packet.extend_from_slice(&code.0.to_be_bytes());
+    packet.extend_from_slice(message.as_bytes());
+    packet.push(0);
+    packet
+}
+
+fn oack_packet(options: &[(&str, String)]) -> Vec<u8> {
+    let mut packet = Vec::new();
+    packet.extend_from_slice(&Opcode::OACK.0.to_be_bytes());
+    for (name, value) in options {
+        packet.extend_from_slice(name.as_bytes());
+        packet.push(0);
+        packet.extend_from_slice(value.as_bytes());
+        packet.push(0);
+    }
+    packet
+}
+
+/// Opens the requested file and builds the first packet of the transfer.
+fn start_transfer(
+    root: &Path,
+    request: &ReadRequest<'_>,
+    server_port: u16,
+    max_block_size: usize,
+) -> Result<Transfer, TftpError> {
+    if !request.mode.eq_ignore_ascii_case("octet") {
+        return Err(TftpError(
+            ErrorCode::ILLEGAL_OPERATION,
+            "only octet mode is supported",
+        ));
+    }
+    let path = resolve_path(root, request.filename)?;
+    let file = File::open(&path)
+        .map_err(|_| TftpError(ErrorCode::ACCESS_VIOLATION, "cannot open file"))?;
+    let metadata = file
+        .metadata()
+        .map_err(|_| TftpError(ErrorCode::ACCESS_VIOLATION, "cannot open file"))?;
+    if !metadata.is_file() {
+        return Err(TftpError(ErrorCode::FILE_NOT_FOUND, "file not found"));
+    }
+
+    let mut block_size = DEFAULT_BLOCK_SIZE;
+    let mut accepted = Vec::new();
+    for &(name, value) in &request.options {
+        if name.eq_ignore_ascii_case("blksize") {
+            if let Ok(requested) = value.parse::<usize>()
+                && requested >= MIN_BLOCK_SIZE
+            {
+                block_size = requested.min(MAX_BLOCK_SIZE).min(max_block_size);
+                accepted.push(("blksize", block_size.to_string()));
+            }
+        } else if name.eq_ignore_ascii_case("tsize") {
+            accepted.push(("tsize", metadata.len().to_string()));
+        }
+    }
+
+    let mut transfer = Transfer {
+        path,
+        server_port,
+        file,
+        block_size,
+        block: 0,
+        done: false,
+        sent: Vec::new(),
+        last_activity: Instant::now(),
+    };
+    if accepted.is_empty() {
+        transfer
+            .send_next_block()
+            .map_err(|_| TftpError(ErrorCode::NOT_DEFINED, "read failed"))?;
+    } else {
+        transfer.sent = oack_packet(&accepted);
+    }
+    Ok(transfer)
+}
+
+fn send_tftp(
+    client: &mut impl Client,
+    state: &mut ConsommeState,
+    client_mac: EthernetAddress,
+    src: SocketAddr,
+    dst: SocketAddr,
+    packet: &[u8],
+) -> Result<(), DropReason> {
+    let (ip_header_len, checksum_state, gateway_mac) = match src {
+        SocketAddr::V4(_) => (
+            IPV4_HEADER_LEN,
+            ChecksumState::UDP4,
+            state.params.gateway_mac,
+        ),
+        SocketAddr::V6(_) => (
+            IPV6_HEADER_LEN,
+            ChecksumState::NONE,
+            state.params.gateway_mac_ipv6,
+        ),
+    };
+    let buffer = &mut state.buffer;
+    let payload_offset = ETHERNET_HEADER_LEN + ip_header_len + UDP_HEADER_LEN;
+    let required_size = payload_offset + packet.len();
+    if required_size > buffer.len() {
+        return Err(DropReason::SendBufferFull);
+    }
+    buffer[payload_offset..required_size].copy_from_slice(packet);
+
+    let mut eth_frame = EthernetFrame::new_unchecked(&mut buffer[..]);
+    let frame_len = build_udp_packet(
+        &mut eth_frame,
+        src.ip().into(),
+        dst.ip().into(),
+        src.port(),
+        dst.port(),
+        packet.len(),
+        gateway_mac,
+        client_mac,
+    );
+    client.recv(&buffer[..frame_len], &checksum_state);
+    Ok(())
+}
+
+impl<T: Client> Access<'_, T> {
+    /// Handles a UDP packet from `src` to `dst` on the gateway. Returns
+    /// `false` if the packet is not for the TFTP server.
+    pub(crate) fn handle_tftp(
+        &mut self,
+        client_mac: EthernetAddress,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: &[u8],
+    ) -> Result<bool, DropReason> {
+        let params = &self.inner.state.params;
+        let Some(root) = params.tftp_root.clone() else {
+            return Ok(false);
+        };
+        let gateway_ip: IpAddr = match dst {
+            SocketAddr::V4(_) => params.gateway_ip.into(),
+            SocketAddr::V6(_) => params.gateway_link_local_ipv6.into(),
+        };
+        if dst.ip() != gateway_ip {
+            return Ok(false);
+        }
+        if dst.port() == TFTP_PORT {
+            self.handle_tftp_request(client_mac, src, dst.ip(), &root, payload)?;
+            return Ok(true);
+        }
+        if !self
+            .inner
+            .tftp
+            .transfers
+            .get(&src)
+            .is_some_and(|t| t.server_port == dst.port())
+        {
+            return Ok(false);
+        }
+        self.handle_tftp_transfer(client_mac, src, dst, payload)?;
+        Ok(true)
+    }
+
+    fn handle_tftp_request(
+        &mut self,
+        client_mac: EthernetAddress,
+        src: SocketAddr,
+        server_ip: IpAddr,
+        root: &Path,
+        payload: &[u8],
+    ) -> Result<(), DropReason> {
+        let ip_header_len = match server_ip {
+            IpAddr::V4(_) => IPV4_HEADER_LEN,
+            IpAddr::V6(_) => IPV6_HEADER_LEN,
+        };
+        let max_block_size = self
+            .client
+            .rx_mtu()
+            .saturating_sub(ETHERNET_HEADER_LEN + ip_header_len + UDP_HEADER_LEN + DATA_HEADER_LEN);
+
+        let tftp = &mut self.inner.tftp;
+        tftp.stats.requests.increment();
+        let now = Instant::now();
+        tftp.transfers
+            .retain(|_, t| now.duration_since(t.last_activity) < TRANSFER_TIMEOUT);
+        // A repeated request restarts the transfer, since the client did not
+        // see the response.
+        tftp.transfers.remove(&src);
+        let server_port = tftp.allocate_port();
+
+        let opcode = Opcode(u16::from_be_bytes(
+            payload
+                .get(..2)
+                .ok_or(DropReason::MalformedPacket)?
+                .try_into()
+                .unwrap(),
+        ));
+        let result = match opcode {
+            Opcode::RRQ => ReadRequest::parse(&payload[2..]).and_then(|request| {
+                if tftp.transfers.len() >= MAX_TRANSFERS {
+                    return Err(TftpError(ErrorCode::NOT_DEFINED, "too many transfers"));
+                }
+                tracing::debug!(
+                    client = %src,
+                    filename = request.filename,
+                    "tftp read request"
+                );
+                start_transfer(root, &request, server_port, max_block_size)
+            }),
+            Opcode::WRQ => Err(TftpError(
+                ErrorCode::ACCESS_VIOLATION,
+                "the server is read-only",
+            )),
+            _ => Err(TftpError(ErrorCode::ILLEGAL_OPERATION, "unexpected opcode")),
+        };
+
+        let server = SocketAddr::new(server_ip, server_port);
+        match result {
+            Ok(transfer) => {
+                let transfer = tftp.transfers.entry(src).insert_entry(transfer).into_mut();
+                send_tftp(
+                    self.client,
+                    &mut self.inner.state,
+                    client_mac,
+                    server,
+                    src,
+                    &transfer.sent,
+                )
+            }
+            Err(TftpError(code, message)) => {
+                tftp.stats.failed.increment();
+                send_tftp(
+                    self.client,
+                    &mut self.inner.state,
+                    client_mac,
+                    server,
+                    src,
+                    &error_packet(code, message),
+                )
+            }
+        }
+    }
+
+    fn handle_tftp_transfer(
+        &mut self,
+        client_mac: EthernetAddress,
+        src: SocketAddr,
+        dst: SocketAddr,
+        payload: &[u8],
+    ) -> Result<(), DropReason> {
+        let tftp = &mut self.inner.tftp;
+        let transfer = tftp.transfers.get_mut(&src).unwrap();
+        let header = payload.get(..4).ok_or(DropReason::MalformedPacket)?;
+        let opcode = Opcode(u16::from_be_bytes([header[0], header[1]]));
+        let packet = match opcode {
+            Opcode::ACK => {
+                let block = u16::from_be_bytes([header[2], header[3]]);
+                transfer.last_activity = Instant::now();
+                if block == transfer.block {
+                    if transfer.done {
+                        tftp.transfers.remove(&src);
+                        tftp.stats.completed.increment();
+                        return Ok(());
+                    }
+                    if let Err(err) = transfer.send_next_block() {
+                        tracelimit::warn_ratelimited!(
+                            error = &err as &dyn std::error::Error,
+                            path = %transfer.path.display(),
+                            "tftp read failed"
+                        );
+                        tftp.transfers.remove(&src);
+                        tftp.stats.failed.increment();
+                        error_packet(ErrorCode::NOT_DEFINED, "read failed")
+                    } else {
+                        transfer.sent.clone()
+                    }
+                } else if block == transfer.block.wrapping_sub(1) {
+                    // The client did not receive the last packet.
+                    transfer.sent.clone()
+                } else {
+                    return Ok(());
+                }
+            }
+            Opcode::ERROR => {
+                tftp.transfers.remove(&src);
+                tftp.stats.failed.increment();
+                return Ok(());
+            }
+            _ => {
+                tftp.transfers.remove(&src);
+                tftp.stats.failed.increment();
+                error_packet(ErrorCode::ILLEGAL_OPERATION, "unexpected opcode")
+            }
+        };
+        send_tftp(
+            self.client,
+            &mut self.inner.state,
+            client_mac,
+            dst,
+            src,
+            &packet,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Consomme;
+    use crate::ConsommeParams;
+    use crate::MIN_MTU;
+    use pal_async::DefaultDriver;
+    use pal_async::driver::Driver;
+    use smoltcp::wire::Ipv4Address;
+    use smoltcp::wire::Ipv4Packet;
+    use smoltcp::wire::UdpPacket;
+
+    const CLIENT_MAC: EthernetAddress = EthernetAddress([0x00, 0x15, 0x5d, 0x12, 0x34, 0x56]);
+    const GATEWAY_IP: Ipv4Address = Ipv4Address::new(10, 0, 0, 1);
+    const CLIENT: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Address::new(10, 0, 0, 2)), 2000);
+    const SERVER: SocketAddr = SocketAddr::new(IpAddr::V4(GATEWAY_IP), TFTP_PORT);
+
+    struct CaptureClient {
+        driver: DefaultDriver,
+        packets: Vec<(u16, Vec<u8>)>,
+    }
+
+    impl Client for CaptureClient {
+        fn driver(&self) -> &dyn Driver {
+            &self.driver
+        }
+
+        fn recv(&mut self, data: &[u8], _checksum: &ChecksumState) {
+            let ethernet = EthernetFrame::new_checked(data).unwrap();
+            let ipv4 = Ipv4Packet::new_checked(ethernet.payload()).unwrap();
+            let udp = UdpPacket::new_checked(ipv4.payload()).unwrap();
+            assert_eq!(ipv4.src_addr(), GATEWAY_IP);
+            assert_eq!(udp.dst_port(), CLIENT.port());
+            self.packets.push((udp.src_port(), udp.payload().to_vec()));
+        }
+
+        fn rx_mtu(&mut self) -> usize {
+            MIN_MTU
+        }
+    }
+
+    struct Server {
+        consomme: Consomme,
+        client: CaptureClient,
+        root: tempfile::TempDir,
+    }
+
+    impl Server {
+        fn new(driver: DefaultDriver) -> Self {
+            let root = tempfile::tempdir().unwrap();
+            let mut params = ConsommeParams::new().unwrap();
+            params.gateway_ip = GATEWAY_IP;
+            params.tftp_root = Some(root.path().to_owned());
+            Self {
+                consomme: Consomme::new(params),
+                client: CaptureClient {
+                    driver,
+                    packets: Vec::new(),
+                },
+                root,
+            }
+        }
+
+        fn send(&mut self, dst: SocketAddr, payload: &[u8]) -> Option<(u16, Vec<u8>)> {
+            assert!(
+                self.consomme
+                    .access(&mut self.client)
+                    .handle_tftp(CLIENT_MAC, CLIENT, dst, payload)
+                    .unwrap()
+            );
+            self.client.packets.pop()
+        }
+
+        fn read(&mut self, filename: &str, options: &[(&str, &str)]) -> (u16, Vec<u8>) {
+            let mut request = Opcode::RRQ.0.to_be_bytes().to_vec();
+            for field in [filename, "octet"]
+                .into_iter()
+                .chain(options.iter().flat_map(|(name, value)| [*name, *value]))
+            {
+                request.extend_from_slice(field.as_bytes());
+                request.push(0);
+            }
+            self.send(SERVER, &request).unwrap()
+        }
+
+        fn ack(&mut self, port: u16, block: u16) -> Option<Vec<u8>> {
+            let mut ack = Opcode::ACK.0.to_be_bytes().to_vec();
+            ack.extend_from_slice(&block.to_be_bytes());
+            let (src_port, packet) = self.send(SocketAddr::new(SERVER.ip(), port), &ack)?;
+            assert_eq!(src_port, port);
+            Some(packet)
+        }
+    }
+
+    fn data(block: u16, len: usize) -> Vec<u8> {
+        let contents = (0..).map(|i: usize| i as u8);
+        let start = (block as usize - 1) * DEFAULT_BLOCK_SIZE;
+        data_packet(block, &contents.skip(start).take(len).collect::<Vec<_>>())
+    }
+
+    fn error_code(packet: &[u8]) -> ErrorCode {
+        assert_eq!(packet[..2], Opcode::ERROR.0.to_be_bytes());
+        ErrorCode(u16::from_be_bytes([packet[2], packet[3]]))
+    }
+
+    fn write_file(server: &Server, name: &str, len: usize) {
+        let contents: Vec<u8> = (0..len).map(|i| i as u8).collect();
+        std::fs::write(server.root.path().join(name), contents).unwrap();
+    }
+
+    #[pal_async::async_test]
+    async fn reads_file_in_blocks(driver: DefaultDriver) {
+        let mut server = Server::new(driver);
+        write_file(&server, "boot.efi", 1000);
+
+        let (port, packet) = server.read("boot.efi", &[]);
+        assert_ne!(port, TFTP_PORT);
+        assert_eq!(packet, data(1, 512));
+        assert_eq!(server.ack(port, 1).unwrap(), data(2, 488));
+        assert!(server.ack(port, 2).is_none());
+        assert!(server.consomme.tftp.transfers.is_empty());
+    }
+
+    #[pal_async::async_test]
+    async fn resends_on_duplicate_ack(driver: DefaultDriver) {
+        let mut server = Server::new(driver);
+        write_file(&server, "boot.efi", 1024);
+
+        let (port, _) = server.read("boot.efi", &[]);
+        assert_eq!(server.ack(port, 1).unwrap(), data(2, 512));
+        assert_eq!(server.ack(port, 1).unwrap(), data(2, 512));
+        // The file is a multiple of the block size, so it ends with an empty
+        // block.
+        assert_eq!(server.ack(port, 2).unwrap(), data_packet(3, &[]));
+        assert!(server.ack(port, 3).is_none());
+    }
+
+    #[pal_async::async_test]
+    async fn negotiates_options(driver: DefaultDriver) {
+        let mut server = Server::new(driver);
+        write_file(&server, "boot.efi", 3000);
+
+        let (port, packet) = server.read("/boot.efi", &[("tsize", "0"), ("blksize", "9000")]);
+        assert_eq!(
+            packet,
+            oack_packet(&[("tsize", "3000".into()), ("blksize", "1468".into())])
+        );
+        let packet = server.ack(port, 0).unwrap();
+        assert_eq!(packet.len(), DATA_HEADER_LEN + 1468);
+        assert_eq!(packet[..DATA_HEADER_LEN], [0, 3, 0, 1]);
+    }
+
+    #[pal_async::async_test]
+    async fn rejects_missing_and_outside_files(driver: DefaultDriver) {
+        let mut server = Server::new(driver);
+        write_file(&server, "boot.efi", 10);
+
+        let (_, packet) = server.read("missing.efi", &[]);
+        assert_eq!(error_code(&packet), ErrorCode::FILE_NOT_FOUND);
+        let (_, packet) = server.read("../boot.efi", &[]);
+        assert_eq!(error_code(&packet), ErrorCode::ACCESS_VIOLATION);
+        assert!(server.consomme.tftp.transfers.is_empty());
+    }
+
+    #[pal_async::async_test]
+    async fn rejects_writes(driver: DefaultDriver) {
+        let mut server = Server::new(driver);
+
+        let mut request = Opcode::WRQ.0.to_be_bytes().to_vec();
+        request.extend_from_slice(b"boot.efi\0octet\0");
+        let (_, packet) = server.send(SERVER, &request).unwrap();
+        assert_eq!(error_code(&packet), ErrorCode::ACCESS_VIOLATION);
+    }
+}
@@ -10,11 +10,13 @@
 use super::dhcpv6::DHCPV6_SERVER;
 use crate::ChecksumState;
 use crate::ConsommeState;
+use crate::FlowProtocol;
 use crate::FourTuple;
 use crate::IpAddresses;
 use crate::IpVersion;
 use crate::Ipv4Addresses;
 use crate::Ipv6Addresses;
+use crate::PortForward;
 use crate::PortForwardKey;
 use crate::dns_resolver::DnsFlow;
 use crate::dns_resolver::DnsRequest;
@@ -79,6 +81,14 @@ pub fn new(timeout: Duration) -> Self {
             timeout,
         }
     }
+
+    pub(crate) fn port_forwards(&self) -> impl Iterator<Item = PortForward> + '_ {
+        self.listeners.values().map(|listener| PortForward {
+            protocol: FlowProtocol::Udp,
+            host_addr: listener.host_addr,
+            guest_port: listener.guest_port,
+        })
+    }
 }
 
 impl InspectMut for Udp {
@@ -455,6 +465,13 @@ pub(crate) fn handle_udp(
 
         // Resolve virtual mapped addresses back to the real host address.
         let mut dst_sock_addr = self.inner.state.resolve_destination(&dst_sock_addr);
+        if !self
+            .inner
+            .state
+            .egress_allowed(FlowProtocol::Udp, &dst_sock_addr)
+        {
+            return Err(DropReason::EgressDenied);
+        }
         if self.inner.state.params.is_local_address(&dst_sock_addr) {
             // This packet is destined for a local address. If the port matches a listener,
             // translate it so that the connection loops back to the expected destination.
@@ -543,7 +560,12 @@ fn handle_gateway_udp(
                 udp,
                 false,
             ),
-            _ => Ok(false),
+            _ => self.handle_tftp(
+                frame.src_addr,
+                SocketAddr::V4(SocketAddrV4::new(addresses.src_addr, udp.src_port())),
+                SocketAddr::V4(SocketAddrV4::new(addresses.dst_addr, udp.dst_port())),
+                udp.payload(),
+            ),
         }
     }
 
@@ -566,7 +588,12 @@ fn handle_gateway_udp_v6(
                 udp,
                 false,
             ),
-            _ => Ok(false),
+            _ => self.handle_tftp(
+                frame.src_addr,
+                SocketAddr::V6(SocketAddrV6::new(addresses.src_addr, udp.src_port(), 0, 0)),
+                SocketAddr::V6(SocketAddrV6::new(addresses.dst_addr, udp.dst_port(), 0, 0)),
+                payload,
+            ),
         }
     }
 
@@ -734,7 +761,7 @@ pub fn udp_connection_count(&self) -> usize {
 /// the UDP payload is already present in the buffer at the correct offset.
 ///
 /// Returns the total length of the constructed frame.
-fn build_udp_packet<T: AsRef<[u8]> + AsMut<[u8]> + ?Sized>(
+pub(crate) fn build_udp_packet<T: AsRef<[u8]> + AsMut<[u8]> + ?Sized>(
     eth_frame: &mut EthernetFrame<&mut T>,
     src_ip: IpAddress,
     dst_ip: IpAddress,
@@ -1010,6 +1037,33 @@ async fn test_udp_bind_duplicate_port(driver: DefaultDriver) {
         );
     }
 
+    #[pal_async::async_test]
+    async fn test_udp_port_forwards_listed(driver: DefaultDriver) {
+        let driver = Arc::new(driver);
+        let mut consomme = create_consomme_with_timeout(Duration::from_secs(30));
+        let mut client = TestClient::new(driver);
+
+        let socket = Socket::new(socket2::Domain::IPV4, socket2::Type::DGRAM, None).unwrap();
+        socket
+            .bind(&SocketAddrV4::new(Ipv4Addr::LOCALHOST, 0).into())
+            .unwrap();
+        let host_addr: SocketAddr = socket.local_addr().unwrap().as_socket().unwrap();
+
+        let mut access = consomme.access(&mut client);
+        access.bind_udp_port(socket, 5353).unwrap();
+        assert_eq!(
+            access.get().port_forwards(),
+            [PortForward {
+                protocol: FlowProtocol::Udp,
+                host_addr,
+                guest_port: 5353,
+            }]
+        );
+
+        access.unbind_udp_port(IpVersion::Ipv4, 5353).unwrap();
+        assert!(access.get().port_forwards().is_empty());
+    }
+
     #[pal_async::async_test]
     async fn test_udp_bind_same_port_different_families(driver: DefaultDriver) {
         let driver = Arc::new(driver);
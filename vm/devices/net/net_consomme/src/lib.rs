@@ -35,6 +35,7 @@
 use net_backend_resources::consomme::ConsommeRequest;
 use net_backend_resources::consomme::HostPortConfig;
 use net_backend_resources::consomme::HostPortProtocol;
+use net_backend_resources::consomme::PortForwardInfo;
 use pal_async::driver::Driver;
 use parking_lot::Mutex;
 use std::collections::VecDeque;
@@ -634,6 +635,34 @@ fn process_port_request(
                 },
             );
         }
+        ConsommeRequest::List(rpc) => {
+            rpc.handle_sync(|()| {
+                consomme
+                    .get()
+                    .port_forwards()
+                    .into_iter()
+                    .map(|forward| PortForwardInfo {
+                        protocol: match forward.protocol {
+                            consomme::FlowProtocol::Tcp => HostPortProtocol::Tcp,
+                            consomme::FlowProtocol::Udp => HostPortProtocol::Udp,
+                        },
+                        host_address: forward.host_addr.ip().into(),
+                        host_port: forward.host_addr.port(),
+                        guest_port: forward.guest_port,
+                    })
+                    .collect()
+            });
+        }
+        ConsommeRequest::SetEgressPolicy(rpc) => {
+            rpc.handle_failable_sync(
+                |config| -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+                    let policy = resolver::egress_policy(config)?;
+                    consomme.set_egress_policy(policy);
+                    tracing::info!("egress policy updated");
+                    Ok(())
+                },
+            );
+        }
     }
 }
 
@@ -730,9 +759,8 @@ fn poll_ready(&mut self, cx: &mut Context<'_>, pool: &mut dyn BufferAccess) -> P
                 tracing::debug!(error = &err as &dyn std::error::Error, "tx packet ignored");
                 match err {
                     consomme::DropReason::SendBufferFull
-                    | consomme::DropReason::DestinationNotAllowed => {
-                        self.stats.tx_dropped.increment()
-                    }
+                    | consomme::DropReason::DestinationNotAllowed
+                    | consomme::DropReason::EgressDenied => self.stats.tx_dropped.increment(),
                     consomme::DropReason::UnsupportedEthertype(_)
                     | consomme::DropReason::UnsupportedIpProtocol(_)
                     | consomme::DropReason::UnsupportedIcmpv6(_)
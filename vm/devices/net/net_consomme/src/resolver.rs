@@ -6,11 +6,18 @@
 use crate::PortForwardConfig;
 use crate::create_bound_socket;
 use consomme::ConsommeParams;
+use consomme::EgressAction;
+use consomme::EgressPolicy;
+use consomme::EgressRule;
+use consomme::FlowProtocol;
+use consomme::IpNetwork;
 use net_backend::resolve::ResolveEndpointParams;
 use net_backend::resolve::ResolvedEndpoint;
 use net_backend_resources::consomme::ConsommeHandle;
+use net_backend_resources::consomme::EgressPolicyConfig;
 use net_backend_resources::consomme::HostPort;
 use net_backend_resources::consomme::HostPortProtocol;
+use std::path::Path;
 use thiserror::Error;
 use vm_resource::ResolveResource;
 use vm_resource::declare_static_resolver;
@@ -29,6 +36,12 @@ pub enum ResolveConsommeError {
     Consomme(consomme::Error),
     #[error(transparent)]
     InvalidCidr(consomme::InvalidCidr),
+    #[error("tftp root {0} is not a directory")]
+    InvalidTftpRoot(String),
+    #[error("boot file name is longer than 255 bytes")]
+    BootFileTooLong,
+    #[error("invalid egress rule: {0}")]
+    InvalidEgressRule(&'static str),
     #[error("failed to create socket for port forward ({details})")]
     SocketCreation {
         #[source]
@@ -53,6 +66,22 @@ fn resolve(
                 .set_cidr(cidr)
                 .map_err(ResolveConsommeError::InvalidCidr)?;
         }
+        if let Some(root) = resource.tftp_root {
+            if !Path::new(&root).is_dir() {
+                return Err(ResolveConsommeError::InvalidTftpRoot(root));
+            }
+            state.tftp_root = Some(root.into());
+        }
+        if let Some(boot_file) = resource.boot_file {
+            // DHCP options are limited to 255 bytes.
+            if boot_file.len() > u8::MAX.into() {
+                return Err(ResolveConsommeError::BootFileTooLong);
+            }
+            state.boot_file = Some(boot_file);
+        }
+        if let Some(policy) = resource.egress_policy {
+            state.egress_policy = egress_policy(policy)?;
+        }
         let port_forwards: Vec<PortForwardConfig> = resource
             .ports
             .into_iter()
@@ -104,3 +133,43 @@ fn resolve(
         Ok(endpoint.into())
     }
 }
+
+pub(crate) fn egress_policy(
+    config: EgressPolicyConfig,
+) -> Result<EgressPolicy, ResolveConsommeError> {
+    let rules = config
+        .rules
+        .into_iter()
+        .map(|rule| {
+            let destination = rule
+                .destination
+                .map(|(addr, prefix_len)| IpNetwork::new(addr.into(), prefix_len))
+                .transpose()
+                .map_err(|_| ResolveConsommeError::InvalidEgressRule("invalid prefix length"))?;
+            if rule.ports.is_some_and(|(start, end)| start > end) {
+                return Err(ResolveConsommeError::InvalidEgressRule("empty port range"));
+            }
+            Ok(EgressRule {
+                action: if rule.allow {
+                    EgressAction::Allow
+                } else {
+                    EgressAction::Deny
+                },
+                protocol: rule.protocol.map(|p| match p {
+                    HostPortProtocol::Tcp => FlowProtocol::Tcp,
+                    HostPortProtocol::Udp => FlowProtocol::Udp,
+                }),
+                destination,
+                ports: rule.ports.map(|(start, end)| start..=end),
+            })
+        })
+        .collect::<Result<_, _>>()?;
+    Ok(EgressPolicy {
+        rules,
+        default_action: if config.default_allow {
+            EgressAction::Allow
+        } else {
+            EgressAction::Deny
+        },
+    })
+}
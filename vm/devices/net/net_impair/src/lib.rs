@@ -0,0 +1,695 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A network endpoint that impairs the packets of another endpoint, adding
+//! delay, jitter, loss, duplication, reordering, and a bandwidth cap.
+//!
+//! This reproduces unreliable networks without host privileges. The random
+//! choices are seeded, so a run with the same traffic sees the same
+//! impairments. The settings can be changed at runtime through inspect.
+
+#![forbid(unsafe_code)]
+
+mod link;
+pub mod resolver;
+
+use crate::link::Fate;
+use crate::link::Link;
+use crate::link::Params;
+use async_trait::async_trait;
+use guestmem::GuestMemory;
+use inspect::Inspect;
+use inspect::InspectMut;
+use net_backend::BufferAccess;
+use net_backend::Endpoint;
+use net_backend::EndpointAction;
+use net_backend::MultiQueueSupport;
+use net_backend::Queue;
+use net_backend::QueueConfig;
+use net_backend::RssConfig;
+use net_backend::RxBufferSegment;
+use net_backend::RxId;
+use net_backend::RxMetadata;
+use net_backend::TxError;
+use net_backend::TxId;
+use net_backend::TxOffloadSupport;
+use net_backend::TxSegment;
+use net_backend::next_packet;
+use net_backend_resources::impair::ImpairmentConfig;
+use pal_async::timer::Instant;
+use pal_async::timer::PolledTimer;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::sync::atomic::AtomicU32;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Duration;
+
+/// The number of receive buffers withheld from the inner queue while
+/// duplication is enabled, to hold duplicated receives.
+const RX_SPARE_BUFFERS: usize = 4;
+
+/// The impairment settings, shared with the queues so that changes made
+/// through inspect apply to the next packet.
+#[derive(Inspect)]
+struct Settings {
+    #[inspect(with = "inspect::AtomicMut")]
+    delay_us: AtomicU64,
+    #[inspect(with = "inspect::AtomicMut")]
+    jitter_us: AtomicU64,
+    #[inspect(with = "inspect::AtomicMut")]
+    loss_ppm: AtomicU32,
+    #[inspect(with = "inspect::AtomicMut")]
+    duplicate_ppm: AtomicU32,
+    #[inspect(with = "inspect::AtomicMut")]
+    reorder_ppm: AtomicU32,
+    #[inspect(with = "inspect::AtomicMut")]
+    rate_bps: AtomicU64,
+    seed: u64,
+}
+
+impl Settings {
+    fn new(config: &ImpairmentConfig) -> Self {
+        Self {
+            delay_us: (config.delay.as_micros() as u64).into(),
+            jitter_us: (config.jitter.as_micros() as u64).into(),
+            loss_ppm: config.loss_ppm.into(),
+            duplicate_ppm: config.duplicate_ppm.into(),
+            reorder_ppm: config.reorder_ppm.into(),
+            rate_bps: config.rate.into(),
+            seed: config.seed,
+        }
+    }
+
+    fn params(&self) -> Params {
+        Params {
+            delay: Duration::from_micros(self.delay_us.load(Ordering::Relaxed)),
+            jitter: Duration::from_micros(self.jitter_us.load(Ordering::Relaxed)),
+            loss_ppm: self.loss_ppm.load(Ordering::Relaxed),
+            duplicate_ppm: self.duplicate_ppm.load(Ordering::Relaxed),
+            reorder_ppm: self.reorder_ppm.load(Ordering::Relaxed),
+            rate: self.rate_bps.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// An endpoint that impairs the packets of an inner endpoint.
+pub struct ImpairedEndpoint {
+    endpoint: Box<dyn Endpoint>,
+    settings: Arc<Settings>,
+}
+
+impl ImpairedEndpoint {
+    /// Returns a new endpoint impairing `endpoint` as described by `config`.
+    pub fn new(endpoint: Box<dyn Endpoint>, config: &ImpairmentConfig) -> Self {
+        Self {
+            endpoint,
+            settings: Arc::new(Settings::new(config)),
+        }
+    }
+}
+
+impl InspectMut for ImpairedEndpoint {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        req.respond()
+            .merge(&*self.settings)
+            .field_mut("inner", self.endpoint.as_mut());
+    }
+}
+
+#[async_trait]
+impl Endpoint for ImpairedEndpoint {
+    fn endpoint_type(&self) -> &'static str {
+        "impair"
+    }
+
+    async fn get_queues(
+        &mut self,
+        config: Vec<QueueConfig>,
+        rss: Option<&RssConfig<'_>>,
+        queues: &mut Vec<Box<dyn Queue>>,
+    ) -> anyhow::Result<()> {
+        let timers = config
+            .iter()
+            .map(|config| PolledTimer::new(config.driver.as_ref()))
+            .collect::<Vec<_>>();
+        let mut inner = Vec::new();
+        self.endpoint.get_queues(config, rss, &mut inner).await?;
+        queues.extend(
+            inner
+                .into_iter()
+                .zip(timers)
+                .enumerate()
+                .map(|(index, (queue, timer))| {
+                    Box::new(ImpairedQueue::new(
+                        queue,
+                        timer,
+                        self.settings.clone(),
+                        index as u64,
+                    )) as _
+                }),
+        );
+        Ok(())
+    }
+
+    async fn stop(&mut self) {
+        self.endpoint.stop().await
+    }
+
+    fn is_ordered(&self) -> bool {
+        false
+    }
+
+    fn tx_offload_support(&self) -> TxOffloadSupport {
+        self.endpoint.tx_offload_support()
+    }
+
+    fn multiqueue_support(&self) -> MultiQueueSupport {
+        self.endpoint.multiqueue_support()
+    }
+
+    async fn set_data_path_to_guest_vf(&self, use_vf: bool) -> anyhow::Result<()> {
+        self.endpoint.set_data_path_to_guest_vf(use_vf).await
+    }
+
+    async fn get_data_path_to_guest_vf(&self) -> anyhow::Result<bool> {
+        self.endpoint.get_data_path_to_guest_vf().await
+    }
+
+    async fn wait_for_endpoint_action(&mut self) -> EndpointAction {
+        self.endpoint.wait_for_endpoint_action().await
+    }
+
+    fn link_speed(&self) -> u64 {
+        let speed = self.endpoint.link_speed();
+        match self.settings.rate_bps.load(Ordering::Relaxed) {
+            0 => speed,
+            rate => speed.min(rate),
+        }
+    }
+}
+
+#[derive(Default)]
+struct TxState {
+    /// Copies not yet completed by the inner queue, including held ones.
+    copies: u32,
+    /// Packets to complete once all copies are complete.
+    owed: u32,
+}
+
+/// Tracks transmit completions.
+///
+/// A packet is completed only once every copy of every packet sharing its ID
+/// is complete, since the guest may reuse the packet's memory afterwards.
+#[derive(Default)]
+struct TxTracker {
+    packets: HashMap<u32, TxState>,
+    done: VecDeque<TxId>,
+}
+
+impl TxTracker {
+    /// Tracks a packet sent as `copies` copies.
+    fn packet(&mut self, id: TxId, copies: u32) {
+        let state = self.packets.entry(id.0).or_default();
+        state.copies += copies;
+        state.owed += 1;
+        self.check(id);
+    }
+
+    /// Records the completion of a copy by the inner queue.
+    fn copy_done(&mut self, id: TxId) {
+        if let Some(state) = self.packets.get_mut(&id.0) {
+            state.copies -= 1;
+            self.check(id);
+        }
+    }
+
+    fn check(&mut self, id: TxId) {
+        if let Some(state) = self.packets.get(&id.0)
+            && state.copies == 0
+        {
+            let owed = state.owed as usize;
+            self.packets.remove(&id.0);
+            self.done.extend(std::iter::repeat_n(id, owed));
+        }
+    }
+}
+
+/// What the inner queue wrote to a receive buffer.
+#[derive(Default)]
+struct RxCopy {
+    metadata: Option<RxMetadata>,
+    /// The packet data, recorded only while duplication is enabled.
+    data: Option<Vec<u8>>,
+}
+
+/// Passes buffer accesses through to the frontend, recording what the inner
+/// queue writes to receive buffers: the metadata, for the packet length, and
+/// optionally the data, so that the receive can be duplicated.
+///
+/// Only packets the inner queue writes through [`BufferAccess`] are recorded,
+/// so receives written directly to guest memory are never duplicated.
+struct RecordingPool<'a> {
+    pool: &'a mut dyn BufferAccess,
+    copies: &'a mut HashMap<u32, RxCopy>,
+    record_data: bool,
+}
+
+impl BufferAccess for RecordingPool<'_> {
+    fn guest_memory(&self) -> &GuestMemory {
+        self.pool.guest_memory()
+    }
+
+    fn write_data(&mut self, id: RxId, data: &[u8]) {
+        if self.record_data {
+            self.copies.entry(id.0).or_default().data = Some(data.to_vec());
+        }
+        self.pool.write_data(id, data)
+    }
+
+    fn push_guest_addresses(&self, id: RxId, buf: &mut Vec<RxBufferSegment>) {
+        self.pool.push_guest_addresses(id, buf)
+    }
+
+    fn capacity(&self, id: RxId) -> u32 {
+        self.pool.capacity(id)
+    }
+
+    fn write_header(&mut self, id: RxId, metadata: &RxMetadata) {
+        self.copies.entry(id.0).or_default().metadata = Some(*metadata);
+        self.pool.write_header(id, metadata)
+    }
+
+    fn write_packet(&mut self, id: RxId, metadata: &RxMetadata, data: &[u8]) {
+        let copy = self.copies.entry(id.0).or_default();
+        copy.metadata = Some(*metadata);
+        if self.record_data {
+            copy.data = Some(data.to_vec());
+        }
+        self.pool.write_packet(id, metadata, data)
+    }
+
+    fn write_packet_segments(&mut self, id: RxId, metadata: &RxMetadata, segments: &[&[u8]]) {
+        let copy = self.copies.entry(id.0).or_default();
+        copy.metadata = Some(*metadata);
+        if self.record_data {
+            copy.data = Some(segments.concat());
+        }
+        self.pool.write_packet_segments(id, metadata, segments)
+    }
+}
+
+#[derive(InspectMut)]
+struct ImpairedQueue {
+    #[inspect(mut)]
+    inner: Box<dyn Queue>,
+    #[inspect(skip)]
+    timer: PolledTimer,
+    #[inspect(skip)]
+    settings: Arc<Settings>,
+    /// Copies of transmitted packets, as their segments.
+    tx: Link<Vec<TxSegment>>,
+    rx: Link<RxId>,
+    /// Released transmit segments not yet accepted by the inner queue.
+    #[inspect(with = "Vec::len")]
+    tx_staged: Vec<TxSegment>,
+    #[inspect(skip)]
+    tx_tracker: TxTracker,
+    #[inspect(skip)]
+    tx_scratch: Vec<TxId>,
+    #[inspect(with = "VecDeque::len")]
+    rx_ready: VecDeque<RxId>,
+    #[inspect(with = "Vec::len")]
+    rx_spare: Vec<RxId>,
+    #[inspect(skip)]
+    rx_copies: HashMap<u32, RxCopy>,
+    #[inspect(skip)]
+    rx_scratch: Vec<RxId>,
+}
+
+impl ImpairedQueue {
+    fn new(inner: Box<dyn Queue>, timer: PolledTimer, settings: Arc<Settings>, index: u64) -> Self {
+        // Give each direction of each queue its own random sequence.
+        let seed = settings.seed.wrapping_add(index * 2);
+        Self {
+            inner,
+            timer,
+            settings,
+            tx: Link::new(seed),
+            rx: Link::new(seed.wrapping_add(1)),
+            tx_staged: Vec::new(),
+            tx_tracker: TxTracker::default(),
+            tx_scratch: vec![TxId(0); 64],
+            rx_ready: VecDeque::new(),
+            rx_spare: Vec::new(),
+            rx_copies: HashMap::new(),
+            rx_scratch: vec![RxId(0); 64],
+        }
+    }
+
+    fn recording_pool<'a>(
+        copies: &'a mut HashMap<u32, RxCopy>,
+        settings: &Settings,
+        pool: &'a mut dyn BufferAccess,
+    ) -> RecordingPool<'a> {
+        RecordingPool {
+            pool,
+            copies,
+            record_data: settings.duplicate_ppm.load(Ordering::Relaxed) != 0,
+        }
+    }
+
+    /// Sends released transmits to the inner queue.
+    fn flush_tx(&mut self, pool: &mut dyn BufferAccess) -> anyhow::Result<()> {
+        let now = Instant::now();
+        while let Some(segments) = self.tx.pop(now) {
+            self.tx_staged.extend(segments);
+        }
+        if self.tx_staged.is_empty() {
+            return Ok(());
+        }
+        let mut pool = Self::recording_pool(&mut self.rx_copies, &self.settings, pool);
+        let (sync, n) = self.inner.tx_avail(&mut pool, &self.tx_staged)?;
+        if sync {
+            let mut segments = &self.tx_staged[..n];
+            while !segments.is_empty() {
+                let (metadata, _, rest) = next_packet(segments);
+                self.tx_tracker.copy_done(metadata.id);
+                segments = rest;
+            }
+        }
+        self.tx_staged.drain(..n);
+        Ok(())
+    }
+
+    /// Applies the impairments to a packet received by the inner queue.
+    fn impair_rx(
+        &mut self,
+        pool: &mut dyn BufferAccess,
+        params: &Params,
+        now: Instant,
+        id: RxId,
+        recycle: &mut Vec<RxId>,
+    ) {
+        let copy = self.rx_copies.remove(&id.0).unwrap_or_default();
+        let duplicate = match self.rx.fate(params) {
+            Fate::Lost => {
+                recycle.push(id);
+                return;
+            }
+            Fate::Deliver { duplicate } => duplicate,
+        };
+        let len = copy.metadata.map_or(0, |metadata| metadata.len);
+        if let Err(id) = self.rx.schedule(params, now, len, id) {
+            recycle.push(id);
+            return;
+        }
+        if duplicate
+            && let (Some(metadata), Some(data)) = (copy.metadata, copy.data)
+            && let Some(spare) = self.rx_spare.pop()
+        {
+            pool.write_data(spare, &data);
+            pool.write_header(spare, &metadata);
+            match self.rx.schedule(params, now, len, spare) {
+                Ok(()) => self.rx.stats.duplicated.increment(),
+                Err(spare) => self.rx_spare.push(spare),
+            }
+        }
+    }
+}
+
+impl Queue for ImpairedQueue {
+    fn poll_ready(&mut self, cx: &mut Context<'_>, pool: &mut dyn BufferAccess) -> Poll<()> {
+        let mut recording = Self::recording_pool(&mut self.rx_copies, &self.settings, pool);
+        if self.inner.poll_ready(cx, &mut recording).is_ready()
+            || !self.rx_ready.is_empty()
+            || !self.tx_tracker.done.is_empty()
+        {
+            return Poll::Ready(());
+        }
+        // Wake when the next held packet is due.
+        match [self.rx.next_release(), self.tx.next_release()]
+            .into_iter()
+            .flatten()
+            .min()
+        {
+            Some(deadline) => self.timer.poll_until(cx, deadline).map(drop),
+            None => Poll::Pending,
+        }
+    }
+
+    fn rx_avail(&mut self, pool: &mut dyn BufferAccess, done: &[RxId]) {
+        let duplicating = self.settings.duplicate_ppm.load(Ordering::Relaxed) != 0;
+        let mut pool = Self::recording_pool(&mut self.rx_copies, &self.settings, pool);
+        if duplicating {
+            let n = RX_SPARE_BUFFERS
+                .saturating_sub(self.rx_spare.len())
+                .min(done.len());
+            self.rx_spare.extend(&done[..n]);
+            self.inner.rx_avail(&mut pool, &done[n..]);
+        } else if !self.rx_spare.is_empty() {
+            let mut done = done.to_vec();
+            done.append(&mut self.rx_spare);
+            self.inner.rx_avail(&mut pool, &done);
+        } else {
+            self.inner.rx_avail(&mut pool, done);
+        }
+    }
+
+    fn rx_poll(
+        &mut self,
+        pool: &mut dyn BufferAccess,
+        packets: &mut [RxId],
+    ) -> anyhow::Result<usize> {
+        let params = self.settings.params();
+        let now = Instant::now();
+        let mut recycle = Vec::new();
+        let mut scratch = std::mem::take(&mut self.rx_scratch);
+        loop {
+            let mut recording = Self::recording_pool(&mut self.rx_copies, &self.settings, pool);
+            let n = self.inner.rx_poll(&mut recording, &mut scratch)?;
+            for &id in &scratch[..n] {
+                self.impair_rx(pool, &params, now, id, &mut recycle);
+            }
+            if n < scratch.len() {
+                break;
+            }
+        }
+        self.rx_scratch = scratch;
+        if !recycle.is_empty() {
+            let mut recording = Self::recording_pool(&mut self.rx_copies, &self.settings, pool);
+            self.inner.rx_avail(&mut recording, &recycle);
+        }
+
+        while let Some(id) = self.rx.pop(now) {
+            self.rx_ready.push_back(id);
+        }
+        let n = packets.len().min(self.rx_ready.len());
+        for (d, s) in packets.iter_mut().zip(self.rx_ready.drain(..n)) {
+            *d = s;
+        }
+        Ok(n)
+    }
+
+    fn tx_avail(
+        &mut self,
+        pool: &mut dyn BufferAccess,
+        segments: &[TxSegment],
+    ) -> anyhow::Result<(bool, usize)> {
+        let params = self.settings.params();
+        let now = Instant::now();
+        let mut rest = segments;
+        while !rest.is_empty() {
+            let (metadata, this, next) = next_packet(rest);
+            rest = next;
+            let id = metadata.id;
+            let len = metadata.len as usize;
+            let mut copies = 0;
+            if let Fate::Deliver { duplicate } = self.tx.fate(&params) {
+                for _ in 0..1 + u32::from(duplicate) {
+                    if self.tx.schedule(&params, now, len, this.to_vec()).is_ok() {
+                        copies += 1;
+                    }
+                }
+                if copies == 2 {
+                    self.tx.stats.duplicated.increment();
+                }
+            }
+            self.tx_tracker.packet(id, copies);
+        }
+        self.flush_tx(pool)?;
+        Ok((false, segments.len()))
+    }
+
+    fn tx_poll(
+        &mut self,
+        pool: &mut dyn BufferAccess,
+        done: &mut [TxId],
+    ) -> Result<usize, TxError> {
+        self.flush_tx(pool).map_err(TxError::TryRestart)?;
+        loop {
+            let mut recording = Self::recording_pool(&mut self.rx_copies, &self.settings, pool);
+            let n = self.inner.tx_poll(&mut recording, &mut self.tx_scratch)?;
+            for &id in &self.tx_scratch[..n] {
+                self.tx_tracker.copy_done(id);
+            }
+            if n < self.tx_scratch.len() {
+                break;
+            }
+        }
+        let n = done.len().min(self.tx_tracker.done.len());
+        for (d, s) in done.iter_mut().zip(self.tx_tracker.done.drain(..n)) {
+            *d = s;
+        }
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use net_backend::TxFlags;
+    use net_backend::TxMetadata;
+    use net_backend::TxSegmentType;
+    use net_backend::loopback::LoopbackEndpoint;
+    use net_backend::tests::Bufs;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use std::future::poll_fn;
+
+    async fn queue(driver: &DefaultDriver, config: &ImpairmentConfig) -> (Box<dyn Queue>, Bufs) {
+        let mut endpoint = ImpairedEndpoint::new(Box::new(LoopbackEndpoint::new()), config);
+        let mut queues = Vec::new();
+        endpoint
+            .get_queues(
+                vec![QueueConfig {
+                    driver: Box::new(driver.clone()),
+                }],
+                None,
+                &mut queues,
+            )
+            .await
+            .unwrap();
+        let mut bufs = Bufs::new(GuestMemory::allocate(128 * 2048));
+        let mut queue = queues.pop().unwrap();
+        // Buffers 0..64 are reserved for transmits.
+        queue.rx_avail(&mut bufs, &(64..128).map(RxId).collect::<Vec<_>>());
+        (queue, bufs)
+    }
+
+    fn send(queue: &mut dyn Queue, bufs: &mut Bufs, packets: impl IntoIterator<Item = u8>) {
+        let mut segments = Vec::new();
+        for n in packets {
+            let gpa = u64::from(n) * 2048;
+            bufs.guest_memory().write_at(gpa, &[n; 60]).unwrap();
+            segments.push(TxSegment {
+                ty: TxSegmentType::Head(TxMetadata {
+                    id: TxId(n.into()),
+                    segment_count: 1,
+                    flags: TxFlags::new(),
+                    len: 60,
+                    ..Default::default()
+                }),
+                gpa,
+                len: 60,
+            });
+        }
+        assert_eq!(
+            queue.tx_avail(bufs, &segments).unwrap(),
+            (false, segments.len())
+        );
+    }
+
+    /// Waits for the queue and returns the first byte of each received packet
+    /// and the IDs of the completed transmits.
+    async fn poll(queue: &mut dyn Queue, bufs: &mut Bufs) -> (Vec<u8>, Vec<u32>) {
+        poll_fn(|cx| queue.poll_ready(cx, bufs)).await;
+        let mut done = [TxId(0); 64];
+        let n = queue.tx_poll(bufs, &mut done).unwrap();
+        let completed = done[..n].iter().map(|id| id.0).collect();
+        let mut ids = [RxId(0); 64];
+        let n = queue.rx_poll(bufs, &mut ids).unwrap();
+        let received = ids[..n]
+            .iter()
+            .map(|id| {
+                assert_eq!(bufs.rx_metadata(*id).unwrap().len, 60);
+                let mut b = [0];
+                bufs.guest_memory()
+                    .read_at(u64::from(id.0) * 2048, &mut b)
+                    .unwrap();
+                b[0]
+            })
+            .collect();
+        queue.rx_avail(bufs, &ids[..n]);
+        (received, completed)
+    }
+
+    #[async_test]
+    async fn passthrough(driver: DefaultDriver) {
+        let (mut queue, mut bufs) = queue(&driver, &ImpairmentConfig::default()).await;
+        send(queue.as_mut(), &mut bufs, 0..4);
+        let (received, completed) = poll(queue.as_mut(), &mut bufs).await;
+        assert_eq!(received, [0, 1, 2, 3]);
+        assert_eq!(completed, [0, 1, 2, 3]);
+    }
+
+    #[async_test]
+    async fn delay(driver: DefaultDriver) {
+        let delay = Duration::from_millis(20);
+        let (mut queue, mut bufs) = queue(
+            &driver,
+            &ImpairmentConfig {
+                delay,
+                ..Default::default()
+            },
+        )
+        .await;
+        let start = Instant::now();
+        send(queue.as_mut(), &mut bufs, [1]);
+        let mut received = Vec::new();
+        while received.is_empty() {
+            received = poll(queue.as_mut(), &mut bufs).await.0;
+        }
+        // Each direction is delayed.
+        assert!(Instant::now().saturating_sub(start) >= delay * 2);
+        assert_eq!(received, [1]);
+    }
+
+    #[async_test]
+    async fn loss_completes_transmits(driver: DefaultDriver) {
+        let (mut queue, mut bufs) = queue(
+            &driver,
+            &ImpairmentConfig {
+                loss_ppm: 1_000_000,
+                ..Default::default()
+            },
+        )
+        .await;
+        send(queue.as_mut(), &mut bufs, 0..4);
+        let (received, completed) = poll(queue.as_mut(), &mut bufs).await;
+        assert!(received.is_empty());
+        assert_eq!(completed, [0, 1, 2, 3]);
+    }
+
+    #[async_test]
+    async fn duplication(driver: DefaultDriver) {
+        let (mut queue, mut bufs) = queue(
+            &driver,
+            &ImpairmentConfig {
+                duplicate_ppm: 1_000_000,
+                ..Default::default()
+            },
+        )
+        .await;
+        send(queue.as_mut(), &mut bufs, [7]);
+        let (mut received, completed) = poll(queue.as_mut(), &mut bufs).await;
+        // The packet is duplicated on transmit, then each copy is duplicated
+        // on receive, but the transmit completes once.
+        received.sort();
+        assert_eq!(received, [7, 7, 7, 7]);
+        assert_eq!(completed, [7]);
+    }
+}
@@ -0,0 +1,319 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The impairment model for one direction of a queue.
+
+use inspect::Inspect;
+use inspect_counters::Counter;
+use pal_async::timer::Instant;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+/// The maximum number of packets held by a link. Packets arriving at a full
+/// link are dropped, as they would be by a router with a full buffer.
+const QUEUE_LIMIT: usize = 1000;
+
+/// A snapshot of the impairment settings.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Params {
+    pub delay: Duration,
+    pub jitter: Duration,
+    pub loss_ppm: u32,
+    pub duplicate_ppm: u32,
+    pub reorder_ppm: u32,
+    /// Bits per second, or zero for no cap.
+    pub rate: u64,
+}
+
+/// What happens to a packet entering a link.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Fate {
+    Lost,
+    Deliver { duplicate: bool },
+}
+
+/// A deterministic pseudorandom number generator (SplitMix64).
+struct Rng(u64);
+
+impl Rng {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a value in `0..n`.
+    fn below(&mut self, n: u64) -> u64 {
+        self.next_u64() % n
+    }
+
+    fn chance(&mut self, ppm: u32) -> bool {
+        self.below(1_000_000) < ppm.into()
+    }
+}
+
+struct Held<T> {
+    release: Instant,
+    seq: u64,
+    item: T,
+}
+
+// Ordered so that the earliest release is the greatest, for `BinaryHeap`.
+// Packets released at the same time keep their order.
+impl<T> Ord for Held<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (other.release, other.seq).cmp(&(self.release, self.seq))
+    }
+}
+
+impl<T> PartialOrd for Held<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> PartialEq for Held<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for Held<T> {}
+
+#[derive(Inspect, Default)]
+pub(crate) struct LinkStats {
+    pub lost: Counter,
+    pub duplicated: Counter,
+    pub reordered: Counter,
+    pub overflow: Counter,
+}
+
+/// Packets in flight in one direction, and the random choices made for them.
+pub(crate) struct Link<T> {
+    rng: Rng,
+    /// When the packets already sent will have been serialized at the
+    /// configured rate.
+    busy_until: Option<Instant>,
+    held: BinaryHeap<Held<T>>,
+    next_seq: u64,
+    pub stats: LinkStats,
+}
+
+impl<T> Inspect for Link<T> {
+    fn inspect(&self, req: inspect::Request<'_>) {
+        req.respond()
+            .field("held", self.held.len())
+            .merge(&self.stats);
+    }
+}
+
+impl<T> Link<T> {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: Rng(seed),
+            busy_until: None,
+            held: BinaryHeap::new(),
+            next_seq: 0,
+            stats: Default::default(),
+        }
+    }
+
+    /// Decides whether a packet is lost or duplicated.
+    pub fn fate(&mut self, params: &Params) -> Fate {
+        let lost = self.rng.chance(params.loss_ppm);
+        let duplicate = self.rng.chance(params.duplicate_ppm);
+        if lost {
+            self.stats.lost.increment();
+            Fate::Lost
+        } else {
+            Fate::Deliver { duplicate }
+        }
+    }
+
+    /// Holds a packet of `len` bytes until it should be delivered. Returns the
+    /// packet back if the link is full.
+    pub fn schedule(
+        &mut self,
+        params: &Params,
+        now: Instant,
+        len: usize,
+        item: T,
+    ) -> Result<(), T> {
+        let reorder = self.rng.chance(params.reorder_ppm);
+        let jitter = params.jitter.as_nanos() as i128;
+        let jitter = self.rng.below(2 * jitter as u64 + 1) as i128 - jitter;
+        if self.held.len() >= QUEUE_LIMIT {
+            self.stats.overflow.increment();
+            return Err(item);
+        }
+
+        let mut release = now;
+        if params.rate != 0 {
+            let start = self.busy_until.map_or(now, |t| t.max(now));
+            let nanos = len as u128 * 8 * 1_000_000_000 / u128::from(params.rate);
+            release =
+                start.saturating_add(Duration::from_nanos(nanos.try_into().unwrap_or(u64::MAX)));
+            self.busy_until = Some(release);
+        }
+
+        let delay = (params.delay.as_nanos() as i128 + jitter).max(0) as u64;
+        if reorder && delay != 0 {
+            // Skip the delay, overtaking packets still in flight.
+            self.stats.reordered.increment();
+        } else {
+            release = release.saturating_add(Duration::from_nanos(delay));
+        }
+
+        self.held.push(Held {
+            release,
+            seq: self.next_seq,
+            item,
+        });
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    /// Returns the next packet due for delivery at `now`.
+    pub fn pop(&mut self, now: Instant) -> Option<T> {
+        if self.held.peek()?.release <= now {
+            Some(self.held.pop().unwrap().item)
+        } else {
+            None
+        }
+    }
+
+    /// Returns when the next packet is due.
+    pub fn next_release(&self) -> Option<Instant> {
+        Some(self.held.peek()?.release)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MS: Duration = Duration::from_millis(1);
+
+    fn drain_at(link: &mut Link<u32>, now: Instant) -> Vec<u32> {
+        std::iter::from_fn(|| link.pop(now)).collect()
+    }
+
+    #[test]
+    fn delay() {
+        let mut link = Link::new(0);
+        let params = Params {
+            delay: 10 * MS,
+            ..Default::default()
+        };
+        let now = Instant::from_nanos(0);
+        for i in 0..3 {
+            link.schedule(&params, now.saturating_add(i * MS), 100, i)
+                .unwrap();
+        }
+        assert_eq!(link.next_release(), Some(now.saturating_add(10 * MS)));
+        assert_eq!(drain_at(&mut link, now.saturating_add(11 * MS)), [0, 1]);
+        assert_eq!(drain_at(&mut link, now.saturating_add(12 * MS)), [2]);
+        assert_eq!(link.next_release(), None);
+    }
+
+    #[test]
+    fn jitter_bounds() {
+        let mut link = Link::new(1);
+        let params = Params {
+            delay: 10 * MS,
+            jitter: 5 * MS,
+            ..Default::default()
+        };
+        let now = Instant::from_nanos(0);
+        for i in 0..1000 {
+            link.schedule(&params, now, 100, i).unwrap();
+        }
+        assert!(link.next_release().unwrap() >= now.saturating_add(5 * MS));
+        assert!(drain_at(&mut link, now.saturating_add(5 * MS)).len() < 10);
+        assert_eq!(drain_at(&mut link, now.saturating_add(15 * MS)).len(), 1000);
+    }
+
+    #[test]
+    fn rate() {
+        let mut link = Link::new(0);
+        // 1000 bytes take 1ms at 8 Mbps.
+        let params = Params {
+            rate: 8_000_000,
+            ..Default::default()
+        };
+        let now = Instant::from_nanos(0);
+        for i in 0..4 {
+            link.schedule(&params, now, 1000, i).unwrap();
+        }
+        assert_eq!(drain_at(&mut link, now.saturating_add(2 * MS)), [0, 1]);
+        // The link is idle again after the backlog drains.
+        let later = now.saturating_add(10 * MS);
+        link.schedule(&params, later, 1000, 4).unwrap();
+        assert_eq!(link.next_release(), Some(later.saturating_add(MS)));
+    }
+
+    #[test]
+    fn reorder() {
+        let mut link = Link::new(0);
+        let params = Params {
+            delay: 10 * MS,
+            reorder_ppm: 1_000_000,
+            ..Default::default()
+        };
+        let now = Instant::from_nanos(0);
+        link.schedule(
+            &Params {
+                reorder_ppm: 0,
+                ..params.clone()
+            },
+            now,
+            100,
+            0,
+        )
+        .unwrap();
+        link.schedule(&params, now, 100, 1).unwrap();
+        assert_eq!(drain_at(&mut link, now.saturating_add(10 * MS)), [1, 0]);
+        assert_eq!(link.stats.reordered.get(), 1);
+    }
+
+    #[test]
+    fn loss_and_duplication_are_deterministic() {
+        let params = Params {
+            loss_ppm: 300_000,
+            duplicate_ppm: 200_000,
+            ..Default::default()
+        };
+        let fates = |seed| {
+            let mut link = Link::<()>::new(seed);
+            (0..1000).map(|_| link.fate(&params)).collect::<Vec<_>>()
+        };
+        let a = fates(7);
+        assert_eq!(a, fates(7));
+        assert_ne!(a, fates(8));
+        let lost = a.iter().filter(|f| **f == Fate::Lost).count();
+        assert!((200..400).contains(&lost), "{lost}");
+        let duplicated = a
+            .iter()
+            .filter(|f| **f == Fate::Deliver { duplicate: true })
+            .count();
+        assert!((80..200).contains(&duplicated), "{duplicated}");
+    }
+
+    #[test]
+    fn overflow() {
+        let mut link = Link::new(0);
+        let params = Params {
+            delay: MS,
+            ..Default::default()
+        };
+        let now = Instant::from_nanos(0);
+        for i in 0..QUEUE_LIMIT as u32 {
+            link.schedule(&params, now, 100, i).unwrap();
+        }
+        assert_eq!(link.schedule(&params, now, 100, 0), Err(0));
+        assert_eq!(link.stats.overflow.get(), 1);
+    }
+}
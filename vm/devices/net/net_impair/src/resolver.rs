@@ -0,0 +1,45 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resolver for impaired endpoints.
+
+use crate::ImpairedEndpoint;
+use async_trait::async_trait;
+use net_backend::resolve::ResolveEndpointParams;
+use net_backend::resolve::ResolvedEndpoint;
+use net_backend_resources::impair::ImpairedHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::NetEndpointHandleKind;
+
+/// A resolver for endpoints that impair another endpoint's packets.
+pub struct ImpairedResolver;
+
+declare_static_async_resolver! {
+    ImpairedResolver,
+    (NetEndpointHandleKind, ImpairedHandle),
+}
+
+#[async_trait]
+impl AsyncResolveResource<NetEndpointHandleKind, ImpairedHandle> for ImpairedResolver {
+    type Output = ResolvedEndpoint;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: ImpairedHandle,
+        input: ResolveEndpointParams,
+    ) -> Result<Self::Output, Self::Error> {
+        let inner = resolver
+            .resolve(
+                resource.endpoint,
+                ResolveEndpointParams {
+                    mac_address: input.mac_address,
+                },
+            )
+            .await?;
+        Ok(ImpairedEndpoint::new(inner.0, &resource.config).into())
+    }
+}
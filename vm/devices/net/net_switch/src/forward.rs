@@ -0,0 +1,335 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The switch's forwarding logic: address learning, flooding, and 802.1Q VLAN
+//! handling.
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::time::Duration;
+use std::time::Instant;
+
+/// How long a learned address remains valid without traffic from it.
+const AGING_TIME: Duration = Duration::from_secs(300);
+/// The maximum number of learned addresses. Frames to addresses that cannot be
+/// learned are flooded.
+const MAX_ADDRESSES: usize = 4096;
+
+const ETHERNET_HEADER_LEN: usize = 14;
+const VLAN_TAG_LEN: usize = 4;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const VLAN_ID_MASK: u16 = 0xfff;
+
+/// The VLAN ID of the untagged network on trunk ports.
+const UNTAGGED: u16 = 0;
+
+/// A switch port identifier.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub(crate) struct PortId(pub u64);
+
+/// The VLAN mode of a port.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub(crate) enum PortMode {
+    /// Untagged frames on the given VLAN.
+    Access(u16),
+    /// Untagged frames on the untagged network, and tagged frames on their
+    /// VLANs.
+    Trunk,
+}
+
+impl PortMode {
+    fn carries(&self, vlan: u16) -> bool {
+        match *self {
+            PortMode::Access(id) => id == vlan,
+            PortMode::Trunk => true,
+        }
+    }
+}
+
+struct Learned {
+    port: PortId,
+    last_seen: Instant,
+}
+
+/// A MAC-learning forwarding table.
+pub(crate) struct Forwarder {
+    ports: BTreeMap<PortId, PortMode>,
+    addresses: HashMap<([u8; 6], u16), Learned>,
+}
+
+impl Forwarder {
+    pub fn new() -> Self {
+        Self {
+            ports: BTreeMap::new(),
+            addresses: HashMap::new(),
+        }
+    }
+
+    pub fn add_port(&mut self, port: PortId, mode: PortMode) {
+        self.ports.insert(port, mode);
+    }
+
+    /// Removes a port, forgetting the addresses learned on it.
+    pub fn remove_port(&mut self, port: PortId) {
+        self.ports.remove(&port);
+        self.addresses.retain(|_, learned| learned.port != port);
+    }
+
+    pub fn address_count(&self) -> usize {
+        self.addresses.len()
+    }
+
+    /// Forwards `frame`, received on port `from`, calling `deliver` with each
+    /// destination port and the frame as it should be delivered there.
+    ///
+    /// Malformed frames, and tagged frames received on access ports, are
+    /// dropped; returns false if the frame was dropped for either reason.
+    pub fn forward(
+        &mut self,
+        from: PortId,
+        frame: &[u8],
+        now: Instant,
+        mut deliver: impl FnMut(PortId, Vec<u8>),
+    ) -> bool {
+        let Some(&mode) = self.ports.get(&from) else {
+            return false;
+        };
+        if frame.len() < ETHERNET_HEADER_LEN {
+            return false;
+        }
+
+        // Classify the frame onto a VLAN, keeping the tag control information
+        // so that trunk ports see the original priority.
+        let ethertype = u16::from_be_bytes([frame[12], frame[13]]);
+        let (tci, untagged) = if ethertype == ETHERTYPE_VLAN {
+            if mode != PortMode::Trunk || frame.len() < ETHERNET_HEADER_LEN + VLAN_TAG_LEN {
+                return false;
+            }
+            let tci = u16::from_be_bytes([frame[14], frame[15]]);
+            let mut untagged = Vec::with_capacity(frame.len() - VLAN_TAG_LEN);
+            untagged.extend_from_slice(&frame[..12]);
+            untagged.extend_from_slice(&frame[12 + VLAN_TAG_LEN..]);
+            (tci, Cow::Owned(untagged))
+        } else {
+            let vlan = match mode {
+                PortMode::Access(id) => id,
+                PortMode::Trunk => UNTAGGED,
+            };
+            (vlan, Cow::Borrowed(frame))
+        };
+        let vlan = tci & VLAN_ID_MASK;
+
+        let dst: [u8; 6] = frame[..6].try_into().unwrap();
+        let src: [u8; 6] = frame[6..12].try_into().unwrap();
+        if !is_multicast(&src) {
+            self.learn(src, vlan, from, now);
+        }
+
+        let known = (!is_multicast(&dst))
+            .then(|| self.addresses.get(&(dst, vlan)))
+            .flatten()
+            .filter(|learned| now.duration_since(learned.last_seen) < AGING_TIME)
+            .map(|learned| learned.port);
+
+        let egress = |mode: PortMode| match mode {
+            PortMode::Trunk if vlan != UNTAGGED => tag(&untagged, tci),
+            _ => untagged.to_vec(),
+        };
+        match known {
+            // The destination is on the port the frame came from.
+            Some(port) if port == from => {}
+            Some(port) => {
+                if let Some(&mode) = self.ports.get(&port) {
+                    deliver(port, egress(mode));
+                }
+            }
+            None => {
+                for (&port, &mode) in &self.ports {
+                    if port != from && mode.carries(vlan) {
+                        deliver(port, egress(mode));
+                    }
+                }
+            }
+        }
+        true
+    }
+
+    fn learn(&mut self, mac: [u8; 6], vlan: u16, port: PortId, now: Instant) {
+        if self.addresses.len() >= MAX_ADDRESSES && !self.addresses.contains_key(&(mac, vlan)) {
+            self.addresses
+                .retain(|_, learned| now.duration_since(learned.last_seen) < AGING_TIME);
+            if self.addresses.len() >= MAX_ADDRESSES {
+                return;
+            }
+        }
+        self.addresses.insert(
+            (mac, vlan),
+            Learned {
+                port,
+                last_seen: now,
+            },
+        );
+    }
+}
+
+fn is_multicast(mac: &[u8; 6]) -> bool {
+    mac[0] & 1 != 0
+}
+
+/// Inserts an 802.1Q tag into an untagged frame.
+fn tag(frame: &[u8], tci: u16) -> Vec<u8> {
+    let mut tagged = Vec::with_capacity(frame.len() + VLAN_TAG_LEN);
+    tagged.extend_from_slice(&frame[..12]);
+    tagged.extend_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+    tagged.extend_from_slice(&tci.to_be_bytes());
+    tagged.extend_from_slice(&frame[12..]);
+    tagged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BROADCAST: [u8; 6] = [0xff; 6];
+
+    fn mac(n: u8) -> [u8; 6] {
+        [0x02, 0, 0, 0, 0, n]
+    }
+
+    fn frame(dst: [u8; 6], src: [u8; 6]) -> Vec<u8> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(&src);
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(&[0xaa; 46]);
+        frame
+    }
+
+    fn forward(
+        forwarder: &mut Forwarder,
+        from: u64,
+        frame: &[u8],
+        now: Instant,
+    ) -> Vec<(u64, Vec<u8>)> {
+        let mut out = Vec::new();
+        forwarder.forward(PortId(from), frame, now, |port, frame| {
+            out.push((port.0, frame))
+        });
+        out
+    }
+
+    fn ports(out: &[(u64, Vec<u8>)]) -> Vec<u64> {
+        out.iter().map(|(port, _)| *port).collect()
+    }
+
+    fn forwarder(modes: &[PortMode]) -> Forwarder {
+        let mut forwarder = Forwarder::new();
+        for (i, &mode) in modes.iter().enumerate() {
+            forwarder.add_port(PortId(i as u64), mode);
+        }
+        forwarder
+    }
+
+    #[test]
+    fn learning() {
+        let mut f = forwarder(&[PortMode::Trunk; 3]);
+        let now = Instant::now();
+
+        // Unknown unicast and broadcast are flooded.
+        let out = forward(&mut f, 0, &frame(mac(1), mac(0)), now);
+        assert_eq!(ports(&out), [1, 2]);
+        assert_eq!(out[0].1, frame(mac(1), mac(0)));
+        assert_eq!(
+            ports(&forward(&mut f, 1, &frame(BROADCAST, mac(1)), now)),
+            [0, 2]
+        );
+
+        // Learned addresses are forwarded directly.
+        assert_eq!(ports(&forward(&mut f, 2, &frame(mac(0), mac(2)), now)), [0]);
+        assert_eq!(ports(&forward(&mut f, 0, &frame(mac(1), mac(0)), now)), [1]);
+
+        // Frames to an address on the sending port are filtered.
+        assert!(forward(&mut f, 0, &frame(mac(0), mac(3)), now).is_empty());
+
+        // Addresses move when a station moves.
+        forward(&mut f, 2, &frame(BROADCAST, mac(1)), now);
+        assert_eq!(ports(&forward(&mut f, 0, &frame(mac(1), mac(0)), now)), [2]);
+    }
+
+    #[test]
+    fn aging_and_port_removal() {
+        let mut f = forwarder(&[PortMode::Trunk; 3]);
+        let now = Instant::now();
+        forward(&mut f, 1, &frame(BROADCAST, mac(1)), now);
+        forward(&mut f, 2, &frame(BROADCAST, mac(2)), now);
+        assert_eq!(f.address_count(), 2);
+
+        let later = now + AGING_TIME;
+        assert_eq!(
+            ports(&forward(&mut f, 0, &frame(mac(1), mac(0)), later)),
+            [1, 2]
+        );
+
+        f.remove_port(PortId(2));
+        assert_eq!(f.address_count(), 2);
+        assert_eq!(
+            ports(&forward(&mut f, 0, &frame(mac(2), mac(0)), later)),
+            [1]
+        );
+    }
+
+    #[test]
+    fn vlans() {
+        let mut f = forwarder(&[
+            PortMode::Access(10),
+            PortMode::Access(10),
+            PortMode::Access(20),
+            PortMode::Trunk,
+        ]);
+        let now = Instant::now();
+        let untagged = frame(BROADCAST, mac(0));
+        let tagged = tag(&untagged, 10 | (5 << 13));
+
+        // Access ports only reach their VLAN, and trunk ports see the frame
+        // tagged.
+        let out = forward(&mut f, 0, &untagged, now);
+        assert_eq!(ports(&out), [1, 3]);
+        assert_eq!(out[0].1, untagged);
+        assert_eq!(out[1].1, tag(&untagged, 10));
+
+        // Tagged frames from trunk ports reach the VLAN's access ports
+        // untagged.
+        let out = forward(&mut f, 3, &tagged, now);
+        assert_eq!(ports(&out), [0, 1]);
+        assert_eq!(out[0].1, untagged);
+
+        // Untagged frames from trunk ports do not reach access ports.
+        assert!(forward(&mut f, 3, &frame(BROADCAST, mac(3)), now).is_empty());
+
+        // Access ports drop tagged frames.
+        assert!(!f.forward(PortId(2), &tagged, now, |_, _| panic!()));
+
+        // Learning is per VLAN.
+        let out = forward(&mut f, 2, &frame(mac(0), mac(2)), now);
+        assert_eq!(ports(&out), [3]);
+        assert_eq!(out[0].1, tag(&frame(mac(0), mac(2)), 20));
+    }
+
+    #[test]
+    fn trunk_priority_is_preserved() {
+        let mut f = forwarder(&[PortMode::Trunk, PortMode::Trunk]);
+        let tagged = tag(&frame(BROADCAST, mac(0)), 7 | (3 << 13));
+        let out = forward(&mut f, 0, &tagged, Instant::now());
+        assert_eq!(out, [(1, tagged)]);
+    }
+
+    #[test]
+    fn runt_frames() {
+        let mut f = forwarder(&[PortMode::Trunk, PortMode::Trunk]);
+        assert!(!f.forward(PortId(0), &[0; 13], Instant::now(), |_, _| panic!()));
+        let mut short_tag = frame(BROADCAST, mac(0))[..14].to_vec();
+        short_tag[12..].copy_from_slice(&ETHERTYPE_VLAN.to_be_bytes());
+        assert!(!f.forward(PortId(0), &short_tag, Instant::now(), |_, _| panic!()));
+    }
+}
@@ -0,0 +1,434 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A software Ethernet switch, and a network endpoint that is a port on it.
+//!
+//! The switch learns the source MAC address of each frame. Frames to a learned
+//! unicast address go only to the port the address was learned on; broadcast,
+//! multicast, and unknown unicast frames are flooded to every other port on the
+//! same VLAN. Each port is either an access port on a single VLAN or a trunk
+//! port that carries 802.1Q-tagged frames for any VLAN.
+//!
+//! Ports talk to the switch over mesh channels, so NICs in other processes can
+//! attach to it. [`socket`] shares a switch with other processes over a Unix
+//! socket.
+
+#![forbid(unsafe_code)]
+
+mod forward;
+pub mod resolver;
+pub mod socket;
+
+use crate::forward::Forwarder;
+use crate::forward::PortId;
+use crate::forward::PortMode;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use futures::StreamExt;
+use futures::stream::SelectAll;
+use futures_concurrency::future::Race;
+use inspect::InspectMut;
+use inspect_counters::Counter;
+use mesh::rpc::RpcSend;
+use net_backend::BufferAccess;
+use net_backend::Endpoint;
+use net_backend::Queue;
+use net_backend::QueueConfig;
+use net_backend::RssConfig;
+use net_backend::RxId;
+use net_backend::RxMetadata;
+use net_backend::TxError;
+use net_backend::TxId;
+use net_backend::TxSegment;
+use net_backend::linearize;
+use net_backend::next_packet;
+use net_backend_resources::switch::SwitchPortConnection;
+use net_backend_resources::switch::SwitchRequest;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::future::pending;
+use std::task::Context;
+use std::task::Poll;
+use std::time::Instant;
+
+/// The valid range of 802.1Q VLAN IDs for access ports.
+pub const VLAN_IDS: std::ops::RangeInclusive<u16> = 1..=4094;
+
+/// A running software Ethernet switch.
+///
+/// The switch stops when this is dropped.
+pub struct Switch {
+    send: mesh::Sender<SwitchRequest>,
+    _task: Task<()>,
+}
+
+impl Switch {
+    /// Starts a new switch with no ports.
+    pub fn new(spawn: impl Spawn) -> Self {
+        let (send, recv) = mesh::channel();
+        let task = spawn.spawn("net-switch", run_switch(recv));
+        Self { send, _task: task }
+    }
+
+    /// Returns a sender for attaching ports to the switch.
+    pub fn sender(&self) -> mesh::Sender<SwitchRequest> {
+        self.send.clone()
+    }
+}
+
+async fn run_switch(mut requests: mesh::Receiver<SwitchRequest>) {
+    enum Event {
+        Request(Option<SwitchRequest>),
+        Frame(PortId, Option<Vec<u8>>),
+    }
+
+    let mut forwarder = Forwarder::new();
+    let mut ports = HashMap::new();
+    let mut port_frames = SelectAll::new();
+    let mut next_port = 0;
+    let mut accepting = true;
+    loop {
+        let request = async {
+            if accepting {
+                Event::Request(requests.next().await)
+            } else {
+                pending().await
+            }
+        };
+        let frame = async {
+            match port_frames.next().await {
+                Some((port, frame)) => Event::Frame(port, frame),
+                None => pending().await,
+            }
+        };
+        match (request, frame).race().await {
+            Event::Request(Some(SwitchRequest::Connect(rpc))) => {
+                rpc.handle_failable_sync(|connection| {
+                    let SwitchPortConnection {
+                        vlan,
+                        from_port,
+                        to_port,
+                    } = connection;
+                    let mode = match vlan {
+                        None => PortMode::Trunk,
+                        Some(id) if VLAN_IDS.contains(&id) => PortMode::Access(id),
+                        Some(id) => anyhow::bail!("invalid vlan id {id}"),
+                    };
+                    let port = PortId(next_port);
+                    next_port += 1;
+                    tracing::debug!(port = port.0, ?mode, "switch port connected");
+                    forwarder.add_port(port, mode);
+                    ports.insert(port, to_port);
+                    port_frames.push(
+                        from_port
+                            .map(move |frame| (port, Some(frame)))
+                            .chain(futures::stream::once(async move { (port, None) }))
+                            .boxed(),
+                    );
+                    Ok(())
+                });
+            }
+            Event::Request(None) => {
+                accepting = false;
+                if ports.is_empty() {
+                    break;
+                }
+            }
+            Event::Frame(port, Some(frame)) => {
+                forwarder.forward(port, &frame, Instant::now(), |to, frame| {
+                    ports[&to].send(frame);
+                });
+            }
+            Event::Frame(port, None) => {
+                tracing::debug!(port = port.0, "switch port disconnected");
+                forwarder.remove_port(port);
+                ports.remove(&port);
+                if !accepting && ports.is_empty() {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// An endpoint that is a port on a software Ethernet switch.
+///
+/// The port is attached to the switch while the endpoint's queue exists.
+#[derive(InspectMut)]
+pub struct SwitchEndpoint {
+    #[inspect(skip)]
+    switch: mesh::Sender<SwitchRequest>,
+    vlan: Option<u16>,
+}
+
+impl SwitchEndpoint {
+    /// Returns a new endpoint for `switch`, as an access port on `vlan` or,
+    /// if `vlan` is `None`, as a trunk port.
+    pub fn new(switch: mesh::Sender<SwitchRequest>, vlan: Option<u16>) -> Self {
+        Self { switch, vlan }
+    }
+}
+
+#[async_trait]
+impl Endpoint for SwitchEndpoint {
+    fn endpoint_type(&self) -> &'static str {
+        "switch"
+    }
+
+    async fn get_queues(
+        &mut self,
+        config: Vec<QueueConfig>,
+        _rss: Option<&RssConfig<'_>>,
+        queues: &mut Vec<Box<dyn Queue>>,
+    ) -> anyhow::Result<()> {
+        assert_eq!(config.len(), 1);
+        let (send, from_port) = mesh::channel();
+        let (to_port, recv) = mesh::channel();
+        self.switch
+            .call_failable(
+                SwitchRequest::Connect,
+                SwitchPortConnection {
+                    vlan: self.vlan,
+                    from_port,
+                    to_port,
+                },
+            )
+            .await
+            .context("failed to attach to the switch")?;
+        queues.push(Box::new(SwitchQueue {
+            send,
+            recv,
+            rx_avail: VecDeque::new(),
+            rx_ready: VecDeque::new(),
+            stats: Default::default(),
+        }));
+        Ok(())
+    }
+
+    async fn stop(&mut self) {}
+
+    fn is_ordered(&self) -> bool {
+        true
+    }
+
+    fn tx_fast_completions(&self) -> bool {
+        true
+    }
+}
+
+#[derive(InspectMut)]
+struct SwitchQueue {
+    #[inspect(skip)]
+    send: mesh::Sender<Vec<u8>>,
+    #[inspect(skip)]
+    recv: mesh::Receiver<Vec<u8>>,
+    #[inspect(with = "VecDeque::len")]
+    rx_avail: VecDeque<RxId>,
+    #[inspect(with = "VecDeque::len")]
+    rx_ready: VecDeque<RxId>,
+    #[inspect(flatten)]
+    stats: QueueStats,
+}
+
+#[derive(inspect::Inspect, Default)]
+struct QueueStats {
+    rx_packets: Counter,
+    rx_dropped: Counter,
+    tx_packets: Counter,
+}
+
+impl Queue for SwitchQueue {
+    fn poll_ready(&mut self, cx: &mut Context<'_>, pool: &mut dyn BufferAccess) -> Poll<()> {
+        // Like a physical NIC, drop frames that arrive when there are no
+        // receive buffers, rather than letting them queue without bound.
+        while let Poll::Ready(Ok(frame)) = self.recv.poll_recv(cx) {
+            match self.rx_avail.front() {
+                Some(&id) if frame.len() <= pool.capacity(id) as usize => {
+                    self.rx_avail.pop_front();
+                    pool.write_packet(
+                        id,
+                        &RxMetadata {
+                            offset: 0,
+                            len: frame.len(),
+                            ..Default::default()
+                        },
+                        &frame,
+                    );
+                    self.rx_ready.push_back(id);
+                    self.stats.rx_packets.increment();
+                }
+                _ => self.stats.rx_dropped.increment(),
+            }
+        }
+        if self.rx_ready.is_empty() {
+            Poll::Pending
+        } else {
+            Poll::Ready(())
+        }
+    }
+
+    fn rx_avail(&mut self, _pool: &mut dyn BufferAccess, done: &[RxId]) {
+        self.rx_avail.extend(done);
+    }
+
+    fn rx_poll(
+        &mut self,
+        _pool: &mut dyn BufferAccess,
+        packets: &mut [RxId],
+    ) -> anyhow::Result<usize> {
+        let n = packets.len().min(self.rx_ready.len());
+        for (d, s) in packets.iter_mut().zip(self.rx_ready.drain(..n)) {
+            *d = s;
+        }
+        Ok(n)
+    }
+
+    fn tx_avail(
+        &mut self,
+        pool: &mut dyn BufferAccess,
+        mut segments: &[TxSegment],
+    ) -> anyhow::Result<(bool, usize)> {
+        let n = segments.len();
+        while !segments.is_empty() {
+            let (meta, _, _) = next_packet(segments);
+            let vlan = meta.vlan;
+            let mut frame = linearize(pool, &mut segments)?;
+            // Apply an offloaded VLAN tag in-band.
+            if let Some(vlan) = vlan {
+                if frame.len() >= 12 {
+                    let tci = (u16::from(vlan.priority()) << 13)
+                        | (u16::from(vlan.drop_eligible_indicator()) << 12)
+                        | vlan.vlan_id();
+                    let [hi, lo] = tci.to_be_bytes();
+                    frame.splice(12..12, [0x81, 0x00, hi, lo]);
+                }
+            }
+            self.send.send(frame);
+            self.stats.tx_packets.increment();
+        }
+        Ok((true, n))
+    }
+
+    fn tx_poll(
+        &mut self,
+        _pool: &mut dyn BufferAccess,
+        _done: &mut [TxId],
+    ) -> Result<usize, TxError> {
+        Ok(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guestmem::GuestMemory;
+    use net_backend::TxFlags;
+    use net_backend::TxMetadata;
+    use net_backend::TxSegmentType;
+    use net_backend::tests::Bufs;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use std::future::poll_fn;
+
+    struct Port {
+        queue: Box<dyn Queue>,
+        bufs: Bufs,
+    }
+
+    impl Port {
+        async fn new(driver: &DefaultDriver, switch: &Switch, vlan: Option<u16>) -> Self {
+            let mut endpoint = SwitchEndpoint::new(switch.sender(), vlan);
+            let mut queues = Vec::new();
+            endpoint
+                .get_queues(
+                    vec![QueueConfig {
+                        driver: Box::new(driver.clone()),
+                    }],
+                    None,
+                    &mut queues,
+                )
+                .await
+                .unwrap();
+            let mut bufs = Bufs::new(GuestMemory::allocate(64 * 2048));
+            let mut queue = queues.pop().unwrap();
+            queue.rx_avail(&mut bufs, &(1..32).map(RxId).collect::<Vec<_>>());
+            Self { queue, bufs }
+        }
+
+        fn send(&mut self, frame: &[u8]) {
+            // Buffer 0 is reserved for transmits.
+            self.bufs.guest_memory().write_at(0, frame).unwrap();
+            let segments = [TxSegment {
+                ty: TxSegmentType::Head(TxMetadata {
+                    id: TxId(0),
+                    segment_count: 1,
+                    flags: TxFlags::new(),
+                    len: frame.len() as u32,
+                    ..Default::default()
+                }),
+                gpa: 0,
+                len: frame.len() as u32,
+            }];
+            assert_eq!(
+                self.queue.tx_avail(&mut self.bufs, &segments).unwrap(),
+                (true, 1)
+            );
+        }
+
+        async fn recv(&mut self) -> Vec<u8> {
+            poll_fn(|cx| self.queue.poll_ready(cx, &mut self.bufs)).await;
+            let mut id = [RxId(0)];
+            assert_eq!(self.queue.rx_poll(&mut self.bufs, &mut id).unwrap(), 1);
+            let metadata = self.bufs.rx_metadata(id[0]).unwrap();
+            let mut frame = vec![0; metadata.len];
+            self.bufs
+                .guest_memory()
+                .read_at(id[0].0 as u64 * 2048, &mut frame)
+                .unwrap();
+            frame
+        }
+    }
+
+    fn frame(dst: [u8; 6], src: [u8; 6], payload: u8) -> Vec<u8> {
+        let mut frame = Vec::new();
+        frame.extend_from_slice(&dst);
+        frame.extend_from_slice(&src);
+        frame.extend_from_slice(&0x0800u16.to_be_bytes());
+        frame.extend_from_slice(&[payload; 46]);
+        frame
+    }
+
+    #[async_test]
+    async fn endpoints(driver: DefaultDriver) {
+        let switch = Switch::new(&driver);
+        let mut a = Port::new(&driver, &switch, None).await;
+        let mut b = Port::new(&driver, &switch, None).await;
+        let mut c = Port::new(&driver, &switch, Some(5)).await;
+
+        let mac_a = [2, 0, 0, 0, 0, 0xa];
+        let mac_b = [2, 0, 0, 0, 0, 0xb];
+
+        // Broadcast from a reaches b but not c, which is on another VLAN.
+        a.send(&frame([0xff; 6], mac_a, 1));
+        assert_eq!(b.recv().await, frame([0xff; 6], mac_a, 1));
+
+        // The reply is unicast to a.
+        b.send(&frame(mac_a, mac_b, 2));
+        assert_eq!(a.recv().await, frame(mac_a, mac_b, 2));
+
+        // A tagged frame from trunk port b reaches access port c untagged.
+        let mut tagged = frame([0xff; 6], mac_b, 3);
+        tagged.splice(12..12, [0x81, 0x00, 0x00, 0x05]);
+        b.send(&tagged);
+        assert_eq!(c.recv().await, frame([0xff; 6], mac_b, 3));
+        assert_eq!(a.recv().await, tagged);
+
+        // Nothing else was delivered.
+        let mut cx = Context::from_waker(std::task::Waker::noop());
+        for port in [&mut a, &mut b, &mut c] {
+            assert!(port.queue.poll_ready(&mut cx, &mut port.bufs).is_pending());
+        }
+    }
+}
@@ -0,0 +1,34 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resolver for switch endpoints.
+
+use crate::SwitchEndpoint;
+use net_backend::resolve::ResolveEndpointParams;
+use net_backend::resolve::ResolvedEndpoint;
+use net_backend_resources::switch::SwitchHandle;
+use std::convert::Infallible;
+use vm_resource::ResolveResource;
+use vm_resource::declare_static_resolver;
+use vm_resource::kind::NetEndpointHandleKind;
+
+/// A resolver for endpoints attached to a software Ethernet switch.
+pub struct SwitchResolver;
+
+declare_static_resolver! {
+    SwitchResolver,
+    (NetEndpointHandleKind, SwitchHandle),
+}
+
+impl ResolveResource<NetEndpointHandleKind, SwitchHandle> for SwitchResolver {
+    type Output = ResolvedEndpoint;
+    type Error = Infallible;
+
+    fn resolve(
+        &self,
+        resource: SwitchHandle,
+        _input: ResolveEndpointParams,
+    ) -> Result<Self::Output, Self::Error> {
+        Ok(SwitchEndpoint::new(resource.switch, resource.vlan).into())
+    }
+}
@@ -0,0 +1,123 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Sharing a switch with other processes over a Unix socket.
+//!
+//! Each connection carries a point-to-point mesh whose initial port sends
+//! [`SwitchRequest`]s to the switch, so ports in the connecting process are
+//! attached exactly as local ones are.
+
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use mesh_remote::PointToPointMesh;
+use net_backend_resources::switch::SwitchRequest;
+use pal_async::driver::Driver;
+use pal_async::socket::PolledSocket;
+use pal_async::task::Spawn;
+use std::io;
+use unix_socket::UnixListener;
+use unix_socket::UnixStream;
+
+/// Accepts connections on `listener` and attaches their ports to `switch`,
+/// until accepting fails.
+pub async fn serve(
+    driver: &(impl Driver + Spawn),
+    listener: UnixListener,
+    switch: mesh::Sender<SwitchRequest>,
+) -> io::Result<()> {
+    let mut listener = PolledSocket::new(driver, listener)?;
+    let mut connections = FuturesUnordered::new();
+    loop {
+        let socket = futures::select! { // merge semantics
+            r = listener.accept().fuse() => r?.0,
+            _ = connections.select_next_some() => continue,
+        };
+        let socket = PolledSocket::new(driver, socket)?;
+        let (send, mut recv) = mesh::channel();
+        let mesh = PointToPointMesh::new(driver, socket, send.into());
+        let switch = switch.clone();
+        connections.push(async move {
+            // Forward requests until the peer closes its sender or the
+            // connection fails.
+            while let Some(request) = recv.next().await {
+                switch.send(request);
+            }
+            mesh.shutdown().await;
+        });
+    }
+}
+
+/// A connection to a switch served by another process.
+///
+/// Ports attached through the connection are detached when it is dropped.
+#[must_use]
+pub struct SwitchClient {
+    send: mesh::Sender<SwitchRequest>,
+    _mesh: PointToPointMesh,
+}
+
+impl SwitchClient {
+    /// Connects to the switch served on `stream`.
+    pub fn new(driver: &(impl Driver + Spawn), stream: UnixStream) -> io::Result<Self> {
+        let socket = PolledSocket::new(driver, stream)?;
+        let (send, recv) = mesh::channel();
+        let mesh = PointToPointMesh::new(driver, socket, recv.into());
+        Ok(Self { send, _mesh: mesh })
+    }
+
+    /// Returns a sender for attaching ports to the remote switch.
+    pub fn sender(&self) -> mesh::Sender<SwitchRequest> {
+        self.send.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Switch;
+    use crate::SwitchEndpoint;
+    use net_backend::Endpoint;
+    use net_backend::QueueConfig;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+
+    #[async_test]
+    async fn remote_port(driver: DefaultDriver) {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("switch.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let switch = Switch::new(&driver);
+        let _server = driver.spawn("serve", {
+            let driver = driver.clone();
+            let sender = switch.sender();
+            async move { serve(&driver, listener, sender).await.unwrap() }
+        });
+
+        let client = SwitchClient::new(&driver, UnixStream::connect(&path).unwrap()).unwrap();
+        let mut queues = Vec::new();
+        SwitchEndpoint::new(client.sender(), Some(1))
+            .get_queues(
+                vec![QueueConfig {
+                    driver: Box::new(driver.clone()),
+                }],
+                None,
+                &mut queues,
+            )
+            .await
+            .unwrap();
+
+        // Invalid configurations are reported across the connection.
+        let mut queues = Vec::new();
+        SwitchEndpoint::new(client.sender(), Some(4095))
+            .get_queues(
+                vec![QueueConfig {
+                    driver: Box::new(driver.clone()),
+                }],
+                None,
+                &mut queues,
+            )
+            .await
+            .unwrap_err();
+    }
+}
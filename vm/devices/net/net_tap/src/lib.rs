@@ -8,6 +8,7 @@
 
 pub mod resolver;
 pub mod tap;
+pub mod vhost;
 
 use async_trait::async_trait;
 use futures::io::AsyncRead;
@@ -28,11 +29,13 @@
 use net_backend::TxSegment;
 use net_backend::linearize;
 use net_backend::next_packet;
+use net_backend::vhost::VhostNet;
 use pal_async::driver::Driver;
 use parking_lot::Mutex;
 use std::collections::VecDeque;
 use std::io::ErrorKind;
 use std::io::Write;
+use std::os::fd::OwnedFd;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::task::Context;
@@ -112,6 +115,7 @@ pub struct VirtioNetHdr {
 /// An endpoint based on a TAP interface.
 pub struct TapEndpoint {
     tap: Arc<Mutex<Option<tap::Tap>>>,
+    vhost: Option<Box<dyn VhostNet>>,
 }
 
 impl TapEndpoint {
@@ -138,8 +142,25 @@ pub fn new(tap: tap::Tap) -> Result<Self, tap::Error> {
 
         Ok(Self {
             tap: Arc::new(Mutex::new(Some(tap))),
+            vhost: None,
         })
     }
+
+    /// Offers a `vhost_net` datapath for this endpoint, using an open
+    /// `/dev/vhost-net` fd.
+    ///
+    /// A virtio-net frontend hands its virtqueues to the kernel through this
+    /// datapath. Other frontends continue to use the userspace queues.
+    pub fn with_vhost(mut self, vhost: OwnedFd) -> Result<Self, tap::Error> {
+        let tap = self
+            .tap
+            .lock()
+            .as_ref()
+            .expect("no queues are active")
+            .try_clone()?;
+        self.vhost = Some(Box::new(vhost::TapVhostNet::new(vhost, tap)?));
+        Ok(self)
+    }
 }
 
 impl InspectMut for TapEndpoint {
@@ -190,6 +211,10 @@ fn tx_offload_support(&self) -> TxOffloadSupport {
             uso: true,
         }
     }
+
+    fn take_vhost_net(&mut self) -> Option<Box<dyn VhostNet>> {
+        self.vhost.take()
+    }
 }
 
 struct TapQueue {
@@ -27,7 +27,11 @@ fn resolve(
         _input: ResolveEndpointParams,
     ) -> Result<Self::Output, Self::Error> {
         let tap = tap::Tap::new(resource.fd)?;
+        let mut endpoint = TapEndpoint::new(tap)?;
+        if let Some(vhost) = resource.vhost {
+            endpoint = endpoint.with_vhost(vhost)?;
+        }
 
-        Ok(TapEndpoint::new(tap)?.into())
+        Ok(endpoint.into())
     }
 }
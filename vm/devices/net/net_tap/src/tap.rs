@@ -21,6 +21,8 @@
 use std::fs::File;
 use std::io;
 use std::io::Write;
+use std::os::fd::AsFd;
+use std::os::fd::BorrowedFd;
 use std::os::fd::OwnedFd;
 use std::os::raw::c_short;
 use std::os::unix::prelude::AsRawFd;
@@ -51,6 +53,12 @@ pub enum Error {
     NoVnetHdr,
     #[error("TAP interface has unexpected vnet header size {actual}, expected {expected}")]
     WrongVnetHdrSize { expected: usize, actual: usize },
+    #[error("failed to duplicate the TAP fd")]
+    CloneTap(#[source] io::Error),
+    #[error("VHOST_SET_OWNER ioctl failed")]
+    VhostSetOwner(#[source] io::Error),
+    #[error("VHOST_GET_FEATURES ioctl failed")]
+    VhostGetFeatures(#[source] io::Error),
 }
 
 /// Opens a TAP interface by name and returns the fd.
@@ -163,6 +171,13 @@ pub fn set_offloads(&self, flags: u32) -> Result<(), Error> {
         Ok(())
     }
 
+    /// Returns a new handle to the same TAP interface.
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        Ok(Self {
+            tap: self.tap.try_clone().map_err(Error::CloneTap)?,
+        })
+    }
+
     pub fn polled(self, driver: &(impl Driver + ?Sized)) -> io::Result<PolledTap> {
         Ok(PolledTap {
             tap: PolledPipe::new(driver, self.tap)?,
@@ -170,6 +185,12 @@ pub fn polled(self, driver: &(impl Driver + ?Sized)) -> io::Result<PolledTap> {
     }
 }
 
+impl AsFd for Tap {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.tap.as_fd()
+    }
+}
+
 /// A version of [`Tap`] that implements [`AsyncRead`].
 pub struct PolledTap {
     tap: PolledPipe,
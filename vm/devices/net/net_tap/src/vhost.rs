@@ -0,0 +1,403 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Linux kernel `vhost_net` datapath for TAP endpoints.
+//!
+//! `/dev/vhost-net` moves packets directly between the virtio-net virtqueues
+//! in guest memory and the TAP device. The TAP device adds and strips the
+//! virtio-net header, so the kernel is not asked to handle it
+//! (`VHOST_NET_F_VIRTIO_NET_HDR` is never negotiated), and the receive
+//! offloads the guest accepts are applied to the TAP device with
+//! `TUNSETOFFLOAD`.
+
+// UNSAFETY: Calling vhost ioctls.
+#![expect(unsafe_code)]
+
+use crate::tap;
+use anyhow::Context as _;
+use linux_net_bindings::gen_if_tun;
+use net_backend::vhost::VhostMemoryRegion;
+use net_backend::vhost::VhostNet;
+use net_backend::vhost::VhostQueue;
+use std::os::fd::AsFd;
+use std::os::fd::AsRawFd;
+use std::os::fd::OwnedFd;
+use std::os::fd::RawFd;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+// Dirty logging is a vhost control feature, not a guest virtio feature.
+const VHOST_F_LOG_ALL: u64 = 1 << 26;
+// The TAP device handles the virtio-net header. This shares a bit with
+// VIRTIO_F_ANY_LAYOUT, which OpenVMM's modern-only transport never offers.
+const VHOST_NET_F_VIRTIO_NET_HDR: u64 = 1 << 27;
+// The memory table is a direct GPA-to-HVA mapping; no vhost IOTLB is
+// configured.
+const VIRTIO_F_ACCESS_PLATFORM: u64 = 1 << 33;
+const MASKED_FEATURES: u64 =
+    VHOST_F_LOG_ALL | VHOST_NET_F_VIRTIO_NET_HDR | VIRTIO_F_ACCESS_PLATFORM;
+
+const VIRTIO_NET_F_GUEST_CSUM: u64 = 1 << 1;
+const VIRTIO_NET_F_GUEST_TSO4: u64 = 1 << 7;
+const VIRTIO_NET_F_GUEST_TSO6: u64 = 1 << 8;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VhostVringState {
+    index: u32,
+    num: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VhostVringAddr {
+    index: u32,
+    flags: u32,
+    desc_user_addr: u64,
+    used_user_addr: u64,
+    avail_user_addr: u64,
+    log_guest_addr: u64,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct VhostVringFile {
+    index: u32,
+    fd: i32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout)]
+struct VhostMemoryHeader {
+    nregions: u32,
+    padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, IntoBytes, Immutable, KnownLayout)]
+struct VhostMemoryRegionDesc {
+    guest_phys_addr: u64,
+    memory_size: u64,
+    userspace_addr: u64,
+    flags_padding: u64,
+}
+
+/// Returns the features of the kernel datapath, given the features the vhost
+/// device reports.
+fn datapath_features(vhost_features: u64) -> u64 {
+    vhost_features & !MASKED_FEATURES
+}
+
+/// Returns the TAP receive offloads matching the negotiated virtio-net
+/// features.
+fn tap_offloads(features: u64) -> u32 {
+    // Segmentation offloads require checksum offload.
+    let mut offloads = 0;
+    if features & VIRTIO_NET_F_GUEST_CSUM != 0 {
+        offloads |= gen_if_tun::TUN_F_CSUM;
+        if features & VIRTIO_NET_F_GUEST_TSO4 != 0 {
+            offloads |= gen_if_tun::TUN_F_TSO4;
+        }
+        if features & VIRTIO_NET_F_GUEST_TSO6 != 0 {
+            offloads |= gen_if_tun::TUN_F_TSO6;
+        }
+    }
+    offloads
+}
+
+/// Builds a `vhost_memory` table: a header followed by one entry per region.
+fn memory_table(regions: &[VhostMemoryRegion]) -> anyhow::Result<Vec<u8>> {
+    let nregions = u32::try_from(regions.len()).context("too many vhost memory regions")?;
+    let mut table = Vec::with_capacity(
+        size_of::<VhostMemoryHeader>() + regions.len() * size_of::<VhostMemoryRegionDesc>(),
+    );
+    table.extend_from_slice(
+        VhostMemoryHeader {
+            nregions,
+            padding: 0,
+        }
+        .as_bytes(),
+    );
+    for region in regions {
+        table.extend_from_slice(
+            VhostMemoryRegionDesc {
+                guest_phys_addr: region.guest_address,
+                memory_size: region.size,
+                userspace_addr: region.host_address,
+                flags_padding: 0,
+            }
+            .as_bytes(),
+        );
+    }
+    Ok(table)
+}
+
+mod ioctl {
+    use super::VhostMemoryHeader;
+    use super::VhostVringAddr;
+    use super::VhostVringFile;
+    use super::VhostVringState;
+
+    const VHOST_VIRTIO: u8 = 0xaf;
+
+    nix::ioctl_read!(get_features, VHOST_VIRTIO, 0x00, u64);
+    nix::ioctl_write_ptr!(set_features, VHOST_VIRTIO, 0x00, u64);
+    nix::ioctl_none!(set_owner, VHOST_VIRTIO, 0x01);
+    nix::ioctl_write_ptr!(set_mem_table, VHOST_VIRTIO, 0x03, VhostMemoryHeader);
+    nix::ioctl_write_ptr!(set_vring_num, VHOST_VIRTIO, 0x10, VhostVringState);
+    nix::ioctl_write_ptr!(set_vring_addr, VHOST_VIRTIO, 0x11, VhostVringAddr);
+    nix::ioctl_write_ptr!(set_vring_base, VHOST_VIRTIO, 0x12, VhostVringState);
+    nix::ioctl_readwrite!(get_vring_base, VHOST_VIRTIO, 0x12, VhostVringState);
+    nix::ioctl_write_ptr!(set_vring_kick, VHOST_VIRTIO, 0x20, VhostVringFile);
+    nix::ioctl_write_ptr!(set_vring_call, VHOST_VIRTIO, 0x21, VhostVringFile);
+    nix::ioctl_write_ptr!(net_set_backend, VHOST_VIRTIO, 0x30, VhostVringFile);
+}
+
+/// A `vhost_net` datapath attached to a TAP device.
+pub struct TapVhostNet {
+    vhost: OwnedFd,
+    tap: tap::Tap,
+    kernel_features: u64,
+}
+
+impl TapVhostNet {
+    /// Takes ownership of an open `/dev/vhost-net` fd, to be attached to `tap`.
+    pub fn new(vhost: OwnedFd, tap: tap::Tap) -> Result<Self, tap::Error> {
+        // SAFETY: The fd refers to a vhost-net device and this ioctl has no argument.
+        unsafe { ioctl::set_owner(vhost.as_raw_fd()) }
+            .map_err(|err| tap::Error::VhostSetOwner(err.into()))?;
+
+        let mut features = 0;
+        // SAFETY: The ioctl writes a u64 to the supplied pointer.
+        unsafe { ioctl::get_features(vhost.as_raw_fd(), &mut features) }
+            .map_err(|err| tap::Error::VhostGetFeatures(err.into()))?;
+
+        Ok(Self {
+            vhost,
+            tap,
+            kernel_features: datapath_features(features),
+        })
+    }
+
+    fn raw_fd(&self) -> RawFd {
+        self.vhost.as_raw_fd()
+    }
+
+    fn set_vring_file(&self, index: u16, fd: RawFd, call: bool) -> anyhow::Result<()> {
+        let file = VhostVringFile {
+            index: u32::from(index),
+            fd,
+        };
+        if call {
+            // SAFETY: The ioctl copies a VhostVringFile from the supplied pointer.
+            unsafe { ioctl::set_vring_call(self.raw_fd(), &file) }
+                .with_context(|| format!("VHOST_SET_VRING_CALL failed for queue {index}"))?;
+        } else {
+            // SAFETY: The ioctl copies a VhostVringFile from the supplied pointer.
+            unsafe { ioctl::set_vring_kick(self.raw_fd(), &file) }
+                .with_context(|| format!("VHOST_SET_VRING_KICK failed for queue {index}"))?;
+        }
+        Ok(())
+    }
+
+    fn set_backend(&self, index: u16, fd: RawFd) -> anyhow::Result<()> {
+        let file = VhostVringFile {
+            index: u32::from(index),
+            fd,
+        };
+        // SAFETY: The ioctl copies a VhostVringFile from the supplied pointer.
+        // The kernel takes its own reference to the TAP file.
+        unsafe { ioctl::net_set_backend(self.raw_fd(), &file) }
+            .with_context(|| format!("VHOST_NET_SET_BACKEND failed for queue {index}"))?;
+        Ok(())
+    }
+
+    fn unbind_vring_events(&self, index: u16) {
+        for call in [false, true] {
+            if let Err(error) = self.set_vring_file(index, -1, call) {
+                tracing::warn!(
+                    error = &*error as &dyn std::error::Error,
+                    index,
+                    "failed to unbind vhost-net event"
+                );
+            }
+        }
+    }
+}
+
+impl VhostNet for TapVhostNet {
+    fn features(&self) -> u64 {
+        self.kernel_features
+    }
+
+    fn set_features(&mut self, features: u64) -> anyhow::Result<()> {
+        let kernel_features = features & self.kernel_features;
+        // SAFETY: The ioctl copies a u64 from the supplied pointer.
+        unsafe { ioctl::set_features(self.raw_fd(), &kernel_features) }
+            .context("VHOST_SET_FEATURES failed")?;
+
+        self.tap.set_offloads(tap_offloads(features))?;
+        Ok(())
+    }
+
+    fn set_memory(&mut self, regions: &[VhostMemoryRegion]) -> anyhow::Result<()> {
+        let table = memory_table(regions)?;
+        // SAFETY: `table` contains a vhost_memory header followed by exactly
+        // one vhost_memory_region entry per region, and the kernel copies it
+        // during the ioctl.
+        unsafe { ioctl::set_mem_table(self.raw_fd(), table.as_ptr().cast::<VhostMemoryHeader>()) }
+            .context("VHOST_SET_MEM_TABLE failed")?;
+        Ok(())
+    }
+
+    fn start_queue(&mut self, index: u16, queue: VhostQueue<'_>) -> anyhow::Result<()> {
+        let state = VhostVringState {
+            index: u32::from(index),
+            num: u32::from(queue.size),
+        };
+        // SAFETY: The ioctl copies a VhostVringState from the supplied pointer.
+        unsafe { ioctl::set_vring_num(self.raw_fd(), &state) }
+            .with_context(|| format!("VHOST_SET_VRING_NUM failed for queue {index}"))?;
+
+        let state = VhostVringState {
+            index: u32::from(index),
+            num: queue.base,
+        };
+        // SAFETY: The ioctl copies a VhostVringState from the supplied pointer.
+        unsafe { ioctl::set_vring_base(self.raw_fd(), &state) }
+            .with_context(|| format!("VHOST_SET_VRING_BASE failed for queue {index}"))?;
+
+        let addr = VhostVringAddr {
+            index: u32::from(index),
+            flags: 0,
+            desc_user_addr: queue.desc_addr,
+            used_user_addr: queue.used_addr,
+            avail_user_addr: queue.avail_addr,
+            log_guest_addr: 0,
+        };
+        // SAFETY: The ioctl copies a VhostVringAddr from the supplied pointer.
+        unsafe { ioctl::set_vring_addr(self.raw_fd(), &addr) }
+            .with_context(|| format!("VHOST_SET_VRING_ADDR failed for queue {index}"))?;
+
+        let result = self
+            .set_vring_file(index, queue.kick.as_raw_fd(), false)
+            .and_then(|()| self.set_vring_file(index, queue.call.as_raw_fd(), true))
+            .and_then(|()| self.set_backend(index, self.tap.as_fd().as_raw_fd()));
+        if result.is_err() {
+            self.unbind_vring_events(index);
+        }
+        result
+    }
+
+    fn stop_queue(&mut self, index: u16) -> anyhow::Result<u32> {
+        // Detach the TAP device first so that the kernel stops processing the
+        // queue before its state is read.
+        let result = self.set_backend(index, -1).and_then(|()| {
+            let mut state = VhostVringState {
+                index: u32::from(index),
+                num: 0,
+            };
+            // SAFETY: The ioctl reads and writes a VhostVringState through
+            // the supplied pointer.
+            unsafe { ioctl::get_vring_base(self.raw_fd(), &mut state) }
+                .with_context(|| format!("VHOST_GET_VRING_BASE failed for queue {index}"))?;
+            Ok(state.num)
+        });
+        self.unbind_vring_events(index);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VIRTIO_RING_F_EVENT_IDX: u64 = 1 << 29;
+    const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+    #[test]
+    fn datapath_features_mask_vhost_features() {
+        let vhost_features = VHOST_F_LOG_ALL
+            | VHOST_NET_F_VIRTIO_NET_HDR
+            | VIRTIO_F_ACCESS_PLATFORM
+            | VIRTIO_RING_F_EVENT_IDX
+            | VIRTIO_F_VERSION_1;
+        assert_eq!(
+            datapath_features(vhost_features),
+            VIRTIO_RING_F_EVENT_IDX | VIRTIO_F_VERSION_1
+        );
+        assert_eq!(datapath_features(VHOST_F_LOG_ALL), 0);
+    }
+
+    #[test]
+    fn tap_offloads_follow_guest_features() {
+        let csum = gen_if_tun::TUN_F_CSUM;
+        let tso4 = gen_if_tun::TUN_F_TSO4;
+        let tso6 = gen_if_tun::TUN_F_TSO6;
+        assert_eq!(tap_offloads(0), 0);
+        assert_eq!(tap_offloads(VIRTIO_NET_F_GUEST_CSUM), csum);
+        assert_eq!(
+            tap_offloads(VIRTIO_NET_F_GUEST_CSUM | VIRTIO_NET_F_GUEST_TSO4),
+            csum | tso4
+        );
+        assert_eq!(
+            tap_offloads(
+                VIRTIO_NET_F_GUEST_CSUM | VIRTIO_NET_F_GUEST_TSO4 | VIRTIO_NET_F_GUEST_TSO6
+            ),
+            csum | tso4 | tso6
+        );
+        // Segmentation offloads are dropped without checksum offload.
+        assert_eq!(
+            tap_offloads(VIRTIO_NET_F_GUEST_TSO4 | VIRTIO_NET_F_GUEST_TSO6),
+            0
+        );
+    }
+
+    #[test]
+    fn memory_table_layout() {
+        let regions = [
+            VhostMemoryRegion {
+                guest_address: 0,
+                size: 0x1000,
+                host_address: 0x7f00_0000_0000,
+            },
+            VhostMemoryRegion {
+                guest_address: 0x1_0000_0000,
+                size: 0x2000,
+                host_address: 0x7f00_1000_0000,
+            },
+        ];
+        let table = memory_table(&regions).unwrap();
+
+        let header = size_of::<VhostMemoryHeader>();
+        let entry = size_of::<VhostMemoryRegionDesc>();
+        assert_eq!(header, 8);
+        assert_eq!(entry, 32);
+        assert_eq!(table.len(), header + 2 * entry);
+        assert_eq!(
+            &table[..header],
+            VhostMemoryHeader {
+                nregions: 2,
+                padding: 0,
+            }
+            .as_bytes()
+        );
+        for (i, region) in regions.iter().enumerate() {
+            let offset = header + i * entry;
+            assert_eq!(
+                &table[offset..offset + entry],
+                VhostMemoryRegionDesc {
+                    guest_phys_addr: region.guest_address,
+                    memory_size: region.size,
+                    userspace_addr: region.host_address,
+                    flags_padding: 0,
+                }
+                .as_bytes()
+            );
+        }
+
+        let table = memory_table(&[]).unwrap();
+        assert_eq!(table.len(), header);
+    }
+}
@@ -4859,7 +4859,7 @@ async fn restart_queues(&mut self, c_state: &mut CoordinatorState) -> Result<(),
                 .map(|rss| net_backend::RssConfig {
                     key: &rss.key,
                     indirection_table: &rss.indirection_table,
-                    flags: 0,
+                    flags: net_backend::RssFlags::new(),
                 });
 
             c_state
@@ -124,6 +124,10 @@ pub enum Subclass: u8 {
             // Other values: 0x01 - 0x08, 0x80
             NETWORK_CONTROLLER_ETHERNET = 0x00,
 
+            // Display Controller (Class code: 0x03)
+            // Other values: 0x00 - 0x02
+            DISPLAY_CONTROLLER_OTHER = 0x80,
+
             // Simple Communication Controller (Class code: 0x07)
             // Other values: 0x00 - 0x07
             SIMPLE_COMMUNICATION_CONTROLLER_OTHER = 0x80,
@@ -138,6 +142,10 @@ pub enum Subclass: u8 {
             // Base System Peripheral (Class code: 0x08)
             // Other values: 0x00 - 0x06
             BASE_SYSTEM_PERIPHERAL_OTHER = 0x80,
+
+            // Input Device Controller (Class code: 0x09)
+            // Other values: 0x00 - 0x04
+            INPUT_DEVICE_CONTROLLER_OTHER = 0x80,
         }
     }
 
@@ -60,6 +60,7 @@
 //! |---------|-------|-------------|
 //! | `FileDisk` | `disk_file` | Host file, cross-platform |
 //! | `Vhd1Disk` | `disk_vhd1` | VHD1 fixed format |
+//! | `Qcow2Disk` | `disk_qcow2` | qcow2 images with backing chains |
 //! | `VhdmpDisk` | `disk_vhdmp` | Windows vhdmp driver |
 //! | `BlobDisk` | `disk_blob` | Read-only HTTP / Azure Blob |
 //! | `BlockDeviceDisk` | `disk_blockdevice` | Linux block device (io_uring) |
@@ -87,3 +87,19 @@ pub struct VhdxDiskLayerHandle {
 impl ResourceId<DiskLayerHandleKind> for VhdxDiskLayerHandle {
     const ID: &'static str = "vhdx";
 }
+
+/// Handle for a qcow2 disk layer.
+///
+/// If the image has a backing file, unallocated clusters are read from the
+/// next layer, and the layer must be read-only.
+#[derive(MeshPayload)]
+pub struct Qcow2DiskLayerHandle {
+    /// The open file handle for the qcow2 image.
+    pub file: std::fs::File,
+    /// Whether to open the image as read-only.
+    pub read_only: bool,
+}
+
+impl ResourceId<DiskLayerHandleKind> for Qcow2DiskLayerHandle {
+    const ID: &'static str = "qcow2";
+}
@@ -69,6 +69,22 @@ impl ResourceId<DiskHandleKind> for FixedVhd1DiskHandle {
     const ID: &'static str = "fixed_vhd1";
 }
 
+/// Disk handle for a qcow2 image.
+#[derive(MeshPayload)]
+pub struct Qcow2DiskHandle {
+    /// The open file handle for the qcow2 image.
+    pub file: std::fs::File,
+    /// Whether to open the image as read-only.
+    pub read_only: bool,
+    /// The disk holding the contents of the image's backing file, if it has
+    /// one. Clusters that are not allocated in the image are read from it.
+    pub backing: Option<Resource<DiskHandleKind>>,
+}
+
+impl ResourceId<DiskHandleKind> for Qcow2DiskHandle {
+    const ID: &'static str = "qcow2";
+}
+
 /// Disk configuration for a striped disk.
 #[derive(MeshPayload)]
 pub struct StripedDiskHandle {
@@ -128,6 +144,50 @@ pub enum BlobDiskFormat {
     FixedVhd1,
 }
 
+// nbd
+
+/// The address of an NBD server.
+#[derive(MeshPayload, Clone, Debug, PartialEq, Eq)]
+pub enum NbdAddress {
+    /// A TCP address, as `host:port`.
+    Tcp(String),
+    /// The path to a Unix domain socket.
+    Unix(String),
+}
+
+/// Handle for a disk backed by an export on an NBD server.
+#[derive(MeshPayload)]
+pub struct NbdDiskHandle {
+    /// The address of the server.
+    pub address: NbdAddress,
+    /// The name of the export. The empty string selects the server's default
+    /// export.
+    pub export_name: String,
+}
+
+impl ResourceId<DiskHandleKind> for NbdDiskHandle {
+    const ID: &'static str = "nbd";
+}
+
+/// Handle for a disk that is also exported to NBD clients while in use.
+///
+/// IO from the VM is passed through to `disk` unchanged.
+#[derive(MeshPayload)]
+pub struct NbdExportDiskHandle {
+    /// The disk to export.
+    pub disk: Resource<DiskHandleKind>,
+    /// The bound listening socket to accept NBD clients on.
+    pub listener: socket2::Socket,
+    /// The name of the export.
+    pub export_name: String,
+    /// Whether NBD clients are prevented from writing to the disk.
+    pub read_only: bool,
+}
+
+impl ResourceId<DiskHandleKind> for NbdExportDiskHandle {
+    const ID: &'static str = "nbd_export";
+}
+
 /// Handle for a disk that is backed by one or more layers.
 #[derive(MeshPayload)]
 pub struct LayeredDiskHandle {
@@ -33,11 +33,14 @@
 use nvme_spec::nvm;
 use pal::unix::affinity;
 use pal_async::driver::Driver;
+use pal_async::io_uring::FixedBuffer;
+use parking_lot::Mutex;
 use scsi_buffers::BounceBuffer;
 use scsi_buffers::BounceBufferTracker;
 use scsi_buffers::RequestBuffers;
 use std::fmt::Debug;
 use std::fs;
+use std::ops::Deref;
 use std::os::unix::io::AsRawFd;
 use std::os::unix::prelude::FileTypeExt;
 use std::os::unix::prelude::MetadataExt;
@@ -45,7 +48,9 @@
 use std::path::PathBuf;
 use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::atomic::AtomicBool;
 use std::sync::atomic::AtomicU64;
+use std::sync::atomic::AtomicUsize;
 use std::sync::atomic::Ordering;
 use thiserror::Error;
 use uevent::CallbackHandle;
@@ -92,6 +97,96 @@ fn as_mut_bytes(&mut self) -> &mut [u8] {
     }
 }
 
+/// The size of each registered bounce buffer. Larger bounce buffered IOs use
+/// an unregistered bounce buffer.
+const FIXED_BOUNCE_BUFFER_SIZE: usize = 256 * 1024;
+
+/// The maximum number of registered bounce buffers per device.
+const FIXED_BOUNCE_BUFFER_COUNT: usize = 8;
+
+/// A pool of bounce buffers registered with io-uring, for use with `ReadFixed`
+/// and `WriteFixed`.
+///
+/// Buffers are allocated on demand, up to [`FIXED_BOUNCE_BUFFER_COUNT`], and
+/// are returned to the pool when the IO completes.
+struct FixedBouncePool {
+    free: Mutex<Vec<FixedBuffer>>,
+    allocated: AtomicUsize,
+    /// Cleared if the ring cannot register buffers, after which bounce
+    /// buffered IOs always use unregistered buffers.
+    enabled: AtomicBool,
+}
+
+impl FixedBouncePool {
+    fn new(enabled: bool) -> Self {
+        Self {
+            free: Mutex::new(Vec::new()),
+            allocated: AtomicUsize::new(0),
+            enabled: AtomicBool::new(enabled),
+        }
+    }
+
+    /// Takes a buffer of at least `size` bytes from the pool, or returns `None`
+    /// if one is not available.
+    fn acquire(&self, size: usize) -> Option<FixedBounceBuffer<'_>> {
+        if size > FIXED_BOUNCE_BUFFER_SIZE || !self.enabled.load(Ordering::Relaxed) {
+            return None;
+        }
+        let buffer = if let Some(buffer) = self.free.lock().pop() {
+            buffer
+        } else {
+            self.allocated
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                    (n < FIXED_BOUNCE_BUFFER_COUNT).then_some(n + 1)
+                })
+                .ok()?;
+            let Some(buffer) = FixedBuffer::new(FIXED_BOUNCE_BUFFER_SIZE) else {
+                self.allocated.fetch_sub(1, Ordering::Relaxed);
+                return None;
+            };
+            buffer
+        };
+        Some(FixedBounceBuffer {
+            pool: self,
+            buffer: Some(buffer),
+        })
+    }
+
+    fn disable(&self) {
+        self.enabled.store(false, Ordering::Relaxed);
+        self.free.lock().clear();
+    }
+}
+
+/// A registered bounce buffer, returned to its pool on drop.
+struct FixedBounceBuffer<'a> {
+    pool: &'a FixedBouncePool,
+    buffer: Option<FixedBuffer>,
+}
+
+impl Deref for FixedBounceBuffer<'_> {
+    type Target = FixedBuffer;
+
+    fn deref(&self) -> &FixedBuffer {
+        self.buffer.as_ref().unwrap()
+    }
+}
+
+impl FixedBounceBuffer<'_> {
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        self.buffer.as_mut().unwrap().as_mut_slice()
+    }
+}
+
+impl Drop for FixedBounceBuffer<'_> {
+    fn drop(&mut self) {
+        let buffer = self.buffer.take().unwrap();
+        if self.pool.enabled.load(Ordering::Relaxed) {
+            self.pool.free.lock().push(buffer);
+        }
+    }
+}
+
 /// A storvsp disk backed by a raw block device.
 #[derive(Inspect)]
 #[inspect(extra = "BlockDevice::inspect_extra")]
@@ -116,6 +211,18 @@ pub struct BlockDevice {
     #[inspect(skip)]
     bounce_buffer_tracker: Option<Arc<BounceBufferTracker>>,
     always_bounce: bool,
+    #[inspect(skip)]
+    fixed_bounce_buffers: FixedBouncePool,
+}
+
+impl Debug for BlockDevice {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BlockDevice")
+            .field("device_type", &self.device_type)
+            .field("sector_size", &self.sector_size)
+            .field("read_only", &self.read_only)
+            .finish()
+    }
 }
 
 #[derive(Inspect, Debug, Default)]
@@ -209,15 +316,19 @@ pub async fn new(
         bounce_buffer_tracker: Option<Arc<BounceBufferTracker>>,
         always_bounce: bool,
     ) -> Result<BlockDevice, NewDeviceError> {
-        if !driver.io_uring_probe(opcode::Read::CODE) {
-            return Err(NewDeviceError::NoIoUring);
-        }
-        assert!(driver.io_uring_probe(opcode::Write::CODE));
-        assert!(driver.io_uring_probe(opcode::Readv::CODE));
-        assert!(driver.io_uring_probe(opcode::Writev::CODE));
-        assert!(driver.io_uring_probe(opcode::Fsync::CODE));
+        probe_io_uring(&driver)?;
 
         let metadata = file.metadata().map_err(DiskError::Io)?;
+        if metadata.file_type().is_file() {
+            return Self::from_file(
+                file,
+                &metadata,
+                read_only,
+                driver,
+                bounce_buffer_tracker,
+                always_bounce,
+            );
+        }
 
         let mut uevent_filter = None;
         let resize_epoch = Arc::new(ResizeEpoch::default());
@@ -244,24 +355,97 @@ pub async fn new(
 
             DeviceMetadata::from_block_device(&file, major, minor)
                 .map_err(NewDeviceError::DeviceMetadata)?
-        } else if metadata.file_type().is_file() {
-            // Discard on a file is serviced via `fallocate(PUNCH_HOLE)`, issued
-            // asynchronously through io-uring. Only offer it when the file is
-            // writable and the ring supports the FALLOCATE opcode (Linux 5.6+).
-            let allow_discard = !read_only && driver.io_uring_probe(opcode::Fallocate::CODE);
-            DeviceMetadata::from_file(&file, &metadata, allow_discard)
-                .map_err(NewDeviceError::DeviceMetadata)?
         } else {
             return Err(NewDeviceError::InvalidFileType);
         };
 
+        Ok(Self::from_metadata(
+            file,
+            devmeta,
+            read_only,
+            Box::new(driver),
+            uevent_filter,
+            resize_epoch,
+            bounce_buffer_tracker,
+            always_bounce,
+        ))
+    }
+
+    /// Constructs a new `BlockDevice` backed by the regular file `file`.
+    ///
+    /// Unlike [`BlockDevice::new`], this does not register for resize events,
+    /// so it can be called from synchronous contexts, such as the file disk
+    /// resolver.
+    pub fn new_file(
+        file: fs::File,
+        read_only: bool,
+        driver: impl Driver,
+        bounce_buffer_tracker: Option<Arc<BounceBufferTracker>>,
+        always_bounce: bool,
+    ) -> Result<BlockDevice, NewDeviceError> {
+        probe_io_uring(&driver)?;
+
+        let metadata = file.metadata().map_err(DiskError::Io)?;
+        if !metadata.file_type().is_file() {
+            return Err(NewDeviceError::InvalidFileType);
+        }
+        Self::from_file(
+            file,
+            &metadata,
+            read_only,
+            driver,
+            bounce_buffer_tracker,
+            always_bounce,
+        )
+    }
+
+    fn from_file(
+        file: fs::File,
+        metadata: &fs::Metadata,
+        read_only: bool,
+        driver: impl Driver,
+        bounce_buffer_tracker: Option<Arc<BounceBufferTracker>>,
+        always_bounce: bool,
+    ) -> Result<BlockDevice, NewDeviceError> {
+        // Discard on a file is serviced via `fallocate(PUNCH_HOLE)`, issued
+        // asynchronously through io-uring. Only offer it when the file is
+        // writable and the ring supports the FALLOCATE opcode (Linux 5.6+).
+        let allow_discard = !read_only && driver.io_uring_probe(opcode::Fallocate::CODE);
+        let devmeta = DeviceMetadata::from_file(&file, metadata, allow_discard)
+            .map_err(NewDeviceError::DeviceMetadata)?;
+        Ok(Self::from_metadata(
+            file,
+            devmeta,
+            read_only,
+            Box::new(driver),
+            None,
+            Default::default(),
+            bounce_buffer_tracker,
+            always_bounce,
+        ))
+    }
+
+    fn from_metadata(
+        file: fs::File,
+        devmeta: DeviceMetadata,
+        read_only: bool,
+        driver: Box<dyn Driver>,
+        uevent_filter: Option<CallbackHandle>,
+        resize_epoch: Arc<ResizeEpoch>,
+        bounce_buffer_tracker: Option<Arc<BounceBufferTracker>>,
+        always_bounce: bool,
+    ) -> BlockDevice {
         let sector_size = devmeta.logical_block_size;
         let sector_shift = sector_size.trailing_zeros();
         let physical_sector_size = devmeta.physical_block_size.max(sector_size);
         let sector_count = devmeta.disk_size >> sector_shift;
         let unmap_granularity = devmeta.discard_granularity >> sector_shift;
         let file = Arc::new(file);
-        let device = BlockDevice {
+        let fixed_bounce_buffers = FixedBouncePool::new(
+            driver.io_uring_probe(opcode::ReadFixed::CODE)
+                && driver.io_uring_probe(opcode::WriteFixed::CODE),
+        );
+        BlockDevice {
             file,
             sector_size,
             physical_sector_size,
@@ -269,7 +453,7 @@ pub async fn new(
             sector_count: sector_count.into(),
             optimal_unmap_sectors: unmap_granularity,
             read_only,
-            driver: Box::new(driver),
+            driver,
             device_type: devmeta.device_type,
             supports_pr: devmeta.supports_pr,
             supports_fua: devmeta.fua,
@@ -278,9 +462,8 @@ pub async fn new(
             resized_acked: 0.into(),
             bounce_buffer_tracker,
             always_bounce,
-        };
-
-        Ok(device)
+            fixed_bounce_buffers,
+        }
     }
 
     /// Use a box to avoid embedding a large `TrackedBounceBuffer` directly in
@@ -297,6 +480,35 @@ async fn acquire_bounce_buffer(&self, size: usize) -> Box<MaybeBounceBuffer<'_>>
         })
     }
 
+    /// Submits `sqe`, which references the registered bounce buffer `buf`.
+    ///
+    /// Returns `None` if `buf` could not be registered with the ring. In that
+    /// case, registered bounce buffers are disabled for this device and the
+    /// caller should fall back to an unregistered bounce buffer.
+    ///
+    /// # Safety
+    ///
+    /// `buf` must be the only buffer referenced by `sqe`, and it must be owned
+    /// by the calling `async fn` so that it outlives the IO.
+    async unsafe fn submit_fixed(
+        &self,
+        sqe: io_uring::squeue::Entry,
+        buf: &FixedBuffer,
+    ) -> Option<Result<i32, DiskError>> {
+        // SAFETY: guaranteed by the caller.
+        match unsafe { self.driver.io_uring_submit_fixed(sqe, buf) }.await {
+            Err(err) if err.kind() == std::io::ErrorKind::Unsupported => {
+                tracing::debug!(
+                    error = &err as &dyn std::error::Error,
+                    "registered bounce buffers unavailable"
+                );
+                self.fixed_bounce_buffers.disable();
+                None
+            }
+            r => Some(r.map_err(|err| self.map_io_error(err))),
+        }
+    }
+
     fn handle_resize(&self) {
         if let Err(err) = self.handle_resize_inner() {
             tracing::error!(
@@ -348,6 +560,18 @@ fn map_io_error(&self, err: std::io::Error) -> DiskError {
     }
 }
 
+/// Ensures that `driver` supports the io-uring operations needed for disk IO.
+fn probe_io_uring(driver: &impl Driver) -> Result<(), NewDeviceError> {
+    if !driver.io_uring_probe(opcode::Read::CODE) {
+        return Err(NewDeviceError::NoIoUring);
+    }
+    assert!(driver.io_uring_probe(opcode::Write::CODE));
+    assert!(driver.io_uring_probe(opcode::Readv::CODE));
+    assert!(driver.io_uring_probe(opcode::Writev::CODE));
+    assert!(driver.io_uring_probe(opcode::Fsync::CODE));
+    Ok(())
+}
+
 /// Probes whether the filesystem backing `file` supports hole punching via
 /// `fallocate(FALLOC_FL_PUNCH_HOLE)`, used to service guest discard/unmap.
 ///
@@ -482,7 +706,11 @@ fn from_file(
             physical_block_size,
             discard_granularity,
             supports_pr: false,
-            fua: false,
+            // There is no FUA for regular files. Emulate it by following each
+            // FUA write with an `fdatasync`, which makes the written data
+            // durable regardless of the underlying device's FUA support. See
+            // `write_vectored`.
+            fua: true,
         }
         .validate()
     }
@@ -577,6 +805,30 @@ async fn read_vectored(
         let should_bounce = self.always_bounce
             || !buffers.is_aligned(self.sector_size() as usize)
             || !buffers.guest_memory().supports_locking();
+
+        if should_bounce && let Some(fixed) = self.fixed_bounce_buffers.acquire(io_size) {
+            tracing::trace!("bounce buffering IO with registered buffer");
+            let sqe = opcode::ReadFixed::new(
+                types::Fd(self.file.as_raw_fd()),
+                fixed.as_mut_ptr(),
+                io_size as u32,
+                fixed.index(),
+            )
+            .offset((sector * self.sector_size() as u64) as _)
+            .build();
+            // SAFETY: `fixed` is a local in this `async fn` and is the only
+            // buffer referenced by the SQE.
+            if let Some(bytes_read) = unsafe { self.submit_fixed(sqe, &fixed) }.await {
+                let bytes_read = bytes_read?;
+                tracing::trace!(bytes_read, "read_vectored");
+                if bytes_read != io_size as i32 {
+                    return Err(DiskError::IllegalBlock);
+                }
+                buffers.writer().write(&fixed.as_slice()[..io_size])?;
+                return Ok(());
+            }
+        }
+
         let io_vecs = if !should_bounce {
             locked = buffers.lock(true)?;
             locked.io_vecs()
@@ -625,6 +877,8 @@ async fn write_vectored(
         let io_size = buffers.len();
         tracing::trace!(sector, io_size, "write_vectored");
 
+        let is_file = matches!(self.device_type, DeviceType::File { .. });
+
         // Ensure the write doesn't extend the file.
         if let DeviceType::File { sector_count } = self.device_type {
             if sector + (io_size as u64 >> self.sector_shift) > sector_count {
@@ -632,6 +886,9 @@ async fn write_vectored(
             }
         }
 
+        let offset = sector * self.sector_size() as u64;
+        let rw_flags = if fua && !is_file { libc::RWF_DSYNC } else { 0 };
+
         let mut bounce_buffer;
         let locked;
         // Memory behind an emulated IOMMU cannot be locked for zero-copy IO, so
@@ -639,39 +896,81 @@ async fn write_vectored(
         let should_bounce = self.always_bounce
             || !buffers.is_aligned(self.sector_size() as usize)
             || !buffers.guest_memory().supports_locking();
-        let io_vecs = if !should_bounce {
-            locked = buffers.lock(false)?;
-            locked.io_vecs()
-        } else {
-            tracing::trace!("bounce buffering IO");
-            bounce_buffer = self.acquire_bounce_buffer(buffers.len()).await;
-            buffers.reader().read(bounce_buffer.as_mut_bytes())?;
-            bounce_buffer.io_vecs()
-        };
 
-        // SAFETY: `io_vecs` and the underlying locked pages are locals
-        // in this `async fn`--they are part of the same state machine as
-        // the returned future and will not be freed before it completes
-        // or is dropped (which aborts).
-        let bytes_written = unsafe {
-            self.driver.io_uring_submit(
-                opcode::Writev::new(
-                    types::Fd(self.file.as_raw_fd()),
-                    io_vecs.as_ptr().cast::<libc::iovec>(),
-                    io_vecs.len() as _,
-                )
-                .offset((sector * self.sector_size() as u64) as _)
-                .rw_flags(if fua { libc::RWF_DSYNC } else { 0 })
-                .build(),
+        let mut bytes_written = None;
+        if should_bounce && let Some(mut fixed) = self.fixed_bounce_buffers.acquire(io_size) {
+            tracing::trace!("bounce buffering IO with registered buffer");
+            buffers
+                .reader()
+                .read(&mut fixed.as_mut_slice()[..io_size])?;
+            let sqe = opcode::WriteFixed::new(
+                types::Fd(self.file.as_raw_fd()),
+                fixed.as_mut_ptr(),
+                io_size as u32,
+                fixed.index(),
             )
+            .offset(offset as _)
+            .rw_flags(rw_flags)
+            .build();
+            // SAFETY: `fixed` is a local in this `async fn` and is the only
+            // buffer referenced by the SQE.
+            bytes_written = unsafe { self.submit_fixed(sqe, &fixed) }
+                .await
+                .transpose()?;
         }
-        .await
-        .map_err(|err| self.map_io_error(err))?;
+
+        let bytes_written = if let Some(bytes_written) = bytes_written {
+            bytes_written
+        } else {
+            let io_vecs = if !should_bounce {
+                locked = buffers.lock(false)?;
+                locked.io_vecs()
+            } else {
+                tracing::trace!("bounce buffering IO");
+                bounce_buffer = self.acquire_bounce_buffer(buffers.len()).await;
+                buffers.reader().read(bounce_buffer.as_mut_bytes())?;
+                bounce_buffer.io_vecs()
+            };
+
+            // SAFETY: `io_vecs` and the underlying locked pages are locals
+            // in this `async fn`--they are part of the same state machine as
+            // the returned future and will not be freed before it completes
+            // or is dropped (which aborts).
+            unsafe {
+                self.driver.io_uring_submit(
+                    opcode::Writev::new(
+                        types::Fd(self.file.as_raw_fd()),
+                        io_vecs.as_ptr().cast::<libc::iovec>(),
+                        io_vecs.len() as _,
+                    )
+                    .offset(offset as _)
+                    .rw_flags(rw_flags)
+                    .build(),
+                )
+            }
+            .await
+            .map_err(|err| self.map_io_error(err))?
+        };
         tracing::trace!(bytes_written, "write_vectored");
         if bytes_written != io_size as i32 {
             return Err(DiskError::IllegalBlock);
         }
 
+        // Regular files have no FUA, so emulate it with an `fdatasync` once
+        // the write has completed.
+        if fua && is_file {
+            // SAFETY: No data buffers.
+            unsafe {
+                self.driver.io_uring_submit(
+                    opcode::Fsync::new(types::Fd(self.file.as_raw_fd()))
+                        .flags(types::FsyncFlags::DATASYNC)
+                        .build(),
+                )
+            }
+            .await
+            .map_err(|err| self.map_io_error(err))?;
+        }
+
         Ok(())
     }
 
@@ -1132,6 +1431,36 @@ async fn test_async_disk_io_unaligned_fua() {
         run_async_disk_io_unaligned(true).await;
     }
 
+    #[async_test]
+    async fn test_registered_bounce_buffer() {
+        let disk = get_block_device_or_skip!();
+
+        let gm = GuestMemory::allocate(0x2000);
+        gm.write_at(0, &[0xa5; 0x1000]).unwrap();
+        let write_buffers = OwnedRequestBuffers::new_unaligned(&[0], 512, 1024);
+        disk.write_vectored(&write_buffers.buffer(&gm), 0, false)
+            .await
+            .unwrap();
+        let read_buffers = OwnedRequestBuffers::new_unaligned(&[1], 512, 1024);
+        disk.read_vectored(&read_buffers.buffer(&gm), 0)
+            .await
+            .unwrap();
+
+        let mut target = vec![0; 1024];
+        gm.read_at(HV_PAGE_SIZE + 512, &mut target).unwrap();
+        assert_eq!(target, [0xa5; 1024]);
+
+        let pool = &disk.fixed_bounce_buffers;
+        if !pool.enabled.load(Ordering::Relaxed) {
+            println!("Test case skipped (no registered buffer support)");
+            return;
+        }
+        // Both IOs used the same registered buffer, which was returned to
+        // the pool afterwards.
+        assert_eq!(pool.allocated.load(Ordering::Relaxed), 1);
+        assert_eq!(pool.free.lock().len(), 1);
+    }
+
     #[async_test]
     async fn test_illegal_lba() {
         let disk = get_block_device_or_skip!();
@@ -1,8 +1,13 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
+//! A disk backed by a file.
+//!
+//! On Linux, when given an io-uring capable driver, IO is issued directly via
+//! io-uring, using the same path as [`disk_blockdevice::BlockDevice`].
+//! Otherwise, each IO is issued synchronously on the blocking thread pool.
+
 #![expect(missing_docs)]
-#![forbid(unsafe_code)]
 
 mod readwriteat;
 
@@ -16,6 +21,7 @@
 use guestmem::MemoryRead;
 use guestmem::MemoryWrite;
 use inspect::Inspect;
+use scsi_buffers::BounceBuffer;
 use scsi_buffers::RequestBuffers;
 use std::fs;
 use std::sync::Arc;
@@ -44,10 +50,13 @@ fn resolve(
         rsrc: FileDiskHandle,
         input: ResolveDiskParameters<'_>,
     ) -> Result<Self::Output, Self::Error> {
-        ResolvedDisk::new(
-            FileDisk::open(rsrc.0, input.read_only).map_err(ResolveFileDiskError::Io)?,
-        )
-        .map_err(ResolveFileDiskError::InvalidDisk)
+        #[cfg(target_os = "linux")]
+        let disk =
+            FileDisk::open_with_driver(rsrc.0, input.read_only, input.driver_source.current());
+        #[cfg(not(target_os = "linux"))]
+        let disk = FileDisk::open(rsrc.0, input.read_only);
+        ResolvedDisk::new(disk.map_err(ResolveFileDiskError::Io)?)
+            .map_err(ResolveFileDiskError::InvalidDisk)
     }
 }
 
@@ -56,6 +65,8 @@ pub struct FileDisk {
     file: Arc<fs::File>,
     metadata: Metadata,
     sector_shift: u32,
+    #[cfg(target_os = "linux")]
+    uring: Option<disk_blockdevice::BlockDevice>,
 }
 
 #[derive(Debug, Inspect)]
@@ -90,9 +101,39 @@ pub fn with_metadata(file: fs::File, metadata: Metadata) -> Self {
             file: Arc::new(file),
             metadata,
             sector_shift,
+            #[cfg(target_os = "linux")]
+            uring: None,
         }
     }
 
+    /// Opens the disk, issuing IO via io-uring on `driver` if it supports
+    /// io-uring, or on the blocking thread pool otherwise.
+    ///
+    /// With io-uring, FUA writes are followed by an `fdatasync`, unmap punches
+    /// holes in the file if the filesystem supports it, the file may be opened
+    /// with `O_DIRECT`, and bounce buffered IOs use buffers registered with the
+    /// ring (`ReadFixed`/`WriteFixed`) when the kernel supports sparse buffer
+    /// tables.
+    #[cfg(target_os = "linux")]
+    pub fn open_with_driver(
+        file: fs::File,
+        read_only: bool,
+        driver: impl pal_async::driver::Driver,
+    ) -> Result<Self, std::io::Error> {
+        let mut disk = Self::open(file, read_only)?;
+        // Fall back to the thread pool if the file cannot be serviced via
+        // io-uring, e.g. because the driver does not support it.
+        disk.uring = disk_blockdevice::BlockDevice::new_file(
+            disk.file.try_clone()?,
+            read_only,
+            driver,
+            None,
+            false,
+        )
+        .ok();
+        Ok(disk)
+    }
+
     pub fn into_inner(self) -> fs::File {
         Arc::try_unwrap(self.file).expect("no outstanding IOs")
     }
@@ -103,16 +144,21 @@ pub async fn read(&self, buffers: &RequestBuffers<'_>, sector: u64) -> Result<()
         if ((sector << self.sector_shift) + buffers.len() as u64) > self.metadata.disk_size {
             return Err(DiskError::IllegalBlock);
         }
-        let mut buffer = vec![0; buffers.len()];
-        let file = self.file.clone();
         let offset = sector << self.sector_shift;
-        let buffer = unblock(move || -> Result<_, std::io::Error> {
-            file.read_at(&mut buffer, offset)?;
+        #[cfg(target_os = "linux")]
+        if let Some(uring) = &self.uring {
+            return uring.read_vectored(buffers, sector).await;
+        }
+        // Use a page-aligned buffer so that files opened with `O_DIRECT` work.
+        let mut buffer = BounceBuffer::new(buffers.len());
+        let file = self.file.clone();
+        let mut buffer = unblock(move || -> Result<_, std::io::Error> {
+            file.read_at(buffer.as_mut_bytes(), offset)?;
             Ok(buffer)
         })
         .await
         .map_err(DiskError::Io)?;
-        buffers.writer().write(&buffer)?;
+        buffers.writer().write(buffer.as_mut_bytes())?;
         Ok(())
     }
 
@@ -120,22 +166,32 @@ pub async fn write(
         &self,
         buffers: &RequestBuffers<'_>,
         sector: u64,
-        _fua: bool,
+        fua: bool,
     ) -> Result<(), DiskError> {
         if ((sector << self.sector_shift) + buffers.len() as u64) > self.metadata.disk_size {
             return Err(DiskError::IllegalBlock);
         }
-        let mut buffer = vec![0; buffers.len()];
-        let file = self.file.clone();
-        buffers.reader().read(&mut buffer)?;
         let offset = sector << self.sector_shift;
-        unblock(move || file.write_at(&buffer, offset))
+        #[cfg(target_os = "linux")]
+        if let Some(uring) = &self.uring {
+            return uring.write_vectored(buffers, sector, fua).await;
+        }
+        // FUA is not respected on this path; see `is_fua_respected`.
+        let _ = fua;
+        let mut buffer = BounceBuffer::new(buffers.len());
+        let file = self.file.clone();
+        buffers.reader().read(buffer.as_mut_bytes())?;
+        unblock(move || file.write_at(buffer.as_mut_bytes(), offset))
             .await
             .map_err(DiskError::Io)?;
         Ok(())
     }
 
     pub async fn flush(&self) -> Result<(), DiskError> {
+        #[cfg(target_os = "linux")]
+        if let Some(uring) = &self.uring {
+            return uring.sync_cache().await;
+        }
         let file = self.file.clone();
         unblock(move || file.sync_all())
             .await
@@ -170,6 +226,10 @@ fn physical_sector_size(&self) -> u32 {
     }
 
     fn is_fua_respected(&self) -> bool {
+        #[cfg(target_os = "linux")]
+        if let Some(uring) = &self.uring {
+            return uring.is_fua_respected();
+        }
         false
     }
 
@@ -196,16 +256,33 @@ async fn sync_cache(&self) -> Result<(), DiskError> {
 
     async fn unmap(
         &self,
-        _sector: u64,
-        _count: u64,
-        _block_level_only: bool,
+        sector: u64,
+        count: u64,
+        block_level_only: bool,
     ) -> Result<(), DiskError> {
+        #[cfg(target_os = "linux")]
+        if let Some(uring) = &self.uring {
+            return uring.unmap(sector, count, block_level_only).await;
+        }
+        let _ = (sector, count, block_level_only);
         Ok(())
     }
 
     fn unmap_behavior(&self) -> disk_backend::UnmapBehavior {
+        #[cfg(target_os = "linux")]
+        if let Some(uring) = &self.uring {
+            return uring.unmap_behavior();
+        }
         disk_backend::UnmapBehavior::Ignored
     }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        #[cfg(target_os = "linux")]
+        if let Some(uring) = &self.uring {
+            return uring.optimal_unmap_sectors();
+        }
+        1
+    }
 }
 
 #[cfg(test)]
@@ -267,4 +344,147 @@ async fn sector_does_not_wrap_when_shifted() {
         assert_ne!(buf, [0xcd; SECTOR_SIZE], "read returned sector 0");
         assert!(matches!(r, Err(DiskError::IllegalBlock)), "{r:?}");
     }
+
+    #[cfg(target_os = "linux")]
+    mod uring {
+        use super::DISK_SIZE;
+        use super::SECTOR_SIZE;
+        use crate::FileDisk;
+        use disk_backend::Disk;
+        use disk_backend::UnmapBehavior;
+        use guestmem::GuestMemory;
+        use pal_async::async_test;
+        use pal_uring::IoUringPool;
+        use pal_uring::PoolClient;
+        use scsi_buffers::OwnedRequestBuffers;
+        use std::sync::OnceLock;
+
+        /// Returns a disk issuing IO via io-uring, or `None` if io-uring is
+        /// not available on this kernel.
+        fn uring_file_disk() -> Option<Disk> {
+            static POOL: OnceLock<Option<PoolClient>> = OnceLock::new();
+
+            let client = POOL.get_or_init(|| match IoUringPool::new("test", 16) {
+                Ok(pool) => {
+                    let client = pool.client().clone();
+                    std::thread::spawn(|| pool.run());
+                    Some(client)
+                }
+                Err(err) if err.raw_os_error() == Some(libc::ENOSYS) => None,
+                Err(err) => panic!("{err}"),
+            });
+            let Some(client) = client else {
+                println!("Test case skipped (no IO-Uring support)");
+                return None;
+            };
+
+            let file = tempfile::tempfile().unwrap();
+            file.set_len(DISK_SIZE).unwrap();
+            let disk = FileDisk::open_with_driver(file, false, client.initiator().clone()).unwrap();
+            assert!(disk.uring.is_some());
+            Some(Disk::new(disk).unwrap())
+        }
+
+        async fn write_read_roundtrip(disk: &Disk, mem_offset: u64, fua: bool) {
+            let mem = GuestMemory::allocate(0x4000);
+            let pattern: Vec<u8> = (0..SECTOR_SIZE * 2).map(|i| i as u8).collect();
+
+            mem.write_at(mem_offset, &pattern).unwrap();
+            disk.write_vectored(
+                &OwnedRequestBuffers::linear(mem_offset, pattern.len(), false).buffer(&mem),
+                3,
+                fua,
+            )
+            .await
+            .unwrap();
+
+            mem.fill_at(mem_offset, 0, pattern.len()).unwrap();
+            disk.read_vectored(
+                &OwnedRequestBuffers::linear(mem_offset, pattern.len(), true).buffer(&mem),
+                3,
+            )
+            .await
+            .unwrap();
+
+            let mut buf = vec![0; pattern.len()];
+            mem.read_at(mem_offset, &mut buf).unwrap();
+            assert_eq!(buf, pattern);
+        }
+
+        #[async_test]
+        async fn sector_range_conformance() {
+            let Some(disk) = uring_file_disk() else {
+                return;
+            };
+            storage_tests::sector_range::test_disk_sector_range_conformance(&disk).await;
+        }
+
+        #[async_test]
+        async fn write_read_aligned() {
+            let Some(disk) = uring_file_disk() else {
+                return;
+            };
+            assert!(disk.is_fua_respected());
+            write_read_roundtrip(&disk, 0x1000, false).await;
+            write_read_roundtrip(&disk, 0x1000, true).await;
+        }
+
+        /// Buffers not aligned to the sector size are bounced, since they
+        /// cannot be used with `O_DIRECT`.
+        #[async_test]
+        async fn write_read_unaligned() {
+            let Some(disk) = uring_file_disk() else {
+                return;
+            };
+            write_read_roundtrip(&disk, 0x1008, false).await;
+            write_read_roundtrip(&disk, 0x1008, true).await;
+        }
+
+        #[async_test]
+        async fn unmap_reads_back_zeroes() {
+            let Some(disk) = uring_file_disk() else {
+                return;
+            };
+            if disk.unmap_behavior() != UnmapBehavior::Zeroes {
+                println!("Test case skipped (no hole punch support)");
+                return;
+            }
+
+            let granularity = disk.optimal_unmap_sectors() as u64;
+            let len = (granularity as usize) * SECTOR_SIZE;
+            let mem = GuestMemory::allocate(len);
+            mem.fill_at(0, 0xcd, len).unwrap();
+            disk.write_vectored(
+                &OwnedRequestBuffers::linear(0, len, false).buffer(&mem),
+                0,
+                false,
+            )
+            .await
+            .unwrap();
+
+            disk.unmap(0, granularity, false).await.unwrap();
+
+            mem.fill_at(0, 0xff, len).unwrap();
+            disk.read_vectored(&OwnedRequestBuffers::linear(0, len, true).buffer(&mem), 0)
+                .await
+                .unwrap();
+            let mut buf = vec![0xff; len];
+            mem.read_at(0, &mut buf).unwrap();
+            assert!(buf.iter().all(|&b| b == 0));
+        }
+
+        #[async_test]
+        async fn unmap_out_of_range() {
+            let Some(disk) = uring_file_disk() else {
+                return;
+            };
+            if disk.unmap_behavior() != UnmapBehavior::Zeroes {
+                println!("Test case skipped (no hole punch support)");
+                return;
+            }
+            let sector_count = disk.sector_count();
+            assert!(disk.unmap(sector_count, 1, false).await.is_err());
+            assert!(disk.unmap(1, u64::MAX, false).await.is_err());
+        }
+    }
 }
@@ -0,0 +1,551 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The client side of an NBD connection: the handshake, and the task that
+//! sends requests and dispatches replies to them.
+//!
+//! Requests are pipelined. Each is assigned a cookie and recorded as pending
+//! before it is sent, and the reply chunks for it (which may arrive in any
+//! order relative to other requests) are accumulated until the final one.
+
+use crate::Extent;
+use crate::protocol::*;
+use disk_backend::DiskError;
+use futures::AsyncRead;
+use futures::AsyncReadExt;
+use futures::AsyncWrite;
+use futures::AsyncWriteExt;
+use futures::future::Either;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::io;
+use std::pin::pin;
+use thiserror::Error;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+use zerocopy::big_endian::U16;
+use zerocopy::big_endian::U32;
+
+/// An error connecting to an NBD server.
+#[derive(Debug, Error)]
+pub enum ConnectError {
+    /// Failed to connect to the server.
+    #[error("failed to connect to the server")]
+    Connect(#[source] io::Error),
+    /// An IO error occurred during the handshake.
+    #[error("handshake io error")]
+    Io(#[from] io::Error),
+    /// The server violated the protocol during the handshake.
+    #[error("protocol error: {0}")]
+    Protocol(&'static str),
+    /// The server does not support the fixed newstyle handshake.
+    #[error("server does not support the fixed newstyle handshake")]
+    NotFixedNewstyle,
+    /// The server does not have the requested export.
+    #[error("unknown export {0:?}")]
+    UnknownExport(String),
+    /// The server rejected an option.
+    #[error("server rejected option {option} with error {error:#x}")]
+    OptionRejected {
+        /// The option.
+        option: u32,
+        /// The error reply type.
+        error: u32,
+    },
+    /// The server's block size constraints cannot be represented by a disk.
+    #[error("unsupported minimum block size {0}")]
+    UnsupportedBlockSize(u32),
+}
+
+/// The export parameters negotiated during the handshake.
+pub struct Negotiated {
+    pub size: u64,
+    pub transmission_flags: u16,
+    pub structured_replies: bool,
+    /// The ID of the `base:allocation` metadata context, if negotiated.
+    pub allocation_context: Option<u32>,
+    /// The minimum, preferred, and maximum block sizes, if advertised.
+    pub block_size: Option<(u32, u32, u32)>,
+}
+
+async fn send_option(
+    socket: &mut (impl AsyncWrite + Unpin),
+    option: u32,
+    data: &[u8],
+) -> io::Result<()> {
+    let header = OptionHeader {
+        magic: IHAVEOPT.into(),
+        option: option.into(),
+        len: (data.len() as u32).into(),
+    };
+    socket.write_all(header.as_bytes()).await?;
+    socket.write_all(data).await?;
+    Ok(())
+}
+
+/// Reads an option reply, returning its type and data.
+async fn read_option_reply(
+    socket: &mut (impl AsyncRead + Unpin),
+    option: u32,
+) -> Result<(u32, Vec<u8>), ConnectError> {
+    let mut header = OptionReplyHeader::new_zeroed();
+    socket.read_exact(header.as_mut_bytes()).await?;
+    if header.magic.get() != REPLY_MAGIC {
+        return Err(ConnectError::Protocol("invalid option reply magic"));
+    }
+    if header.option.get() != option {
+        return Err(ConnectError::Protocol("option reply for the wrong option"));
+    }
+    if header.len.get() > MAX_OPTION_LEN {
+        return Err(ConnectError::Protocol("option reply too large"));
+    }
+    let mut data = vec![0; header.len.get() as usize];
+    socket.read_exact(&mut data).await?;
+    Ok((header.reply_type.get(), data))
+}
+
+/// Returns the option data for an export name, prefixed by its length.
+fn name_data(export_name: &str) -> Vec<u8> {
+    let mut data = (export_name.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(export_name.as_bytes());
+    data
+}
+
+/// Performs the fixed newstyle handshake for `export_name`.
+pub async fn handshake(
+    socket: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    export_name: &str,
+) -> Result<Negotiated, ConnectError> {
+    let mut greeting = Greeting::new_zeroed();
+    socket.read_exact(greeting.as_mut_bytes()).await?;
+    if greeting.nbdmagic.get() != NBDMAGIC {
+        return Err(ConnectError::Protocol("invalid greeting magic"));
+    }
+    if greeting.ihaveopt.get() != IHAVEOPT {
+        // An oldstyle server, which is obsolete.
+        return Err(ConnectError::NotFixedNewstyle);
+    }
+    let handshake_flags = greeting.handshake_flags.get();
+    if handshake_flags & NBD_FLAG_FIXED_NEWSTYLE == 0 {
+        return Err(ConnectError::NotFixedNewstyle);
+    }
+    let no_zeroes = handshake_flags & NBD_FLAG_NO_ZEROES != 0;
+    let client_flags = NBD_FLAG_C_FIXED_NEWSTYLE | if no_zeroes { NBD_FLAG_C_NO_ZEROES } else { 0 };
+    socket.write_all(&client_flags.to_be_bytes()).await?;
+
+    // Structured replies let the server send holes without their data and
+    // are required for block status.
+    send_option(socket, NBD_OPT_STRUCTURED_REPLY, &[]).await?;
+    let structured_replies = match read_option_reply(socket, NBD_OPT_STRUCTURED_REPLY).await? {
+        (NBD_REP_ACK, _) => true,
+        (reply, _) if reply & NBD_REP_FLAG_ERROR != 0 => false,
+        _ => {
+            return Err(ConnectError::Protocol(
+                "unexpected reply to the structured reply option",
+            ));
+        }
+    };
+
+    let mut allocation_context = None;
+    if structured_replies {
+        let mut data = name_data(export_name);
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(&(BASE_ALLOCATION.len() as u32).to_be_bytes());
+        data.extend_from_slice(BASE_ALLOCATION.as_bytes());
+        send_option(socket, NBD_OPT_SET_META_CONTEXT, &data).await?;
+        loop {
+            match read_option_reply(socket, NBD_OPT_SET_META_CONTEXT).await? {
+                (NBD_REP_META_CONTEXT, data) => {
+                    let (id, name) = U32::read_from_prefix(&data)
+                        .map_err(|_| ConnectError::Protocol("meta context reply too short"))?;
+                    if name == BASE_ALLOCATION.as_bytes() {
+                        allocation_context = Some(id.get());
+                    }
+                }
+                (NBD_REP_ACK, _) => break,
+                // Block status is optional.
+                (reply, _) if reply & NBD_REP_FLAG_ERROR != 0 => break,
+                _ => return Err(ConnectError::Protocol("unexpected meta context reply")),
+            }
+        }
+    }
+
+    let mut data = name_data(export_name);
+    data.extend_from_slice(&1u16.to_be_bytes());
+    data.extend_from_slice(&NBD_INFO_BLOCK_SIZE.to_be_bytes());
+    send_option(socket, NBD_OPT_GO, &data).await?;
+    let mut export = None;
+    let mut block_size = None;
+    loop {
+        match read_option_reply(socket, NBD_OPT_GO).await? {
+            (NBD_REP_INFO, data) => {
+                let (info_type, info) = U16::read_from_prefix(&data)
+                    .map_err(|_| ConnectError::Protocol("info reply too short"))?;
+                match info_type.get() {
+                    NBD_INFO_EXPORT => {
+                        let info = InfoExport::read_from_bytes(info)
+                            .map_err(|_| ConnectError::Protocol("invalid export info"))?;
+                        export = Some((info.size.get(), info.transmission_flags.get()));
+                    }
+                    NBD_INFO_BLOCK_SIZE => {
+                        let info = InfoBlockSize::read_from_bytes(info)
+                            .map_err(|_| ConnectError::Protocol("invalid block size info"))?;
+                        block_size =
+                            Some((info.minimum.get(), info.preferred.get(), info.maximum.get()));
+                    }
+                    _ => {}
+                }
+            }
+            (NBD_REP_ACK, _) => break,
+            (NBD_REP_ERR_UNSUP, _) => {
+                // A server that predates NBD_OPT_GO.
+                return export_name_fallback(
+                    socket,
+                    export_name,
+                    no_zeroes,
+                    structured_replies,
+                    allocation_context,
+                )
+                .await;
+            }
+            (NBD_REP_ERR_UNKNOWN, _) => {
+                return Err(ConnectError::UnknownExport(export_name.to_owned()));
+            }
+            (reply, _) if reply & NBD_REP_FLAG_ERROR != 0 => {
+                return Err(ConnectError::OptionRejected {
+                    option: NBD_OPT_GO,
+                    error: reply,
+                });
+            }
+            _ => return Err(ConnectError::Protocol("unexpected go reply")),
+        }
+    }
+
+    let (size, transmission_flags) =
+        export.ok_or(ConnectError::Protocol("no export info in go reply"))?;
+    Ok(Negotiated {
+        size,
+        transmission_flags,
+        structured_replies,
+        allocation_context,
+        block_size,
+    })
+}
+
+/// Enters transmission with `NBD_OPT_EXPORT_NAME`, for servers that do not
+/// support `NBD_OPT_GO`.
+async fn export_name_fallback(
+    socket: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    export_name: &str,
+    no_zeroes: bool,
+    structured_replies: bool,
+    allocation_context: Option<u32>,
+) -> Result<Negotiated, ConnectError> {
+    send_option(socket, NBD_OPT_EXPORT_NAME, export_name.as_bytes()).await?;
+    // There is no error reply: the server disconnects if the export is
+    // unknown.
+    let mut info = InfoExport::new_zeroed();
+    socket
+        .read_exact(info.as_mut_bytes())
+        .await
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => ConnectError::UnknownExport(export_name.to_owned()),
+            _ => err.into(),
+        })?;
+    if !no_zeroes {
+        socket.read_exact(&mut [0; 124]).await?;
+    }
+    Ok(Negotiated {
+        size: info.size.get(),
+        transmission_flags: info.transmission_flags.get(),
+        structured_replies,
+        allocation_context,
+        block_size: None,
+    })
+}
+
+/// A request to send to the server.
+pub struct Request {
+    pub command: u16,
+    pub flags: u16,
+    pub offset: u64,
+    pub len: u32,
+    /// The payload for `NBD_CMD_WRITE`.
+    pub data: Vec<u8>,
+    pub done: mesh::OneshotSender<Result<Reply, ReplyError>>,
+}
+
+/// The successful result of a request.
+#[derive(Default)]
+pub struct Reply {
+    /// The data read by `NBD_CMD_READ`.
+    pub data: Vec<u8>,
+    /// The extents returned by `NBD_CMD_BLOCK_STATUS`.
+    pub extents: Vec<Extent>,
+}
+
+/// An error returned by the server for a request.
+#[derive(Debug)]
+pub struct ReplyError {
+    pub error: u32,
+    pub message: String,
+}
+
+impl From<ReplyError> for DiskError {
+    fn from(err: ReplyError) -> Self {
+        match err.error {
+            NBD_EPERM => DiskError::ReadOnly,
+            NBD_EINVAL => DiskError::InvalidInput,
+            NBD_ENOSPC => DiskError::Io(io::Error::new(
+                io::ErrorKind::StorageFull,
+                "nbd server out of space",
+            )),
+            error => DiskError::Io(io::Error::other(format!(
+                "nbd server error {error}: {}",
+                err.message
+            ))),
+        }
+    }
+}
+
+/// A request that has been sent and is awaiting its reply.
+struct Pending {
+    command: u16,
+    offset: u64,
+    reply: Reply,
+    error: Option<ReplyError>,
+    done: mesh::OneshotSender<Result<Reply, ReplyError>>,
+}
+
+impl Pending {
+    fn complete(self) {
+        self.done.send(match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.reply),
+        });
+    }
+
+    /// Returns the range of the read buffer for `len` bytes of data at
+    /// `offset`.
+    fn read_range(&self, offset: u64, len: u32) -> io::Result<std::ops::Range<usize>> {
+        if self.command != NBD_CMD_READ {
+            return Err(invalid_data("read data for a non-read request"));
+        }
+        offset
+            .checked_sub(self.offset)
+            .map(|start| start as usize..start as usize + len as usize)
+            .filter(|range| range.end <= self.reply.data.len())
+            .ok_or_else(|| invalid_data("read data out of range"))
+    }
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+type PendingMap = Mutex<HashMap<u64, Pending>>;
+
+/// Runs the connection, sending requests received on `recv` until it is
+/// closed, and dispatching replies to them.
+pub async fn run(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    mut recv: mesh::Receiver<Request>,
+    allocation_context: Option<u32>,
+) {
+    let pending = PendingMap::default();
+    let send = pin!(send_requests(&mut writer, &mut recv, &pending));
+    let receive = pin!(receive_replies(&mut reader, &pending, allocation_context));
+    let (Either::Left((result, _)) | Either::Right((result, _))) =
+        futures::future::select(send, receive).await;
+    if let Err(err) = result {
+        tracing::warn!(
+            error = &err as &dyn std::error::Error,
+            "nbd connection failed"
+        );
+    }
+    // Dropping the pending requests fails them.
+}
+
+async fn send_requests(
+    writer: &mut (impl AsyncWrite + Unpin),
+    recv: &mut mesh::Receiver<Request>,
+    pending: &PendingMap,
+) -> io::Result<()> {
+    let mut next_cookie = 0u64;
+    while let Ok(req) = recv.recv().await {
+        let cookie = next_cookie;
+        next_cookie += 1;
+        let header = crate::protocol::Request {
+            magic: NBD_REQUEST_MAGIC.into(),
+            flags: req.flags.into(),
+            command: req.command.into(),
+            cookie: cookie.into(),
+            offset: req.offset.into(),
+            len: req.len.into(),
+        };
+        let data = if req.command == NBD_CMD_READ {
+            vec![0; req.len as usize]
+        } else {
+            Vec::new()
+        };
+        pending.lock().insert(
+            cookie,
+            Pending {
+                command: req.command,
+                offset: req.offset,
+                reply: Reply {
+                    data,
+                    extents: Vec::new(),
+                },
+                error: None,
+                done: req.done,
+            },
+        );
+        writer.write_all(header.as_bytes()).await?;
+        writer.write_all(&req.data).await?;
+    }
+
+    // The disk has been dropped, so disconnect cleanly.
+    let header = crate::protocol::Request {
+        magic: NBD_REQUEST_MAGIC.into(),
+        flags: 0.into(),
+        command: NBD_CMD_DISC.into(),
+        cookie: next_cookie.into(),
+        offset: 0.into(),
+        len: 0.into(),
+    };
+    writer.write_all(header.as_bytes()).await?;
+    writer.flush().await
+}
+
+fn take_pending(pending: &PendingMap, cookie: u64) -> io::Result<Pending> {
+    pending
+        .lock()
+        .remove(&cookie)
+        .ok_or_else(|| invalid_data("reply for unknown cookie"))
+}
+
+async fn receive_replies(
+    reader: &mut (impl AsyncRead + Unpin),
+    pending: &PendingMap,
+    allocation_context: Option<u32>,
+) -> io::Result<()> {
+    loop {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).await?;
+        match u32::from_be_bytes(magic) {
+            NBD_SIMPLE_REPLY_MAGIC => {
+                let mut reply = SimpleReply::new_zeroed();
+                reply.as_mut_bytes()[..4].copy_from_slice(&magic);
+                reader.read_exact(&mut reply.as_mut_bytes()[4..]).await?;
+                let mut p = take_pending(pending, reply.cookie.get())?;
+                let error = reply.error.get();
+                if error != 0 {
+                    p.error = Some(ReplyError {
+                        error,
+                        message: String::new(),
+                    });
+                } else if p.command == NBD_CMD_READ {
+                    reader.read_exact(&mut p.reply.data).await?;
+                } else if p.command == NBD_CMD_BLOCK_STATUS {
+                    return Err(invalid_data("simple reply to block status"));
+                }
+                p.complete();
+            }
+            NBD_STRUCTURED_REPLY_MAGIC => {
+                let mut header = StructuredReplyHeader::new_zeroed();
+                header.as_mut_bytes()[..4].copy_from_slice(&magic);
+                reader.read_exact(&mut header.as_mut_bytes()[4..]).await?;
+                let mut p = take_pending(pending, header.cookie.get())?;
+                receive_chunk(reader, &header, &mut p, allocation_context).await?;
+                if header.flags.get() & NBD_REPLY_FLAG_DONE != 0 {
+                    p.complete();
+                } else {
+                    pending.lock().insert(header.cookie.get(), p);
+                }
+            }
+            _ => return Err(invalid_data("invalid reply magic")),
+        }
+    }
+}
+
+/// Receives the payload of a structured reply chunk for `p`.
+async fn receive_chunk(
+    reader: &mut (impl AsyncRead + Unpin),
+    header: &StructuredReplyHeader,
+    p: &mut Pending,
+    allocation_context: Option<u32>,
+) -> io::Result<()> {
+    let len = header.len.get();
+    match header.reply_type.get() {
+        NBD_REPLY_TYPE_NONE => {
+            if len != 0 {
+                return Err(invalid_data("invalid none chunk"));
+            }
+        }
+        NBD_REPLY_TYPE_OFFSET_DATA => {
+            let data_len = len
+                .checked_sub(8)
+                .ok_or_else(|| invalid_data("offset data chunk too short"))?;
+            let mut offset = [0; 8];
+            reader.read_exact(&mut offset).await?;
+            let range = p.read_range(u64::from_be_bytes(offset), data_len)?;
+            reader.read_exact(&mut p.reply.data[range]).await?;
+        }
+        NBD_REPLY_TYPE_OFFSET_HOLE => {
+            let mut hole = OffsetHole::new_zeroed();
+            if len as usize != size_of_val(&hole) {
+                return Err(invalid_data("invalid offset hole chunk"));
+            }
+            reader.read_exact(hole.as_mut_bytes()).await?;
+            let range = p.read_range(hole.offset.get(), hole.len.get())?;
+            p.reply.data[range].fill(0);
+        }
+        NBD_REPLY_TYPE_BLOCK_STATUS => {
+            if p.command != NBD_CMD_BLOCK_STATUS
+                || len < 4
+                || (len - 4) as usize % size_of::<BlockDescriptor>() != 0
+                || len > MAX_OPTION_LEN
+            {
+                return Err(invalid_data("invalid block status chunk"));
+            }
+            let mut payload = vec![0; len as usize];
+            reader.read_exact(&mut payload).await?;
+            let (context_id, descriptors) = payload.split_at(4);
+            if Some(u32::from_be_bytes(context_id.try_into().unwrap())) == allocation_context {
+                p.reply.extents.extend(
+                    <[BlockDescriptor]>::ref_from_bytes(descriptors)
+                        .unwrap()
+                        .iter()
+                        .map(|d| Extent {
+                            len: d.len.get(),
+                            hole: d.status.get() & NBD_STATE_HOLE != 0,
+                            zero: d.status.get() & NBD_STATE_ZERO != 0,
+                        }),
+                );
+            }
+        }
+        reply_type if reply_type & (1 << 15) != 0 => {
+            // An error chunk. Unknown error types still start with the error
+            // and message.
+            if len > MAX_OPTION_LEN {
+                return Err(invalid_data("error chunk too large"));
+            }
+            let mut payload = vec![0; len as usize];
+            reader.read_exact(&mut payload).await?;
+            let (chunk, rest) = ErrorChunk::read_from_prefix(&payload)
+                .map_err(|_| invalid_data("error chunk too short"))?;
+            let message = rest
+                .get(..chunk.message_len.get() as usize)
+                .ok_or_else(|| invalid_data("error chunk message too long"))?;
+            p.error.get_or_insert(ReplyError {
+                error: chunk.error.get(),
+                message: String::from_utf8_lossy(message).into_owned(),
+            });
+        }
+        _ => return Err(invalid_data("unknown structured reply chunk type")),
+    }
+    Ok(())
+}
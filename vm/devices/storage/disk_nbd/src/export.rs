@@ -0,0 +1,131 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! A disk wrapper that exports the disk to NBD clients while it is in use.
+
+use crate::server::NbdServer;
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use inspect::Inspect;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use scsi_buffers::RequestBuffers;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// A disk that passes IO through to an inner disk, which it also exports to
+/// NBD clients for as long as it is alive.
+#[derive(Inspect)]
+pub struct NbdExportDisk {
+    inner: Disk,
+    export_name: String,
+    read_only_export: bool,
+    #[inspect(skip)]
+    _server: Task<()>,
+}
+
+impl NbdExportDisk {
+    /// Exports `inner` as `export_name` to clients accepted on `listener`,
+    /// which must be bound and listening.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        inner: Disk,
+        listener: socket2::Socket,
+        export_name: String,
+        read_only: bool,
+    ) -> Self {
+        let driver = driver_source.simple();
+        let server = NbdServer::new(inner.clone(), export_name.clone(), read_only);
+        let task = driver.spawn(format!("nbd-export-{export_name}"), {
+            let driver = driver.clone();
+            async move {
+                if let Err(err) = server.run(&driver, listener).await {
+                    tracing::error!(error = &err as &dyn std::error::Error, "nbd export stopped");
+                }
+            }
+        });
+        Self {
+            inner,
+            export_name,
+            read_only_export: read_only,
+            _server: task,
+        }
+    }
+}
+
+impl DiskIo for NbdExportDisk {
+    fn disk_type(&self) -> &str {
+        "nbd_export"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.inner.sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.inner.sector_size()
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        self.inner.disk_id()
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.inner.physical_sector_size()
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        self.inner.is_fua_respected()
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.inner.is_read_only()
+    }
+
+    fn pr(&self) -> Option<&dyn disk_backend::pr::PersistentReservation> {
+        self.inner.pr()
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        self.inner.read_vectored(buffers, sector).await
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        self.inner.write_vectored(buffers, sector, fua).await
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        self.inner.sync_cache().await
+    }
+
+    async fn wait_resize(&self, sector_count: u64) -> u64 {
+        self.inner.wait_resize(sector_count).await
+    }
+
+    fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        block_level_only: bool,
+    ) -> impl Future<Output = Result<(), DiskError>> + Send {
+        self.inner.unmap(sector, count, block_level_only)
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        self.inner.unmap_behavior()
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.inner.optimal_unmap_sectors()
+    }
+}
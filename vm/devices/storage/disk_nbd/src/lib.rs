@@ -0,0 +1,368 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! NBD (Network Block Device) support: a disk backend for an export on an NBD
+//! server, and an NBD server exporting any [`Disk`](disk_backend::Disk).
+//!
+//! Both sides negotiate structured replies and the `base:allocation` metadata
+//! context when the peer supports them, so that holes are not transferred on
+//! reads and the allocation status of an export can be queried. Unmaps are
+//! sent as `NBD_CMD_WRITE_ZEROES` (allowing the server to punch holes) or
+//! `NBD_CMD_TRIM`, depending on what the server supports.
+
+#![forbid(unsafe_code)]
+
+mod connection;
+mod export;
+pub mod protocol;
+pub mod resolver;
+pub mod server;
+
+#[cfg(test)]
+mod tests;
+
+pub use connection::ConnectError;
+pub use export::NbdExportDisk;
+
+use connection::Reply;
+use connection::Request;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use disk_backend_resources::NbdAddress;
+use guestmem::MemoryRead;
+use guestmem::MemoryWrite;
+use inspect::Inspect;
+use pal_async::driver::Driver;
+use pal_async::socket::PolledSocket;
+use pal_async::task::Spawn;
+use protocol::*;
+use scsi_buffers::RequestBuffers;
+use std::io;
+use std::net::ToSocketAddrs;
+
+/// The maximum payload of a single request, when the server does not
+/// advertise one. This is the limit every server is required to accept.
+const DEFAULT_MAX_PAYLOAD: u32 = 32 << 20;
+
+/// The allocation status of a range of an export, as reported by the server's
+/// `base:allocation` metadata context.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Extent {
+    /// The length of the extent in bytes.
+    pub len: u32,
+    /// The extent is not allocated on the server.
+    pub hole: bool,
+    /// The extent reads as zero.
+    pub zero: bool,
+}
+
+/// A disk backed by an export on an NBD server.
+#[derive(Inspect)]
+pub struct NbdDisk {
+    #[inspect(skip)]
+    send: mesh::Sender<Request>,
+    export_name: String,
+    size: u64,
+    sector_size: u32,
+    sector_shift: u32,
+    physical_sector_size: u32,
+    #[inspect(hex)]
+    transmission_flags: u16,
+    read_only: bool,
+    structured_replies: bool,
+    block_status: bool,
+    max_payload: u32,
+}
+
+impl NbdDisk {
+    /// Connects to export `export_name` on the server at `address`.
+    ///
+    /// The disk is read only if `read_only` is set or if the server only
+    /// allows reading the export.
+    pub async fn connect(
+        driver: &(impl Driver + Spawn),
+        address: &NbdAddress,
+        export_name: &str,
+        read_only: bool,
+    ) -> Result<Self, ConnectError> {
+        let socket = match address {
+            NbdAddress::Tcp(addr) => {
+                let addr = addr.clone();
+                let addrs = blocking::unblock(move || {
+                    addr.to_socket_addrs()
+                        .map(|addrs| addrs.collect::<Vec<_>>())
+                })
+                .await
+                .map_err(ConnectError::Connect)?;
+                let mut result = Err(io::ErrorKind::NotFound.into());
+                for addr in addrs {
+                    result = PolledSocket::connect_tcp(driver, addr).await;
+                    if result.is_ok() {
+                        break;
+                    }
+                }
+                let socket = result.map_err(ConnectError::Connect)?;
+                // Requests are small and latency sensitive.
+                socket
+                    .get()
+                    .set_nodelay(true)
+                    .map_err(ConnectError::Connect)?;
+                socket.convert()
+            }
+            NbdAddress::Unix(path) => PolledSocket::connect_unix(driver, path)
+                .await
+                .map_err(ConnectError::Connect)?
+                .convert(),
+        };
+        Self::new(driver, socket, export_name, read_only).await
+    }
+
+    /// Performs the handshake for export `export_name` over a connected
+    /// `socket`.
+    pub async fn new(
+        spawn: &impl Spawn,
+        mut socket: PolledSocket<socket2::Socket>,
+        export_name: &str,
+        read_only: bool,
+    ) -> Result<Self, ConnectError> {
+        let negotiated = connection::handshake(&mut socket, export_name).await?;
+
+        let (minimum, preferred, maximum) =
+            negotiated
+                .block_size
+                .unwrap_or((1, 4096, DEFAULT_MAX_PAYLOAD));
+        // Every request must be a multiple of the server's minimum block size,
+        // so the sector size must cover it.
+        let sector_size = minimum.max(512);
+        if !sector_size.is_power_of_two() || sector_size > 4096 {
+            return Err(ConnectError::UnsupportedBlockSize(minimum));
+        }
+        let physical_sector_size = if preferred.is_power_of_two() {
+            preferred.clamp(sector_size, 4096)
+        } else {
+            sector_size
+        };
+        let max_payload = maximum.clamp(sector_size, DEFAULT_MAX_PAYLOAD) & !(sector_size - 1);
+
+        let flags = negotiated.transmission_flags;
+        let read_only = read_only || flags & NBD_FLAG_READ_ONLY != 0;
+
+        let (reader, writer) = socket.split();
+        let (send, recv) = mesh::channel();
+        // The task exits, disconnecting from the server, once the disk is
+        // dropped.
+        spawn
+            .spawn(
+                format!("nbd-{export_name}"),
+                connection::run(reader, writer, recv, negotiated.allocation_context),
+            )
+            .detach();
+
+        Ok(Self {
+            send,
+            export_name: export_name.to_owned(),
+            size: negotiated.size,
+            sector_size,
+            sector_shift: sector_size.trailing_zeros(),
+            physical_sector_size,
+            transmission_flags: flags,
+            read_only,
+            structured_replies: negotiated.structured_replies,
+            block_status: negotiated.allocation_context.is_some(),
+            max_payload,
+        })
+    }
+
+    fn has_flag(&self, flag: u16) -> bool {
+        self.transmission_flags & flag != 0
+    }
+
+    async fn request(
+        &self,
+        command: u16,
+        flags: u16,
+        offset: u64,
+        len: u32,
+        data: Vec<u8>,
+    ) -> Result<Reply, DiskError> {
+        let (done, recv) = mesh::oneshot();
+        self.send.send(Request {
+            command,
+            flags,
+            offset,
+            len,
+            data,
+            done,
+        });
+        let result = recv.await.map_err(|_| {
+            DiskError::Io(io::Error::new(
+                io::ErrorKind::NotConnected,
+                "nbd connection closed",
+            ))
+        })?;
+        result.map_err(Into::into)
+    }
+
+    /// Validates the range of `count` sectors at `sector` and returns its byte
+    /// offset.
+    fn check_range(&self, sector: u64, count: u64) -> Result<u64, DiskError> {
+        sector
+            .checked_add(count)
+            .filter(|&end| end <= self.sector_count())
+            .ok_or(DiskError::IllegalBlock)?;
+        Ok(sector << self.sector_shift)
+    }
+
+    /// Splits the byte range into requests of at most `max_len` bytes.
+    fn split(offset: u64, len: u64, max_len: u32) -> impl Iterator<Item = (u64, u32)> {
+        (0..len)
+            .step_by(max_len as usize)
+            .map(move |start| (offset + start, (len - start).min(max_len.into()) as u32))
+    }
+
+    /// Returns the allocation status of the `count` sectors at `sector`.
+    ///
+    /// The server may describe only a prefix of the range, or extend the last
+    /// extent past its end. If the server does not support block status, the
+    /// whole range is reported as allocated data.
+    pub async fn block_status(&self, sector: u64, count: u64) -> Result<Vec<Extent>, DiskError> {
+        let offset = self.check_range(sector, count)?;
+        let len =
+            (count << self.sector_shift).min((u32::MAX & !(self.sector_size - 1)).into()) as u32;
+        if len == 0 {
+            return Ok(Vec::new());
+        }
+        if !self.block_status {
+            return Ok(vec![Extent {
+                len,
+                hole: false,
+                zero: false,
+            }]);
+        }
+        let reply = self
+            .request(NBD_CMD_BLOCK_STATUS, 0, offset, len, Vec::new())
+            .await?;
+        Ok(reply.extents)
+    }
+}
+
+impl DiskIo for NbdDisk {
+    fn disk_type(&self) -> &str {
+        "nbd"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.size >> self.sector_shift
+    }
+
+    fn sector_size(&self) -> u32 {
+        self.sector_size
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        None
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        self.physical_sector_size
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        self.has_flag(NBD_FLAG_SEND_FUA)
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        let offset = self.check_range(sector, buffers.len() as u64 >> self.sector_shift)?;
+        let replies = futures::future::try_join_all(
+            Self::split(offset, buffers.len() as u64, self.max_payload)
+                .map(|(offset, len)| self.request(NBD_CMD_READ, 0, offset, len, Vec::new())),
+        )
+        .await?;
+        let mut writer = buffers.writer();
+        for reply in replies {
+            writer.write(&reply.data)?;
+        }
+        Ok(())
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        if self.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        let offset = self.check_range(sector, buffers.len() as u64 >> self.sector_shift)?;
+        let flags = if fua && self.has_flag(NBD_FLAG_SEND_FUA) {
+            NBD_CMD_FLAG_FUA
+        } else {
+            0
+        };
+        let mut reader = buffers.reader();
+        let mut requests = Vec::new();
+        for (offset, len) in Self::split(offset, buffers.len() as u64, self.max_payload) {
+            let mut data = vec![0; len as usize];
+            reader.read(&mut data)?;
+            requests.push(self.request(NBD_CMD_WRITE, flags, offset, len, data));
+        }
+        futures::future::try_join_all(requests).await?;
+        Ok(())
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        if self.has_flag(NBD_FLAG_SEND_FLUSH) {
+            self.request(NBD_CMD_FLUSH, 0, 0, 0, Vec::new()).await?;
+        }
+        Ok(())
+    }
+
+    async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        _block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        let offset = self.check_range(sector, count)?;
+        let command = match self.unmap_behavior() {
+            // Without NBD_CMD_FLAG_NO_HOLE, the server may punch holes to
+            // write zeroes.
+            UnmapBehavior::Zeroes => NBD_CMD_WRITE_ZEROES,
+            UnmapBehavior::Unspecified => NBD_CMD_TRIM,
+            UnmapBehavior::Ignored => return Ok(()),
+        };
+        let max_len = u32::MAX & !(self.sector_size - 1);
+        futures::future::try_join_all(
+            Self::split(offset, count << self.sector_shift, max_len)
+                .map(|(offset, len)| self.request(command, 0, offset, len, Vec::new())),
+        )
+        .await?;
+        Ok(())
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        if self.read_only {
+            UnmapBehavior::Ignored
+        } else if self.has_flag(NBD_FLAG_SEND_WRITE_ZEROES) {
+            UnmapBehavior::Zeroes
+        } else if self.has_flag(NBD_FLAG_SEND_TRIM) {
+            UnmapBehavior::Unspecified
+        } else {
+            UnmapBehavior::Ignored
+        }
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.physical_sector_size >> self.sector_shift
+    }
+}
@@ -0,0 +1,218 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! NBD wire protocol definitions, from the NBD protocol specification
+//! (`doc/proto.md` in the `nbd` project).
+//!
+//! All fields are big endian. Only the fixed newstyle handshake is defined
+//! here; the oldstyle and plain newstyle handshakes are obsolete.
+
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+use zerocopy::big_endian::U16;
+use zerocopy::big_endian::U32;
+use zerocopy::big_endian::U64;
+
+/// The default TCP port for NBD servers.
+pub const DEFAULT_PORT: u16 = 10809;
+
+// Handshake magic values.
+pub const NBDMAGIC: u64 = 0x4e42444d41474943;
+pub const IHAVEOPT: u64 = 0x49484156454f5054;
+pub const REPLY_MAGIC: u64 = 0x3e889045565a9;
+
+// Handshake flags, sent by the server.
+pub const NBD_FLAG_FIXED_NEWSTYLE: u16 = 1 << 0;
+pub const NBD_FLAG_NO_ZEROES: u16 = 1 << 1;
+
+// Client flags, sent in response to the handshake flags.
+pub const NBD_FLAG_C_FIXED_NEWSTYLE: u32 = 1 << 0;
+pub const NBD_FLAG_C_NO_ZEROES: u32 = 1 << 1;
+
+// Transmission flags, describing the export.
+pub const NBD_FLAG_HAS_FLAGS: u16 = 1 << 0;
+pub const NBD_FLAG_READ_ONLY: u16 = 1 << 1;
+pub const NBD_FLAG_SEND_FLUSH: u16 = 1 << 2;
+pub const NBD_FLAG_SEND_FUA: u16 = 1 << 3;
+pub const NBD_FLAG_SEND_TRIM: u16 = 1 << 5;
+pub const NBD_FLAG_SEND_WRITE_ZEROES: u16 = 1 << 6;
+pub const NBD_FLAG_SEND_DF: u16 = 1 << 7;
+pub const NBD_FLAG_CAN_MULTI_CONN: u16 = 1 << 8;
+
+// Options.
+pub const NBD_OPT_EXPORT_NAME: u32 = 1;
+pub const NBD_OPT_ABORT: u32 = 2;
+pub const NBD_OPT_LIST: u32 = 3;
+pub const NBD_OPT_INFO: u32 = 6;
+pub const NBD_OPT_GO: u32 = 7;
+pub const NBD_OPT_STRUCTURED_REPLY: u32 = 8;
+pub const NBD_OPT_LIST_META_CONTEXT: u32 = 9;
+pub const NBD_OPT_SET_META_CONTEXT: u32 = 10;
+
+// Option reply types.
+pub const NBD_REP_ACK: u32 = 1;
+pub const NBD_REP_SERVER: u32 = 2;
+pub const NBD_REP_INFO: u32 = 3;
+pub const NBD_REP_META_CONTEXT: u32 = 4;
+pub const NBD_REP_FLAG_ERROR: u32 = 1 << 31;
+pub const NBD_REP_ERR_UNSUP: u32 = NBD_REP_FLAG_ERROR | 1;
+pub const NBD_REP_ERR_POLICY: u32 = NBD_REP_FLAG_ERROR | 2;
+pub const NBD_REP_ERR_INVALID: u32 = NBD_REP_FLAG_ERROR | 3;
+pub const NBD_REP_ERR_UNKNOWN: u32 = NBD_REP_FLAG_ERROR | 6;
+
+// Information types, for NBD_OPT_INFO and NBD_OPT_GO.
+pub const NBD_INFO_EXPORT: u16 = 0;
+pub const NBD_INFO_BLOCK_SIZE: u16 = 3;
+
+// Commands.
+pub const NBD_CMD_READ: u16 = 0;
+pub const NBD_CMD_WRITE: u16 = 1;
+pub const NBD_CMD_DISC: u16 = 2;
+pub const NBD_CMD_FLUSH: u16 = 3;
+pub const NBD_CMD_TRIM: u16 = 4;
+pub const NBD_CMD_WRITE_ZEROES: u16 = 6;
+pub const NBD_CMD_BLOCK_STATUS: u16 = 7;
+
+// Command flags.
+pub const NBD_CMD_FLAG_FUA: u16 = 1 << 0;
+pub const NBD_CMD_FLAG_NO_HOLE: u16 = 1 << 1;
+pub const NBD_CMD_FLAG_DF: u16 = 1 << 2;
+pub const NBD_CMD_FLAG_REQ_ONE: u16 = 1 << 3;
+
+// Transmission magic values.
+pub const NBD_REQUEST_MAGIC: u32 = 0x25609513;
+pub const NBD_SIMPLE_REPLY_MAGIC: u32 = 0x67446698;
+pub const NBD_STRUCTURED_REPLY_MAGIC: u32 = 0x668e33ef;
+
+// Structured reply flags and chunk types.
+pub const NBD_REPLY_FLAG_DONE: u16 = 1 << 0;
+pub const NBD_REPLY_TYPE_NONE: u16 = 0;
+pub const NBD_REPLY_TYPE_OFFSET_DATA: u16 = 1;
+pub const NBD_REPLY_TYPE_OFFSET_HOLE: u16 = 2;
+pub const NBD_REPLY_TYPE_BLOCK_STATUS: u16 = 5;
+pub const NBD_REPLY_TYPE_ERROR: u16 = (1 << 15) | 1;
+pub const NBD_REPLY_TYPE_ERROR_OFFSET: u16 = (1 << 15) | 2;
+
+/// The metadata context describing allocation status.
+pub const BASE_ALLOCATION: &str = "base:allocation";
+
+// `base:allocation` status flags.
+pub const NBD_STATE_HOLE: u32 = 1 << 0;
+pub const NBD_STATE_ZERO: u32 = 1 << 1;
+
+// Error values, which match the Linux errno values of the same name.
+pub const NBD_EPERM: u32 = 1;
+pub const NBD_EIO: u32 = 5;
+pub const NBD_EINVAL: u32 = 22;
+pub const NBD_ENOSPC: u32 = 28;
+pub const NBD_EOVERFLOW: u32 = 75;
+pub const NBD_ENOTSUP: u32 = 95;
+pub const NBD_ESHUTDOWN: u32 = 108;
+
+/// The largest payload accepted in an option or a structured reply chunk
+/// that does not carry read data.
+pub const MAX_OPTION_LEN: u32 = 0x10000;
+
+/// The initial server greeting.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct Greeting {
+    pub nbdmagic: U64,
+    pub ihaveopt: U64,
+    pub handshake_flags: U16,
+}
+
+/// The header of an option sent by the client.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct OptionHeader {
+    pub magic: U64,
+    pub option: U32,
+    pub len: U32,
+}
+
+/// The header of an option reply sent by the server.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct OptionReplyHeader {
+    pub magic: U64,
+    pub option: U32,
+    pub reply_type: U32,
+    pub len: U32,
+}
+
+/// `NBD_INFO_EXPORT`, following the information type.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct InfoExport {
+    pub size: U64,
+    pub transmission_flags: U16,
+}
+
+/// `NBD_INFO_BLOCK_SIZE`, following the information type.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct InfoBlockSize {
+    pub minimum: U32,
+    pub preferred: U32,
+    pub maximum: U32,
+}
+
+/// A transmission request, followed by the data for `NBD_CMD_WRITE`.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct Request {
+    pub magic: U32,
+    pub flags: U16,
+    pub command: U16,
+    pub cookie: U64,
+    pub offset: U64,
+    pub len: U32,
+}
+
+/// A simple reply, followed by the data for a successful `NBD_CMD_READ`.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct SimpleReply {
+    pub magic: U32,
+    pub error: U32,
+    pub cookie: U64,
+}
+
+/// The header of a structured reply chunk.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct StructuredReplyHeader {
+    pub magic: U32,
+    pub flags: U16,
+    pub reply_type: U16,
+    pub cookie: U64,
+    pub len: U32,
+}
+
+/// The payload of an `NBD_REPLY_TYPE_OFFSET_HOLE` chunk.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct OffsetHole {
+    pub offset: U64,
+    pub len: U32,
+}
+
+/// The start of the payload of an error chunk, followed by the message.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct ErrorChunk {
+    pub error: U32,
+    pub message_len: U16,
+}
+
+/// A `base:allocation` extent in an `NBD_REPLY_TYPE_BLOCK_STATUS` chunk,
+/// following the context ID.
+#[repr(C)]
+#[derive(Debug, IntoBytes, FromBytes, Immutable, KnownLayout)]
+pub struct BlockDescriptor {
+    pub len: U32,
+    pub status: U32,
+}
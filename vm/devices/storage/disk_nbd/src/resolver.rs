@@ -0,0 +1,70 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resolvers for NBD disks.
+
+use crate::NbdDisk;
+use crate::NbdExportDisk;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::NbdDiskHandle;
+use disk_backend_resources::NbdExportDiskHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+
+/// A resolver for disks backed by an NBD export.
+pub struct NbdDiskResolver;
+
+declare_static_async_resolver!(NbdDiskResolver, (DiskHandleKind, NbdDiskHandle));
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, NbdDiskHandle> for NbdDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        _resolver: &ResourceResolver,
+        rsrc: NbdDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let disk = NbdDisk::connect(
+            &input.driver_source.simple(),
+            &rsrc.address,
+            &rsrc.export_name,
+            input.read_only,
+        )
+        .await?;
+        Ok(ResolvedDisk::new(disk)?)
+    }
+}
+
+/// A resolver for disks exported to NBD clients.
+pub struct NbdExportDiskResolver;
+
+declare_static_async_resolver!(NbdExportDiskResolver, (DiskHandleKind, NbdExportDiskHandle));
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, NbdExportDiskHandle> for NbdExportDiskResolver {
+    type Output = ResolvedDisk;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: NbdExportDiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let inner = resolver.resolve(rsrc.disk, input).await?;
+        Ok(ResolvedDisk::new(NbdExportDisk::new(
+            input.driver_source,
+            inner.0,
+            rsrc.listener,
+            rsrc.export_name,
+            rsrc.read_only,
+        ))?)
+    }
+}
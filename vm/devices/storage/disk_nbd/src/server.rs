@@ -0,0 +1,680 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! An NBD server exporting a [`Disk`].
+//!
+//! Requests on a connection are processed in order. Multiple connections are
+//! served concurrently, and since they all share the same disk, the export
+//! advertises `NBD_FLAG_CAN_MULTI_CONN`.
+//!
+//! There is no way to query the allocation status of an arbitrary disk, so
+//! `base:allocation` reports zero-filled ranges as `NBD_STATE_ZERO` (never as
+//! holes), and reads send them as `NBD_REPLY_TYPE_OFFSET_HOLE` chunks. This is
+//! enough for clients to preserve sparseness when copying the export.
+
+use crate::protocol::*;
+use anyhow::Context as _;
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::UnmapBehavior;
+use futures::AsyncRead;
+use futures::AsyncReadExt;
+use futures::AsyncWrite;
+use futures::AsyncWriteExt;
+use futures::FutureExt;
+use futures::StreamExt;
+use futures::stream::FuturesUnordered;
+use guestmem::GuestMemory;
+use pal_async::driver::Driver;
+use pal_async::socket::PolledSocket;
+use scsi_buffers::OwnedRequestBuffers;
+use std::io;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+use zerocopy::big_endian::U16;
+use zerocopy::big_endian::U32;
+
+/// The maximum payload of a single request accepted by the server.
+pub const MAX_PAYLOAD: u32 = 32 << 20;
+
+/// The ID of the `base:allocation` metadata context.
+const ALLOCATION_CONTEXT_ID: u32 = 1;
+
+/// An NBD server exporting a single disk.
+pub struct NbdServer {
+    disk: Disk,
+    export_name: String,
+    read_only: bool,
+}
+
+/// The per-connection state negotiated during the handshake.
+struct Session {
+    structured_replies: bool,
+    block_status: bool,
+}
+
+/// An error to return to the client for a request, as an NBD error value.
+struct RequestError(u32);
+
+impl From<DiskError> for RequestError {
+    fn from(err: DiskError) -> Self {
+        Self(match err {
+            DiskError::ReadOnly => NBD_EPERM,
+            DiskError::IllegalBlock | DiskError::InvalidInput => NBD_EINVAL,
+            DiskError::Io(err) if err.kind() == io::ErrorKind::StorageFull => NBD_ENOSPC,
+            _ => NBD_EIO,
+        })
+    }
+}
+
+impl NbdServer {
+    /// Returns a new server exporting `disk` as `export_name`.
+    ///
+    /// Clients may also connect to the export as the default export, with an
+    /// empty name. If `read_only` is set, clients cannot write to the disk.
+    pub fn new(disk: Disk, export_name: impl Into<String>, read_only: bool) -> Self {
+        Self {
+            read_only: read_only || disk.is_read_only(),
+            disk,
+            export_name: export_name.into(),
+        }
+    }
+
+    /// Accepts and serves clients on `listener`, which must be bound and
+    /// listening, until accepting fails.
+    pub async fn run(
+        &self,
+        driver: &(impl Driver + ?Sized),
+        listener: socket2::Socket,
+    ) -> io::Result<()> {
+        let mut listener = PolledSocket::new(driver, listener)?;
+        let mut connections = FuturesUnordered::new();
+        loop {
+            let socket = futures::select! { // merge semantics
+                r = listener.accept().fuse() => r?.0,
+                _ = connections.select_next_some() => continue,
+            };
+            let socket = PolledSocket::new(driver, socket)?;
+            connections.push(async move {
+                if let Err(err) = self.serve(socket).await {
+                    tracing::warn!(
+                        error = err.as_ref() as &dyn std::error::Error,
+                        "nbd client connection failed"
+                    );
+                }
+            });
+        }
+    }
+
+    /// Serves a single connected client until it disconnects.
+    pub async fn serve(&self, mut socket: PolledSocket<socket2::Socket>) -> anyhow::Result<()> {
+        if let Some(session) = self.handshake(&mut socket).await? {
+            self.transmit(&mut socket, session).await?;
+        }
+        Ok(())
+    }
+
+    fn transmission_flags(&self) -> u16 {
+        let mut flags = NBD_FLAG_HAS_FLAGS | NBD_FLAG_SEND_FLUSH | NBD_FLAG_CAN_MULTI_CONN;
+        if self.read_only {
+            flags |= NBD_FLAG_READ_ONLY;
+        } else {
+            flags |= NBD_FLAG_SEND_FUA | NBD_FLAG_SEND_TRIM | NBD_FLAG_SEND_WRITE_ZEROES;
+        }
+        flags
+    }
+
+    fn is_export(&self, name: &[u8]) -> bool {
+        name.is_empty() || name == self.export_name.as_bytes()
+    }
+
+    /// Performs the fixed newstyle handshake. Returns `None` if the client
+    /// ended the connection without entering transmission.
+    async fn handshake(
+        &self,
+        socket: &mut (impl AsyncRead + AsyncWrite + Unpin),
+    ) -> anyhow::Result<Option<Session>> {
+        let greeting = Greeting {
+            nbdmagic: NBDMAGIC.into(),
+            ihaveopt: IHAVEOPT.into(),
+            handshake_flags: (NBD_FLAG_FIXED_NEWSTYLE | NBD_FLAG_NO_ZEROES).into(),
+        };
+        socket.write_all(greeting.as_bytes()).await?;
+        let mut client_flags = U32::new_zeroed();
+        socket.read_exact(client_flags.as_mut_bytes()).await?;
+        let client_flags = client_flags.get();
+        if client_flags & NBD_FLAG_C_FIXED_NEWSTYLE == 0 {
+            anyhow::bail!("client does not support the fixed newstyle handshake");
+        }
+        let no_zeroes = client_flags & NBD_FLAG_C_NO_ZEROES != 0;
+
+        let mut session = Session {
+            structured_replies: false,
+            block_status: false,
+        };
+        loop {
+            let mut header = OptionHeader::new_zeroed();
+            match socket.read_exact(header.as_mut_bytes()).await {
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+                r => r?,
+            }
+            if header.magic.get() != IHAVEOPT {
+                anyhow::bail!("invalid option magic");
+            }
+            let option = header.option.get();
+            if header.len.get() > MAX_OPTION_LEN {
+                anyhow::bail!("option {option} too large");
+            }
+            let mut data = vec![0; header.len.get() as usize];
+            socket.read_exact(&mut data).await?;
+
+            match option {
+                NBD_OPT_EXPORT_NAME => {
+                    if !self.is_export(&data) {
+                        // There is no error reply for this option.
+                        anyhow::bail!("client requested an unknown export");
+                    }
+                    let info = InfoExport {
+                        size: self.size().into(),
+                        transmission_flags: self.transmission_flags().into(),
+                    };
+                    socket.write_all(info.as_bytes()).await?;
+                    if !no_zeroes {
+                        socket.write_all(&[0; 124]).await?;
+                    }
+                    return Ok(Some(session));
+                }
+                NBD_OPT_ABORT => {
+                    send_option_reply(socket, option, NBD_REP_ACK, &[]).await?;
+                    return Ok(None);
+                }
+                NBD_OPT_LIST => {
+                    let mut reply = (self.export_name.len() as u32).to_be_bytes().to_vec();
+                    reply.extend_from_slice(self.export_name.as_bytes());
+                    send_option_reply(socket, option, NBD_REP_SERVER, &reply).await?;
+                    send_option_reply(socket, option, NBD_REP_ACK, &[]).await?;
+                }
+                NBD_OPT_STRUCTURED_REPLY => {
+                    if data.is_empty() {
+                        session.structured_replies = true;
+                        send_option_reply(socket, option, NBD_REP_ACK, &[]).await?;
+                    } else {
+                        send_option_reply(socket, option, NBD_REP_ERR_INVALID, &[]).await?;
+                    }
+                }
+                NBD_OPT_LIST_META_CONTEXT | NBD_OPT_SET_META_CONTEXT => {
+                    let set = option == NBD_OPT_SET_META_CONTEXT;
+                    let Some((name, queries)) = parse_meta_context(&data) else {
+                        send_option_reply(socket, option, NBD_REP_ERR_INVALID, &[]).await?;
+                        continue;
+                    };
+                    if set && !session.structured_replies {
+                        send_option_reply(socket, option, NBD_REP_ERR_INVALID, &[]).await?;
+                        continue;
+                    }
+                    if !self.is_export(name) {
+                        send_option_reply(socket, option, NBD_REP_ERR_UNKNOWN, &[]).await?;
+                        continue;
+                    }
+                    // Listing with no queries returns every context.
+                    let allocation = queries.iter().any(|&query| {
+                        query == BASE_ALLOCATION.as_bytes() || (!set && query == b"base:")
+                    }) || (!set && queries.is_empty());
+                    if set {
+                        session.block_status = allocation;
+                    }
+                    if allocation {
+                        let mut reply = ALLOCATION_CONTEXT_ID.to_be_bytes().to_vec();
+                        reply.extend_from_slice(BASE_ALLOCATION.as_bytes());
+                        send_option_reply(socket, option, NBD_REP_META_CONTEXT, &reply).await?;
+                    }
+                    send_option_reply(socket, option, NBD_REP_ACK, &[]).await?;
+                }
+                NBD_OPT_INFO | NBD_OPT_GO => {
+                    let Some(name) = parse_info_request(&data) else {
+                        send_option_reply(socket, option, NBD_REP_ERR_INVALID, &[]).await?;
+                        continue;
+                    };
+                    if !self.is_export(name) {
+                        send_option_reply(socket, option, NBD_REP_ERR_UNKNOWN, &[]).await?;
+                        continue;
+                    }
+                    let mut reply = NBD_INFO_EXPORT.to_be_bytes().to_vec();
+                    reply.extend_from_slice(
+                        InfoExport {
+                            size: self.size().into(),
+                            transmission_flags: self.transmission_flags().into(),
+                        }
+                        .as_bytes(),
+                    );
+                    send_option_reply(socket, option, NBD_REP_INFO, &reply).await?;
+                    // Always send the block size constraints: requests must be
+                    // sector aligned.
+                    let mut reply = NBD_INFO_BLOCK_SIZE.to_be_bytes().to_vec();
+                    reply.extend_from_slice(
+                        InfoBlockSize {
+                            minimum: self.disk.sector_size().into(),
+                            preferred: self.disk.physical_sector_size().into(),
+                            maximum: MAX_PAYLOAD.into(),
+                        }
+                        .as_bytes(),
+                    );
+                    send_option_reply(socket, option, NBD_REP_INFO, &reply).await?;
+                    send_option_reply(socket, option, NBD_REP_ACK, &[]).await?;
+                    if option == NBD_OPT_GO {
+                        return Ok(Some(session));
+                    }
+                }
+                _ => {
+                    send_option_reply(socket, option, NBD_REP_ERR_UNSUP, &[]).await?;
+                }
+            }
+        }
+    }
+
+    fn size(&self) -> u64 {
+        self.disk.sector_count() << self.disk.sector_shift()
+    }
+
+    /// Validates a request for `len` bytes at `offset`, returning the sector
+    /// range.
+    fn sectors(&self, offset: u64, len: u32, write: bool) -> Result<(u64, u64), RequestError> {
+        let mask = u64::from(self.disk.sector_size()) - 1;
+        if (offset | u64::from(len)) & mask != 0 {
+            return Err(RequestError(NBD_EINVAL));
+        }
+        if offset
+            .checked_add(len.into())
+            .is_none_or(|end| end > self.size())
+        {
+            return Err(RequestError(if write { NBD_ENOSPC } else { NBD_EINVAL }));
+        }
+        let shift = self.disk.sector_shift();
+        Ok((offset >> shift, u64::from(len) >> shift))
+    }
+
+    async fn read(&self, offset: u64, len: u32) -> Result<Vec<u8>, RequestError> {
+        if len > MAX_PAYLOAD {
+            return Err(RequestError(NBD_EOVERFLOW));
+        }
+        let (sector, _) = self.sectors(offset, len, false)?;
+        let mut data = vec![0; len as usize];
+        if len != 0 {
+            let mem = GuestMemory::allocate(len as usize);
+            self.disk
+                .read_vectored(
+                    &OwnedRequestBuffers::linear(0, len as usize, true).buffer(&mem),
+                    sector,
+                )
+                .await?;
+            mem.read_at(0, &mut data)
+                .map_err(|_| RequestError(NBD_EIO))?;
+        }
+        Ok(data)
+    }
+
+    async fn write(&self, offset: u64, data: &[u8], fua: bool) -> Result<(), RequestError> {
+        if self.read_only {
+            return Err(RequestError(NBD_EPERM));
+        }
+        let (sector, _) = self.sectors(offset, data.len() as u32, true)?;
+        if data.is_empty() {
+            return Ok(());
+        }
+        let mem = GuestMemory::allocate(data.len());
+        mem.write_at(0, data).map_err(|_| RequestError(NBD_EIO))?;
+        self.disk
+            .write_vectored(
+                &OwnedRequestBuffers::linear(0, data.len(), false).buffer(&mem),
+                sector,
+                fua,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn trim(&self, offset: u64, len: u32) -> Result<(), RequestError> {
+        if self.read_only {
+            return Err(RequestError(NBD_EPERM));
+        }
+        let (sector, count) = self.sectors(offset, len, true)?;
+        if count != 0 {
+            self.disk.unmap(sector, count, false).await?;
+        }
+        Ok(())
+    }
+
+    async fn write_zeroes(
+        &self,
+        offset: u64,
+        len: u32,
+        may_punch: bool,
+        fua: bool,
+    ) -> Result<(), RequestError> {
+        if self.read_only {
+            return Err(RequestError(NBD_EPERM));
+        }
+        let (sector, count) = self.sectors(offset, len, true)?;
+        if count == 0 {
+            return Ok(());
+        }
+        if may_punch && self.disk.unmap_behavior() == UnmapBehavior::Zeroes {
+            self.disk.unmap(sector, count, false).await?;
+            if fua {
+                self.disk.sync_cache().await?;
+            }
+            return Ok(());
+        }
+        let chunk_len = len.min(MAX_PAYLOAD) as usize;
+        let mem = GuestMemory::allocate(chunk_len);
+        let shift = self.disk.sector_shift();
+        let mut done = 0;
+        while done < count {
+            let n = (count - done).min((chunk_len >> shift) as u64);
+            self.disk
+                .write_vectored(
+                    &OwnedRequestBuffers::linear(0, (n << shift) as usize, false).buffer(&mem),
+                    sector + done,
+                    fua,
+                )
+                .await?;
+            done += n;
+        }
+        Ok(())
+    }
+
+    /// Returns the `base:allocation` extents for `len` bytes at `offset`.
+    async fn block_status(
+        &self,
+        offset: u64,
+        len: u32,
+        one: bool,
+    ) -> Result<Vec<BlockDescriptor>, RequestError> {
+        if len == 0 {
+            return Err(RequestError(NBD_EINVAL));
+        }
+        // The reply may describe less than was asked for, so limit how much
+        // is read to find zeroes.
+        let len = len.min(MAX_PAYLOAD);
+        let data = self.read(offset, len).await?;
+        let mut descriptors = Vec::new();
+        for (range, zero) in zero_runs(&data, self.disk.sector_size() as usize) {
+            descriptors.push(BlockDescriptor {
+                len: (range.len() as u32).into(),
+                status: if zero { NBD_STATE_ZERO } else { 0 }.into(),
+            });
+            if one {
+                break;
+            }
+        }
+        Ok(descriptors)
+    }
+
+    async fn transmit(
+        &self,
+        socket: &mut (impl AsyncRead + AsyncWrite + Unpin),
+        session: Session,
+    ) -> anyhow::Result<()> {
+        loop {
+            let mut req = Request::new_zeroed();
+            match socket.read_exact(req.as_mut_bytes()).await {
+                // The client went away without NBD_CMD_DISC.
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+                r => r.context("failed to read request")?,
+            }
+            if req.magic.get() != NBD_REQUEST_MAGIC {
+                anyhow::bail!("invalid request magic");
+            }
+            let flags = req.flags.get();
+            let cookie = req.cookie.get();
+            let offset = req.offset.get();
+            let len = req.len.get();
+            let fua = flags & NBD_CMD_FLAG_FUA != 0;
+            let reply = Reply {
+                structured: session.structured_replies,
+                cookie,
+            };
+
+            let result = match req.command.get() {
+                NBD_CMD_READ => match self.read(offset, len).await {
+                    Ok(data) => {
+                        reply
+                            .send_data(socket, offset, &data, self.disk.sector_size())
+                            .await
+                    }
+                    Err(err) => reply.send_error(socket, err).await,
+                },
+                NBD_CMD_WRITE => {
+                    if len > MAX_PAYLOAD {
+                        // The data cannot be skipped safely.
+                        anyhow::bail!("write of {len} bytes too large");
+                    }
+                    let mut data = vec![0; len as usize];
+                    socket.read_exact(&mut data).await?;
+                    let result = self.write(offset, &data, fua).await;
+                    reply.send_result(socket, result).await
+                }
+                NBD_CMD_DISC => return Ok(()),
+                NBD_CMD_FLUSH => {
+                    let result = self.disk.sync_cache().await.map_err(Into::into);
+                    reply.send_result(socket, result).await
+                }
+                NBD_CMD_TRIM => {
+                    let result = self.trim(offset, len).await;
+                    reply.send_result(socket, result).await
+                }
+                NBD_CMD_WRITE_ZEROES => {
+                    let may_punch = flags & NBD_CMD_FLAG_NO_HOLE == 0;
+                    let result = self.write_zeroes(offset, len, may_punch, fua).await;
+                    reply.send_result(socket, result).await
+                }
+                NBD_CMD_BLOCK_STATUS if session.block_status => {
+                    let one = flags & NBD_CMD_FLAG_REQ_ONE != 0;
+                    match self.block_status(offset, len, one).await {
+                        Ok(descriptors) => reply.send_block_status(socket, &descriptors).await,
+                        Err(err) => reply.send_error(socket, err).await,
+                    }
+                }
+                _ => reply.send_error(socket, RequestError(NBD_EINVAL)).await,
+            };
+            result.context("failed to send reply")?;
+        }
+    }
+}
+
+async fn send_option_reply(
+    socket: &mut (impl AsyncWrite + Unpin),
+    option: u32,
+    reply_type: u32,
+    data: &[u8],
+) -> io::Result<()> {
+    let header = OptionReplyHeader {
+        magic: REPLY_MAGIC.into(),
+        option: option.into(),
+        reply_type: reply_type.into(),
+        len: (data.len() as u32).into(),
+    };
+    socket.write_all(header.as_bytes()).await?;
+    socket.write_all(data).await
+}
+
+/// Splits a length-prefixed name from the start of option data.
+fn parse_name(data: &[u8]) -> Option<(&[u8], &[u8])> {
+    let (len, rest) = U32::read_from_prefix(data).ok()?;
+    rest.split_at_checked(len.get() as usize)
+}
+
+/// Parses `NBD_OPT_INFO` or `NBD_OPT_GO` data, returning the export name.
+/// Information requests are ignored, since the server always sends all the
+/// information it has.
+fn parse_info_request(data: &[u8]) -> Option<&[u8]> {
+    let (name, rest) = parse_name(data)?;
+    let (count, rest) = U16::read_from_prefix(rest).ok()?;
+    (rest.len() == count.get() as usize * 2).then_some(name)
+}
+
+/// Parses `NBD_OPT_LIST_META_CONTEXT` or `NBD_OPT_SET_META_CONTEXT` data,
+/// returning the export name and the queries.
+fn parse_meta_context(data: &[u8]) -> Option<(&[u8], Vec<&[u8]>)> {
+    let (name, rest) = parse_name(data)?;
+    let (count, mut rest) = U32::read_from_prefix(rest).ok()?;
+    let mut queries = Vec::new();
+    for _ in 0..count.get() {
+        let (query, next) = parse_name(rest)?;
+        queries.push(query);
+        rest = next;
+    }
+    rest.is_empty().then_some((name, queries))
+}
+
+/// Splits `data` into runs of blocks of `block_size` bytes, each reported
+/// with whether it is all zero.
+fn zero_runs(data: &[u8], block_size: usize) -> Vec<(std::ops::Range<usize>, bool)> {
+    let mut runs: Vec<(std::ops::Range<usize>, bool)> = Vec::new();
+    for (i, block) in data.chunks(block_size).enumerate() {
+        let zero = block.iter().all(|&b| b == 0);
+        let start = i * block_size;
+        match runs.last_mut() {
+            Some((range, run_zero)) if *run_zero == zero => range.end = start + block.len(),
+            _ => runs.push((start..start + block.len(), zero)),
+        }
+    }
+    runs
+}
+
+/// Sends the reply to a request.
+struct Reply {
+    structured: bool,
+    cookie: u64,
+}
+
+impl Reply {
+    async fn send_simple(
+        &self,
+        socket: &mut (impl AsyncWrite + Unpin),
+        error: u32,
+        data: &[u8],
+    ) -> io::Result<()> {
+        let reply = SimpleReply {
+            magic: NBD_SIMPLE_REPLY_MAGIC.into(),
+            error: error.into(),
+            cookie: self.cookie.into(),
+        };
+        socket.write_all(reply.as_bytes()).await?;
+        socket.write_all(data).await
+    }
+
+    async fn send_chunk(
+        &self,
+        socket: &mut (impl AsyncWrite + Unpin),
+        done: bool,
+        reply_type: u16,
+        payload: &[&[u8]],
+    ) -> io::Result<()> {
+        let header = StructuredReplyHeader {
+            magic: NBD_STRUCTURED_REPLY_MAGIC.into(),
+            flags: if done { NBD_REPLY_FLAG_DONE } else { 0 }.into(),
+            reply_type: reply_type.into(),
+            cookie: self.cookie.into(),
+            len: (payload.iter().map(|p| p.len()).sum::<usize>() as u32).into(),
+        };
+        socket.write_all(header.as_bytes()).await?;
+        for p in payload {
+            socket.write_all(p).await?;
+        }
+        Ok(())
+    }
+
+    async fn send_result(
+        &self,
+        socket: &mut (impl AsyncWrite + Unpin),
+        result: Result<(), RequestError>,
+    ) -> io::Result<()> {
+        match result {
+            Ok(()) if self.structured => {
+                self.send_chunk(socket, true, NBD_REPLY_TYPE_NONE, &[])
+                    .await
+            }
+            Ok(()) => self.send_simple(socket, 0, &[]).await,
+            Err(err) => self.send_error(socket, err).await,
+        }
+    }
+
+    async fn send_error(
+        &self,
+        socket: &mut (impl AsyncWrite + Unpin),
+        err: RequestError,
+    ) -> io::Result<()> {
+        if self.structured {
+            let chunk = ErrorChunk {
+                error: err.0.into(),
+                message_len: 0.into(),
+            };
+            self.send_chunk(socket, true, NBD_REPLY_TYPE_ERROR, &[chunk.as_bytes()])
+                .await
+        } else {
+            self.send_simple(socket, err.0, &[]).await
+        }
+    }
+
+    /// Sends read data, sending zeroed blocks as holes if structured replies
+    /// are in use.
+    async fn send_data(
+        &self,
+        socket: &mut (impl AsyncWrite + Unpin),
+        offset: u64,
+        data: &[u8],
+        block_size: u32,
+    ) -> io::Result<()> {
+        if !self.structured {
+            return self.send_simple(socket, 0, data).await;
+        }
+        let runs = zero_runs(data, block_size as usize);
+        if runs.is_empty() {
+            return self
+                .send_chunk(socket, true, NBD_REPLY_TYPE_NONE, &[])
+                .await;
+        }
+        let last = runs.len() - 1;
+        for (i, (range, zero)) in runs.into_iter().enumerate() {
+            let run_offset = offset + range.start as u64;
+            if zero {
+                let hole = OffsetHole {
+                    offset: run_offset.into(),
+                    len: (range.len() as u32).into(),
+                };
+                self.send_chunk(
+                    socket,
+                    i == last,
+                    NBD_REPLY_TYPE_OFFSET_HOLE,
+                    &[hole.as_bytes()],
+                )
+                .await?;
+            } else {
+                self.send_chunk(
+                    socket,
+                    i == last,
+                    NBD_REPLY_TYPE_OFFSET_DATA,
+                    &[&run_offset.to_be_bytes(), &data[range]],
+                )
+                .await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn send_block_status(
+        &self,
+        socket: &mut (impl AsyncWrite + Unpin),
+        descriptors: &[BlockDescriptor],
+    ) -> io::Result<()> {
+        self.send_chunk(
+            socket,
+            true,
+            NBD_REPLY_TYPE_BLOCK_STATUS,
+            &[&ALLOCATION_CONTEXT_ID.to_be_bytes(), descriptors.as_bytes()],
+        )
+        .await
+    }
+}
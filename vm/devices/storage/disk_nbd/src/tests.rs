@@ -0,0 +1,246 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tests for the NBD client against the in-process NBD server.
+
+use crate::ConnectError;
+use crate::Extent;
+use crate::NbdDisk;
+use crate::NbdExportDisk;
+use crate::server::NbdServer;
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::UnmapBehavior;
+use disk_backend_resources::NbdAddress;
+use guestmem::GuestMemory;
+use pal_async::DefaultDriver;
+use pal_async::async_test;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use scsi_buffers::OwnedRequestBuffers;
+use vmcore::vm_task::SingleDriverBackend;
+use vmcore::vm_task::VmTaskDriverSource;
+
+const SECTOR_SIZE: usize = 512;
+const DISK_SIZE: u64 = 1024 * 1024;
+const EXPORT_NAME: &str = "test";
+
+/// Returns a bound and listening loopback socket and its address.
+fn listener() -> (socket2::Socket, NbdAddress) {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = NbdAddress::Tcp(listener.local_addr().unwrap().to_string());
+    (listener.into(), address)
+}
+
+/// Serves `disk` on a loopback socket until the returned task is dropped.
+fn serve(driver: &DefaultDriver, disk: Disk, read_only: bool) -> (Task<()>, NbdAddress) {
+    let (listener, address) = listener();
+    let server = NbdServer::new(disk, EXPORT_NAME, read_only);
+    let task = driver.spawn("nbd-server", {
+        let driver = driver.clone();
+        async move { server.run(&driver, listener).await.unwrap() }
+    });
+    (task, address)
+}
+
+async fn connect(driver: &DefaultDriver, address: &NbdAddress) -> NbdDisk {
+    NbdDisk::connect(driver, address, EXPORT_NAME, false)
+        .await
+        .unwrap()
+}
+
+async fn write(disk: &Disk, sector: u64, data: &[u8]) {
+    let mem = GuestMemory::allocate(data.len());
+    mem.write_at(0, data).unwrap();
+    disk.write_vectored(
+        &OwnedRequestBuffers::linear(0, data.len(), false).buffer(&mem),
+        sector,
+        false,
+    )
+    .await
+    .unwrap();
+}
+
+async fn read(disk: &Disk, sector: u64, len: usize) -> Vec<u8> {
+    let mem = GuestMemory::allocate(len);
+    disk.read_vectored(
+        &OwnedRequestBuffers::linear(0, len, true).buffer(&mem),
+        sector,
+    )
+    .await
+    .unwrap();
+    let mut data = vec![0; len];
+    mem.read_at(0, &mut data).unwrap();
+    data
+}
+
+#[async_test]
+async fn sector_range_conformance(driver: DefaultDriver) {
+    let (_server, address) = serve(
+        &driver,
+        disklayer_ram::ram_disk(DISK_SIZE, false).unwrap(),
+        false,
+    );
+    let disk = Disk::new(connect(&driver, &address).await).unwrap();
+    storage_tests::sector_range::test_disk_sector_range_conformance(&disk).await;
+}
+
+#[async_test]
+async fn write_read(driver: DefaultDriver) {
+    let backing = disklayer_ram::ram_disk(DISK_SIZE, false).unwrap();
+    let (_server, address) = serve(&driver, backing.clone(), false);
+    let disk = Disk::new(connect(&driver, &address).await).unwrap();
+    assert_eq!(disk.sector_count(), DISK_SIZE / SECTOR_SIZE as u64);
+    assert!(disk.is_fua_respected());
+    assert!(!disk.is_read_only());
+
+    let pattern: Vec<u8> = (0..SECTOR_SIZE * 4).map(|i| (i % 251) as u8).collect();
+    write(&disk, 6, &pattern).await;
+    disk.sync_cache().await.unwrap();
+
+    // The data reached the server's disk, and reads back through the client,
+    // including the zeroed sectors around it.
+    assert_eq!(read(&backing, 6, pattern.len()).await, pattern);
+    let data = read(&disk, 5, SECTOR_SIZE * 6).await;
+    assert_eq!(data[..SECTOR_SIZE], [0; SECTOR_SIZE]);
+    assert_eq!(data[SECTOR_SIZE..SECTOR_SIZE * 5], pattern);
+    assert_eq!(data[SECTOR_SIZE * 5..], [0; SECTOR_SIZE]);
+}
+
+#[async_test]
+async fn concurrent_requests(driver: DefaultDriver) {
+    let (_server, address) = serve(
+        &driver,
+        disklayer_ram::ram_disk(DISK_SIZE, false).unwrap(),
+        false,
+    );
+    let disk = Disk::new(connect(&driver, &address).await).unwrap();
+
+    futures::future::join_all((0..16u8).map(|i| {
+        let disk = &disk;
+        async move { write(disk, i.into(), &[i; SECTOR_SIZE]).await }
+    }))
+    .await;
+    let reads =
+        futures::future::join_all((0..16u8).map(|i| read(&disk, i.into(), SECTOR_SIZE))).await;
+    for (i, data) in reads.into_iter().enumerate() {
+        assert_eq!(data, [i as u8; SECTOR_SIZE]);
+    }
+}
+
+#[async_test]
+async fn unmap_zeroes(driver: DefaultDriver) {
+    let (_server, address) = serve(
+        &driver,
+        disklayer_ram::ram_disk(DISK_SIZE, false).unwrap(),
+        false,
+    );
+    let disk = Disk::new(connect(&driver, &address).await).unwrap();
+    // Unmaps are sent as NBD_CMD_WRITE_ZEROES.
+    assert_eq!(disk.unmap_behavior(), UnmapBehavior::Zeroes);
+
+    write(&disk, 0, &[0xcd; SECTOR_SIZE * 4]).await;
+    disk.unmap(1, 2, false).await.unwrap();
+    let data = read(&disk, 0, SECTOR_SIZE * 4).await;
+    assert_eq!(data[..SECTOR_SIZE], [0xcd; SECTOR_SIZE]);
+    assert_eq!(data[SECTOR_SIZE..SECTOR_SIZE * 3], [0; SECTOR_SIZE * 2]);
+    assert_eq!(data[SECTOR_SIZE * 3..], [0xcd; SECTOR_SIZE]);
+}
+
+#[async_test]
+async fn block_status(driver: DefaultDriver) {
+    let (_server, address) = serve(
+        &driver,
+        disklayer_ram::ram_disk(DISK_SIZE, false).unwrap(),
+        false,
+    );
+    let nbd = connect(&driver, &address).await;
+    let disk = Disk::new(connect(&driver, &address).await).unwrap();
+    write(&disk, 2, &[1; SECTOR_SIZE * 3]).await;
+
+    let extents = nbd.block_status(0, 8).await.unwrap();
+    let len = |sectors: usize| (sectors * SECTOR_SIZE) as u32;
+    assert_eq!(
+        extents,
+        [
+            Extent {
+                len: len(2),
+                hole: false,
+                zero: true
+            },
+            Extent {
+                len: len(3),
+                hole: false,
+                zero: false
+            },
+            Extent {
+                len: len(3),
+                hole: false,
+                zero: true
+            },
+        ]
+    );
+    assert!(matches!(
+        nbd.block_status(DISK_SIZE / SECTOR_SIZE as u64, 1).await,
+        Err(DiskError::IllegalBlock)
+    ));
+}
+
+#[async_test]
+async fn read_only_export(driver: DefaultDriver) {
+    let (_server, address) = serve(
+        &driver,
+        disklayer_ram::ram_disk(DISK_SIZE, false).unwrap(),
+        true,
+    );
+    let disk = Disk::new(connect(&driver, &address).await).unwrap();
+    assert!(disk.is_read_only());
+    assert_eq!(disk.unmap_behavior(), UnmapBehavior::Ignored);
+
+    let mem = GuestMemory::allocate(SECTOR_SIZE);
+    let r = disk
+        .write_vectored(
+            &OwnedRequestBuffers::linear(0, SECTOR_SIZE, false).buffer(&mem),
+            0,
+            false,
+        )
+        .await;
+    assert!(matches!(r, Err(DiskError::ReadOnly)), "{r:?}");
+}
+
+#[async_test]
+async fn unknown_export(driver: DefaultDriver) {
+    let (_server, address) = serve(
+        &driver,
+        disklayer_ram::ram_disk(DISK_SIZE, false).unwrap(),
+        false,
+    );
+    let r = NbdDisk::connect(&driver, &address, "missing", false).await;
+    assert!(matches!(r, Err(ConnectError::UnknownExport(_))));
+
+    // The empty name selects the default export.
+    NbdDisk::connect(&driver, &address, "", false)
+        .await
+        .unwrap();
+}
+
+/// The export wrapper passes IO through, and clients see the VM's writes.
+#[async_test]
+async fn export_disk(driver: DefaultDriver) {
+    let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+    let (listener, address) = listener();
+    let disk = Disk::new(NbdExportDisk::new(
+        &driver_source,
+        disklayer_ram::ram_disk(DISK_SIZE, false).unwrap(),
+        listener,
+        EXPORT_NAME.into(),
+        true,
+    ))
+    .unwrap();
+    storage_tests::sector_range::test_disk_sector_range_conformance(&disk).await;
+
+    write(&disk, 3, &[0x5a; SECTOR_SIZE]).await;
+    let client = Disk::new(connect(&driver, &address).await).unwrap();
+    assert!(client.is_read_only());
+    assert_eq!(read(&client, 3, SECTOR_SIZE).await, [0x5a; SECTOR_SIZE]);
+}
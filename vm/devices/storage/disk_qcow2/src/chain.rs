@@ -0,0 +1,161 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! qcow2 chain helpers.
+//!
+//! Functions for opening a qcow2 image and its backing files as a
+//! [`Qcow2DiskHandle`] ready for resource resolution.
+
+use crate::image::Qcow2Image;
+use anyhow::Context;
+use disk_backend_resources::FileDiskHandle;
+use disk_backend_resources::Qcow2DiskHandle;
+use std::fs::File;
+use std::path::Path;
+use std::path::PathBuf;
+use vm_resource::IntoResource;
+use vm_resource::Resource;
+use vm_resource::kind::DiskHandleKind;
+
+/// The maximum number of files in a chain, to detect cycles.
+const MAX_CHAIN_DEPTH: usize = 256;
+
+/// Open a qcow2 image and its backing chain by walking the backing file
+/// names recorded in each image.
+///
+/// The image at `path` is opened for read+write (unless `read_only` is
+/// true); all backing files are opened read-only. Relative backing file names
+/// are resolved against the directory of the image that references them.
+///
+/// Backing files with format `qcow2` are opened as qcow2 images and those with
+/// format `raw` as flat files. If an image does not record its backing file's
+/// format, the format is detected from the file's contents.
+///
+/// # Errors
+///
+/// Returns an error if:
+/// - Any file in the chain cannot be opened or parsed
+/// - A backing file has an unsupported format
+/// - The chain exceeds a reasonable depth limit (detect cycles)
+pub fn open_qcow2_chain(path: &Path, read_only: bool) -> anyhow::Result<Resource<DiskHandleKind>> {
+    enum Link {
+        Qcow2(File),
+        Raw(File),
+    }
+
+    // Walk the chain from the leaf to the base.
+    let mut links = Vec::new();
+    let mut path = path.to_path_buf();
+    let mut format = Some("qcow2".to_string());
+    loop {
+        anyhow::ensure!(
+            links.len() < MAX_CHAIN_DEPTH,
+            "qcow2 chain exceeds maximum depth of {MAX_CHAIN_DEPTH}"
+        );
+        let is_leaf = links.is_empty();
+        let file = std::fs::OpenOptions::new()
+            .read(true)
+            .write(is_leaf && !read_only)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+
+        let is_qcow2 = match format.as_deref() {
+            Some("qcow2") => true,
+            Some("raw") => false,
+            Some(format) => {
+                anyhow::bail!(
+                    "unsupported backing file format {format:?} for {}",
+                    path.display()
+                )
+            }
+            None => Qcow2Image::probe(&file)
+                .with_context(|| format!("failed to read {}", path.display()))?,
+        };
+        if !is_qcow2 {
+            links.push(Link::Raw(file));
+            break;
+        }
+
+        let backing = Qcow2Image::probe_backing(&file)
+            .with_context(|| format!("failed to parse qcow2 image {}", path.display()))?;
+        links.push(Link::Qcow2(file));
+        let Some(backing) = backing else {
+            break;
+        };
+        path = resolve_backing_path(&path, &backing.name);
+        format = backing.format;
+    }
+
+    // Build the resource from the base up.
+    let mut resource = None;
+    for (i, link) in links.into_iter().enumerate().rev() {
+        resource = Some(match link {
+            Link::Qcow2(file) => Qcow2DiskHandle {
+                file,
+                read_only: i != 0 || read_only,
+                backing: resource.take(),
+            }
+            .into_resource(),
+            Link::Raw(file) => FileDiskHandle(file).into_resource(),
+        });
+    }
+    Ok(resource.expect("chain has at least one link"))
+}
+
+/// Resolves a backing file name relative to the image that references it.
+fn resolve_backing_path(image: &Path, name: &str) -> PathBuf {
+    let dir = image.parent().unwrap_or(Path::new("."));
+    dir.join(name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create::CreateParams;
+    use crate::create::create;
+
+    fn create_at(path: &Path, backing_file: Option<&str>, backing_format: Option<&str>) {
+        let file = File::create(path).unwrap();
+        create(
+            &file,
+            &CreateParams {
+                size: 1024 * 1024,
+                backing_file,
+                backing_format,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn walks_chain() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("base.raw"), vec![0; 1024 * 1024]).unwrap();
+        create_at(&dir.path().join("mid.qcow2"), Some("base.raw"), Some("raw"));
+        // No backing format, so it must be probed.
+        create_at(&dir.path().join("leaf.qcow2"), Some("mid.qcow2"), None);
+
+        let resource = open_qcow2_chain(&dir.path().join("leaf.qcow2"), false).unwrap();
+        assert_eq!(resource.id(), "qcow2");
+    }
+
+    #[test]
+    fn missing_backing_file() {
+        let dir = tempfile::tempdir().unwrap();
+        create_at(
+            &dir.path().join("leaf.qcow2"),
+            Some("missing.qcow2"),
+            Some("qcow2"),
+        );
+        assert!(open_qcow2_chain(&dir.path().join("leaf.qcow2"), false).is_err());
+    }
+
+    #[test]
+    fn cycle() {
+        let dir = tempfile::tempdir().unwrap();
+        create_at(&dir.path().join("a.qcow2"), Some("b.qcow2"), Some("qcow2"));
+        create_at(&dir.path().join("b.qcow2"), Some("a.qcow2"), Some("qcow2"));
+        assert!(open_qcow2_chain(&dir.path().join("a.qcow2"), true).is_err());
+    }
+}
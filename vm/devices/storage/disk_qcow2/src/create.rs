@@ -0,0 +1,175 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Creation of new, empty qcow2 images.
+
+use crate::file::write_exact_at;
+use crate::format;
+use crate::format::Header;
+use crate::format::HeaderExtension;
+use std::fs::File;
+use std::io;
+use thiserror::Error;
+use zerocopy::IntoBytes;
+
+/// Parameters for creating a qcow2 image.
+#[derive(Debug, Clone)]
+pub struct CreateParams<'a> {
+    /// The virtual disk size in bytes. Must be a multiple of 512.
+    pub size: u64,
+    /// log2 of the cluster size, between 9 and 21.
+    pub cluster_bits: u32,
+    /// The backing file name to record in the image.
+    ///
+    /// Relative names are resolved against the directory containing the
+    /// image.
+    pub backing_file: Option<&'a str>,
+    /// The backing file format to record in the image (e.g. `qcow2` or
+    /// `raw`).
+    pub backing_format: Option<&'a str>,
+}
+
+impl Default for CreateParams<'_> {
+    fn default() -> Self {
+        Self {
+            size: 0,
+            cluster_bits: format::DEFAULT_CLUSTER_BITS,
+            backing_file: None,
+            backing_format: None,
+        }
+    }
+}
+
+/// An error encountered while creating a qcow2 image.
+#[derive(Debug, Error)]
+pub enum CreateError {
+    /// An I/O error occurred.
+    #[error("io error")]
+    Io(#[from] io::Error),
+    /// The disk size is not a multiple of 512 or is too large.
+    #[error("invalid disk size {0:#x}")]
+    InvalidDiskSize(u64),
+    /// The cluster size is out of range.
+    #[error("invalid cluster bits {0}")]
+    InvalidClusterBits(u32),
+    /// The backing file name is empty or too long.
+    #[error("invalid backing file name")]
+    InvalidBackingFile,
+    /// The header, extensions and backing file name do not fit in a cluster.
+    #[error("header does not fit in the first cluster")]
+    HeaderTooLarge,
+}
+
+/// Writes a new, empty version 3 qcow2 image to `file`, replacing its
+/// contents.
+///
+/// The image uses 16-bit refcounts. Its metadata is laid out as the header
+/// cluster, followed by the refcount table, the refcount blocks covering the
+/// metadata, and the L1 table.
+pub fn create(file: &File, params: &CreateParams<'_>) -> Result<(), CreateError> {
+    let cluster_bits = params.cluster_bits;
+    if !(format::MIN_CLUSTER_BITS..=format::MAX_CLUSTER_BITS).contains(&cluster_bits) {
+        return Err(CreateError::InvalidClusterBits(cluster_bits));
+    }
+    let cluster_size = 1u64 << cluster_bits;
+    let size = params.size;
+    let l1_entries = size.div_ceil(1 << (2 * cluster_bits - 3)).max(1);
+    if size % format::SECTOR_SIZE != 0
+        || size > i64::MAX as u64
+        || l1_entries * 8 > format::MAX_L1_SIZE
+    {
+        return Err(CreateError::InvalidDiskSize(size));
+    }
+
+    // Build the first cluster: header, extensions, and backing file name.
+    let mut cluster0 = vec![0; format::V3_HEADER_LEN];
+    if let Some(backing_format) = params.backing_format {
+        let ext = HeaderExtension {
+            ext_type: format::HEADER_EXT_BACKING_FORMAT.into(),
+            len: (backing_format.len() as u32).into(),
+        };
+        cluster0.extend_from_slice(ext.as_bytes());
+        cluster0.extend_from_slice(backing_format.as_bytes());
+        cluster0.resize(cluster0.len().next_multiple_of(8), 0);
+    }
+    let end = HeaderExtension {
+        ext_type: format::HEADER_EXT_END.into(),
+        len: 0.into(),
+    };
+    cluster0.extend_from_slice(end.as_bytes());
+    let (backing_file_offset, backing_file_size) = match params.backing_file {
+        Some(name) => {
+            if name.is_empty() || name.len() > format::MAX_BACKING_FILE_NAME as usize {
+                return Err(CreateError::InvalidBackingFile);
+            }
+            let offset = cluster0.len() as u64;
+            cluster0.extend_from_slice(name.as_bytes());
+            (offset, name.len() as u32)
+        }
+        None => (0, 0),
+    };
+    if cluster0.len() as u64 > cluster_size {
+        return Err(CreateError::HeaderTooLarge);
+    }
+
+    // The refcount blocks must cover all the metadata clusters, including
+    // themselves and the refcount table, so iterate until the layout is
+    // stable.
+    let refcounts_per_block = cluster_size / 2;
+    let table_entries_per_cluster = cluster_size / 8;
+    let l1_clusters = (l1_entries * 8).div_ceil(cluster_size);
+    let mut table_clusters = 1;
+    let mut blocks = 1;
+    let total_clusters = loop {
+        let total = 1 + table_clusters + blocks + l1_clusters;
+        let new_blocks = total.div_ceil(refcounts_per_block);
+        let new_table_clusters = new_blocks.div_ceil(table_entries_per_cluster);
+        if new_blocks == blocks && new_table_clusters == table_clusters {
+            break total;
+        }
+        blocks = new_blocks;
+        table_clusters = new_table_clusters;
+    };
+    let table_offset = cluster_size;
+    let blocks_offset = table_offset + (table_clusters << cluster_bits);
+    let l1_offset = blocks_offset + (blocks << cluster_bits);
+
+    let header = Header {
+        magic: format::QCOW2_MAGIC.into(),
+        version: 3.into(),
+        backing_file_offset: backing_file_offset.into(),
+        backing_file_size: backing_file_size.into(),
+        cluster_bits: cluster_bits.into(),
+        size: size.into(),
+        crypt_method: 0.into(),
+        l1_size: (l1_entries as u32).into(),
+        l1_table_offset: l1_offset.into(),
+        refcount_table_offset: table_offset.into(),
+        refcount_table_clusters: (table_clusters as u32).into(),
+        nb_snapshots: 0.into(),
+        snapshots_offset: 0.into(),
+        incompatible_features: 0.into(),
+        compatible_features: 0.into(),
+        autoclear_features: 0.into(),
+        refcount_order: 4.into(),
+        header_length: (format::V3_HEADER_LEN as u32).into(),
+    };
+    cluster0[..format::V3_HEADER_LEN].copy_from_slice(header.as_bytes());
+
+    let table = (0..blocks)
+        .flat_map(|i| (blocks_offset + (i << cluster_bits)).to_be_bytes())
+        .collect::<Vec<_>>();
+    let refcounts = (0..total_clusters)
+        .flat_map(|_| 1u16.to_be_bytes())
+        .collect::<Vec<_>>();
+
+    // Write the header last so that a partially created file is not mistaken
+    // for a valid image.
+    file.set_len(0)?;
+    file.set_len(total_clusters << cluster_bits)?;
+    write_exact_at(file, &table, table_offset)?;
+    write_exact_at(file, &refcounts, blocks_offset)?;
+    write_exact_at(file, &cluster0, 0)?;
+    file.sync_all()?;
+    Ok(())
+}
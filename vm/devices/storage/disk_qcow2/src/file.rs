@@ -0,0 +1,107 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Positional file I/O for qcow2 images.
+//!
+//! All I/O is dispatched to the `blocking` thread pool. Positional I/O is used
+//! throughout, so the file can be shared between concurrent requests without a
+//! lock.
+
+use std::fs;
+use std::io;
+use std::sync::Arc;
+
+/// Platform-specific positional read.
+#[cfg(unix)]
+fn file_read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::read_at(file, buf, offset)
+}
+
+/// Platform-specific positional read.
+#[cfg(windows)]
+fn file_read_at(file: &fs::File, buf: &mut [u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_read(file, buf, offset)
+}
+
+/// Platform-specific positional write.
+#[cfg(unix)]
+fn file_write_at(file: &fs::File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::unix::fs::FileExt::write_at(file, buf, offset)
+}
+
+/// Platform-specific positional write.
+#[cfg(windows)]
+fn file_write_at(file: &fs::File, buf: &[u8], offset: u64) -> io::Result<usize> {
+    std::os::windows::fs::FileExt::seek_write(file, buf, offset)
+}
+
+/// Reads exactly `buf.len()` bytes at `offset`.
+///
+/// Unlike a plain `read_exact`, reading past the end of the file is not an
+/// error: the remainder of the buffer is zeroed. qcow2 images are allowed to
+/// end in the middle of their last cluster.
+pub(crate) fn read_exact_at(
+    file: &fs::File,
+    mut buf: &mut [u8],
+    mut offset: u64,
+) -> io::Result<()> {
+    while !buf.is_empty() {
+        let n = file_read_at(file, buf, offset)?;
+        if n == 0 {
+            buf.fill(0);
+            break;
+        }
+        offset += n as u64;
+        buf = &mut buf[n..];
+    }
+    Ok(())
+}
+
+/// Writes exactly `buf.len()` bytes at `offset`.
+pub(crate) fn write_exact_at(file: &fs::File, mut buf: &[u8], mut offset: u64) -> io::Result<()> {
+    while !buf.is_empty() {
+        let n = file_write_at(file, buf, offset)?;
+        if n == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::WriteZero,
+                "failed to write any bytes",
+            ));
+        }
+        offset += n as u64;
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+/// An image file shared between concurrent requests.
+#[derive(Debug, Clone)]
+pub(crate) struct ImageFile(Arc<fs::File>);
+
+impl ImageFile {
+    pub fn new(file: fs::File) -> Self {
+        Self(Arc::new(file))
+    }
+
+    /// Reads `len` bytes at `offset`.
+    pub async fn read_at(&self, offset: u64, len: usize) -> io::Result<Vec<u8>> {
+        let file = self.0.clone();
+        blocking::unblock(move || {
+            let mut buf = vec![0; len];
+            read_exact_at(&file, &mut buf, offset)?;
+            Ok(buf)
+        })
+        .await
+    }
+
+    /// Writes `buf` at `offset`.
+    pub async fn write_at(&self, offset: u64, buf: Vec<u8>) -> io::Result<()> {
+        let file = self.0.clone();
+        blocking::unblock(move || write_exact_at(&file, &buf, offset)).await
+    }
+
+    /// Flushes data and metadata to stable storage.
+    pub async fn flush(&self) -> io::Result<()> {
+        let file = self.0.clone();
+        blocking::unblock(move || file.sync_all()).await
+    }
+}
@@ -0,0 +1,121 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! On-disk format types and constants for the qcow2 file format.
+//!
+//! All multi-byte fields in a qcow2 image are big-endian.
+
+use zerocopy::BigEndian;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+use zerocopy::U32;
+use zerocopy::U64;
+
+/// The qcow2 magic number (`"QFI\xfb"`).
+pub const QCOW2_MAGIC: u32 = u32::from_be_bytes(*b"QFI\xfb");
+
+/// The smallest supported cluster size (512 bytes).
+pub const MIN_CLUSTER_BITS: u32 = 9;
+/// The largest supported cluster size (2 MiB).
+pub const MAX_CLUSTER_BITS: u32 = 21;
+/// The default cluster size used when creating images (64 KiB).
+pub const DEFAULT_CLUSTER_BITS: u32 = 16;
+
+/// The size of the version 2 header.
+pub const V2_HEADER_LEN: usize = 72;
+/// The size of the version 3 header, without the optional trailing fields.
+pub const V3_HEADER_LEN: usize = size_of::<Header>();
+/// Offset of the optional compression type byte in a version 3 header.
+pub const COMPRESSION_TYPE_OFFSET: usize = 104;
+
+/// The size of a qcow2 "sector", used for compressed cluster sizes and the
+/// virtual disk size granularity.
+pub const SECTOR_SIZE: u64 = 512;
+
+/// The largest supported L1 table, in bytes (matches qemu's limit).
+pub const MAX_L1_SIZE: u64 = 32 << 20;
+
+/// The maximum length of a backing file name.
+pub const MAX_BACKING_FILE_NAME: u32 = 1023;
+
+// Incompatible feature bits.
+/// The refcounts may be inconsistent (lazy refcounts).
+pub const INCOMPAT_DIRTY: u64 = 1 << 0;
+/// The image is known to be corrupt.
+pub const INCOMPAT_CORRUPT: u64 = 1 << 1;
+/// The compression type field is valid.
+pub const INCOMPAT_COMPRESSION_TYPE: u64 = 1 << 3;
+
+/// The incompatible features understood by this implementation.
+pub const INCOMPAT_KNOWN: u64 = INCOMPAT_DIRTY | INCOMPAT_CORRUPT | INCOMPAT_COMPRESSION_TYPE;
+
+/// Compression type: deflate (raw, no zlib header).
+pub const COMPRESSION_TYPE_DEFLATE: u8 = 0;
+/// Compression type: zstd.
+pub const COMPRESSION_TYPE_ZSTD: u8 = 1;
+
+// Header extension types.
+/// End of the header extension area.
+pub const HEADER_EXT_END: u32 = 0;
+/// Backing file format name.
+pub const HEADER_EXT_BACKING_FORMAT: u32 = 0xe2792aca;
+
+/// Bits of an L1 entry that hold the L2 table offset.
+pub const L1E_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+/// Bits of a standard L2 entry that hold the host cluster offset.
+pub const L2E_OFFSET_MASK: u64 = 0x00ff_ffff_ffff_fe00;
+/// Bits of a refcount table entry that hold the refcount block offset.
+pub const REFT_OFFSET_MASK: u64 = 0xffff_ffff_ffff_fe00;
+
+/// The entry refers to a cluster with a refcount of exactly one, so it can be
+/// written in place.
+pub const OFLAG_COPIED: u64 = 1 << 63;
+/// The L2 entry describes a compressed cluster.
+pub const OFLAG_COMPRESSED: u64 = 1 << 62;
+/// The L2 entry reads as zeroes (version 3 only).
+pub const OFLAG_ZERO: u64 = 1 << 0;
+
+/// The qcow2 image header.
+///
+/// Version 2 images only have the first [`V2_HEADER_LEN`] bytes; the
+/// remaining fields are implied (no features, 16-bit refcounts).
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct Header {
+    pub magic: U32<BigEndian>,
+    pub version: U32<BigEndian>,
+    pub backing_file_offset: U64<BigEndian>,
+    pub backing_file_size: U32<BigEndian>,
+    pub cluster_bits: U32<BigEndian>,
+    pub size: U64<BigEndian>,
+    pub crypt_method: U32<BigEndian>,
+    pub l1_size: U32<BigEndian>,
+    pub l1_table_offset: U64<BigEndian>,
+    pub refcount_table_offset: U64<BigEndian>,
+    pub refcount_table_clusters: U32<BigEndian>,
+    pub nb_snapshots: U32<BigEndian>,
+    pub snapshots_offset: U64<BigEndian>,
+    // Version 3 fields.
+    pub incompatible_features: U64<BigEndian>,
+    pub compatible_features: U64<BigEndian>,
+    pub autoclear_features: U64<BigEndian>,
+    pub refcount_order: U32<BigEndian>,
+    pub header_length: U32<BigEndian>,
+}
+
+/// Offset of [`Header::refcount_table_offset`] within the header. It is
+/// immediately followed by [`Header::refcount_table_clusters`].
+pub const HEADER_REFCOUNT_TABLE_OFFSET: u64 = 48;
+/// Offset of [`Header::autoclear_features`] within the header.
+pub const HEADER_AUTOCLEAR_FEATURES: u64 = 88;
+
+/// A header extension descriptor, followed by `len` bytes of data padded to a
+/// multiple of 8 bytes.
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct HeaderExtension {
+    pub ext_type: U32<BigEndian>,
+    pub len: U32<BigEndian>,
+}
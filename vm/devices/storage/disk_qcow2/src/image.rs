@@ -0,0 +1,1249 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! The qcow2 image engine.
+//!
+//! [`Qcow2Image`] translates guest offsets into host file offsets through the
+//! two-level L1/L2 tables, allocates clusters on first write, and keeps the
+//! refcount structures consistent. It knows nothing about backing files beyond
+//! their names: callers decide what unallocated clusters read as, and supply
+//! the backing disk when a partial write needs to copy data up.
+//!
+//! # Metadata
+//!
+//! The L1 table is kept in memory. L2 tables are cached, and every change to
+//! them is written through to the file immediately. For writable images the
+//! entire refcount array is loaded at open time (two bytes per host cluster),
+//! which makes allocation a simple scan and lets the on-disk refcount blocks be
+//! treated as a write-through copy.
+//!
+//! Refcounts are increased before a new cluster is referenced, and references
+//! are dropped before refcounts are decreased. This only orders when the
+//! writes are issued: there are no flushes between them, so the host may
+//! persist them in any order. The image is consistent on disk after a flush,
+//! but a host crash between flushes can leave it needing a repair (for
+//! example with `qemu-img check -r all`).
+//!
+//! # Concurrency
+//!
+//! Metadata is protected by an async mutex. Data I/O to clusters that are
+//! already allocated is issued after the lock is dropped, so a cluster that
+//! loses its last reference (via discard or copy-on-write) is not returned to
+//! the allocator until there is no I/O in flight that might still be using a
+//! stale mapping.
+
+use crate::file::ImageFile;
+use crate::file::read_exact_at;
+use crate::file::write_exact_at;
+use crate::format;
+use crate::format::Header;
+use crate::format::HeaderExtension;
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use futures::lock::Mutex;
+use guestmem::GuestMemory;
+use guestmem::MemoryRead;
+use guestmem::MemoryWrite;
+use inspect::Inspect;
+use scsi_buffers::OwnedRequestBuffers;
+use scsi_buffers::RequestBuffers;
+use std::collections::BTreeMap;
+use std::collections::BTreeSet;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::ops::Range;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering;
+use thiserror::Error;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// The number of L2 tables to keep cached.
+const L2_CACHE_TABLES: usize = 32;
+
+/// The largest refcount table accepted, in bytes (matches qemu's limit).
+const MAX_REFCOUNT_TABLE_SIZE: u64 = 8 << 20;
+
+/// log2 of the sector size used for guest I/O.
+const SECTOR_SHIFT: u32 = 9;
+
+/// An error encountered while opening a qcow2 image.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum OpenError {
+    /// An I/O error occurred.
+    #[error("io error")]
+    Io(#[from] io::Error),
+    /// The file does not start with the qcow2 magic number.
+    #[error("not a qcow2 image")]
+    BadMagic,
+    /// The image version is not 2 or 3.
+    #[error("unsupported qcow2 version {0}")]
+    UnsupportedVersion(u32),
+    /// The cluster size is out of range.
+    #[error("invalid cluster bits {0}")]
+    InvalidClusterBits(u32),
+    /// The image is encrypted.
+    #[error("encrypted images are not supported")]
+    Encrypted,
+    /// The image uses incompatible features that are not implemented.
+    #[error("unsupported incompatible features {0:#x}")]
+    UnsupportedFeatures(u64),
+    /// The image is marked corrupt and cannot be opened for write.
+    #[error("image is marked corrupt")]
+    Corrupt,
+    /// The image has lazy refcounts that were not written back.
+    #[error("image has inconsistent refcounts and must be repaired first")]
+    Dirty,
+    /// The image has internal snapshots and cannot be opened for write.
+    #[error("writing to images with internal snapshots is not supported")]
+    InternalSnapshots,
+    /// The refcount width is not supported.
+    #[error("unsupported refcount order {0}")]
+    UnsupportedRefcountOrder(u32),
+    /// The header is malformed.
+    #[error("invalid header: {0}")]
+    InvalidHeader(&'static str),
+    /// The virtual disk size is invalid.
+    #[error("invalid virtual disk size {0:#x}")]
+    InvalidDiskSize(u64),
+    /// The L1 table does not cover the virtual disk.
+    #[error("L1 table is too small for the disk size")]
+    L1TooSmall,
+    /// The backing file name is malformed.
+    #[error("invalid backing file name")]
+    InvalidBackingFile,
+    /// The refcount table is malformed.
+    #[error("invalid refcount table")]
+    InvalidRefcountTable,
+}
+
+/// The backing file recorded in a qcow2 image header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BackingFile {
+    /// The backing file name, as recorded in the image.
+    pub name: String,
+    /// The backing file format (e.g. `qcow2` or `raw`), if recorded.
+    pub format: Option<String>,
+}
+
+/// The header fields relevant to this implementation.
+struct ParsedHeader {
+    header: Header,
+    compression_type: u8,
+    backing: Option<BackingFile>,
+}
+
+/// Reads and validates the image header and header extensions.
+fn parse_header(file: &File) -> Result<ParsedHeader, OpenError> {
+    let mut buf = [0; format::V3_HEADER_LEN + 8];
+    read_exact_at(file, &mut buf, 0)?;
+    let mut header = Header::new_zeroed();
+    header
+        .as_mut_bytes()
+        .copy_from_slice(&buf[..format::V3_HEADER_LEN]);
+    if header.magic.get() != format::QCOW2_MAGIC {
+        return Err(OpenError::BadMagic);
+    }
+
+    let header_len = match header.version.get() {
+        2 => {
+            // Version 2 headers end before the feature fields. Clear them and
+            // fill in the implied values.
+            header.as_mut_bytes()[format::V2_HEADER_LEN..].fill(0);
+            header.refcount_order = 4.into();
+            header.header_length = (format::V2_HEADER_LEN as u32).into();
+            format::V2_HEADER_LEN
+        }
+        3 => {
+            let len = header.header_length.get() as usize;
+            if len < format::V3_HEADER_LEN || len % 8 != 0 {
+                return Err(OpenError::InvalidHeader("header length"));
+            }
+            len
+        }
+        version => return Err(OpenError::UnsupportedVersion(version)),
+    };
+
+    let cluster_bits = header.cluster_bits.get();
+    if !(format::MIN_CLUSTER_BITS..=format::MAX_CLUSTER_BITS).contains(&cluster_bits) {
+        return Err(OpenError::InvalidClusterBits(cluster_bits));
+    }
+    if header.crypt_method.get() != 0 {
+        return Err(OpenError::Encrypted);
+    }
+    let incompatible = header.incompatible_features.get();
+    if incompatible & !format::INCOMPAT_KNOWN != 0 {
+        return Err(OpenError::UnsupportedFeatures(
+            incompatible & !format::INCOMPAT_KNOWN,
+        ));
+    }
+    let compression_type = if incompatible & format::INCOMPAT_COMPRESSION_TYPE != 0 {
+        if header_len <= format::COMPRESSION_TYPE_OFFSET {
+            return Err(OpenError::InvalidHeader("missing compression type"));
+        }
+        buf[format::COMPRESSION_TYPE_OFFSET]
+    } else {
+        format::COMPRESSION_TYPE_DEFLATE
+    };
+    if compression_type != format::COMPRESSION_TYPE_DEFLATE
+        && compression_type != format::COMPRESSION_TYPE_ZSTD
+    {
+        return Err(OpenError::InvalidHeader("compression type"));
+    }
+
+    // Header extensions follow the header and are confined to the first
+    // cluster.
+    let mut cluster0 = vec![0; 1 << cluster_bits];
+    read_exact_at(file, &mut cluster0, 0)?;
+    let mut offset = header_len;
+    let mut backing_format = None;
+    loop {
+        let ext = cluster0
+            .get(offset..)
+            .and_then(|b| HeaderExtension::read_from_prefix(b).ok())
+            .ok_or(OpenError::InvalidHeader("header extension"))?
+            .0;
+        offset += size_of::<HeaderExtension>();
+        let len = ext.len.get() as usize;
+        let data = cluster0
+            .get(offset..offset + len)
+            .ok_or(OpenError::InvalidHeader("header extension length"))?;
+        match ext.ext_type.get() {
+            format::HEADER_EXT_END => break,
+            format::HEADER_EXT_BACKING_FORMAT => {
+                backing_format = Some(
+                    String::from_utf8(data.to_vec()).map_err(|_| OpenError::InvalidBackingFile)?,
+                );
+            }
+            // Unknown extensions can be safely ignored.
+            _ => {}
+        }
+        offset += len.next_multiple_of(8);
+    }
+
+    let backing_offset = header.backing_file_offset.get();
+    let backing = if backing_offset != 0 {
+        let len = header.backing_file_size.get();
+        if len == 0 || len > format::MAX_BACKING_FILE_NAME {
+            return Err(OpenError::InvalidBackingFile);
+        }
+        let mut name = vec![0; len as usize];
+        read_exact_at(file, &mut name, backing_offset)?;
+        Some(BackingFile {
+            name: String::from_utf8(name).map_err(|_| OpenError::InvalidBackingFile)?,
+            format: backing_format,
+        })
+    } else {
+        None
+    };
+
+    Ok(ParsedHeader {
+        header,
+        compression_type,
+        backing,
+    })
+}
+
+/// Returns an error for metadata that is inconsistent with the format.
+fn corrupt(msg: &'static str) -> DiskError {
+    DiskError::Io(io::Error::new(io::ErrorKind::InvalidData, msg))
+}
+
+fn decode_table(buf: &[u8]) -> Vec<u64> {
+    buf.chunks_exact(8)
+        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+        .collect()
+}
+
+fn encode_table(entries: &[u64]) -> Vec<u8> {
+    entries.iter().flat_map(|e| e.to_be_bytes()).collect()
+}
+
+/// An open qcow2 image.
+#[derive(Inspect)]
+pub struct Qcow2Image {
+    #[inspect(skip)]
+    file: ImageFile,
+    read_only: bool,
+    version: u32,
+    cluster_bits: u32,
+    virtual_size: u64,
+    #[inspect(with = "|x| x.as_ref().map(|b| b.name.clone())")]
+    backing: Option<BackingFile>,
+    compression_type: u8,
+    #[inspect(hex)]
+    l1_table_offset: u64,
+    #[inspect(skip)]
+    state: Mutex<MetaState>,
+    #[inspect(skip)]
+    inflight: AtomicUsize,
+}
+
+/// Mutable image metadata.
+struct MetaState {
+    l1: Vec<u64>,
+    l2_cache: HashMap<u64, Vec<u64>>,
+    /// Refcounts, present only for writable images.
+    refcounts: Option<Refcounts>,
+}
+
+/// How a single guest cluster is mapped.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Mapping {
+    /// Not allocated in this image.
+    Unallocated,
+    /// Reads as zero. `host` is a preallocated cluster, or zero.
+    Zero { host: u64 },
+    /// Stored uncompressed at `host`.
+    Data { host: u64 },
+    /// Stored compressed in `len` bytes at `host`.
+    Compressed { host: u64, len: u64 },
+}
+
+/// A run of guest bytes with a uniform mapping.
+struct Extent {
+    offset: u64,
+    len: u64,
+    kind: ExtentKind,
+}
+
+#[derive(Copy, Clone)]
+enum ExtentKind {
+    Unallocated,
+    Zero,
+    /// `host` is the host offset of the first byte of the extent.
+    Data {
+        host: u64,
+    },
+    /// A single compressed cluster; the extent starts `intra` bytes into it.
+    Compressed {
+        host: u64,
+        len: u64,
+        intra: u64,
+    },
+}
+
+/// Marks I/O that uses a mapping after the metadata lock has been dropped.
+struct IoRef<'a>(&'a AtomicUsize);
+
+impl<'a> IoRef<'a> {
+    fn new(inflight: &'a AtomicUsize) -> Self {
+        inflight.fetch_add(1, Ordering::Relaxed);
+        Self(inflight)
+    }
+}
+
+impl Drop for IoRef<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl Qcow2Image {
+    /// Opens a qcow2 image.
+    ///
+    /// This performs blocking I/O. For writable images, the refcount structures
+    /// are read in full.
+    pub fn open(file: File, read_only: bool) -> Result<Self, OpenError> {
+        let ParsedHeader {
+            header,
+            compression_type,
+            backing,
+        } = parse_header(&file)?;
+
+        let cluster_bits = header.cluster_bits.get();
+        let cluster_size = 1u64 << cluster_bits;
+        let virtual_size = header.size.get();
+        if virtual_size % format::SECTOR_SIZE != 0 || virtual_size > i64::MAX as u64 {
+            return Err(OpenError::InvalidDiskSize(virtual_size));
+        }
+
+        // Each L1 entry maps an L2 table of `cluster_size / 8` clusters.
+        let l1_entry_bits = 2 * cluster_bits - 3;
+        let l1_size = header.l1_size.get() as u64;
+        if l1_size * 8 > format::MAX_L1_SIZE {
+            return Err(OpenError::InvalidHeader("L1 table too large"));
+        }
+        if l1_size < virtual_size.div_ceil(1 << l1_entry_bits) {
+            return Err(OpenError::L1TooSmall);
+        }
+        let l1_table_offset = header.l1_table_offset.get();
+        if l1_table_offset & (cluster_size - 1) != 0 {
+            return Err(OpenError::InvalidHeader("unaligned L1 table"));
+        }
+        let mut l1 = vec![0; l1_size as usize * 8];
+        read_exact_at(&file, &mut l1, l1_table_offset)?;
+        let l1 = decode_table(&l1);
+
+        let refcount_order = header.refcount_order.get();
+        if refcount_order > 6 {
+            return Err(OpenError::UnsupportedRefcountOrder(refcount_order));
+        }
+
+        let refcounts = if read_only {
+            None
+        } else {
+            let incompatible = header.incompatible_features.get();
+            if incompatible & format::INCOMPAT_CORRUPT != 0 {
+                return Err(OpenError::Corrupt);
+            }
+            if incompatible & format::INCOMPAT_DIRTY != 0 {
+                return Err(OpenError::Dirty);
+            }
+            if header.nb_snapshots.get() != 0 {
+                return Err(OpenError::InternalSnapshots);
+            }
+            // Refcounts are held in memory as u16.
+            if refcount_order > 4 {
+                return Err(OpenError::UnsupportedRefcountOrder(refcount_order));
+            }
+            let refcounts = Refcounts::load(&file, &header)?;
+
+            // Autoclear features must be cleared by writers that do not
+            // understand them, and none are understood here.
+            if header.autoclear_features.get() != 0 {
+                write_exact_at(
+                    &file,
+                    &0u64.to_be_bytes(),
+                    format::HEADER_AUTOCLEAR_FEATURES,
+                )?;
+            }
+            Some(refcounts)
+        };
+
+        Ok(Self {
+            file: ImageFile::new(file),
+            read_only,
+            version: header.version.get(),
+            cluster_bits,
+            virtual_size,
+            backing,
+            compression_type,
+            l1_table_offset,
+            state: Mutex::new(MetaState {
+                l1,
+                l2_cache: HashMap::new(),
+                refcounts,
+            }),
+            inflight: AtomicUsize::new(0),
+        })
+    }
+
+    /// Returns the backing file recorded in the image at `file`, without fully
+    /// opening it.
+    pub fn probe_backing(file: &File) -> Result<Option<BackingFile>, OpenError> {
+        Ok(parse_header(file)?.backing)
+    }
+
+    /// Returns true if `file` starts with the qcow2 magic number.
+    pub fn probe(file: &File) -> io::Result<bool> {
+        let mut magic = [0; 4];
+        read_exact_at(file, &mut magic, 0)?;
+        Ok(u32::from_be_bytes(magic) == format::QCOW2_MAGIC)
+    }
+
+    /// Returns the virtual disk size in bytes.
+    pub fn virtual_size(&self) -> u64 {
+        self.virtual_size
+    }
+
+    /// Returns the virtual disk size in 512-byte sectors.
+    pub fn sector_count(&self) -> u64 {
+        self.virtual_size >> SECTOR_SHIFT
+    }
+
+    /// Returns the cluster size in bytes.
+    pub fn cluster_size(&self) -> u32 {
+        1 << self.cluster_bits
+    }
+
+    /// Returns the backing file recorded in the image, if any.
+    pub fn backing_file(&self) -> Option<&BackingFile> {
+        self.backing.as_ref()
+    }
+
+    /// Returns true if the image was opened read-only.
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
+    }
+
+    fn cluster_mask(&self) -> u64 {
+        (1 << self.cluster_bits) - 1
+    }
+
+    fn l2_bits(&self) -> u32 {
+        self.cluster_bits - 3
+    }
+
+    fn check_range(&self, sector: u64, count: u64) -> Result<(), DiskError> {
+        let sector_count = self.sector_count();
+        if sector > sector_count || sector_count - sector < count {
+            return Err(DiskError::IllegalBlock);
+        }
+        Ok(())
+    }
+
+    /// Returns the cached L2 table at `l2_offset`, reading it if necessary.
+    async fn l2_table<'a>(
+        &self,
+        state: &'a mut MetaState,
+        l2_offset: u64,
+    ) -> Result<&'a mut Vec<u64>, DiskError> {
+        if !state.l2_cache.contains_key(&l2_offset) {
+            if l2_offset & self.cluster_mask() != 0 {
+                return Err(corrupt("unaligned L2 table"));
+            }
+            let buf = self
+                .file
+                .read_at(l2_offset, 1 << self.cluster_bits)
+                .await
+                .map_err(DiskError::Io)?;
+            self.cache_l2(state, l2_offset, decode_table(&buf));
+        }
+        Ok(state.l2_cache.get_mut(&l2_offset).unwrap())
+    }
+
+    fn cache_l2(&self, state: &mut MetaState, l2_offset: u64, table: Vec<u64>) {
+        if state.l2_cache.len() >= L2_CACHE_TABLES {
+            // Tables are written through, so any of them can be dropped.
+            let victim = *state.l2_cache.keys().next().unwrap();
+            state.l2_cache.remove(&victim);
+        }
+        state.l2_cache.insert(l2_offset, table);
+    }
+
+    /// Returns the raw L2 entry for `guest_cluster`.
+    async fn lookup(&self, state: &mut MetaState, guest_cluster: u64) -> Result<u64, DiskError> {
+        let l1_index = (guest_cluster >> self.l2_bits()) as usize;
+        let l2_offset = state.l1.get(l1_index).copied().unwrap_or(0) & format::L1E_OFFSET_MASK;
+        if l2_offset == 0 {
+            return Ok(0);
+        }
+        let l2_index = (guest_cluster & ((1 << self.l2_bits()) - 1)) as usize;
+        Ok(self.l2_table(state, l2_offset).await?[l2_index])
+    }
+
+    fn decode(&self, entry: u64) -> Result<Mapping, DiskError> {
+        if entry & format::OFLAG_COMPRESSED != 0 {
+            let shift = 62 - (self.cluster_bits - 8);
+            let host = entry & ((1 << shift) - 1);
+            let sectors =
+                ((entry & !(format::OFLAG_COPIED | format::OFLAG_COMPRESSED)) >> shift) + 1;
+            let len = sectors * format::SECTOR_SIZE - (host & (format::SECTOR_SIZE - 1));
+            return Ok(Mapping::Compressed { host, len });
+        }
+        let host = entry & format::L2E_OFFSET_MASK;
+        if host & self.cluster_mask() != 0 {
+            return Err(corrupt("unaligned data cluster"));
+        }
+        Ok(if self.version >= 3 && entry & format::OFLAG_ZERO != 0 {
+            Mapping::Zero { host }
+        } else if host == 0 {
+            Mapping::Unallocated
+        } else {
+            Mapping::Data { host }
+        })
+    }
+
+    /// Maps `len` guest bytes at `offset` into extents.
+    async fn map(
+        &self,
+        state: &mut MetaState,
+        offset: u64,
+        len: u64,
+    ) -> Result<Vec<Extent>, DiskError> {
+        let mut extents = Vec::<Extent>::new();
+        let end = offset + len;
+        let mut pos = offset;
+        while pos < end {
+            let intra = pos & self.cluster_mask();
+            let n = ((1 << self.cluster_bits) - intra).min(end - pos);
+            let entry = self.lookup(state, pos >> self.cluster_bits).await?;
+            let kind = match self.decode(entry)? {
+                Mapping::Unallocated => ExtentKind::Unallocated,
+                Mapping::Zero { .. } => ExtentKind::Zero,
+                Mapping::Data { host } => ExtentKind::Data { host: host + intra },
+                Mapping::Compressed { host, len } => ExtentKind::Compressed { host, len, intra },
+            };
+            if let Some(last) = extents.last_mut() {
+                let contiguous = match (last.kind, kind) {
+                    (ExtentKind::Unallocated, ExtentKind::Unallocated)
+                    | (ExtentKind::Zero, ExtentKind::Zero) => true,
+                    (ExtentKind::Data { host: a }, ExtentKind::Data { host: b }) => {
+                        a + last.len == b
+                    }
+                    _ => false,
+                };
+                if contiguous {
+                    last.len += n;
+                    pos += n;
+                    continue;
+                }
+            }
+            extents.push(Extent {
+                offset: pos,
+                len: n,
+                kind,
+            });
+            pos += n;
+        }
+        Ok(extents)
+    }
+
+    /// Reads and decompresses the cluster stored in `len` bytes at `host`.
+    async fn read_compressed(&self, host: u64, len: u64) -> Result<Vec<u8>, DiskError> {
+        if self.compression_type != format::COMPRESSION_TYPE_DEFLATE {
+            return Err(DiskError::Io(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "zstd-compressed clusters are not supported",
+            )));
+        }
+        let compressed = self
+            .file
+            .read_at(host, len as usize)
+            .await
+            .map_err(DiskError::Io)?;
+        let cluster_size = 1 << self.cluster_bits;
+        blocking::unblock(move || {
+            // Compressed data may be followed by unrelated bytes, so decode
+            // exactly one cluster rather than to the end of the stream.
+            let mut data = vec![0; cluster_size];
+            flate2::read::DeflateDecoder::new(compressed.as_slice()).read_exact(&mut data)?;
+            Ok(data)
+        })
+        .await
+        .map_err(DiskError::Io)
+    }
+
+    /// Reads sectors into `buffers`.
+    ///
+    /// Returns the sector ranges that are not allocated in this image. Their
+    /// contents in `buffers` are left untouched, for the caller to fill from
+    /// the backing disk or with zeroes.
+    pub async fn read(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<Vec<Range<u64>>, DiskError> {
+        let len = buffers.len() as u64;
+        self.check_range(sector, len >> SECTOR_SHIFT)?;
+        let offset = sector << SECTOR_SHIFT;
+        let (extents, _io) = {
+            let mut state = self.state.lock().await;
+            let extents = self.map(&mut state, offset, len).await?;
+            (extents, IoRef::new(&self.inflight))
+        };
+
+        let mut unallocated = Vec::new();
+        for extent in extents {
+            let range = buffers.subrange((extent.offset - offset) as usize, extent.len as usize);
+            match extent.kind {
+                ExtentKind::Unallocated => {
+                    unallocated.push(
+                        extent.offset >> SECTOR_SHIFT..(extent.offset + extent.len) >> SECTOR_SHIFT,
+                    );
+                }
+                ExtentKind::Zero => {
+                    range.writer().zero(extent.len as usize)?;
+                }
+                ExtentKind::Data { host } => {
+                    let data = self
+                        .file
+                        .read_at(host, extent.len as usize)
+                        .await
+                        .map_err(DiskError::Io)?;
+                    range.writer().write(&data)?;
+                }
+                ExtentKind::Compressed { host, len, intra } => {
+                    let data = self.read_compressed(host, len).await?;
+                    range
+                        .writer()
+                        .write(&data[intra as usize..][..extent.len as usize])?;
+                }
+            }
+        }
+        Ok(unallocated)
+    }
+
+    /// Writes sectors from `buffers`.
+    ///
+    /// Clusters that are not yet allocated are allocated. When only part of
+    /// such a cluster is written, the rest of it is filled from `backing`, or
+    /// with zeroes if there is no backing disk.
+    pub async fn write(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+        backing: Option<&Disk>,
+    ) -> Result<(), DiskError> {
+        if self.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        let len = buffers.len() as u64;
+        self.check_range(sector, len >> SECTOR_SHIFT)?;
+        if len == 0 {
+            return Ok(());
+        }
+        let offset = sector << SECTOR_SHIFT;
+        let mut data = vec![0; len as usize];
+        buffers.reader().read(&mut data)?;
+
+        let (in_place, _io) = {
+            let mut state = self.state.lock().await;
+            self.reclaim(&mut state)?;
+            let mut in_place = Vec::<(u64, Range<usize>)>::new();
+            let mut pos = 0;
+            while pos < data.len() {
+                let guest = offset + pos as u64;
+                let intra = guest & self.cluster_mask();
+                let n = (((1 << self.cluster_bits) - intra) as usize).min(data.len() - pos);
+                let guest_cluster = guest >> self.cluster_bits;
+                let mapping = self.decode(self.lookup(&mut state, guest_cluster).await?)?;
+                match mapping {
+                    Mapping::Data { host }
+                        if refcounts(&mut state).get(host >> self.cluster_bits) == 1 =>
+                    {
+                        let host = host + intra;
+                        match in_place.last_mut() {
+                            Some((last_host, range)) if *last_host + range.len() as u64 == host => {
+                                range.end += n;
+                            }
+                            _ => in_place.push((host, pos..pos + n)),
+                        }
+                    }
+                    mapping => {
+                        self.write_new_cluster(
+                            &mut state,
+                            guest_cluster,
+                            mapping,
+                            intra as usize,
+                            &data[pos..pos + n],
+                            backing,
+                        )
+                        .await?;
+                    }
+                }
+                pos += n;
+            }
+            refcounts(&mut state).commit(&self.file).await?;
+            (in_place, IoRef::new(&self.inflight))
+        };
+
+        for (host, range) in in_place {
+            self.file
+                .write_at(host, data[range].to_vec())
+                .await
+                .map_err(DiskError::Io)?;
+        }
+        if fua {
+            self.file.flush().await.map_err(DiskError::Io)?;
+        }
+        Ok(())
+    }
+
+    /// Writes `chunk` at `intra` within `guest_cluster` into a newly allocated
+    /// cluster, replacing `mapping`.
+    async fn write_new_cluster(
+        &self,
+        state: &mut MetaState,
+        guest_cluster: u64,
+        mapping: Mapping,
+        intra: usize,
+        chunk: &[u8],
+        backing: Option<&Disk>,
+    ) -> Result<(), DiskError> {
+        let cluster_size = 1 << self.cluster_bits;
+        let mut data = vec![0; cluster_size];
+        if chunk.len() < cluster_size {
+            match mapping {
+                Mapping::Unallocated => {
+                    if let Some(backing) = backing {
+                        read_backing(backing, guest_cluster << self.cluster_bits, &mut data)
+                            .await?;
+                    }
+                }
+                Mapping::Zero { .. } => {}
+                Mapping::Data { host } => {
+                    data = self
+                        .file
+                        .read_at(host, cluster_size)
+                        .await
+                        .map_err(DiskError::Io)?;
+                }
+                Mapping::Compressed { host, len } => {
+                    data = self.read_compressed(host, len).await?;
+                }
+            }
+        }
+        data[intra..intra + chunk.len()].copy_from_slice(chunk);
+
+        let l2_offset = self
+            .writable_l2(state, (guest_cluster >> self.l2_bits()) as usize)
+            .await?;
+        let rc = refcounts(state);
+        let host = rc.alloc() << self.cluster_bits;
+        rc.commit(&self.file).await?;
+        self.file
+            .write_at(host, data)
+            .await
+            .map_err(DiskError::Io)?;
+        let l2_index = (guest_cluster & ((1 << self.l2_bits()) - 1)) as usize;
+        self.set_l2_entries(state, l2_offset, l2_index, &[host | format::OFLAG_COPIED])
+            .await?;
+        self.release(state, mapping);
+        Ok(())
+    }
+
+    /// Returns the offset of the L2 table for `l1_index`, allocating it if
+    /// necessary.
+    async fn writable_l2(&self, state: &mut MetaState, l1_index: usize) -> Result<u64, DiskError> {
+        let l2_offset = state.l1[l1_index] & format::L1E_OFFSET_MASK;
+        if l2_offset != 0 {
+            // Without internal snapshots every table has exactly one reference.
+            if refcounts(state).get(l2_offset >> self.cluster_bits) != 1 {
+                return Err(corrupt("L2 table refcount is not one"));
+            }
+            return Ok(l2_offset);
+        }
+
+        let rc = refcounts(state);
+        let l2_offset = rc.alloc() << self.cluster_bits;
+        rc.commit(&self.file).await?;
+        self.file
+            .write_at(l2_offset, vec![0; 1 << self.cluster_bits])
+            .await
+            .map_err(DiskError::Io)?;
+        self.cache_l2(state, l2_offset, vec![0; 1 << self.l2_bits()]);
+        let entry = l2_offset | format::OFLAG_COPIED;
+        state.l1[l1_index] = entry;
+        self.file
+            .write_at(
+                self.l1_table_offset + l1_index as u64 * 8,
+                entry.to_be_bytes().to_vec(),
+            )
+            .await
+            .map_err(DiskError::Io)?;
+        Ok(l2_offset)
+    }
+
+    /// Updates and writes through consecutive entries of an L2 table.
+    async fn set_l2_entries(
+        &self,
+        state: &mut MetaState,
+        l2_offset: u64,
+        first: usize,
+        entries: &[u64],
+    ) -> Result<(), DiskError> {
+        self.l2_table(state, l2_offset).await?[first..first + entries.len()]
+            .copy_from_slice(entries);
+        self.file
+            .write_at(l2_offset + first as u64 * 8, encode_table(entries))
+            .await
+            .map_err(DiskError::Io)
+    }
+
+    /// Drops the references held by a mapping that has been replaced.
+    fn release(&self, state: &mut MetaState, mapping: Mapping) {
+        let rc = refcounts(state);
+        match mapping {
+            Mapping::Unallocated => {}
+            Mapping::Zero { host } | Mapping::Data { host } => {
+                if host != 0 {
+                    rc.pending_free.push(host >> self.cluster_bits);
+                }
+            }
+            Mapping::Compressed { host, len } => {
+                // Compressed clusters can share host clusters, each of which
+                // holds one reference per compressed cluster touching it.
+                let first = host >> self.cluster_bits;
+                let last = (host + len - 1) >> self.cluster_bits;
+                rc.pending_free.extend(first..=last);
+            }
+        }
+    }
+
+    /// Releases deferred frees, if no I/O might still be using them.
+    fn reclaim(&self, state: &mut MetaState) -> Result<(), DiskError> {
+        let rc = refcounts(state);
+        if !rc.pending_free.is_empty() && self.inflight.load(Ordering::Acquire) == 0 {
+            for cluster in std::mem::take(&mut rc.pending_free) {
+                rc.decref(cluster)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Discards the clusters entirely covered by `count` sectors at `sector`.
+    ///
+    /// If `fall_through_is_zero`, discarded clusters are deallocated.
+    /// Otherwise, on version 3 images, they are marked as zero so that data
+    /// from a backing file does not become visible.
+    pub async fn discard(
+        &self,
+        sector: u64,
+        count: u64,
+        fall_through_is_zero: bool,
+    ) -> Result<(), DiskError> {
+        if self.read_only {
+            return Err(DiskError::ReadOnly);
+        }
+        self.check_range(sector, count)?;
+        let start_cluster = (sector << SECTOR_SHIFT).div_ceil(1 << self.cluster_bits);
+        let end_cluster = ((sector + count) << SECTOR_SHIFT) >> self.cluster_bits;
+        if start_cluster >= end_cluster {
+            return Ok(());
+        }
+        let new_entry = if fall_through_is_zero || self.version < 3 {
+            0
+        } else {
+            format::OFLAG_ZERO
+        };
+
+        let mut state = self.state.lock().await;
+        self.reclaim(&mut state)?;
+        let l2_entries = 1u64 << self.l2_bits();
+        let mut guest_cluster = start_cluster;
+        while guest_cluster < end_cluster {
+            let l1_index = (guest_cluster >> self.l2_bits()) as usize;
+            let first = guest_cluster & (l2_entries - 1);
+            let n = (l2_entries - first).min(end_cluster - guest_cluster);
+            guest_cluster += n;
+
+            let l2_offset = state.l1[l1_index] & format::L1E_OFFSET_MASK;
+            if l2_offset == 0 && new_entry == 0 {
+                continue;
+            }
+            let l2_offset = self.writable_l2(&mut state, l1_index).await?;
+            let range = first as usize..(first + n) as usize;
+            let old = self.l2_table(&mut state, l2_offset).await?[range.clone()].to_vec();
+            if old.iter().all(|&e| e == new_entry) {
+                continue;
+            }
+            let old = old
+                .into_iter()
+                .map(|e| self.decode(e))
+                .collect::<Result<Vec<_>, _>>()?;
+            self.set_l2_entries(
+                &mut state,
+                l2_offset,
+                range.start,
+                &vec![new_entry; range.len()],
+            )
+            .await?;
+            for mapping in old {
+                self.release(&mut state, mapping);
+            }
+        }
+        refcounts(&mut state).commit(&self.file).await
+    }
+
+    /// Writes back outstanding metadata and flushes the file.
+    pub async fn flush(&self) -> Result<(), DiskError> {
+        if self.read_only {
+            return Ok(());
+        }
+        {
+            let mut state = self.state.lock().await;
+            self.reclaim(&mut state)?;
+            refcounts(&mut state).commit(&self.file).await?;
+        }
+        self.file.flush().await.map_err(DiskError::Io)
+    }
+}
+
+fn refcounts(state: &mut MetaState) -> &mut Refcounts {
+    state
+        .refcounts
+        .as_mut()
+        .expect("writable images have refcounts")
+}
+
+/// Reads from `backing` at byte `offset`, leaving the part of `buf` beyond the
+/// end of the backing disk untouched.
+async fn read_backing(backing: &Disk, offset: u64, buf: &mut [u8]) -> Result<(), DiskError> {
+    let backing_len = backing.sector_count() << backing.sector_shift();
+    if offset >= backing_len {
+        return Ok(());
+    }
+    let len = (buf.len() as u64).min(backing_len - offset) as usize;
+    let mem = GuestMemory::allocate(len);
+    backing
+        .read_vectored(
+            &OwnedRequestBuffers::linear(0, len, true).buffer(&mem),
+            offset >> backing.sector_shift(),
+        )
+        .await?;
+    mem.read_at(0, &mut buf[..len])
+        .map_err(|err| DiskError::Io(io::Error::other(err)))
+}
+
+/// The in-memory refcount state of a writable image.
+struct Refcounts {
+    cluster_bits: u32,
+    order: u32,
+    table_offset: u64,
+    table: Vec<u64>,
+    counts: Vec<u16>,
+    free_hint: usize,
+    /// Ranges of clusters whose refcounts changed, by refcount block index.
+    dirty: BTreeMap<u64, Range<u64>>,
+    /// Refcount blocks allocated since the last commit.
+    new_blocks: BTreeSet<u64>,
+    table_moved: bool,
+    /// Clusters whose reference was dropped, to be decremented once no I/O
+    /// can be using them.
+    pending_free: Vec<u64>,
+}
+
+impl Refcounts {
+    fn load(file: &File, header: &Header) -> Result<Self, OpenError> {
+        let cluster_bits = header.cluster_bits.get();
+        let cluster_size = 1u64 << cluster_bits;
+        let order = header.refcount_order.get();
+        let table_offset = header.refcount_table_offset.get();
+        let table_len = (header.refcount_table_clusters.get() as u64) << cluster_bits;
+        if table_offset & (cluster_size - 1) != 0
+            || table_len == 0
+            || table_len > MAX_REFCOUNT_TABLE_SIZE
+        {
+            return Err(OpenError::InvalidRefcountTable);
+        }
+        let mut buf = vec![0; table_len as usize];
+        read_exact_at(file, &mut buf, table_offset)?;
+        let table = decode_table(&buf)
+            .into_iter()
+            .map(|e| e & format::REFT_OFFSET_MASK)
+            .collect::<Vec<_>>();
+
+        let mut this = Self {
+            cluster_bits,
+            order,
+            table_offset,
+            table,
+            counts: Vec::new(),
+            free_hint: 0,
+            dirty: BTreeMap::new(),
+            new_blocks: BTreeSet::new(),
+            table_moved: false,
+            pending_free: Vec::new(),
+        };
+
+        let entries_per_block = 1usize << this.block_bits();
+        let mut block = vec![0; cluster_size as usize];
+        for (index, &offset) in this.table.iter().enumerate() {
+            if offset == 0 {
+                continue;
+            }
+            if offset & (cluster_size - 1) != 0 {
+                return Err(OpenError::InvalidRefcountTable);
+            }
+            read_exact_at(file, &mut block, offset)?;
+            let base = index * entries_per_block;
+            this.counts.resize(base + entries_per_block, 0);
+            for (i, count) in this.counts[base..].iter_mut().enumerate() {
+                *count = decode_refcount(&block, order, i);
+            }
+        }
+
+        // Clusters past the last refcount block are free, but make sure every
+        // cluster in the file is tracked so that allocation never overlaps it.
+        let file_clusters = file.metadata()?.len().div_ceil(cluster_size) as usize;
+        if this.counts.len() < file_clusters {
+            this.counts.resize(file_clusters, 0);
+        }
+        Ok(this)
+    }
+
+    /// log2 of the number of refcounts in one refcount block.
+    fn block_bits(&self) -> u32 {
+        self.cluster_bits + 3 - self.order
+    }
+
+    fn get(&self, cluster: u64) -> u16 {
+        self.counts.get(cluster as usize).copied().unwrap_or(0)
+    }
+
+    fn mark_dirty(&mut self, cluster: u64) {
+        self.dirty
+            .entry(cluster >> self.block_bits())
+            .and_modify(|range| {
+                range.start = range.start.min(cluster);
+                range.end = range.end.max(cluster + 1);
+            })
+            .or_insert(cluster..cluster + 1);
+    }
+
+    /// Allocates a single cluster, returning its index.
+    fn alloc(&mut self) -> u64 {
+        let start = self.free_hint.min(self.counts.len());
+        let index = match self.counts[start..].iter().position(|&n| n == 0) {
+            Some(i) => start + i,
+            None => {
+                self.counts.push(0);
+                self.counts.len() - 1
+            }
+        };
+        self.counts[index] = 1;
+        self.free_hint = index + 1;
+        self.mark_dirty(index as u64);
+        index as u64
+    }
+
+    /// Allocates `n` contiguous clusters at the end of the file, returning the
+    /// index of the first.
+    fn alloc_contiguous(&mut self, n: u64) -> u64 {
+        let start = self.counts.len() as u64;
+        self.counts.resize(self.counts.len() + n as usize, 1);
+        for cluster in start..start + n {
+            self.mark_dirty(cluster);
+        }
+        start
+    }
+
+    fn decref(&mut self, cluster: u64) -> Result<(), DiskError> {
+        match self.counts.get_mut(cluster as usize) {
+            Some(count) if *count > 0 => {
+                *count -= 1;
+                if *count == 0 {
+                    self.free_hint = self.free_hint.min(cluster as usize);
+                }
+            }
+            _ => return Err(corrupt("refcount underflow")),
+        }
+        self.mark_dirty(cluster);
+        Ok(())
+    }
+
+    /// Makes sure every dirty refcount block has a home in the file, growing
+    /// the refcount table if necessary.
+    fn prepare(&mut self) {
+        let table_entries_per_cluster = 1u64 << (self.cluster_bits - 3);
+        loop {
+            let max_block = *self.dirty.keys().next_back().unwrap();
+            if max_block >= self.table.len() as u64 {
+                // Move the table to the end of the file. The old table stays
+                // valid until the header is updated, so release it lazily.
+                let old_first = self.table_offset >> self.cluster_bits;
+                let old_clusters = self.table.len() as u64 / table_entries_per_cluster;
+                let new_len = (max_block + 1)
+                    .max(self.table.len() as u64 * 2)
+                    .next_multiple_of(table_entries_per_cluster);
+                let start = self.alloc_contiguous(new_len / table_entries_per_cluster);
+                self.table.resize(new_len as usize, 0);
+                self.table_offset = start << self.cluster_bits;
+                self.table_moved = true;
+                self.pending_free
+                    .extend(old_first..old_first + old_clusters);
+                continue;
+            }
+
+            // Allocating a block can dirty another block that has no home yet,
+            // so repeat until everything is placed.
+            let missing = self
+                .dirty
+                .keys()
+                .copied()
+                .filter(|&block| self.table[block as usize] == 0)
+                .collect::<Vec<_>>();
+            if missing.is_empty() {
+                break;
+            }
+            for block in missing {
+                let cluster = self.alloc();
+                self.table[block as usize] = cluster << self.cluster_bits;
+                self.new_blocks.insert(block);
+            }
+        }
+    }
+
+    /// Encodes the refcounts of `clusters`, all within `block`, returning the
+    /// byte offset within the block and the bytes.
+    fn encode(&self, block: u64, clusters: Range<u64>) -> (u64, Vec<u8>) {
+        let bits = 1u64 << self.order;
+        let base = block << self.block_bits();
+        let byte_start = (clusters.start - base) * bits / 8;
+        let byte_end = ((clusters.end - base) * bits).div_ceil(8);
+        let bytes = (byte_start..byte_end)
+            .map(|byte| {
+                if bits >= 8 {
+                    let per_entry = bits / 8;
+                    let count = self.get(base + byte / per_entry) as u64;
+                    (count >> ((per_entry - 1 - byte % per_entry) * 8)) as u8
+                } else {
+                    let per_byte = 8 / bits;
+                    (0..per_byte).fold(0, |acc, i| {
+                        let count = self.get(base + byte * per_byte + i) as u64;
+                        acc | ((count & ((1 << bits) - 1)) << (i * bits)) as u8
+                    })
+                }
+            })
+            .collect();
+        (byte_start, bytes)
+    }
+
+    /// Writes changed refcounts to the file.
+    async fn commit(&mut self, file: &ImageFile) -> Result<(), DiskError> {
+        if self.dirty.is_empty() {
+            return Ok(());
+        }
+        self.prepare();
+
+        let block_bits = self.block_bits();
+        for (&block, clusters) in &self.dirty {
+            let clusters = if self.new_blocks.contains(&block) {
+                block << block_bits..(block + 1) << block_bits
+            } else {
+                clusters.clone()
+            };
+            let (offset, bytes) = self.encode(block, clusters);
+            file.write_at(self.table[block as usize] + offset, bytes)
+                .await
+                .map_err(DiskError::Io)?;
+        }
+
+        if self.table_moved {
+            file.write_at(self.table_offset, encode_table(&self.table))
+                .await
+                .map_err(DiskError::Io)?;
+            let clusters = (self.table.len() as u64 * 8) >> self.cluster_bits;
+            let mut header = self.table_offset.to_be_bytes().to_vec();
+            header.extend_from_slice(&(clusters as u32).to_be_bytes());
+            file.write_at(format::HEADER_REFCOUNT_TABLE_OFFSET, header)
+                .await
+                .map_err(DiskError::Io)?;
+        } else {
+            for &block in &self.new_blocks {
+                file.write_at(
+                    self.table_offset + block * 8,
+                    self.table[block as usize].to_be_bytes().to_vec(),
+                )
+                .await
+                .map_err(DiskError::Io)?;
+            }
+        }
+
+        self.dirty.clear();
+        self.new_blocks.clear();
+        self.table_moved = false;
+        Ok(())
+    }
+}
+
+/// Decodes refcount `index` from a refcount block of the given order.
+fn decode_refcount(block: &[u8], order: u32, index: usize) -> u16 {
+    let bits = 1usize << order;
+    if bits >= 8 {
+        let per_entry = bits / 8;
+        block[index * per_entry..][..per_entry]
+            .iter()
+            .fold(0, |acc, &b| (acc << 8) | b as u16)
+    } else {
+        let per_byte = 8 / bits;
+        ((block[index / per_byte] >> ((index % per_byte) * bits)) as u16) & ((1 << bits) - 1)
+    }
+}
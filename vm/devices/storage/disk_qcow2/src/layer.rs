@@ -0,0 +1,242 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! qcow2 disk layer.
+
+use crate::SECTOR_SIZE;
+use crate::image::Qcow2Image;
+use crate::physical_sector_size;
+use disk_backend::DiskError;
+use disk_backend::UnmapBehavior;
+use disk_layered::LayerIo;
+use disk_layered::SectorMarker;
+use guestmem::MemoryWrite;
+use inspect::Inspect;
+use scsi_buffers::RequestBuffers;
+use thiserror::Error;
+
+/// A qcow2 disk layer implementing [`LayerIo`].
+///
+/// If the image has a backing file, unallocated sectors are left unmarked so
+/// that they are read from the next layer, which is expected to hold the
+/// backing file's contents. Otherwise they read as zero.
+///
+/// Layers for images with a backing file must be read-only: a partial write
+/// to an unallocated cluster would need to copy the rest of the cluster from
+/// the layers below, which a layer cannot access. Use [`Qcow2Disk`] to write to
+/// such images.
+///
+/// [`Qcow2Disk`]: crate::Qcow2Disk
+#[derive(Inspect)]
+pub struct Qcow2Layer {
+    #[inspect(flatten)]
+    image: Qcow2Image,
+    has_backing: bool,
+}
+
+/// An error returned by [`Qcow2Layer::new`].
+#[derive(Debug, Error)]
+pub enum NewLayerError {
+    /// The image has a backing file and was opened for write.
+    #[error("writable qcow2 layers cannot have a backing file")]
+    WritableWithBacking,
+}
+
+impl Qcow2Layer {
+    /// Returns a new layer for `image`.
+    pub fn new(image: Qcow2Image) -> Result<Self, NewLayerError> {
+        let has_backing = image.backing_file().is_some();
+        if has_backing && !image.is_read_only() {
+            return Err(NewLayerError::WritableWithBacking);
+        }
+        Ok(Self { image, has_backing })
+    }
+}
+
+impl LayerIo for Qcow2Layer {
+    fn layer_type(&self) -> &str {
+        "qcow2"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.image.sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        SECTOR_SIZE
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        None
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        physical_sector_size(&self.image)
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        true
+    }
+
+    fn is_logically_read_only(&self) -> bool {
+        self.image.is_read_only()
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        self.image.flush().await
+    }
+
+    async fn read(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        mut marker: SectorMarker<'_>,
+    ) -> Result<(), DiskError> {
+        let unallocated = self.image.read(buffers, sector).await?;
+        if self.has_backing {
+            // Mark everything except the unallocated ranges, which fall
+            // through to the next layer.
+            let mut next = sector;
+            for range in &unallocated {
+                marker.set_range(next..range.start);
+                next = range.end;
+            }
+            marker.set_range(next..sector + (buffers.len() / SECTOR_SIZE as usize) as u64);
+        } else {
+            for range in &unallocated {
+                let offset = ((range.start - sector) * SECTOR_SIZE as u64) as usize;
+                let len = ((range.end - range.start) * SECTOR_SIZE as u64) as usize;
+                buffers.subrange(offset, len).writer().zero(len)?;
+            }
+            marker.set_all();
+        }
+        Ok(())
+    }
+
+    async fn write(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        // Layers with a backing file are read-only, so there is never
+        // anything to copy up.
+        self.image.write(buffers, sector, fua, None).await
+    }
+
+    async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        _block_level_only: bool,
+        _next_is_zero: bool,
+    ) -> Result<(), DiskError> {
+        self.image.discard(sector, count, !self.has_backing).await
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        if self.has_backing {
+            UnmapBehavior::Unspecified
+        } else {
+            UnmapBehavior::Zeroes
+        }
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.image.cluster_size() / SECTOR_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create::CreateParams;
+    use crate::create::create;
+    use disk_backend::Disk;
+    use disk_layered::DiskLayer;
+    use disk_layered::LayerConfiguration;
+    use disk_layered::LayeredDisk;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+    use std::io::Write;
+
+    const DISK_SIZE: u64 = 1024 * 1024;
+
+    fn new_layer(backing_file: Option<&str>, read_only: bool) -> Qcow2Layer {
+        let file = tempfile::tempfile().unwrap();
+        create(
+            &file,
+            &CreateParams {
+                size: DISK_SIZE,
+                backing_file,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        Qcow2Layer::new(Qcow2Image::open(file, read_only).unwrap()).unwrap()
+    }
+
+    #[async_test]
+    async fn layer_sector_range() {
+        storage_tests::sector_range::test_layer_sector_range(&new_layer(None, false)).await;
+    }
+
+    #[test]
+    fn writable_with_backing() {
+        let file = tempfile::tempfile().unwrap();
+        create(
+            &file,
+            &CreateParams {
+                size: DISK_SIZE,
+                backing_file: Some("base.qcow2"),
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert!(matches!(
+            Qcow2Layer::new(Qcow2Image::open(file, false).unwrap()),
+            Err(NewLayerError::WritableWithBacking)
+        ));
+    }
+
+    #[async_test]
+    async fn falls_through_when_backed() {
+        // Reads of unallocated sectors in an image with a backing file must
+        // come from the next layer.
+        let mut base = tempfile::tempfile().unwrap();
+        let data = (0..DISK_SIZE as usize)
+            .map(|i| (i % 251) as u8)
+            .collect::<Vec<_>>();
+        base.write_all(&data).unwrap();
+        let base = Disk::new(disk_file::FileDisk::open(base, true).unwrap()).unwrap();
+        let disk = Disk::new(
+            LayeredDisk::new(
+                true,
+                vec![
+                    LayerConfiguration {
+                        layer: DiskLayer::new(new_layer(Some("base.raw"), true)),
+                        write_through: false,
+                        read_cache: false,
+                    },
+                    LayerConfiguration {
+                        layer: DiskLayer::from_disk(base),
+                        write_through: false,
+                        read_cache: false,
+                    },
+                ],
+            )
+            .await
+            .unwrap(),
+        )
+        .unwrap();
+
+        let mem = GuestMemory::allocate(8192);
+        disk.read_vectored(&OwnedRequestBuffers::linear(0, 8192, true).buffer(&mem), 3)
+            .await
+            .unwrap();
+        let mut buf = vec![0; 8192];
+        mem.read_at(0, &mut buf).unwrap();
+        assert_eq!(buf, data[1536..1536 + 8192]);
+    }
+}
@@ -0,0 +1,393 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! qcow2 disk backend for OpenVMM.
+//!
+//! A pure-Rust implementation of the qcow2 image format (versions 2 and 3),
+//! supporting:
+//!
+//! - L1/L2 cluster lookup with an L2 table cache
+//! - allocate-on-write, including copy-up of partial clusters from a backing
+//!   disk
+//! - refcount maintenance, including growing the refcount table
+//! - zero clusters and discard
+//! - reading deflate-compressed clusters
+//! - backing-file chains of arbitrary depth
+//!
+//! Internal snapshots, encryption, external data files and extended L2
+//! entries are not supported. Images with internal snapshots can still be
+//! opened read-only.
+//!
+//! There are two ways to use an image:
+//!
+//! - [`Qcow2Disk`] implements [`DiskIo`] and handles the backing chain itself,
+//!   reading unallocated clusters from a backing [`Disk`]. This is the only
+//!   way to write to an image that has a backing file, since partial writes
+//!   need to copy the rest of the cluster up from the backing disk.
+//! - [`layer::Qcow2Layer`] implements [`LayerIo`](disk_layered::LayerIo), so
+//!   an image can be stacked with other layers in a `LayeredDisk`.
+
+#![forbid(unsafe_code)]
+
+pub mod chain;
+pub mod create;
+mod file;
+mod format;
+pub mod image;
+pub mod layer;
+pub mod resolver;
+
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use disk_backend::DiskIo;
+use disk_backend::UnmapBehavior;
+use guestmem::MemoryWrite;
+use image::Qcow2Image;
+use inspect::Inspect;
+use scsi_buffers::RequestBuffers;
+use thiserror::Error;
+
+/// The sector size exposed by qcow2 disks.
+const SECTOR_SIZE: u32 = 512;
+
+/// Returns the physical sector size to report for an image.
+fn physical_sector_size(image: &Qcow2Image) -> u32 {
+    image.cluster_size().clamp(512, 4096)
+}
+
+/// A qcow2 disk.
+#[derive(Inspect)]
+pub struct Qcow2Disk {
+    #[inspect(flatten)]
+    image: Qcow2Image,
+    backing: Option<Disk>,
+}
+
+/// An error returned by [`Qcow2Disk::new`].
+#[derive(Debug, Error)]
+pub enum NewDiskError {
+    /// The image has a backing file, but no backing disk was provided.
+    #[error("image has backing file {0:?} but no backing disk was provided")]
+    MissingBacking(String),
+    /// A backing disk was provided, but the image has no backing file.
+    #[error("image has no backing file")]
+    UnexpectedBacking,
+    /// The backing disk's sector size is not supported.
+    #[error("unsupported backing disk sector size {0}")]
+    BackingSectorSize(u32),
+}
+
+impl Qcow2Disk {
+    /// Returns a new disk for `image`.
+    ///
+    /// `backing` must be provided if and only if the image has a backing file.
+    /// It supplies the contents of unallocated clusters. If it is smaller than
+    /// the image, the remainder reads as zero.
+    pub fn new(image: Qcow2Image, backing: Option<Disk>) -> Result<Self, NewDiskError> {
+        match (image.backing_file(), &backing) {
+            (Some(file), None) => return Err(NewDiskError::MissingBacking(file.name.clone())),
+            (None, Some(_)) => return Err(NewDiskError::UnexpectedBacking),
+            (_, Some(backing)) if backing.sector_size() != SECTOR_SIZE => {
+                return Err(NewDiskError::BackingSectorSize(backing.sector_size()));
+            }
+            _ => {}
+        }
+        Ok(Self { image, backing })
+    }
+}
+
+impl DiskIo for Qcow2Disk {
+    fn disk_type(&self) -> &str {
+        "qcow2"
+    }
+
+    fn sector_count(&self) -> u64 {
+        self.image.sector_count()
+    }
+
+    fn sector_size(&self) -> u32 {
+        SECTOR_SIZE
+    }
+
+    fn disk_id(&self) -> Option<[u8; 16]> {
+        None
+    }
+
+    fn physical_sector_size(&self) -> u32 {
+        physical_sector_size(&self.image)
+    }
+
+    fn is_fua_respected(&self) -> bool {
+        true
+    }
+
+    fn is_read_only(&self) -> bool {
+        self.image.is_read_only()
+    }
+
+    async fn read_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+    ) -> Result<(), DiskError> {
+        let unallocated = self.image.read(buffers, sector).await?;
+        for range in unallocated {
+            let offset = ((range.start - sector) * SECTOR_SIZE as u64) as usize;
+            let len = ((range.end - range.start) * SECTOR_SIZE as u64) as usize;
+            let buffers = buffers.subrange(offset, len);
+            // The backing disk may be smaller than the image.
+            let backing_len = match &self.backing {
+                Some(backing) => {
+                    let count = backing.sector_count().saturating_sub(range.start);
+                    (count.min(range.end - range.start) * SECTOR_SIZE as u64) as usize
+                }
+                None => 0,
+            };
+            if backing_len > 0 {
+                self.backing
+                    .as_ref()
+                    .unwrap()
+                    .read_vectored(&buffers.subrange(0, backing_len), range.start)
+                    .await?;
+            }
+            if backing_len < len {
+                buffers
+                    .subrange(backing_len, len - backing_len)
+                    .writer()
+                    .zero(len - backing_len)?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn write_vectored(
+        &self,
+        buffers: &RequestBuffers<'_>,
+        sector: u64,
+        fua: bool,
+    ) -> Result<(), DiskError> {
+        self.image
+            .write(buffers, sector, fua, self.backing.as_ref())
+            .await
+    }
+
+    async fn sync_cache(&self) -> Result<(), DiskError> {
+        self.image.flush().await
+    }
+
+    async fn unmap(
+        &self,
+        sector: u64,
+        count: u64,
+        _block_level_only: bool,
+    ) -> Result<(), DiskError> {
+        self.image
+            .discard(sector, count, self.backing.is_none())
+            .await
+    }
+
+    fn unmap_behavior(&self) -> UnmapBehavior {
+        UnmapBehavior::Unspecified
+    }
+
+    fn optimal_unmap_sectors(&self) -> u32 {
+        self.image.cluster_size() / SECTOR_SIZE
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::create::CreateParams;
+    use guestmem::GuestMemory;
+    use pal_async::async_test;
+    use scsi_buffers::OwnedRequestBuffers;
+    use std::fs::File;
+    use std::io::Write;
+
+    const DISK_SIZE: u64 = 1024 * 1024;
+
+    fn new_image(size: u64, cluster_bits: u32, backing_file: Option<&str>) -> File {
+        let file = tempfile::tempfile().unwrap();
+        create::create(
+            &file,
+            &CreateParams {
+                size,
+                cluster_bits,
+                backing_file,
+                backing_format: backing_file.map(|_| "raw"),
+            },
+        )
+        .unwrap();
+        file
+    }
+
+    fn open(file: &File, backing: Option<Disk>) -> Disk {
+        let image = Qcow2Image::open(file.try_clone().unwrap(), false).unwrap();
+        Disk::new(Qcow2Disk::new(image, backing).unwrap()).unwrap()
+    }
+
+    fn raw_disk(data: &[u8]) -> Disk {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(data).unwrap();
+        Disk::new(disk_file::FileDisk::open(file, true).unwrap()).unwrap()
+    }
+
+    async fn write(disk: &Disk, sector: u64, data: &[u8]) {
+        let mem = GuestMemory::allocate(data.len());
+        mem.write_at(0, data).unwrap();
+        disk.write_vectored(
+            &OwnedRequestBuffers::linear(0, data.len(), false).buffer(&mem),
+            sector,
+            false,
+        )
+        .await
+        .unwrap();
+    }
+
+    async fn read(disk: &Disk, sector: u64, len: usize) -> Vec<u8> {
+        let mem = GuestMemory::allocate(len);
+        disk.read_vectored(
+            &OwnedRequestBuffers::linear(0, len, true).buffer(&mem),
+            sector,
+        )
+        .await
+        .unwrap();
+        let mut data = vec![0; len];
+        mem.read_at(0, &mut data).unwrap();
+        data
+    }
+
+    fn pattern(len: usize, seed: u8) -> Vec<u8> {
+        (0..len).map(|i| (i as u8).wrapping_mul(7) ^ seed).collect()
+    }
+
+    #[async_test]
+    async fn sector_range_conformance() {
+        let file = new_image(DISK_SIZE, 16, None);
+        let disk = open(&file, None);
+        storage_tests::sector_range::test_disk_sector_range_conformance(&disk).await;
+    }
+
+    #[async_test]
+    async fn write_read_back() {
+        let file = new_image(DISK_SIZE, 12, None);
+        let disk = open(&file, None);
+        assert_eq!(read(&disk, 0, 8192).await, vec![0; 8192]);
+
+        // Unaligned to clusters, spanning several of them.
+        let data = pattern(12288, 1);
+        write(&disk, 3, &data).await;
+        assert_eq!(read(&disk, 3, data.len()).await, data);
+        assert_eq!(read(&disk, 0, 1536).await, vec![0; 1536]);
+
+        // Overwrite in place.
+        let data2 = pattern(1024, 2);
+        write(&disk, 5, &data2).await;
+        let mut expected = data.clone();
+        expected[1024..2048].copy_from_slice(&data2);
+        assert_eq!(read(&disk, 3, expected.len()).await, expected);
+        disk.sync_cache().await.unwrap();
+
+        // Reopen and check that everything persisted.
+        drop(disk);
+        let disk = open(&file, None);
+        assert_eq!(read(&disk, 3, expected.len()).await, expected);
+    }
+
+    #[async_test]
+    async fn refcount_table_growth() {
+        // With 512-byte clusters, each refcount block covers 256 clusters and
+        // the initial one-cluster refcount table holds 64 blocks, so filling
+        // more than 8 MiB forces the table to be relocated.
+        let file = new_image(10 * 1024 * 1024, 9, None);
+        let disk = open(&file, None);
+        let data = pattern(1024 * 1024, 3);
+        for i in 0..10 {
+            write(&disk, i * 2048, &data).await;
+        }
+        disk.sync_cache().await.unwrap();
+        drop(disk);
+
+        let disk = open(&file, None);
+        for i in 0..10 {
+            assert_eq!(read(&disk, i * 2048, data.len()).await, data);
+        }
+    }
+
+    #[async_test]
+    async fn backing_copy_on_write() {
+        let base = pattern(DISK_SIZE as usize / 2, 5);
+        let backing = raw_disk(&base);
+        let file = new_image(DISK_SIZE, 16, Some("base.raw"));
+        let disk = open(&file, Some(backing));
+
+        // Unallocated reads come from the backing disk, or zero past its end.
+        assert_eq!(read(&disk, 0, base.len()).await, base);
+        assert_eq!(
+            read(&disk, base.len() as u64 / 512, 4096).await,
+            vec![0; 4096]
+        );
+
+        // A partial write copies the rest of the cluster up.
+        let data = pattern(1024, 6);
+        write(&disk, 4, &data).await;
+        let mut expected = base[..65536].to_vec();
+        expected[2048..3072].copy_from_slice(&data);
+        assert_eq!(read(&disk, 0, 65536).await, expected);
+    }
+
+    #[async_test]
+    async fn discard() {
+        let base = pattern(DISK_SIZE as usize, 7);
+        let file = new_image(DISK_SIZE, 12, Some("base.raw"));
+        let disk = open(&file, Some(raw_disk(&base)));
+        write(&disk, 0, &pattern(16384, 8)).await;
+
+        // Partial clusters are left alone, and whole clusters read as zero
+        // rather than exposing the backing disk.
+        disk.unmap(1, 31, false).await.unwrap();
+        let data = read(&disk, 0, 16384).await;
+        assert_eq!(data[..4096], pattern(16384, 8)[..4096]);
+        assert_eq!(data[4096..], vec![0; 12288]);
+
+        // The freed clusters are reused.
+        let len = file.metadata().unwrap().len();
+        write(&disk, 64, &pattern(8192, 9)).await;
+        disk.sync_cache().await.unwrap();
+        write(&disk, 80, &pattern(4096, 9)).await;
+        assert_eq!(file.metadata().unwrap().len(), len);
+    }
+
+    #[async_test]
+    async fn compressed_read() {
+        use flate2::Compression;
+        use flate2::write::DeflateEncoder;
+
+        let file = new_image(DISK_SIZE, 16, None);
+        let disk = open(&file, None);
+        // Allocate the first cluster's L2 table and data cluster.
+        write(&disk, 0, &[1; 512]).await;
+        drop(disk);
+
+        // Append a compressed cluster and point the second L2 entry at it.
+        let data = pattern(65536, 10);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&data).unwrap();
+        let compressed = encoder.finish().unwrap();
+        let host = file.metadata().unwrap().len();
+        file::write_exact_at(&file, &compressed, host).unwrap();
+        let sectors = (compressed.len() as u64).div_ceil(512) - 1;
+        let entry = format::OFLAG_COMPRESSED | (sectors << (62 - (16 - 8))) | host;
+
+        let mut l1 = [0; 8];
+        file::read_exact_at(&file, &mut l1, 3 * 65536).unwrap();
+        let l2 = u64::from_be_bytes(l1) & format::L1E_OFFSET_MASK;
+        file::write_exact_at(&file, &entry.to_be_bytes(), l2 + 8).unwrap();
+
+        let image = Qcow2Image::open(file.try_clone().unwrap(), true).unwrap();
+        let disk = Disk::new(Qcow2Disk::new(image, None).unwrap()).unwrap();
+        assert_eq!(read(&disk, 128, 65536).await, data);
+        assert_eq!(read(&disk, 130, 1024).await, data[1024..2048]);
+    }
+}
@@ -0,0 +1,116 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource resolvers for qcow2 disks and disk layers.
+
+use crate::NewDiskError;
+use crate::Qcow2Disk;
+use crate::image::OpenError;
+use crate::image::Qcow2Image;
+use crate::layer::NewLayerError;
+use crate::layer::Qcow2Layer;
+use async_trait::async_trait;
+use disk_backend::resolve::ResolveDiskParameters;
+use disk_backend::resolve::ResolvedDisk;
+use disk_backend_resources::Qcow2DiskHandle;
+use disk_backend_resources::layer::Qcow2DiskLayerHandle;
+use disk_layered::resolve::ResolveDiskLayerParameters;
+use disk_layered::resolve::ResolvedDiskLayer;
+use thiserror::Error;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResolveError;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::DiskHandleKind;
+use vm_resource::kind::DiskLayerHandleKind;
+
+/// Resolver for [`Qcow2DiskHandle`].
+pub struct Qcow2DiskResolver;
+
+declare_static_async_resolver!(Qcow2DiskResolver, (DiskHandleKind, Qcow2DiskHandle));
+
+/// Resolver for [`Qcow2DiskLayerHandle`].
+pub struct Qcow2DiskLayerResolver;
+
+declare_static_async_resolver!(
+    Qcow2DiskLayerResolver,
+    (DiskLayerHandleKind, Qcow2DiskLayerHandle)
+);
+
+/// Errors from resolving a qcow2 disk or disk layer.
+#[derive(Debug, Error)]
+pub enum ResolveQcow2Error {
+    /// Failed to open the qcow2 image.
+    #[error("failed to open qcow2 image")]
+    Open(#[source] OpenError),
+    /// Failed to resolve the backing disk.
+    #[error("failed to resolve backing disk")]
+    Backing(#[source] ResolveError),
+    /// The backing disk does not match the image.
+    #[error("invalid backing disk")]
+    InvalidBacking(#[source] NewDiskError),
+    /// The image cannot be used as a layer.
+    #[error("invalid qcow2 layer")]
+    InvalidLayer(#[source] NewLayerError),
+    /// The disk is invalid.
+    #[error("invalid disk")]
+    InvalidDisk(#[source] disk_backend::InvalidDisk),
+}
+
+#[async_trait]
+impl AsyncResolveResource<DiskHandleKind, Qcow2DiskHandle> for Qcow2DiskResolver {
+    type Output = ResolvedDisk;
+    type Error = ResolveQcow2Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        rsrc: Qcow2DiskHandle,
+        input: ResolveDiskParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let read_only = rsrc.read_only || input.read_only;
+        let backing = match rsrc.backing {
+            Some(backing) => Some(
+                resolver
+                    .resolve(
+                        backing,
+                        ResolveDiskParameters {
+                            read_only: true,
+                            driver_source: input.driver_source,
+                        },
+                    )
+                    .await
+                    .map_err(ResolveQcow2Error::Backing)?
+                    .0,
+            ),
+            None => None,
+        };
+        let file = rsrc.file;
+        let image = blocking::unblock(move || Qcow2Image::open(file, read_only))
+            .await
+            .map_err(ResolveQcow2Error::Open)?;
+        let disk = Qcow2Disk::new(image, backing).map_err(ResolveQcow2Error::InvalidBacking)?;
+        ResolvedDisk::new(disk).map_err(ResolveQcow2Error::InvalidDisk)
+    }
+}
+
+#[async_trait]
+impl AsyncResolveResource<DiskLayerHandleKind, Qcow2DiskLayerHandle> for Qcow2DiskLayerResolver {
+    type Output = ResolvedDiskLayer;
+    type Error = ResolveQcow2Error;
+
+    async fn resolve(
+        &self,
+        _resolver: &ResourceResolver,
+        rsrc: Qcow2DiskLayerHandle,
+        input: ResolveDiskLayerParameters<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let read_only = rsrc.read_only || input.read_only;
+        let file = rsrc.file;
+        let image = blocking::unblock(move || Qcow2Image::open(file, read_only))
+            .await
+            .map_err(ResolveQcow2Error::Open)?;
+        let layer = Qcow2Layer::new(image).map_err(ResolveQcow2Error::InvalidLayer)?;
+        Ok(ResolvedDiskLayer::new(layer))
+    }
+}
@@ -0,0 +1,134 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Types for the data pointer of an NVMe command, which describes guest memory
+//! with either PRPs or an SGL.
+
+use crate::error::NvmeError;
+use crate::prp::PrpRange;
+use crate::sgl::SglRange;
+use crate::spec;
+use guestmem::GuestMemory;
+use guestmem::ranges::PagedRange;
+use scsi_buffers::OwnedRequestBuffers;
+use scsi_buffers::RequestBuffers;
+
+/// A guest memory range described by a command's data pointer.
+pub enum DataRange {
+    Prp(PrpRange),
+    Sgl(SglRange),
+}
+
+impl DataRange {
+    /// Parses the data pointer of `command` for memory of `len` bytes,
+    /// according to the command's PSDT field.
+    pub fn parse(
+        mem: &GuestMemory,
+        len: usize,
+        command: &spec::Command,
+    ) -> Result<Self, NvmeError> {
+        match spec::Psdt(command.cdw0.psdt()) {
+            spec::Psdt::PRP => Ok(Self::Prp(PrpRange::parse(mem, len, command.dptr)?)),
+            spec::Psdt::SGL_MPTR_CONTIGUOUS => {
+                Ok(Self::Sgl(SglRange::parse(mem, len, command.dptr)?))
+            }
+            psdt => {
+                tracelimit::warn_ratelimited!(?psdt, "unsupported psdt");
+                Err(spec::Status::INVALID_FIELD_IN_COMMAND.into())
+            }
+        }
+    }
+
+    /// Returns the range as a [`PagedRange`], if it can be described as one.
+    fn range(&self) -> Option<PagedRange<'_>> {
+        match self {
+            DataRange::Prp(prp) => Some(prp.range()),
+            DataRange::Sgl(sgl) => sgl.range(),
+        }
+    }
+
+    /// Reads from the range.
+    pub fn read(&self, mem: &GuestMemory, buf: &mut [u8]) -> Result<(), NvmeError> {
+        match self {
+            DataRange::Prp(prp) => prp.read(mem, buf),
+            DataRange::Sgl(sgl) => sgl.read(mem, buf),
+        }
+    }
+
+    /// Writes to the range.
+    pub fn write(&self, mem: &GuestMemory, buf: &[u8]) -> Result<(), NvmeError> {
+        match self {
+            DataRange::Prp(prp) => prp.write(mem, buf),
+            DataRange::Sgl(sgl) => sgl.write(mem, buf),
+        }
+    }
+
+    /// Writes zeroes to the range.
+    pub fn zero(&self, mem: &GuestMemory, len: usize) -> Result<(), NvmeError> {
+        match self {
+            DataRange::Prp(prp) => prp.zero(mem, len),
+            DataRange::Sgl(sgl) => sgl.zero(mem, len),
+        }
+    }
+
+    /// Returns the buffers to use for a disk IO to or from the range.
+    /// `is_write` is true if the IO writes to the range, i.e. reads from the
+    /// disk.
+    ///
+    /// SGL data blocks that cannot be described as a single [`PagedRange`]
+    /// are staged through a bounce buffer, which is filled here for disk
+    /// writes and copied back by [`IoBuffers::finish`] for disk reads.
+    pub fn io_buffers<'a>(
+        &'a self,
+        mem: &'a GuestMemory,
+        is_write: bool,
+    ) -> Result<IoBuffers<'a>, NvmeError> {
+        let bounce = match self {
+            DataRange::Sgl(sgl) if sgl.range().is_none() => {
+                let bounce = GuestMemory::allocate(sgl.len());
+                if !is_write {
+                    let mut buf = vec![0; sgl.len()];
+                    sgl.read(mem, &mut buf)?;
+                    bounce.write_at(0, &buf).unwrap();
+                }
+                Some((bounce, OwnedRequestBuffers::linear(0, sgl.len(), is_write)))
+            }
+            _ => None,
+        };
+        Ok(IoBuffers {
+            mem,
+            range: self,
+            is_write,
+            bounce,
+        })
+    }
+}
+
+/// Buffers for a disk IO to or from a [`DataRange`].
+pub struct IoBuffers<'a> {
+    mem: &'a GuestMemory,
+    range: &'a DataRange,
+    is_write: bool,
+    bounce: Option<(GuestMemory, OwnedRequestBuffers)>,
+}
+
+impl IoBuffers<'_> {
+    /// Returns the request buffers to pass to the disk.
+    pub fn buffers(&self) -> RequestBuffers<'_> {
+        match &self.bounce {
+            Some((bounce, buffers)) => buffers.buffer(bounce),
+            None => RequestBuffers::new(self.mem, self.range.range().unwrap(), self.is_write),
+        }
+    }
+
+    /// Completes the IO, copying the data read from the disk out of the
+    /// bounce buffer, if there is one.
+    pub fn finish(self) -> Result<(), NvmeError> {
+        if let (true, Some((bounce, buffers))) = (self.is_write, &self.bounce) {
+            let mut buf = vec![0; buffers.len()];
+            bounce.read_at(0, &mut buf).unwrap();
+            self.range.write(self.mem, &buf)?;
+        }
+        Ok(())
+    }
+}
@@ -1,7 +1,7 @@
 // Copyright (c) Microsoft Corporation.
 // Licensed under the MIT License.
 
-//! NVMe controller emulator (NVMe 2.0, NVM command set).
+//! NVMe controller emulator (NVMe 2.0, NVM and Zoned Namespace command sets).
 //!
 //! This crate emulates an NVMe controller as a PCI device with MMIO BAR0,
 //! MSI-X, and admin + I/O queue pairs. It targets the
@@ -14,16 +14,19 @@
 //!   config space, MSI-X interrupt routing, doorbell writes.
 //! - **Coordinator** — manages enable/reset sequencing, namespace add/remove.
 //! - **Admin worker** — processes admin commands: Identify Controller/Namespace,
-//!   Create/Delete I/O Queue, Get/Set Features, Async Event Request.
+//!   Create/Delete I/O Queue, Get/Set Features, Async Event Request, Namespace
+//!   Management/Attachment, and Format NVM.
 //! - **I/O workers** — pool of tasks (one per completion queue) processing NVM
 //!   commands: READ, WRITE, FLUSH, Dataset Management (TRIM), and persistent
-//!   reservation commands.
+//!   reservation commands, plus Zone Append and Zone Management Send/Receive
+//!   on zoned namespaces. I/O commands can describe their data with either
+//!   PRPs or SGLs; admin commands only support PRPs.
 //!
 //! # What it doesn't implement
 //!
-//! Firmware update, admin-level namespace management (create/delete), multi-path
-//! I/O, end-to-end data protection (PI), and save/restore (`SaveRestore`
-//! returns not-supported).
+//! Firmware update, multi-path I/O, end-to-end data protection (PI), multiple
+//! LBA formats, cryptographic erase, SGL bit buckets and keyed data blocks, and
+//! save/restore (`SaveRestore` returns not-supported).
 //!
 //! # Namespace management
 //!
@@ -32,6 +35,24 @@
 //! monitors capacity changes via `wait_resize`, completing Async Event Requests
 //! with `CHANGED_NAMESPACE_LIST` when the disk size changes.
 //!
+//! The guest can also create and delete namespaces with the Namespace
+//! Management command. Each created namespace spans a whole disk from a pool
+//! configured by the host, and starts out detached until the guest attaches it
+//! with Namespace Attachment. Format NVM with a user data secure erase unmaps
+//! the namespace's disk.
+//!
+//! # Zoned namespaces
+//!
+//! A namespace added with [`NvmeControllerClient::add_zoned_namespace`] uses
+//! the Zoned Namespace Command Set. The disk is divided into fixed-size,
+//! sequential-write-required zones whose states and write pointers are tracked
+//! by the emulator, so any disk can back a zoned namespace. The zone states
+//! persist in a sidecar state disk if one is configured, and are otherwise
+//! kept in memory and lost when the namespace is removed. For testing, the
+//! host can move a zone to the read-only or offline state with
+//! [`NvmeControllerClient::inject_zone_fault`]; the zone is then reported in
+//! the Changed Zone List log page.
+//!
 //! # Key constants
 //!
 //! - `MAX_DATA_TRANSFER_SIZE`: 256 KB
@@ -40,17 +61,23 @@
 
 #![forbid(unsafe_code)]
 
+mod dptr;
 mod error;
 mod namespace;
 mod pci;
 mod prp;
 mod queue;
 pub mod resolver;
+mod sgl;
 mod workers;
 
 #[cfg(test)]
 mod tests;
 
+pub use namespace::InjectZoneFaultError;
+pub use namespace::ZoneFault;
+pub use namespace::ZoneStateError;
+pub use namespace::ZonedNamespaceConfig;
 pub use pci::NvmeController;
 pub use pci::NvmeControllerCaps;
 pub use workers::AddNamespaceError;
@@ -0,0 +1,922 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Zoned namespace support.
+//!
+//! A zoned namespace divides its disk into fixed size zones that must be
+//! written sequentially. The zone states and write pointers are kept alongside
+//! the disk rather than in it, so any disk backend can back a zoned namespace:
+//! the disk only sees ordinary reads, writes, and unmaps, after the namespace
+//! has checked them against the zone state machine.
+//!
+//! To persist along with the data, the zone states and write pointers are
+//! written through to a sidecar state disk. Its first sector holds a
+//! [`StateHeader`], and the following sectors hold a [`StateZone`] for each
+//! zone. A zone is written to the state disk once its write completes, with
+//! the write's FUA setting, and a flush of the namespace flushes the state
+//! disk too. As on a device that loses power with writes in flight, a write
+//! pointer persisted while an earlier write to the same zone is still in
+//! progress may cover data that never reaches the disk.
+
+use super::Namespace;
+use super::map_disk_error;
+use crate::dptr::DataRange;
+use crate::error::CommandResult;
+use crate::error::NvmeError;
+use crate::spec;
+use crate::spec::nvm;
+use crate::spec::zns;
+use disk_backend::Disk;
+use disk_backend::DiskError;
+use guestmem::GuestMemory;
+use inspect::Inspect;
+use parking_lot::Mutex;
+use scsi_buffers::OwnedRequestBuffers;
+use std::ops::Range;
+use thiserror::Error;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+/// The configuration of a zoned namespace.
+#[derive(Debug, Clone)]
+pub struct ZonedNamespaceConfig {
+    /// The size of each zone, in logical blocks. Any blocks at the end of the
+    /// disk that do not fill a whole zone are not exposed to the guest.
+    pub zone_size: u64,
+    /// The maximum number of zones that can be open at once, if limited.
+    pub max_open_zones: Option<u32>,
+    /// The maximum number of zones that can be open or closed at once, if
+    /// limited.
+    pub max_active_zones: Option<u32>,
+    /// The disk to keep the zone states and write pointers in, so that they
+    /// persist along with the data. A state disk that has never been used
+    /// must be zeroed.
+    ///
+    /// If `None`, the zone states are kept in memory only, and every zone is
+    /// empty each time the namespace is added. This is only consistent with
+    /// data that does not persist either.
+    pub state_disk: Option<Disk>,
+}
+
+/// A controller-initiated zone state change, injected by the host to test
+/// how the guest handles failing media.
+#[derive(Debug, Copy, Clone)]
+pub enum ZoneFault {
+    /// The zone becomes read only.
+    ReadOnly,
+    /// The zone goes offline, and can no longer be read or written.
+    Offline,
+}
+
+/// Error returned when a zone fault cannot be injected.
+#[derive(Debug, Error)]
+pub enum InjectZoneFaultError {
+    /// The namespace does not exist.
+    #[error("namespace {0} not found")]
+    NamespaceNotFound(u32),
+    /// The namespace is not zoned.
+    #[error("namespace {0} is not zoned")]
+    NotZoned(u32),
+    /// The LBA is not the start of a zone.
+    #[error("lba {0:#x} is not the start of a zone")]
+    InvalidZone(u64),
+    /// The new zone state could not be written to the state disk.
+    #[error("failed to write zone state")]
+    StateDisk(#[source] DiskError),
+}
+
+/// Error returned when the zone states cannot be loaded from the state disk.
+#[derive(Debug, Error)]
+pub enum ZoneStateError {
+    /// The state disk cannot hold the state of every zone.
+    #[error("state disk is too small for {0} zones")]
+    DiskTooSmall(u64),
+    /// The state disk was written for a different zone size or count.
+    #[error("state disk is for {zone_count} zones of {zone_size} blocks")]
+    LayoutMismatch {
+        /// The zone size in the state disk.
+        zone_size: u64,
+        /// The zone count in the state disk.
+        zone_count: u64,
+    },
+    /// The state disk has an invalid state for a zone.
+    #[error("state disk has an invalid state for the zone at lba {0:#x}")]
+    InvalidZone(u64),
+    /// The state disk could not be read or written.
+    #[error("state disk io failed")]
+    Io(#[source] DiskError),
+}
+
+/// The maximum number of zone identifiers in the Changed Zone List log page.
+const MAX_CHANGED_ZONES: usize = 511;
+
+/// The signature in the [`StateHeader`] of a state disk that is in use.
+const STATE_SIGNATURE: [u8; 8] = *b"NVMEZONE";
+
+/// The first sector of a state disk.
+#[repr(C)]
+#[derive(IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct StateHeader {
+    signature: [u8; 8],
+    zone_size: u64,
+    zone_count: u64,
+}
+
+/// The state of a zone in a state disk.
+#[repr(C)]
+#[derive(IntoBytes, FromBytes, Immutable, KnownLayout)]
+struct StateZone {
+    wp: u64,
+    state: u8,
+    reserved: [u8; 7],
+}
+
+/// The zone state of a zoned namespace.
+#[derive(Inspect)]
+pub(super) struct Zones {
+    zone_size: u64,
+    zone_count: u64,
+    max_open: Option<u32>,
+    max_active: Option<u32>,
+    #[inspect(flatten)]
+    table: Mutex<ZoneTable>,
+    #[inspect(with = "Option::is_some")]
+    state_disk: Option<StateDisk>,
+}
+
+struct StateDisk {
+    disk: Disk,
+    /// Serializes writes to the disk, so that a sector copied from the zone
+    /// table cannot be overwritten by an older copy of the same sector.
+    write_lock: futures::lock::Mutex<()>,
+}
+
+impl StateDisk {
+    fn zones_per_sector(&self) -> usize {
+        self.disk.sector_size() as usize / size_of::<StateZone>()
+    }
+
+    async fn read(&self, sector: u64, len: usize) -> Result<Vec<u8>, DiskError> {
+        let mem = GuestMemory::allocate(len);
+        self.disk
+            .read_vectored(
+                &OwnedRequestBuffers::linear(0, len, true).buffer(&mem),
+                sector,
+            )
+            .await?;
+        let mut buf = vec![0; len];
+        mem.read_at(0, &mut buf).unwrap();
+        Ok(buf)
+    }
+
+    async fn write(&self, sector: u64, buf: &[u8], fua: bool) -> Result<(), DiskError> {
+        let mem = GuestMemory::allocate(buf.len());
+        mem.write_at(0, buf).unwrap();
+        self.disk
+            .write_vectored(
+                &OwnedRequestBuffers::linear(0, buf.len(), false).buffer(&mem),
+                sector,
+                fua,
+            )
+            .await
+    }
+}
+
+#[derive(Inspect)]
+struct ZoneTable {
+    #[inspect(skip)]
+    zones: Vec<Zone>,
+    open: u32,
+    active: u32,
+    /// The zones whose state was changed by the controller rather than by a
+    /// host command, for the Changed Zone List log page.
+    #[inspect(with = "Vec::len")]
+    changed: Vec<usize>,
+    changed_overflow: bool,
+}
+
+#[derive(Copy, Clone)]
+struct Zone {
+    state: zns::ZoneState,
+    wp: u64,
+}
+
+/// Blocks reserved for a write by [`Zones::write`] or [`Zones::append`].
+pub(super) struct ZoneWrite {
+    /// The LBA to write.
+    pub lba: u64,
+    count: u64,
+    /// The state of the zone before the write.
+    prev_state: zns::ZoneState,
+}
+
+fn is_open(state: zns::ZoneState) -> bool {
+    matches!(
+        state,
+        zns::ZoneState::IMPLICITLY_OPENED | zns::ZoneState::EXPLICITLY_OPENED
+    )
+}
+
+fn is_active(state: zns::ZoneState) -> bool {
+    is_open(state) || state == zns::ZoneState::CLOSED
+}
+
+impl Zones {
+    /// Returns the zone state for a disk of `sector_count` blocks, or `None`
+    /// if the configuration is invalid for the disk.
+    pub fn new(config: &ZonedNamespaceConfig, sector_count: u64) -> Option<Self> {
+        let zone_count = sector_count.checked_div(config.zone_size)?;
+        if zone_count == 0
+            || config.max_open_zones == Some(0)
+            || config.max_active_zones == Some(0)
+            || config
+                .max_open_zones
+                .zip(config.max_active_zones)
+                .is_some_and(|(open, active)| open > active)
+        {
+            return None;
+        }
+        let zones = (0..zone_count)
+            .map(|i| Zone {
+                state: zns::ZoneState::EMPTY,
+                wp: i * config.zone_size,
+            })
+            .collect();
+        Some(Self {
+            zone_size: config.zone_size,
+            zone_count,
+            max_open: config.max_open_zones,
+            max_active: config.max_active_zones,
+            table: Mutex::new(ZoneTable {
+                zones,
+                open: 0,
+                active: 0,
+                changed: Vec::new(),
+                changed_overflow: false,
+            }),
+            state_disk: config.state_disk.clone().map(|disk| StateDisk {
+                disk,
+                write_lock: Default::default(),
+            }),
+        })
+    }
+
+    /// Loads the zone states from the state disk, if there is one. A state
+    /// disk that has never been used is initialized with every zone empty.
+    ///
+    /// Open zones are loaded as closed, as after a power cycle.
+    pub async fn load(&self) -> Result<(), ZoneStateError> {
+        let Some(state_disk) = &self.state_disk else {
+            return Ok(());
+        };
+        let sector_size = state_disk.disk.sector_size() as usize;
+        let zone_sectors = self
+            .zone_count
+            .div_ceil(state_disk.zones_per_sector() as u64);
+        if state_disk.disk.sector_count() <= zone_sectors {
+            return Err(ZoneStateError::DiskTooSmall(self.zone_count));
+        }
+
+        let buf = state_disk
+            .read(0, sector_size)
+            .await
+            .map_err(ZoneStateError::Io)?;
+        let header = StateHeader::read_from_prefix(&buf).unwrap().0;
+        if header.signature != STATE_SIGNATURE {
+            // Write the header last, so that the disk is only used once every
+            // zone has been written.
+            self.persist(0..self.zone_count as usize, true)
+                .await
+                .map_err(ZoneStateError::Io)?;
+            let header = StateHeader {
+                signature: STATE_SIGNATURE,
+                zone_size: self.zone_size,
+                zone_count: self.zone_count,
+            };
+            let mut buf = vec![0; sector_size];
+            buf[..size_of_val(&header)].copy_from_slice(header.as_bytes());
+            state_disk
+                .write(0, &buf, true)
+                .await
+                .map_err(ZoneStateError::Io)?;
+            return Ok(());
+        }
+        if header.zone_size != self.zone_size || header.zone_count != self.zone_count {
+            return Err(ZoneStateError::LayoutMismatch {
+                zone_size: header.zone_size,
+                zone_count: header.zone_count,
+            });
+        }
+
+        let buf = state_disk
+            .read(1, zone_sectors as usize * sector_size)
+            .await
+            .map_err(ZoneStateError::Io)?;
+        let mut table = self.table.lock();
+        let table = &mut *table;
+        for (index, (zone, buf)) in table
+            .zones
+            .iter_mut()
+            .zip(buf.chunks_exact(size_of::<StateZone>()))
+            .enumerate()
+        {
+            let saved = StateZone::read_from_bytes(buf).unwrap();
+            let zslba = index as u64 * self.zone_size;
+            if !(zslba..=zslba + self.zone_size).contains(&saved.wp) {
+                return Err(ZoneStateError::InvalidZone(zslba));
+            }
+            let state = match zns::ZoneState(saved.state) {
+                zns::ZoneState::IMPLICITLY_OPENED
+                | zns::ZoneState::EXPLICITLY_OPENED
+                | zns::ZoneState::CLOSED => {
+                    if saved.wp == zslba {
+                        zns::ZoneState::EMPTY
+                    } else {
+                        zns::ZoneState::CLOSED
+                    }
+                }
+                state @ (zns::ZoneState::EMPTY
+                | zns::ZoneState::FULL
+                | zns::ZoneState::READ_ONLY
+                | zns::ZoneState::OFFLINE) => state,
+                _ => return Err(ZoneStateError::InvalidZone(zslba)),
+            };
+            *zone = Zone {
+                state,
+                wp: saved.wp,
+            };
+            table.active += is_active(state) as u32;
+        }
+        Ok(())
+    }
+
+    /// Writes the states of `zones` to the state disk, if there is one.
+    async fn persist(&self, zones: Range<usize>, fua: bool) -> Result<(), DiskError> {
+        let Some(state_disk) = &self.state_disk else {
+            return Ok(());
+        };
+        if zones.is_empty() {
+            return Ok(());
+        }
+        let zones_per_sector = state_disk.zones_per_sector();
+        let sectors = zones.start / zones_per_sector..(zones.end - 1) / zones_per_sector + 1;
+        let _guard = state_disk.write_lock.lock().await;
+        let buf = {
+            let table = self.table.lock();
+            let first = sectors.start * zones_per_sector;
+            let end = (sectors.end * zones_per_sector).min(table.zones.len());
+            let mut buf = vec![0; sectors.len() * state_disk.disk.sector_size() as usize];
+            for (buf, zone) in buf
+                .chunks_exact_mut(size_of::<StateZone>())
+                .zip(&table.zones[first..end])
+            {
+                let saved = StateZone {
+                    wp: zone.wp,
+                    state: zone.state.0,
+                    reserved: [0; 7],
+                };
+                buf.copy_from_slice(saved.as_bytes());
+            }
+            buf
+        };
+        state_disk.write(1 + sectors.start as u64, &buf, fua).await
+    }
+
+    /// Flushes the state disk, if there is one.
+    pub async fn flush(&self) -> Result<(), DiskError> {
+        if let Some(state_disk) = &self.state_disk {
+            state_disk.disk.sync_cache().await?;
+        }
+        Ok(())
+    }
+
+    /// Returns the number of blocks covered by zones.
+    pub fn capacity(&self) -> u64 {
+        self.zone_count * self.zone_size
+    }
+
+    pub fn identify(&self, buf: &mut [u8]) {
+        let id = zns::IdentifyNamespaceZns::mut_from_prefix(buf).unwrap().0; // TODO: zerocopy: from-prefix (mut_from_prefix): use-rest-of-range (https://github.com/microsoft/openvmm/issues/759)
+        *id = zns::IdentifyNamespaceZns {
+            ozcs: zns::Ozcs::new().with_razb(true),
+            mar: self.max_active.map_or(!0, |n| n - 1),
+            mor: self.max_open.map_or(!0, |n| n - 1),
+            ..FromZeros::new_zeroed()
+        };
+        id.lbafe[0].zsze = self.zone_size;
+    }
+
+    fn zone_index(&self, lba: u64) -> usize {
+        (lba / self.zone_size) as usize
+    }
+
+    /// Moves zone `index` to `state`, updating the open and active zone
+    /// counts.
+    fn transition(
+        &self,
+        table: &mut ZoneTable,
+        index: usize,
+        state: zns::ZoneState,
+    ) -> Result<(), NvmeError> {
+        let old = table.zones[index].state;
+        if is_active(state)
+            && !is_active(old)
+            && self.max_active.is_some_and(|max| table.active >= max)
+        {
+            return Err(spec::Status::TOO_MANY_ACTIVE_ZONES.into());
+        }
+        if is_open(state) && !is_open(old) && self.max_open.is_some_and(|max| table.open >= max) {
+            return Err(spec::Status::TOO_MANY_OPEN_ZONES.into());
+        }
+        table.active = table.active + is_active(state) as u32 - is_active(old) as u32;
+        table.open = table.open + is_open(state) as u32 - is_open(old) as u32;
+
+        let zslba = index as u64 * self.zone_size;
+        let zone = &mut table.zones[index];
+        zone.state = state;
+        match state {
+            zns::ZoneState::EMPTY => zone.wp = zslba,
+            zns::ZoneState::FULL => zone.wp = zslba + self.zone_size,
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Checks that `count` blocks at `lba`, which must be in range, can be
+    /// read.
+    pub fn check_read(&self, lba: u64, count: u64) -> Result<(), NvmeError> {
+        let table = self.table.lock();
+        let zones = &table.zones[self.zone_index(lba)..=self.zone_index(lba + count - 1)];
+        if zones
+            .iter()
+            .any(|zone| zone.state == zns::ZoneState::OFFLINE)
+        {
+            return Err(spec::Status::ZONE_IS_OFFLINE.into());
+        }
+        Ok(())
+    }
+
+    /// Advances the write pointer of zone `index` for a write of `count`
+    /// blocks at `lba`, implicitly opening the zone if necessary.
+    fn write_locked(
+        &self,
+        table: &mut ZoneTable,
+        index: usize,
+        lba: u64,
+        count: u64,
+    ) -> Result<ZoneWrite, NvmeError> {
+        let zone = table.zones[index];
+        match zone.state {
+            zns::ZoneState::FULL => return Err(spec::Status::ZONE_IS_FULL.into()),
+            zns::ZoneState::READ_ONLY => return Err(spec::Status::ZONE_IS_READ_ONLY.into()),
+            zns::ZoneState::OFFLINE => return Err(spec::Status::ZONE_IS_OFFLINE.into()),
+            _ => {}
+        }
+        let end = (index as u64 + 1) * self.zone_size;
+        if lba != zone.wp {
+            return Err(spec::Status::ZONE_INVALID_WRITE.into());
+        }
+        if end - lba < count {
+            return Err(spec::Status::ZONE_BOUNDARY_ERROR.into());
+        }
+        if !is_open(zone.state) {
+            self.transition(table, index, zns::ZoneState::IMPLICITLY_OPENED)?;
+        }
+        table.zones[index].wp = lba + count;
+        if lba + count == end {
+            self.transition(table, index, zns::ZoneState::FULL)?;
+        }
+        Ok(ZoneWrite {
+            lba,
+            count,
+            prev_state: zone.state,
+        })
+    }
+
+    /// Reserves `count` blocks at `lba`, which must be in range, for a write.
+    ///
+    /// The write pointer advances immediately, so that the next sequential
+    /// write can be issued before this one completes. Once the write
+    /// completes, the reservation must be finished with
+    /// [`Zones::commit_write`] or, if the write failed, undone with
+    /// [`Zones::abort_write`].
+    pub fn write(&self, lba: u64, count: u64) -> Result<ZoneWrite, NvmeError> {
+        let mut table = self.table.lock();
+        self.write_locked(&mut table, self.zone_index(lba), lba, count)
+    }
+
+    /// Reserves `count` blocks at the write pointer of the zone starting at
+    /// `zslba`, which must be in range, for a write, as for [`Zones::write`].
+    pub fn append(&self, zslba: u64, count: u64) -> Result<ZoneWrite, NvmeError> {
+        if zslba % self.zone_size != 0 {
+            return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+        }
+        let mut table = self.table.lock();
+        let index = self.zone_index(zslba);
+        let lba = table.zones[index].wp;
+        self.write_locked(&mut table, index, lba, count)
+    }
+
+    /// Persists the write pointer advanced for `write`, whose disk write
+    /// succeeded.
+    pub async fn commit_write(&self, write: &ZoneWrite, fua: bool) -> Result<(), NvmeError> {
+        let index = self.zone_index(write.lba);
+        self.persist(index..index + 1, fua)
+            .await
+            .map_err(map_disk_error)
+    }
+
+    /// Undoes the reservation for `write`, whose disk write failed, so that
+    /// the guest can retry it.
+    ///
+    /// If a later write to the zone has already been reserved, rewinding the
+    /// write pointer would strand that write's data past it. The zone is made
+    /// read only instead, and reported in the Changed Zone List log page.
+    pub async fn abort_write(&self, write: ZoneWrite) {
+        let index = self.zone_index(write.lba);
+        {
+            let mut table = self.table.lock();
+            let zone = table.zones[index];
+            if !is_active(zone.state) && zone.state != zns::ZoneState::FULL {
+                // The zone has been reset or has failed since, so there is
+                // nothing to undo.
+                return;
+            }
+            let mut undone = false;
+            if zone.wp == write.lba + write.count {
+                let state = if write.lba == index as u64 * self.zone_size {
+                    zns::ZoneState::EMPTY
+                } else if zone.state == zns::ZoneState::FULL {
+                    write.prev_state
+                } else {
+                    zone.state
+                };
+                // Reopening a zone that the write filled fails if the zone
+                // limits have been reached since.
+                if self.transition(&mut table, index, state).is_ok() {
+                    table.zones[index].wp = write.lba;
+                    undone = true;
+                }
+            }
+            if !undone {
+                tracelimit::warn_ratelimited!(
+                    lba = write.lba,
+                    count = write.count,
+                    "cannot undo failed zone write, making zone read only"
+                );
+                self.fail_zone(&mut table, index, zns::ZoneState::READ_ONLY);
+            }
+        }
+        // The write pointer was only advanced in memory, but a later write
+        // may have persisted it, or the zone may now be read only.
+        if let Err(err) = self.persist(index..index + 1, true).await {
+            tracelimit::error_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to persist zone state"
+            );
+        }
+    }
+
+    /// Moves zone `index` to `state`, which must be read only or offline, and
+    /// records the change for the Changed Zone List log page.
+    fn fail_zone(&self, table: &mut ZoneTable, index: usize, state: zns::ZoneState) {
+        // Neither state is active, so this cannot fail.
+        self.transition(table, index, state).unwrap();
+        if !table.changed.contains(&index) {
+            if table.changed.len() < MAX_CHANGED_ZONES {
+                table.changed.push(index);
+            } else {
+                table.changed_overflow = true;
+            }
+        }
+    }
+
+    /// Applies the zone send `action` to the zone starting at `zslba`, or to
+    /// every zone it applies to if `zslba` is `None`.
+    ///
+    /// Returns the start LBAs of the zones that were reset, whose blocks
+    /// should be deallocated.
+    pub async fn send(
+        &self,
+        action: zns::ZoneSendAction,
+        zslba: Option<u64>,
+    ) -> Result<Vec<u64>, NvmeError> {
+        use zns::ZoneState as S;
+        // The states the action transitions from for a single zone, and when
+        // selecting all zones.
+        let (from, from_all, to): (&[S], &[S], S) = match action {
+            zns::ZoneSendAction::CLOSE => (
+                &[S::IMPLICITLY_OPENED, S::EXPLICITLY_OPENED],
+                &[S::IMPLICITLY_OPENED, S::EXPLICITLY_OPENED],
+                S::CLOSED,
+            ),
+            zns::ZoneSendAction::FINISH => (
+                &[
+                    S::EMPTY,
+                    S::IMPLICITLY_OPENED,
+                    S::EXPLICITLY_OPENED,
+                    S::CLOSED,
+                ],
+                &[S::IMPLICITLY_OPENED, S::EXPLICITLY_OPENED, S::CLOSED],
+                S::FULL,
+            ),
+            zns::ZoneSendAction::OPEN => (
+                &[S::EMPTY, S::IMPLICITLY_OPENED, S::CLOSED],
+                &[S::CLOSED],
+                S::EXPLICITLY_OPENED,
+            ),
+            zns::ZoneSendAction::RESET => (
+                &[
+                    S::IMPLICITLY_OPENED,
+                    S::EXPLICITLY_OPENED,
+                    S::CLOSED,
+                    S::FULL,
+                ],
+                &[
+                    S::IMPLICITLY_OPENED,
+                    S::EXPLICITLY_OPENED,
+                    S::CLOSED,
+                    S::FULL,
+                ],
+                S::EMPTY,
+            ),
+            zns::ZoneSendAction::OFFLINE => (&[S::READ_ONLY], &[S::READ_ONLY], S::OFFLINE),
+            action => {
+                tracelimit::warn_ratelimited!(?action, "unsupported zone send action");
+                return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+            }
+        };
+
+        let mut reset = Vec::new();
+        let mut apply = |table: &mut ZoneTable, index: usize| -> Result<(), NvmeError> {
+            self.transition(table, index, to)?;
+            if to == S::EMPTY {
+                reset.push(index as u64 * self.zone_size);
+            }
+            Ok(())
+        };
+        // Persist every zone that was changed, even if a later one failed.
+        let (changed, result) = {
+            let mut table = self.table.lock();
+            if let Some(zslba) = zslba {
+                let index = self.zone_index(zslba);
+                let state = table.zones[index].state;
+                let result = if from.contains(&state) {
+                    apply(&mut table, index)
+                } else if state != to {
+                    Err(spec::Status::INVALID_ZONE_STATE_TRANSITION.into())
+                } else {
+                    Ok(())
+                };
+                (index..index + 1, result)
+            } else {
+                let result = (0..table.zones.len()).try_for_each(|index| {
+                    if from_all.contains(&table.zones[index].state) {
+                        apply(&mut table, index)?;
+                    }
+                    Ok(())
+                });
+                (0..table.zones.len(), result)
+            }
+        };
+        self.persist(changed, true).await.map_err(map_disk_error)?;
+        result?;
+        Ok(reset)
+    }
+
+    /// Resets every zone that is not read only or offline, for a format.
+    pub async fn reset_all(&self) -> Result<(), NvmeError> {
+        {
+            let mut table = self.table.lock();
+            for index in 0..table.zones.len() {
+                if !matches!(
+                    table.zones[index].state,
+                    zns::ZoneState::READ_ONLY | zns::ZoneState::OFFLINE
+                ) {
+                    self.transition(&mut table, index, zns::ZoneState::EMPTY)
+                        .unwrap();
+                }
+            }
+        }
+        self.persist(0..self.zone_count as usize, true)
+            .await
+            .map_err(map_disk_error)
+    }
+
+    /// Returns up to `max` descriptors of the zones matching `filter`,
+    /// starting with the zone containing `lba`, which must be in range.
+    ///
+    /// Also returns the total number of matching zones, or the number of
+    /// returned descriptors if `partial` is set.
+    pub fn report(
+        &self,
+        lba: u64,
+        filter: zns::ZoneReportFilter,
+        max: usize,
+        partial: bool,
+    ) -> Result<(u64, Vec<zns::ZoneDescriptor>), NvmeError> {
+        let state = match filter {
+            zns::ZoneReportFilter::ALL => None,
+            zns::ZoneReportFilter::EMPTY => Some(zns::ZoneState::EMPTY),
+            zns::ZoneReportFilter::IMPLICITLY_OPENED => Some(zns::ZoneState::IMPLICITLY_OPENED),
+            zns::ZoneReportFilter::EXPLICITLY_OPENED => Some(zns::ZoneState::EXPLICITLY_OPENED),
+            zns::ZoneReportFilter::CLOSED => Some(zns::ZoneState::CLOSED),
+            zns::ZoneReportFilter::FULL => Some(zns::ZoneState::FULL),
+            zns::ZoneReportFilter::READ_ONLY => Some(zns::ZoneState::READ_ONLY),
+            zns::ZoneReportFilter::OFFLINE => Some(zns::ZoneState::OFFLINE),
+            filter => {
+                tracelimit::warn_ratelimited!(?filter, "unsupported zone report filter");
+                return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+            }
+        };
+        let table = self.table.lock();
+        let matching = table
+            .zones
+            .iter()
+            .enumerate()
+            .skip(self.zone_index(lba))
+            .filter(|(_, zone)| state.is_none_or(|state| zone.state == state));
+
+        let mut count = 0;
+        let mut descriptors = Vec::new();
+        for (index, zone) in matching {
+            count += 1;
+            if descriptors.len() < max {
+                let wp = match zone.state {
+                    // The write pointer is not valid for these states.
+                    zns::ZoneState::READ_ONLY | zns::ZoneState::OFFLINE => !0,
+                    _ => zone.wp,
+                };
+                descriptors.push(zns::ZoneDescriptor {
+                    zt: zns::ZoneType::SEQUENTIAL_WRITE_REQUIRED.0,
+                    zs: zone.state.0 << 4,
+                    zcap: self.zone_size,
+                    zslba: index as u64 * self.zone_size,
+                    wp,
+                    ..FromZeros::new_zeroed()
+                });
+            } else if partial {
+                break;
+            }
+        }
+        if partial {
+            count = descriptors.len() as u64;
+        }
+        Ok((count, descriptors))
+    }
+
+    /// Moves the zone starting at `zslba` to the state for `fault`, and
+    /// records the change for the Changed Zone List log page.
+    pub async fn inject_fault(
+        &self,
+        zslba: u64,
+        fault: ZoneFault,
+    ) -> Result<(), InjectZoneFaultError> {
+        if zslba % self.zone_size != 0 || zslba >= self.capacity() {
+            return Err(InjectZoneFaultError::InvalidZone(zslba));
+        }
+        let state = match fault {
+            ZoneFault::ReadOnly => zns::ZoneState::READ_ONLY,
+            ZoneFault::Offline => zns::ZoneState::OFFLINE,
+        };
+        let index = self.zone_index(zslba);
+        self.fail_zone(&mut self.table.lock(), index, state);
+        self.persist(index..index + 1, true)
+            .await
+            .map_err(InjectZoneFaultError::StateDisk)
+    }
+
+    /// Fills in the Changed Zone List log page, and clears the list.
+    pub fn take_changed_zones(&self, log: &mut zns::ChangedZoneList) {
+        let mut table = self.table.lock();
+        if std::mem::take(&mut table.changed_overflow) {
+            log.nz = !0;
+        } else {
+            log.nz = table.changed.len() as u16;
+            for (zid, &index) in log.zid.iter_mut().zip(&table.changed) {
+                *zid = index as u64 * self.zone_size;
+            }
+        }
+        table.changed.clear();
+    }
+}
+
+impl Namespace {
+    pub(super) async fn zns_command(
+        &self,
+        zones: &Zones,
+        max_data_transfer_size: usize,
+        command: &spec::Command,
+    ) -> Result<CommandResult, NvmeError> {
+        let opcode = zns::ZnsOpcode(command.cdw0.opcode());
+        let slba = command.cdw10 as u64 | ((command.cdw11 as u64) << 32);
+        let check_slba = || {
+            if slba >= zones.capacity() {
+                return Err(NvmeError::from(spec::Status::LBA_OUT_OF_RANGE));
+            }
+            Ok(())
+        };
+
+        match opcode {
+            zns::ZnsOpcode::ZONE_APPEND => {
+                let cdw12 = nvm::Cdw12ReadWrite::from(command.cdw12);
+                let count = cdw12.nlb_z() as usize + 1;
+                let byte_count = count << self.block_shift;
+                if byte_count > max_data_transfer_size {
+                    return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+                }
+                let range = DataRange::parse(&self.mem, byte_count, command)?;
+                check_slba()?;
+
+                let buffers = range.io_buffers(&self.mem, false)?;
+                let write = zones.append(slba, count as u64)?;
+                let lba = write.lba;
+
+                tracing::trace!(nsid = self.nsid, slba, lba, count, "zone append");
+
+                match self
+                    .disk
+                    .write_vectored(&buffers.buffers(), lba, cdw12.fua())
+                    .await
+                {
+                    Ok(()) => zones.commit_write(&write, cdw12.fua()).await?,
+                    Err(err) => {
+                        zones.abort_write(write).await;
+                        return Err(map_disk_error(err));
+                    }
+                }
+                Ok(CommandResult::new(
+                    spec::Status::SUCCESS,
+                    [lba as u32, (lba >> 32) as u32],
+                ))
+            }
+            zns::ZnsOpcode::ZONE_MANAGEMENT_SEND => {
+                let cdw13 = zns::Cdw13ZoneManagementSend::from(command.cdw13);
+                let action = zns::ZoneSendAction(cdw13.zsa());
+                tracing::trace!(nsid = self.nsid, slba, ?action, ?cdw13, "zone send");
+                let zslba = if cdw13.select_all() {
+                    None
+                } else {
+                    check_slba()?;
+                    if slba % zones.zone_size != 0 {
+                        return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+                    }
+                    Some(slba)
+                };
+                let reset = zones.send(action, zslba).await?;
+                if !self.disk.is_read_only() {
+                    for zslba in reset {
+                        self.disk
+                            .unmap(zslba, zones.zone_size, false)
+                            .await
+                            .map_err(map_disk_error)?;
+                    }
+                }
+                Ok(Default::default())
+            }
+            zns::ZnsOpcode::ZONE_MANAGEMENT_RECEIVE => {
+                let cdw12 = zns::Cdw12ZoneManagementReceive::from(command.cdw12);
+                let cdw13 = zns::Cdw13ZoneManagementReceive::from(command.cdw13);
+                // The controller does not support zone descriptor extensions,
+                // so only the basic report is supported.
+                if zns::ZoneReceiveAction(cdw13.zra()) != zns::ZoneReceiveAction::REPORT_ZONES {
+                    return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+                }
+                let len = (cdw12.numd_z() as usize + 1) * 4;
+                if len > max_data_transfer_size {
+                    return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+                }
+                let range = DataRange::parse(&self.mem, len, command)?;
+                check_slba()?;
+
+                let header_len = size_of::<zns::ZoneReportHeader>();
+                let max = len.saturating_sub(header_len) / size_of::<zns::ZoneDescriptor>();
+                let (nrz, descriptors) = zones.report(
+                    slba,
+                    zns::ZoneReportFilter(cdw13.zrasf()),
+                    max,
+                    cdw13.partial(),
+                )?;
+                tracing::trace!(nsid = self.nsid, slba, ?cdw13, nrz, "report zones");
+
+                let header = zns::ZoneReportHeader {
+                    nrz,
+                    ..FromZeros::new_zeroed()
+                };
+                let mut report = vec![0; len.max(header_len)];
+                report[..header_len].copy_from_slice(header.as_bytes());
+                let descriptors = descriptors.as_bytes();
+                report[header_len..header_len + descriptors.len()].copy_from_slice(descriptors);
+                range.write(&self.mem, &report[..len])?;
+                Ok(Default::default())
+            }
+            opcode => {
+                tracelimit::warn_ratelimited!(nsid = self.nsid, ?opcode, "unsupported zns opcode");
+                Err(spec::Status::INVALID_COMMAND_OPCODE.into())
+            }
+        }
+    }
+}
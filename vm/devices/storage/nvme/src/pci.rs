@@ -94,6 +94,7 @@ fn new() -> Self {
     .with_mqes_z(MAX_QES - 1)
     .with_cqr(true)
     .with_css_nvm(true)
+    .with_multiple_io(true)
     .with_to(!0);
 
 /// The NVMe controller's capabilities.
@@ -324,8 +325,11 @@ fn set_cc(&mut self, cc: spec::Cc) {
             return;
         }
 
-        if cc.css() != 0 {
-            tracelimit::warn_ratelimited!("This implementation only supports the NVM command set.");
+        if !matches!(
+            spec::CommandSetSelected(cc.css()),
+            spec::CommandSetSelected::NVM | spec::CommandSetSelected::ALL_SUPPORTED_IO
+        ) {
+            tracelimit::warn_ratelimited!("Unsupported command set selection.");
             self.fatal_error();
             return;
         }
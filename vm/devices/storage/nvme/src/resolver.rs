@@ -7,6 +7,8 @@
 use crate::NvmeController;
 use crate::NvmeControllerCaps;
 use crate::NvmeControllerClient;
+use crate::ZoneFault;
+use crate::ZonedNamespaceConfig;
 use anyhow::Context;
 use async_trait::async_trait;
 use disk_backend::resolve::ResolveDiskParameters;
@@ -14,6 +16,8 @@
 use nvme_resources::NamespaceDefinition;
 use nvme_resources::NvmeControllerHandle;
 use nvme_resources::NvmeControllerRequest;
+use nvme_resources::ZoneFaultDefinition;
+use nvme_resources::ZonedNamespaceDefinition;
 use pal_async::task::Spawn;
 use pci_resources::ResolvePciDeviceHandleParams;
 use pci_resources::ResolvedPciDevice;
@@ -45,6 +49,12 @@ pub enum Error {
     },
     #[error(transparent)]
     AddNamespace(AddNamespaceError),
+    #[error("failed to resolve pool disk {index}")]
+    PoolDiskResolve {
+        index: usize,
+        #[source]
+        source: ResolveError,
+    },
 }
 
 #[async_trait]
@@ -72,6 +82,7 @@ async fn resolve(
             nsid,
             read_only,
             disk,
+            zoned,
         } in resource.namespaces
         {
             let disk = resolver
@@ -84,13 +95,28 @@ async fn resolve(
                 )
                 .await
                 .map_err(|source| Error::NamespaceResolve { nsid, source })?;
-            controller
-                .client()
-                .add_namespace(nsid, disk.0)
+            let zoned = resolve_zoned(resolver, input.driver_source, read_only, zoned)
+                .await
+                .map_err(|source| Error::NamespaceResolve { nsid, source })?;
+            add_namespace(&controller.client(), nsid, disk.0, zoned)
                 .await
                 .map_err(Error::AddNamespace)?;
         }
 
+        for (index, disk) in resource.disk_pool.into_iter().enumerate() {
+            let disk = resolver
+                .resolve(
+                    disk,
+                    ResolveDiskParameters {
+                        read_only: false,
+                        driver_source: input.driver_source,
+                    },
+                )
+                .await
+                .map_err(|source| Error::PoolDiskResolve { index, source })?;
+            controller.client().add_pool_disk(disk.0).await;
+        }
+
         if let Some(requests) = resource.requests {
             let driver = input.driver_source.simple();
             driver
@@ -124,6 +150,7 @@ async fn handle_requests(
                                nsid,
                                read_only,
                                disk,
+                               zoned,
                            }| {
                         let disk = resolver
                             .resolve(
@@ -135,9 +162,11 @@ async fn handle_requests(
                             )
                             .await
                             .context("failed to resolve disk")?;
+                        let zoned = resolve_zoned(&resolver, &driver_source, read_only, zoned)
+                            .await
+                            .context("failed to resolve zone state disk")?;
 
-                        client
-                            .add_namespace(nsid, disk.0)
+                        add_namespace(&client, nsid, disk.0, zoned)
                             .await
                             .context("failed to add namespace")?;
 
@@ -156,6 +185,67 @@ async fn handle_requests(
                 })
                 .await
             }
+            NvmeControllerRequest::InjectZoneFault(rpc) => {
+                rpc.handle_failable(async |ZoneFaultDefinition { nsid, zslba, fault }| {
+                    let fault = match fault {
+                        nvme_resources::ZoneFault::ReadOnly => ZoneFault::ReadOnly,
+                        nvme_resources::ZoneFault::Offline => ZoneFault::Offline,
+                    };
+                    client.inject_zone_fault(nsid, zslba, fault).await
+                })
+                .await
+            }
         }
     }
 }
+
+/// Resolves the zone state disk of a zoned namespace definition.
+async fn resolve_zoned(
+    resolver: &ResourceResolver,
+    driver_source: &VmTaskDriverSource,
+    read_only: bool,
+    zoned: Option<ZonedNamespaceDefinition>,
+) -> Result<Option<ZonedNamespaceConfig>, ResolveError> {
+    let Some(ZonedNamespaceDefinition {
+        zone_size,
+        max_open_zones,
+        max_active_zones,
+        state_disk,
+    }) = zoned
+    else {
+        return Ok(None);
+    };
+    let state_disk = match state_disk {
+        Some(disk) => Some(
+            resolver
+                .resolve(
+                    disk,
+                    ResolveDiskParameters {
+                        read_only,
+                        driver_source,
+                    },
+                )
+                .await?
+                .0,
+        ),
+        None => None,
+    };
+    Ok(Some(ZonedNamespaceConfig {
+        zone_size,
+        max_open_zones,
+        max_active_zones,
+        state_disk,
+    }))
+}
+
+async fn add_namespace(
+    client: &NvmeControllerClient,
+    nsid: u32,
+    disk: disk_backend::Disk,
+    zoned: Option<ZonedNamespaceConfig>,
+) -> Result<(), AddNamespaceError> {
+    match zoned {
+        Some(config) => client.add_zoned_namespace(nsid, disk, config).await,
+        None => client.add_namespace(nsid, disk).await,
+    }
+}
@@ -0,0 +1,198 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Types for parsing NVMe SGL (Scatter Gather List) descriptors.
+
+use crate::PAGE_SIZE;
+use crate::PAGE_SIZE64;
+use crate::error::NvmeError;
+use crate::spec;
+use guestmem::GuestMemory;
+use guestmem::ranges::PagedRange;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// The maximum number of descriptors the controller will walk for a single
+/// command, to bound the work a guest can cause with a long segment chain.
+const MAX_SGL_DESCRIPTORS: usize = 1024;
+
+/// A guest memory range described by an SGL.
+pub struct SglRange {
+    len: usize,
+    blocks: Vec<DataBlock>,
+    /// The range as page numbers and an offset into the first page, if the
+    /// data blocks are laid out so that they can be described by a
+    /// [`PagedRange`].
+    paged: Option<(usize, Vec<u64>)>,
+}
+
+struct DataBlock {
+    gpa: u64,
+    len: usize,
+}
+
+#[derive(PartialEq)]
+enum SegmentKind {
+    /// The single descriptor in the command's data pointer.
+    Command,
+    Segment,
+    LastSegment,
+}
+
+impl SglRange {
+    /// Parses an SGL for memory of `len` bytes, starting with the descriptor
+    /// in `dptr`.
+    ///
+    /// The data blocks must describe exactly `len` bytes, since the
+    /// controller does not report support for SGLs longer than the data.
+    pub fn parse(mem: &GuestMemory, len: usize, dptr: [u64; 2]) -> Result<Self, NvmeError> {
+        let first = spec::SglDescriptor::read_from_bytes(dptr.as_bytes()).unwrap();
+        let mut segment = vec![first];
+        let mut kind = SegmentKind::Command;
+        let mut blocks = Vec::new();
+        let mut total = 0usize;
+        let mut descriptor_count = 1;
+        loop {
+            let mut next = None;
+            for (i, desc) in segment.iter().enumerate() {
+                let id = desc.sgl_identifier;
+                if spec::SglDescriptorSubType(id.sub_type()) != spec::SglDescriptorSubType::ADDRESS
+                {
+                    return Err(spec::Status::SGL_DESCRIPTOR_TYPE_INVALID.into());
+                }
+                match spec::SglDescriptorType(id.descriptor_type()) {
+                    spec::SglDescriptorType::DATA_BLOCK => {
+                        let block_len = desc.length as usize;
+                        total = total
+                            .checked_add(block_len)
+                            .filter(|&total| total <= len)
+                            .ok_or(spec::Status::DATA_SGL_LENGTH_INVALID)?;
+                        if block_len != 0 {
+                            blocks.push(DataBlock {
+                                gpa: desc.address,
+                                len: block_len,
+                            });
+                        }
+                    }
+                    ty @ (spec::SglDescriptorType::SEGMENT
+                    | spec::SglDescriptorType::LAST_SEGMENT) => {
+                        // A segment descriptor must be the last descriptor of
+                        // its segment, and the last segment cannot chain to
+                        // another one.
+                        if i != segment.len() - 1
+                            || kind == SegmentKind::LastSegment
+                            || desc.length == 0
+                            || desc.length as usize % size_of::<spec::SglDescriptor>() != 0
+                        {
+                            return Err(spec::Status::INVALID_SGL_SEGMENT_DESCRIPTOR.into());
+                        }
+                        next = Some((ty, *desc));
+                    }
+                    ty => {
+                        tracelimit::warn_ratelimited!(?ty, "unsupported sgl descriptor type");
+                        return Err(spec::Status::SGL_DESCRIPTOR_TYPE_INVALID.into());
+                    }
+                }
+            }
+
+            let Some((ty, desc)) = next else {
+                // Only the last segment may end without chaining to another.
+                if kind == SegmentKind::Segment {
+                    return Err(spec::Status::INVALID_SGL_SEGMENT_DESCRIPTOR.into());
+                }
+                break;
+            };
+
+            let count = desc.length as usize / size_of::<spec::SglDescriptor>();
+            descriptor_count += count;
+            if descriptor_count > MAX_SGL_DESCRIPTORS {
+                return Err(spec::Status::INVALID_NUMBER_OF_SGL_DESCRIPTORS.into());
+            }
+            segment = vec![spec::SglDescriptor::new_zeroed(); count];
+            mem.read_at(desc.address, segment.as_mut_bytes())
+                .map_err(|err| NvmeError::new(spec::Status::DATA_TRANSFER_ERROR, err))?;
+            kind = if ty == spec::SglDescriptorType::LAST_SEGMENT {
+                SegmentKind::LastSegment
+            } else {
+                SegmentKind::Segment
+            };
+        }
+
+        if total != len {
+            return Err(spec::Status::DATA_SGL_LENGTH_INVALID.into());
+        }
+
+        let paged = paged_layout(&blocks);
+        Ok(Self { len, blocks, paged })
+    }
+
+    /// Returns the length of the range in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Returns the range as a [`PagedRange`], if the data blocks can be
+    /// described as one.
+    pub fn range(&self) -> Option<PagedRange<'_>> {
+        let (offset, gpns) = self.paged.as_ref()?;
+        Some(PagedRange::new(*offset, self.len, gpns).unwrap())
+    }
+
+    /// Calls `f` with the guest address and the offset and length within the
+    /// range of each data block covering the first `len` bytes.
+    fn for_each_block(
+        &self,
+        len: usize,
+        mut f: impl FnMut(u64, usize, usize) -> Result<(), guestmem::GuestMemoryError>,
+    ) -> Result<(), NvmeError> {
+        let mut offset = 0;
+        for block in &self.blocks {
+            if offset == len {
+                break;
+            }
+            let n = block.len.min(len - offset);
+            f(block.gpa, offset, n)
+                .map_err(|err| NvmeError::new(spec::Status::DATA_TRANSFER_ERROR, err))?;
+            offset += n;
+        }
+        Ok(())
+    }
+
+    /// Reads from the range.
+    pub fn read(&self, mem: &GuestMemory, buf: &mut [u8]) -> Result<(), NvmeError> {
+        self.for_each_block(buf.len(), |gpa, offset, len| {
+            mem.read_at(gpa, &mut buf[offset..offset + len])
+        })
+    }
+
+    /// Writes to the range.
+    pub fn write(&self, mem: &GuestMemory, buf: &[u8]) -> Result<(), NvmeError> {
+        self.for_each_block(buf.len(), |gpa, offset, len| {
+            mem.write_at(gpa, &buf[offset..offset + len])
+        })
+    }
+
+    /// Writes zeroes to the range.
+    pub fn zero(&self, mem: &GuestMemory, len: usize) -> Result<(), NvmeError> {
+        self.for_each_block(len, |gpa, _, len| mem.fill_at(gpa, 0, len))
+    }
+}
+
+/// Returns the page offset and page numbers describing `blocks`, if every
+/// block but the first starts on a page boundary and every block but the last
+/// ends on one.
+fn paged_layout(blocks: &[DataBlock]) -> Option<(usize, Vec<u64>)> {
+    let first = blocks.first()?;
+    let mut gpns = Vec::new();
+    for (i, block) in blocks.iter().enumerate() {
+        let end = block.gpa.checked_add(block.len as u64)?;
+        if (i != 0 && block.gpa % PAGE_SIZE64 != 0)
+            || (i != blocks.len() - 1 && end % PAGE_SIZE64 != 0)
+        {
+            return None;
+        }
+        gpns.extend(block.gpa / PAGE_SIZE64..end.div_ceil(PAGE_SIZE64));
+    }
+    Some((first.gpa as usize % PAGE_SIZE, gpns))
+}
@@ -8,9 +8,13 @@
 use crate::NvmeController;
 use crate::NvmeControllerCaps;
 use crate::PAGE_SIZE64;
+use crate::ZoneFault;
+use crate::ZoneStateError;
+use crate::ZonedNamespaceConfig;
 use crate::prp::PrpRange;
 use crate::spec;
 use crate::spec::nvm;
+use crate::spec::zns;
 use crate::tests::test_helpers::read_completion_from_queue;
 use crate::tests::test_helpers::test_memory;
 use crate::tests::test_helpers::write_command_to_queue;
@@ -27,9 +31,11 @@
 use pci_core::dma::DmaTarget;
 use pci_core::msi::MsiConnection;
 use pci_core::test_helpers::TestPciInterruptController;
+use scsi_buffers::OwnedRequestBuffers;
 use user_driver::backoff::Backoff;
 use vmcore::vm_task::SingleDriverBackend;
 use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
 use zerocopy::FromZeros;
 use zerocopy::IntoBytes;
 
@@ -237,7 +243,7 @@ async fn test_basic_registers(driver: DefaultDriver) {
     assert_eq!(dword, 0xFF0100FF);
     let mut qword = 0u64;
     nvmec.read_bar0(0, qword.as_mut_bytes()).unwrap();
-    assert_eq!(qword, 0x20FF0100FF);
+    assert_eq!(qword, 0x820FF0100FF);
     nvmec.read_bar0(8, dword.as_mut_bytes()).unwrap();
     assert_eq!(dword, 0x20000);
 
@@ -1499,3 +1505,1041 @@ async fn test_get_supported_log_pages(driver: DefaultDriver) {
         );
     }
 }
+
+fn identify_command(cns: spec::Cns, nsid: u32, data_gpa: u64) -> spec::Command {
+    let mut command = spec::Command::new_zeroed();
+    command.cdw0.set_opcode(spec::AdminOpcode::IDENTIFY.0);
+    command.cdw10 = spec::Cdw10Identify::new().with_cns(cns.0).into();
+    command.nsid = nsid;
+    command.dptr[0] = data_gpa;
+    command
+}
+
+/// Namespaces created with Namespace Management come from the host's disk
+/// pool, start out detached, and return their disk to the pool when deleted.
+#[async_test]
+async fn test_namespace_management(driver: DefaultDriver) {
+    let acq = PrpRange::new(vec![0], 0, PAGE_SIZE64).unwrap();
+    let asq = PrpRange::new(vec![0x1000], 0, PAGE_SIZE64).unwrap();
+    let gm = test_memory();
+    let int_controller = TestPciInterruptController::new();
+
+    let mut nvmec = instantiate_and_build_admin_queue(
+        &acq,
+        64,
+        &asq,
+        64,
+        true,
+        Some(&int_controller),
+        driver.clone(),
+        &gm,
+    )
+    .await;
+
+    nvmec
+        .client()
+        .add_pool_disk(ram_disk(1 << 20, false).unwrap())
+        .await;
+
+    let id = nvm::IdentifyNamespace {
+        nsze: 2048,
+        ncap: 2048,
+        ..FromZeros::new_zeroed()
+    };
+    gm.write_plain(0x8000, &id).unwrap();
+    let mut create = spec::Command::new_zeroed();
+    create
+        .cdw0
+        .set_opcode(spec::AdminOpcode::NAMESPACE_MANAGEMENT.0);
+    create.cdw10 = spec::Cdw10NamespaceManagement::new()
+        .with_sel(spec::NamespaceManagementSelect::CREATE.0)
+        .into();
+    create.dptr[0] = 0x8000;
+
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        0,
+        &create,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    assert_eq!(cqe.dw0, 1, "the lowest free NSID should be allocated");
+
+    // The only pool disk is in use.
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        1,
+        &create,
+    )
+    .await;
+    assert_eq!(
+        cqe.status.status(),
+        spec::Status::NAMESPACE_INSUFFICIENT_CAPACITY.0
+    );
+
+    // The new namespace is allocated but not active.
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        2,
+        &identify_command(spec::Cns::ALLOCATED_NAMESPACE_LIST, 0, 0x9000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    assert_eq!(gm.read_plain::<[u32; 2]>(0x9000).unwrap(), [1, 0]);
+
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        3,
+        &identify_command(spec::Cns::ACTIVE_NAMESPACES, 0, 0xa000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    assert_eq!(gm.read_plain::<u32>(0xa000).unwrap(), 0);
+
+    // Attach the namespace to this controller.
+    gm.write_plain::<[u16; 2]>(0xb000, &[1, 0]).unwrap();
+    let mut attach = spec::Command::new_zeroed();
+    attach
+        .cdw0
+        .set_opcode(spec::AdminOpcode::NAMESPACE_ATTACHMENT.0);
+    attach.cdw10 = spec::Cdw10NamespaceAttachment::new()
+        .with_sel(spec::NamespaceAttachmentSelect::ATTACH.0)
+        .into();
+    attach.nsid = 1;
+    attach.dptr[0] = 0xb000;
+
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        4,
+        &attach,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        5,
+        &attach,
+    )
+    .await;
+    assert_eq!(
+        cqe.status.status(),
+        spec::Status::NAMESPACE_ALREADY_ATTACHED.0
+    );
+
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        6,
+        &identify_command(spec::Cns::ACTIVE_NAMESPACES, 0, 0xa000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    assert_eq!(gm.read_plain::<[u32; 2]>(0xa000).unwrap(), [1, 0]);
+
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        7,
+        &identify_command(spec::Cns::CONTROLLER_LIST_OF_NSID, 1, 0xc000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    assert_eq!(gm.read_plain::<[u16; 3]>(0xc000).unwrap(), [1, 0, 0]);
+
+    // Deleting the attached namespace detaches it and frees its disk.
+    let mut delete = spec::Command::new_zeroed();
+    delete
+        .cdw0
+        .set_opcode(spec::AdminOpcode::NAMESPACE_MANAGEMENT.0);
+    delete.cdw10 = spec::Cdw10NamespaceManagement::new()
+        .with_sel(spec::NamespaceManagementSelect::DELETE.0)
+        .into();
+    delete.nsid = 1;
+
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        8,
+        &delete,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        9,
+        &identify_command(spec::Cns::ALLOCATED_NAMESPACE_LIST, 0, 0x9000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    assert_eq!(gm.read_plain::<u32>(0x9000).unwrap(), 0);
+
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        10,
+        &create,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    assert_eq!(cqe.dw0, 1);
+}
+
+/// Format NVM only supports LBA format 0, and implements a user data erase by
+/// unmapping the disk.
+#[async_test]
+async fn test_format_nvm(driver: DefaultDriver) {
+    let acq = PrpRange::new(vec![0], 0, PAGE_SIZE64).unwrap();
+    let asq = PrpRange::new(vec![0x1000], 0, PAGE_SIZE64).unwrap();
+    let gm = test_memory();
+    let int_controller = TestPciInterruptController::new();
+
+    let mut nvmec = instantiate_and_build_admin_queue(
+        &acq,
+        64,
+        &asq,
+        64,
+        true,
+        Some(&int_controller),
+        driver.clone(),
+        &gm,
+    )
+    .await;
+
+    let disk = ram_disk(1 << 20, false).unwrap();
+    let mem = GuestMemory::allocate(512);
+    mem.write_at(0, &[0xa5; 512]).unwrap();
+    disk.write_vectored(
+        &OwnedRequestBuffers::linear(0, 512, false).buffer(&mem),
+        0,
+        false,
+    )
+    .await
+    .unwrap();
+    nvmec.client().add_namespace(1, disk.clone()).await.unwrap();
+
+    let mut format = spec::Command::new_zeroed();
+    format.cdw0.set_opcode(spec::AdminOpcode::FORMAT_NVM.0);
+    format.nsid = 1;
+
+    format.cdw10 = spec::Cdw10FormatNvm::new()
+        .with_ses(spec::SecureEraseSetting::CRYPTOGRAPHIC_ERASE.0)
+        .into();
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        0,
+        &format,
+    )
+    .await;
+    assert_eq!(
+        cqe.status.status(),
+        spec::Status::INVALID_FIELD_IN_COMMAND.0
+    );
+
+    format.cdw10 = spec::Cdw10FormatNvm::new().with_lbafl(1).into();
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        1,
+        &format,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::INVALID_FORMAT.0);
+
+    format.cdw10 = spec::Cdw10FormatNvm::new()
+        .with_ses(spec::SecureEraseSetting::USER_DATA_ERASE.0)
+        .into();
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        2,
+        &format,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+
+    disk.read_vectored(&OwnedRequestBuffers::linear(0, 512, true).buffer(&mem), 0)
+        .await
+        .unwrap();
+    assert_eq!(mem.read_plain::<[u8; 512]>(0).unwrap(), [0; 512]);
+}
+
+fn sgl_descriptor(ty: spec::SglDescriptorType, address: u64, length: u32) -> spec::SglDescriptor {
+    spec::SglDescriptor {
+        address,
+        length,
+        rsvd: [0; 3],
+        sgl_identifier: spec::SglIdentifier::new().with_descriptor_type(ty.0),
+    }
+}
+
+fn sgl_io_command(
+    opcode: nvm::NvmOpcode,
+    cid: u16,
+    lba: u32,
+    block_count: u16,
+    desc: spec::SglDescriptor,
+) -> spec::Command {
+    let mut command = spec::Command::new_zeroed();
+    command.cdw0.set_opcode(opcode.0);
+    command.cdw0.set_cid(cid);
+    command.cdw0.set_psdt(spec::Psdt::SGL_MPTR_CONTIGUOUS.0);
+    command.nsid = 1;
+    command.cdw10 = nvm::Cdw10ReadWrite::new().with_sbla_low(lba).into();
+    command.cdw12 = nvm::Cdw12ReadWrite::new()
+        .with_nlb_z(block_count - 1)
+        .into();
+    command.dptr = <[u64; 2]>::read_from_bytes(desc.as_bytes()).unwrap();
+    command
+}
+
+/// I/O commands can describe their data with SGLs, including data blocks in
+/// chained segments that do not line up with page boundaries.
+#[async_test]
+async fn test_sgl_read_write(driver: DefaultDriver) {
+    let admin_cq_buf = PrpRange::new(vec![0], 0, PAGE_SIZE64).unwrap();
+    let admin_sq_buf = PrpRange::new(vec![0x1000], 0, PAGE_SIZE64).unwrap();
+    let gm = test_memory();
+    let int_controller = TestPciInterruptController::new();
+
+    let mut nvmec = instantiate_and_build_admin_queue(
+        &admin_cq_buf,
+        64,
+        &admin_sq_buf,
+        64,
+        true,
+        Some(&int_controller),
+        driver.clone(),
+        &gm,
+    )
+    .await;
+    nvmec
+        .client()
+        .add_namespace(1, ram_disk(1 << 20, false).unwrap())
+        .await
+        .unwrap();
+
+    write_msix_table_entry(&mut nvmec, 1, 0xfeed0000, 0x2222, false);
+
+    let io_cq_gpa: u64 = 0x4000;
+    let io_sq_gpa: u64 = 0x5000;
+    create_io_queue_pair(
+        &mut nvmec,
+        &gm,
+        &admin_cq_buf,
+        &admin_sq_buf,
+        &int_controller,
+        driver.clone(),
+        0,
+        /* qid = */ 1,
+        io_cq_gpa,
+        io_sq_gpa,
+        /* cq_qsize_z = */ 16,
+        /* sq_qsize_z = */ 16,
+        /* cq_iv = */ 1,
+    )
+    .await;
+    let io_sq_buf = PrpRange::new(vec![io_sq_gpa], 0, PAGE_SIZE64).unwrap();
+    let io_cq_buf = PrpRange::new(vec![io_cq_gpa], 0, PAGE_SIZE64).unwrap();
+
+    // Write two blocks from a last segment holding two data blocks, one of
+    // which starts mid-page.
+    gm.write_at(0xb100, &[0x11; 512]).unwrap();
+    gm.write_at(0xc000, &[0x22; 512]).unwrap();
+    gm.write_plain(
+        0xa000,
+        &[
+            sgl_descriptor(spec::SglDescriptorType::DATA_BLOCK, 0xb100, 512),
+            sgl_descriptor(spec::SglDescriptorType::DATA_BLOCK, 0xc000, 512),
+        ],
+    )
+    .unwrap();
+    let write = sgl_io_command(
+        nvm::NvmOpcode::WRITE,
+        1,
+        4,
+        2,
+        sgl_descriptor(spec::SglDescriptorType::LAST_SEGMENT, 0xa000, 32),
+    );
+    write_command_to_queue(&gm, &io_sq_buf, 0, &write);
+    nvmec.write_bar0(sq_db(1), 1u32.as_bytes()).unwrap();
+    wait_for_msi(driver.clone(), &int_controller, 1000, 0xfeed0000, 0x2222).await;
+    let cqe = read_completion_from_queue(&gm, &io_cq_buf, 0);
+    assert_eq!(cqe.cid, 1);
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+
+    // Read them back through a single data block in the command.
+    let read = sgl_io_command(
+        nvm::NvmOpcode::READ,
+        2,
+        4,
+        2,
+        sgl_descriptor(spec::SglDescriptorType::DATA_BLOCK, 0xd000, 1024),
+    );
+    write_command_to_queue(&gm, &io_sq_buf, 1, &read);
+    nvmec.write_bar0(sq_db(1), 2u32.as_bytes()).unwrap();
+    wait_for_msi(driver.clone(), &int_controller, 1000, 0xfeed0000, 0x2222).await;
+    let cqe = read_completion_from_queue(&gm, &io_cq_buf, 1);
+    assert_eq!(cqe.cid, 2);
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let data = gm.read_plain::<[u8; 1024]>(0xd000).unwrap();
+    assert_eq!(data[..512], [0x11; 512]);
+    assert_eq!(data[512..], [0x22; 512]);
+
+    // The SGL must describe the whole transfer.
+    let read = sgl_io_command(
+        nvm::NvmOpcode::READ,
+        3,
+        4,
+        2,
+        sgl_descriptor(spec::SglDescriptorType::DATA_BLOCK, 0xd000, 512),
+    );
+    write_command_to_queue(&gm, &io_sq_buf, 2, &read);
+    nvmec.write_bar0(sq_db(1), 3u32.as_bytes()).unwrap();
+    wait_for_msi(driver.clone(), &int_controller, 1000, 0xfeed0000, 0x2222).await;
+    let cqe = read_completion_from_queue(&gm, &io_cq_buf, 2);
+    assert_eq!(cqe.cid, 3);
+    assert_eq!(cqe.status.status(), spec::Status::DATA_SGL_LENGTH_INVALID.0);
+}
+
+/// Submits a single I/O command in slot `slot` of I/O queue 1 and returns its
+/// completion.
+async fn submit_io_command(
+    nvmec: &mut NvmeController,
+    gm: &GuestMemory,
+    sq: &PrpRange,
+    cq: &PrpRange,
+    int_controller: &TestPciInterruptController,
+    driver: DefaultDriver,
+    slot: u32,
+    command: &spec::Command,
+) -> spec::Completion {
+    write_command_to_queue(gm, sq, slot as usize, command);
+    nvmec.write_bar0(sq_db(1), (slot + 1).as_bytes()).unwrap();
+    wait_for_msi(driver, int_controller, 1000, 0xfeed0000, 0x2222).await;
+    read_completion_from_queue(gm, cq, slot as usize)
+}
+
+fn zns_io_command(opcode: u8, slba: u64, data_gpa: u64) -> spec::Command {
+    let mut command = spec::Command::new_zeroed();
+    command.cdw0.set_opcode(opcode);
+    command.nsid = 1;
+    command.cdw10 = slba as u32;
+    command.cdw11 = (slba >> 32) as u32;
+    command.dptr[0] = data_gpa;
+    command
+}
+
+/// Zoned namespaces enforce sequential writes within each zone, track zone
+/// states through writes, appends, and zone management commands, and report
+/// host-injected zone faults in the Changed Zone List log page.
+#[async_test]
+async fn test_zoned_namespace(driver: DefaultDriver) {
+    let admin_cq_buf = PrpRange::new(vec![0], 0, PAGE_SIZE64).unwrap();
+    let admin_sq_buf = PrpRange::new(vec![0x1000], 0, PAGE_SIZE64).unwrap();
+    let gm = test_memory();
+    let int_controller = TestPciInterruptController::new();
+
+    let mut nvmec = instantiate_and_build_admin_queue(
+        &admin_cq_buf,
+        64,
+        &admin_sq_buf,
+        64,
+        true,
+        Some(&int_controller),
+        driver.clone(),
+        &gm,
+    )
+    .await;
+
+    // 2048 blocks in 8 zones, at most 2 of them open.
+    nvmec
+        .client()
+        .add_zoned_namespace(
+            1,
+            ram_disk(1 << 20, false).unwrap(),
+            ZonedNamespaceConfig {
+                zone_size: 256,
+                max_open_zones: Some(2),
+                max_active_zones: None,
+                state_disk: None,
+            },
+        )
+        .await
+        .unwrap();
+
+    let mut identify = identify_command(spec::Cns::SPECIFIC_NAMESPACE_IO_COMMAND_SET, 1, 0x8000);
+    identify.cdw11 = spec::Cdw11Identify::new()
+        .with_csi(spec::Csi::ZONED_NAMESPACE.0)
+        .into();
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &admin_sq_buf,
+        &admin_cq_buf,
+        &int_controller,
+        driver.clone(),
+        0,
+        &identify,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let id = gm.read_plain::<zns::IdentifyNamespaceZns>(0x8000).unwrap();
+    assert_eq!(id.lbafe[0].zsze, 256);
+    assert_eq!(id.mor, 1);
+    assert_eq!(id.mar, !0);
+
+    write_msix_table_entry(&mut nvmec, 1, 0xfeed0000, 0x2222, false);
+
+    let io_cq_gpa: u64 = 0x4000;
+    let io_sq_gpa: u64 = 0x5000;
+    let admin_slot = create_io_queue_pair(
+        &mut nvmec,
+        &gm,
+        &admin_cq_buf,
+        &admin_sq_buf,
+        &int_controller,
+        driver.clone(),
+        1,
+        /* qid = */ 1,
+        io_cq_gpa,
+        io_sq_gpa,
+        /* cq_qsize_z = */ 16,
+        /* sq_qsize_z = */ 16,
+        /* cq_iv = */ 1,
+    )
+    .await;
+    let io_sq_buf = PrpRange::new(vec![io_sq_gpa], 0, PAGE_SIZE64).unwrap();
+    let io_cq_buf = PrpRange::new(vec![io_cq_gpa], 0, PAGE_SIZE64).unwrap();
+
+    // Writes must start at the write pointer.
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        0,
+        &zns_io_command(nvm::NvmOpcode::WRITE.0, 0, 0x8000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        1,
+        &zns_io_command(nvm::NvmOpcode::WRITE.0, 4, 0x8000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::ZONE_INVALID_WRITE.0);
+
+    // Zone append writes at the write pointer and returns the LBA written.
+    let mut append = zns_io_command(zns::ZnsOpcode::ZONE_APPEND.0, 256, 0x8000);
+    append.cdw12 = nvm::Cdw12ReadWrite::new().with_nlb_z(1).into();
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        2,
+        &append,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    assert_eq!((cqe.dw0, cqe.dw1), (256, 0));
+
+    // Two zones are now implicitly open, so a third cannot be opened.
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        3,
+        &zns_io_command(nvm::NvmOpcode::WRITE.0, 512, 0x8000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::TOO_MANY_OPEN_ZONES.0);
+
+    // Report the first three zones.
+    let report_len = size_of::<zns::ZoneReportHeader>() + 3 * size_of::<zns::ZoneDescriptor>();
+    let mut report = zns_io_command(zns::ZnsOpcode::ZONE_MANAGEMENT_RECEIVE.0, 0, 0x9000);
+    report.cdw12 = zns::Cdw12ZoneManagementReceive::new()
+        .with_numd_z((report_len / 4 - 1) as u32)
+        .into();
+    report.cdw13 = zns::Cdw13ZoneManagementReceive::new()
+        .with_zra(zns::ZoneReceiveAction::REPORT_ZONES.0)
+        .with_zrasf(zns::ZoneReportFilter::ALL.0)
+        .into();
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        4,
+        &report,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let header = gm.read_plain::<zns::ZoneReportHeader>(0x9000).unwrap();
+    assert_eq!(header.nrz, 8);
+    let zones = gm
+        .read_plain::<[zns::ZoneDescriptor; 3]>(0x9000 + size_of::<zns::ZoneReportHeader>() as u64)
+        .unwrap();
+    let states = zones.map(|zone| (zone.zslba, zone.zs >> 4, zone.wp));
+    assert_eq!(
+        states,
+        [
+            (0, zns::ZoneState::IMPLICITLY_OPENED.0, 1),
+            (256, zns::ZoneState::IMPLICITLY_OPENED.0, 258),
+            (512, zns::ZoneState::EMPTY.0, 512),
+        ]
+    );
+
+    // Resetting a zone empties it and rewinds its write pointer.
+    let mut reset = zns_io_command(zns::ZnsOpcode::ZONE_MANAGEMENT_SEND.0, 0, 0);
+    reset.cdw13 = zns::Cdw13ZoneManagementSend::new()
+        .with_zsa(zns::ZoneSendAction::RESET.0)
+        .into();
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        5,
+        &reset,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        6,
+        &report,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let zone = gm
+        .read_plain::<zns::ZoneDescriptor>(0x9000 + size_of::<zns::ZoneReportHeader>() as u64)
+        .unwrap();
+    assert_eq!((zone.zs >> 4, zone.wp), (zns::ZoneState::EMPTY.0, 0));
+
+    // An injected fault takes the zone offline and is reported in the
+    // Changed Zone List log page.
+    nvmec
+        .client()
+        .inject_zone_fault(1, 768, ZoneFault::Offline)
+        .await
+        .unwrap();
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        7,
+        &zns_io_command(nvm::NvmOpcode::READ.0, 768, 0x8000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::ZONE_IS_OFFLINE.0);
+
+    let mut log = spec::Command::new_zeroed();
+    log.cdw0.set_opcode(spec::AdminOpcode::GET_LOG_PAGE.0);
+    log.nsid = 1;
+    log.cdw10 = spec::Cdw10GetLogPage::new()
+        .with_lid(spec::LogPageIdentifier::CHANGED_ZONE_LIST.0)
+        .with_numdl_z(1023)
+        .into();
+    log.cdw14 = spec::Cdw14GetLogPage::new()
+        .with_csi(spec::Csi::ZONED_NAMESPACE.0)
+        .into();
+    log.dptr[0] = 0xa000;
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &admin_sq_buf,
+        &admin_cq_buf,
+        &int_controller,
+        driver.clone(),
+        admin_slot,
+        &log,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let changed = gm.read_plain::<zns::ChangedZoneList>(0xa000).unwrap();
+    assert_eq!(changed.nz, 1);
+    assert_eq!(changed.zid[0], 768);
+}
+
+/// A zoned write or append whose disk write fails leaves the write pointer
+/// where it was, so that the guest can retry it.
+#[async_test]
+async fn test_zoned_namespace_failed_write(driver: DefaultDriver) {
+    let admin_cq_buf = PrpRange::new(vec![0], 0, PAGE_SIZE64).unwrap();
+    let admin_sq_buf = PrpRange::new(vec![0x1000], 0, PAGE_SIZE64).unwrap();
+    let gm = test_memory();
+    let int_controller = TestPciInterruptController::new();
+
+    let mut nvmec = instantiate_and_build_admin_queue(
+        &admin_cq_buf,
+        64,
+        &admin_sq_buf,
+        64,
+        true,
+        Some(&int_controller),
+        driver.clone(),
+        &gm,
+    )
+    .await;
+
+    nvmec
+        .client()
+        .add_zoned_namespace(
+            1,
+            ram_disk(1 << 20, false).unwrap(),
+            ZonedNamespaceConfig {
+                zone_size: 256,
+                max_open_zones: None,
+                max_active_zones: None,
+                state_disk: None,
+            },
+        )
+        .await
+        .unwrap();
+
+    write_msix_table_entry(&mut nvmec, 1, 0xfeed0000, 0x2222, false);
+
+    let io_cq_gpa: u64 = 0x4000;
+    let io_sq_gpa: u64 = 0x5000;
+    create_io_queue_pair(
+        &mut nvmec,
+        &gm,
+        &admin_cq_buf,
+        &admin_sq_buf,
+        &int_controller,
+        driver.clone(),
+        1,
+        /* qid = */ 1,
+        io_cq_gpa,
+        io_sq_gpa,
+        /* cq_qsize_z = */ 16,
+        /* sq_qsize_z = */ 16,
+        /* cq_iv = */ 1,
+    )
+    .await;
+    let io_sq_buf = PrpRange::new(vec![io_sq_gpa], 0, PAGE_SIZE64).unwrap();
+    let io_cq_buf = PrpRange::new(vec![io_cq_gpa], 0, PAGE_SIZE64).unwrap();
+
+    // The data buffer is outside guest memory, so the disk write fails.
+    let bad_gpa = 0x100000;
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        0,
+        &zns_io_command(nvm::NvmOpcode::WRITE.0, 0, bad_gpa),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::DATA_TRANSFER_ERROR.0);
+
+    // The write pointer did not move, so the write can be retried.
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        1,
+        &zns_io_command(nvm::NvmOpcode::WRITE.0, 0, 0x8000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        2,
+        &zns_io_command(zns::ZnsOpcode::ZONE_APPEND.0, 256, bad_gpa),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::DATA_TRANSFER_ERROR.0);
+
+    // The first zone is open after the successful retry, and the failed
+    // append left the second zone empty.
+    let report_len = size_of::<zns::ZoneReportHeader>() + 2 * size_of::<zns::ZoneDescriptor>();
+    let mut report = zns_io_command(zns::ZnsOpcode::ZONE_MANAGEMENT_RECEIVE.0, 0, 0x9000);
+    report.cdw12 = zns::Cdw12ZoneManagementReceive::new()
+        .with_numd_z((report_len / 4 - 1) as u32)
+        .into();
+    report.cdw13 = zns::Cdw13ZoneManagementReceive::new()
+        .with_zra(zns::ZoneReceiveAction::REPORT_ZONES.0)
+        .with_zrasf(zns::ZoneReportFilter::ALL.0)
+        .with_partial(true)
+        .into();
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        3,
+        &report,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let zones = gm
+        .read_plain::<[zns::ZoneDescriptor; 2]>(0x9000 + size_of::<zns::ZoneReportHeader>() as u64)
+        .unwrap();
+    let states = zones.map(|zone| (zone.zs >> 4, zone.wp));
+    assert_eq!(
+        states,
+        [
+            (zns::ZoneState::IMPLICITLY_OPENED.0, 1),
+            (zns::ZoneState::EMPTY.0, 256),
+        ]
+    );
+}
+
+/// Zone states and write pointers persist in the state disk, so a zoned
+/// namespace added again over the same disks picks up where it left off.
+#[async_test]
+async fn test_zoned_namespace_state_disk(driver: DefaultDriver) {
+    let admin_cq_buf = PrpRange::new(vec![0], 0, PAGE_SIZE64).unwrap();
+    let admin_sq_buf = PrpRange::new(vec![0x1000], 0, PAGE_SIZE64).unwrap();
+    let gm = test_memory();
+    let int_controller = TestPciInterruptController::new();
+
+    let mut nvmec = instantiate_and_build_admin_queue(
+        &admin_cq_buf,
+        64,
+        &admin_sq_buf,
+        64,
+        true,
+        Some(&int_controller),
+        driver.clone(),
+        &gm,
+    )
+    .await;
+
+    let disk = ram_disk(1 << 20, false).unwrap();
+    let state_disk = ram_disk(1 << 16, false).unwrap();
+    let config = ZonedNamespaceConfig {
+        zone_size: 256,
+        max_open_zones: None,
+        max_active_zones: None,
+        state_disk: Some(state_disk.clone()),
+    };
+    nvmec
+        .client()
+        .add_zoned_namespace(1, disk.clone(), config.clone())
+        .await
+        .unwrap();
+
+    write_msix_table_entry(&mut nvmec, 1, 0xfeed0000, 0x2222, false);
+
+    let io_cq_gpa: u64 = 0x4000;
+    let io_sq_gpa: u64 = 0x5000;
+    create_io_queue_pair(
+        &mut nvmec,
+        &gm,
+        &admin_cq_buf,
+        &admin_sq_buf,
+        &int_controller,
+        driver.clone(),
+        1,
+        /* qid = */ 1,
+        io_cq_gpa,
+        io_sq_gpa,
+        /* cq_qsize_z = */ 16,
+        /* sq_qsize_z = */ 16,
+        /* cq_iv = */ 1,
+    )
+    .await;
+    let io_sq_buf = PrpRange::new(vec![io_sq_gpa], 0, PAGE_SIZE64).unwrap();
+    let io_cq_buf = PrpRange::new(vec![io_cq_gpa], 0, PAGE_SIZE64).unwrap();
+
+    // Write a block to the first zone, and fill the second.
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        0,
+        &zns_io_command(nvm::NvmOpcode::WRITE.0, 0, 0x8000),
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let mut finish = zns_io_command(zns::ZnsOpcode::ZONE_MANAGEMENT_SEND.0, 256, 0);
+    finish.cdw13 = zns::Cdw13ZoneManagementSend::new()
+        .with_zsa(zns::ZoneSendAction::FINISH.0)
+        .into();
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        1,
+        &finish,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+
+    assert!(nvmec.client().remove_namespace(1).await);
+    nvmec
+        .client()
+        .add_zoned_namespace(1, disk.clone(), config)
+        .await
+        .unwrap();
+
+    // The open zone comes back closed, with its write pointer intact.
+    let report_len = size_of::<zns::ZoneReportHeader>() + 3 * size_of::<zns::ZoneDescriptor>();
+    let mut report = zns_io_command(zns::ZnsOpcode::ZONE_MANAGEMENT_RECEIVE.0, 0, 0x9000);
+    report.cdw12 = zns::Cdw12ZoneManagementReceive::new()
+        .with_numd_z((report_len / 4 - 1) as u32)
+        .into();
+    report.cdw13 = zns::Cdw13ZoneManagementReceive::new()
+        .with_zra(zns::ZoneReceiveAction::REPORT_ZONES.0)
+        .with_zrasf(zns::ZoneReportFilter::ALL.0)
+        .with_partial(true)
+        .into();
+    let cqe = submit_io_command(
+        &mut nvmec,
+        &gm,
+        &io_sq_buf,
+        &io_cq_buf,
+        &int_controller,
+        driver.clone(),
+        2,
+        &report,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let zones = gm
+        .read_plain::<[zns::ZoneDescriptor; 3]>(0x9000 + size_of::<zns::ZoneReportHeader>() as u64)
+        .unwrap();
+    let states = zones.map(|zone| (zone.zs >> 4, zone.wp));
+    assert_eq!(
+        states,
+        [
+            (zns::ZoneState::CLOSED.0, 1),
+            (zns::ZoneState::FULL.0, 512),
+            (zns::ZoneState::EMPTY.0, 512),
+        ]
+    );
+
+    // The state disk cannot be reused with a different zone layout.
+    assert!(nvmec.client().remove_namespace(1).await);
+    let err = nvmec
+        .client()
+        .add_zoned_namespace(
+            1,
+            disk,
+            ZonedNamespaceConfig {
+                zone_size: 128,
+                max_open_zones: None,
+                max_active_zones: None,
+                state_disk: Some(state_disk),
+            },
+        )
+        .await
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        AddNamespaceError::ZoneState(1, ZoneStateError::LayoutMismatch { .. })
+    ));
+}
@@ -16,7 +16,11 @@
 use crate::VENDOR_ID;
 use crate::error::CommandResult;
 use crate::error::NvmeError;
+use crate::namespace::InjectZoneFaultError;
 use crate::namespace::Namespace;
+use crate::namespace::ZoneFault;
+use crate::namespace::ZoneStateError;
+use crate::namespace::ZonedNamespaceConfig;
 use crate::prp::PrpRange;
 use crate::queue::CompletionQueue;
 use crate::queue::DoorbellMemory;
@@ -36,6 +40,7 @@
 use parking_lot::Mutex;
 use parking_lot::RwLock;
 use std::collections::BTreeMap;
+use std::collections::BTreeSet;
 use std::collections::btree_map;
 use std::future::pending;
 use std::future::poll_fn;
@@ -59,6 +64,8 @@
 const IOCQES: u8 = 4;
 const MAX_ASYNC_EVENT_REQUESTS: u8 = 4; // minimum recommended by spec
 const ERROR_LOG_PAGE_ENTRIES: u8 = 1;
+/// The controller ID. This is the only controller in its NVM subsystem.
+const CONTROLLER_ID: u16 = 0;
 
 #[derive(Inspect)]
 pub struct AdminConfig {
@@ -83,6 +90,16 @@ pub struct AdminHandler {
     config: AdminConfig,
     #[inspect(iter_by_key)]
     namespaces: BTreeMap<u32, Arc<Namespace>>,
+    /// Allocated namespaces that are not attached to the controller.
+    #[inspect(iter_by_key)]
+    detached_namespaces: BTreeMap<u32, Arc<Namespace>>,
+    /// Namespaces created by the guest with Namespace Management. Their disks
+    /// return to the pool when they are deleted.
+    #[inspect(iter_by_index)]
+    created_namespaces: BTreeSet<u32>,
+    /// Disks the guest can create namespaces on.
+    #[inspect(iter_by_index)]
+    disk_pool: Vec<Disk>,
 }
 
 #[derive(Inspect)]
@@ -399,6 +416,12 @@ pub enum AddNamespaceError {
     /// subsystem (see the `NN` field of Identify Controller).
     #[error("namespace id {0} is out of range (must be 1..={MAX_NSID})")]
     OutOfRange(u32),
+    /// The zone configuration is not valid for the disk.
+    #[error("invalid zone configuration for namespace {0}")]
+    InvalidZoneConfig(u32),
+    /// The zone states could not be loaded from the state disk.
+    #[error("failed to load zone states for namespace {0}")]
+    ZoneState(u32, #[source] ZoneStateError),
 }
 
 impl AdminHandler {
@@ -407,26 +430,48 @@ pub fn new(driver: VmTaskDriver, config: AdminConfig) -> Self {
             driver,
             config,
             namespaces: Default::default(),
+            detached_namespaces: Default::default(),
+            created_namespaces: Default::default(),
+            disk_pool: Vec::new(),
         }
     }
 
+    /// Adds a disk that the guest can create a namespace on with the
+    /// Namespace Management command.
+    pub fn add_pool_disk(&mut self, disk: Disk) {
+        self.disk_pool.push(disk);
+    }
+
     pub async fn add_namespace(
         &mut self,
         state: Option<&mut AdminState>,
         nsid: u32,
         disk: Disk,
+        zoned: Option<ZonedNamespaceConfig>,
     ) -> Result<(), AddNamespaceError> {
         if nsid == 0 || nsid > MAX_NSID {
             return Err(AddNamespaceError::OutOfRange(nsid));
         }
-        let namespace = &*match self.namespaces.entry(nsid) {
-            btree_map::Entry::Vacant(entry) => entry.insert(Arc::new(Namespace::new(
-                self.config.mem.clone(),
-                nsid,
-                disk,
-            ))),
-            btree_map::Entry::Occupied(_) => return Err(AddNamespaceError::Conflict(nsid)),
+        if self.detached_namespaces.contains_key(&nsid) {
+            return Err(AddNamespaceError::Conflict(nsid));
+        }
+        let btree_map::Entry::Vacant(entry) = self.namespaces.entry(nsid) else {
+            return Err(AddNamespaceError::Conflict(nsid));
+        };
+        let mem = self.config.mem.clone();
+        let namespace = match zoned {
+            Some(config) => {
+                let namespace = Namespace::new_zoned(mem, nsid, disk, &config)
+                    .ok_or(AddNamespaceError::InvalidZoneConfig(nsid))?;
+                namespace
+                    .load_zones()
+                    .await
+                    .map_err(|err| AddNamespaceError::ZoneState(nsid, err))?;
+                namespace
+            }
+            None => Namespace::new(mem, nsid, disk),
         };
+        let namespace = &*entry.insert(Arc::new(namespace));
 
         if let Some(state) = state {
             state.add_namespace(&self.driver, nsid, namespace).await;
@@ -436,6 +481,10 @@ pub async fn add_namespace(
     }
 
     pub async fn remove_namespace(&mut self, state: Option<&mut AdminState>, nsid: u32) -> bool {
+        self.created_namespaces.remove(&nsid);
+        if self.detached_namespaces.remove(&nsid).is_some() {
+            return true;
+        }
         if self.namespaces.remove(&nsid).is_none() {
             return false;
         }
@@ -447,6 +496,26 @@ pub async fn remove_namespace(&mut self, state: Option<&mut AdminState>, nsid: u
         true
     }
 
+    /// Injects a zone fault into zoned namespace `nsid`, for testing.
+    pub async fn inject_zone_fault(
+        &self,
+        nsid: u32,
+        zslba: u64,
+        fault: ZoneFault,
+    ) -> Result<(), InjectZoneFaultError> {
+        self.allocated_namespace(nsid)
+            .ok_or(InjectZoneFaultError::NamespaceNotFound(nsid))?
+            .inject_zone_fault(zslba, fault)
+            .await
+    }
+
+    /// Returns the namespace `nsid`, whether or not it is attached.
+    fn allocated_namespace(&self, nsid: u32) -> Option<&Arc<Namespace>> {
+        self.namespaces
+            .get(&nsid)
+            .or_else(|| self.detached_namespaces.get(&nsid))
+    }
+
     async fn next_event(&mut self, state: &mut AdminState) -> Result<Event, QueueError> {
         let event = loop {
             // Wait for there to be room for a completion for the next
@@ -524,6 +593,10 @@ async fn process_event(
                 tracing::trace!(?opcode, ?command, "command");
 
                 let result = match opcode {
+                    _ if command.cdw0.psdt() != spec::Psdt::PRP.0 => {
+                        // Admin commands must use PRPs over PCIe.
+                        Err(spec::Status::INVALID_FIELD_IN_COMMAND.into())
+                    }
                     spec::AdminOpcode::IDENTIFY => self
                         .handle_identify(state, &command)
                         .map(|()| Some(Default::default())),
@@ -555,6 +628,18 @@ async fn process_event(
                     spec::AdminOpcode::GET_LOG_PAGE => self
                         .handle_get_log_page(state, &command)
                         .map(|()| Some(Default::default())),
+                    spec::AdminOpcode::NAMESPACE_MANAGEMENT => self
+                        .handle_namespace_management(state, &command)
+                        .await
+                        .map(Some),
+                    spec::AdminOpcode::NAMESPACE_ATTACHMENT => self
+                        .handle_namespace_attachment(state, &command)
+                        .await
+                        .map(|()| Some(Default::default())),
+                    spec::AdminOpcode::FORMAT_NVM => self
+                        .handle_format_nvm(&command)
+                        .await
+                        .map(|()| Some(Default::default())),
                     spec::AdminOpcode::DOORBELL_BUFFER_CONFIG
                         if self.supports_shadow_doorbells(state) =>
                     {
@@ -669,18 +754,89 @@ fn handle_identify(
                     tracing::trace!(nsid = command.nsid, "inactive namespace id");
                 }
             }
+            spec::Cns::ALLOCATED_NAMESPACE_LIST => {
+                if command.nsid >= 0xfffffffe {
+                    return Err(spec::Status::INVALID_NAMESPACE_OR_FORMAT.into());
+                }
+                let mut allocated = self
+                    .namespaces
+                    .keys()
+                    .chain(self.detached_namespaces.keys())
+                    .copied()
+                    .filter(|&ns| ns > command.nsid)
+                    .collect::<Vec<_>>();
+                allocated.sort_unstable();
+                let nsids = <[u32]>::mut_from_bytes(buf).unwrap();
+                for (ns, nsid) in allocated.into_iter().zip(nsids) {
+                    *nsid = ns;
+                }
+            }
+            spec::Cns::ALLOCATED_NAMESPACE => {
+                if command.nsid == 0 || command.nsid > MAX_NSID {
+                    return Err(spec::Status::INVALID_NAMESPACE_OR_FORMAT.into());
+                }
+                if let Some(ns) = self.allocated_namespace(command.nsid) {
+                    ns.identify(buf);
+                } else {
+                    // Unallocated namespace: return a zero-filled structure.
+                    tracing::trace!(nsid = command.nsid, "unallocated namespace id");
+                }
+            }
+            spec::Cns::CONTROLLER_LIST_OF_NSID => {
+                if command.nsid == 0 || command.nsid > MAX_NSID {
+                    return Err(spec::Status::INVALID_NAMESPACE_OR_FORMAT.into());
+                }
+                let attached = self.namespaces.contains_key(&command.nsid);
+                write_controller_list(buf, attached && CONTROLLER_ID >= cdw10.cntid());
+            }
+            spec::Cns::CONTROLLER_LIST_OF_NVM_SUBSYSTEM => {
+                write_controller_list(buf, CONTROLLER_ID >= cdw10.cntid());
+            }
+            spec::Cns::SPECIFIC_NAMESPACE_IO_COMMAND_SET => {
+                if command.nsid == 0 || command.nsid > MAX_NSID {
+                    return Err(spec::Status::INVALID_NAMESPACE_OR_FORMAT.into());
+                }
+                let csi = supported_csi(command)?;
+                if let Some(ns) = self.namespaces.get(&command.nsid) {
+                    ns.identify_io_command_set(csi, buf)?;
+                } else {
+                    // Valid but inactive namespace: return a zero-filled
+                    // structure (the buffer is already zeroed).
+                    tracing::trace!(nsid = command.nsid, "inactive namespace id");
+                }
+            }
             spec::Cns::SPECIFIC_CONTROLLER_IO_COMMAND_SET => {
-                // CSI is in Command Dword 11, bits 31:24. Only the NVM command
-                // set (CSI 0h) is supported, and it defines no I/O Command Set
-                // specific Identify Controller data structure, so return a
-                // zero-filled structure per NVMe Base 2.3 section 5.2.13.2.6.
-                // Any other command set is unsupported.
-                let csi = (command.cdw11 >> 24) as u8;
-                if csi != 0 {
-                    return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+                // The NVM command set defines no I/O Command Set specific
+                // Identify Controller data structure, so return a zero-filled
+                // structure per NVMe Base 2.3 section 5.2.13.2.6. The Zoned
+                // Namespace structure is zero-filled too: a ZASL of zero
+                // limits zone appends only by MDTS.
+                supported_csi(command)?;
+            }
+            spec::Cns::ACTIVE_NAMESPACE_LIST_IO_COMMAND_SET => {
+                if command.nsid >= 0xfffffffe {
+                    return Err(spec::Status::INVALID_NAMESPACE_OR_FORMAT.into());
                 }
-                // The buffer is already zero-filled; fall through to write it
-                // back to the host.
+                let csi = supported_csi(command)?;
+                let nsids = <[u32]>::mut_from_bytes(buf).unwrap();
+                for (ns, nsid) in self
+                    .namespaces
+                    .iter()
+                    .filter(|&(&ns, namespace)| ns > command.nsid && namespace.csi() == csi)
+                    .map(|(&ns, _)| ns)
+                    .zip(nsids)
+                {
+                    *nsid = ns;
+                }
+            }
+            spec::Cns::IO_COMMAND_SET => {
+                // Report a single command set combination, with every
+                // supported command set. This is the combination selected
+                // by the I/O Command Set Profile feature's default index 0.
+                let vectors = <[spec::IoCommandSetVector]>::mut_from_bytes(buf).unwrap();
+                vectors[0] = spec::IoCommandSetVector::new()
+                    .with_nvm(true)
+                    .with_zoned_namespace(true);
             }
             cns => {
                 tracelimit::warn_ratelimited!(?cns, "unsupported cns");
@@ -692,6 +848,14 @@ fn handle_identify(
     }
 
     fn identify_controller(&self, state: &AdminState) -> spec::IdentifyController {
+        let capacity = |disk: &Disk| disk.sector_count() as u128 * disk.sector_size() as u128;
+        let unallocated: u128 = self.disk_pool.iter().map(capacity).sum();
+        let allocated: u128 = self
+            .created_namespaces
+            .iter()
+            .filter_map(|&nsid| self.allocated_namespace(nsid))
+            .map(|ns| capacity(ns.disk()))
+            .sum();
         spec::IdentifyController {
             vid: VENDOR_ID,
             ssvid: VENDOR_ID,
@@ -705,6 +869,7 @@ fn identify_controller(&self, state: &AdminState) -> spec::IdentifyController {
             cqes: spec::QueueEntrySize::new()
                 .with_min(IOCQES)
                 .with_max(IOCQES),
+            cntlid: CONTROLLER_ID,
             frmw: spec::FirmwareUpdates::new().with_ffsro(true).with_nofs(1),
             nn: MAX_NSID,
             ieee: [0x74, 0xe2, 0x8c], // Microsoft
@@ -723,11 +888,179 @@ fn identify_controller(&self, state: &AdminState) -> spec::IdentifyController {
                 .with_broadcast_flush_behavior(spec::BroadcastFlushBehavior::NOT_SUPPORTED.0),
             cntrltype: spec::ControllerType::IO_CONTROLLER,
             oacs: spec::OptionalAdminCommandSupport::new()
+                .with_format_nvm(true)
+                .with_ns_management(true)
                 .with_doorbell_buffer_config(self.supports_shadow_doorbells(state)),
+            tnvmcap: (allocated + unallocated).into(),
+            unvmcap: unallocated.into(),
+            sgls: spec::SglSupport::new().with_supported(spec::SglSupportLevel::NO_ALIGNMENT.0),
             ..FromZeros::new_zeroed()
         }
     }
 
+    async fn handle_namespace_management(
+        &mut self,
+        state: &mut AdminState,
+        command: &spec::Command,
+    ) -> Result<CommandResult, NvmeError> {
+        let cdw10 = spec::Cdw10NamespaceManagement::from(command.cdw10);
+        match spec::NamespaceManagementSelect(cdw10.sel()) {
+            spec::NamespaceManagementSelect::CREATE => {
+                // Only the NVM command set is supported.
+                if cdw10.csi() != 0 {
+                    return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+                }
+                let mut id = spec::nvm::IdentifyNamespace::new_zeroed();
+                PrpRange::parse(&self.config.mem, size_of_val(&id), command.dptr)?
+                    .read(&self.config.mem, id.as_mut_bytes())?;
+                let nsid = self.create_namespace(&id)?;
+                Ok(CommandResult::new(spec::Status::SUCCESS, [nsid, 0]))
+            }
+            spec::NamespaceManagementSelect::DELETE => {
+                let nsids = if command.nsid == !0 {
+                    self.created_namespaces.iter().copied().collect()
+                } else if self.created_namespaces.contains(&command.nsid) {
+                    vec![command.nsid]
+                } else {
+                    return Err(spec::Status::INVALID_NAMESPACE_OR_FORMAT.into());
+                };
+                for nsid in nsids {
+                    self.delete_namespace(state, nsid).await;
+                }
+                Ok(Default::default())
+            }
+            sel => {
+                tracelimit::warn_ratelimited!(?sel, "unsupported namespace management select");
+                Err(spec::Status::INVALID_FIELD_IN_COMMAND.into())
+            }
+        }
+    }
+
+    /// Creates a detached namespace from a pool disk, as specified by the
+    /// host-specified fields of `id`.
+    fn create_namespace(&mut self, id: &spec::nvm::IdentifyNamespace) -> Result<u32, NvmeError> {
+        // Namespaces only support LBA format 0 (the disk's sector size), with
+        // no metadata or protection information.
+        if id.flbas.low_index() != 0
+            || id.flbas.high_index() != 0
+            || id.flbas.inband_metadata()
+            || id.dps != 0
+        {
+            return Err(spec::Status::INVALID_FORMAT.into());
+        }
+        if id.nsze == 0 {
+            return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+        }
+        if id.ncap != id.nsze {
+            return Err(spec::Status::THIN_PROVISIONING_NOT_SUPPORTED.into());
+        }
+        let nsid = (1..=MAX_NSID)
+            .find(|&nsid| self.allocated_namespace(nsid).is_none())
+            .ok_or(spec::Status::NAMESPACE_IDENTIFIER_UNAVAILABLE)?;
+        // Use the smallest disk that is large enough. The namespace spans the
+        // whole disk, so it may be larger than requested.
+        let index = self
+            .disk_pool
+            .iter()
+            .enumerate()
+            .filter(|(_, disk)| disk.sector_count() >= id.nsze)
+            .min_by_key(|(_, disk)| disk.sector_count())
+            .map(|(index, _)| index)
+            .ok_or(spec::Status::NAMESPACE_INSUFFICIENT_CAPACITY)?;
+        let disk = self.disk_pool.remove(index);
+        self.detached_namespaces.insert(
+            nsid,
+            Arc::new(Namespace::new(self.config.mem.clone(), nsid, disk)),
+        );
+        self.created_namespaces.insert(nsid);
+        tracing::info!(nsid, "created namespace");
+        Ok(nsid)
+    }
+
+    /// Deletes a namespace created with Namespace Management, returning its
+    /// disk to the pool.
+    async fn delete_namespace(&mut self, state: &mut AdminState, nsid: u32) {
+        let namespace = if let Some(namespace) = self.namespaces.remove(&nsid) {
+            state.remove_namespace(nsid).await;
+            namespace
+        } else {
+            self.detached_namespaces.remove(&nsid).unwrap()
+        };
+        self.created_namespaces.remove(&nsid);
+        self.disk_pool.push(namespace.disk().clone());
+        tracing::info!(nsid, "deleted namespace");
+    }
+
+    async fn handle_namespace_attachment(
+        &mut self,
+        state: &mut AdminState,
+        command: &spec::Command,
+    ) -> Result<(), NvmeError> {
+        let cdw10 = spec::Cdw10NamespaceAttachment::from(command.cdw10);
+        let attach = match spec::NamespaceAttachmentSelect(cdw10.sel()) {
+            spec::NamespaceAttachmentSelect::ATTACH => true,
+            spec::NamespaceAttachmentSelect::DETACH => false,
+            sel => {
+                tracelimit::warn_ratelimited!(?sel, "unsupported namespace attachment select");
+                return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+            }
+        };
+
+        // The controller list is a count followed by controller IDs. Since
+        // this is the only controller, it must name exactly this one.
+        let mut list = [0u16; 2048];
+        PrpRange::parse(&self.config.mem, size_of_val(&list), command.dptr)?
+            .read(&self.config.mem, list.as_mut_bytes())?;
+        if list[0] != 1 || list[1] != CONTROLLER_ID {
+            return Err(spec::Status::CONTROLLER_LIST_INVALID.into());
+        }
+
+        let nsid = command.nsid;
+        if attach {
+            if self.namespaces.contains_key(&nsid) {
+                return Err(spec::Status::NAMESPACE_ALREADY_ATTACHED.into());
+            }
+            let namespace = self
+                .detached_namespaces
+                .remove(&nsid)
+                .ok_or(spec::Status::INVALID_NAMESPACE_OR_FORMAT)?;
+            state.add_namespace(&self.driver, nsid, &namespace).await;
+            self.namespaces.insert(nsid, namespace);
+        } else {
+            if self.detached_namespaces.contains_key(&nsid) {
+                return Err(spec::Status::NAMESPACE_NOT_ATTACHED.into());
+            }
+            let namespace = self
+                .namespaces
+                .remove(&nsid)
+                .ok_or(spec::Status::INVALID_NAMESPACE_OR_FORMAT)?;
+            state.remove_namespace(nsid).await;
+            self.detached_namespaces.insert(nsid, namespace);
+        }
+        Ok(())
+    }
+
+    async fn handle_format_nvm(&self, command: &spec::Command) -> Result<(), NvmeError> {
+        let cdw10 = spec::Cdw10FormatNvm::from(command.cdw10);
+        // Only LBA format 0, without protection information, is supported.
+        if cdw10.lbafl() != 0 || cdw10.lbafu() != 0 || cdw10.pi() != 0 {
+            return Err(spec::Status::INVALID_FORMAT.into());
+        }
+        let ses = spec::SecureEraseSetting(cdw10.ses());
+        if command.nsid == !0 {
+            for namespace in self.namespaces.values() {
+                namespace.format(ses).await?;
+            }
+        } else {
+            self.namespaces
+                .get(&command.nsid)
+                .ok_or(spec::Status::INVALID_NAMESPACE_OR_FORMAT)?
+                .format(ses)
+                .await?;
+        }
+        Ok(())
+    }
+
     fn handle_set_features(
         &mut self,
         state: &mut AdminState,
@@ -816,6 +1149,14 @@ fn handle_set_features(
                     .ok_or(spec::Status::INVALID_FIELD_IN_COMMAND)?;
                 *cd = cdw11.cd();
             }
+            spec::Feature::IO_COMMAND_SET_PROFILE => {
+                // Only the single combination reported for
+                // `Cns::IO_COMMAND_SET` can be selected.
+                let cdw11 = spec::Cdw11FeatureIoCommandSetProfile::from(command.cdw11);
+                if cdw11.iocsci() != 0 {
+                    return Err(spec::Status::IO_COMMAND_SET_COMBINATION_REJECTED.into());
+                }
+            }
             feature => {
                 tracelimit::warn_ratelimited!(?feature, "unsupported feature");
                 return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
@@ -905,6 +1246,12 @@ async fn handle_get_features(
                     .with_cd(cd)
                     .into();
             }
+            spec::Feature::IO_COMMAND_SET_PROFILE => {
+                // The only combination is always selected.
+                dw[0] = spec::Cdw11FeatureIoCommandSetProfile::new()
+                    .with_iocsci(0)
+                    .into();
+            }
             feature => {
                 tracelimit::warn_ratelimited!(?feature, "unsupported feature");
                 return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
@@ -1133,6 +1480,7 @@ fn handle_get_log_page(
     ) -> Result<(), NvmeError> {
         let cdw10 = spec::Cdw10GetLogPage::from(command.cdw10);
         let cdw11 = spec::Cdw11GetLogPage::from(command.cdw11);
+        let cdw14 = spec::Cdw14GetLogPage::from(command.cdw14);
         let numd =
             ((cdw10.numdl_z() as u32) | ((cdw11.numdu() as u32) << 16)).saturating_add(1) as usize;
         let len = numd * 4;
@@ -1154,6 +1502,9 @@ fn handle_get_log_page(
                 ] {
                     page[lid.0 as usize] = supported.into();
                 }
+                if spec::Csi(cdw14.csi()) == spec::Csi::ZONED_NAMESPACE {
+                    page[spec::LogPageIdentifier::CHANGED_ZONE_LIST.0 as usize] = supported.into();
+                }
                 let bytes = page.as_bytes();
                 prp.write(&self.config.mem, &bytes[..len.min(bytes.len())])?;
             }
@@ -1194,6 +1545,18 @@ fn handle_get_log_page(
                     state.notified_changed_namespaces = false;
                 }
             }
+            spec::LogPageIdentifier::CHANGED_ZONE_LIST
+                if spec::Csi(cdw14.csi()) == spec::Csi::ZONED_NAMESPACE =>
+            {
+                let namespace = self
+                    .namespaces
+                    .get(&command.nsid)
+                    .ok_or(spec::Status::INVALID_NAMESPACE_OR_FORMAT)?;
+                let mut log = spec::zns::ChangedZoneList::new_zeroed();
+                namespace.changed_zone_list(&mut log)?;
+                let bytes = log.as_bytes();
+                prp.write(&self.config.mem, &bytes[..len.min(bytes.len())])?;
+            }
             lid => {
                 tracelimit::warn_ratelimited!(?lid, "unsupported log page");
                 return Err(spec::Status::INVALID_LOG_PAGE.into());
@@ -1234,6 +1597,29 @@ async fn handle_doorbell_buffer_config(
     }
 }
 
+/// Writes a controller list to `buf`, containing this controller if `include`
+/// is true.
+fn write_controller_list(buf: &mut [u8], include: bool) {
+    if include {
+        let list = <[u16]>::mut_from_bytes(buf).unwrap();
+        list[0] = 1;
+        list[1] = CONTROLLER_ID;
+    }
+}
+
+/// Returns the command set identifier of an Identify command, if it is
+/// supported by the controller.
+fn supported_csi(command: &spec::Command) -> Result<spec::Csi, NvmeError> {
+    let csi = spec::Csi(spec::Cdw11Identify::from(command.cdw11).csi());
+    match csi {
+        spec::Csi::NVM | spec::Csi::ZONED_NAMESPACE => Ok(csi),
+        csi => {
+            tracelimit::warn_ratelimited!(?csi, "unsupported csi");
+            Err(spec::Status::INVALID_FIELD_IN_COMMAND.into())
+        }
+    }
+}
+
 impl AsyncRun<AdminState> for AdminHandler {
     async fn run(
         &mut self,
@@ -8,6 +8,9 @@
 use super::admin::AdminConfig;
 use super::admin::AdminHandler;
 use super::admin::AdminState;
+use crate::namespace::InjectZoneFaultError;
+use crate::namespace::ZoneFault;
+use crate::namespace::ZonedNamespaceConfig;
 use crate::queue::DoorbellMemory;
 use crate::queue::InvalidDoorbell;
 use disk_backend::Disk;
@@ -195,7 +198,20 @@ impl NvmeControllerClient {
     /// Adds a namespace.
     pub async fn add_namespace(&self, nsid: u32, disk: Disk) -> Result<(), AddNamespaceError> {
         self.send
-            .call(CoordinatorRequest::AddNamespace, (nsid, disk))
+            .call(CoordinatorRequest::AddNamespace, (nsid, disk, None))
+            .await
+            .unwrap()
+    }
+
+    /// Adds a zoned namespace, which uses the Zoned Namespace Command Set.
+    pub async fn add_zoned_namespace(
+        &self,
+        nsid: u32,
+        disk: Disk,
+        config: ZonedNamespaceConfig,
+    ) -> Result<(), AddNamespaceError> {
+        self.send
+            .call(CoordinatorRequest::AddNamespace, (nsid, disk, Some(config)))
             .await
             .unwrap()
     }
@@ -207,6 +223,30 @@ pub async fn remove_namespace(&self, nsid: u32) -> bool {
             .await
             .unwrap()
     }
+
+    /// Adds a disk to the pool that the guest can create namespaces on with
+    /// the Namespace Management command.
+    pub async fn add_pool_disk(&self, disk: Disk) {
+        self.send
+            .call(CoordinatorRequest::AddPoolDisk, disk)
+            .await
+            .unwrap()
+    }
+
+    /// Moves the zone starting at `zslba` in zoned namespace `nsid` to a
+    /// faulted state, and reports it in the namespace's Changed Zone List log
+    /// page. Used to test guest handling of zone failures.
+    pub async fn inject_zone_fault(
+        &self,
+        nsid: u32,
+        zslba: u64,
+        fault: ZoneFault,
+    ) -> Result<(), InjectZoneFaultError> {
+        self.send
+            .call(CoordinatorRequest::InjectZoneFault, (nsid, zslba, fault))
+            .await
+            .unwrap()
+    }
 }
 
 #[derive(Inspect)]
@@ -220,8 +260,10 @@ struct Coordinator {
 
 enum CoordinatorRequest {
     EnableAdmin(Rpc<EnableAdminParams, ()>),
-    AddNamespace(Rpc<(u32, Disk), Result<(), AddNamespaceError>>),
+    AddNamespace(Rpc<(u32, Disk, Option<ZonedNamespaceConfig>), Result<(), AddNamespaceError>>),
     RemoveNamespace(Rpc<u32, bool>),
+    AddPoolDisk(Rpc<Disk, ()>),
+    InjectZoneFault(Rpc<(u32, u64, ZoneFault), Result<(), InjectZoneFaultError>>),
     Inspect(inspect::Deferred),
     ControllerReset(Rpc<(), ()>),
 }
@@ -280,10 +322,10 @@ enum Event {
                         },
                     ),
                     CoordinatorRequest::AddNamespace(rpc) => {
-                        rpc.handle(async |(nsid, disk)| {
+                        rpc.handle(async |(nsid, disk, zoned)| {
                             let running = self.admin.stop().await;
                             let (admin, state) = self.admin.get_mut();
-                            let r = admin.add_namespace(state, nsid, disk).await;
+                            let r = admin.add_namespace(state, nsid, disk, zoned).await;
                             if running {
                                 self.admin.start();
                             }
@@ -303,6 +345,31 @@ enum Event {
                         })
                         .await
                     }
+                    CoordinatorRequest::AddPoolDisk(rpc) => {
+                        rpc.handle(async |disk| {
+                            let running = self.admin.stop().await;
+                            self.admin.task_mut().add_pool_disk(disk);
+                            if running {
+                                self.admin.start();
+                            }
+                        })
+                        .await
+                    }
+                    CoordinatorRequest::InjectZoneFault(rpc) => {
+                        rpc.handle(async |(nsid, zslba, fault)| {
+                            let running = self.admin.stop().await;
+                            let r = self
+                                .admin
+                                .task()
+                                .inject_zone_fault(nsid, zslba, fault)
+                                .await;
+                            if running {
+                                self.admin.start();
+                            }
+                            r
+                        })
+                        .await
+                    }
                     CoordinatorRequest::ControllerReset(rpc) => {
                         assert!(self.reset.is_none());
                         self.reset = Some(rpc);
@@ -3,9 +3,11 @@
 
 //! Provides an interface to programmatically and deterministically inject faults in the NVMe fault controller.
 
+use crate::ZoneFaultDefinition;
 use mesh::Cell;
 use mesh::MeshPayload;
 use mesh::OneshotSender;
+use mesh::rpc::FailableRpc;
 use mesh::rpc::Rpc;
 use nvme_spec::Command;
 use nvme_spec::Completion;
@@ -154,6 +156,52 @@ pub struct NamespaceFaultConfig {
     pub recv_changed_namespace: mesh::Receiver<NamespaceChange>,
 }
 
+/// A fault config to fail zones of zoned namespaces, as failing media would.
+///
+/// The fault controller listens on the provided channel for
+/// [`ZoneFaultDefinition`]s. Each one moves a zone to the read only or offline
+/// state and records it in the namespace's Changed Zone List log page. The
+/// RPC fails if the namespace is not zoned or the LBA is not the start of a
+/// zone. Like the namespace fault, zone faults are not gated by the
+/// `fault_active` flag.
+///
+/// # Example
+/// Make the second zone of a zoned namespace with 0x1000 block zones read only.
+/// ```no_run
+/// use mesh::CellUpdater;
+/// use mesh::rpc::RpcSend;
+/// use nvme_resources::ZoneFault;
+/// use nvme_resources::ZoneFaultDefinition;
+/// use nvme_resources::fault::FaultConfiguration;
+/// use nvme_resources::fault::ZoneFaultConfig;
+///
+/// pub async fn send_zone_fault() {
+///     let mut fault_start_updater = CellUpdater::new(false);
+///     let (zone_fault_send, zone_fault_recv) = mesh::channel();
+///     let fault_configuration = FaultConfiguration::new(fault_start_updater.cell())
+///         .with_zone_fault(ZoneFaultConfig::new(zone_fault_recv));
+///     // Complete setup, with a zoned namespace 1.
+///
+///     // Inject the fault and wait for it to be applied.
+///     zone_fault_send
+///         .call_failable(
+///             |rpc| rpc,
+///             ZoneFaultDefinition {
+///                 nsid: 1,
+///                 zslba: 0x1000,
+///                 fault: ZoneFault::ReadOnly,
+///             },
+///         )
+///         .await
+///         .unwrap();
+/// }
+/// ```
+#[derive(MeshPayload)]
+pub struct ZoneFaultConfig {
+    /// Receiver for zone faults to inject
+    pub recv_zone_fault: mesh::Receiver<FailableRpc<ZoneFaultDefinition, ()>>,
+}
+
 /// A fault configuration to inject faults into the admin submission and completion queues.
 ///
 /// This struct maintains a mapping from [`CommandMatch`] to [`AdminQueueFaultBehavior`] for
@@ -424,6 +472,9 @@ pub struct FaultConfiguration {
     pub pci_fault: Option<PciFaultConfig>,
     /// Fault for test triggered namespace change notifications
     pub namespace_fault: NamespaceFaultConfig,
+    /// Fault for test triggered zone failures. Option because it needs to be
+    /// extracted by the controller during initialization.
+    pub zone_fault: Option<ZoneFaultConfig>,
     /// Fault to apply to all IO queues
     pub io_fault: Arc<IoQueueFaultConfig>,
     /// Fault to apply to the Hardware Configuration
@@ -441,6 +492,7 @@ pub fn new(fault_active: Cell<bool>) -> Self {
             admin_fault: AdminQueueFaultConfig::new(),
             pci_fault: Some(PciFaultConfig::new()),
             namespace_fault: NamespaceFaultConfig::new(mesh::channel().1),
+            zone_fault: None,
             io_fault: Arc::new(IoQueueFaultConfig::new(fault_active)),
             hardware_config_fault: None,
         }
@@ -470,6 +522,12 @@ pub fn with_namespace_fault(mut self, namespace_fault: NamespaceFaultConfig) ->
         self
     }
 
+    /// Add a zone fault configuration to the fault configuration
+    pub fn with_zone_fault(mut self, zone_fault: ZoneFaultConfig) -> Self {
+        self.zone_fault = Some(zone_fault);
+        self
+    }
+
     /// Add a hardware config fault configuration to the fault configuration
     pub fn with_hardware_config_fault(
         mut self,
@@ -591,6 +649,13 @@ pub fn new(recv_changed_namespace: mesh::Receiver<NamespaceChange>) -> Self {
     }
 }
 
+impl ZoneFaultConfig {
+    /// Creates a new ZoneFaultConfig that receives faults on `recv_zone_fault`.
+    pub fn new(recv_zone_fault: mesh::Receiver<FailableRpc<ZoneFaultDefinition, ()>>) -> Self {
+        Self { recv_zone_fault }
+    }
+}
+
 impl IoQueueFaultConfig {
     /// Create an empty IO queue fault configuration
     pub fn new(fault_active: Cell<bool>) -> Self {
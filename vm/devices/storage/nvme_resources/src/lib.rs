@@ -5,7 +5,7 @@
 //!
 //! [`NvmeControllerHandle`] configures the controller with its initial
 //! namespaces, MSI-X count, and queue limits. [`NvmeControllerRequest`] enables
-//! runtime namespace add/remove.
+//! runtime namespace add/remove and zone fault injection.
 
 #![forbid(unsafe_code)]
 
@@ -31,6 +31,9 @@ pub struct NvmeControllerHandle {
     pub max_io_queues: u16,
     /// The initial set of namespaces.
     pub namespaces: Vec<NamespaceDefinition>,
+    /// Disks that the guest can create namespaces on with the Namespace
+    /// Management command. Each created namespace spans a whole disk.
+    pub disk_pool: Vec<Resource<DiskHandleKind>>,
     /// Runtime request channel for hot add/remove of namespaces.
     pub requests: Option<mesh::Receiver<NvmeControllerRequest>>,
 }
@@ -46,6 +49,28 @@ pub enum NvmeControllerRequest {
     AddNamespace(FailableRpc<NamespaceDefinition, ()>),
     /// Remove a namespace by its NSID.
     RemoveNamespace(FailableRpc<u32, ()>),
+    /// Inject a fault into a zone of a zoned namespace.
+    InjectZoneFault(FailableRpc<ZoneFaultDefinition, ()>),
+}
+
+/// A zone fault to inject into a zoned namespace.
+#[derive(MeshPayload)]
+pub struct ZoneFaultDefinition {
+    /// The namespace ID.
+    pub nsid: u32,
+    /// The start LBA of the zone.
+    pub zslba: u64,
+    /// The state to move the zone to.
+    pub fault: ZoneFault,
+}
+
+/// The faulted state of a zone.
+#[derive(MeshPayload, Debug, Copy, Clone)]
+pub enum ZoneFault {
+    /// The zone becomes read only.
+    ReadOnly,
+    /// The zone goes offline.
+    Offline,
 }
 
 /// A handle to a NVMe fault controller.
@@ -78,4 +103,21 @@ pub struct NamespaceDefinition {
     pub read_only: bool,
     /// The backing disk resource.
     pub disk: Resource<DiskHandleKind>,
+    /// If set, the namespace uses the Zoned Namespace Command Set with this
+    /// zone layout.
+    pub zoned: Option<ZonedNamespaceDefinition>,
+}
+
+/// The zone layout of a zoned namespace.
+#[derive(MeshPayload)]
+pub struct ZonedNamespaceDefinition {
+    /// The size of each zone, in logical blocks.
+    pub zone_size: u64,
+    /// The maximum number of open zones, if limited.
+    pub max_open_zones: Option<u32>,
+    /// The maximum number of active (open or closed) zones, if limited.
+    pub max_active_zones: Option<u32>,
+    /// The disk to persist the zone states and write pointers in, alongside
+    /// the namespace's disk. If `None`, the zone states are not persisted.
+    pub state_disk: Option<Resource<DiskHandleKind>>,
 }
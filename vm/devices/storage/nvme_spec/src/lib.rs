@@ -5,7 +5,8 @@
 //!
 //! Provides bitfield structs, command/completion queue entry formats, status
 //! codes, and register definitions. The [`nvm`] submodule defines the NVM
-//! command set (read, write, flush, DSM, reservations, namespace identification).
+//! command set (read, write, flush, DSM, reservations, namespace identification),
+//! and the [`zns`] submodule defines the Zoned Namespace command set.
 //!
 //! Base 2.0c: <https://nvmexpress.org/wp-content/uploads/NVM-Express-Base-Specification-2.0c-2022.10.04-Ratified.pdf>
 //! PCIe transport 1.0c: <https://nvmexpress.org/wp-content/uploads/NVM-Express-PCIe-Transport-Specification-1.0c-2022.10.03-Ratified.pdf>
@@ -15,6 +16,7 @@
 #![no_std]
 
 pub mod nvm;
+pub mod zns;
 
 use bitfield_struct::bitfield;
 use inspect::Inspect;
@@ -106,6 +108,15 @@ pub struct Cc {
     pub reserved2: u8,
 }
 
+open_enum! {
+    /// The I/O command set selected by [`Cc::css`].
+    pub enum CommandSetSelected: u8 {
+        NVM = 0b000,
+        ALL_SUPPORTED_IO = 0b110,
+        ADMIN_ONLY = 0b111,
+    }
+}
+
 #[derive(Inspect)]
 #[bitfield(u32)]
 pub struct Csts {
@@ -360,6 +371,16 @@ pub enum Status: u16 {
         ATTEMPTED_WRITE_TO_READ_ONLY_RANGE = 0x182,         // Dataset Management, Write, Write Uncorrectable, Write Zeroes
         COMMAND_SIZE_LIMIT_EXCEEDED = 0x183,         // Dataset Management
 
+        // Zoned Namespace command set
+        ZONE_BOUNDARY_ERROR = 0x1b8,
+        ZONE_IS_FULL = 0x1b9,
+        ZONE_IS_READ_ONLY = 0x1ba,
+        ZONE_IS_OFFLINE = 0x1bb,
+        ZONE_INVALID_WRITE = 0x1bc,
+        TOO_MANY_ACTIVE_ZONES = 0x1bd,
+        TOO_MANY_OPEN_ZONES = 0x1be,
+        INVALID_ZONE_STATE_TRANSITION = 0x1bf,
+
         MEDIA_WRITE_FAULT                             = 0x280,
         MEDIA_UNRECOVERED_READ_ERROR                  = 0x281,
         MEDIA_END_TO_END_GUARD_CHECK_ERROR            = 0x282,
@@ -389,6 +410,35 @@ pub struct Cdw10Identify {
     pub cntid: u16,
 }
 
+#[bitfield(u32)]
+pub struct Cdw11Identify {
+    /// CNS specific identifier
+    pub cnssid: u16,
+    _rsvd: u8,
+    /// Command set identifier. See [`Csi`].
+    pub csi: u8,
+}
+
+open_enum! {
+    pub enum Csi: u8 {
+        NVM = 0x0,
+        KEY_VALUE = 0x1,
+        ZONED_NAMESPACE = 0x2,
+    }
+}
+
+/// An I/O command set vector, in the data structure returned for
+/// [`Cns::IO_COMMAND_SET`].
+#[bitfield(u64)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct IoCommandSetVector {
+    pub nvm: bool,
+    pub key_value: bool,
+    pub zoned_namespace: bool,
+    #[bits(61)]
+    _rsvd: u64,
+}
+
 open_enum! {
     pub enum Cns: u8 {
         NAMESPACE = 0x0,
@@ -518,7 +568,7 @@ pub struct IdentifyController {
     pub nn: u32,
     pub oncs: Oncs,
     pub fuses: u16,
-    pub fna: u8,
+    pub fna: Fna,
     pub vwc: VolatileWriteCache,
     pub awun: u16,
     pub awupf: u16,
@@ -526,7 +576,7 @@ pub struct IdentifyController {
     pub nwpc: u8,
     pub acwu: u16,
     pub copy_descriptor_fmt: u16,
-    pub sgls: u32,
+    pub sgls: SglSupport,
     pub mnan: u32,
     #[inspect(display)]
     pub maxdna: U128LE,
@@ -611,6 +661,52 @@ pub struct Oncs {
     _rsvd: u16,
 }
 
+/// Format NVM attributes
+#[derive(Inspect)]
+#[bitfield(u8)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct Fna {
+    /// Format NVM applies to all namespaces rather than a single one.
+    pub format_applies_to_all: bool,
+    /// Secure erase applies to all namespaces rather than a single one.
+    pub secure_erase_applies_to_all: bool,
+    /// Cryptographic erase is supported as part of secure erase.
+    pub crypto_erase_supported: bool,
+    /// Format NVM with a broadcast NSID is not supported.
+    pub broadcast_not_supported: bool,
+    #[bits(4)]
+    _rsvd: u8,
+}
+
+/// SGL support
+#[derive(Inspect)]
+#[bitfield(u32)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct SglSupport {
+    /// SGL support for the NVM command set. See [`SglSupportLevel`].
+    #[bits(2)]
+    pub supported: u8,
+    pub keyed_data_block: bool,
+    #[bits(13)]
+    _rsvd: u16,
+    pub bit_bucket: bool,
+    pub byte_aligned_contiguous_metadata: bool,
+    pub length_larger_than_data: bool,
+    pub mptr_single_descriptor: bool,
+    pub address_as_offset: bool,
+    pub transport_data_block: bool,
+    #[bits(10)]
+    _rsvd2: u16,
+}
+
+open_enum! {
+    pub enum SglSupportLevel: u8 {
+        NOT_SUPPORTED = 0,
+        NO_ALIGNMENT = 1,
+        DWORD_ALIGNMENT = 2,
+    }
+}
+
 open_enum! {
     #[derive(IntoBytes, Immutable, KnownLayout, FromBytes, Inspect)]
     #[inspect(debug)]
@@ -841,6 +937,16 @@ pub struct Cdw11FeatureInterruptVectorConfig {
     _rsvd: u16,
 }
 
+#[bitfield(u32)]
+pub struct Cdw11FeatureIoCommandSetProfile {
+    /// Index of the I/O command set combination to enable, in the data
+    /// structure returned for [`Cns::IO_COMMAND_SET`].
+    #[bits(9)]
+    pub iocsci: u16,
+    #[bits(23)]
+    _rsvd: u32,
+}
+
 #[bitfield(u32)]
 pub struct Cdw10CreateIoQueue {
     pub qid: u16,
@@ -889,6 +995,18 @@ pub struct Cdw11GetLogPage {
     pub lsi: u16,
 }
 
+#[bitfield(u32)]
+pub struct Cdw14GetLogPage {
+    #[bits(7)]
+    pub uuid_index: u8,
+    #[bits(16)]
+    _rsvd: u16,
+    /// Offset type
+    pub ot: bool,
+    /// Command set identifier. See [`Csi`].
+    pub csi: u8,
+}
+
 open_enum! {
     pub enum LogPageIdentifier: u8 {
         SUPPORTED_LOG_PAGES = 0,
@@ -896,6 +1014,7 @@ pub enum LogPageIdentifier: u8 {
         HEALTH_INFORMATION = 2,
         FIRMWARE_SLOT_INFORMATION = 3,
         CHANGED_NAMESPACE_LIST = 4,
+        CHANGED_ZONE_LIST = 0xbf,
     }
 }
 
@@ -946,3 +1065,117 @@ pub enum AsynchronousEventInformationNotice: u8 {
         ENDURANCE_GROUP_EVENT_AGGREGATE_LOG_PAGE_CHANGE = 6,
     }
 }
+
+open_enum! {
+    /// PRP or SGL for Data Transfer (PSDT) in command dword 0.
+    pub enum Psdt: u8 {
+        PRP = 0,
+        SGL_MPTR_CONTIGUOUS = 1,
+        SGL_MPTR_SGL = 2,
+    }
+}
+
+/// An SGL descriptor, as stored in the command's data pointer or in an SGL
+/// segment in guest memory.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct SglDescriptor {
+    pub address: u64,
+    pub length: u32,
+    pub rsvd: [u8; 3],
+    pub sgl_identifier: SglIdentifier,
+}
+
+const _: () = assert!(size_of::<SglDescriptor>() == 16);
+
+#[bitfield(u8)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct SglIdentifier {
+    #[bits(4)]
+    pub sub_type: u8,
+    /// See [`SglDescriptorType`].
+    #[bits(4)]
+    pub descriptor_type: u8,
+}
+
+open_enum! {
+    pub enum SglDescriptorType: u8 {
+        DATA_BLOCK = 0,
+        BIT_BUCKET = 1,
+        SEGMENT = 2,
+        LAST_SEGMENT = 3,
+        KEYED_DATA_BLOCK = 4,
+        TRANSPORT_DATA_BLOCK = 5,
+    }
+}
+
+open_enum! {
+    pub enum SglDescriptorSubType: u8 {
+        ADDRESS = 0,
+        OFFSET = 1,
+    }
+}
+
+#[bitfield(u32)]
+pub struct Cdw10NamespaceManagement {
+    /// See [`NamespaceManagementSelect`].
+    #[bits(4)]
+    pub sel: u8,
+    #[bits(20)]
+    _rsvd: u32,
+    /// Command set identifier
+    pub csi: u8,
+}
+
+open_enum! {
+    pub enum NamespaceManagementSelect: u8 {
+        CREATE = 0,
+        DELETE = 1,
+    }
+}
+
+#[bitfield(u32)]
+pub struct Cdw10NamespaceAttachment {
+    /// See [`NamespaceAttachmentSelect`].
+    #[bits(4)]
+    pub sel: u8,
+    #[bits(28)]
+    _rsvd: u32,
+}
+
+open_enum! {
+    pub enum NamespaceAttachmentSelect: u8 {
+        ATTACH = 0,
+        DETACH = 1,
+    }
+}
+
+#[bitfield(u32)]
+pub struct Cdw10FormatNvm {
+    /// Low bits of the LBA format index.
+    #[bits(4)]
+    pub lbafl: u8,
+    /// Metadata settings
+    pub mset: bool,
+    /// Protection information
+    #[bits(3)]
+    pub pi: u8,
+    /// Protection information location
+    pub pil: bool,
+    /// Secure erase settings. See [`SecureEraseSetting`].
+    #[bits(3)]
+    pub ses: u8,
+    /// High bits of the LBA format index.
+    #[bits(2)]
+    pub lbafu: u8,
+    #[bits(18)]
+    _rsvd: u32,
+}
+
+open_enum! {
+    pub enum SecureEraseSetting: u8 {
+        NONE = 0,
+        USER_DATA_ERASE = 1,
+        CRYPTOGRAPHIC_ERASE = 2,
+    }
+}
@@ -0,0 +1,255 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Zoned Namespace command set definitions
+//!
+//! Zoned Namespace Command Set 1.1d: <https://nvmexpress.org/wp-content/uploads/NVM-Express-Zoned-Namespace-Command-Set-Specification-1.1d-2023.12.28-Ratified.pdf>
+
+use bitfield_struct::bitfield;
+use inspect::Inspect;
+use open_enum::open_enum;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+/// I/O Command Set specific Identify Namespace data structure for the Zoned
+/// Namespace Command Set.
+#[repr(C)]
+#[derive(Debug, IntoBytes, Immutable, KnownLayout, FromBytes, Inspect, Clone)]
+pub struct IdentifyNamespaceZns {
+    /// Zone operation characteristics
+    pub zoc: Zoc,
+    /// Optional zoned command support
+    pub ozcs: Ozcs,
+    /// Maximum active resources. Zero based, or `!0` for no limit.
+    pub mar: u32,
+    /// Maximum open resources. Zero based, or `!0` for no limit.
+    pub mor: u32,
+    /// Reset recommended limit
+    pub rrl: u32,
+    /// Finish recommended limit
+    pub frl: u32,
+    pub rrl1: u32,
+    pub rrl2: u32,
+    pub rrl3: u32,
+    pub frl1: u32,
+    pub frl2: u32,
+    pub frl3: u32,
+    /// Number of ZRWA resources
+    pub numzrwa: u32,
+    /// ZRWA flush granularity
+    pub zrwafg: u16,
+    /// ZRWA size
+    pub zrwasz: u16,
+    /// ZRWA capability
+    pub zrwacap: u8,
+    #[inspect(skip)]
+    pub rsvd: [u8; 2763],
+    /// LBA format extensions, one per LBA format.
+    #[inspect(iter_by_index)]
+    pub lbafe: [Lbafe; 16],
+    #[inspect(skip)]
+    pub vs: [u8; 1024],
+}
+
+static_assertions::assert_eq_size!(IdentifyNamespaceZns, [u8; 4096]);
+
+#[derive(Inspect)]
+#[bitfield(u16)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct Zoc {
+    /// Variable zone capacity
+    pub vzcs: bool,
+    /// Zone active excursions
+    pub zae: bool,
+    #[bits(14)]
+    _rsvd: u16,
+}
+
+#[derive(Inspect)]
+#[bitfield(u16)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct Ozcs {
+    /// Read across zone boundaries
+    pub razb: bool,
+    /// Zone random write area supported
+    pub zrwasup: bool,
+    #[bits(14)]
+    _rsvd: u16,
+}
+
+/// LBA format extension
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes, Inspect)]
+pub struct Lbafe {
+    /// Zone size in logical blocks
+    pub zsze: u64,
+    /// Zone descriptor extension size, in 64 byte units
+    pub zdes: u8,
+    #[inspect(skip)]
+    pub rsvd: [u8; 7],
+}
+
+/// I/O Command Set specific Identify Controller data structure for the Zoned
+/// Namespace Command Set.
+#[repr(C)]
+#[derive(Debug, IntoBytes, Immutable, KnownLayout, FromBytes, Inspect, Clone)]
+pub struct IdentifyControllerZns {
+    /// Zone append size limit, as a power of two multiple of the minimum
+    /// memory page size. Zero means the limit is MDTS.
+    pub zasl: u8,
+    #[inspect(skip)]
+    pub rsvd: [u8; 4095],
+}
+
+open_enum! {
+    pub enum ZnsOpcode: u8 {
+        ZONE_MANAGEMENT_SEND = 0x79,
+        ZONE_MANAGEMENT_RECEIVE = 0x7a,
+        ZONE_APPEND = 0x7d,
+    }
+}
+
+open_enum! {
+    pub enum ZoneState: u8 {
+        EMPTY = 0x1,
+        IMPLICITLY_OPENED = 0x2,
+        EXPLICITLY_OPENED = 0x3,
+        CLOSED = 0x4,
+        READ_ONLY = 0xd,
+        FULL = 0xe,
+        OFFLINE = 0xf,
+    }
+}
+
+open_enum! {
+    pub enum ZoneType: u8 {
+        SEQUENTIAL_WRITE_REQUIRED = 0x2,
+    }
+}
+
+#[bitfield(u32)]
+pub struct Cdw13ZoneManagementSend {
+    /// Zone send action. See [`ZoneSendAction`].
+    pub zsa: u8,
+    /// Select all zones, ignoring the starting LBA.
+    pub select_all: bool,
+    /// Zone send action specific option
+    pub zsaso: bool,
+    #[bits(22)]
+    _rsvd: u32,
+}
+
+open_enum! {
+    pub enum ZoneSendAction: u8 {
+        CLOSE = 0x1,
+        FINISH = 0x2,
+        OPEN = 0x3,
+        RESET = 0x4,
+        OFFLINE = 0x5,
+        SET_ZONE_DESCRIPTOR_EXTENSION = 0x10,
+        FLUSH_EXPLICIT_ZRWA_RANGE = 0x11,
+    }
+}
+
+#[bitfield(u32)]
+pub struct Cdw12ZoneManagementReceive {
+    /// Number of dwords to transfer. Zero based.
+    pub numd_z: u32,
+}
+
+#[bitfield(u32)]
+pub struct Cdw13ZoneManagementReceive {
+    /// Zone receive action. See [`ZoneReceiveAction`].
+    pub zra: u8,
+    /// Zone receive action specific field. See [`ZoneReportFilter`] for
+    /// the report zones actions.
+    pub zrasf: u8,
+    /// Partial report: if set, the report header counts only the returned
+    /// descriptors.
+    pub partial: bool,
+    #[bits(15)]
+    _rsvd: u32,
+}
+
+open_enum! {
+    pub enum ZoneReceiveAction: u8 {
+        REPORT_ZONES = 0x0,
+        EXTENDED_REPORT_ZONES = 0x1,
+    }
+}
+
+open_enum! {
+    pub enum ZoneReportFilter: u8 {
+        ALL = 0x0,
+        EMPTY = 0x1,
+        IMPLICITLY_OPENED = 0x2,
+        EXPLICITLY_OPENED = 0x3,
+        CLOSED = 0x4,
+        FULL = 0x5,
+        READ_ONLY = 0x6,
+        OFFLINE = 0x7,
+    }
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ZoneReportHeader {
+    /// Number of zones
+    pub nrz: u64,
+    pub rsvd: [u8; 56],
+    // Followed by `[ZoneDescriptor; _]`.
+}
+
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ZoneDescriptor {
+    /// Zone type. See [`ZoneType`].
+    pub zt: u8,
+    /// Zone state, in bits 7:4. See [`ZoneState`].
+    pub zs: u8,
+    /// Zone attributes
+    pub za: ZoneAttributes,
+    /// Zone attributes information
+    pub zai: u8,
+    pub rsvd: [u8; 4],
+    /// Zone capacity
+    pub zcap: u64,
+    /// Zone start LBA
+    pub zslba: u64,
+    /// Write pointer
+    pub wp: u64,
+    pub rsvd2: [u8; 32],
+}
+
+#[bitfield(u8)]
+#[derive(IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ZoneAttributes {
+    /// Zone finished by controller
+    pub zfc: bool,
+    /// Finish zone recommended
+    pub fzr: bool,
+    /// Reset zone recommended
+    pub rzr: bool,
+    /// Zone random write area valid
+    pub zrwav: bool,
+    #[bits(3)]
+    _rsvd: u8,
+    /// Zone descriptor extension valid
+    pub zdev: bool,
+}
+
+/// The Changed Zone List log page.
+#[repr(C)]
+#[derive(Debug, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ChangedZoneList {
+    /// Number of zone identifiers, or `!0` if more zones changed than fit in
+    /// the list.
+    pub nz: u16,
+    pub rsvd: [u8; 6],
+    /// The start LBAs of the changed zones.
+    pub zid: [u64; 511],
+}
+
+static_assertions::assert_eq_size!(ChangedZoneList, [u8; 4096]);
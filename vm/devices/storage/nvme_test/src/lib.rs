@@ -17,6 +17,8 @@
 #[cfg(test)]
 mod tests;
 
+pub use namespace::ZoneStateError;
+pub use namespace::ZonedNamespaceConfig;
 pub use pci::NvmeFaultController;
 pub use pci::NvmeFaultControllerCaps;
 pub use workers::NvmeFaultControllerClient;
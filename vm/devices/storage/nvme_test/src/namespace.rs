@@ -4,6 +4,11 @@
 //! NVMe NVM namespace implementation.
 
 mod reservations;
+mod zoned;
+
+pub use zoned::InjectZoneFaultError;
+pub use zoned::ZoneStateError;
+pub use zoned::ZonedNamespaceConfig;
 
 use crate::error::CommandResult;
 use crate::error::NvmeError;
@@ -13,10 +18,12 @@
 use disk_backend::Disk;
 use guestmem::GuestMemory;
 use inspect::Inspect;
+use nvme_resources::ZoneFault;
 use scsi_buffers::RequestBuffers;
 use zerocopy::FromBytes;
 use zerocopy::FromZeros;
 use zerocopy::IntoBytes;
+use zoned::Zones;
 
 /// An NVMe namespace built on top of a [`Disk`].
 #[derive(Inspect)]
@@ -26,6 +33,7 @@ pub struct Namespace {
     mem: GuestMemory,
     block_shift: u32,
     pr: bool,
+    zones: Option<Zones>,
 }
 
 impl Namespace {
@@ -36,12 +44,55 @@ pub fn new(mem: GuestMemory, nsid: u32, disk: Disk) -> Self {
             mem,
             disk,
             nsid,
+            zones: None,
+        }
+    }
+
+    /// Returns a zoned namespace, or `None` if `config` is invalid for the
+    /// disk. The zone states must then be loaded with
+    /// [`Namespace::load_zones`].
+    pub fn new_zoned(
+        mem: GuestMemory,
+        nsid: u32,
+        disk: Disk,
+        config: &ZonedNamespaceConfig,
+    ) -> Option<Self> {
+        let zones = Zones::new(config, disk.sector_count())?;
+        Some(Self {
+            zones: Some(zones),
+            ..Self::new(mem, nsid, disk)
+        })
+    }
+
+    /// Loads the zone states of a zoned namespace from its state disk.
+    pub async fn load_zones(&self) -> Result<(), ZoneStateError> {
+        match &self.zones {
+            Some(zones) => zones.load().await,
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the command set of the namespace.
+    pub fn csi(&self) -> spec::Csi {
+        if self.zones.is_some() {
+            spec::Csi::ZONED_NAMESPACE
+        } else {
+            spec::Csi::NVM
+        }
+    }
+
+    /// Returns the size of the namespace in blocks. A zoned namespace only
+    /// covers the whole zones of its disk.
+    fn sector_count(&self) -> u64 {
+        match &self.zones {
+            Some(zones) => zones.capacity(),
+            None => self.disk.sector_count(),
         }
     }
 
     pub fn identify(&self, buf: &mut [u8]) {
         let id = nvm::IdentifyNamespace::mut_from_prefix(buf).unwrap().0; // TODO: zerocopy: from-prefix (mut_from_prefix): use-rest-of-range (https://github.com/microsoft/openvmm/issues/759)
-        let size = self.disk.sector_count();
+        let size = self.sector_count();
 
         let rescap = if let Some(pr) = self.disk.pr() {
             let caps = pr.capabilities();
@@ -68,10 +119,29 @@ pub fn identify(&self, buf: &mut [u8]) {
         id.lbaf[0] = nvm::Lbaf::new().with_lbads(self.block_shift as u8);
     }
 
+    /// Writes the I/O command set specific Identify Namespace data structure
+    /// for `csi`.
+    pub fn identify_io_command_set(&self, csi: spec::Csi, buf: &mut [u8]) -> Result<(), NvmeError> {
+        match csi {
+            // The NVM command set specific structure only describes features
+            // that are not supported, so leave it zeroed. Zoned namespaces
+            // support the NVM command set too.
+            spec::Csi::NVM => {}
+            spec::Csi::ZONED_NAMESPACE => self
+                .zones
+                .as_ref()
+                .ok_or(spec::Status::INVALID_IO_COMMAND_SET)?
+                .identify(buf),
+            csi => {
+                tracelimit::warn_ratelimited!(nsid = self.nsid, ?csi, "unsupported csi");
+                return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+            }
+        }
+        Ok(())
+    }
+
     pub fn namespace_id_descriptor(&self, buf: &mut [u8]) {
-        let id = nvm::NamespaceIdentificationDescriptor::mut_from_prefix(buf)
-            .unwrap()
-            .0; // TODO: zerocopy: from-prefix (mut_from_prefix): use-rest-of-range (https://github.com/microsoft/openvmm/issues/759)
+        let (id, rest) = nvm::NamespaceIdentificationDescriptor::mut_from_prefix(buf).unwrap(); // TODO: zerocopy: from-prefix (mut_from_prefix): use-rest-of-range (https://github.com/microsoft/openvmm/issues/759)
         let mut nid = [0u8; 0x10];
         if let Some(guid) = self.disk.disk_id() {
             nid = guid;
@@ -82,6 +152,30 @@ pub fn namespace_id_descriptor(&self, buf: &mut [u8]) {
             rsvd: [0, 0],
             nid,
         };
+        // The command set identifier descriptor has a one byte identifier.
+        rest[..5].copy_from_slice(&[nvm::NamespaceIdentifierType::CSI.0, 1, 0, 0, self.csi().0]);
+    }
+
+    /// Injects a zone fault, for testing.
+    pub async fn inject_zone_fault(
+        &self,
+        zslba: u64,
+        fault: ZoneFault,
+    ) -> Result<(), InjectZoneFaultError> {
+        self.zones
+            .as_ref()
+            .ok_or(InjectZoneFaultError::NotZoned(self.nsid))?
+            .inject_fault(zslba, fault)
+            .await
+    }
+
+    /// Writes the Changed Zone List log page, and clears the list.
+    pub fn changed_zone_list(&self, log: &mut spec::zns::ChangedZoneList) -> Result<(), NvmeError> {
+        self.zones
+            .as_ref()
+            .ok_or(spec::Status::INVALID_FIELD_IN_COMMAND)?
+            .take_changed_zones(log);
+        Ok(())
     }
 
     pub async fn get_feature(&self, command: &spec::Command) -> Result<CommandResult, NvmeError> {
@@ -135,10 +229,13 @@ pub async fn nvm_command(
                 }
                 let range = PrpRange::parse(&self.mem, byte_count, command.dptr)?;
 
-                let disk_sector_count = self.disk.sector_count();
-                if disk_sector_count < lba || disk_sector_count - lba < count as u64 {
+                let sector_count = self.sector_count();
+                if sector_count < lba || sector_count - lba < count as u64 {
                     return Err(spec::Status::LBA_OUT_OF_RANGE.into());
                 }
+                if let Some(zones) = &self.zones {
+                    zones.check_read(lba, count as u64)?;
+                }
 
                 tracing::trace!(nsid = self.nsid, lba, count, byte_count, "read");
 
@@ -160,23 +257,35 @@ pub async fn nvm_command(
                 }
                 let range = PrpRange::parse(&self.mem, byte_count, command.dptr)?;
 
-                let disk_sector_count = self.disk.sector_count();
-                if disk_sector_count < lba || disk_sector_count - lba < count as u64 {
+                let sector_count = self.sector_count();
+                if sector_count < lba || sector_count - lba < count as u64 {
                     return Err(spec::Status::LBA_OUT_OF_RANGE.into());
                 }
 
                 tracing::trace!(nsid = self.nsid, lba, count, byte_count, "write");
 
                 let buffers = RequestBuffers::new(&self.mem, range.range(), false);
-                self.disk
-                    .write_vectored(&buffers, lba, cdw12.fua())
-                    .await
-                    .map_err(map_disk_error)?;
+                let zone_write = self
+                    .zones
+                    .as_ref()
+                    .map(|zones| zones.write(lba, count as u64))
+                    .transpose()?;
+                let result = self.disk.write_vectored(&buffers, lba, cdw12.fua()).await;
+                if let Some((zones, zone_write)) = self.zones.as_ref().zip(zone_write) {
+                    match &result {
+                        Ok(()) => zones.commit_write(&zone_write, cdw12.fua()).await?,
+                        Err(_) => zones.abort_write(zone_write).await,
+                    }
+                }
+                result.map_err(map_disk_error)?;
             }
             nvm::NvmOpcode::FLUSH => {
                 tracing::debug!(nsid = self.nsid, "flush");
                 if !self.disk.is_read_only() {
                     self.disk.sync_cache().await.map_err(map_disk_error)?;
+                    if let Some(zones) = &self.zones {
+                        zones.flush().await.map_err(map_disk_error)?;
+                    }
                 }
             }
             nvm::NvmOpcode::DSM => {
@@ -216,6 +325,11 @@ pub async fn nvm_command(
                     .await?
             }
             opcode => {
+                if let Some(zones) = &self.zones {
+                    return self
+                        .zns_command(zones, max_data_transfer_size, command)
+                        .await;
+                }
                 tracelimit::warn_ratelimited!(nsid = self.nsid, ?opcode, "unsupported nvm opcode");
                 return Err(spec::Status::INVALID_COMMAND_OPCODE.into());
             }
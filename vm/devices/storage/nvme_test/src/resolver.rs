@@ -6,10 +6,12 @@
 use crate::AddNamespaceError;
 use crate::NvmeFaultController;
 use crate::NvmeFaultControllerCaps;
+use crate::ZonedNamespaceConfig;
 use async_trait::async_trait;
 use disk_backend::resolve::ResolveDiskParameters;
 use nvme_resources::NamespaceDefinition;
 use nvme_resources::NvmeFaultControllerHandle;
+use nvme_resources::ZonedNamespaceDefinition;
 use pci_resources::ResolvePciDeviceHandleParams;
 use pci_resources::ResolvedPciDevice;
 use tdisp::test_helpers::new_null_tdisp_interface;
@@ -19,6 +21,7 @@
 use vm_resource::ResourceResolver;
 use vm_resource::declare_static_async_resolver;
 use vm_resource::kind::PciDeviceHandleKind;
+use vmcore::vm_task::VmTaskDriverSource;
 
 /// Resource resolver for [`NvmeFaultControllerHandle`].
 pub struct NvmeFaultControllerResolver;
@@ -81,6 +84,7 @@ async fn resolve(
             nsid,
             read_only,
             disk,
+            zoned,
         } in resource.namespaces
         {
             let disk = resolver
@@ -93,12 +97,55 @@ async fn resolve(
                 )
                 .await
                 .map_err(|source| Error::NamespaceResolve { nsid, source })?;
-            controller
-                .client()
-                .add_namespace(nsid, disk.0)
+            let zoned = resolve_zoned(resolver, input.driver_source, read_only, zoned)
                 .await
-                .map_err(Error::AddNamespace)?;
+                .map_err(|source| Error::NamespaceResolve { nsid, source })?;
+            let client = controller.client();
+            match zoned {
+                Some(config) => client.add_zoned_namespace(nsid, disk.0, config).await,
+                None => client.add_namespace(nsid, disk.0).await,
+            }
+            .map_err(Error::AddNamespace)?;
         }
         Ok(controller.into())
     }
 }
+
+/// Resolves the zone state disk of a zoned namespace definition.
+async fn resolve_zoned(
+    resolver: &ResourceResolver,
+    driver_source: &VmTaskDriverSource,
+    read_only: bool,
+    zoned: Option<ZonedNamespaceDefinition>,
+) -> Result<Option<ZonedNamespaceConfig>, ResolveError> {
+    let Some(ZonedNamespaceDefinition {
+        zone_size,
+        max_open_zones,
+        max_active_zones,
+        state_disk,
+    }) = zoned
+    else {
+        return Ok(None);
+    };
+    let state_disk = match state_disk {
+        Some(disk) => Some(
+            resolver
+                .resolve(
+                    disk,
+                    ResolveDiskParameters {
+                        read_only,
+                        driver_source,
+                    },
+                )
+                .await?
+                .0,
+        ),
+        None => None,
+    };
+    Ok(Some(ZonedNamespaceConfig {
+        zone_size,
+        max_open_zones,
+        max_active_zones,
+        state_disk,
+    }))
+}
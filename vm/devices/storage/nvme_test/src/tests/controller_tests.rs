@@ -6,9 +6,11 @@
 use crate::NvmeFaultController;
 use crate::NvmeFaultControllerCaps;
 use crate::PAGE_SIZE64;
+use crate::ZonedNamespaceConfig;
 use crate::command_match::CommandMatchBuilder;
 use crate::prp::PrpRange;
 use crate::spec;
+use crate::spec::zns;
 use crate::tests::test_helpers::read_completion_from_queue;
 use crate::tests::test_helpers::test_memory;
 use crate::tests::test_helpers::write_command_to_queue;
@@ -16,12 +18,17 @@
 use chipset_device::pci::ByteEnabledDwordRead;
 use chipset_device::pci::ByteEnabledDwordWrite;
 use chipset_device::pci::PciConfigSpace;
+use disklayer_ram::ram_disk;
 use guestmem::GuestMemory;
 use guid::Guid;
 use mesh::CellUpdater;
+use mesh::rpc::RpcSend;
+use nvme_resources::ZoneFault;
+use nvme_resources::ZoneFaultDefinition;
 use nvme_resources::fault::AdminQueueFaultBehavior;
 use nvme_resources::fault::AdminQueueFaultConfig;
 use nvme_resources::fault::FaultConfiguration;
+use nvme_resources::fault::ZoneFaultConfig;
 use nvme_spec::Command;
 use nvme_spec::Completion;
 use pal_async::DefaultDriver;
@@ -243,7 +250,7 @@ async fn test_basic_registers(driver: DefaultDriver) {
     assert_eq!(dword, 0xFF0100FF);
     let mut qword = 0u64;
     nvmec.read_bar0(0, qword.as_mut_bytes()).unwrap();
-    assert_eq!(qword, 0x20FF0100FF);
+    assert_eq!(qword, 0x820FF0100FF);
     nvmec.read_bar0(8, dword.as_mut_bytes()).unwrap();
     assert_eq!(dword, 0x20000);
 
@@ -405,3 +412,134 @@ async fn test_send_identify_with_sq_fault(driver: DefaultDriver) {
     assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
     assert_eq!(cqe.cid, 10); // The CID should have been overwritten by the fault.
 }
+
+/// Submits a single admin command in slot `slot` of the admin queue and
+/// returns its completion.
+async fn submit_admin_command(
+    nvmec: &mut NvmeFaultController,
+    gm: &GuestMemory,
+    asq: &PrpRange,
+    acq: &PrpRange,
+    int_controller: &TestPciInterruptController,
+    driver: DefaultDriver,
+    slot: u32,
+    command: &Command,
+) -> Completion {
+    write_command_to_queue(gm, asq, slot as usize, command);
+    nvmec.write_bar0(0x1000, (slot + 1).as_bytes()).unwrap();
+    wait_for_msi(driver, int_controller, 1000, 0xfeed0000, 0x1111).await;
+    read_completion_from_queue(gm, acq, slot as usize)
+}
+
+/// Zone faults sent through the fault configuration fail the zone, and are
+/// reported in the Changed Zone List log page of the zoned namespace.
+#[async_test]
+async fn test_zone_fault(driver: DefaultDriver) {
+    let acq = PrpRange::new(vec![0], 0, PAGE_SIZE64).unwrap();
+    let asq = PrpRange::new(vec![0x1000], 0, PAGE_SIZE64).unwrap();
+    let gm = test_memory();
+    let int_controller = TestPciInterruptController::new();
+    let (zone_fault_send, zone_fault_recv) = mesh::channel();
+    let fault_configuration = FaultConfiguration::new(CellUpdater::new(false).cell())
+        .with_zone_fault(ZoneFaultConfig::new(zone_fault_recv));
+
+    let mut nvmec = instantiate_and_build_admin_queue(
+        &acq,
+        64,
+        &asq,
+        64,
+        true,
+        Some(&int_controller),
+        driver.clone(),
+        &gm,
+        fault_configuration,
+    )
+    .await;
+
+    // 2048 blocks in 8 zones.
+    nvmec
+        .client()
+        .add_zoned_namespace(
+            1,
+            ram_disk(1 << 20, false).unwrap(),
+            ZonedNamespaceConfig {
+                zone_size: 256,
+                max_open_zones: None,
+                max_active_zones: None,
+                state_disk: None,
+            },
+        )
+        .await
+        .unwrap();
+
+    let mut identify = Command::new_zeroed();
+    identify.cdw0.set_opcode(spec::AdminOpcode::IDENTIFY.0);
+    identify.nsid = 1;
+    identify.cdw10 = spec::Cdw10Identify::new()
+        .with_cns(spec::Cns::SPECIFIC_NAMESPACE_IO_COMMAND_SET.0)
+        .into();
+    identify.cdw11 = spec::Cdw11Identify::new()
+        .with_csi(spec::Csi::ZONED_NAMESPACE.0)
+        .into();
+    identify.dptr[0] = 0x8000;
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        0,
+        &identify,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let id = gm.read_plain::<zns::IdentifyNamespaceZns>(0x8000).unwrap();
+    assert_eq!(id.lbafe[0].zsze, 256);
+
+    let fault = |nsid, zslba| ZoneFaultDefinition {
+        nsid,
+        zslba,
+        fault: ZoneFault::ReadOnly,
+    };
+    zone_fault_send
+        .call_failable(|rpc| rpc, fault(1, 256))
+        .await
+        .unwrap();
+    // A fault must name the start of a zone in a zoned namespace.
+    zone_fault_send
+        .call_failable(|rpc| rpc, fault(1, 257))
+        .await
+        .unwrap_err();
+    zone_fault_send
+        .call_failable(|rpc| rpc, fault(2, 0))
+        .await
+        .unwrap_err();
+
+    let mut log = Command::new_zeroed();
+    log.cdw0.set_opcode(spec::AdminOpcode::GET_LOG_PAGE.0);
+    log.nsid = 1;
+    log.cdw10 = spec::Cdw10GetLogPage::new()
+        .with_lid(spec::LogPageIdentifier::CHANGED_ZONE_LIST.0)
+        .with_numdl_z(1023)
+        .into();
+    log.cdw14 = spec::Cdw14GetLogPage::new()
+        .with_csi(spec::Csi::ZONED_NAMESPACE.0)
+        .into();
+    log.dptr[0] = 0xa000;
+    let cqe = submit_admin_command(
+        &mut nvmec,
+        &gm,
+        &asq,
+        &acq,
+        &int_controller,
+        driver.clone(),
+        1,
+        &log,
+    )
+    .await;
+    assert_eq!(cqe.status.status(), spec::Status::SUCCESS.0);
+    let changed = gm.read_plain::<zns::ChangedZoneList>(0xa000).unwrap();
+    assert_eq!(changed.nz, 1);
+    assert_eq!(changed.zid[0], 256);
+}
@@ -17,7 +17,10 @@
 use crate::command_match::match_command_pattern;
 use crate::error::CommandResult;
 use crate::error::NvmeError;
+use crate::namespace::InjectZoneFaultError;
 use crate::namespace::Namespace;
+use crate::namespace::ZoneStateError;
+use crate::namespace::ZonedNamespaceConfig;
 use crate::prp::PrpRange;
 use crate::queue::CompletionQueue;
 use crate::queue::DoorbellMemory;
@@ -33,6 +36,7 @@
 use guid::Guid;
 use inspect::Inspect;
 use mesh::rpc::Rpc;
+use nvme_resources::ZoneFault;
 use nvme_resources::fault::AdminQueueFaultBehavior;
 use nvme_resources::fault::CommandMatch;
 use nvme_resources::fault::FaultConfiguration;
@@ -418,6 +422,12 @@ pub enum AddNamespaceError {
     /// subsystem (see the `NN` field of Identify Controller).
     #[error("namespace id {0} is out of range (must be 1..={MAX_NSID})")]
     OutOfRange(u32),
+    /// The zone configuration is not valid for the disk.
+    #[error("invalid zone configuration for namespace {0}")]
+    InvalidZoneConfig(u32),
+    /// The zone states could not be loaded from the state disk.
+    #[error("failed to load zone states for namespace {0}")]
+    ZoneState(u32, #[source] ZoneStateError),
 }
 
 impl AdminHandler {
@@ -435,18 +445,28 @@ pub async fn add_namespace(
         state: Option<&mut AdminState>,
         nsid: u32,
         disk: Disk,
+        zoned: Option<ZonedNamespaceConfig>,
     ) -> Result<(), AddNamespaceError> {
         if nsid == 0 || nsid > MAX_NSID {
             return Err(AddNamespaceError::OutOfRange(nsid));
         }
-        let namespace = &*match self.namespaces.entry(nsid) {
-            btree_map::Entry::Vacant(entry) => entry.insert(Arc::new(Namespace::new(
-                self.config.mem.clone(),
-                nsid,
-                disk,
-            ))),
-            btree_map::Entry::Occupied(_) => return Err(AddNamespaceError::Conflict(nsid)),
+        let btree_map::Entry::Vacant(entry) = self.namespaces.entry(nsid) else {
+            return Err(AddNamespaceError::Conflict(nsid));
+        };
+        let mem = self.config.mem.clone();
+        let namespace = match zoned {
+            Some(config) => {
+                let namespace = Namespace::new_zoned(mem, nsid, disk, &config)
+                    .ok_or(AddNamespaceError::InvalidZoneConfig(nsid))?;
+                namespace
+                    .load_zones()
+                    .await
+                    .map_err(|err| AddNamespaceError::ZoneState(nsid, err))?;
+                namespace
+            }
+            None => Namespace::new(mem, nsid, disk),
         };
+        let namespace = &*entry.insert(Arc::new(namespace));
 
         if let Some(state) = state {
             state.add_namespace(&self.driver, nsid, namespace).await;
@@ -467,6 +487,20 @@ pub async fn remove_namespace(&mut self, state: Option<&mut AdminState>, nsid: u
         true
     }
 
+    /// Injects a zone fault into zoned namespace `nsid`, for testing.
+    pub async fn inject_zone_fault(
+        &self,
+        nsid: u32,
+        zslba: u64,
+        fault: ZoneFault,
+    ) -> Result<(), InjectZoneFaultError> {
+        self.namespaces
+            .get(&nsid)
+            .ok_or(InjectZoneFaultError::NamespaceNotFound(nsid))?
+            .inject_zone_fault(zslba, fault)
+            .await
+    }
+
     async fn next_event(&mut self, state: &mut AdminState) -> Result<Event, QueueError> {
         let event = loop {
             // Wait for there to be room for a completion for the next
@@ -862,18 +896,51 @@ fn handle_identify(
                     tracing::trace!(nsid = command.nsid, "inactive namespace id");
                 }
             }
+            spec::Cns::SPECIFIC_NAMESPACE_IO_COMMAND_SET => {
+                if command.nsid == 0 || command.nsid > MAX_NSID {
+                    return Err(spec::Status::INVALID_NAMESPACE_OR_FORMAT.into());
+                }
+                let csi = supported_csi(command)?;
+                if let Some(ns) = self.namespaces.get(&command.nsid) {
+                    ns.identify_io_command_set(csi, buf)?;
+                } else {
+                    // Valid but inactive namespace: return a zero-filled
+                    // structure (the buffer is already zeroed).
+                    tracing::trace!(nsid = command.nsid, "inactive namespace id");
+                }
+            }
             spec::Cns::SPECIFIC_CONTROLLER_IO_COMMAND_SET => {
-                // CSI is in Command Dword 11, bits 31:24. Only the NVM command
-                // set (CSI 0h) is supported, and it defines no I/O Command Set
-                // specific Identify Controller data structure, so return a
-                // zero-filled structure per NVMe Base 2.3 section 5.2.13.2.6.
-                // Any other command set is unsupported.
-                let csi = (command.cdw11 >> 24) as u8;
-                if csi != 0 {
-                    return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
+                // The NVM command set defines no I/O Command Set specific
+                // Identify Controller data structure, so return a zero-filled
+                // structure per NVMe Base 2.3 section 5.2.13.2.6. The Zoned
+                // Namespace structure is zero-filled too: a ZASL of zero
+                // limits zone appends only by MDTS.
+                supported_csi(command)?;
+            }
+            spec::Cns::ACTIVE_NAMESPACE_LIST_IO_COMMAND_SET => {
+                if command.nsid >= 0xfffffffe {
+                    return Err(spec::Status::INVALID_NAMESPACE_OR_FORMAT.into());
+                }
+                let csi = supported_csi(command)?;
+                let nsids = <[u32]>::mut_from_bytes(buf).unwrap();
+                for (ns, nsid) in self
+                    .namespaces
+                    .iter()
+                    .filter(|&(&ns, namespace)| ns > command.nsid && namespace.csi() == csi)
+                    .map(|(&ns, _)| ns)
+                    .zip(nsids)
+                {
+                    *nsid = ns;
                 }
-                // The buffer is already zero-filled; fall through to write it
-                // back to the host.
+            }
+            spec::Cns::IO_COMMAND_SET => {
+                // Report a single command set combination, with every
+                // supported command set. This is the combination selected
+                // by the I/O Command Set Profile feature's default index 0.
+                let vectors = <[spec::IoCommandSetVector]>::mut_from_bytes(buf).unwrap();
+                vectors[0] = spec::IoCommandSetVector::new()
+                    .with_nvm(true)
+                    .with_zoned_namespace(true);
             }
             cns => {
                 tracelimit::warn_ratelimited!(?cns, "unsupported cns");
@@ -998,6 +1065,14 @@ fn handle_set_features(
                     .ok_or(spec::Status::INVALID_FIELD_IN_COMMAND)?;
                 *cd = cdw11.cd();
             }
+            spec::Feature::IO_COMMAND_SET_PROFILE => {
+                // Only the single combination reported for
+                // `Cns::IO_COMMAND_SET` can be selected.
+                let cdw11 = spec::Cdw11FeatureIoCommandSetProfile::from(command.cdw11);
+                if cdw11.iocsci() != 0 {
+                    return Err(spec::Status::IO_COMMAND_SET_COMBINATION_REJECTED.into());
+                }
+            }
             feature => {
                 tracelimit::warn_ratelimited!(?feature, "unsupported feature");
                 return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
@@ -1078,6 +1153,12 @@ async fn handle_get_features(
                     .with_cd(cd)
                     .into();
             }
+            spec::Feature::IO_COMMAND_SET_PROFILE => {
+                // The only combination is always selected.
+                dw[0] = spec::Cdw11FeatureIoCommandSetProfile::new()
+                    .with_iocsci(0)
+                    .into();
+            }
             feature => {
                 tracelimit::warn_ratelimited!(?feature, "unsupported feature");
                 return Err(spec::Status::INVALID_FIELD_IN_COMMAND.into());
@@ -1297,6 +1378,7 @@ fn handle_get_log_page(
     ) -> Result<(), NvmeError> {
         let cdw10 = spec::Cdw10GetLogPage::from(command.cdw10);
         let cdw11 = spec::Cdw11GetLogPage::from(command.cdw11);
+        let cdw14 = spec::Cdw14GetLogPage::from(command.cdw14);
         let numd =
             ((cdw10.numdl_z() as u32) | ((cdw11.numdu() as u32) << 16)).saturating_add(1) as usize;
         let len = numd * 4;
@@ -1318,6 +1400,9 @@ fn handle_get_log_page(
                 ] {
                     page[lid.0 as usize] = supported.into();
                 }
+                if spec::Csi(cdw14.csi()) == spec::Csi::ZONED_NAMESPACE {
+                    page[spec::LogPageIdentifier::CHANGED_ZONE_LIST.0 as usize] = supported.into();
+                }
                 let bytes = page.as_bytes();
                 prp.write(&self.config.mem, &bytes[..len.min(bytes.len())])?;
             }
@@ -1358,6 +1443,18 @@ fn handle_get_log_page(
                     state.notified_changed_namespaces = false;
                 }
             }
+            spec::LogPageIdentifier::CHANGED_ZONE_LIST
+                if spec::Csi(cdw14.csi()) == spec::Csi::ZONED_NAMESPACE =>
+            {
+                let namespace = self
+                    .namespaces
+                    .get(&command.nsid)
+                    .ok_or(spec::Status::INVALID_NAMESPACE_OR_FORMAT)?;
+                let mut log = spec::zns::ChangedZoneList::new_zeroed();
+                namespace.changed_zone_list(&mut log)?;
+                let bytes = log.as_bytes();
+                prp.write(&self.config.mem, &bytes[..len.min(bytes.len())])?;
+            }
             lid => {
                 tracelimit::warn_ratelimited!(?lid, "unsupported log page");
                 return Err(spec::Status::INVALID_LOG_PAGE.into());
@@ -1409,6 +1506,19 @@ fn get_configured_fault_behavior_mut<'a, T: Clone>(
     }
 }
 
+/// Returns the command set identifier of an Identify command, if it is
+/// supported by the controller.
+fn supported_csi(command: &spec::Command) -> Result<spec::Csi, NvmeError> {
+    let csi = spec::Csi(spec::Cdw11Identify::from(command.cdw11).csi());
+    match csi {
+        spec::Csi::NVM | spec::Csi::ZONED_NAMESPACE => Ok(csi),
+        csi => {
+            tracelimit::warn_ratelimited!(?csi, "unsupported csi");
+            Err(spec::Status::INVALID_FIELD_IN_COMMAND.into())
+        }
+    }
+}
+
 impl AsyncRun<AdminState> for AdminHandler {
     async fn run(
         &mut self,
@@ -8,6 +8,8 @@
 use super::admin::AdminConfig;
 use super::admin::AdminHandler;
 use super::admin::AdminState;
+use crate::namespace::InjectZoneFaultError;
+use crate::namespace::ZonedNamespaceConfig;
 use crate::queue::DoorbellMemory;
 use crate::queue::InvalidDoorbell;
 use disk_backend::Disk;
@@ -18,9 +20,12 @@
 use guid::Guid;
 use inspect::Inspect;
 use inspect::InspectMut;
+use mesh::rpc::FailableRpc;
 use mesh::rpc::PendingRpc;
 use mesh::rpc::Rpc;
 use mesh::rpc::RpcSend;
+use nvme_resources::ZoneFault;
+use nvme_resources::ZoneFaultDefinition;
 use nvme_resources::fault::FaultConfiguration;
 use pal_async::task::Spawn;
 use pal_async::task::Task;
@@ -61,8 +66,12 @@ pub fn new(
         max_cqs: u16,
         qe_sizes: Arc<parking_lot::Mutex<IoQueueEntrySizes>>,
         subsystem_id: Guid,
-        fault_configuration: FaultConfiguration,
+        mut fault_configuration: FaultConfiguration,
     ) -> Self {
+        let zone_faults = fault_configuration
+            .zone_fault
+            .take()
+            .map(|config| config.recv_zone_fault);
         let num_qids = 2 + max_sqs.max(max_cqs) * 2;
         let doorbells = Arc::new(RwLock::new(DoorbellMemory::new(num_qids)));
 
@@ -87,7 +96,7 @@ pub fn new(
             reset: None,
         };
         let (send, recv) = mesh::mpsc_channel();
-        let task = driver.spawn("nvme-coord", coordinator.run(recv));
+        let task = driver.spawn("nvme-coord", coordinator.run(recv, zone_faults));
         Self {
             _task: task,
             send,
@@ -198,7 +207,20 @@ impl NvmeFaultControllerClient {
     /// Adds a namespace.
     pub async fn add_namespace(&self, nsid: u32, disk: Disk) -> Result<(), AddNamespaceError> {
         self.send
-            .call(CoordinatorRequest::AddNamespace, (nsid, disk))
+            .call(CoordinatorRequest::AddNamespace, (nsid, disk, None))
+            .await
+            .unwrap()
+    }
+
+    /// Adds a zoned namespace, which uses the Zoned Namespace Command Set.
+    pub async fn add_zoned_namespace(
+        &self,
+        nsid: u32,
+        disk: Disk,
+        config: ZonedNamespaceConfig,
+    ) -> Result<(), AddNamespaceError> {
+        self.send
+            .call(CoordinatorRequest::AddNamespace, (nsid, disk, Some(config)))
             .await
             .unwrap()
     }
@@ -223,7 +245,7 @@ struct Coordinator {
 
 enum CoordinatorRequest {
     EnableAdmin(Rpc<EnableAdminParams, ()>),
-    AddNamespace(Rpc<(u32, Disk), Result<(), AddNamespaceError>>),
+    AddNamespace(Rpc<(u32, Disk, Option<ZonedNamespaceConfig>), Result<(), AddNamespaceError>>),
     RemoveNamespace(Rpc<u32, bool>),
     Inspect(inspect::Deferred),
     ControllerReset(Rpc<(), ()>),
@@ -237,11 +259,34 @@ struct EnableAdminParams {
 }
 
 impl Coordinator {
-    async fn run(mut self, mut recv: mesh::Receiver<CoordinatorRequest>) {
+    async fn inject_zone_fault(
+        &mut self,
+        nsid: u32,
+        zslba: u64,
+        fault: ZoneFault,
+    ) -> Result<(), InjectZoneFaultError> {
+        let running = self.admin.stop().await;
+        let r = self
+            .admin
+            .task()
+            .inject_zone_fault(nsid, zslba, fault)
+            .await;
+        if running {
+            self.admin.start();
+        }
+        r
+    }
+
+    async fn run(
+        mut self,
+        mut recv: mesh::Receiver<CoordinatorRequest>,
+        mut zone_faults: Option<mesh::Receiver<FailableRpc<ZoneFaultDefinition, ()>>>,
+    ) {
         loop {
             enum Event {
                 Request(Option<CoordinatorRequest>),
                 ResetComplete,
+                ZoneFault(FailableRpc<ZoneFaultDefinition, ()>),
             }
 
             let controller_reset = async {
@@ -256,9 +301,20 @@ enum Event {
                 }
             };
 
+            let zone_fault = async {
+                let Some(recv) = &mut zone_faults else {
+                    pending().await
+                };
+                let Some(rpc) = recv.next().await else {
+                    pending().await
+                };
+                Event::ZoneFault(rpc)
+            };
+
             let event = (
                 recv.next().map(Event::Request),
                 controller_reset.map(|_| Event::ResetComplete),
+                zone_fault,
             )
                 .race()
                 .await;
@@ -283,10 +339,10 @@ enum Event {
                         },
                     ),
                     CoordinatorRequest::AddNamespace(rpc) => {
-                        rpc.handle(async |(nsid, disk)| {
+                        rpc.handle(async |(nsid, disk, zoned)| {
                             let running = self.admin.stop().await;
                             let (admin, state) = self.admin.get_mut();
-                            let r = admin.add_namespace(state, nsid, disk).await;
+                            let r = admin.add_namespace(state, nsid, disk, zoned).await;
                             if running {
                                 self.admin.start();
                             }
@@ -316,6 +372,12 @@ enum Event {
                 Event::ResetComplete => {
                     self.reset.take().unwrap().complete(());
                 }
+                Event::ZoneFault(rpc) => {
+                    rpc.handle_failable(async |ZoneFaultDefinition { nsid, zslba, fault }| {
+                        self.inject_zone_fault(nsid, zslba, fault).await
+                    })
+                    .await
+                }
             }
         }
     }
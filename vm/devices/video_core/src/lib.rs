@@ -5,9 +5,11 @@
 
 #![forbid(unsafe_code)]
 
+use guestmem::GuestMemory;
 use inspect::Inspect;
 use mesh::MeshPayload;
 use mesh::payload::Protobuf;
+use std::io;
 use vm_resource::CanResolveTo;
 use vm_resource::ResourceId;
 use vm_resource::kind::FramebufferHandleKind;
@@ -80,4 +82,10 @@ pub trait FramebufferControl: Send {
     async fn unmap(&mut self);
     /// Updates the framebuffer format.
     async fn set_format(&mut self, format: FramebufferFormat);
+    /// Returns memory for writing the framebuffer contents directly, for
+    /// video devices that present the guest's display by copying it into the
+    /// framebuffer rather than by mapping the framebuffer into the guest.
+    fn memory(&self) -> io::Result<GuestMemory> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
 }
@@ -63,6 +63,18 @@ fn set_shared_memory_region(
         Ok(())
     }
 
+    /// Take the receiver on which the device reports changes to its
+    /// device-specific config registers.
+    ///
+    /// The transport calls this once, at construction. Each message bumps
+    /// the config generation and, if the driver is active, raises a
+    /// config-change interrupt, e.g. when the host changes a balloon target.
+    ///
+    /// Default: `None`.
+    fn take_config_change_recv(&mut self) -> Option<mesh::Receiver<()>> {
+        None
+    }
+
     /// Start a single queue.
     ///
     /// Called when a queue becomes active — either because the guest set
@@ -146,6 +158,9 @@ fn set_shared_memory_region(
         region: &Arc<dyn MappedMemoryRegion>,
     ) -> anyhow::Result<()>;
 
+    /// Take the config change receiver.
+    fn take_config_change_recv(&mut self) -> Option<mesh::Receiver<()>>;
+
     /// Start a single queue.
     fn start_queue<'a>(
         &'a mut self,
@@ -199,6 +214,10 @@ fn set_shared_memory_region(
         VirtioDevice::set_shared_memory_region(self, region)
     }
 
+    fn take_config_change_recv(&mut self) -> Option<mesh::Receiver<()>> {
+        VirtioDevice::take_config_change_recv(self)
+    }
+
     fn start_queue<'a>(
         &'a mut self,
         idx: u16,
@@ -26,4 +26,6 @@ fn from(value: T) -> Self {
 pub struct VirtioResolveInput<'a> {
     /// The VM driver source.
     pub driver_source: &'a VmTaskDriverSource,
+    /// The number of VPs in the VM.
+    pub vp_count: u32,
 }
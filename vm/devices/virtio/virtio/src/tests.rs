@@ -4150,6 +4150,7 @@ struct PartialFailTestDevice {
     started: Vec<u16>,
     stopped: Vec<u16>,
     reset_count: usize,
+    config_change_recv: Option<mesh::Receiver<()>>,
 }
 
 impl PartialFailTestDevice {
@@ -4167,6 +4168,7 @@ fn new(max_queues: u16, fail_at: u16) -> Self {
             started: Vec::new(),
             stopped: Vec::new(),
             reset_count: 0,
+            config_change_recv: None,
         }
     }
 }
@@ -4179,6 +4181,9 @@ async fn read_registers_u32(&mut self, _offset: u16) -> u32 {
         0
     }
     async fn write_registers_u32(&mut self, _offset: u16, _val: u32) {}
+    fn take_config_change_recv(&mut self) -> Option<mesh::Receiver<()>> {
+        self.config_change_recv.take()
+    }
     async fn start_queue(
         &mut self,
         idx: u16,
@@ -5212,6 +5217,44 @@ async fn stop_during_failed_enable_resets_config_pci(_driver: DefaultDriver) {
     verify_stop_during_failed_enable_resets_config(&mut transport).await;
 }
 
+/// Verify that device-reported config changes bump the config generation,
+/// coalescing changes that arrive between polls.
+async fn verify_device_config_change(transport: &mut impl TestTransport, send: mesh::Sender<()>) {
+    transport.write_driver_ok();
+    yield_and_poll(transport).await;
+    let generation = transport.read_config_generation();
+
+    send.send(());
+    send.send(());
+    transport.poll_once();
+    assert_eq!(transport.read_config_generation(), generation + 1);
+
+    // Nothing new to report.
+    transport.poll_once();
+    assert_eq!(transport.read_config_generation(), generation + 1);
+}
+
+fn config_change_test_device() -> (Box<dyn DynVirtioDevice>, mesh::Sender<()>) {
+    let (send, recv) = mesh::channel();
+    let mut device = PartialFailTestDevice::new(1, 99);
+    device.config_change_recv = Some(recv);
+    (Box::new(device), send)
+}
+
+#[async_test]
+async fn device_config_change_mmio(driver: DefaultDriver) {
+    let (device, send) = config_change_test_device();
+    let mut transport = MmioTestTransport::new(device, &driver, 1);
+    verify_device_config_change(&mut transport, send).await;
+}
+
+#[async_test]
+async fn device_config_change_pci(driver: DefaultDriver) {
+    let (device, send) = config_change_test_device();
+    let mut transport = PciTestTransport::new(device, &driver, 1);
+    verify_device_config_change(&mut transport, send).await;
+}
+
 /// Test for the `write_at_offset` address-overflow hardening gap.
 ///
 /// The readable-payload reader (`read_from_payload_at_offset`) uses
@@ -93,12 +93,14 @@ pub(crate) struct VirtioTransportCore {
     pub pending_status_deferred: Option<DeferredWrite>,
     #[inspect(with = "Vec::len")]
     pub stalled_io: Vec<StalledIo>,
+    #[inspect(skip)]
+    pub config_change_recv: Option<mesh::Receiver<()>>,
 }
 
 impl VirtioTransportCore {
     /// Create a new transport core, spawning the device task.
     pub fn new(
-        device: Box<dyn DynVirtioDevice>,
+        mut device: Box<dyn DynVirtioDevice>,
         driver: &impl Spawn,
         guest_memory: GuestMemory,
         doorbell_registration: Option<Arc<dyn DoorbellRegistration>>,
@@ -129,6 +131,7 @@ pub fn new(
             .with_version_1(true)
             .with_access_platform(true);
         let supports_save_restore = device.supports_save_restore();
+        let config_change_recv = device.take_config_change_recv();
 
         let (sender, receiver) = mesh::channel();
         let _device_task = driver.spawn("virtio-device-task", async move {
@@ -153,6 +156,7 @@ pub fn new(
             guest_memory,
             pending_status_deferred: None,
             stalled_io: Vec::new(),
+            config_change_recv,
         })
     }
 
@@ -190,6 +194,7 @@ pub fn reset_status(&mut self, ops: &mut dyn TransportOps) {
             device_feature: _,
             supports_save_restore: _,
             guest_memory: _,
+            config_change_recv: _,
 
             // Async state machine — not owned by reset_status.
             state: _,
@@ -345,6 +350,18 @@ pub fn write_device_status(&mut self, ops: &mut dyn TransportOps, val: u8) {
     pub fn poll_device(&mut self, ops: &mut dyn TransportOps, cx: &mut std::task::Context<'_>) {
         self.poll_waker = Some(cx.waker().clone());
 
+        // Coalesce any config changes reported by the device into a single
+        // generation bump.
+        let mut config_changed = false;
+        if let Some(recv) = &mut self.config_change_recv {
+            while let Poll::Ready(Ok(())) = recv.poll_recv(cx) {
+                config_changed = true;
+            }
+        }
+        if config_changed {
+            self.update_config_generation(ops);
+        }
+
         if let Poll::Ready(result) = self.state.poll(cx) {
             // Complete the deferred STATUS write before applying the
             // result, since apply_transport_result may call reset_status
@@ -92,7 +92,7 @@ fn virtio_class_code(device_id: VirtioDeviceType) -> (ClassCode, Subclass) {
             ClassCode::NETWORK_CONTROLLER,
             Subclass::NETWORK_CONTROLLER_ETHERNET,
         ),
-        VirtioDeviceType::BLK => (
+        VirtioDeviceType::BLK | VirtioDeviceType::SCSI => (
             ClassCode::MASS_STORAGE_CONTROLLER,
             Subclass::MASS_STORAGE_CONTROLLER_SCSI,
         ),
@@ -100,9 +100,18 @@ fn virtio_class_code(device_id: VirtioDeviceType) -> (ClassCode, Subclass) {
             ClassCode::SIMPLE_COMMUNICATION_CONTROLLER,
             Subclass::SIMPLE_COMMUNICATION_CONTROLLER_OTHER,
         ),
+        VirtioDeviceType::GPU => (
+            ClassCode::DISPLAY_CONTROLLER,
+            Subclass::DISPLAY_CONTROLLER_OTHER,
+        ),
+        VirtioDeviceType::INPUT => (
+            ClassCode::INPUT_DEVICE_CONTROLLER,
+            Subclass::INPUT_DEVICE_CONTROLLER_OTHER,
+        ),
         // These device types have no well-established class code; report a
         // generic base system peripheral.
         VirtioDeviceType::RNG
+        | VirtioDeviceType::BALLOON
         | VirtioDeviceType::P9
         | VirtioDeviceType::VSOCK
         | VirtioDeviceType::FS
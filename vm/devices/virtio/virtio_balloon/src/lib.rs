@@ -0,0 +1,628 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio memory balloon device.
+//!
+//! Implements the virtio balloon device (device ID 5) as specified in the
+//! VIRTIO 1.3 specification, §5.5 "Traditional Memory Balloon Device". The
+//! host sets a target balloon size with [`BalloonRequest::SetTarget`], and the
+//! guest driver inflates the balloon by handing pages to the device, which
+//! releases the host memory backing them.
+//!
+//! # Queues
+//!
+//! | Queue | Present when | Purpose |
+//! |-------|--------------|---------|
+//! | inflateq | always | pages the guest gives up |
+//! | deflateq | always | pages the guest takes back |
+//! | statsq | `F_STATS_VQ` | guest memory statistics |
+//! | reporting_vq | `F_PAGE_REPORTING` | free page ranges the host may release |
+//!
+//! Queues that are not negotiated take up no queue index, so the reporting
+//! queue's index depends on whether the statistics queue is in use.
+//!
+//! # Statistics
+//!
+//! The guest keeps a single statistics buffer outstanding. The device reads
+//! it without consuming it from the ring; to get fresh numbers, it returns the
+//! buffer to the guest and waits for the guest to post it again.
+//!
+//! # Saved state
+//!
+//! The target and the size reported by the guest are not part of the saved
+//! state. After a restore, the target is zero until the host sets it again.
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+mod spec;
+#[cfg(test)]
+mod tests;
+
+use anyhow::Context as _;
+use futures::StreamExt;
+use futures_concurrency::future::Race as _;
+use guestmem::GuestMemory;
+use inspect::Inspect;
+use inspect::InspectMut;
+use mesh::rpc::Rpc;
+use mesh::rpc::RpcSend;
+use pal_async::task::Spawn;
+use pal_async::task::Task;
+use pal_async::timer::PolledTimer;
+use pal_async::wait::PolledWait;
+use parking_lot::Mutex;
+use spec::*;
+use std::future::pending;
+use std::sync::Arc;
+use std::time::Duration;
+use task_control::AsyncRun;
+use task_control::Cancelled;
+use task_control::InspectTaskMut;
+use task_control::StopTask;
+use task_control::TaskControl;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::PeekedWork;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use virtio::VirtioQueueCallbackWork;
+use virtio::queue::QueueState;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio_resources::balloon::BalloonRequest;
+use virtio_resources::balloon::BalloonStats;
+use virtio_resources::balloon::BalloonStatus;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// The maximum number of queues: inflate, deflate, statistics and free page
+/// reporting.
+const MAX_QUEUES: usize = 4;
+
+/// How long a query waits for the guest to refresh its statistics.
+const STATS_REFRESH_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Maximum number of PFNs read from a single inflate or deflate buffer, to
+/// bound host allocations. Linux sends at most 256.
+const MAX_PFNS_PER_REQUEST: usize = 1024;
+
+/// Maximum number of statistics read from a single buffer.
+const MAX_STATS: usize = 64;
+
+/// A virtio balloon device.
+#[derive(InspectMut)]
+pub struct VirtioBalloonDevice {
+    driver: VmTaskDriver,
+    deflate_on_oom: bool,
+    free_page_reporting: bool,
+    #[inspect(flatten)]
+    shared: Arc<Mutex<SharedState>>,
+    #[inspect(skip)]
+    queue_kinds: [Option<QueueKind>; MAX_QUEUES],
+    #[inspect(skip)]
+    config_change_recv: Option<mesh::Receiver<()>>,
+    #[inspect(skip)]
+    _control_task: Task<()>,
+    #[inspect(mut)]
+    worker: TaskControl<BalloonWorker, BalloonQueues>,
+}
+
+impl VirtioBalloonDevice {
+    /// Creates a new balloon device, controlled by requests on `control`.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        deflate_on_oom: bool,
+        free_page_reporting: bool,
+        control: mesh::Receiver<BalloonRequest>,
+    ) -> Self {
+        let driver = driver_source.simple();
+        let shared = Arc::new(Mutex::new(SharedState::default()));
+        let (config_change_send, config_change_recv) = mesh::channel();
+        let (refresh_send, refresh_recv) = mesh::channel();
+        let control_task = driver.spawn(
+            "virtio-balloon-control",
+            run_control(
+                driver.clone(),
+                shared.clone(),
+                config_change_send,
+                refresh_send,
+                control,
+            ),
+        );
+        Self {
+            driver,
+            deflate_on_oom,
+            free_page_reporting,
+            shared: shared.clone(),
+            queue_kinds: [None; MAX_QUEUES],
+            config_change_recv: Some(config_change_recv),
+            _control_task: control_task,
+            worker: TaskControl::new(BalloonWorker {
+                shared,
+                refresh_recv,
+                pending_refresh: Vec::new(),
+            }),
+        }
+    }
+}
+
+impl VirtioDevice for VirtioBalloonDevice {
+    fn traits(&self) -> DeviceTraits {
+        let mut features = 1 << VIRTIO_BALLOON_F_STATS_VQ;
+        if self.deflate_on_oom {
+            features |= 1 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM;
+        }
+        if self.free_page_reporting {
+            features |= 1 << VIRTIO_BALLOON_F_PAGE_REPORTING;
+        }
+        DeviceTraits {
+            device_id: virtio::spec::VirtioDeviceType::BALLOON,
+            device_features: VirtioDeviceFeatures::new()
+                .with_device_specific_low(features)
+                .with_ring_event_idx(true)
+                .with_ring_indirect_desc(true)
+                .with_ring_packed(true),
+            max_queues: MAX_QUEUES as u16,
+            device_register_length: CONFIG_LEN,
+            shared_memory: DeviceTraitsSharedMemory::default(),
+        }
+    }
+
+    async fn read_registers_u32(&mut self, offset: u16) -> u32 {
+        let shared = self.shared.lock();
+        match offset {
+            CONFIG_NUM_PAGES => shared.num_pages,
+            CONFIG_ACTUAL => shared.actual,
+            _ => {
+                tracelimit::warn_ratelimited!(offset, "invalid config read offset");
+                0
+            }
+        }
+    }
+
+    async fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        match offset {
+            CONFIG_ACTUAL => self.shared.lock().actual = val,
+            _ => {
+                tracelimit::warn_ratelimited!(offset, "invalid config write offset");
+            }
+        }
+    }
+
+    fn take_config_change_recv(&mut self) -> Option<mesh::Receiver<()>> {
+        self.config_change_recv.take()
+    }
+
+    async fn start_queue(
+        &mut self,
+        idx: u16,
+        resources: QueueResources,
+        features: &VirtioDeviceFeatures,
+        initial_state: Option<QueueState>,
+    ) -> anyhow::Result<()> {
+        let kind = QueueKind::from_index(features, idx)
+            .with_context(|| format!("queue {idx} is not in use"))?;
+
+        let queue_event = PolledWait::new(&self.driver, resources.event)
+            .context("failed to create polled wait")?;
+        let queue = VirtioQueue::new(
+            *features,
+            resources.params,
+            resources.guest_memory.clone(),
+            resources.notify,
+            queue_event,
+            initial_state,
+        )
+        .context("failed to create virtio queue")?;
+
+        if self.worker.has_state() {
+            self.worker.stop().await;
+        } else {
+            self.worker.insert(
+                &self.driver,
+                "virtio-balloon",
+                BalloonQueues {
+                    mem: resources.guest_memory,
+                    inflateq: None,
+                    deflateq: None,
+                    statsq: None,
+                    reportingq: None,
+                    stats_read: false,
+                },
+            );
+        }
+        let state = self.worker.state_mut().unwrap();
+        if kind == QueueKind::Stats {
+            state.stats_read = false;
+        }
+        *state.queue_mut(kind) = Some(queue);
+        self.queue_kinds[idx as usize] = Some(kind);
+        self.worker.start();
+        Ok(())
+    }
+
+    async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
+        let kind = self.queue_kinds[idx as usize].take()?;
+        if !self.worker.has_state() {
+            return None;
+        }
+
+        // The worker is shared by all the queues. Stop it to take this queue,
+        // then restart it if any others remain.
+        self.worker.stop().await;
+        let state = self.worker.state_mut().unwrap();
+        let queue = state.queue_mut(kind).take();
+        if self.queue_kinds.iter().all(Option::is_none) {
+            self.worker.remove();
+        } else {
+            self.worker.start();
+        }
+        queue.map(|q| q.queue_state())
+    }
+
+    async fn reset(&mut self) {
+        let mut shared = self.shared.lock();
+        shared.actual = 0;
+        shared.stats = None;
+    }
+
+    fn supports_save_restore(&self) -> bool {
+        true
+    }
+}
+
+/// The role of a virtqueue.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum QueueKind {
+    Inflate,
+    Deflate,
+    Stats,
+    Reporting,
+}
+
+impl QueueKind {
+    /// Maps a queue index to its role. Queues for features the driver did not
+    /// negotiate are skipped when numbering.
+    fn from_index(features: &VirtioDeviceFeatures, idx: u16) -> Option<Self> {
+        let negotiated = |bit: u32| features.device_specific_low() & (1 << bit) != 0;
+        [
+            Some(Self::Inflate),
+            Some(Self::Deflate),
+            negotiated(VIRTIO_BALLOON_F_STATS_VQ).then_some(Self::Stats),
+            negotiated(VIRTIO_BALLOON_F_PAGE_REPORTING).then_some(Self::Reporting),
+        ]
+        .into_iter()
+        .flatten()
+        .nth(idx.into())
+    }
+}
+
+/// State shared between the device, its worker, and the control task.
+#[derive(Inspect, Default)]
+struct SharedState {
+    /// The balloon size requested by the host, in 4KiB pages.
+    num_pages: u32,
+    /// The balloon size reported by the guest, in 4KiB pages.
+    actual: u32,
+    #[inspect(with = "Option::is_some")]
+    stats: Option<BalloonStats>,
+    inflated_pages: u64,
+    deflated_pages: u64,
+    released_pages: u64,
+    reported_bytes: u64,
+}
+
+impl SharedState {
+    fn status(&self) -> BalloonStatus {
+        BalloonStatus {
+            target: u64::from(self.num_pages) << VIRTIO_BALLOON_PFN_SHIFT,
+            actual: u64::from(self.actual) << VIRTIO_BALLOON_PFN_SHIFT,
+            reported: self.reported_bytes,
+            stats: self.stats.clone(),
+        }
+    }
+}
+
+/// Handles host requests. This runs for the lifetime of the device, so that
+/// the host can set the target while the guest driver is not loaded.
+async fn run_control(
+    driver: VmTaskDriver,
+    shared: Arc<Mutex<SharedState>>,
+    config_change_send: mesh::Sender<()>,
+    refresh_send: mesh::Sender<Rpc<(), ()>>,
+    mut recv: mesh::Receiver<BalloonRequest>,
+) {
+    let mut timer = PolledTimer::new(&driver);
+    while let Ok(req) = recv.recv().await {
+        match req {
+            BalloonRequest::SetTarget(rpc) => rpc.handle_sync(|target| {
+                let num_pages = (target >> VIRTIO_BALLOON_PFN_SHIFT)
+                    .try_into()
+                    .unwrap_or(u32::MAX);
+                shared.lock().num_pages = num_pages;
+                config_change_send.send(());
+            }),
+            BalloonRequest::Query(rpc) => {
+                rpc.handle(async |()| {
+                    // Give the guest a chance to send fresh statistics, but
+                    // don't wait long: the VM may be paused, or the driver
+                    // may not be loaded.
+                    let refresh = refresh_send.call(|rpc| rpc, ());
+                    (
+                        async {
+                            let _ = refresh.await;
+                        },
+                        timer.sleep(STATS_REFRESH_TIMEOUT),
+                    )
+                        .race()
+                        .await;
+                    shared.lock().status()
+                })
+                .await
+            }
+        }
+    }
+}
+
+#[derive(InspectMut)]
+struct BalloonWorker {
+    #[inspect(skip)]
+    shared: Arc<Mutex<SharedState>>,
+    #[inspect(skip)]
+    refresh_recv: mesh::Receiver<Rpc<(), ()>>,
+    #[inspect(with = "Vec::len")]
+    pending_refresh: Vec<Rpc<(), ()>>,
+}
+
+#[derive(InspectMut)]
+struct BalloonQueues {
+    #[inspect(skip)]
+    mem: GuestMemory,
+    inflateq: Option<VirtioQueue>,
+    deflateq: Option<VirtioQueue>,
+    statsq: Option<VirtioQueue>,
+    reportingq: Option<VirtioQueue>,
+    /// Whether the guest's outstanding statistics buffer has been read.
+    stats_read: bool,
+}
+
+impl BalloonQueues {
+    fn queue_mut(&mut self, kind: QueueKind) -> &mut Option<VirtioQueue> {
+        match kind {
+            QueueKind::Inflate => &mut self.inflateq,
+            QueueKind::Deflate => &mut self.deflateq,
+            QueueKind::Stats => &mut self.statsq,
+            QueueKind::Reporting => &mut self.reportingq,
+        }
+    }
+}
+
+impl InspectTaskMut<BalloonQueues> for BalloonWorker {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>, state: Option<&mut BalloonQueues>) {
+        req.respond().merge(self).merge(state);
+    }
+}
+
+impl AsyncRun<BalloonQueues> for BalloonWorker {
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        state: &mut BalloonQueues,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(self.run_queues(state)).await.map(|r| {
+            if let Err(err) = r {
+                tracelimit::error_ratelimited!(
+                    err = &err as &dyn std::error::Error,
+                    "virtio-balloon queue error"
+                );
+            }
+        })
+    }
+}
+
+impl BalloonWorker {
+    /// Processes all the running queues.
+    ///
+    /// This must be cancel safe: the worker is stopped at an arbitrary await
+    /// point whenever a queue starts or stops.
+    async fn run_queues(&mut self, state: &mut BalloonQueues) -> Result<(), std::io::Error> {
+        let Self {
+            shared,
+            refresh_recv,
+            pending_refresh,
+        } = self;
+        let BalloonQueues {
+            mem,
+            inflateq,
+            deflateq,
+            statsq,
+            reportingq,
+            stats_read,
+        } = state;
+        let shared = &**shared;
+        let mem = &*mem;
+
+        let inflate = async {
+            let Some(queue) = inflateq else {
+                return pending::<Result<(), std::io::Error>>().await;
+            };
+            loop {
+                let work = next_work(queue).await?;
+                let mut pfns = read_pfns(mem, &work);
+                let released = release_pages(mem, &mut pfns);
+                let mut shared = shared.lock();
+                shared.inflated_pages += pfns.len() as u64;
+                shared.released_pages += released;
+                drop(shared);
+                queue.complete(work, 0);
+            }
+        };
+
+        let deflate = async {
+            let Some(queue) = deflateq else {
+                return pending::<Result<(), std::io::Error>>().await;
+            };
+            loop {
+                // Released pages are repopulated with zeroes when the guest
+                // touches them, so there is nothing to do but count.
+                let work = next_work(queue).await?;
+                let pfns = read_pfns(mem, &work);
+                shared.lock().deflated_pages += pfns.len() as u64;
+                queue.complete(work, 0);
+            }
+        };
+
+        let stats = async {
+            let Some(queue) = statsq else {
+                // Without a statistics queue, there is nothing to refresh.
+                while let Some(rpc) = refresh_recv.next().await {
+                    rpc.complete(());
+                }
+                return pending::<Result<(), std::io::Error>>().await;
+            };
+            loop {
+                if !*stats_read {
+                    // Read the guest's buffer, but leave it on the ring until
+                    // the next refresh.
+                    let work = queue.peek().await?;
+                    let stats = read_stats(mem, &work);
+                    shared.lock().stats = Some(stats);
+                    *stats_read = true;
+                    for rpc in pending_refresh.drain(..) {
+                        rpc.complete(());
+                    }
+                    // Requests that arrived while waiting are satisfied too.
+                    while let Ok(rpc) = refresh_recv.try_recv() {
+                        rpc.complete(());
+                    }
+                } else {
+                    if pending_refresh.is_empty() {
+                        let Some(rpc) = refresh_recv.next().await else {
+                            return pending::<Result<(), std::io::Error>>().await;
+                        };
+                        pending_refresh.push(rpc);
+                    }
+                    // Return the buffer to ask the guest for fresh statistics.
+                    let work = queue.peek().await?.consume();
+                    queue.complete(work, 0);
+                    *stats_read = false;
+                }
+            }
+        };
+
+        let report = async {
+            let Some(queue) = reportingq else {
+                return pending::<Result<(), std::io::Error>>().await;
+            };
+            loop {
+                let work = next_work(queue).await?;
+                let mut reported = 0;
+                for payload in work.payload.iter().filter(|p| p.writeable) {
+                    if discard(mem, payload.address, payload.length.into()) {
+                        reported += u64::from(payload.length);
+                    }
+                }
+                shared.lock().reported_bytes += reported;
+                queue.complete(work, 0);
+            }
+        };
+
+        (inflate, deflate, stats, report).race().await
+    }
+}
+
+async fn next_work(queue: &mut VirtioQueue) -> Result<VirtioQueueCallbackWork, std::io::Error> {
+    match queue.next().await {
+        Some(work) => work,
+        None => pending().await,
+    }
+}
+
+/// Reads the 4KiB page frame numbers in an inflate or deflate buffer.
+fn read_pfns(mem: &GuestMemory, work: &VirtioQueueCallbackWork) -> Vec<u64> {
+    let len = (work.get_payload_length(false) as usize).min(MAX_PFNS_PER_REQUEST * 4);
+    let mut buf = vec![0u8; len];
+    if let Err(err) = work.read(mem, &mut buf) {
+        tracelimit::warn_ratelimited!(
+            error = &err as &dyn std::error::Error,
+            "failed to read balloon page frame numbers"
+        );
+        return Vec::new();
+    }
+    buf.chunks_exact(4)
+        .map(|pfn| u32::from_le_bytes(pfn.try_into().unwrap()).into())
+        .collect()
+}
+
+/// Releases the host memory behind the pages in `pfns`, merging adjacent
+/// pages into a single range. Returns the number of pages released.
+fn release_pages(mem: &GuestMemory, pfns: &mut [u64]) -> u64 {
+    pfns.sort_unstable();
+    let mut released = 0;
+    let mut pfns = pfns.iter().copied().peekable();
+    while let Some(start) = pfns.next() {
+        let mut end = start + 1;
+        while let Some(pfn) = pfns.next_if(|&pfn| pfn <= end) {
+            end = end.max(pfn + 1);
+        }
+        if discard(
+            mem,
+            start << VIRTIO_BALLOON_PFN_SHIFT,
+            (end - start) << VIRTIO_BALLOON_PFN_SHIFT,
+        ) {
+            released += end - start;
+        }
+    }
+    released
+}
+
+fn discard(mem: &GuestMemory, gpa: u64, len: u64) -> bool {
+    match mem.discard_at(gpa, len) {
+        Ok(released) => released,
+        Err(err) => {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                gpa,
+                len,
+                "failed to release balloon memory"
+            );
+            false
+        }
+    }
+}
+
+/// Parses a statistics buffer. Unknown tags are ignored.
+fn read_stats(mem: &GuestMemory, work: &PeekedWork<'_>) -> BalloonStats {
+    let mut buf = vec![0u8; MAX_STATS * STAT_ENTRY_SIZE];
+    let mut stats = BalloonStats::default();
+    let len = match work.read(mem, &mut buf) {
+        Ok(len) => len,
+        Err(err) => {
+            tracelimit::warn_ratelimited!(
+                error = &err as &dyn std::error::Error,
+                "failed to read balloon statistics"
+            );
+            return stats;
+        }
+    };
+    for entry in buf[..len].chunks_exact(STAT_ENTRY_SIZE) {
+        let tag = u16::from_le_bytes(entry[..2].try_into().unwrap());
+        let val = u64::from_le_bytes(entry[2..].try_into().unwrap());
+        let field = match tag {
+            VIRTIO_BALLOON_S_SWAP_IN => &mut stats.swap_in,
+            VIRTIO_BALLOON_S_SWAP_OUT => &mut stats.swap_out,
+            VIRTIO_BALLOON_S_MAJFLT => &mut stats.major_faults,
+            VIRTIO_BALLOON_S_MINFLT => &mut stats.minor_faults,
+            VIRTIO_BALLOON_S_MEMFREE => &mut stats.free_memory,
+            VIRTIO_BALLOON_S_MEMTOT => &mut stats.total_memory,
+            VIRTIO_BALLOON_S_AVAIL => &mut stats.available_memory,
+            VIRTIO_BALLOON_S_CACHES => &mut stats.disk_caches,
+            VIRTIO_BALLOON_S_HTLB_PGALLOC => &mut stats.hugetlb_allocations,
+            VIRTIO_BALLOON_S_HTLB_PGFAIL => &mut stats.hugetlb_failures,
+            _ => continue,
+        };
+        *field = Some(val);
+    }
+    stats
+}
@@ -0,0 +1,39 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio balloon spec constants.
+
+/// Feature bit: the statistics queue is present.
+pub const VIRTIO_BALLOON_F_STATS_VQ: u32 = 1;
+/// Feature bit: the guest may deflate the balloon when it runs out of memory.
+pub const VIRTIO_BALLOON_F_DEFLATE_ON_OOM: u32 = 2;
+/// Feature bit: the free page reporting queue is present.
+pub const VIRTIO_BALLOON_F_PAGE_REPORTING: u32 = 5;
+
+/// Page frame numbers on the inflate and deflate queues are always in units
+/// of 4KiB, whatever the guest's page size.
+pub const VIRTIO_BALLOON_PFN_SHIFT: u32 = 12;
+
+/// Config space offset of `num_pages`, the balloon size requested by the
+/// host. Read-only for the guest.
+pub const CONFIG_NUM_PAGES: u16 = 0;
+/// Config space offset of `actual`, the balloon size reported by the guest.
+pub const CONFIG_ACTUAL: u16 = 4;
+/// Size of the config space fields this device implements.
+pub const CONFIG_LEN: u32 = 8;
+
+/// Size of a `virtio_balloon_stat` entry: a le16 tag followed by a le64
+/// value, packed.
+pub const STAT_ENTRY_SIZE: usize = 10;
+
+// Statistics tags (spec §5.5.6.3).
+pub const VIRTIO_BALLOON_S_SWAP_IN: u16 = 0;
+pub const VIRTIO_BALLOON_S_SWAP_OUT: u16 = 1;
+pub const VIRTIO_BALLOON_S_MAJFLT: u16 = 2;
+pub const VIRTIO_BALLOON_S_MINFLT: u16 = 3;
+pub const VIRTIO_BALLOON_S_MEMFREE: u16 = 4;
+pub const VIRTIO_BALLOON_S_MEMTOT: u16 = 5;
+pub const VIRTIO_BALLOON_S_AVAIL: u16 = 6;
+pub const VIRTIO_BALLOON_S_CACHES: u16 = 7;
+pub const VIRTIO_BALLOON_S_HTLB_PGALLOC: u16 = 8;
+pub const VIRTIO_BALLOON_S_HTLB_PGFAIL: u16 = 9;
@@ -0,0 +1,360 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tests for the virtio-balloon device, driving its queues through guest
+//! memory as a guest driver would.
+
+use crate::QueueKind;
+use crate::VirtioBalloonDevice;
+use crate::spec::*;
+use guestmem::GuestMemory;
+use mesh::rpc::RpcSend;
+use pal_async::DefaultDriver;
+use pal_async::async_test;
+use pal_async::timer::PolledTimer;
+use pal_event::Event;
+use std::time::Duration;
+use test_with_tracing::test;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::queue::QueueParams;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio::spec::queue::DescriptorFlags;
+use virtio::test_helpers::init_avail_ring;
+use virtio::test_helpers::init_used_ring;
+use virtio::test_helpers::make_available;
+use virtio::test_helpers::wait_for_used;
+use virtio::test_helpers::write_descriptor;
+use virtio_resources::balloon::BalloonRequest;
+use vmcore::interrupt::Interrupt;
+use vmcore::vm_task::SingleDriverBackend;
+use vmcore::vm_task::VmTaskDriverSource;
+
+const QUEUE_SIZE: u16 = 16;
+
+// Each queue gets 64KiB: descriptors, then the avail ring, then the used ring.
+const QUEUE_STRIDE: u64 = 0x10000;
+const AVAIL_OFFSET: u64 = 0x1000;
+const USED_OFFSET: u64 = 0x2000;
+
+// Buffers passed on the queues.
+const BUFFER_BASE: u64 = 0x40000;
+// Guest pages handed to the balloon. 64KiB aligned so that they cover whole
+// host pages on any host.
+const DATA_BASE: u64 = 0x100000;
+const TOTAL_MEM_SIZE: usize = 0x200000;
+
+const ALL_FEATURES: u32 = (1 << VIRTIO_BALLOON_F_STATS_VQ)
+    | (1 << VIRTIO_BALLOON_F_DEFLATE_ON_OOM)
+    | (1 << VIRTIO_BALLOON_F_PAGE_REPORTING);
+
+struct TestQueue {
+    base: u64,
+    event: Event,
+    interrupt_event: Event,
+    avail_idx: u16,
+    used_idx: u16,
+}
+
+struct TestHarness {
+    device: VirtioBalloonDevice,
+    mem: GuestMemory,
+    driver: DefaultDriver,
+    control: mesh::Sender<BalloonRequest>,
+    queues: Vec<TestQueue>,
+}
+
+impl TestHarness {
+    fn new(driver: &DefaultDriver) -> Self {
+        let mapping = sparse_mmap::SparseMapping::new(TOTAL_MEM_SIZE).unwrap();
+        mapping.alloc(0, TOTAL_MEM_SIZE).unwrap();
+        let mem = GuestMemory::new("test", mapping);
+
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+        let (control, control_recv) = mesh::channel();
+        let device = VirtioBalloonDevice::new(&driver_source, true, true, control_recv);
+
+        Self {
+            device,
+            mem,
+            driver: driver.clone(),
+            control,
+            queues: Vec::new(),
+        }
+    }
+
+    /// Starts `count` queues with the given device-specific features.
+    async fn enable(&mut self, features: u32, count: u16) {
+        let features = VirtioDeviceFeatures::new().with_device_specific_low(features);
+        for idx in 0..count {
+            let queue = TestQueue {
+                base: idx as u64 * QUEUE_STRIDE,
+                event: Event::new(),
+                interrupt_event: Event::new(),
+                avail_idx: 0,
+                used_idx: 0,
+            };
+            init_avail_ring(&self.mem, queue.base + AVAIL_OFFSET);
+            init_used_ring(&self.mem, queue.base + USED_OFFSET);
+            self.device
+                .start_queue(
+                    idx,
+                    QueueResources {
+                        params: QueueParams {
+                            size: QUEUE_SIZE,
+                            enable: true,
+                            desc_addr: queue.base,
+                            avail_addr: queue.base + AVAIL_OFFSET,
+                            used_addr: queue.base + USED_OFFSET,
+                        },
+                        notify: Interrupt::from_event(queue.interrupt_event.clone()),
+                        event: queue.event.clone(),
+                        guest_memory: self.mem.clone(),
+                    },
+                    &features,
+                    None,
+                )
+                .await
+                .unwrap();
+            self.queues.push(queue);
+        }
+    }
+
+    /// Posts a single-descriptor buffer on queue `idx` and notifies the
+    /// device.
+    fn post(&mut self, idx: usize, desc: u16, gpa: u64, len: u32, writeable: bool) {
+        let queue = &mut self.queues[idx];
+        write_descriptor(
+            &self.mem,
+            queue.base,
+            desc,
+            gpa,
+            len,
+            DescriptorFlags::new().with_write(writeable),
+            0,
+        );
+        make_available(
+            &self.mem,
+            queue.base + AVAIL_OFFSET,
+            QUEUE_SIZE,
+            desc,
+            &mut queue.avail_idx,
+        );
+        queue.event.signal();
+    }
+
+    /// Writes `pfns` to a buffer and posts it on queue `idx`.
+    fn post_pfns(&mut self, idx: usize, pfns: &[u32]) {
+        let buf: Vec<u8> = pfns.iter().flat_map(|pfn| pfn.to_le_bytes()).collect();
+        let gpa = BUFFER_BASE + idx as u64 * 0x1000;
+        self.mem.write_at(gpa, &buf).unwrap();
+        self.post(idx, 0, gpa, buf.len() as u32, false);
+    }
+
+    /// Writes a statistics buffer and posts it on the statistics queue.
+    fn post_stats(&mut self, stats: &[(u16, u64)]) {
+        let buf: Vec<u8> = stats
+            .iter()
+            .flat_map(|(tag, val)| tag.to_le_bytes().into_iter().chain(val.to_le_bytes()))
+            .collect();
+        let gpa = BUFFER_BASE + 2 * 0x1000;
+        self.mem.write_at(gpa, &buf).unwrap();
+        self.post(2, 0, gpa, buf.len() as u32, false);
+    }
+
+    /// Waits for the device to read the guest's first statistics buffer.
+    async fn wait_for_stats(&self) {
+        let mut timer = PolledTimer::new(&self.driver);
+        while self.device.shared.lock().stats.is_none() {
+            timer.sleep(Duration::from_millis(1)).await;
+        }
+    }
+
+    async fn wait_for_used(&mut self, idx: usize) -> (u16, u32) {
+        let queue = &mut self.queues[idx];
+        wait_for_used(
+            &self.driver,
+            &queue.interrupt_event,
+            &self.mem,
+            queue.base + USED_OFFSET,
+            QUEUE_SIZE,
+            &mut queue.used_idx,
+        )
+        .await
+    }
+
+    fn pfn(offset: u64) -> u32 {
+        ((DATA_BASE + offset) >> VIRTIO_BALLOON_PFN_SHIFT) as u32
+    }
+}
+
+#[test]
+fn queue_index_mapping() {
+    let features = |bits: u32| VirtioDeviceFeatures::new().with_device_specific_low(bits);
+
+    let all = features(ALL_FEATURES);
+    assert_eq!(QueueKind::from_index(&all, 0), Some(QueueKind::Inflate));
+    assert_eq!(QueueKind::from_index(&all, 1), Some(QueueKind::Deflate));
+    assert_eq!(QueueKind::from_index(&all, 2), Some(QueueKind::Stats));
+    assert_eq!(QueueKind::from_index(&all, 3), Some(QueueKind::Reporting));
+
+    // Without the statistics queue, the reporting queue moves down.
+    let reporting = features(1 << VIRTIO_BALLOON_F_PAGE_REPORTING);
+    assert_eq!(
+        QueueKind::from_index(&reporting, 2),
+        Some(QueueKind::Reporting)
+    );
+    assert_eq!(QueueKind::from_index(&reporting, 3), None);
+
+    assert_eq!(QueueKind::from_index(&features(0), 2), None);
+}
+
+#[async_test]
+async fn traits_and_target(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver);
+    let traits = harness.device.traits();
+    assert_eq!(traits.device_features.device_specific_low(), ALL_FEATURES);
+    let mut config_change = harness.device.take_config_change_recv().unwrap();
+
+    harness
+        .control
+        .call(BalloonRequest::SetTarget, 1 << 20)
+        .await
+        .unwrap();
+    config_change.recv().await.unwrap();
+    assert_eq!(
+        harness.device.read_registers_u32(CONFIG_NUM_PAGES).await,
+        256
+    );
+
+    // The guest reports its progress through `actual`.
+    harness.device.write_registers_u32(CONFIG_ACTUAL, 128).await;
+    // The target is read-only for the guest.
+    harness
+        .device
+        .write_registers_u32(CONFIG_NUM_PAGES, 0)
+        .await;
+    assert_eq!(
+        harness.device.read_registers_u32(CONFIG_NUM_PAGES).await,
+        256
+    );
+
+    // The driver is not running, so the query gives up waiting for fresh
+    // statistics after a timeout.
+    let status = harness
+        .control
+        .call(BalloonRequest::Query, ())
+        .await
+        .unwrap();
+    assert_eq!(status.target, 1 << 20);
+    assert_eq!(status.actual, 128 << 12);
+    assert!(status.stats.is_none());
+}
+
+#[async_test]
+async fn inflate_releases_pages(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver);
+    harness.enable(ALL_FEATURES, 4).await;
+    harness.mem.fill_at(DATA_BASE, 0xaa, 0x30000).unwrap();
+
+    // Inflate 16 pages in the middle, out of order.
+    let pfns: Vec<u32> = (0x10..0x20)
+        .rev()
+        .map(|i| TestHarness::pfn(i << 12))
+        .collect();
+    harness.post_pfns(0, &pfns);
+    let (_, len) = harness.wait_for_used(0).await;
+    assert_eq!(len, 0);
+
+    if cfg!(unix) {
+        assert_eq!(
+            harness.mem.read_plain::<u8>(DATA_BASE + 0x10000).unwrap(),
+            0
+        );
+        assert_eq!(
+            harness.mem.read_plain::<u8>(DATA_BASE + 0x1ffff).unwrap(),
+            0
+        );
+    }
+    assert_eq!(
+        harness.mem.read_plain::<u8>(DATA_BASE + 0xffff).unwrap(),
+        0xaa
+    );
+    assert_eq!(
+        harness.mem.read_plain::<u8>(DATA_BASE + 0x20000).unwrap(),
+        0xaa
+    );
+
+    // Deflating hands the pages back; the guest can use them again.
+    harness.post_pfns(1, &pfns);
+    harness.wait_for_used(1).await;
+    harness
+        .mem
+        .write_plain(DATA_BASE + 0x10000, &0x55u8)
+        .unwrap();
+    assert_eq!(
+        harness.mem.read_plain::<u8>(DATA_BASE + 0x10000).unwrap(),
+        0x55
+    );
+
+    let shared = harness.device.shared.lock();
+    assert_eq!(shared.inflated_pages, 16);
+    assert_eq!(shared.deflated_pages, 16);
+}
+
+#[async_test]
+async fn stats_refresh(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver);
+    harness.enable(ALL_FEATURES, 4).await;
+
+    harness.post_stats(&[
+        (VIRTIO_BALLOON_S_MEMFREE, 100 << 20),
+        (VIRTIO_BALLOON_S_MEMTOT, 1 << 30),
+        (0xffff, 1),
+    ]);
+    harness.wait_for_stats().await;
+    let stats = harness.device.shared.lock().stats.clone().unwrap();
+    assert_eq!(stats.free_memory, Some(100 << 20));
+    assert_eq!(stats.total_memory, Some(1 << 30));
+
+    // The query returns the buffer to the guest and waits for it to come back
+    // with fresh statistics.
+    let query = harness.control.call(BalloonRequest::Query, ());
+    harness.wait_for_used(2).await;
+    harness.post_stats(&[(VIRTIO_BALLOON_S_MEMFREE, 200 << 20)]);
+
+    let stats = query.await.unwrap().stats.unwrap();
+    assert_eq!(stats.free_memory, Some(200 << 20));
+    assert_eq!(stats.total_memory, None);
+}
+
+#[async_test]
+async fn free_page_reporting(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver);
+    // Without the statistics queue, the reporting queue is queue 2.
+    harness
+        .enable(1 << VIRTIO_BALLOON_F_PAGE_REPORTING, 3)
+        .await;
+    harness.mem.fill_at(DATA_BASE, 0xaa, 0x30000).unwrap();
+
+    harness.post(2, 0, DATA_BASE + 0x10000, 0x10000, true);
+    let (_, len) = harness.wait_for_used(2).await;
+    assert_eq!(len, 0);
+
+    let status = harness
+        .control
+        .call(BalloonRequest::Query, ())
+        .await
+        .unwrap();
+    if cfg!(unix) {
+        assert_eq!(status.reported, 0x10000);
+        assert_eq!(
+            harness.mem.read_plain::<u8>(DATA_BASE + 0x10000).unwrap(),
+            0
+        );
+    }
+    assert_eq!(
+        harness.mem.read_plain::<u8>(DATA_BASE + 0x20000).unwrap(),
+        0xaa
+    );
+}
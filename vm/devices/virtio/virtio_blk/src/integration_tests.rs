@@ -89,6 +89,22 @@ fn with_device_driver(
         device_driver: &DefaultDriver,
         disk: Disk,
         read_only: bool,
+    ) -> Self {
+        Self::build(driver, device_driver, disk, read_only, 1)
+    }
+
+    /// Like [`TestHarness::new`], but with `num_queues` request queues. Only
+    /// one of them can be enabled at a time, since they share the rings.
+    fn with_queues(driver: &DefaultDriver, disk: Disk, num_queues: u16) -> Self {
+        Self::build(driver, driver, disk, false, num_queues)
+    }
+
+    fn build(
+        driver: &DefaultDriver,
+        device_driver: &DefaultDriver,
+        disk: Disk,
+        read_only: bool,
+        num_queues: u16,
     ) -> Self {
         let mem = GuestMemory::allocate(TOTAL_MEM_SIZE);
 
@@ -97,7 +113,7 @@ fn with_device_driver(
 
         let driver_source =
             VmTaskDriverSource::new(SingleDriverBackend::new(device_driver.clone()));
-        let device = VirtioBlkDevice::new(&driver_source, disk, read_only);
+        let device = VirtioBlkDevice::new(&driver_source, disk, read_only, num_queues);
 
         let queue_event = Event::new();
         let interrupt_event = Event::new();
@@ -116,11 +132,16 @@ fn with_device_driver(
 
     /// Enable the device with one queue.
     async fn enable(&mut self) {
+        self.enable_queue(0).await;
+    }
+
+    /// Enable request queue `idx` on the harness's rings.
+    async fn enable_queue(&mut self, idx: u16) {
         let interrupt = Interrupt::from_event(self.interrupt_event.clone());
 
         self.device
             .start_queue(
-                0,
+                idx,
                 QueueResources {
                     params: QueueParams {
                         size: QUEUE_SIZE,
@@ -395,22 +416,59 @@ fn post_discard_request(
         discard_sector: u64,
         num_sectors: u32,
         flags: u32,
+    ) -> u64 {
+        self.post_segment_request(
+            head_desc,
+            VIRTIO_BLK_T_DISCARD,
+            discard_sector,
+            num_sectors,
+            flags,
+        )
+    }
+
+    /// Build a write zeroes request descriptor chain. The layout is the same
+    /// as for [`TestHarness::post_discard_request`].
+    ///
+    /// Returns the status byte GPA.
+    fn post_write_zeroes_request(
+        &mut self,
+        head_desc: u16,
+        sector: u64,
+        num_sectors: u32,
+        flags: u32,
+    ) -> u64 {
+        self.post_segment_request(
+            head_desc,
+            VIRTIO_BLK_T_WRITE_ZEROES,
+            sector,
+            num_sectors,
+            flags,
+        )
+    }
+
+    fn post_segment_request(
+        &mut self,
+        head_desc: u16,
+        request_type: u32,
+        seg_sector: u64,
+        num_sectors: u32,
+        flags: u32,
     ) -> u64 {
         // Combined header + discard segment = 16 + 16 = 32 bytes
         let req_gpa = self.alloc_data(32);
         let status_gpa = self.alloc_data(1);
 
-        // Write the header (type=DISCARD, sector field unused for discard)
+        // Write the header (sector field unused for segment requests)
         let header = VirtioBlkReqHeader {
-            request_type: VIRTIO_BLK_T_DISCARD,
+            request_type,
             reserved: 0,
             sector: 0,
         };
         self.mem.write_at(req_gpa, header.as_bytes()).unwrap();
 
-        // Write the discard segment immediately after the header
+        // Write the segment immediately after the header
         let seg = VirtioBlkDiscardWriteZeroes {
-            sector: discard_sector,
+            sector: seg_sector,
             num_sectors,
             flags,
         };
@@ -805,7 +863,7 @@ struct TestDisk4K {
     #[inspect(skip)]
     storage: Mutex<Vec<u8>>,
     #[inspect(skip)]
-    supports_discard: bool,
+    unmap_behavior: disk_backend::UnmapBehavior,
 }
 
 impl TestDisk4K {
@@ -815,12 +873,18 @@ fn new(total_bytes: usize, sector_size: u32) -> Self {
         Self {
             sector_size,
             storage: Mutex::new(vec![0u8; total_bytes]),
-            supports_discard: false,
+            unmap_behavior: disk_backend::UnmapBehavior::Ignored,
         }
     }
 
     fn with_discard(mut self) -> Self {
-        self.supports_discard = true;
+        self.unmap_behavior = disk_backend::UnmapBehavior::Unspecified;
+        self
+    }
+
+    /// Make unmap zero the range, and report that it does.
+    fn with_zeroing_unmap(mut self) -> Self {
+        self.unmap_behavior = disk_backend::UnmapBehavior::Zeroes;
         self
     }
 }
@@ -891,19 +955,24 @@ async fn sync_cache(&self) -> Result<(), DiskError> {
 
     async fn unmap(
         &self,
-        _sector: u64,
-        _count: u64,
+        sector: u64,
+        count: u64,
         _block_level_only: bool,
     ) -> Result<(), DiskError> {
+        if self.unmap_behavior == disk_backend::UnmapBehavior::Zeroes {
+            let offset = sector as usize * self.sector_size as usize;
+            let end = offset + count as usize * self.sector_size as usize;
+            let mut storage = self.storage.lock();
+            if end > storage.len() {
+                return Err(DiskError::IllegalBlock);
+            }
+            storage[offset..end].fill(0);
+        }
         Ok(())
     }
 
     fn unmap_behavior(&self) -> disk_backend::UnmapBehavior {
-        if self.supports_discard {
-            disk_backend::UnmapBehavior::Unspecified
-        } else {
-            disk_backend::UnmapBehavior::Ignored
-        }
+        self.unmap_behavior
     }
 }
 
@@ -1126,6 +1195,158 @@ async fn discard_512b_sector_any_count_succeeds(driver: DefaultDriver) {
     .await;
 }
 
+// --- Write zeroes integration tests ---
+
+fn test_disk_4k_zeroing() -> Disk {
+    Disk::new(TestDisk4K::new(64 * 1024, 4096).with_zeroing_unmap()).unwrap()
+}
+
+/// Write zeroes is only advertised when the backend guarantees that unmapped
+/// sectors read back as zero.
+#[async_test]
+async fn write_zeroes_advertised_only_when_unmap_zeroes(driver: DefaultDriver) {
+    let features = |disk| {
+        TestHarness::new(&driver, disk, false)
+            .device
+            .traits()
+            .device_features
+            .device_specific_low()
+    };
+    assert_eq!(
+        features(test_disk_4k_zeroing()) & (VIRTIO_BLK_F_WRITE_ZEROES | VIRTIO_BLK_F_DISCARD),
+        VIRTIO_BLK_F_WRITE_ZEROES | VIRTIO_BLK_F_DISCARD
+    );
+    assert_eq!(
+        features(test_disk_4k_discard()) & VIRTIO_BLK_F_WRITE_ZEROES,
+        0
+    );
+}
+
+/// Write zeroes, with or without the unmap flag, zeroes exactly the
+/// requested range.
+#[async_test]
+async fn write_zeroes_zeroes_range(driver: DefaultDriver) {
+    for flags in [0, VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP] {
+        let mut harness = TestHarness::new(&driver, test_disk_4k_zeroing(), false);
+        harness.enable().await;
+
+        // Fill virtio sectors 0..24 (disk sectors 0..3).
+        harness.post_write_request(0, 0, &[0xAA; 3 * 4096]);
+        harness.wait_for_used().await;
+
+        // Zero virtio sectors 8..16 (disk sector 1).
+        let status_gpa = harness.post_write_zeroes_request(3, 8, 8, flags);
+        let (_id, used_len) = harness.wait_for_used().await;
+        assert_eq!(used_len, 1);
+        assert_eq!(harness.read_status(status_gpa), VIRTIO_BLK_S_OK);
+
+        let data_gpa = harness.post_read_request(5, 0, 3 * 4096);
+        harness.wait_for_used().await;
+        let mut buf = vec![0u8; 3 * 4096];
+        harness.mem.read_at(data_gpa, &mut buf).unwrap();
+        assert!(buf[..4096].iter().all(|&b| b == 0xAA));
+        assert!(buf[4096..8192].iter().all(|&b| b == 0));
+        assert!(buf[8192..].iter().all(|&b| b == 0xAA));
+    }
+}
+
+/// Write zeroes on a backend whose unmap does not guarantee zeroes is
+/// rejected with UNSUPP rather than silently leaving data in place.
+#[async_test]
+async fn write_zeroes_without_zeroing_unmap_returns_unsupp(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver, test_disk_4k_discard(), false);
+    harness.enable().await;
+    let status_gpa = harness.post_write_zeroes_request(0, 0, 8, 0);
+    harness.wait_for_used().await;
+    assert_eq!(harness.read_status(status_gpa), VIRTIO_BLK_S_UNSUPP);
+}
+
+/// Write zeroes with an unknown flag set is rejected with UNSUPP.
+#[async_test]
+async fn write_zeroes_unknown_flag_returns_unsupp(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver, test_disk_4k_zeroing(), false);
+    harness.enable().await;
+    let status_gpa = harness.post_write_zeroes_request(0, 0, 8, 2);
+    harness.wait_for_used().await;
+    assert_eq!(harness.read_status(status_gpa), VIRTIO_BLK_S_UNSUPP);
+}
+
+/// Write zeroes on a read-only disk should fail with IOERR.
+#[async_test]
+async fn write_zeroes_on_read_only_disk_fails(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver, test_disk_4k_zeroing(), true);
+    harness.enable().await;
+    let status_gpa = harness.post_write_zeroes_request(0, 0, 8, 0);
+    harness.wait_for_used().await;
+    assert_eq!(harness.read_status(status_gpa), VIRTIO_BLK_S_IOERR);
+}
+
+// --- Multiqueue integration tests ---
+
+/// A single-queue device does not advertise MQ.
+#[async_test]
+async fn single_queue_does_not_advertise_mq(driver: DefaultDriver) {
+    let harness = TestHarness::new(&driver, ram_disk(64 * 1024, false), false);
+    let traits = harness.device.traits();
+    assert_eq!(traits.max_queues, 1);
+    assert_eq!(
+        traits.device_features.device_specific_low() & VIRTIO_BLK_F_MQ,
+        0
+    );
+}
+
+/// A multiqueue device advertises MQ and reports its queue count in config
+/// space.
+#[async_test]
+async fn multiqueue_advertised(driver: DefaultDriver) {
+    let mut harness = TestHarness::with_queues(&driver, ram_disk(64 * 1024, false), 4);
+    let traits = harness.device.traits();
+    assert_eq!(traits.max_queues, 4);
+    assert_ne!(
+        traits.device_features.device_specific_low() & VIRTIO_BLK_F_MQ,
+        0
+    );
+    // num_queues is the upper half of the u32 at offset 32.
+    let num_queues = harness.device.read_registers_u32(32).await >> 16;
+    assert_eq!(num_queues, 4);
+}
+
+/// The queue count is clamped to the supported range.
+#[async_test]
+async fn multiqueue_count_clamped(driver: DefaultDriver) {
+    let harness = TestHarness::with_queues(&driver, ram_disk(64 * 1024, false), 0);
+    assert_eq!(harness.device.traits().max_queues, 1);
+    let harness = TestHarness::with_queues(&driver, ram_disk(64 * 1024, false), 1000);
+    assert_eq!(harness.device.traits().max_queues, 64);
+}
+
+/// Requests submitted on each queue other than the first are processed and
+/// completed on that queue.
+#[async_test]
+async fn multiqueue_requests_on_each_queue(driver: DefaultDriver) {
+    let mut harness = TestHarness::with_queues(&driver, ram_disk(64 * 1024, false), 4);
+    for idx in 1..4u16 {
+        harness.enable_queue(idx).await;
+        let pattern = idx as u8;
+        let sector = idx as u64;
+        harness.post_write_request(0, sector, &[pattern; 512]);
+        harness.wait_for_used().await;
+        let data_gpa = harness.post_read_request(3, sector, 512);
+        let (_id, used_len) = harness.wait_for_used().await;
+        assert_eq!(used_len, 513);
+        let mut buf = [0u8; 512];
+        harness.mem.read_at(data_gpa, &mut buf).unwrap();
+        assert!(buf.iter().all(|&b| b == pattern));
+
+        // Hand the rings to the next queue, resetting them to a fresh state.
+        assert!(harness.device.stop_queue(idx).await.is_some());
+        init_avail_ring(&harness.mem, AVAIL_ADDR);
+        init_used_ring(&harness.mem, USED_ADDR);
+        harness.avail_idx = 0;
+        harness.used_idx = 0;
+    }
+}
+
 // --- Bounce buffer integration tests ---
 
 /// Write and read using a descriptor chain that forces the bounce buffer
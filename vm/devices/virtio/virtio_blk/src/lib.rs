@@ -58,18 +58,25 @@
 
 const MAX_IO_DEPTH: usize = 64;
 
+/// The maximum number of request queues. This matches the number of IO
+/// queues offered by the NVMe emulator.
+const MAX_QUEUES: u16 = 64;
+
 /// The virtio-blk device.
-#[derive(InspectMut)]
 pub struct VirtioBlkDevice {
-    #[inspect(flatten, mut)]
-    worker: TaskControl<BlkWorker, BlkQueueState>,
-    #[inspect(skip)]
-    driver: VmTaskDriver,
+    queues: Vec<BlkQueue>,
     read_only: bool,
     supports_discard: bool,
+    supports_write_zeroes: bool,
     config: VirtioBlkConfig,
 }
 
+/// A request queue and the driver its worker task runs on.
+struct BlkQueue {
+    worker: TaskControl<BlkWorker, BlkQueueState>,
+    driver: VmTaskDriver,
+}
+
 /// Persistent worker state. Survives across enable/disable cycles.
 ///
 /// Holds the disk backend, stats counters, and the
@@ -99,6 +106,7 @@ struct WorkerStats {
     write_ops: Counter,
     flush_ops: Counter,
     discard_ops: Counter,
+    write_zeroes_ops: Counter,
     bounce_ops: Counter,
     errors: Counter,
 }
@@ -124,6 +132,7 @@ enum IoStat {
     Write,
     Flush,
     Discard,
+    WriteZeroes,
     Error,
     None,
 }
@@ -137,6 +146,7 @@ fn finish_io(&mut self, queue: &mut VirtioQueue, completion: IoCompletion) {
             IoStat::Write => self.stats.write_ops.increment(),
             IoStat::Flush => self.stats.flush_ops.increment(),
             IoStat::Discard => self.stats.discard_ops.increment(),
+            IoStat::WriteZeroes => self.stats.write_zeroes_ops.increment(),
             IoStat::Error => self.stats.errors.increment(),
             IoStat::None => {}
         }
@@ -226,8 +236,18 @@ enum Event {
 }
 
 impl VirtioBlkDevice {
-    /// Creates a new virtio-blk device backed by the given disk.
-    pub fn new(driver_source: &VmTaskDriverSource, disk: Disk, read_only: bool) -> Self {
+    /// Creates a new virtio-blk device backed by the given disk, with
+    /// `num_queues` request queues, clamped to `1..=64`.
+    ///
+    /// Each queue is processed by its own task, targeted at the VP with the
+    /// same index as the queue.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        disk: Disk,
+        read_only: bool,
+        num_queues: u16,
+    ) -> Self {
+        let num_queues = num_queues.clamp(1, MAX_QUEUES);
         let sector_count = disk.sector_count();
         let sector_size = disk.sector_size();
         let physical_sector_size = disk.physical_sector_size();
@@ -273,8 +293,9 @@ pub fn new(driver_source: &VmTaskDriverSource, disk: Disk, read_only: bool) -> S
             // writeback cache semantics (driver should use FLUSH).
             writeback: 1,
             unused0: 0,
-            // We don't advertise MQ; single queue.
-            num_queues: 1,
+            // Number of request queues (VIRTIO_BLK_F_MQ, advertised only if
+            // more than one).
+            num_queues,
             // Discard fields (VIRTIO_BLK_F_DISCARD, spec §5.2.4).
             // u32::MAX × 512 bytes ≈ 2 TiB per segment; no practical limit.
             max_discard_sectors: u32::MAX,
@@ -283,31 +304,67 @@ pub fn new(driver_source: &VmTaskDriverSource, disk: Disk, read_only: bool) -> S
             // backend's optimal unmap granularity (same as SCSI Optimal
             // Unmap Granularity), converted to 512-byte units.
             discard_sector_alignment: disk.optimal_unmap_sectors() * (sector_size / 512),
-            // Write zeroes fields (VIRTIO_BLK_F_WRITE_ZEROES) — not advertised.
-            max_write_zeroes_sectors: 0,
-            max_write_zeroes_seg: 0,
-            write_zeroes_may_unmap: 0,
+            // Write zeroes fields (VIRTIO_BLK_F_WRITE_ZEROES). Only
+            // advertised if unmapping is guaranteed to zero, since write
+            // zeroes is implemented as an unmap.
+            max_write_zeroes_sectors: u32::MAX,
+            max_write_zeroes_seg: 1,
+            write_zeroes_may_unmap: 1,
             unused1: [0; 3],
             _padding: [0; 4],
         };
 
         let supports_discard = disk.unmap_behavior() != disk_backend::UnmapBehavior::Ignored;
+        let supports_write_zeroes = disk.unmap_behavior() == disk_backend::UnmapBehavior::Zeroes;
+
+        let queues = (0..num_queues)
+            .map(|i| {
+                // Guest drivers typically spread the queues across VPs in
+                // order, so queue N's requests mostly come from VP N.
+                let driver = driver_source
+                    .builder()
+                    .target_vp(i.into())
+                    .run_on_target(true)
+                    .build(format!("virtio-blk-{i}"));
+                BlkQueue {
+                    worker: TaskControl::new(BlkWorker {
+                        disk: disk.clone(),
+                        read_only,
+                        stats: WorkerStats::default(),
+                        ios: FuturesUnordered::new(),
+                    }),
+                    driver,
+                }
+            })
+            .collect();
 
         Self {
-            worker: TaskControl::new(BlkWorker {
-                disk,
-                read_only,
-                stats: WorkerStats::default(),
-                ios: FuturesUnordered::new(),
-            }),
-            driver: driver_source.simple(),
+            queues,
             read_only,
             supports_discard,
+            supports_write_zeroes,
             config,
         }
     }
 }
 
+impl InspectMut for VirtioBlkDevice {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        req.respond()
+            .field("read_only", self.read_only)
+            .field("supports_discard", self.supports_discard)
+            .field("supports_write_zeroes", self.supports_write_zeroes)
+            .field("config", &self.config)
+            .fields_mut(
+                "queues",
+                self.queues
+                    .iter_mut()
+                    .map(|queue| &mut queue.worker)
+                    .enumerate(),
+            );
+    }
+}
+
 impl VirtioDevice for VirtioBlkDevice {
     fn traits(&self) -> DeviceTraits {
         let mut features = VIRTIO_BLK_F_SEG_MAX
@@ -320,9 +377,12 @@ fn traits(&self) -> DeviceTraits {
         }
         if self.supports_discard {
             features |= VIRTIO_BLK_F_DISCARD;
-            // FUTURE: investigate adding VIRTIO_BLK_F_WRITE_ZEROES support
-            // by adding an explicit write_zeroes operation to the DiskIo
-            // backend trait, rather than emulating it with bounce-buffer writes.
+        }
+        if self.supports_write_zeroes {
+            features |= VIRTIO_BLK_F_WRITE_ZEROES;
+        }
+        if self.queues.len() > 1 {
+            features |= VIRTIO_BLK_F_MQ;
         }
 
         DeviceTraits {
@@ -332,7 +392,7 @@ fn traits(&self) -> DeviceTraits {
                 .with_ring_event_idx(true)
                 .with_ring_indirect_desc(true)
                 .with_ring_packed(true),
-            max_queues: 1,
+            max_queues: self.queues.len() as u16,
             // Config space is 60 bytes (size_of minus 4 bytes of struct padding).
             device_register_length: (size_of::<VirtioBlkConfig>() - 4) as u32,
             shared_memory: DeviceTraitsSharedMemory::default(),
@@ -372,9 +432,8 @@ async fn start_queue(
         features: &VirtioDeviceFeatures,
         initial_state: Option<QueueState>,
     ) -> anyhow::Result<()> {
-        assert_eq!(idx, 0);
-
-        let queue_event = PolledWait::new(&self.driver, resources.event)
+        let blk_queue = &mut self.queues[idx as usize];
+        let queue_event = PolledWait::new(&blk_queue.driver, resources.event)
             .context("failed to create queue event")?;
 
         let queue = VirtioQueue::new(
@@ -387,33 +446,33 @@ async fn start_queue(
         )
         .context("failed to create virtio queue")?;
 
-        self.worker.insert(
-            self.driver.clone(),
-            "virtio-blk-worker",
+        blk_queue.worker.insert(
+            blk_queue.driver.clone(),
+            format!("virtio-blk-worker-{idx}"),
             BlkQueueState {
                 queue,
                 memory: resources.guest_memory,
             },
         );
-        self.worker.start();
+        blk_queue.worker.start();
         Ok(())
     }
 
     async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
-        assert_eq!(idx, 0);
-        if !self.worker.has_state() {
+        let worker = &mut self.queues[idx as usize].worker;
+        if !worker.has_state() {
             return None;
         }
         // Stop the worker task (cancels the run loop via until_stopped).
-        self.worker.stop().await;
+        worker.stop().await;
         // Drain in-flight IOs to completion. The FuturesUnordered lives in
         // BlkWorker and survives the stop — its pending disk IO futures are
         // polled here until all descriptors are completed in the used ring.
-        let (worker, queue_state) = self.worker.get_mut();
+        let (blk_worker, queue_state) = worker.get_mut();
         let queue = &mut queue_state.expect("state exists after stop").queue;
-        poll_fn(|cx| worker.poll_drain(queue, cx)).await;
+        poll_fn(|cx| blk_worker.poll_drain(queue, cx)).await;
         // Remove the queue state (drops VirtioQueue).
-        let state = self.worker.remove().queue.queue_state();
+        let state = worker.remove().queue.queue_state();
         Some(state)
     }
 
@@ -485,7 +544,8 @@ async fn process_request_inner(
 
     let request_type = header.request_type;
     // Shift to convert 512-byte virtio sectors to backend disk sectors.
-    // Only meaningful for commands that use sector addressing (IN, OUT, DISCARD).
+    // Only meaningful for commands that use sector addressing (IN, OUT,
+    // DISCARD, WRITE_ZEROES).
     let sector_shift = disk.sector_shift() - 9;
     let sector_mask = (1u64 << sector_shift) - 1; // alignment mask for validation
 
@@ -527,47 +587,85 @@ async fn process_request_inner(
             // Per spec §5.2.6.1: "The unmap bit MUST be zero for discard commands."
             // Per spec §5.2.6.2: "the device MAY deallocate the specified range."
             // Discard is a hint — no data-content guarantee.
-
-            /// Combined header + discard/write-zeroes segment, used to read
-            /// the full request in one `work.read()` call.
-            #[repr(C)]
-            #[derive(
-                zerocopy::FromBytes, zerocopy::IntoBytes, zerocopy::Immutable, zerocopy::KnownLayout,
-            )]
-            struct VirtioBlkDiscardReq {
-                header: VirtioBlkReqHeader,
-                seg: VirtioBlkDiscardWriteZeroes,
-            }
-
-            let mut discard_req = VirtioBlkDiscardReq::new_zeroed();
-            let read_len = work
-                .read(mem, discard_req.as_mut_bytes())
-                .map_err(|_| VIRTIO_BLK_S_IOERR)?;
-            if read_len < size_of_val(&discard_req) {
-                return Err(VIRTIO_BLK_S_IOERR);
-            }
-            let seg = discard_req.seg;
+            let seg = read_segment(mem, work)?;
             // Spec §5.2.6.2: "the device MUST set the status byte to
             // VIRTIO_BLK_S_UNSUPP for discard commands if the unmap flag is set."
             if seg.flags != 0 {
                 return Err(VIRTIO_BLK_S_UNSUPP);
             }
-            // Discard segment has its own sector/count fields (in 512-byte units).
-            let disk_sector = virtio_to_disk_sector(seg.sector, sector_shift, sector_mask)?;
-            let num_sectors = seg.num_sectors as u64;
-            if num_sectors & sector_mask != 0 {
+            let (disk_sector, disk_count) = segment_to_disk_range(&seg, sector_shift, sector_mask)?;
+            disk.unmap(disk_sector, disk_count, false)
+                .await
+                .map_err(|_| VIRTIO_BLK_S_IOERR)?;
+            Ok((0, IoStat::Discard, false))
+        }
+        VIRTIO_BLK_T_WRITE_ZEROES => {
+            // Write zeroes is only advertised when unmapped sectors are
+            // guaranteed to read back as zero, so it is implemented as an
+            // unmap regardless of whether the guest allowed deallocation
+            // via VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP.
+            if disk.unmap_behavior() != disk_backend::UnmapBehavior::Zeroes {
+                return Err(VIRTIO_BLK_S_UNSUPP);
+            }
+            if read_only {
                 return Err(VIRTIO_BLK_S_IOERR);
             }
-            let disk_count = num_sectors >> sector_shift;
+            let seg = read_segment(mem, work)?;
+            if seg.flags & !VIRTIO_BLK_WRITE_ZEROES_FLAG_UNMAP != 0 {
+                return Err(VIRTIO_BLK_S_UNSUPP);
+            }
+            let (disk_sector, disk_count) = segment_to_disk_range(&seg, sector_shift, sector_mask)?;
             disk.unmap(disk_sector, disk_count, false)
                 .await
                 .map_err(|_| VIRTIO_BLK_S_IOERR)?;
-            Ok((0, IoStat::Discard, false))
+            Ok((0, IoStat::WriteZeroes, false))
         }
         _ => Err(VIRTIO_BLK_S_UNSUPP),
     }
 }
 
+/// Read the single segment of a discard or write zeroes request, which
+/// immediately follows the request header.
+fn read_segment(
+    mem: &GuestMemory,
+    work: &VirtioQueueCallbackWork,
+) -> Result<VirtioBlkDiscardWriteZeroes, u8> {
+    /// Combined header + discard/write-zeroes segment, used to read
+    /// the full request in one `work.read()` call.
+    #[repr(C)]
+    #[derive(
+        zerocopy::FromBytes, zerocopy::IntoBytes, zerocopy::Immutable, zerocopy::KnownLayout,
+    )]
+    struct VirtioBlkSegmentReq {
+        header: VirtioBlkReqHeader,
+        seg: VirtioBlkDiscardWriteZeroes,
+    }
+
+    let mut req = VirtioBlkSegmentReq::new_zeroed();
+    let read_len = work
+        .read(mem, req.as_mut_bytes())
+        .map_err(|_| VIRTIO_BLK_S_IOERR)?;
+    if read_len < size_of_val(&req) {
+        return Err(VIRTIO_BLK_S_IOERR);
+    }
+    Ok(req.seg)
+}
+
+/// Convert a discard or write zeroes segment, which has its own sector and
+/// count fields in 512-byte units, to a backend disk sector and count.
+fn segment_to_disk_range(
+    seg: &VirtioBlkDiscardWriteZeroes,
+    sector_shift: u32,
+    sector_mask: u64,
+) -> Result<(u64, u64), u8> {
+    let disk_sector = virtio_to_disk_sector(seg.sector, sector_shift, sector_mask)?;
+    let num_sectors = seg.num_sectors as u64;
+    if num_sectors & sector_mask != 0 {
+        return Err(VIRTIO_BLK_S_IOERR);
+    }
+    Ok((disk_sector, num_sectors >> sector_shift))
+}
+
 /// Convert a 512-byte virtio sector number to a backend disk sector,
 /// validating alignment for disks with larger native sectors.
 fn virtio_to_disk_sector(
@@ -14,6 +14,9 @@
 use vm_resource::declare_static_async_resolver;
 use vm_resource::kind::VirtioDeviceHandle;
 
+/// The default number of request queues.
+const DEFAULT_NUM_QUEUES: u16 = 1;
+
 /// Resolver for virtio-blk devices.
 pub struct VirtioBlkResolver;
 
@@ -43,6 +46,12 @@ async fn resolve(
             )
             .await?;
 
-        Ok(VirtioBlkDevice::new(input.driver_source, disk.0, resource.read_only).into())
+        Ok(VirtioBlkDevice::new(
+            input.driver_source,
+            disk.0,
+            resource.read_only,
+            resource.num_queues.unwrap_or(DEFAULT_NUM_QUEUES),
+        )
+        .into())
     }
 }
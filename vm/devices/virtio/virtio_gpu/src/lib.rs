@@ -0,0 +1,769 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio GPU device.
+//!
+//! Implements the 2D subset of the virtio GPU device (device ID 16) as
+//! specified in the VIRTIO 1.3 specification, §5.7 "GPU Device". The device
+//! has a single scanout, which is shown in the VM's framebuffer so that the VNC
+//! server can display it.
+//!
+//! The guest draws into resources backed by guest memory and copies them to
+//! the host with `TRANSFER_TO_HOST_2D`. `RESOURCE_FLUSH` then copies the
+//! flushed region of the scanout's resource into the framebuffer, converting
+//! it to the framebuffer's BGRX format, and reports the region as dirty.
+//!
+//! The cursor queue is processed, but the cursor is not drawn: VNC clients
+//! draw their own pointer.
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+mod spec;
+#[cfg(test)]
+mod tests;
+
+use anyhow::Context as _;
+use framebuffer::FRAMEBUFFER_SIZE;
+use futures_concurrency::future::Race as _;
+use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
+use inspect::Inspect;
+use inspect::InspectMut;
+use pal_async::wait::PolledWait;
+use spec::*;
+use std::collections::HashMap;
+use std::future::pending;
+use task_control::AsyncRun;
+use task_control::Cancelled;
+use task_control::InspectTaskMut;
+use task_control::StopTask;
+use task_control::TaskControl;
+use thiserror::Error;
+use video_core::DirtyRect;
+use video_core::FramebufferControl;
+use video_core::FramebufferFormat;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use virtio::VirtioQueueCallbackWork;
+use virtio::queue::QueueState;
+use virtio::spec::VirtioDeviceFeatures;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
+use zerocopy::FromZeros;
+use zerocopy::IntoBytes;
+
+/// The display size reported to the guest. This matches the framebuffer's
+/// initial format.
+const DISPLAY_WIDTH: u32 = 1024;
+const DISPLAY_HEIGHT: u32 = 768;
+
+/// All supported formats use 32 bits per pixel, as does the framebuffer.
+const BYTES_PER_PIXEL: usize = 4;
+
+/// The limit on the total size of the host copies of the guest's resources.
+const MAX_HOST_MEMORY: usize = 256 * 1024 * 1024;
+
+/// The maximum number of guest memory ranges backing a resource.
+const MAX_BACKING_ENTRIES: u32 = 16384;
+
+/// A virtio GPU device.
+#[derive(InspectMut)]
+pub struct VirtioGpuDevice {
+    driver: VmTaskDriver,
+    #[inspect(mut)]
+    worker: TaskControl<GpuWorker, GpuQueues>,
+}
+
+impl VirtioGpuDevice {
+    /// Creates a new virtio GPU device that shows its scanout in
+    /// `framebuffer`, and reports the regions the guest flushes to
+    /// `dirt_send`.
+    pub fn new(
+        driver_source: &VmTaskDriverSource,
+        framebuffer: Box<dyn FramebufferControl>,
+        dirt_send: Option<mesh::Sender<Vec<DirtyRect>>>,
+    ) -> anyhow::Result<Self> {
+        let vram = framebuffer
+            .memory()
+            .context("failed to access framebuffer memory")?;
+        Ok(Self {
+            driver: driver_source.simple(),
+            worker: TaskControl::new(GpuWorker {
+                state: GpuState {
+                    framebuffer,
+                    vram,
+                    dirt_send,
+                    resources: HashMap::new(),
+                    host_memory: 0,
+                    scanout: None,
+                    cursor: Cursor::default(),
+                },
+            }),
+        })
+    }
+}
+
+impl VirtioDevice for VirtioGpuDevice {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: virtio::spec::VirtioDeviceType::GPU,
+            device_features: VirtioDeviceFeatures::new()
+                .with_ring_event_idx(true)
+                .with_ring_indirect_desc(true)
+                .with_ring_packed(true),
+            max_queues: 2,
+            device_register_length: CONFIG_LEN,
+            shared_memory: DeviceTraitsSharedMemory::default(),
+        }
+    }
+
+    async fn read_registers_u32(&mut self, offset: u16) -> u32 {
+        match offset {
+            // No display change events are raised, so `events_read` is
+            // always zero, and there are no 3D capability sets.
+            CONFIG_NUM_SCANOUTS => 1,
+            _ => 0,
+        }
+    }
+
+    async fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        // The only writable field is `events_clear`, and no events are ever
+        // raised.
+        tracing::trace!(offset, val, "config write");
+    }
+
+    async fn start_queue(
+        &mut self,
+        idx: u16,
+        resources: QueueResources,
+        features: &VirtioDeviceFeatures,
+        initial_state: Option<QueueState>,
+    ) -> anyhow::Result<()> {
+        let queue_event = PolledWait::new(&self.driver, resources.event)
+            .context("failed to create polled wait")?;
+        let queue = VirtioQueue::new(
+            *features,
+            resources.params,
+            resources.guest_memory.clone(),
+            resources.notify,
+            queue_event,
+            initial_state,
+        )
+        .context("failed to create virtio queue")?;
+
+        if self.worker.has_state() {
+            self.worker.stop().await;
+        } else {
+            self.worker.insert(
+                &self.driver,
+                "virtio-gpu",
+                GpuQueues {
+                    mem: resources.guest_memory,
+                    controlq: None,
+                    cursorq: None,
+                },
+            );
+        }
+        *self.worker.state_mut().unwrap().queue_mut(idx) = Some(queue);
+        self.worker.start();
+        Ok(())
+    }
+
+    async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
+        if !self.worker.has_state() {
+            return None;
+        }
+        self.worker.stop().await;
+        let state = self.worker.state_mut().unwrap();
+        let queue = state.queue_mut(idx).take();
+        if state.controlq.is_none() && state.cursorq.is_none() {
+            self.worker.remove();
+        } else {
+            self.worker.start();
+        }
+        queue.map(|q| q.queue_state())
+    }
+
+    async fn reset(&mut self) {
+        self.worker.task_mut().state.reset();
+    }
+}
+
+/// The host side of the device: the guest's resources and what is being
+/// displayed.
+#[derive(Inspect)]
+struct GpuState {
+    #[inspect(skip)]
+    framebuffer: Box<dyn FramebufferControl>,
+    #[inspect(skip)]
+    vram: GuestMemory,
+    #[inspect(skip)]
+    dirt_send: Option<mesh::Sender<Vec<DirtyRect>>>,
+    #[inspect(iter_by_key)]
+    resources: HashMap<u32, Resource2d>,
+    /// The total size of `resources`' host copies.
+    host_memory: usize,
+    scanout: Option<Scanout>,
+    cursor: Cursor,
+}
+
+#[derive(Inspect)]
+struct Resource2d {
+    #[inspect(hex)]
+    format: u32,
+    width: u32,
+    height: u32,
+    #[inspect(with = "Vec::len")]
+    backing: Vec<MemEntry>,
+    /// The host copy of the resource, in the resource's format.
+    #[inspect(skip)]
+    data: Vec<u8>,
+}
+
+impl Resource2d {
+    fn stride(&self) -> usize {
+        self.width as usize * BYTES_PER_PIXEL
+    }
+
+    /// Returns whether `r` lies within the resource.
+    fn contains(&self, r: &Rect) -> bool {
+        r.x.checked_add(r.width).is_some_and(|x| x <= self.width)
+            && r.y.checked_add(r.height).is_some_and(|y| y <= self.height)
+    }
+}
+
+#[derive(Inspect)]
+struct Scanout {
+    resource_id: u32,
+    /// The region of the resource that is displayed.
+    #[inspect(skip)]
+    r: Rect,
+}
+
+#[derive(Default, Inspect)]
+struct Cursor {
+    resource_id: u32,
+    x: u32,
+    y: u32,
+    hot_x: u32,
+    hot_y: u32,
+}
+
+/// A failed command, reported to the guest as an error response.
+#[derive(Debug, Error)]
+enum CommandError {
+    #[error("unsupported command {0:#x}")]
+    Unsupported(u32),
+    #[error("request too short")]
+    TooShort,
+    #[error("invalid resource id {0}")]
+    InvalidResourceId(u32),
+    #[error("invalid scanout id {0}")]
+    InvalidScanoutId(u32),
+    #[error("invalid parameter: {0}")]
+    InvalidParameter(&'static str),
+    #[error("resource too large for host memory limit")]
+    OutOfMemory,
+    #[error("guest memory access failed")]
+    Memory(#[source] GuestMemoryError),
+}
+
+impl CommandError {
+    fn response_type(&self) -> u32 {
+        match self {
+            CommandError::Unsupported(_) | CommandError::Memory(_) => VIRTIO_GPU_RESP_ERR_UNSPEC,
+            CommandError::TooShort | CommandError::InvalidParameter(_) => {
+                VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER
+            }
+            CommandError::InvalidResourceId(_) => VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID,
+            CommandError::InvalidScanoutId(_) => VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID,
+            CommandError::OutOfMemory => VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY,
+        }
+    }
+}
+
+/// Parses the command structure `T` from the start of `request`.
+fn parse<T: FromBytes>(request: &[u8]) -> Result<T, CommandError> {
+    T::read_from_prefix(request)
+        .map(|(cmd, _)| cmd)
+        .map_err(|_| CommandError::TooShort)
+}
+
+/// Converts a pixel in `format` to the framebuffer's BGRX byte order.
+fn to_bgrx(format: u32, [a, b, c, d]: [u8; 4]) -> [u8; 4] {
+    match format {
+        VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM | VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM => [d, c, b, a],
+        VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM | VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM => [c, b, a, d],
+        VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM | VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM => [b, c, d, a],
+        _ => [a, b, c, d],
+    }
+}
+
+/// Reads `buf` from `offset` bytes into a resource's backing.
+fn read_backing(
+    mem: &GuestMemory,
+    backing: &[MemEntry],
+    mut offset: u64,
+    mut buf: &mut [u8],
+) -> Result<(), CommandError> {
+    for entry in backing {
+        if buf.is_empty() {
+            break;
+        }
+        let length = entry.length as u64;
+        if offset >= length {
+            offset -= length;
+            continue;
+        }
+        let len = buf.len().min((length - offset) as usize);
+        let (this, rest) = buf.split_at_mut(len);
+        mem.read_at(entry.addr.wrapping_add(offset), this)
+            .map_err(CommandError::Memory)?;
+        buf = rest;
+        offset = 0;
+    }
+    if !buf.is_empty() {
+        return Err(CommandError::InvalidParameter(
+            "transfer beyond resource backing",
+        ));
+    }
+    Ok(())
+}
+
+impl GpuState {
+    fn reset(&mut self) {
+        self.resources.clear();
+        self.host_memory = 0;
+        self.scanout = None;
+        self.cursor = Cursor::default();
+    }
+
+    fn resource_mut(&mut self, resource_id: u32) -> Result<&mut Resource2d, CommandError> {
+        self.resources
+            .get_mut(&resource_id)
+            .ok_or(CommandError::InvalidResourceId(resource_id))
+    }
+
+    /// Handles a control queue request, returning the response.
+    async fn handle_control(
+        &mut self,
+        mem: &GuestMemory,
+        request: &[u8],
+    ) -> Result<Vec<u8>, CommandError> {
+        let hdr: CtrlHeader = parse(request)?;
+        match hdr.ty {
+            VIRTIO_GPU_CMD_GET_DISPLAY_INFO => {
+                let mut info = RespDisplayInfo::new_zeroed();
+                info.hdr.ty = VIRTIO_GPU_RESP_OK_DISPLAY_INFO;
+                info.pmodes[0] = DisplayOne {
+                    r: Rect {
+                        x: 0,
+                        y: 0,
+                        width: DISPLAY_WIDTH,
+                        height: DISPLAY_HEIGHT,
+                    },
+                    enabled: 1,
+                    flags: 0,
+                };
+                return Ok(info.as_bytes().to_vec());
+            }
+            VIRTIO_GPU_CMD_RESOURCE_CREATE_2D => self.resource_create_2d(parse(request)?)?,
+            VIRTIO_GPU_CMD_RESOURCE_UNREF => {
+                let cmd: ResourceCommand = parse(request)?;
+                let resource = self
+                    .resources
+                    .remove(&cmd.resource_id)
+                    .ok_or(CommandError::InvalidResourceId(cmd.resource_id))?;
+                self.host_memory -= resource.data.len();
+                if self
+                    .scanout
+                    .as_ref()
+                    .is_some_and(|s| s.resource_id == cmd.resource_id)
+                {
+                    self.scanout = None;
+                }
+            }
+            VIRTIO_GPU_CMD_SET_SCANOUT => self.set_scanout(parse(request)?).await?,
+            VIRTIO_GPU_CMD_RESOURCE_FLUSH => self.resource_flush(parse(request)?)?,
+            VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D => self.transfer_to_host_2d(mem, parse(request)?)?,
+            VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING => {
+                let cmd: ResourceAttachBacking = parse(request)?;
+                if cmd.nr_entries > MAX_BACKING_ENTRIES {
+                    return Err(CommandError::InvalidParameter("too many backing entries"));
+                }
+                let entries = &request[size_of::<ResourceAttachBacking>()..];
+                let backing = entries
+                    .chunks_exact(size_of::<MemEntry>())
+                    .take(cmd.nr_entries as usize)
+                    .map(|entry| MemEntry::read_from_bytes(entry).unwrap())
+                    .collect::<Vec<_>>();
+                if backing.len() != cmd.nr_entries as usize {
+                    return Err(CommandError::TooShort);
+                }
+                self.resource_mut(cmd.resource_id)?.backing = backing;
+            }
+            VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING => {
+                let cmd: ResourceCommand = parse(request)?;
+                self.resource_mut(cmd.resource_id)?.backing.clear();
+            }
+            ty => return Err(CommandError::Unsupported(ty)),
+        }
+        Ok(CtrlHeader {
+            ty: VIRTIO_GPU_RESP_OK_NODATA,
+            ..Default::default()
+        }
+        .as_bytes()
+        .to_vec())
+    }
+
+    fn resource_create_2d(&mut self, cmd: ResourceCreate2d) -> Result<(), CommandError> {
+        if cmd.resource_id == 0 || self.resources.contains_key(&cmd.resource_id) {
+            return Err(CommandError::InvalidResourceId(cmd.resource_id));
+        }
+        match cmd.format {
+            VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM
+            | VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM
+            | VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM
+            | VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM
+            | VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM
+            | VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM
+            | VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM
+            | VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM => {}
+            _ => return Err(CommandError::InvalidParameter("unsupported format")),
+        }
+        if cmd.width == 0 || cmd.height == 0 {
+            return Err(CommandError::InvalidParameter("empty resource"));
+        }
+        let size = (cmd.width as usize)
+            .checked_mul(cmd.height as usize)
+            .and_then(|n| n.checked_mul(BYTES_PER_PIXEL))
+            .filter(|&size| self.host_memory + size <= MAX_HOST_MEMORY)
+            .ok_or(CommandError::OutOfMemory)?;
+        self.host_memory += size;
+        self.resources.insert(
+            cmd.resource_id,
+            Resource2d {
+                format: cmd.format,
+                width: cmd.width,
+                height: cmd.height,
+                backing: Vec::new(),
+                data: vec![0; size],
+            },
+        );
+        Ok(())
+    }
+
+    async fn set_scanout(&mut self, cmd: SetScanout) -> Result<(), CommandError> {
+        if cmd.scanout_id != 0 {
+            return Err(CommandError::InvalidScanoutId(cmd.scanout_id));
+        }
+        if cmd.resource_id == 0 {
+            // The guest is disabling the display.
+            self.scanout = None;
+            return Ok(());
+        }
+        let resource = self
+            .resources
+            .get(&cmd.resource_id)
+            .ok_or(CommandError::InvalidResourceId(cmd.resource_id))?;
+        if cmd.r.width == 0 || cmd.r.height == 0 || !resource.contains(&cmd.r) {
+            return Err(CommandError::InvalidParameter("invalid scanout rectangle"));
+        }
+        let bytes_per_line = cmd.r.width as usize * BYTES_PER_PIXEL;
+        if bytes_per_line * cmd.r.height as usize > FRAMEBUFFER_SIZE {
+            return Err(CommandError::InvalidParameter(
+                "scanout larger than framebuffer",
+            ));
+        }
+        self.framebuffer
+            .set_format(FramebufferFormat {
+                width: cmd.r.width as usize,
+                height: cmd.r.height as usize,
+                bytes_per_line,
+                offset: 0,
+            })
+            .await;
+        self.scanout = Some(Scanout {
+            resource_id: cmd.resource_id,
+            r: cmd.r,
+        });
+        Ok(())
+    }
+
+    fn transfer_to_host_2d(
+        &mut self,
+        mem: &GuestMemory,
+        cmd: TransferToHost2d,
+    ) -> Result<(), CommandError> {
+        let resource = self.resource_mut(cmd.resource_id)?;
+        if !resource.contains(&cmd.r) {
+            return Err(CommandError::InvalidParameter("transfer outside resource"));
+        }
+        let stride = resource.stride();
+        let len = cmd.r.width as usize * BYTES_PER_PIXEL;
+        for row in 0..cmd.r.height as usize {
+            let src = cmd.offset.wrapping_add((stride * row) as u64);
+            let dst = (cmd.r.y as usize + row) * stride + cmd.r.x as usize * BYTES_PER_PIXEL;
+            read_backing(
+                mem,
+                &resource.backing,
+                src,
+                &mut resource.data[dst..dst + len],
+            )?;
+        }
+        Ok(())
+    }
+
+    fn resource_flush(&mut self, cmd: ResourceFlush) -> Result<(), CommandError> {
+        let resource = self
+            .resources
+            .get(&cmd.resource_id)
+            .ok_or(CommandError::InvalidResourceId(cmd.resource_id))?;
+        if !resource.contains(&cmd.r) {
+            return Err(CommandError::InvalidParameter("flush outside resource"));
+        }
+        let Some(scanout) = self
+            .scanout
+            .as_ref()
+            .filter(|s| s.resource_id == cmd.resource_id)
+        else {
+            // Not displayed, so there is nothing to do.
+            return Ok(());
+        };
+
+        // Clip the flushed region to the displayed region.
+        let s = scanout.r;
+        let left = cmd.r.x.max(s.x);
+        let top = cmd.r.y.max(s.y);
+        let right = (cmd.r.x + cmd.r.width).min(s.x + s.width);
+        let bottom = (cmd.r.y + cmd.r.height).min(s.y + s.height);
+        if left >= right || top >= bottom {
+            return Ok(());
+        }
+
+        let stride = resource.stride();
+        let bytes_per_line = s.width as usize * BYTES_PER_PIXEL;
+        let mut line = vec![0; (right - left) as usize * BYTES_PER_PIXEL];
+        for y in top..bottom {
+            let src = y as usize * stride + left as usize * BYTES_PER_PIXEL;
+            let src = &resource.data[src..][..line.len()];
+            for (dst, src) in line
+                .chunks_exact_mut(BYTES_PER_PIXEL)
+                .zip(src.chunks_exact(BYTES_PER_PIXEL))
+            {
+                dst.copy_from_slice(&to_bgrx(resource.format, src.try_into().unwrap()));
+            }
+            let offset =
+                (y - s.y) as usize * bytes_per_line + (left - s.x) as usize * BYTES_PER_PIXEL;
+            if let Err(err) = self.vram.write_at(offset as u64, &line) {
+                tracelimit::error_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to write framebuffer"
+                );
+                return Ok(());
+            }
+        }
+
+        if let Some(send) = &self.dirt_send {
+            send.send(vec![DirtyRect {
+                left: (left - s.x) as i32,
+                top: (top - s.y) as i32,
+                right: (right - s.x) as i32,
+                bottom: (bottom - s.y) as i32,
+            }]);
+        }
+        Ok(())
+    }
+
+    /// Handles a cursor queue request. These have no response.
+    fn handle_cursor(&mut self, request: &[u8]) -> Result<(), CommandError> {
+        let cmd: UpdateCursor = parse(request)?;
+        match cmd.hdr.ty {
+            VIRTIO_GPU_CMD_UPDATE_CURSOR => {
+                self.cursor = Cursor {
+                    resource_id: cmd.resource_id,
+                    x: cmd.pos.x,
+                    y: cmd.pos.y,
+                    hot_x: cmd.hot_x,
+                    hot_y: cmd.hot_y,
+                };
+            }
+            VIRTIO_GPU_CMD_MOVE_CURSOR => {
+                self.cursor.x = cmd.pos.x;
+                self.cursor.y = cmd.pos.y;
+            }
+            ty => return Err(CommandError::Unsupported(ty)),
+        }
+        Ok(())
+    }
+}
+
+#[derive(InspectMut)]
+struct GpuWorker {
+    #[inspect(flatten)]
+    state: GpuState,
+}
+
+#[derive(InspectMut)]
+struct GpuQueues {
+    #[inspect(skip)]
+    mem: GuestMemory,
+    controlq: Option<VirtioQueue>,
+    cursorq: Option<VirtioQueue>,
+}
+
+impl GpuQueues {
+    fn queue_mut(&mut self, idx: u16) -> &mut Option<VirtioQueue> {
+        match idx {
+            0 => &mut self.controlq,
+            1 => &mut self.cursorq,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl InspectTaskMut<GpuQueues> for GpuWorker {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>, state: Option<&mut GpuQueues>) {
+        req.respond().merge(self).merge(state);
+    }
+}
+
+impl AsyncRun<GpuQueues> for GpuWorker {
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        state: &mut GpuQueues,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async {
+            if let Err(err) = self.process(state).await {
+                tracelimit::error_ratelimited!(
+                    err = &err as &dyn std::error::Error,
+                    "virtio-gpu queue error"
+                );
+            }
+        })
+        .await
+    }
+}
+
+/// The largest request accepted: an attach backing command with the maximum
+/// number of entries.
+const MAX_REQUEST_SIZE: usize =
+    size_of::<ResourceAttachBacking>() + MAX_BACKING_ENTRIES as usize * size_of::<MemEntry>();
+
+/// Reads the readable part of `work`.
+fn read_request(
+    mem: &GuestMemory,
+    work: &VirtioQueueCallbackWork,
+) -> Result<Vec<u8>, CommandError> {
+    let len = work.get_payload_length(false) as usize;
+    if len > MAX_REQUEST_SIZE {
+        return Err(CommandError::InvalidParameter("request too large"));
+    }
+    let mut request = vec![0; len];
+    work.read(mem, &mut request).map_err(CommandError::Memory)?;
+    Ok(request)
+}
+
+impl GpuWorker {
+    async fn process(&mut self, state: &mut GpuQueues) -> Result<(), std::io::Error> {
+        enum Event {
+            Control(Result<VirtioQueueCallbackWork, std::io::Error>),
+            Cursor(Result<VirtioQueueCallbackWork, std::io::Error>),
+        }
+
+        let GpuQueues {
+            mem,
+            controlq,
+            cursorq,
+        } = state;
+        loop {
+            let control = async {
+                match controlq {
+                    Some(queue) => Event::Control(next_work(queue).await),
+                    None => pending().await,
+                }
+            };
+            let cursor = async {
+                match cursorq {
+                    Some(queue) => Event::Cursor(next_work(queue).await),
+                    None => pending().await,
+                }
+            };
+
+            match (control, cursor).race().await {
+                Event::Control(work) => {
+                    let work = work?;
+                    let request = read_request(mem, &work);
+                    let hdr = request
+                        .as_deref()
+                        .ok()
+                        .and_then(|request| parse::<CtrlHeader>(request).ok())
+                        .unwrap_or_default();
+                    let result = match request {
+                        Ok(request) => self.state.handle_control(mem, &request).await,
+                        Err(err) => Err(err),
+                    };
+                    let mut response = result.unwrap_or_else(|err| {
+                        tracelimit::warn_ratelimited!(
+                            ty = hdr.ty,
+                            error = &err as &dyn std::error::Error,
+                            "virtio-gpu command failed"
+                        );
+                        CtrlHeader {
+                            ty: err.response_type(),
+                            ..Default::default()
+                        }
+                        .as_bytes()
+                        .to_vec()
+                    });
+                    if hdr.flags & VIRTIO_GPU_FLAG_FENCE != 0 {
+                        // Commands complete synchronously, so the fence has
+                        // already signaled.
+                        let (mut resp, _) = CtrlHeader::read_from_prefix(&response).unwrap();
+                        resp.flags |= VIRTIO_GPU_FLAG_FENCE;
+                        resp.fence_id = hdr.fence_id;
+                        resp.ctx_id = hdr.ctx_id;
+                        resp.ring_idx = hdr.ring_idx;
+                        resp.write_to_prefix(&mut response).unwrap();
+                    }
+                    let len = match work.write(mem, &response) {
+                        Ok(()) => response.len() as u32,
+                        Err(err) => {
+                            tracelimit::warn_ratelimited!(
+                                error = &err as &dyn std::error::Error,
+                                "failed to write virtio-gpu response"
+                            );
+                            0
+                        }
+                    };
+                    controlq.as_mut().unwrap().complete(work, len);
+                }
+                Event::Cursor(work) => {
+                    let work = work?;
+                    if let Err(err) = read_request(mem, &work)
+                        .and_then(|request| self.state.handle_cursor(&request))
+                    {
+                        tracelimit::warn_ratelimited!(
+                            error = &err as &dyn std::error::Error,
+                            "virtio-gpu cursor command failed"
+                        );
+                    }
+                    cursorq.as_mut().unwrap().complete(work, 0);
+                }
+            }
+        }
+    }
+}
+
+async fn next_work(queue: &mut VirtioQueue) -> Result<VirtioQueueCallbackWork, std::io::Error> {
+    match queue.next().await {
+        Some(work) => work,
+        None => pending().await,
+    }
+}
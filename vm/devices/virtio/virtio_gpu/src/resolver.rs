@@ -0,0 +1,43 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource resolver for the virtio GPU device.
+
+use crate::VirtioGpuDevice;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use virtio::resolve::ResolvedVirtioDevice;
+use virtio::resolve::VirtioResolveInput;
+use virtio_resources::gpu::VirtioGpuHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::VirtioDeviceHandle;
+
+/// Resolver for the virtio GPU device.
+pub struct VirtioGpuResolver;
+
+declare_static_async_resolver! {
+    VirtioGpuResolver,
+    (VirtioDeviceHandle, VirtioGpuHandle),
+}
+
+#[async_trait]
+impl AsyncResolveResource<VirtioDeviceHandle, VirtioGpuHandle> for VirtioGpuResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = anyhow::Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VirtioGpuHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let framebuffer = resolver
+            .resolve(resource.framebuffer, ())
+            .await
+            .context("failed to resolve framebuffer")?;
+        let device = VirtioGpuDevice::new(input.driver_source, framebuffer.0, resource.dirt_send)?;
+        Ok(device.into())
+    }
+}
@@ -0,0 +1,191 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio GPU spec constants and structures (spec §5.7), limited to the 2D
+//! command set.
+
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+/// The maximum number of scanouts the protocol allows.
+pub const VIRTIO_GPU_MAX_SCANOUTS: usize = 16;
+
+/// Config space: `events_read`, `events_clear`, `num_scanouts`,
+/// `num_capsets`, each le32.
+pub const CONFIG_NUM_SCANOUTS: u16 = 8;
+pub const CONFIG_LEN: u32 = 16;
+
+// 2D commands.
+pub const VIRTIO_GPU_CMD_GET_DISPLAY_INFO: u32 = 0x0100;
+pub const VIRTIO_GPU_CMD_RESOURCE_CREATE_2D: u32 = 0x0101;
+pub const VIRTIO_GPU_CMD_RESOURCE_UNREF: u32 = 0x0102;
+pub const VIRTIO_GPU_CMD_SET_SCANOUT: u32 = 0x0103;
+pub const VIRTIO_GPU_CMD_RESOURCE_FLUSH: u32 = 0x0104;
+pub const VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D: u32 = 0x0105;
+pub const VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING: u32 = 0x0106;
+pub const VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING: u32 = 0x0107;
+
+// Cursor commands.
+pub const VIRTIO_GPU_CMD_UPDATE_CURSOR: u32 = 0x0300;
+pub const VIRTIO_GPU_CMD_MOVE_CURSOR: u32 = 0x0301;
+
+// Success responses.
+pub const VIRTIO_GPU_RESP_OK_NODATA: u32 = 0x1100;
+pub const VIRTIO_GPU_RESP_OK_DISPLAY_INFO: u32 = 0x1101;
+
+// Error responses.
+pub const VIRTIO_GPU_RESP_ERR_UNSPEC: u32 = 0x1200;
+pub const VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY: u32 = 0x1201;
+pub const VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID: u32 = 0x1202;
+pub const VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID: u32 = 0x1203;
+pub const VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER: u32 = 0x1205;
+
+/// Header flag: the driver wants the fence ID echoed in the response.
+pub const VIRTIO_GPU_FLAG_FENCE: u32 = 1 << 0;
+
+// Resource formats. All are 32 bits per pixel; the name gives the byte order
+// in memory.
+pub const VIRTIO_GPU_FORMAT_B8G8R8A8_UNORM: u32 = 1;
+pub const VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM: u32 = 2;
+pub const VIRTIO_GPU_FORMAT_A8R8G8B8_UNORM: u32 = 3;
+pub const VIRTIO_GPU_FORMAT_X8R8G8B8_UNORM: u32 = 4;
+pub const VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM: u32 = 67;
+pub const VIRTIO_GPU_FORMAT_X8B8G8R8_UNORM: u32 = 68;
+pub const VIRTIO_GPU_FORMAT_A8B8G8R8_UNORM: u32 = 121;
+pub const VIRTIO_GPU_FORMAT_R8G8B8X8_UNORM: u32 = 134;
+
+/// `virtio_gpu_ctrl_hdr`, at the start of every request and response.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct CtrlHeader {
+    pub ty: u32,
+    pub flags: u32,
+    pub fence_id: u64,
+    pub ctx_id: u32,
+    pub ring_idx: u8,
+    pub padding: [u8; 3],
+}
+
+/// `virtio_gpu_rect`.
+#[repr(C)]
+#[derive(
+    Debug, Copy, Clone, Default, PartialEq, Eq, IntoBytes, Immutable, KnownLayout, FromBytes,
+)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// `virtio_gpu_display_one`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct DisplayOne {
+    pub r: Rect,
+    pub enabled: u32,
+    pub flags: u32,
+}
+
+/// `virtio_gpu_resp_display_info`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct RespDisplayInfo {
+    pub hdr: CtrlHeader,
+    pub pmodes: [DisplayOne; VIRTIO_GPU_MAX_SCANOUTS],
+}
+
+/// `virtio_gpu_resource_create_2d`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ResourceCreate2d {
+    pub hdr: CtrlHeader,
+    pub resource_id: u32,
+    pub format: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// `virtio_gpu_resource_unref` and `virtio_gpu_resource_detach_backing`,
+/// which share a layout.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ResourceCommand {
+    pub hdr: CtrlHeader,
+    pub resource_id: u32,
+    pub padding: u32,
+}
+
+/// `virtio_gpu_set_scanout`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct SetScanout {
+    pub hdr: CtrlHeader,
+    pub r: Rect,
+    pub scanout_id: u32,
+    pub resource_id: u32,
+}
+
+/// `virtio_gpu_resource_flush`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ResourceFlush {
+    pub hdr: CtrlHeader,
+    pub r: Rect,
+    pub resource_id: u32,
+    pub padding: u32,
+}
+
+/// `virtio_gpu_transfer_to_host_2d`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct TransferToHost2d {
+    pub hdr: CtrlHeader,
+    pub r: Rect,
+    pub offset: u64,
+    pub resource_id: u32,
+    pub padding: u32,
+}
+
+/// `virtio_gpu_resource_attach_backing`, followed by `nr_entries`
+/// [`MemEntry`]s.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct ResourceAttachBacking {
+    pub hdr: CtrlHeader,
+    pub resource_id: u32,
+    pub nr_entries: u32,
+}
+
+/// `virtio_gpu_mem_entry`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct MemEntry {
+    pub addr: u64,
+    pub length: u32,
+    pub padding: u32,
+}
+
+/// `virtio_gpu_cursor_pos`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct CursorPos {
+    pub scanout_id: u32,
+    pub x: u32,
+    pub y: u32,
+    pub padding: u32,
+}
+
+/// `virtio_gpu_update_cursor`, used for both cursor commands.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct UpdateCursor {
+    pub hdr: CtrlHeader,
+    pub pos: CursorPos,
+    pub resource_id: u32,
+    pub hot_x: u32,
+    pub hot_y: u32,
+    pub padding: u32,
+}
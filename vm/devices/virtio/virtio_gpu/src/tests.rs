@@ -0,0 +1,472 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tests for the virtio GPU device.
+
+use crate::VirtioGpuDevice;
+use crate::spec::*;
+use framebuffer::FRAMEBUFFER_SIZE;
+use guestmem::GuestMemory;
+use pal_async::DefaultDriver;
+use pal_async::async_test;
+use pal_event::Event;
+use std::io;
+use video_core::DirtyRect;
+use video_core::FramebufferControl;
+use video_core::FramebufferFormat;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::queue::QueueParams;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio::spec::queue::DescriptorFlags;
+use virtio::test_helpers::init_avail_ring;
+use virtio::test_helpers::init_used_ring;
+use virtio::test_helpers::make_available;
+use virtio::test_helpers::wait_for_used;
+use virtio::test_helpers::write_descriptor;
+use vmcore::interrupt::Interrupt;
+use vmcore::vm_task::SingleDriverBackend;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+
+const QUEUE_SIZE: u16 = 16;
+const QUEUE_BASE: [u64; 2] = [0x0000, 0x4000];
+const AVAIL_OFFSET: u64 = 0x1000;
+const USED_OFFSET: u64 = 0x2000;
+const REQUEST_ADDR: u64 = 0x10000;
+const RESPONSE_ADDR: u64 = 0x18000;
+/// Where the guest keeps the pixels of the resources it creates.
+const BACKING_ADDR: u64 = 0x20000;
+const TOTAL_MEM_SIZE: usize = 0x40000;
+
+/// A framebuffer backed by plain memory.
+struct TestFramebuffer {
+    vram: GuestMemory,
+    format_send: mesh::Sender<FramebufferFormat>,
+}
+
+#[async_trait::async_trait]
+impl FramebufferControl for TestFramebuffer {
+    async fn map(&mut self, _gpa: u64) {}
+    async fn unmap(&mut self) {}
+    async fn set_format(&mut self, format: FramebufferFormat) {
+        self.format_send.send(format);
+    }
+    fn memory(&self) -> io::Result<GuestMemory> {
+        Ok(self.vram.clone())
+    }
+}
+
+struct Queue {
+    event: Event,
+    interrupt: Event,
+    avail_idx: u16,
+    used_idx: u16,
+}
+
+struct TestHarness {
+    device: VirtioGpuDevice,
+    mem: GuestMemory,
+    vram: GuestMemory,
+    driver: DefaultDriver,
+    queues: Vec<Queue>,
+    format_recv: mesh::Receiver<FramebufferFormat>,
+    dirt_recv: mesh::Receiver<Vec<DirtyRect>>,
+}
+
+impl TestHarness {
+    async fn new(driver: &DefaultDriver) -> Self {
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+        let vram = GuestMemory::allocate(FRAMEBUFFER_SIZE);
+        let (format_send, format_recv) = mesh::channel();
+        let (dirt_send, dirt_recv) = mesh::channel();
+        let mut device = VirtioGpuDevice::new(
+            &driver_source,
+            Box::new(TestFramebuffer {
+                vram: vram.clone(),
+                format_send,
+            }),
+            Some(dirt_send),
+        )
+        .unwrap();
+
+        let mem = GuestMemory::allocate(TOTAL_MEM_SIZE);
+        let features = VirtioDeviceFeatures::new();
+        let mut queues = Vec::new();
+        for (idx, base) in QUEUE_BASE.into_iter().enumerate() {
+            init_avail_ring(&mem, base + AVAIL_OFFSET);
+            init_used_ring(&mem, base + USED_OFFSET);
+            let queue = Queue {
+                event: Event::new(),
+                interrupt: Event::new(),
+                avail_idx: 0,
+                used_idx: 0,
+            };
+            device
+                .start_queue(
+                    idx as u16,
+                    QueueResources {
+                        params: QueueParams {
+                            size: QUEUE_SIZE,
+                            enable: true,
+                            desc_addr: base,
+                            avail_addr: base + AVAIL_OFFSET,
+                            used_addr: base + USED_OFFSET,
+                        },
+                        notify: Interrupt::from_event(queue.interrupt.clone()),
+                        event: queue.event.clone(),
+                        guest_memory: mem.clone(),
+                    },
+                    &features,
+                    None,
+                )
+                .await
+                .unwrap();
+            queues.push(queue);
+        }
+
+        Self {
+            device,
+            mem,
+            vram,
+            driver: driver.clone(),
+            queues,
+            format_recv,
+            dirt_recv,
+        }
+    }
+
+    /// Sends `request` on queue `idx`, with a response buffer of
+    /// `response_len` bytes, and waits for the device to complete it. Returns
+    /// the bytes the device wrote.
+    async fn submit(&mut self, idx: usize, request: &[u8], response_len: u32) -> Vec<u8> {
+        let base = QUEUE_BASE[idx];
+        let queue = &mut self.queues[idx];
+        self.mem.write_at(REQUEST_ADDR, request).unwrap();
+        write_descriptor(
+            &self.mem,
+            base,
+            0,
+            REQUEST_ADDR,
+            request.len() as u32,
+            DescriptorFlags::new().with_next(response_len != 0),
+            1,
+        );
+        write_descriptor(
+            &self.mem,
+            base,
+            1,
+            RESPONSE_ADDR,
+            response_len,
+            DescriptorFlags::new().with_write(true),
+            0,
+        );
+        make_available(
+            &self.mem,
+            base + AVAIL_OFFSET,
+            QUEUE_SIZE,
+            0,
+            &mut queue.avail_idx,
+        );
+        queue.event.signal();
+        let (_, len) = wait_for_used(
+            &self.driver,
+            &queue.interrupt,
+            &self.mem,
+            base + USED_OFFSET,
+            QUEUE_SIZE,
+            &mut queue.used_idx,
+        )
+        .await;
+        let mut response = vec![0; len as usize];
+        self.mem.read_at(RESPONSE_ADDR, &mut response).unwrap();
+        response
+    }
+
+    /// Sends a control command and returns the response header.
+    async fn command(&mut self, cmd: &(impl IntoBytes + Immutable)) -> CtrlHeader {
+        let response = self
+            .submit(0, cmd.as_bytes(), size_of::<CtrlHeader>() as u32)
+            .await;
+        CtrlHeader::read_from_bytes(&response).unwrap()
+    }
+
+    /// Creates a `width` by `height` resource in `format`, backed by
+    /// `pixels` in guest memory, split across two backing entries.
+    async fn create_resource(
+        &mut self,
+        resource_id: u32,
+        format: u32,
+        width: u32,
+        height: u32,
+        pixels: &[u8],
+    ) {
+        let resp = self
+            .command(&ResourceCreate2d {
+                hdr: header(VIRTIO_GPU_CMD_RESOURCE_CREATE_2D),
+                resource_id,
+                format,
+                width,
+                height,
+            })
+            .await;
+        assert_eq!(resp.ty, VIRTIO_GPU_RESP_OK_NODATA);
+
+        // Put the two halves of the pixels in separate pages, in reverse
+        // order.
+        let (first, second) = pixels.split_at(pixels.len() / 2);
+        let entries = [
+            MemEntry {
+                addr: BACKING_ADDR + 0x1000,
+                length: first.len() as u32,
+                padding: 0,
+            },
+            MemEntry {
+                addr: BACKING_ADDR,
+                length: second.len() as u32,
+                padding: 0,
+            },
+        ];
+        self.mem.write_at(entries[0].addr, first).unwrap();
+        self.mem.write_at(entries[1].addr, second).unwrap();
+        let mut request = ResourceAttachBacking {
+            hdr: header(VIRTIO_GPU_CMD_RESOURCE_ATTACH_BACKING),
+            resource_id,
+            nr_entries: entries.len() as u32,
+        }
+        .as_bytes()
+        .to_vec();
+        request.extend_from_slice(entries.as_bytes());
+        let response = self
+            .submit(0, &request, size_of::<CtrlHeader>() as u32)
+            .await;
+        let resp = CtrlHeader::read_from_bytes(&response).unwrap();
+        assert_eq!(resp.ty, VIRTIO_GPU_RESP_OK_NODATA);
+    }
+
+    fn read_vram(&self, offset: u64, len: usize) -> Vec<u8> {
+        let mut buf = vec![0; len];
+        self.vram.read_at(offset, &mut buf).unwrap();
+        buf
+    }
+}
+
+fn header(ty: u32) -> CtrlHeader {
+    CtrlHeader {
+        ty,
+        ..Default::default()
+    }
+}
+
+fn rect(x: u32, y: u32, width: u32, height: u32) -> Rect {
+    Rect {
+        x,
+        y,
+        width,
+        height,
+    }
+}
+
+#[async_test]
+async fn display_info(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+    assert_eq!(
+        harness.device.read_registers_u32(CONFIG_NUM_SCANOUTS).await,
+        1
+    );
+
+    let response = harness
+        .submit(
+            0,
+            header(VIRTIO_GPU_CMD_GET_DISPLAY_INFO).as_bytes(),
+            size_of::<RespDisplayInfo>() as u32,
+        )
+        .await;
+    let info = RespDisplayInfo::read_from_bytes(&response).unwrap();
+    assert_eq!(info.hdr.ty, VIRTIO_GPU_RESP_OK_DISPLAY_INFO);
+    assert_eq!(info.pmodes[0].r, rect(0, 0, 1024, 768));
+    assert_eq!(info.pmodes[0].enabled, 1);
+    assert_eq!(info.pmodes[1].enabled, 0);
+}
+
+#[async_test]
+async fn scanout(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+
+    // A 4x2 RGBA resource where pixel N is [N, N + 0x10, N + 0x20, 0xff].
+    let pixels: Vec<u8> = (0..8u8)
+        .flat_map(|n| [n, n + 0x10, n + 0x20, 0xff])
+        .collect();
+    harness
+        .create_resource(1, VIRTIO_GPU_FORMAT_R8G8B8A8_UNORM, 4, 2, &pixels)
+        .await;
+
+    let resp = harness
+        .command(&TransferToHost2d {
+            hdr: header(VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D),
+            r: rect(0, 0, 4, 2),
+            offset: 0,
+            resource_id: 1,
+            padding: 0,
+        })
+        .await;
+    assert_eq!(resp.ty, VIRTIO_GPU_RESP_OK_NODATA);
+
+    let resp = harness
+        .command(&SetScanout {
+            hdr: header(VIRTIO_GPU_CMD_SET_SCANOUT),
+            r: rect(0, 0, 4, 2),
+            scanout_id: 0,
+            resource_id: 1,
+        })
+        .await;
+    assert_eq!(resp.ty, VIRTIO_GPU_RESP_OK_NODATA);
+    assert_eq!(
+        harness.format_recv.try_recv().unwrap(),
+        FramebufferFormat {
+            width: 4,
+            height: 2,
+            bytes_per_line: 16,
+            offset: 0,
+        }
+    );
+
+    // Nothing is displayed until the guest flushes.
+    assert!(harness.read_vram(0, 32).iter().all(|&b| b == 0));
+
+    let resp = harness
+        .command(&ResourceFlush {
+            hdr: header(VIRTIO_GPU_CMD_RESOURCE_FLUSH),
+            r: rect(1, 1, 2, 1),
+            resource_id: 1,
+            padding: 0,
+        })
+        .await;
+    assert_eq!(resp.ty, VIRTIO_GPU_RESP_OK_NODATA);
+
+    // Pixels 5 and 6, converted to BGRX.
+    assert_eq!(
+        harness.read_vram(16, 16),
+        [
+            0, 0, 0, 0, 0x25, 0x15, 5, 0xff, 0x26, 0x16, 6, 0xff, 0, 0, 0, 0
+        ]
+    );
+    assert!(harness.read_vram(0, 16).iter().all(|&b| b == 0));
+    let rects = harness.dirt_recv.try_recv().unwrap();
+    assert_eq!(
+        rects
+            .iter()
+            .map(|r| (r.left, r.top, r.right, r.bottom))
+            .collect::<Vec<_>>(),
+        [(1, 1, 3, 2)]
+    );
+}
+
+#[async_test]
+async fn errors(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+
+    let resp = harness
+        .command(&ResourceFlush {
+            hdr: header(VIRTIO_GPU_CMD_RESOURCE_FLUSH),
+            r: rect(0, 0, 1, 1),
+            resource_id: 7,
+            padding: 0,
+        })
+        .await;
+    assert_eq!(resp.ty, VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID);
+
+    let resp = harness
+        .command(&ResourceCreate2d {
+            hdr: header(VIRTIO_GPU_CMD_RESOURCE_CREATE_2D),
+            resource_id: 1,
+            format: 0x1234,
+            width: 4,
+            height: 4,
+        })
+        .await;
+    assert_eq!(resp.ty, VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+
+    let resp = harness
+        .command(&ResourceCreate2d {
+            hdr: header(VIRTIO_GPU_CMD_RESOURCE_CREATE_2D),
+            resource_id: 1,
+            format: VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM,
+            width: 0x10000,
+            height: 0x10000,
+        })
+        .await;
+    assert_eq!(resp.ty, VIRTIO_GPU_RESP_ERR_OUT_OF_MEMORY);
+
+    let resp = harness
+        .command(&SetScanout {
+            hdr: header(VIRTIO_GPU_CMD_SET_SCANOUT),
+            r: rect(0, 0, 1, 1),
+            scanout_id: 1,
+            resource_id: 0,
+        })
+        .await;
+    assert_eq!(resp.ty, VIRTIO_GPU_RESP_ERR_INVALID_SCANOUT_ID);
+
+    // Transfers must stay within the resource's backing.
+    harness
+        .create_resource(2, VIRTIO_GPU_FORMAT_B8G8R8X8_UNORM, 2, 2, &[0; 16])
+        .await;
+    let resp = harness
+        .command(&TransferToHost2d {
+            hdr: header(VIRTIO_GPU_CMD_TRANSFER_TO_HOST_2D),
+            r: rect(0, 0, 2, 2),
+            offset: 8,
+            resource_id: 2,
+            padding: 0,
+        })
+        .await;
+    assert_eq!(resp.ty, VIRTIO_GPU_RESP_ERR_INVALID_PARAMETER);
+
+    // 3D commands are not supported.
+    let resp = harness.command(&header(0x0200)).await;
+    assert_eq!(resp.ty, VIRTIO_GPU_RESP_ERR_UNSPEC);
+}
+
+#[async_test]
+async fn fence(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+    let resp = harness
+        .command(&ResourceCommand {
+            hdr: CtrlHeader {
+                ty: VIRTIO_GPU_CMD_RESOURCE_DETACH_BACKING,
+                flags: VIRTIO_GPU_FLAG_FENCE,
+                fence_id: 0x1234,
+                ..Default::default()
+            },
+            resource_id: 9,
+            padding: 0,
+        })
+        .await;
+    // Errors complete the fence too.
+    assert_eq!(resp.ty, VIRTIO_GPU_RESP_ERR_INVALID_RESOURCE_ID);
+    assert_eq!(resp.flags, VIRTIO_GPU_FLAG_FENCE);
+    assert_eq!(resp.fence_id, 0x1234);
+}
+
+#[async_test]
+async fn cursor(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+    let cmd = UpdateCursor {
+        hdr: header(VIRTIO_GPU_CMD_MOVE_CURSOR),
+        pos: CursorPos {
+            scanout_id: 0,
+            x: 10,
+            y: 20,
+            padding: 0,
+        },
+        resource_id: 0,
+        hot_x: 0,
+        hot_y: 0,
+        padding: 0,
+    };
+    let response = harness.submit(1, cmd.as_bytes(), 0).await;
+    assert!(response.is_empty());
+}
@@ -0,0 +1,55 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Translation from PC scan codes to Linux key codes.
+
+use crate::spec::*;
+
+/// Returns the Linux key code for a set 1 scan code, as found in
+/// [`input_core::KeyboardData`]: the make code in the low byte, with any
+/// `0xe0` or `0xe1` prefix in the high byte.
+pub fn linux_key_code(scan_code: u16) -> Option<u16> {
+    let code = scan_code & 0x7f;
+    match scan_code >> 8 {
+        // For the base set, Linux key codes match the scan codes.
+        0 => match code {
+            0x01..=0x53 | 0x56..=0x58 => Some(code),
+            0x54 => Some(KEY_SYSRQ),
+            _ => None,
+        },
+        0xe0 => Some(match code {
+            0x1c => KEY_KPENTER,
+            0x1d => KEY_RIGHTCTRL,
+            0x20 => KEY_MUTE,
+            0x2e => KEY_VOLUMEDOWN,
+            0x30 => KEY_VOLUMEUP,
+            0x35 => KEY_KPSLASH,
+            0x37 => KEY_SYSRQ,
+            0x38 => KEY_RIGHTALT,
+            0x47 => KEY_HOME,
+            0x48 => KEY_UP,
+            0x49 => KEY_PAGEUP,
+            0x4b => KEY_LEFT,
+            0x4d => KEY_RIGHT,
+            0x4f => KEY_END,
+            0x50 => KEY_DOWN,
+            0x51 => KEY_PAGEDOWN,
+            0x52 => KEY_INSERT,
+            0x53 => KEY_DELETE,
+            0x5b => KEY_LEFTMETA,
+            0x5c => KEY_RIGHTMETA,
+            0x5d => KEY_COMPOSE,
+            _ => return None,
+        }),
+        0xe1 => (code == 0x1d).then_some(KEY_PAUSE),
+        _ => None,
+    }
+}
+
+/// Returns every key code [`linux_key_code`] can produce.
+pub fn all_key_codes() -> impl Iterator<Item = u16> {
+    [0, 0xe000, 0xe100]
+        .into_iter()
+        .flat_map(|prefix| (0..0x80).map(move |code| prefix | code))
+        .filter_map(linux_key_code)
+}
@@ -0,0 +1,630 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio input devices.
+//!
+//! Implements the virtio input device (device ID 18) as specified in the
+//! VIRTIO 1.3 specification, §5.8 "Input Device". The device presents itself
+//! to the guest as a keyboard, a relative mouse, or an absolute tablet, and
+//! translates host input from an [`InputSource`] (usually the VM's input
+//! distributor) into Linux evdev events.
+//!
+//! The guest posts one buffer per event on the event queue. Events that
+//! arrive while the guest has no buffers posted are held, up to a limit, and
+//! delivered when buffers become available. The status queue carries events
+//! from the guest, such as keyboard LED changes, which are ignored.
+
+#![forbid(unsafe_code)]
+
+mod keymap;
+pub mod resolver;
+mod spec;
+#[cfg(test)]
+mod tests;
+
+use anyhow::Context as _;
+use futures::StreamExt;
+use futures_concurrency::future::Race as _;
+use guestmem::GuestMemory;
+use input_core::InputSource;
+use input_core::KeyboardData;
+use input_core::MouseData;
+use inspect::Inspect;
+use inspect::InspectMut;
+use pal_async::wait::PolledWait;
+use spec::*;
+use std::collections::VecDeque;
+use std::future::pending;
+use task_control::AsyncRun;
+use task_control::Cancelled;
+use task_control::InspectTaskMut;
+use task_control::StopTask;
+use task_control::TaskControl;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use virtio::VirtioQueueCallbackWork;
+use virtio::queue::QueueState;
+use virtio::spec::VirtioDeviceFeatures;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// The maximum number of events held while the guest has no buffers posted.
+/// Reports that would exceed this are dropped.
+const MAX_PENDING_EVENTS: usize = 1024;
+
+/// The largest absolute pointer coordinate in [`MouseData`].
+const POINTER_MAX: u16 = 0x7fff;
+
+/// The relative mouse reports motion as if the pointer moved across a screen
+/// of this size, which matches the default framebuffer resolution.
+const RELATIVE_WIDTH: i32 = 1024;
+const RELATIVE_HEIGHT: i32 = 768;
+
+/// Pointer buttons, as bits in [`MouseData::button_mask`], and their Linux
+/// codes.
+const POINTER_BUTTONS: [(u8, u16); 3] = [(0, BTN_LEFT), (1, BTN_MIDDLE), (2, BTN_RIGHT)];
+/// [`MouseData::button_mask`] bits for the wheel moving up and down.
+const WHEEL_UP: u8 = 1 << 3;
+const WHEEL_DOWN: u8 = 1 << 4;
+
+/// A virtio input device.
+#[derive(InspectMut)]
+pub struct VirtioInputDevice {
+    driver: VmTaskDriver,
+    #[inspect(flatten)]
+    config: InputConfig,
+    select: u8,
+    subsel: u8,
+    #[inspect(mut)]
+    worker: TaskControl<InputWorker, InputQueues>,
+}
+
+impl VirtioInputDevice {
+    /// Creates a keyboard.
+    pub fn keyboard(
+        driver_source: &VmTaskDriverSource,
+        source: Box<dyn InputSource<KeyboardData>>,
+    ) -> Self {
+        Self::new(
+            driver_source,
+            InputConfig {
+                name: "OpenVMM Virtio Keyboard",
+                product: 1,
+                ev_bits: vec![
+                    (EV_KEY, keymap::all_key_codes().collect()),
+                    (EV_LED, vec![LED_NUML, LED_CAPSL, LED_SCROLLL]),
+                ],
+                abs_info: Vec::new(),
+            },
+            Source::Keyboard { source, pressed: 0 },
+        )
+    }
+
+    /// Creates a mouse that reports relative motion.
+    ///
+    /// The host reports absolute pointer positions, so the motion is derived
+    /// from the change in position. Prefer [`Self::tablet`], which keeps the
+    /// guest pointer in step with the host.
+    pub fn mouse(
+        driver_source: &VmTaskDriverSource,
+        source: Box<dyn InputSource<MouseData>>,
+    ) -> Self {
+        Self::new(
+            driver_source,
+            InputConfig {
+                name: "OpenVMM Virtio Mouse",
+                product: 2,
+                ev_bits: vec![
+                    (EV_KEY, POINTER_BUTTONS.map(|(_, code)| code).to_vec()),
+                    (EV_REL, vec![REL_X, REL_Y, REL_WHEEL]),
+                ],
+                abs_info: Vec::new(),
+            },
+            Source::Pointer {
+                source,
+                absolute: false,
+                buttons: 0,
+                position: None,
+            },
+        )
+    }
+
+    /// Creates a tablet that reports absolute pointer positions.
+    pub fn tablet(
+        driver_source: &VmTaskDriverSource,
+        source: Box<dyn InputSource<MouseData>>,
+    ) -> Self {
+        let axis = AbsInfo {
+            min: 0,
+            max: POINTER_MAX.into(),
+        };
+        Self::new(
+            driver_source,
+            InputConfig {
+                name: "OpenVMM Virtio Tablet",
+                product: 3,
+                ev_bits: vec![
+                    (EV_KEY, POINTER_BUTTONS.map(|(_, code)| code).to_vec()),
+                    (EV_REL, vec![REL_WHEEL]),
+                    (EV_ABS, vec![ABS_X, ABS_Y]),
+                ],
+                abs_info: vec![(ABS_X, axis), (ABS_Y, axis)],
+            },
+            Source::Pointer {
+                source,
+                absolute: true,
+                buttons: 0,
+                position: None,
+            },
+        )
+    }
+
+    fn new(driver_source: &VmTaskDriverSource, config: InputConfig, source: Source) -> Self {
+        Self {
+            driver: driver_source.simple(),
+            config,
+            select: VIRTIO_INPUT_CFG_UNSET,
+            subsel: 0,
+            worker: TaskControl::new(InputWorker {
+                source,
+                pending: VecDeque::new(),
+                dropped_events: 0,
+            }),
+        }
+    }
+}
+
+impl VirtioDevice for VirtioInputDevice {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: virtio::spec::VirtioDeviceType::INPUT,
+            device_features: VirtioDeviceFeatures::new()
+                .with_ring_event_idx(true)
+                .with_ring_indirect_desc(true)
+                .with_ring_packed(true),
+            max_queues: 2,
+            device_register_length: CONFIG_LEN as u32,
+            shared_memory: DeviceTraitsSharedMemory::default(),
+        }
+    }
+
+    async fn read_registers_u32(&mut self, offset: u16) -> u32 {
+        let mut config = [0; CONFIG_LEN];
+        let payload = self.config.payload(self.select, self.subsel);
+        config[0] = self.select;
+        config[1] = self.subsel;
+        config[2] = payload.len() as u8;
+        config[CONFIG_HEADER_LEN..][..payload.len()].copy_from_slice(&payload);
+        let offset = offset as usize;
+        match config.get(offset..offset + 4) {
+            Some(val) => u32::from_le_bytes(val.try_into().unwrap()),
+            None => {
+                tracelimit::warn_ratelimited!(offset, "invalid config read offset");
+                0
+            }
+        }
+    }
+
+    async fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        // Only `select` and `subsel` are writable.
+        if offset == 0 {
+            [self.select, self.subsel, _, _] = val.to_le_bytes();
+        }
+    }
+
+    async fn start_queue(
+        &mut self,
+        idx: u16,
+        resources: QueueResources,
+        features: &VirtioDeviceFeatures,
+        initial_state: Option<QueueState>,
+    ) -> anyhow::Result<()> {
+        let queue_event = PolledWait::new(&self.driver, resources.event)
+            .context("failed to create polled wait")?;
+        let queue = VirtioQueue::new(
+            *features,
+            resources.params,
+            resources.guest_memory.clone(),
+            resources.notify,
+            queue_event,
+            initial_state,
+        )
+        .context("failed to create virtio queue")?;
+
+        if self.worker.has_state() {
+            self.worker.stop().await;
+        } else {
+            self.worker.insert(
+                &self.driver,
+                "virtio-input",
+                InputQueues {
+                    mem: resources.guest_memory,
+                    eventq: None,
+                    statusq: None,
+                },
+            );
+        }
+        *self.worker.state_mut().unwrap().queue_mut(idx) = Some(queue);
+        self.worker.start();
+        Ok(())
+    }
+
+    async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
+        if !self.worker.has_state() {
+            return None;
+        }
+        self.worker.stop().await;
+        let state = self.worker.state_mut().unwrap();
+        let queue = state.queue_mut(idx).take();
+        if state.eventq.is_none() && state.statusq.is_none() {
+            self.worker.remove();
+            // Let input go to other devices while the driver is not running.
+            self.worker.task_mut().source.set_active(false).await;
+        } else {
+            self.worker.start();
+        }
+        queue.map(|q| q.queue_state())
+    }
+
+    async fn reset(&mut self) {
+        self.select = VIRTIO_INPUT_CFG_UNSET;
+        self.subsel = 0;
+        self.worker.task_mut().pending.clear();
+    }
+
+    fn supports_save_restore(&self) -> bool {
+        true
+    }
+}
+
+/// What the device tells the guest about itself through config space.
+#[derive(Inspect)]
+struct InputConfig {
+    name: &'static str,
+    #[inspect(skip)]
+    product: u16,
+    /// The codes reported for each event type.
+    #[inspect(skip)]
+    ev_bits: Vec<(u16, Vec<u16>)>,
+    #[inspect(skip)]
+    abs_info: Vec<(u16, AbsInfo)>,
+}
+
+#[derive(Copy, Clone)]
+struct AbsInfo {
+    min: u32,
+    max: u32,
+}
+
+impl InputConfig {
+    /// Returns the config space union for the given selector.
+    fn payload(&self, select: u8, subsel: u8) -> Vec<u8> {
+        match select {
+            VIRTIO_INPUT_CFG_ID_NAME if subsel == 0 => self.name.as_bytes().to_vec(),
+            VIRTIO_INPUT_CFG_ID_DEVIDS if subsel == 0 => [
+                BUS_VIRTUAL,
+                virtio::spec::pci::VIRTIO_VENDOR_ID,
+                self.product,
+                1,
+            ]
+            .into_iter()
+            .flat_map(u16::to_le_bytes)
+            .collect(),
+            VIRTIO_INPUT_CFG_EV_BITS => {
+                let codes = self
+                    .ev_bits
+                    .iter()
+                    .find(|(ty, _)| *ty == subsel.into())
+                    .map_or(&[][..], |(_, codes)| codes);
+                let mut bitmap = Vec::new();
+                for &code in codes {
+                    let byte = code as usize / 8;
+                    if byte >= bitmap.len() {
+                        bitmap.resize(byte + 1, 0);
+                    }
+                    bitmap[byte] |= 1 << (code % 8);
+                }
+                bitmap
+            }
+            VIRTIO_INPUT_CFG_ABS_INFO => self
+                .abs_info
+                .iter()
+                .find(|(axis, _)| *axis == subsel.into())
+                .map_or(Vec::new(), |(_, info)| {
+                    // min, max, fuzz, flat, res
+                    [info.min, info.max, 0, 0, 0]
+                        .into_iter()
+                        .flat_map(u32::to_le_bytes)
+                        .collect()
+                }),
+            // No serial number or input properties.
+            VIRTIO_INPUT_CFG_ID_SERIAL | VIRTIO_INPUT_CFG_PROP_BITS => Vec::new(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// A `virtio_input_event`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct InputEvent {
+    ty: u16,
+    code: u16,
+    value: i32,
+}
+
+impl InputEvent {
+    fn to_bytes(self) -> [u8; EVENT_SIZE] {
+        let mut buf = [0; EVENT_SIZE];
+        buf[0..2].copy_from_slice(&self.ty.to_le_bytes());
+        buf[2..4].copy_from_slice(&self.code.to_le_bytes());
+        buf[4..8].copy_from_slice(&self.value.to_le_bytes());
+        buf
+    }
+}
+
+/// Host input, and the state needed to translate it to evdev events.
+enum Source {
+    Keyboard {
+        source: Box<dyn InputSource<KeyboardData>>,
+        /// Bitmap of pressed Linux key codes, to report repeats.
+        pressed: u128,
+    },
+    Pointer {
+        source: Box<dyn InputSource<MouseData>>,
+        absolute: bool,
+        buttons: u8,
+        position: Option<(u16, u16)>,
+    },
+}
+
+enum SourceInput {
+    Keyboard(KeyboardData),
+    Pointer(MouseData),
+}
+
+impl Source {
+    async fn set_active(&mut self, active: bool) {
+        match self {
+            Source::Keyboard { source, .. } => source.set_active(active).await,
+            Source::Pointer { source, .. } => source.set_active(active).await,
+        }
+    }
+
+    async fn next(&mut self) -> Option<SourceInput> {
+        match self {
+            Source::Keyboard { source, .. } => source.next().await.map(SourceInput::Keyboard),
+            Source::Pointer { source, .. } => source.next().await.map(SourceInput::Pointer),
+        }
+    }
+
+    /// Translates host input to a report: a series of events ending with
+    /// `SYN_REPORT`. Returns an empty report if nothing changed.
+    fn translate(&mut self, input: SourceInput) -> Vec<InputEvent> {
+        let mut events = Vec::new();
+        let mut push = |ty, code, value| events.push(InputEvent { ty, code, value });
+        match (self, input) {
+            (Source::Keyboard { pressed, .. }, SourceInput::Keyboard(input)) => {
+                let Some(code) = keymap::linux_key_code(input.code) else {
+                    tracelimit::warn_ratelimited!(code = input.code, "unknown scan code");
+                    return Vec::new();
+                };
+                let bit = 1 << code;
+                let value = if !input.make {
+                    0
+                } else if *pressed & bit != 0 {
+                    // The host sends a make for each repeat.
+                    2
+                } else {
+                    1
+                };
+                if input.make {
+                    *pressed |= bit;
+                } else {
+                    *pressed &= !bit;
+                }
+                push(EV_KEY, code, value);
+            }
+            (
+                Source::Pointer {
+                    absolute,
+                    buttons,
+                    position,
+                    ..
+                },
+                SourceInput::Pointer(input),
+            ) => {
+                if *absolute {
+                    if *position != Some((input.x, input.y)) {
+                        push(EV_ABS, ABS_X, input.x.into());
+                        push(EV_ABS, ABS_Y, input.y.into());
+                    }
+                } else if let Some((x, y)) = *position {
+                    let scale =
+                        |v: u16, size: i32| i32::from(v) * size / (i32::from(POINTER_MAX) + 1);
+                    let dx = scale(input.x, RELATIVE_WIDTH) - scale(x, RELATIVE_WIDTH);
+                    let dy = scale(input.y, RELATIVE_HEIGHT) - scale(y, RELATIVE_HEIGHT);
+                    if dx != 0 {
+                        push(EV_REL, REL_X, dx);
+                    }
+                    if dy != 0 {
+                        push(EV_REL, REL_Y, dy);
+                    }
+                }
+                *position = Some((input.x, input.y));
+
+                let changed = *buttons ^ input.button_mask;
+                for (bit, code) in POINTER_BUTTONS {
+                    if changed & (1 << bit) != 0 {
+                        push(EV_KEY, code, (input.button_mask >> bit & 1).into());
+                    }
+                }
+                // The wheel moves one notch each time its button is pressed.
+                let newly_pressed = changed & input.button_mask;
+                if newly_pressed & WHEEL_UP != 0 {
+                    push(EV_REL, REL_WHEEL, 1);
+                }
+                if newly_pressed & WHEEL_DOWN != 0 {
+                    push(EV_REL, REL_WHEEL, -1);
+                }
+                *buttons = input.button_mask;
+            }
+            _ => unreachable!(),
+        }
+        if !events.is_empty() {
+            events.push(InputEvent {
+                ty: EV_SYN,
+                code: SYN_REPORT,
+                value: 0,
+            });
+        }
+        events
+    }
+}
+
+#[derive(InspectMut)]
+struct InputWorker {
+    #[inspect(skip)]
+    source: Source,
+    #[inspect(with = "VecDeque::len")]
+    pending: VecDeque<InputEvent>,
+    #[inspect(counter)]
+    dropped_events: u64,
+}
+
+#[derive(InspectMut)]
+struct InputQueues {
+    #[inspect(skip)]
+    mem: GuestMemory,
+    eventq: Option<VirtioQueue>,
+    statusq: Option<VirtioQueue>,
+}
+
+impl InputQueues {
+    fn queue_mut(&mut self, idx: u16) -> &mut Option<VirtioQueue> {
+        match idx {
+            0 => &mut self.eventq,
+            1 => &mut self.statusq,
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl InspectTaskMut<InputQueues> for InputWorker {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>, state: Option<&mut InputQueues>) {
+        req.respond().merge(self).merge(state);
+    }
+}
+
+impl AsyncRun<InputQueues> for InputWorker {
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        state: &mut InputQueues,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async {
+            // Direct input to this device now that its driver is running.
+            self.source.set_active(true).await;
+            if let Err(err) = self.process(state).await {
+                tracelimit::error_ratelimited!(
+                    err = &err as &dyn std::error::Error,
+                    "virtio-input queue error"
+                );
+            }
+        })
+        .await
+    }
+}
+
+impl InputWorker {
+    async fn process(&mut self, state: &mut InputQueues) -> Result<(), std::io::Error> {
+        enum Event {
+            Input(Option<SourceInput>),
+            Buffer(Result<VirtioQueueCallbackWork, std::io::Error>),
+            Status(Result<VirtioQueueCallbackWork, std::io::Error>),
+        }
+
+        let Self {
+            source,
+            pending,
+            dropped_events,
+        } = self;
+        let InputQueues {
+            mem,
+            eventq,
+            statusq,
+        } = state;
+        let mut source_done = false;
+        loop {
+            let input = async {
+                if source_done {
+                    pending::<()>().await;
+                }
+                Event::Input(source.next().await)
+            };
+            // Only take buffers from the guest when there is something to put
+            // in them.
+            let buffer = async {
+                match eventq {
+                    Some(queue) if !pending.is_empty() => Event::Buffer(next_work(queue).await),
+                    _ => pending().await,
+                }
+            };
+            let status = async {
+                match statusq {
+                    Some(queue) => Event::Status(next_work(queue).await),
+                    None => pending().await,
+                }
+            };
+
+            match (input, buffer, status).race().await {
+                Event::Input(Some(input)) => {
+                    let report = source.translate(input);
+                    if pending.len() + report.len() > MAX_PENDING_EVENTS {
+                        tracelimit::warn_ratelimited!("guest is not taking input, dropping events");
+                        *dropped_events += report.len() as u64;
+                    } else {
+                        pending.extend(report);
+                    }
+                }
+                Event::Input(None) => source_done = true,
+                Event::Buffer(work) => {
+                    let work = work?;
+                    let event = pending.pop_front().unwrap();
+                    let len = match work.write(mem, &event.to_bytes()) {
+                        Ok(()) => EVENT_SIZE as u32,
+                        Err(err) => {
+                            tracelimit::warn_ratelimited!(
+                                error = &err as &dyn std::error::Error,
+                                "failed to write input event"
+                            );
+                            0
+                        }
+                    };
+                    eventq.as_mut().unwrap().complete(work, len);
+                }
+                Event::Status(work) => {
+                    let work = work?;
+                    let mut buf = [0; EVENT_SIZE];
+                    if work.read(mem, &mut buf).is_ok() {
+                        tracing::trace!(
+                            ty = u16::from_le_bytes([buf[0], buf[1]]),
+                            code = u16::from_le_bytes([buf[2], buf[3]]),
+                            "guest status event"
+                        );
+                    }
+                    statusq.as_mut().unwrap().complete(work, 0);
+                }
+            }
+        }
+    }
+}
+
+async fn next_work(queue: &mut VirtioQueue) -> Result<VirtioQueueCallbackWork, std::io::Error> {
+    match queue.next().await {
+        Some(work) => work,
+        None => pending().await,
+    }
+}
@@ -0,0 +1,349 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tests for the virtio-input devices.
+
+use crate::InputEvent;
+use crate::Source;
+use crate::SourceInput;
+use crate::VirtioInputDevice;
+use crate::keymap::linux_key_code;
+use crate::spec::*;
+use guestmem::GuestMemory;
+use input_core::KeyboardData;
+use input_core::MouseData;
+use input_core::mesh_input::MeshInputSink;
+use input_core::mesh_input::input_pair;
+use pal_async::DefaultDriver;
+use pal_async::async_test;
+use pal_event::Event;
+use test_with_tracing::test;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::queue::QueueParams;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio::spec::queue::DescriptorFlags;
+use virtio::test_helpers::init_avail_ring;
+use virtio::test_helpers::init_used_ring;
+use virtio::test_helpers::make_available;
+use virtio::test_helpers::wait_for_used;
+use virtio::test_helpers::write_descriptor;
+use vmcore::interrupt::Interrupt;
+use vmcore::vm_task::SingleDriverBackend;
+use vmcore::vm_task::VmTaskDriverSource;
+
+const QUEUE_SIZE: u16 = 16;
+
+const EVENTQ_DESC_ADDR: u64 = 0x0000;
+const EVENTQ_AVAIL_ADDR: u64 = 0x1000;
+const EVENTQ_USED_ADDR: u64 = 0x2000;
+const STATUSQ_DESC_ADDR: u64 = 0x10000;
+const STATUSQ_AVAIL_ADDR: u64 = 0x11000;
+const STATUSQ_USED_ADDR: u64 = 0x12000;
+const DATA_BASE: u64 = 0x20000;
+const TOTAL_MEM_SIZE: usize = 0x30000;
+
+fn event(ty: u16, code: u16, value: i32) -> InputEvent {
+    InputEvent { ty, code, value }
+}
+
+fn syn() -> InputEvent {
+    event(EV_SYN, SYN_REPORT, 0)
+}
+
+struct TestHarness {
+    device: VirtioInputDevice,
+    mem: GuestMemory,
+    driver: DefaultDriver,
+    event_event: Event,
+    event_interrupt: Event,
+    status_event: Event,
+    status_interrupt: Event,
+    avail_idx: u16,
+    used_idx: u16,
+}
+
+impl TestHarness {
+    fn new(driver: &DefaultDriver, device: VirtioInputDevice) -> Self {
+        let mem = GuestMemory::allocate(TOTAL_MEM_SIZE);
+        init_avail_ring(&mem, EVENTQ_AVAIL_ADDR);
+        init_used_ring(&mem, EVENTQ_USED_ADDR);
+        init_avail_ring(&mem, STATUSQ_AVAIL_ADDR);
+        init_used_ring(&mem, STATUSQ_USED_ADDR);
+        Self {
+            device,
+            mem,
+            driver: driver.clone(),
+            event_event: Event::new(),
+            event_interrupt: Event::new(),
+            status_event: Event::new(),
+            status_interrupt: Event::new(),
+            avail_idx: 0,
+            used_idx: 0,
+        }
+    }
+
+    async fn enable(&mut self) {
+        let features = VirtioDeviceFeatures::new();
+        let queues = [
+            (
+                EVENTQ_DESC_ADDR,
+                EVENTQ_AVAIL_ADDR,
+                EVENTQ_USED_ADDR,
+                &self.event_event,
+                &self.event_interrupt,
+            ),
+            (
+                STATUSQ_DESC_ADDR,
+                STATUSQ_AVAIL_ADDR,
+                STATUSQ_USED_ADDR,
+                &self.status_event,
+                &self.status_interrupt,
+            ),
+        ];
+        for (idx, (desc_addr, avail_addr, used_addr, event, interrupt)) in
+            queues.into_iter().enumerate()
+        {
+            self.device
+                .start_queue(
+                    idx as u16,
+                    QueueResources {
+                        params: QueueParams {
+                            size: QUEUE_SIZE,
+                            enable: true,
+                            desc_addr,
+                            avail_addr,
+                            used_addr,
+                        },
+                        notify: Interrupt::from_event(interrupt.clone()),
+                        event: event.clone(),
+                        guest_memory: self.mem.clone(),
+                    },
+                    &features,
+                    None,
+                )
+                .await
+                .unwrap();
+        }
+    }
+
+    /// Posts `count` event buffers, as the guest driver does at startup.
+    fn post_event_buffers(&mut self, count: u16) {
+        for desc in 0..count {
+            write_descriptor(
+                &self.mem,
+                EVENTQ_DESC_ADDR,
+                desc,
+                DATA_BASE + desc as u64 * EVENT_SIZE as u64,
+                EVENT_SIZE as u32,
+                DescriptorFlags::new().with_write(true),
+                0,
+            );
+            make_available(
+                &self.mem,
+                EVENTQ_AVAIL_ADDR,
+                QUEUE_SIZE,
+                desc,
+                &mut self.avail_idx,
+            );
+        }
+        self.event_event.signal();
+    }
+
+    /// Waits for the device to fill the next event buffer, and returns the
+    /// event.
+    async fn next_event(&mut self) -> InputEvent {
+        let (desc, len) = wait_for_used(
+            &self.driver,
+            &self.event_interrupt,
+            &self.mem,
+            EVENTQ_USED_ADDR,
+            QUEUE_SIZE,
+            &mut self.used_idx,
+        )
+        .await;
+        assert_eq!(len, EVENT_SIZE as u32);
+        let mut buf = [0; EVENT_SIZE];
+        self.mem
+            .read_at(DATA_BASE + desc as u64 * EVENT_SIZE as u64, &mut buf)
+            .unwrap();
+        event(
+            u16::from_le_bytes([buf[0], buf[1]]),
+            u16::from_le_bytes([buf[2], buf[3]]),
+            i32::from_le_bytes(buf[4..].try_into().unwrap()),
+        )
+    }
+
+    /// Reads the config space union for `select` and `subsel`.
+    async fn read_config(&mut self, select: u8, subsel: u8) -> Vec<u8> {
+        self.device
+            .write_registers_u32(0, u32::from_le_bytes([select, subsel, 0, 0]))
+            .await;
+        let size = self.device.read_registers_u32(0).await.to_le_bytes()[2] as usize;
+        let mut data = Vec::new();
+        for offset in (0..size).step_by(4) {
+            let val = self
+                .device
+                .read_registers_u32((CONFIG_HEADER_LEN + offset) as u16)
+                .await;
+            data.extend(val.to_le_bytes());
+        }
+        data.truncate(size);
+        data
+    }
+}
+
+fn pointer_source(absolute: bool) -> (Source, MeshInputSink<MouseData>) {
+    let (source, sink) = input_pair();
+    (
+        Source::Pointer {
+            source: Box::new(source),
+            absolute,
+            buttons: 0,
+            position: None,
+        },
+        sink,
+    )
+}
+
+fn mouse(button_mask: u8, x: u16, y: u16) -> SourceInput {
+    SourceInput::Pointer(MouseData { button_mask, x, y })
+}
+
+#[test]
+fn key_codes() {
+    // KEY_A, KEY_ESC, KEY_F12
+    assert_eq!(linux_key_code(0x1e), Some(30));
+    assert_eq!(linux_key_code(0x01), Some(1));
+    assert_eq!(linux_key_code(0x58), Some(88));
+    assert_eq!(linux_key_code(0xe048), Some(KEY_UP));
+    assert_eq!(linux_key_code(0xe01d), Some(KEY_RIGHTCTRL));
+    assert_eq!(linux_key_code(0xe11d), Some(KEY_PAUSE));
+    assert_eq!(linux_key_code(0x59), None);
+    assert_eq!(linux_key_code(0xe001), None);
+}
+
+#[test]
+fn keyboard_repeat() {
+    let (source, _sink) = input_pair();
+    let mut source = Source::Keyboard {
+        source: Box::new(source),
+        pressed: 0,
+    };
+    let key = |make| SourceInput::Keyboard(KeyboardData { code: 0x1e, make });
+    assert_eq!(source.translate(key(true)), [event(EV_KEY, 30, 1), syn()]);
+    assert_eq!(source.translate(key(true)), [event(EV_KEY, 30, 2), syn()]);
+    assert_eq!(source.translate(key(false)), [event(EV_KEY, 30, 0), syn()]);
+    assert_eq!(source.translate(key(true)), [event(EV_KEY, 30, 1), syn()]);
+}
+
+#[test]
+fn relative_motion() {
+    let (mut source, _sink) = pointer_source(false);
+    // The first position only establishes where the pointer is.
+    assert!(source.translate(mouse(0, 0x4000, 0x4000)).is_empty());
+    // Quarter of the screen to the right, an eighth up.
+    assert_eq!(
+        source.translate(mouse(0, 0x6000, 0x3000)),
+        [event(EV_REL, REL_X, 256), event(EV_REL, REL_Y, -96), syn()]
+    );
+    // Buttons and the wheel.
+    assert_eq!(
+        source.translate(mouse(0b1_0001, 0x6000, 0x3000)),
+        [
+            event(EV_KEY, BTN_LEFT, 1),
+            event(EV_REL, REL_WHEEL, -1),
+            syn()
+        ]
+    );
+    assert_eq!(
+        source.translate(mouse(0, 0x6000, 0x3000)),
+        [event(EV_KEY, BTN_LEFT, 0), syn()]
+    );
+}
+
+#[test]
+fn absolute_position() {
+    let (mut source, _sink) = pointer_source(true);
+    assert_eq!(
+        source.translate(mouse(0b100, 100, 200)),
+        [
+            event(EV_ABS, ABS_X, 100),
+            event(EV_ABS, ABS_Y, 200),
+            event(EV_KEY, BTN_RIGHT, 1),
+            syn()
+        ]
+    );
+    // Nothing changed.
+    assert!(source.translate(mouse(0b100, 100, 200)).is_empty());
+}
+
+#[async_test]
+async fn config_space(driver: DefaultDriver) {
+    let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+    let (source, _sink) = input_pair();
+    let device = VirtioInputDevice::keyboard(&driver_source, Box::new(source));
+    let mut harness = TestHarness::new(&driver, device);
+
+    assert_eq!(
+        harness.read_config(VIRTIO_INPUT_CFG_ID_NAME, 0).await,
+        b"OpenVMM Virtio Keyboard"
+    );
+
+    let keys = harness
+        .read_config(VIRTIO_INPUT_CFG_EV_BITS, EV_KEY as u8)
+        .await;
+    let has_key = |code: u16| keys[code as usize / 8] & (1 << (code % 8)) != 0;
+    assert!(has_key(30));
+    assert!(has_key(KEY_UP));
+    assert!(!has_key(0));
+
+    // A keyboard has no absolute axes.
+    assert!(
+        harness
+            .read_config(VIRTIO_INPUT_CFG_EV_BITS, EV_ABS as u8)
+            .await
+            .is_empty()
+    );
+    assert!(
+        harness
+            .read_config(VIRTIO_INPUT_CFG_ABS_INFO, ABS_X as u8)
+            .await
+            .is_empty()
+    );
+}
+
+#[async_test]
+async fn tablet_events(driver: DefaultDriver) {
+    let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+    let (source, mut sink) = input_pair();
+    let device = VirtioInputDevice::tablet(&driver_source, Box::new(source));
+    let mut harness = TestHarness::new(&driver, device);
+
+    let abs = harness
+        .read_config(VIRTIO_INPUT_CFG_ABS_INFO, ABS_Y as u8)
+        .await;
+    assert_eq!(u32::from_le_bytes(abs[4..8].try_into().unwrap()), 0x7fff);
+
+    harness.enable().await;
+
+    // Input that arrives before the guest posts buffers is held.
+    sink.send(MouseData {
+        button_mask: 1,
+        x: 100,
+        y: 200,
+    });
+    harness.post_event_buffers(QUEUE_SIZE);
+    assert_eq!(harness.next_event().await, event(EV_ABS, ABS_X, 100));
+    assert_eq!(harness.next_event().await, event(EV_ABS, ABS_Y, 200));
+    assert_eq!(harness.next_event().await, event(EV_KEY, BTN_LEFT, 1));
+    assert_eq!(harness.next_event().await, syn());
+
+    sink.send(MouseData {
+        button_mask: 0,
+        x: 100,
+        y: 200,
+    });
+    assert_eq!(harness.next_event().await, event(EV_KEY, BTN_LEFT, 0));
+    assert_eq!(harness.next_event().await, syn());
+}
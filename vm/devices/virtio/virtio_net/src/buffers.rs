@@ -3,8 +3,11 @@
 
 use crate::VirtioNetHeader;
 use crate::VirtioNetHeaderFlags;
-use crate::header_size;
+use crate::hash::HASH_PEEK_SIZE;
+use crate::hash::HashConfig;
+use crate::hash::HashReport;
 use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
 use inspect::Inspect;
 use net_backend::BufferAccess;
 use net_backend::RxBufferSegment;
@@ -27,6 +30,10 @@ pub struct VirtioWorkPool {
     mem: GuestMemory,
     #[inspect(skip)]
     rx_packets: Vec<Option<RxPacket>>,
+    /// The size of the virtio-net header preceding each packet.
+    header_size: usize,
+    /// The configuration for reporting receive hashes in the header.
+    hash: Option<HashConfig>,
 }
 
 /// Reason a submitted RX buffer could not be queued to the backend, returned
@@ -50,13 +57,21 @@ fn inspect_extra(&self, resp: &mut inspect::Response<'_>) {
     }
 
     /// Create a new instance.
-    pub fn new(mem: GuestMemory, queue_size: u16) -> Self {
+    pub fn new(mem: GuestMemory, queue_size: u16, header_size: usize) -> Self {
         Self {
             mem,
             rx_packets: (0..queue_size).map(|_| None).collect(),
+            header_size,
+            hash: None,
         }
     }
 
+    /// Sets the hash configuration used to fill in the header's hash fields.
+    /// Only takes effect if the header includes them.
+    pub fn set_hash_config(&mut self, hash: Option<HashConfig>) {
+        self.hash = hash;
+    }
+
     /// Returns a reference to the guest memory.
     pub fn mem(&self) -> &GuestMemory {
         &self.mem
@@ -94,7 +109,7 @@ pub fn queue_work(&mut self, work: VirtioQueueCallbackWork) -> Result<RxId, RxQu
             return Err(RxQueueError::DuplicateIndex(work));
         }
         let payload_length = work.get_payload_length(true) as u32;
-        let Some(cap) = payload_length.checked_sub(header_size() as u32) else {
+        let Some(cap) = payload_length.checked_sub(self.header_size as u32) else {
             tracelimit::warn_ratelimited!(
                 len = payload_length,
                 "dropping RX buffer: payload length smaller than virtio-net header size"
@@ -118,12 +133,40 @@ pub fn take_rx_work(&mut self, rx_id: RxId) -> (VirtioQueueCallbackWork, u32) {
             tracelimit::warn_ratelimited!("dropping RX buffer: header not written");
             0
         } else {
-            packet.len + header_size() as u32
+            packet.len + self.header_size as u32
         };
         (packet.work, payload_len)
     }
 }
 
+/// Reads back data written to the writeable part of `work`, skipping the first
+/// `offset` bytes.
+fn read_written(
+    work: &VirtioQueueCallbackWork,
+    offset: u64,
+    mem: &GuestMemory,
+    target: &mut [u8],
+) -> Result<(), GuestMemoryError> {
+    let mut skip = offset;
+    let mut remaining = target;
+    for payload in work.payload.iter().filter(|p| p.writeable) {
+        if remaining.is_empty() {
+            break;
+        }
+        let len = payload.length as u64;
+        if skip >= len {
+            skip -= len;
+            continue;
+        }
+        let size = remaining.len().min((len - skip) as usize);
+        let (current, next) = remaining.split_at_mut(size);
+        mem.read_at(payload.address.saturating_add(skip), current)?;
+        remaining = next;
+        skip = 0;
+    }
+    Ok(())
+}
+
 impl BufferAccess for VirtioWorkPool {
     fn guest_memory(&self) -> &GuestMemory {
         &self.mem
@@ -135,7 +178,7 @@ fn write_data(&mut self, id: RxId, data: &[u8]) {
             .expect("invalid buffer index");
         if let Err(err) = packet
             .work
-            .write_at_offset(header_size() as u64, &self.mem, data)
+            .write_at_offset(self.header_size as u64, &self.mem, data)
         {
             tracelimit::warn_ratelimited!(
                 len = data.len(),
@@ -149,7 +192,7 @@ fn write_packet_segments(&mut self, id: RxId, metadata: &RxMetadata, segments: &
         let packet = self.rx_packets[id.0 as usize]
             .as_mut()
             .expect("invalid buffer index");
-        let mut offset = header_size() as u64;
+        let mut offset = self.header_size as u64;
         for segment in segments {
             if let Err(err) = packet.work.write_at_offset(offset, &self.mem, segment) {
                 tracelimit::warn_ratelimited!(
@@ -198,7 +241,7 @@ fn write_header(&mut self, id: RxId, metadata: &RxMetadata) {
         let data_valid = metadata.ip_checksum.is_valid() && metadata.l4_checksum.is_valid();
         let flags = VirtioNetHeaderFlags::new().with_data_valid(data_valid);
 
-        let virtio_net_header = VirtioNetHeader {
+        let mut virtio_net_header = VirtioNetHeader {
             flags: flags.into(),
             num_buffers: 1,
             ..FromZeros::new_zeroed()
@@ -206,9 +249,33 @@ fn write_header(&mut self, id: RxId, metadata: &RxMetadata) {
         let packet = self.rx_packets[id.0 as usize]
             .as_mut()
             .expect("invalid buffer index");
+
+        if self.header_size == size_of::<VirtioNetHeader>() {
+            let (hash_value, hash_report) = self
+                .hash
+                .as_ref()
+                .and_then(|hash| {
+                    // The packet has already been written, so read its headers
+                    // back from the buffer.
+                    let mut frame = [0; HASH_PEEK_SIZE];
+                    let len = metadata.len.min(frame.len());
+                    read_written(
+                        &packet.work,
+                        self.header_size as u64,
+                        &self.mem,
+                        &mut frame[..len],
+                    )
+                    .ok()?;
+                    hash.hash(&frame[..len])
+                })
+                .unwrap_or((0, HashReport::NONE));
+            virtio_net_header.hash_value = hash_value;
+            virtio_net_header.hash_report = hash_report.0;
+        }
+
         if let Err(err) = packet
             .work
-            .write(&self.mem, &virtio_net_header.as_bytes()[..header_size()])
+            .write(&self.mem, &virtio_net_header.as_bytes()[..self.header_size])
         {
             tracelimit::warn_ratelimited!(
                 error = &err as &dyn std::error::Error,
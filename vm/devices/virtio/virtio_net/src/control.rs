@@ -0,0 +1,76 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Control virtqueue command definitions and parsing.
+
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::KnownLayout;
+
+// Acknowledgement values written back to the guest.
+pub const VIRTIO_NET_OK: u8 = 0;
+pub const VIRTIO_NET_ERR: u8 = 1;
+
+// Command classes. Only the multiqueue class is supported.
+pub const VIRTIO_NET_CTRL_MQ: u8 = 4;
+
+// Commands in the VIRTIO_NET_CTRL_MQ class.
+pub const VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET: u8 = 0;
+pub const VIRTIO_NET_CTRL_MQ_RSS_CONFIG: u8 = 1;
+pub const VIRTIO_NET_CTRL_MQ_HASH_CONFIG: u8 = 2;
+
+/// The largest control command accepted, enough for an RSS configuration with
+/// the largest possible indirection table and key.
+pub const MAX_COMMAND_SIZE: usize = size_of::<CtrlHeader>() + 12 + 0x8000 * 2 + 0xff;
+
+/// `virtio_net_ctrl_hdr`.
+#[repr(C)]
+#[derive(Debug, FromBytes, Immutable, KnownLayout)]
+pub struct CtrlHeader {
+    pub class: u8,
+    pub command: u8,
+}
+
+/// `virtio_net_rss_config` up to the indirection table.
+#[repr(C)]
+#[derive(Debug, FromBytes, Immutable, KnownLayout)]
+struct RssConfigHeader {
+    hash_types: u32,
+    indirection_table_mask: u16,
+    unclassified_queue: u16,
+}
+
+/// A parsed `virtio_net_rss_config` or `virtio_net_hash_config` command. The
+/// latter has the same layout, with its reserved fields in place of a
+/// single-entry indirection table.
+#[derive(Debug)]
+pub struct RssConfigCommand {
+    pub hash_types: u32,
+    pub unclassified_queue: u16,
+    pub indirection_table: Vec<u16>,
+    pub max_tx_vq: u16,
+    pub key: Vec<u8>,
+}
+
+impl RssConfigCommand {
+    /// Parses the command data following the control header. Returns `None`
+    /// if the command is truncated.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let (header, rest) = RssConfigHeader::read_from_prefix(data).ok()?;
+        let table_len = header.indirection_table_mask as usize + 1;
+        let (table, rest) = rest.split_at_checked(table_len * 2)?;
+        let (max_tx_vq, rest) = u16::read_from_prefix(rest).ok()?;
+        let (&key_len, rest) = rest.split_first()?;
+        let key = rest.get(..key_len as usize)?;
+        Some(Self {
+            hash_types: header.hash_types,
+            unclassified_queue: header.unclassified_queue,
+            indirection_table: table
+                .chunks_exact(2)
+                .map(|x| u16::from_le_bytes(x.try_into().unwrap()))
+                .collect(),
+            max_tx_vq,
+            key: key.to_vec(),
+        })
+    }
+}
@@ -0,0 +1,172 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Receive hash calculation for `VIRTIO_NET_F_HASH_REPORT`.
+//!
+//! Network backends do not report the RSS hash of received packets, so the
+//! device computes it itself from the packet headers, using the key and hash
+//! types most recently configured by the guest.
+
+use bitfield_struct::bitfield;
+use inspect::Inspect;
+
+/// The maximum RSS key size offered to the guest. This is the Toeplitz key
+/// size used by every RSS implementation, including netvsp's.
+pub const MAX_KEY_SIZE: u8 = 40;
+
+// These correspond to VIRTIO_NET_RSS_HASH_TYPE_ flags.
+#[bitfield(u32)]
+pub struct HashTypes {
+    pub ipv4: bool,
+    pub tcpv4: bool,
+    pub udpv4: bool,
+    pub ipv6: bool,
+    pub tcpv6: bool,
+    pub udpv6: bool,
+    pub ip_ex: bool,
+    pub tcp_ex: bool,
+    pub udp_ex: bool,
+    #[bits(23)]
+    _reserved: u32,
+}
+
+impl HashTypes {
+    /// The hash types the device can compute. The IPv6 extension header
+    /// variants are not supported.
+    pub fn supported() -> Self {
+        Self::new()
+            .with_ipv4(true)
+            .with_tcpv4(true)
+            .with_udpv4(true)
+            .with_ipv6(true)
+            .with_tcpv6(true)
+            .with_udpv6(true)
+    }
+}
+
+// These correspond to VIRTIO_NET_HASH_REPORT_ values.
+open_enum::open_enum! {
+    pub enum HashReport: u16 {
+        NONE = 0,
+        IPV4 = 1,
+        TCPV4 = 2,
+        UDPV4 = 3,
+        IPV6 = 4,
+        TCPV6 = 5,
+        UDPV6 = 6,
+    }
+}
+
+/// The receive hash configuration set by the guest via the control queue.
+#[derive(Debug, Clone, Inspect)]
+pub struct HashConfig {
+    #[inspect(with = "|x| inspect::AsBytes(x)")]
+    pub key: Vec<u8>,
+    #[inspect(hex, with = "|x| u32::from(*x)")]
+    pub types: HashTypes,
+}
+
+const ETHERTYPE_IPV4: u16 = 0x0800;
+const ETHERTYPE_IPV6: u16 = 0x86DD;
+const ETHERTYPE_VLAN: u16 = 0x8100;
+const IPPROTO_TCP: u8 = 6;
+const IPPROTO_UDP: u8 = 17;
+
+/// The number of bytes of an Ethernet frame needed to compute any supported
+/// hash: a VLAN-tagged Ethernet header, an IPv4 header with maximal options,
+/// and the L4 ports.
+pub const HASH_PEEK_SIZE: usize = 18 + 60 + 4;
+
+impl HashConfig {
+    /// Computes the hash of the Ethernet frame starting with `frame`, which
+    /// must contain at least [`HASH_PEEK_SIZE`] bytes if the frame is that
+    /// long.
+    ///
+    /// Returns `None` if none of the configured hash types apply to the
+    /// frame.
+    pub fn hash(&self, frame: &[u8]) -> Option<(u32, HashReport)> {
+        let (ethertype, ip) = match be16(frame, 12)? {
+            ETHERTYPE_VLAN => (be16(frame, 16)?, frame.get(18..)?),
+            ethertype => (ethertype, frame.get(14..)?),
+        };
+
+        let mut input = [0; 36];
+        let (addrs, protocol, l4, reports) = match ethertype {
+            ETHERTYPE_IPV4 => {
+                let ihl = (*ip.first()? & 0xf) as usize * 4;
+                if ip[0] >> 4 != 4 || ihl < 20 || ip.len() < ihl {
+                    return None;
+                }
+                // Fragments carry no (or only partial) L4 headers.
+                let fragmented = be16(ip, 6)? & 0x3fff != 0;
+                let l4 = if fragmented { &[][..] } else { &ip[ihl..] };
+                (
+                    &ip[12..20],
+                    ip[9],
+                    l4,
+                    [
+                        (self.types.tcpv4(), HashReport::TCPV4),
+                        (self.types.udpv4(), HashReport::UDPV4),
+                        (self.types.ipv4(), HashReport::IPV4),
+                    ],
+                )
+            }
+            ETHERTYPE_IPV6 => {
+                if ip.len() < 40 || ip[0] >> 4 != 6 {
+                    return None;
+                }
+                (
+                    &ip[8..40],
+                    ip[6],
+                    &ip[40..],
+                    [
+                        (self.types.tcpv6(), HashReport::TCPV6),
+                        (self.types.udpv6(), HashReport::UDPV6),
+                        (self.types.ipv6(), HashReport::IPV6),
+                    ],
+                )
+            }
+            _ => return None,
+        };
+
+        input[..addrs.len()].copy_from_slice(addrs);
+        let [tcp, udp, ip_only] = reports;
+        let ports = l4.get(..4);
+        let (len, report) = match (protocol, ports) {
+            (IPPROTO_TCP, Some(ports)) if tcp.0 => {
+                input[addrs.len()..][..4].copy_from_slice(ports);
+                (addrs.len() + 4, tcp.1)
+            }
+            (IPPROTO_UDP, Some(ports)) if udp.0 => {
+                input[addrs.len()..][..4].copy_from_slice(ports);
+                (addrs.len() + 4, udp.1)
+            }
+            _ if ip_only.0 => (addrs.len(), ip_only.1),
+            _ => return None,
+        };
+        Some((toeplitz(&self.key, &input[..len]), report))
+    }
+}
+
+fn be16(buf: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        buf.get(offset..offset + 2)?.try_into().unwrap(),
+    ))
+}
+
+/// Computes the Toeplitz hash of `input` with `key`. Key bits past the end
+/// of `key` are treated as zero.
+pub fn toeplitz(key: &[u8], input: &[u8]) -> u32 {
+    let key_bit = |i: usize| key.get(i / 8).is_some_and(|b| b & (0x80 >> (i % 8)) != 0);
+    let mut window = (0..32).fold(0u32, |w, i| (w << 1) | key_bit(i) as u32);
+    let mut hash = 0;
+    for (i, byte) in input.iter().enumerate() {
+        for bit in 0..8 {
+            if byte & (0x80 >> bit) != 0 {
+                hash ^= window;
+            }
+            window = (window << 1) | key_bit(i * 8 + bit + 32) as u32;
+        }
+    }
+    hash
+}
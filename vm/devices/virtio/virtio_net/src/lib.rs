@@ -4,24 +4,49 @@
 //! Virtio network device implementation.
 //!
 //! This crate implements a virtio-net device that connects a guest's virtual
-//! NIC to a pluggable [`net_backend::Endpoint`]. It currently operates with a
-//! single queue pair (one RX, one TX) and supports synchronous and asynchronous
-//! TX completion modes depending on the backend.
+//! NIC to a pluggable [`net_backend::Endpoint`]. Each virtio queue pair (one
+//! RX, one TX) maps onto one backend [`net_backend::Queue`]. When the backend
+//! supports multiple queues, the device offers `VIRTIO_NET_F_MQ` and
+//! `VIRTIO_NET_F_RSS` through the control queue, and computes receive hashes
+//! itself for `VIRTIO_NET_F_HASH_REPORT`. It supports synchronous and
+//! asynchronous TX completion modes depending on the backend.
+//!
+//! On Linux, an endpoint may instead offer a kernel vhost-net datapath (see
+//! [`net_backend::vhost`]). The device then hands its single queue pair to the
+//! kernel and only emulates the transport and configuration space.
 
 #![expect(missing_docs)]
 #![forbid(unsafe_code)]
 
 mod buffers;
+mod control;
+mod hash;
 pub mod resolver;
+#[cfg(target_os = "linux")]
+mod vhost;
 
 #[cfg(test)]
 mod tests;
 
 use crate::buffers::RxQueueError;
 use crate::buffers::VirtioWorkPool;
+use crate::control::CtrlHeader;
+use crate::control::MAX_COMMAND_SIZE;
+use crate::control::RssConfigCommand;
+use crate::control::VIRTIO_NET_CTRL_MQ;
+use crate::control::VIRTIO_NET_CTRL_MQ_HASH_CONFIG;
+use crate::control::VIRTIO_NET_CTRL_MQ_RSS_CONFIG;
+use crate::control::VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET;
+use crate::control::VIRTIO_NET_ERR;
+use crate::control::VIRTIO_NET_OK;
+use crate::hash::HashConfig;
+use crate::hash::HashTypes;
 use anyhow::Context as _;
 use bitfield_struct::bitfield;
+use futures::StreamExt;
+use futures_concurrency::future::Race;
 use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
 use inspect::Inspect;
 use inspect::InspectMut;
 use inspect_counters::Counter;
@@ -29,6 +54,8 @@
 use net_backend::Endpoint;
 use net_backend::EndpointAction;
 use net_backend::QueueConfig;
+use net_backend::RssConfig;
+use net_backend::RssFlags;
 use net_backend::RxId;
 use net_backend::TxFlags;
 use net_backend::TxId;
@@ -141,9 +168,12 @@ struct NetStatus {
 
 const DEFAULT_MTU: u16 = 1514;
 
-#[expect(dead_code)]
 const VIRTIO_NET_MAX_QUEUES: u16 = 0x8000;
 
+/// The indirection table size offered to the guest when the backend does not
+/// specify one.
+const DEFAULT_INDIRECTION_TABLE_SIZE: u16 = 128;
+
 #[repr(C)]
 struct NetConfig {
     pub mac: [u8; 6],
@@ -215,14 +245,26 @@ struct VirtioNetHeader {
     pub padding_reserved: u16, // Only if VIRTIO_NET_F_HASH_REPORT negotiated
 }
 
+/// The size of the virtio-net header when `VIRTIO_NET_F_HASH_REPORT` is not
+/// negotiated.
 const fn header_size() -> usize {
-    // TODO: Verify hash flags are not set, since header size would be larger in that case.
     offset_of!(VirtioNetHeader, hash_value)
 }
 
+/// The size of the virtio-net header for the negotiated features.
+fn negotiated_header_size(features_bank1: NetworkFeaturesBank1) -> usize {
+    if features_bank1.hash_report() {
+        size_of::<VirtioNetHeader>()
+    } else {
+        header_size()
+    }
+}
+
 struct Adapter {
     driver: VmTaskDriver,
     max_queue_pairs: u16,
+    /// The maximum RSS indirection table length accepted from the guest.
+    indirection_table_size: u16,
     tx_fast_completions: bool,
     mac_address: MacAddress,
     tx_offload_support: TxOffloadSupport,
@@ -233,8 +275,14 @@ pub struct Device {
     coordinator: TaskControl<CoordinatorState, Coordinator>,
     adapter: Arc<Adapter>,
     driver_source: VmTaskDriverSource,
+    vp_count: u32,
     /// Per-pair state tracking.
     pairs: Vec<QueuePairState>,
+    /// The index of the control queue, if it has been started.
+    control_queue_index: Option<u16>,
+    /// The kernel datapath, which replaces the endpoint queues if present.
+    #[cfg(target_os = "linux")]
+    vhost: Option<vhost::VhostDatapath>,
 }
 
 /// Tracks the state of a queue pair through the start_queue lifecycle.
@@ -266,6 +314,10 @@ fn traits(&self) -> DeviceTraits {
         // HOST_UFO (bit 14) is not offered because it is deprecated in modern
         // Linux kernels.
         let host_uso = offloads.uso && offloads.udp;
+        // The control queue is only needed to configure multiple queues, so
+        // it and the multiqueue features are only offered if there is more
+        // than one queue pair.
+        let multiqueue = self.registers.max_virtqueue_pairs > 1;
 
         let features_bank0 = NetworkFeaturesBank0::new()
             .with_mac(true)
@@ -273,23 +325,52 @@ fn traits(&self) -> DeviceTraits {
             .with_csum(csum)
             .with_guest_csum(true)
             .with_host_tso4(host_tso)
-            .with_host_tso6(host_tso);
-
-        let features_bank1 = NetworkFeaturesBank1::new().with_host_uso(host_uso);
+            .with_host_tso6(host_tso)
+            .with_ctrl_vq(multiqueue)
+            .with_mq(multiqueue);
+
+        let features_bank1 = NetworkFeaturesBank1::new()
+            .with_host_uso(host_uso)
+            .with_rss(multiqueue)
+            .with_hash_report(multiqueue);
+
+        let device_features = VirtioDeviceFeatures::new()
+            .with_ring_event_idx(true)
+            .with_ring_indirect_desc(true)
+            .with_ring_packed(true)
+            // We guarantee in-order descriptor completion per queue. We
+            // don't yet take advantage of the ability to do batched
+            // completions, but we may in the future.
+            .with_in_order(true);
+
+        // The kernel processes the rings, so only offer the ring features it
+        // implements. The TAP device delivers coalesced receive packets, and
+        // uses mergeable receive buffers, as the guest allows.
+        #[cfg(target_os = "linux")]
+        let (device_features, features_bank0) = match &self.vhost {
+            Some(vhost) => {
+                let kernel = vhost.features();
+                (
+                    device_features
+                        .with_ring_event_idx(kernel.ring_event_idx())
+                        .with_ring_indirect_desc(kernel.ring_indirect_desc())
+                        .with_ring_packed(kernel.ring_packed())
+                        .with_in_order(kernel.in_order()),
+                    features_bank0
+                        .with_guest_tso4(true)
+                        .with_guest_tso6(true)
+                        .with_mrg_rxbuf(NetworkFeaturesBank0::from(kernel.bank(0)).mrg_rxbuf()),
+                )
+            }
+            None => (device_features, features_bank0),
+        };
 
         DeviceTraits {
             device_id: virtio::spec::VirtioDeviceType::NET,
-            device_features: VirtioDeviceFeatures::new()
+            device_features: device_features
                 .with_bank(0, features_bank0.into_bits())
-                .with_bank(1, features_bank1.into_bits())
-                .with_ring_event_idx(true)
-                .with_ring_indirect_desc(true)
-                .with_ring_packed(true)
-                // We guarantee in-order descriptor completion per queue. We
-                // don't yet take advantage of the ability to do batched
-                // completions, but we may in the future.
-                .with_in_order(true),
-            max_queues: 2 * self.registers.max_virtqueue_pairs,
+                .with_bank(1, features_bank1.into_bits()),
+            max_queues: 2 * self.registers.max_virtqueue_pairs + multiqueue as u16,
             device_register_length: size_of::<NetConfig>() as u32,
             shared_memory: DeviceTraitsSharedMemory { id: 0, size: 0 },
         }
@@ -323,6 +404,19 @@ async fn start_queue(
         features: &VirtioDeviceFeatures,
         initial_state: Option<QueueState>,
     ) -> anyhow::Result<()> {
+        #[cfg(target_os = "linux")]
+        if let Some(vhost) = &mut self.vhost {
+            return vhost
+                .start_queue(
+                    &self.adapter.driver,
+                    idx,
+                    resources,
+                    features,
+                    initial_state,
+                )
+                .await;
+        }
+
         let guest_memory = resources.guest_memory.clone();
         let queue_size = resources.params.size;
         let queue_event = PolledWait::new(&self.adapter.driver, resources.event)
@@ -339,8 +433,37 @@ async fn start_queue(
 
         let negotiated_features = NetworkFeaturesBank0::from(features.bank(0));
         let negotiated_features_bank1 = NetworkFeaturesBank1::from(features.bank(1));
+
+        // Per the spec, the control queue follows all the queue pairs if
+        // multiqueue or RSS is negotiated, and the first pair otherwise.
+        if negotiated_features.ctrl_vq() {
+            let control_idx = if negotiated_features.mq() || negotiated_features_bank1.rss() {
+                2 * self.registers.max_virtqueue_pairs
+            } else {
+                2
+            };
+            if idx == control_idx {
+                if self.control_queue_index.is_some() {
+                    anyhow::bail!("control queue already active");
+                }
+                let coordinator = self.stop_coordinator().await;
+                coordinator.control = Some(ControlQueue {
+                    queue,
+                    mem: guest_memory,
+                    negotiated_features,
+                    negotiated_features_bank1,
+                });
+                self.coordinator.start();
+                self.control_queue_index = Some(idx);
+                return Ok(());
+            }
+        }
+
         let pair_idx = (idx / 2) as usize;
         let is_rx = idx.is_multiple_of(2);
+        if pair_idx >= self.pairs.len() {
+            anyhow::bail!("invalid queue index {idx}");
+        }
 
         match &self.pairs[pair_idx] {
             QueuePairState::Empty => {
@@ -363,11 +486,6 @@ async fn start_queue(
                 }
 
                 // Second queue — extract the first, form the pair.
-                let first_pair = !self
-                    .pairs
-                    .iter()
-                    .any(|p| matches!(p, QueuePairState::Active));
-
                 let prev = std::mem::replace(&mut self.pairs[pair_idx], QueuePairState::Active);
                 let QueuePairState::HalfOpen {
                     queue: pending_queue,
@@ -384,9 +502,7 @@ async fn start_queue(
                     (queue, queue_size, pending_queue, pending_queue_size)
                 };
 
-                if first_pair {
-                    self.insert_coordinator(self.pairs.len() as u16);
-                }
+                self.stop_coordinator().await;
 
                 let virtio_state = VirtioState {
                     rx_queue,
@@ -404,9 +520,10 @@ async fn start_queue(
                     negotiated_features_bank1,
                 );
 
-                if first_pair {
-                    self.coordinator.start();
-                }
+                // Restart the coordinator to get an endpoint queue for the
+                // new pair.
+                self.coordinator.state_mut().unwrap().restart = true;
+                self.coordinator.start();
             }
             QueuePairState::Active => {
                 anyhow::bail!("queue pair {pair_idx} already active");
@@ -416,9 +533,19 @@ async fn start_queue(
     }
 
     async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
+        #[cfg(target_os = "linux")]
+        if let Some(vhost) = &mut self.vhost {
+            return vhost.stop_queue(idx);
+        }
+
         let pair_idx = (idx / 2) as usize;
 
-        if pair_idx < self.pairs.len() {
+        if self.control_queue_index == Some(idx) {
+            self.coordinator.stop().await;
+            self.coordinator.state_mut().unwrap().control = None;
+            self.control_queue_index = None;
+            self.resume_coordinator();
+        } else if pair_idx < self.pairs.len() {
             if let QueuePairState::HalfOpen { is_rx, .. } = self.pairs[pair_idx] {
                 let stopping_rx = idx.is_multiple_of(2);
                 if is_rx != stopping_rx {
@@ -429,15 +556,14 @@ async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
                 // Drop the pending half-open queue.
                 self.pairs[pair_idx] = QueuePairState::Empty;
             } else if matches!(self.pairs[pair_idx], QueuePairState::Active) {
-                // Stop the coordinator (which stops all workers).
+                // Stop the coordinator and all workers, then drop this pair's
+                // worker.
                 self.coordinator.stop().await;
-                if let Some(coordinator) = self.coordinator.state_mut() {
-                    for worker in &mut coordinator.workers {
-                        worker.stop().await;
-                    }
-                }
-                let _ = self.coordinator.remove();
+                let coordinator = self.coordinator.state_mut().unwrap();
+                coordinator.stop_workers().await;
+                coordinator.workers[pair_idx].remove();
                 self.pairs[pair_idx] = QueuePairState::Empty;
+                self.resume_coordinator();
             }
         }
 
@@ -446,7 +572,19 @@ async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
     }
 
     async fn reset(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Some(vhost) = &mut self.vhost {
+            vhost.reset();
+        }
+
         self.pairs.fill_with(|| QueuePairState::Empty);
+        self.control_queue_index = None;
+        // Drop any remaining queues, along with the multiqueue configuration.
+        self.coordinator.stop().await;
+        if let Some(coordinator) = self.coordinator.state_mut() {
+            coordinator.stop_workers().await;
+            self.coordinator.remove();
+        }
     }
 
     fn supports_save_restore(&self) -> bool {
@@ -515,10 +653,10 @@ struct ActiveState {
 }
 
 impl ActiveState {
-    fn new(mem: GuestMemory, rx_queue_size: u16, tx_queue_size: u16) -> Self {
+    fn new(mem: GuestMemory, rx_queue_size: u16, tx_queue_size: u16, header_size: usize) -> Self {
         Self {
             pending_tx_packets: (0..tx_queue_size).map(|_| None).collect(),
-            pending_rx_packets: VirtioWorkPool::new(mem, rx_queue_size),
+            pending_rx_packets: VirtioWorkPool::new(mem, rx_queue_size, header_size),
             data: ProcessingData::new(rx_queue_size, tx_queue_size),
             stats: Default::default(),
         }
@@ -531,12 +669,25 @@ struct PendingTxPacket {
 }
 
 pub struct NicBuilder {
-    max_queue_pairs: u16,
+    max_queue_pairs: Option<u16>,
+    vp_count: u32,
 }
 
 impl NicBuilder {
+    /// Limits the number of queue pairs. By default, as many are offered as
+    /// the endpoint supports.
+    ///
+    /// An endpoint's kernel datapath supports a single queue pair, so
+    /// [`build`](Self::build) fails if more are requested for one.
     pub fn max_queues(mut self, max_queue_pairs: u16) -> Self {
-        self.max_queue_pairs = max_queue_pairs;
+        self.max_queue_pairs = Some(max_queue_pairs);
+        self
+    }
+
+    /// Sets the number of VPs in the VM, across which the queue pair workers
+    /// are spread. Defaults to 1.
+    pub fn vp_count(mut self, vp_count: u32) -> Self {
+        self.vp_count = vp_count.max(1);
         self
     }
 
@@ -563,16 +714,50 @@ pub fn build(
             );
         }
 
-        // TODO: Implement VIRTIO_NET_F_MQ and VIRTIO_NET_F_RSS logic based on mulitqueue support.
-        // let multiqueue = endpoint.multiqueue_support();
-        // let max_queue_pairs = self.max_queue_pairs.clamp(1, multiqueue.max_queues.min(VIRTIO_NET_MAX_QUEUES));
-        let max_queue_pairs = 1;
+        #[cfg(target_os = "linux")]
+        let mut endpoint = endpoint;
+        #[cfg(target_os = "linux")]
+        let vhost = endpoint.take_vhost_net().map(vhost::VhostDatapath::new);
+        #[cfg(target_os = "linux")]
+        let kernel_datapath = vhost.is_some();
+        #[cfg(not(target_os = "linux"))]
+        let kernel_datapath = false;
+
+        let multiqueue = endpoint.multiqueue_support();
+        // Leave room for the control queue in the 16-bit queue count. The
+        // kernel datapath handles a single queue pair.
+        let max_queue_pairs = if kernel_datapath {
+            if let Some(n @ 2..) = self.max_queue_pairs {
+                anyhow::bail!("vhost-net supports a single queue pair, but {n} were requested");
+            }
+            1
+        } else {
+            self.max_queue_pairs
+                .unwrap_or(!0)
+                .clamp(1, multiqueue.max_queues.clamp(1, VIRTIO_NET_MAX_QUEUES - 1))
+        };
+        let indirection_table_size = if multiqueue.indirection_table_size != 0 {
+            multiqueue.indirection_table_size
+        } else {
+            DEFAULT_INDIRECTION_TABLE_SIZE
+        };
+        let (rss_max_key_size, rss_max_indirection_table_length, supported_hash_types) =
+            if max_queue_pairs > 1 {
+                (
+                    hash::MAX_KEY_SIZE,
+                    indirection_table_size,
+                    u32::from(HashTypes::supported()),
+                )
+            } else {
+                (0, 0, 0)
+            };
 
         let driver = driver_source.simple();
         let tx_offload_support = endpoint.tx_offload_support();
         let adapter = Arc::new(Adapter {
             driver,
             max_queue_pairs,
+            indirection_table_size,
             tx_fast_completions: endpoint.tx_fast_completions(),
             mac_address,
             tx_offload_support,
@@ -590,9 +775,9 @@ pub fn build(
             mtu: DEFAULT_MTU,
             speed: 0xffffffff,
             duplex: 0xff,
-            rss_max_key_size: 0,
-            rss_max_indirection_table_length: 0,
-            supported_hash_types: 0,
+            rss_max_key_size,
+            rss_max_indirection_table_length,
+            supported_hash_types,
         };
 
         Ok(Device {
@@ -600,9 +785,13 @@ pub fn build(
             coordinator,
             adapter,
             driver_source: driver_source.clone(),
+            vp_count: self.vp_count,
             pairs: (0..max_queue_pairs)
                 .map(|_| QueuePairState::Empty)
                 .collect(),
+            control_queue_index: None,
+            #[cfg(target_os = "linux")]
+            vhost,
         })
     }
 }
@@ -610,30 +799,65 @@ pub fn build(
 impl Device {
     pub fn builder() -> NicBuilder {
         NicBuilder {
-            max_queue_pairs: !0,
+            max_queue_pairs: None,
+            vp_count: 1,
         }
     }
 }
 
 impl InspectMut for Device {
     fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        #[cfg(target_os = "linux")]
+        if let Some(vhost) = &self.vhost {
+            req.respond().field("vhost", vhost);
+            return;
+        }
         self.coordinator.inspect_mut(req);
     }
 }
 
 impl Device {
-    fn insert_coordinator(&mut self, num_queues: u16) {
-        self.coordinator.insert(
-            &self.adapter.driver,
-            "virtio-net-coordinator".to_string(),
-            Coordinator {
-                workers: (0..self.adapter.max_queue_pairs)
-                    .map(|_| TaskControl::new(NetQueue { state: None }))
-                    .collect(),
-                num_queues,
-                restart: true,
-            },
-        );
+    /// Stops the coordinator so that its state can be modified, inserting it
+    /// first if this is the first queue to start.
+    async fn stop_coordinator(&mut self) -> &mut Coordinator {
+        if self.coordinator.has_state() {
+            self.coordinator.stop().await;
+        } else {
+            self.coordinator.insert(
+                &self.adapter.driver,
+                "virtio-net-coordinator".to_string(),
+                Coordinator {
+                    workers: (0..self.adapter.max_queue_pairs)
+                        .map(|_| TaskControl::new(NetQueue { state: None }))
+                        .collect(),
+                    // Only the first queue pair is used until the guest
+                    // enables more.
+                    num_queues: 1,
+                    restart: true,
+                    control: None,
+                    rss: None,
+                    hash: None,
+                },
+            );
+        }
+        self.coordinator.state_mut().unwrap()
+    }
+
+    /// Restarts the stopped coordinator after a queue has been stopped, or
+    /// removes it if no queues remain.
+    fn resume_coordinator(&mut self) {
+        let active = self.control_queue_index.is_some()
+            || self
+                .pairs
+                .iter()
+                .any(|p| matches!(p, QueuePairState::Active));
+
+        if active {
+            self.coordinator.state_mut().unwrap().restart = true;
+            self.coordinator.start();
+        } else {
+            self.coordinator.remove();
+        }
     }
 
     /// Allocates and inserts a worker.
@@ -648,8 +872,10 @@ fn insert_worker(
         negotiated_features_bank1: NetworkFeaturesBank1,
     ) {
         let mut builder = self.driver_source.builder();
-        // TODO: set this correctly
-        builder.target_vp(0);
+        // Guest drivers typically spread the queue pairs across VPs in order,
+        // so pair N's interrupts and transmits mostly involve VP N. Wrap
+        // around if there are more pairs than VPs.
+        builder.target_vp(idx as u32 % self.vp_count);
         // If tx completions arrive quickly, then just do tx processing
         // on whatever processor the guest happens to signal from.
         // Subsequent transmits will be pulled from the completion
@@ -657,16 +883,19 @@ fn insert_worker(
         builder.run_on_target(!self.adapter.tx_fast_completions);
         let driver = builder.build("virtio-net");
 
+        let header_size = negotiated_header_size(negotiated_features_bank1);
         let active_state = ActiveState::new(
             guest_memory.clone(),
             virtio_state.rx_queue_size,
             virtio_state.tx_queue_size,
+            header_size,
         );
         let worker = Worker {
             virtio_state,
             active_state,
             negotiated_features,
             negotiated_features_bank1,
+            header_size,
         };
         let coordinator = self.coordinator.state_mut().unwrap();
         let worker_task = &mut coordinator.workers[idx];
@@ -677,8 +906,35 @@ fn insert_worker(
 
 struct Coordinator {
     workers: Vec<TaskControl<NetQueue, Worker>>,
+    /// The number of queue pairs enabled by the guest.
     num_queues: u16,
     restart: bool,
+    control: Option<ControlQueue>,
+    rss: Option<RssState>,
+    hash: Option<HashConfig>,
+}
+
+/// The control queue, processed by the coordinator.
+#[derive(Inspect)]
+struct ControlQueue {
+    queue: VirtioQueue,
+    #[inspect(skip)]
+    mem: GuestMemory,
+    #[inspect(skip)]
+    negotiated_features: NetworkFeaturesBank0,
+    #[inspect(skip)]
+    negotiated_features_bank1: NetworkFeaturesBank1,
+}
+
+/// The RSS configuration passed to the endpoint.
+#[derive(Inspect)]
+struct RssState {
+    #[inspect(with = "|x| inspect::AsBytes(x)")]
+    key: Vec<u8>,
+    #[inspect(iter_by_index)]
+    indirection_table: Vec<u16>,
+    #[inspect(hex, with = "|x| u32::from(*x)")]
+    hash_types: HashTypes,
 }
 
 struct CoordinatorState {
@@ -702,12 +958,15 @@ fn inspect_mut(&mut self, req: inspect::Request<'_>, coordinator: Option<&mut Co
             .field_mut("endpoint", self.endpoint.as_mut());
 
         if let Some(coordinator) = coordinator {
-            resp.fields_mut(
-                "queues",
-                coordinator.workers[..coordinator.num_queues as usize]
-                    .iter_mut()
-                    .enumerate(),
-            );
+            resp.field("rss", &coordinator.rss)
+                .field("hash", &coordinator.hash)
+                .field("control", &coordinator.control)
+                .fields_mut(
+                    "queues",
+                    coordinator.workers[..coordinator.num_queues as usize]
+                        .iter_mut()
+                        .enumerate(),
+                );
         }
     }
 }
@@ -742,16 +1001,151 @@ async fn process(
                 self.restart = false;
             }
             self.start_workers();
-            match stop
-                .until_stopped(state.endpoint.wait_for_endpoint_action())
-                .await?
-            {
-                EndpointAction::RestartRequired => self.restart = true,
-                EndpointAction::LinkStatusNotify(_) => {
+
+            enum Event {
+                Endpoint(EndpointAction),
+                Control(Result<VirtioQueueCallbackWork, std::io::Error>),
+            }
+
+            let control = &mut self.control;
+            let event = stop
+                .until_stopped(async {
+                    let endpoint =
+                        async { Event::Endpoint(state.endpoint.wait_for_endpoint_action().await) };
+                    let control = async {
+                        match control {
+                            Some(control) => match control.queue.next().await {
+                                Some(work) => Event::Control(work),
+                                None => pending().await,
+                            },
+                            None => pending().await,
+                        }
+                    };
+                    (endpoint, control).race().await
+                })
+                .await?;
+
+            match event {
+                Event::Endpoint(EndpointAction::RestartRequired) => self.restart = true,
+                Event::Endpoint(EndpointAction::LinkStatusNotify(_)) => {
                     tracing::error!("unexpected link status notification")
                 }
+                Event::Control(Ok(work)) => self.handle_control(&state.adapter, work),
+                Event::Control(Err(err)) => {
+                    tracing::error!(
+                        error = &err as &dyn std::error::Error,
+                        "control queue failure"
+                    );
+                    self.control = None;
+                }
+            }
+        }
+    }
+
+    /// Handles a control queue command, acknowledging it to the guest.
+    fn handle_control(&mut self, adapter: &Adapter, work: VirtioQueueCallbackWork) {
+        let control = self.control.as_ref().unwrap();
+        let features = (
+            control.negotiated_features,
+            control.negotiated_features_bank1,
+        );
+        let ack = match read_control_command(&control.mem, &work)
+            .and_then(|command| self.control_command(adapter, features, &command))
+        {
+            Ok(()) => VIRTIO_NET_OK,
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "virtio-net control command failed"
+                );
+                VIRTIO_NET_ERR
+            }
+        };
+
+        let control = self.control.as_mut().unwrap();
+        let len = match work.write(&control.mem, &[ack]) {
+            Ok(()) => 1,
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = &err as &dyn std::error::Error,
+                    "failed to write control command ack"
+                );
+                0
+            }
+        };
+        control.queue.complete(work, len);
+    }
+
+    fn control_command(
+        &mut self,
+        adapter: &Adapter,
+        (features, features_bank1): (NetworkFeaturesBank0, NetworkFeaturesBank1),
+        command: &[u8],
+    ) -> Result<(), ControlError> {
+        let (header, data) =
+            CtrlHeader::read_from_prefix(command).map_err(|_| ControlError::Truncated)?;
+        match (header.class, header.command) {
+            (VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_VQ_PAIRS_SET) if features.mq() => {
+                let (pairs, _) =
+                    u16::read_from_prefix(data).map_err(|_| ControlError::Truncated)?;
+                if pairs == 0 || pairs as usize > self.workers.len() {
+                    return Err(ControlError::InvalidQueuePairs(pairs));
+                }
+                self.num_queues = pairs;
+            }
+            (VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_RSS_CONFIG) if features_bank1.rss() => {
+                let config = RssConfigCommand::parse(data).ok_or(ControlError::Truncated)?;
+                let hash = validate_hash_config(config.hash_types, &config.key)?;
+                let max_pairs = self.workers.len() as u16;
+                let table_len = config.indirection_table.len();
+                if !table_len.is_power_of_two()
+                    || table_len > adapter.indirection_table_size as usize
+                {
+                    return Err(ControlError::InvalidIndirectionTable);
+                }
+                // Enable enough pairs to transmit on `max_tx_vq` queues and to
+                // receive on every queue that RSS can select.
+                let max_rx_queue = config
+                    .indirection_table
+                    .iter()
+                    .chain([&config.unclassified_queue])
+                    .max()
+                    .copied()
+                    .unwrap();
+                if max_rx_queue >= max_pairs {
+                    return Err(ControlError::InvalidIndirectionTable);
+                }
+                let rx_pairs = max_rx_queue + 1;
+                if config.max_tx_vq == 0 || config.max_tx_vq > max_pairs {
+                    return Err(ControlError::InvalidQueuePairs(config.max_tx_vq));
+                }
+                self.num_queues = config.max_tx_vq.max(rx_pairs);
+                // With no hash types enabled, every packet is unclassified.
+                let indirection_table = if hash.is_some() {
+                    config.indirection_table
+                } else {
+                    vec![config.unclassified_queue]
+                };
+                self.rss = Some(RssState {
+                    key: config.key,
+                    indirection_table,
+                    hash_types: hash.as_ref().map_or(HashTypes::new(), |hash| hash.types),
+                });
+                if features_bank1.hash_report() {
+                    self.hash = hash;
+                }
             }
+            (VIRTIO_NET_CTRL_MQ, VIRTIO_NET_CTRL_MQ_HASH_CONFIG)
+                if features_bank1.hash_report() =>
+            {
+                let config = RssConfigCommand::parse(data).ok_or(ControlError::Truncated)?;
+                self.hash = validate_hash_config(config.hash_types, &config.key)?;
+            }
+            (class, command) => return Err(ControlError::Unsupported { class, command }),
         }
+        // Restart the queues to apply the new configuration.
+        self.restart = true;
+        Ok(())
     }
 
     async fn stop_workers(&mut self) {
@@ -766,23 +1160,52 @@ async fn restart_queues(&mut self, c_state: &mut CoordinatorState) -> Result<(),
             worker.task_mut().state = None;
         }
 
-        let queue_config = (0..self.workers.len())
+        // Get endpoint queues for the pairs enabled by the guest, stopping at
+        // the first pair whose queues have not been started.
+        let active = self.workers[..self.num_queues as usize]
+            .iter()
+            .take_while(|worker| worker.has_state())
+            .count();
+        if active == 0 {
+            return Ok(());
+        }
+
+        let queue_config = (0..active)
             .map(|_| QueueConfig {
                 driver: Box::new(c_state.adapter.driver.clone()),
             })
             .collect::<Vec<_>>();
 
+        // Steer packets only to queues that exist.
+        let indirection_table = self.rss.as_ref().map(|rss| {
+            rss.indirection_table
+                .iter()
+                .map(|&queue| queue % active as u16)
+                .collect::<Vec<_>>()
+        });
+        let rss =
+            self.rss
+                .as_ref()
+                .zip(indirection_table.as_deref())
+                .map(|(rss, indirection_table)| RssConfig {
+                    key: &rss.key,
+                    indirection_table,
+                    flags: rss_flags(rss.hash_types),
+                });
+
         let mut queues = Vec::new();
         c_state
             .endpoint
-            .get_queues(queue_config, None, &mut queues)
+            .get_queues(queue_config, rss.as_ref(), &mut queues)
             .await
             .map_err(WorkerError::Endpoint)?;
 
-        assert_eq!(queues.len(), self.workers.len());
+        assert_eq!(queues.len(), active);
 
         for (worker, mut queue) in self.workers.iter_mut().zip(queues) {
-            let state = &mut worker.state_mut().unwrap().active_state;
+            let worker_state = worker.state_mut().unwrap();
+            let state = &mut worker_state.active_state;
+            state.pending_rx_packets.set_hash_config(self.hash.clone());
             let n = state
                 .pending_rx_packets
                 .fill_ready(&mut state.data.rx_ready);
@@ -843,6 +1266,68 @@ enum WorkerError {
     Cancelled(task_control::Cancelled),
 }
 
+#[derive(Debug, Error)]
+enum ControlError {
+    #[error("failed to read control command")]
+    Read(#[source] GuestMemoryError),
+    #[error("control command too large")]
+    TooLarge,
+    #[error("truncated control command")]
+    Truncated,
+    #[error("unsupported control command {class}:{command}")]
+    Unsupported { class: u8, command: u8 },
+    #[error("invalid queue pair count {0}")]
+    InvalidQueuePairs(u16),
+    #[error("invalid rss indirection table")]
+    InvalidIndirectionTable,
+    #[error("unsupported hash types {0:#x}")]
+    UnsupportedHashTypes(u32),
+    #[error("hash key too long")]
+    KeyTooLong,
+}
+
+/// Reads the readable part of a control command.
+fn read_control_command(
+    mem: &GuestMemory,
+    work: &VirtioQueueCallbackWork,
+) -> Result<Vec<u8>, ControlError> {
+    let len = work.get_payload_length(false) as usize;
+    if len > MAX_COMMAND_SIZE {
+        return Err(ControlError::TooLarge);
+    }
+    let mut command = vec![0; len];
+    work.read(mem, &mut command).map_err(ControlError::Read)?;
+    Ok(command)
+}
+
+/// Maps the hash types negotiated by the guest onto the backend's RSS flags.
+/// [`validate_hash_config`] has already rejected the IPv6 extension header
+/// variants, which the backend does not support.
+fn rss_flags(hash_types: HashTypes) -> RssFlags {
+    RssFlags::new()
+        .with_ipv4(hash_types.ipv4())
+        .with_tcp_ipv4(hash_types.tcpv4())
+        .with_udp_ipv4(hash_types.udpv4())
+        .with_ipv6(hash_types.ipv6())
+        .with_tcp_ipv6(hash_types.tcpv6())
+        .with_udp_ipv6(hash_types.udpv6())
+}
+
+/// Validates the hash types and key of an RSS or hash configuration command,
+/// returning the hash configuration if any hash types are enabled.
+fn validate_hash_config(hash_types: u32, key: &[u8]) -> Result<Option<HashConfig>, ControlError> {
+    if hash_types & !u32::from(HashTypes::supported()) != 0 {
+        return Err(ControlError::UnsupportedHashTypes(hash_types));
+    }
+    if key.len() > hash::MAX_KEY_SIZE as usize {
+        return Err(ControlError::KeyTooLong);
+    }
+    Ok((hash_types != 0).then(|| HashConfig {
+        key: key.to_vec(),
+        types: hash_types.into(),
+    }))
+}
+
 #[derive(Debug, Error)]
 enum TxPacketError {
     #[error("failed to read virtio-net header")]
@@ -869,6 +1354,7 @@ struct Worker {
     negotiated_features: NetworkFeaturesBank0,
     #[inspect(skip)]
     negotiated_features_bank1: NetworkFeaturesBank1,
+    header_size: usize,
 }
 
 impl Worker {
@@ -1012,9 +1498,10 @@ fn try_queue_tx_packet(
             return Err(TxPacketError::DuplicateIndex(idx));
         }
 
+        let header_size = self.header_size;
         let total_readable = work.get_payload_length(false) as usize;
         let packet_len: u32 = total_readable
-            .checked_sub(header_size())
+            .checked_sub(header_size)
             .and_then(|len| u32::try_from(len).ok())
             .ok_or(TxPacketError::Empty)?;
 
@@ -1025,22 +1512,22 @@ fn try_queue_tx_packet(
         let bytes_read = work
             .read(
                 self.active_state.pending_rx_packets.mem(),
-                &mut peek_buf[..header_size() + ETH_PEEK],
+                &mut peek_buf[..header_size + ETH_PEEK],
             )
             .map_err(TxPacketError::ReadHeader)?;
 
         let header = VirtioNetHeader::read_from_prefix(&peek_buf)
             .map(|(h, _)| h)
             .ok();
-        let packet_prefix = if bytes_read > header_size() {
-            &peek_buf[header_size()..bytes_read]
+        let packet_prefix = if bytes_read > header_size {
+            &peek_buf[header_size..bytes_read]
         } else {
             &[]
         };
 
         let segments = &mut self.active_state.data.tx_segments;
         let seg_start = segments.len();
-        let mut header_bytes_remaining = header_size() as u32;
+        let mut header_bytes_remaining = header_size as u32;
         for p in &work.payload {
             if p.writeable {
                 continue;
@@ -33,7 +33,7 @@ async fn resolve(
         resource: VirtioNetHandle,
         input: VirtioResolveInput<'_>,
     ) -> Result<Self::Output, Self::Error> {
-        let mut builder = Device::builder();
+        let mut builder = Device::builder().vp_count(input.vp_count);
         if let Some(max_queues) = resource.max_queues {
             builder = builder.max_queues(max_queues);
         }
@@ -9,6 +9,7 @@
 use net_backend::MultiQueueSupport;
 use net_backend::QueueConfig;
 use net_backend::RssConfig;
+use net_backend::RssFlags;
 use net_backend::RxChecksumState;
 use net_backend::RxId;
 use net_backend::RxMetadata;
@@ -54,6 +55,13 @@
 use crate::VirtioNetHeaderGso;
 use crate::VirtioNetHeaderGsoProtocol;
 use crate::Worker;
+use crate::control::VIRTIO_NET_CTRL_MQ;
+use crate::control::VIRTIO_NET_ERR;
+use crate::control::VIRTIO_NET_OK;
+use crate::hash::HashConfig;
+use crate::hash::HashReport;
+use crate::hash::HashTypes;
+use crate::hash::toeplitz;
 use crate::header_size;
 
 // --- Constants ---
@@ -2030,3 +2038,528 @@ async fn wait_for_endpoint_action(&mut self) -> EndpointAction {
         pending().await
     }
 }
+
+// --- Multiqueue Tests ---
+
+/// The endpoint queue count and RSS configuration of a `get_queues` call.
+struct GetQueuesCall {
+    handles: Vec<MockQueueHandle>,
+    rss: Option<(Vec<u8>, Vec<u16>, RssFlags)>,
+}
+
+/// Mock endpoint that supports multiple queues and reports each `get_queues`
+/// call.
+struct MockMultiQueueEndpoint {
+    max_queues: u16,
+    calls: mesh::Sender<GetQueuesCall>,
+}
+
+impl InspectMut for MockMultiQueueEndpoint {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        req.ignore();
+    }
+}
+
+#[async_trait]
+impl Endpoint for MockMultiQueueEndpoint {
+    fn endpoint_type(&self) -> &'static str {
+        "mock-multiqueue"
+    }
+
+    async fn get_queues(
+        &mut self,
+        config: Vec<QueueConfig>,
+        rss: Option<&RssConfig<'_>>,
+        queues: &mut Vec<Box<dyn net_backend::Queue>>,
+    ) -> anyhow::Result<()> {
+        let mut handles = Vec::new();
+        for _ in &config {
+            let (queue, handle) = new_mock_queue();
+            queues.push(Box::new(queue));
+            handles.push(handle);
+        }
+        self.calls.send(GetQueuesCall {
+            handles,
+            rss: rss.map(|rss| (rss.key.to_vec(), rss.indirection_table.to_vec(), rss.flags)),
+        });
+        Ok(())
+    }
+
+    async fn stop(&mut self) {}
+
+    fn is_ordered(&self) -> bool {
+        true
+    }
+
+    fn tx_offload_support(&self) -> TxOffloadSupport {
+        TxOffloadSupport::default()
+    }
+
+    fn multiqueue_support(&self) -> MultiQueueSupport {
+        MultiQueueSupport {
+            max_queues: self.max_queues,
+            indirection_table_size: 0,
+        }
+    }
+
+    fn tx_fast_completions(&self) -> bool {
+        true
+    }
+
+    async fn wait_for_endpoint_action(&mut self) -> EndpointAction {
+        pending().await
+    }
+}
+
+const MQ_QUEUE_STRIDE: u64 = 0x3000;
+const MQ_DATA_BASE: u64 = 0x20000;
+const MQ_MEM_SIZE: usize = 0x30000;
+
+/// The RSS key from the Microsoft RSS verification suite.
+const RSS_KEY: [u8; 40] = [
+    0x6d, 0x5a, 0x56, 0xda, 0x25, 0x5b, 0x0e, 0xc2, 0x41, 0x67, 0x25, 0x3d, 0x43, 0xa3, 0x8f, 0xb0,
+    0xd0, 0xca, 0x2b, 0xcb, 0xae, 0x7b, 0x30, 0xb4, 0x77, 0xcb, 0x2d, 0xa3, 0x80, 0x30, 0xf2, 0x0c,
+    0x6a, 0x42, 0xb7, 0x3b, 0xbe, 0xac, 0x01, 0xfa,
+];
+
+/// A queue started by [`MqHarness`], with its rings at
+/// `idx * MQ_QUEUE_STRIDE`.
+struct MqQueue {
+    event: Event,
+    interrupt_event: Event,
+    avail_idx: u16,
+    used_idx: u16,
+}
+
+/// Harness for devices with multiple queue pairs and a control queue.
+struct MqHarness {
+    device: Device,
+    mem: GuestMemory,
+    driver: DefaultDriver,
+    features: VirtioDeviceFeatures,
+    queues: Vec<Option<MqQueue>>,
+    calls: mesh::Receiver<GetQueuesCall>,
+    next_data_offset: u64,
+}
+
+impl MqHarness {
+    fn new(driver: &DefaultDriver, max_queues: u16, features: VirtioDeviceFeatures) -> Self {
+        let (calls_tx, calls) = mesh::channel();
+        let endpoint = MockMultiQueueEndpoint {
+            max_queues,
+            calls: calls_tx,
+        };
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+        let mac = MacAddress::new([0x00, 0x15, 0x5d, 0xaa, 0xbb, 0xcc]);
+        let device = Device::builder()
+            .build(&driver_source, Box::new(endpoint), mac)
+            .unwrap();
+        let queue_count = device.traits().max_queues;
+        Self {
+            device,
+            mem: GuestMemory::allocate(MQ_MEM_SIZE),
+            driver: driver.clone(),
+            features,
+            queues: (0..queue_count).map(|_| None).collect(),
+            calls,
+            next_data_offset: MQ_DATA_BASE,
+        }
+    }
+
+    fn queue_base(idx: u16) -> u64 {
+        idx as u64 * MQ_QUEUE_STRIDE
+    }
+
+    async fn start_queue(&mut self, idx: u16) {
+        let base = Self::queue_base(idx);
+        init_avail_ring(&self.mem, base + 0x1000);
+        init_used_ring(&self.mem, base + 0x2000);
+        let queue = MqQueue {
+            event: Event::new(),
+            interrupt_event: Event::new(),
+            avail_idx: 0,
+            used_idx: 0,
+        };
+        self.device
+            .start_queue(
+                idx,
+                QueueResources {
+                    params: QueueParams {
+                        size: QUEUE_SIZE,
+                        enable: true,
+                        desc_addr: base,
+                        avail_addr: base + 0x1000,
+                        used_addr: base + 0x2000,
+                    },
+                    notify: Interrupt::from_event(queue.interrupt_event.clone()),
+                    event: queue.event.clone(),
+                    guest_memory: self.mem.clone(),
+                },
+                &self.features,
+                None,
+            )
+            .await
+            .unwrap();
+        self.queues[idx as usize] = Some(queue);
+    }
+
+    fn alloc_data(&mut self, size: u32) -> u64 {
+        let gpa = self.next_data_offset;
+        self.next_data_offset += size as u64;
+        assert!(
+            self.next_data_offset <= MQ_MEM_SIZE as u64,
+            "ran out of test memory"
+        );
+        gpa
+    }
+
+    /// Posts a descriptor chain of `(gpa, len, writeable)` buffers on queue
+    /// `idx` and signals the queue.
+    fn post(&mut self, idx: u16, desc_index: u16, buffers: &[(u64, u32, bool)]) {
+        let base = Self::queue_base(idx);
+        for (i, &(gpa, len, writeable)) in buffers.iter().enumerate() {
+            let is_last = i == buffers.len() - 1;
+            write_descriptor(
+                &self.mem,
+                base,
+                desc_index + i as u16,
+                gpa,
+                len,
+                DescriptorFlags::new()
+                    .with_next(!is_last)
+                    .with_write(writeable),
+                if is_last {
+                    0
+                } else {
+                    desc_index + i as u16 + 1
+                },
+            );
+        }
+        let queue = self.queues[idx as usize].as_mut().unwrap();
+        make_available(
+            &self.mem,
+            base + 0x1000,
+            QUEUE_SIZE,
+            desc_index,
+            &mut queue.avail_idx,
+        );
+        queue.event.signal();
+    }
+
+    async fn wait_for_used(&mut self, idx: u16) -> (u16, u32) {
+        let queue = self.queues[idx as usize].as_mut().unwrap();
+        wait_for_used(
+            &self.driver,
+            &queue.interrupt_event,
+            &self.mem,
+            Self::queue_base(idx) + 0x2000,
+            QUEUE_SIZE,
+            &mut queue.used_idx,
+        )
+        .await
+    }
+
+    /// Sends a control command on control queue `idx`, returning the ack.
+    async fn control(&mut self, idx: u16, class: u8, command: u8, data: &[u8]) -> u8 {
+        let mut buf = vec![class, command];
+        buf.extend_from_slice(data);
+        let command_gpa = self.alloc_data(buf.len() as u32);
+        self.mem.write_at(command_gpa, &buf).unwrap();
+        let ack_gpa = self.alloc_data(1);
+        self.mem.write_at(ack_gpa, &[0xff]).unwrap();
+
+        let desc_index = self.queues[idx as usize].as_ref().unwrap().avail_idx * 2 % QUEUE_SIZE;
+        self.post(
+            idx,
+            desc_index,
+            &[(command_gpa, buf.len() as u32, false), (ack_gpa, 1, true)],
+        );
+        let (used_id, used_len) = self.wait_for_used(idx).await;
+        assert_eq!(used_id, desc_index);
+        assert_eq!(used_len, 1);
+        let mut ack = [0];
+        self.mem.read_at(ack_gpa, &mut ack).unwrap();
+        ack[0]
+    }
+
+    /// Waits for a `get_queues` call matching `pred`, skipping any others.
+    async fn wait_for_get_queues(
+        &mut self,
+        pred: impl Fn(&GetQueuesCall) -> bool,
+    ) -> GetQueuesCall {
+        mesh::CancelContext::new()
+            .with_timeout(Duration::from_secs(5))
+            .until_cancelled(async {
+                loop {
+                    let call = self.calls.next().await.expect("channel closed");
+                    if pred(&call) {
+                        break call;
+                    }
+                }
+            })
+            .await
+            .expect("timed out waiting for get_queues")
+    }
+}
+
+fn mq_features(bank0: NetworkFeaturesBank0, bank1: NetworkFeaturesBank1) -> VirtioDeviceFeatures {
+    VirtioDeviceFeatures::new()
+        .with_bank(0, bank0.into_bits())
+        .with_bank(1, bank1.into_bits())
+}
+
+fn rss_config_command(
+    hash_types: u32,
+    indirection_table: &[u16],
+    max_tx_vq: u16,
+    key: &[u8],
+) -> Vec<u8> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&hash_types.to_le_bytes());
+    data.extend_from_slice(&(indirection_table.len() as u16 - 1).to_le_bytes());
+    data.extend_from_slice(&0u16.to_le_bytes());
+    for entry in indirection_table {
+        data.extend_from_slice(&entry.to_le_bytes());
+    }
+    data.extend_from_slice(&max_tx_vq.to_le_bytes());
+    data.push(key.len() as u8);
+    data.extend_from_slice(key);
+    data
+}
+
+/// A multiqueue endpoint exposes the control queue, MQ, RSS and hash
+/// reporting, along with the RSS limits in config space.
+#[async_test]
+async fn multiqueue_features_advertised(driver: DefaultDriver) {
+    let mut harness = MqHarness::new(&driver, 4, VirtioDeviceFeatures::new());
+    let traits = harness.device.traits();
+
+    let bank0 = NetworkFeaturesBank0::from(traits.device_features.bank(0));
+    let bank1 = NetworkFeaturesBank1::from(traits.device_features.bank(1));
+    assert!(bank0.ctrl_vq());
+    assert!(bank0.mq());
+    assert!(bank1.rss());
+    assert!(bank1.hash_report());
+    // Four queue pairs plus the control queue.
+    assert_eq!(traits.max_queues, 9);
+
+    assert_eq!(harness.device.read_registers_u32(8).await & 0xffff, 4);
+    let rss_limits = harness.device.read_registers_u32(16).await;
+    assert_eq!((rss_limits >> 8) & 0xff, 40);
+    assert_eq!(rss_limits >> 16, 128);
+    assert_eq!(harness.device.read_registers_u32(20).await, 0x3f);
+
+    // The configured queue count is limited by the endpoint.
+    let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+    let (calls, _calls) = mesh::channel();
+    let device = Device::builder()
+        .max_queues(2)
+        .build(
+            &driver_source,
+            Box::new(MockMultiQueueEndpoint {
+                max_queues: 4,
+                calls,
+            }),
+            MacAddress::new([0x00, 0x15, 0x5d, 0xaa, 0xbb, 0xcc]),
+        )
+        .unwrap();
+    assert_eq!(device.traits().max_queues, 5);
+}
+
+/// A single-queue endpoint gets no control queue or multiqueue features.
+#[async_test]
+async fn single_queue_has_no_control_queue(driver: DefaultDriver) {
+    let harness = TestHarness::new(&driver);
+    let traits = harness.device.traits();
+
+    let bank0 = NetworkFeaturesBank0::from(traits.device_features.bank(0));
+    let bank1 = NetworkFeaturesBank1::from(traits.device_features.bank(1));
+    assert!(!bank0.ctrl_vq());
+    assert!(!bank0.mq());
+    assert!(!bank1.rss());
+    assert!(!bank1.hash_report());
+    assert_eq!(traits.max_queues, 2);
+}
+
+/// Only the first queue pair is backed by an endpoint queue until the guest
+/// enables more with VQ_PAIRS_SET.
+#[async_test]
+async fn vq_pairs_set_enables_queue_pairs(driver: DefaultDriver) {
+    let features = mq_features(
+        NetworkFeaturesBank0::new().with_ctrl_vq(true).with_mq(true),
+        NetworkFeaturesBank1::new(),
+    );
+    let mut harness = MqHarness::new(&driver, 2, features);
+    for idx in 0..5 {
+        harness.start_queue(idx).await;
+    }
+    harness
+        .wait_for_get_queues(|call| call.handles.len() == 1)
+        .await;
+
+    // Out of range pair counts are rejected.
+    for pairs in [0u16, 3] {
+        let ack = harness
+            .control(4, VIRTIO_NET_CTRL_MQ, 0, &pairs.to_le_bytes())
+            .await;
+        assert_eq!(ack, VIRTIO_NET_ERR);
+    }
+
+    let ack = harness
+        .control(4, VIRTIO_NET_CTRL_MQ, 0, &2u16.to_le_bytes())
+        .await;
+    assert_eq!(ack, VIRTIO_NET_OK);
+    let call = harness
+        .wait_for_get_queues(|call| call.handles.len() == 2)
+        .await;
+    assert!(call.rss.is_none());
+
+    // Unsupported commands are rejected.
+    let ack = harness.control(4, 0, 0, &[1]).await;
+    assert_eq!(ack, VIRTIO_NET_ERR);
+}
+
+/// RSS_CONFIG passes the key and indirection table to the endpoint and
+/// enables the queue pairs it references.
+#[async_test]
+async fn rss_config_passed_to_endpoint(driver: DefaultDriver) {
+    let features = mq_features(
+        NetworkFeaturesBank0::new().with_ctrl_vq(true).with_mq(true),
+        NetworkFeaturesBank1::new().with_rss(true),
+    );
+    let mut harness = MqHarness::new(&driver, 2, features);
+    for idx in 0..5 {
+        harness.start_queue(idx).await;
+    }
+    harness
+        .wait_for_get_queues(|call| call.handles.len() == 1)
+        .await;
+
+    // The indirection table length must be a power of two.
+    let ack = harness
+        .control(
+            4,
+            VIRTIO_NET_CTRL_MQ,
+            1,
+            &rss_config_command(0x12, &[0, 1, 0], 2, &RSS_KEY),
+        )
+        .await;
+    assert_eq!(ack, VIRTIO_NET_ERR);
+
+    let table = [0, 1, 1, 0];
+    let ack = harness
+        .control(
+            4,
+            VIRTIO_NET_CTRL_MQ,
+            1,
+            &rss_config_command(0x12, &table, 1, &RSS_KEY),
+        )
+        .await;
+    assert_eq!(ack, VIRTIO_NET_OK);
+    let call = harness.wait_for_get_queues(|call| call.rss.is_some()).await;
+    assert_eq!(call.handles.len(), 2);
+    let (key, indirection_table, flags) = call.rss.unwrap();
+    assert_eq!(key, RSS_KEY);
+    assert_eq!(indirection_table, table);
+    // 0x12 is VIRTIO_NET_RSS_HASH_TYPE_TCPv4 | VIRTIO_NET_RSS_HASH_TYPE_TCPv6.
+    assert_eq!(
+        flags,
+        RssFlags::new().with_tcp_ipv4(true).with_tcp_ipv6(true)
+    );
+}
+
+/// With HASH_REPORT negotiated, received packets carry the Toeplitz hash of
+/// the hash types configured with HASH_CONFIG. Without MQ or RSS, the control
+/// queue follows the first queue pair.
+#[async_test]
+async fn hash_report_rx(driver: DefaultDriver) {
+    let features = mq_features(
+        NetworkFeaturesBank0::new().with_ctrl_vq(true),
+        NetworkFeaturesBank1::new().with_hash_report(true),
+    );
+    let mut harness = MqHarness::new(&driver, 2, features);
+    for idx in 0..3 {
+        harness.start_queue(idx).await;
+    }
+    harness
+        .wait_for_get_queues(|call| call.handles.len() == 1)
+        .await;
+
+    // Enable TCPv4 hashing. HASH_CONFIG's reserved fields line up with an RSS
+    // configuration with a single-entry indirection table, all zero.
+    let data = rss_config_command(0x2, &[0], 0, &RSS_KEY);
+    let ack = harness.control(2, VIRTIO_NET_CTRL_MQ, 2, &data).await;
+    assert_eq!(ack, VIRTIO_NET_OK);
+    let mut call = harness
+        .wait_for_get_queues(|call| call.handles.len() == 1)
+        .await;
+    let handle = &mut call.handles[0];
+
+    let header_len = size_of::<VirtioNetHeader>() as u32;
+    let buffer_gpa = harness.alloc_data(1500);
+    harness.post(0, 0, &[(buffer_gpa, 1500, true)]);
+    handle.wait_for_rx_pending().await;
+    let packet = ipv4_tcp_packet([66, 9, 149, 187], [161, 142, 100, 80], 2794, 1766);
+    handle.inject_rx_packet(&packet);
+
+    let (used_id, used_len) = harness.wait_for_used(0).await;
+    assert_eq!(used_id, 0);
+    assert_eq!(used_len, header_len + packet.len() as u32);
+    let mut header = [0; size_of::<VirtioNetHeader>()];
+    harness.mem.read_at(buffer_gpa, &mut header).unwrap();
+    let header = VirtioNetHeader::read_from_bytes(&header).unwrap();
+    assert_eq!(header.hash_value, 0x51ccc178);
+    assert_eq!(header.hash_report, HashReport::TCPV4.0);
+}
+
+fn ipv4_tcp_packet(src: [u8; 4], dst: [u8; 4], src_port: u16, dst_port: u16) -> Vec<u8> {
+    let mut packet = make_eth_header(0x0800).to_vec();
+    let mut ip = [0u8; 20];
+    ip[0] = 0x45;
+    ip[2..4].copy_from_slice(&40u16.to_be_bytes());
+    ip[8] = 64;
+    ip[9] = 6;
+    ip[12..16].copy_from_slice(&src);
+    ip[16..20].copy_from_slice(&dst);
+    packet.extend_from_slice(&ip);
+    let mut tcp = [0u8; 20];
+    tcp[0..2].copy_from_slice(&src_port.to_be_bytes());
+    tcp[2..4].copy_from_slice(&dst_port.to_be_bytes());
+    tcp[12] = 5 << 4;
+    packet.extend_from_slice(&tcp);
+    packet
+}
+
+/// Hashes match the Microsoft RSS verification suite.
+#[test]
+fn toeplitz_verification_vectors() {
+    let packet = ipv4_tcp_packet([66, 9, 149, 187], [161, 142, 100, 80], 2794, 1766);
+    let config = |types: HashTypes| HashConfig {
+        key: RSS_KEY.to_vec(),
+        types,
+    };
+    assert_eq!(
+        config(HashTypes::supported()).hash(&packet),
+        Some((0x51ccc178, HashReport::TCPV4))
+    );
+    assert_eq!(
+        config(HashTypes::new().with_ipv4(true)).hash(&packet),
+        Some((0x323e8fc2, HashReport::IPV4))
+    );
+    assert_eq!(
+        config(HashTypes::new().with_udpv4(true)).hash(&packet),
+        None
+    );
+
+    let src = [
+        0x3f, 0xfe, 0x25, 0x01, 0x02, 0x00, 0x1f, 0xff, 0, 0, 0, 0, 0, 0, 0, 7,
+    ];
+    let dst = [
+        0x3f, 0xfe, 0x25, 0x01, 0x02, 0x00, 0x00, 0x03, 0, 0, 0, 0, 0, 0, 0, 1,
+    ];
+    let mut input = [src, dst].concat();
+    assert_eq!(toeplitz(&RSS_KEY, &input), 0x2cc18cd5);
+    input.extend_from_slice(&2794u16.to_be_bytes());
+    input.extend_from_slice(&1766u16.to_be_bytes());
+    assert_eq!(toeplitz(&RSS_KEY, &input), 0x40207d3d);
+}
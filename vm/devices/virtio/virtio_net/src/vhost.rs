@@ -0,0 +1,756 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Kernel datapath support: hands the data virtqueues to an endpoint's
+//! [`VhostNet`] so that packets bypass the VMM entirely.
+//!
+//! The virtio transport and configuration space remain in OpenVMM. This
+//! requires file-backed guest RAM and identity-mapped DMA addresses; no vhost
+//! IOTLB is configured for a virtual IOMMU.
+
+use anyhow::Context as _;
+use guestmem::GuestMemory;
+use inspect::Inspect;
+use net_backend::vhost::RX_QUEUE;
+use net_backend::vhost::TX_QUEUE;
+use net_backend::vhost::VhostMemoryRegion;
+use net_backend::vhost::VhostNet;
+use net_backend::vhost::VhostQueue;
+use pal_event::Event;
+use sparse_mmap::SparseMapping;
+use std::os::fd::AsFd;
+use virtio::QueueResources;
+use virtio::queue::QueueParams;
+use virtio::queue::QueueState;
+use virtio::spec::VirtioDeviceFeatures;
+use vmcore::interrupt::EventProxy;
+use vmcore::vm_task::VmTaskDriver;
+
+/// A guest RAM region mapped for the kernel's use.
+struct MappedRegion {
+    guest_address: u64,
+    mapping: SparseMapping,
+}
+
+impl MappedRegion {
+    fn host_address(&self) -> u64 {
+        self.mapping.as_ptr() as usize as u64
+    }
+
+    /// Returns the offset of `len` bytes at `gpa` within the region.
+    fn offset(&self, gpa: u64, len: u64) -> Option<usize> {
+        let offset = gpa.checked_sub(self.guest_address)?;
+        (offset.checked_add(len)? <= self.mapping.len() as u64).then_some(offset as usize)
+    }
+}
+
+/// Guest RAM, mapped separately from the `GuestMemory` mapping so that every
+/// region is eagerly accessible to the kernel.
+struct VhostMemory {
+    regions: Vec<MappedRegion>,
+}
+
+impl VhostMemory {
+    async fn new(guest_memory: &GuestMemory) -> anyhow::Result<Self> {
+        let sharing = guest_memory
+            .sharing()
+            .context("kernel vhost-net requires shared file-backed guest memory")?;
+        let shared_regions = sharing
+            .get_regions()
+            .await
+            .map_err(anyhow::Error::from_boxed)
+            .context("failed to query shareable guest-memory regions")?;
+        anyhow::ensure!(
+            !shared_regions.is_empty(),
+            "guest memory has no shareable RAM regions"
+        );
+
+        let mut regions = Vec::with_capacity(shared_regions.len());
+        for region in shared_regions {
+            let len = usize::try_from(region.size)
+                .context("guest-memory region does not fit the host address space")?;
+            let mapping = SparseMapping::new(len).with_context(|| {
+                format!(
+                    "failed to reserve mapping for GPA {:#x}",
+                    region.guest_address
+                )
+            })?;
+            mapping
+                .map_file(0, len, region.file.as_ref(), region.file_offset, true)
+                .with_context(|| {
+                    format!(
+                        "failed to map guest-memory region at GPA {:#x}",
+                        region.guest_address
+                    )
+                })?;
+            regions.push(MappedRegion {
+                guest_address: region.guest_address,
+                mapping,
+            });
+        }
+        Ok(Self { regions })
+    }
+
+    fn table(&self) -> Vec<VhostMemoryRegion> {
+        self.regions
+            .iter()
+            .map(|region| VhostMemoryRegion {
+                guest_address: region.guest_address,
+                size: region.mapping.len() as u64,
+                host_address: region.host_address(),
+            })
+            .collect()
+    }
+
+    /// Translates the `len` bytes at `gpa`, which must be within a single
+    /// region, to a host address.
+    fn translate(&self, gpa: u64, len: u64) -> anyhow::Result<u64> {
+        self.regions
+            .iter()
+            .find_map(|region| Some(region.host_address() + region.offset(gpa, len)? as u64))
+            .with_context(|| format!("guest physical address {gpa:#x} is not in RAM"))
+    }
+
+    fn read_used_index(&self, params: &QueueParams) -> anyhow::Result<u16> {
+        let gpa = params
+            .used_addr
+            .checked_add(2)
+            .context("used ring index address overflow")?;
+        let (region, offset) = self
+            .regions
+            .iter()
+            .find_map(|region| Some((region, region.offset(gpa, 2)?)))
+            .with_context(|| format!("used ring index address {gpa:#x} is not in RAM"))?;
+        let mut bytes = [0; 2];
+        region
+            .mapping
+            .read_at(offset, &mut bytes)
+            .context("failed to read mapped guest memory")?;
+        Ok(u16::from_le_bytes(bytes))
+    }
+}
+
+/// The ring sizes of the split queue layout, used to validate that each ring
+/// lies within one memory region.
+fn ring_sizes(params: &QueueParams) -> (u64, u64, u64) {
+    let size = u64::from(params.size);
+    (16 * size, 6 + 2 * size, 6 + 8 * size)
+}
+
+struct QueueRuntime {
+    params: QueueParams,
+    _kick: Event,
+    _call: Event,
+    _proxy: Option<EventProxy>,
+}
+
+/// The data virtqueues of a virtio-net device, processed by the kernel.
+#[derive(Inspect)]
+pub(crate) struct VhostDatapath {
+    #[inspect(skip)]
+    backend: Box<dyn VhostNet>,
+    #[inspect(hex)]
+    kernel_features: u64,
+    #[inspect(skip)]
+    memory: Option<VhostMemory>,
+    #[inspect(skip)]
+    queues: [Option<QueueRuntime>; 2],
+    #[inspect(skip)]
+    negotiated_features: Option<VirtioDeviceFeatures>,
+}
+
+impl VhostDatapath {
+    pub fn new(backend: Box<dyn VhostNet>) -> Self {
+        Self {
+            kernel_features: backend.features(),
+            backend,
+            memory: None,
+            queues: [const { None }; 2],
+            negotiated_features: None,
+        }
+    }
+
+    /// Returns the virtio features implemented by the kernel.
+    pub fn features(&self) -> VirtioDeviceFeatures {
+        VirtioDeviceFeatures::from_bits(self.kernel_features)
+    }
+
+    async fn prepare(
+        &mut self,
+        guest_memory: &GuestMemory,
+        features: &VirtioDeviceFeatures,
+    ) -> anyhow::Result<()> {
+        if self.memory.is_none() {
+            let memory = VhostMemory::new(guest_memory).await?;
+            self.backend.set_memory(&memory.table())?;
+            self.memory = Some(memory);
+        }
+
+        if let Some(negotiated) = self.negotiated_features {
+            anyhow::ensure!(
+                negotiated.into_bits() == features.into_bits(),
+                "virtio features changed while vhost-net queues were active"
+            );
+        } else {
+            // The TAP device's virtio-net header size must match the guest's.
+            anyhow::ensure!(features.version_1(), "vhost-net requires modern virtio");
+            self.backend.set_features(features.into_bits())?;
+            self.negotiated_features = Some(*features);
+        }
+        Ok(())
+    }
+
+    pub async fn start_queue(
+        &mut self,
+        driver: &VmTaskDriver,
+        idx: u16,
+        resources: QueueResources,
+        features: &VirtioDeviceFeatures,
+        initial_state: Option<QueueState>,
+    ) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            idx == RX_QUEUE || idx == TX_QUEUE,
+            "invalid queue index {idx}"
+        );
+        anyhow::ensure!(
+            self.queues[idx as usize].is_none(),
+            "queue {idx} already active"
+        );
+        self.prepare(&resources.guest_memory, features).await?;
+        anyhow::ensure!(
+            !features.ring_packed(),
+            "vhost-net does not support packed queues"
+        );
+
+        let params = resources.params;
+        let memory = self.memory.as_ref().unwrap();
+        // Validate the used index location now, while the guest-provided
+        // queue parameters can still be rejected, since stop_queue must read
+        // it from guest memory.
+        memory
+            .read_used_index(&params)
+            .context("used ring index is not readable")?;
+        let (desc_len, avail_len, used_len) = ring_sizes(&params);
+        let desc_addr = memory.translate(params.desc_addr, desc_len)?;
+        let avail_addr = memory.translate(params.avail_addr, avail_len)?;
+        let used_addr = memory.translate(params.used_addr, used_len)?;
+
+        let kick = resources.event;
+        let (call, proxy) = resources
+            .notify
+            .event_or_proxy(driver)
+            .context("failed to obtain a virtio interrupt event")?;
+
+        self.backend.start_queue(
+            idx,
+            VhostQueue {
+                size: params.size,
+                base: initial_state.map_or(0, |state| state.avail_index).into(),
+                desc_addr,
+                avail_addr,
+                used_addr,
+                kick: kick.as_fd(),
+                call: call.as_fd(),
+            },
+        )?;
+
+        self.queues[idx as usize] = Some(QueueRuntime {
+            params,
+            _kick: kick,
+            _call: call,
+            _proxy: proxy,
+        });
+        Ok(())
+    }
+
+    pub fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
+        let runtime = self.queues.get_mut(idx as usize)?.take()?;
+        let avail_index = match self.backend.stop_queue(idx) {
+            Ok(base) => base as u16,
+            Err(err) => {
+                tracelimit::warn_ratelimited!(
+                    error = err.as_ref() as &dyn std::error::Error,
+                    idx,
+                    "failed to stop vhost-net queue"
+                );
+                return None;
+            }
+        };
+        // VHOST_GET_VRING_BASE only returns the available index for split
+        // queues.
+        let used_index = self
+            .memory
+            .as_ref()
+            .unwrap()
+            .read_used_index(&runtime.params)
+            .expect("validated used index became unreadable");
+        Some(QueueState {
+            avail_index,
+            used_index,
+        })
+    }
+
+    pub fn reset(&mut self) {
+        for idx in [RX_QUEUE, TX_QUEUE] {
+            self.stop_queue(idx);
+        }
+        self.negotiated_features = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Device;
+    use async_trait::async_trait;
+    use inspect::InspectMut;
+    use net_backend::Endpoint;
+    use net_backend::Queue;
+    use net_backend::QueueConfig;
+    use net_backend::RssConfig;
+    use net_backend_resources::mac_address::MacAddress;
+    use pal_async::DefaultDriver;
+    use pal_async::async_test;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use virtio::VirtioDevice;
+    use vmcore::interrupt::Interrupt;
+    use vmcore::vm_task::SingleDriverBackend;
+    use vmcore::vm_task::VmTaskDriverSource;
+
+    const QUEUE_SIZE: u16 = 256;
+    const REGION_SIZE: usize = 0x10000;
+    const HIGH_REGION: u64 = 0x10_0000;
+
+    #[derive(Debug, PartialEq)]
+    struct StartedQueue {
+        index: u16,
+        size: u16,
+        base: u32,
+        desc_addr: u64,
+        avail_addr: u64,
+        used_addr: u64,
+    }
+
+    #[derive(Default)]
+    struct BackendState {
+        features: Option<u64>,
+        started: Vec<StartedQueue>,
+        stopped: Vec<u16>,
+    }
+
+    struct TestBackend {
+        features: u64,
+        state: Arc<Mutex<BackendState>>,
+    }
+
+    impl VhostNet for TestBackend {
+        fn features(&self) -> u64 {
+            self.features
+        }
+
+        fn set_features(&mut self, features: u64) -> anyhow::Result<()> {
+            self.state.lock().features = Some(features);
+            Ok(())
+        }
+
+        fn set_memory(&mut self, _regions: &[VhostMemoryRegion]) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn start_queue(&mut self, index: u16, queue: VhostQueue<'_>) -> anyhow::Result<()> {
+            self.state.lock().started.push(StartedQueue {
+                index,
+                size: queue.size,
+                base: queue.base,
+                desc_addr: queue.desc_addr,
+                avail_addr: queue.avail_addr,
+                used_addr: queue.used_addr,
+            });
+            Ok(())
+        }
+
+        fn stop_queue(&mut self, index: u16) -> anyhow::Result<u32> {
+            self.state.lock().stopped.push(index);
+            Ok(7)
+        }
+    }
+
+    #[derive(InspectMut)]
+    struct VhostEndpoint {
+        #[inspect(skip)]
+        vhost: Option<Box<dyn VhostNet>>,
+    }
+
+    #[async_trait]
+    impl Endpoint for VhostEndpoint {
+        fn endpoint_type(&self) -> &'static str {
+            "vhost-test"
+        }
+
+        async fn get_queues(
+            &mut self,
+            _config: Vec<QueueConfig>,
+            _rss: Option<&RssConfig<'_>>,
+            _queues: &mut Vec<Box<dyn Queue>>,
+        ) -> anyhow::Result<()> {
+            unreachable!("the kernel datapath replaces the endpoint queues")
+        }
+
+        async fn stop(&mut self) {}
+
+        fn is_ordered(&self) -> bool {
+            true
+        }
+
+        fn take_vhost_net(&mut self) -> Option<Box<dyn VhostNet>> {
+            self.vhost.take()
+        }
+    }
+
+    /// Returns two allocated regions of guest RAM, at GPA 0 and
+    /// `HIGH_REGION`.
+    fn test_memory() -> VhostMemory {
+        let regions = [0, HIGH_REGION].map(|guest_address| {
+            let mapping = SparseMapping::new(REGION_SIZE).unwrap();
+            mapping.alloc(0, REGION_SIZE).unwrap();
+            MappedRegion {
+                guest_address,
+                mapping,
+            }
+        });
+        VhostMemory {
+            regions: regions.into(),
+        }
+    }
+
+    fn test_backend(features: u64) -> (TestBackend, Arc<Mutex<BackendState>>) {
+        let state = Arc::new(Mutex::new(BackendState::default()));
+        (
+            TestBackend {
+                features,
+                state: state.clone(),
+            },
+            state,
+        )
+    }
+
+    fn test_datapath() -> (VhostDatapath, Arc<Mutex<BackendState>>) {
+        let (backend, state) = test_backend(modern_features().into_bits());
+        let mut datapath = VhostDatapath::new(Box::new(backend));
+        datapath.memory = Some(test_memory());
+        (datapath, state)
+    }
+
+    fn modern_features() -> VirtioDeviceFeatures {
+        VirtioDeviceFeatures::new()
+            .with_version_1(true)
+            .with_ring_event_idx(true)
+    }
+
+    fn queue_params(base: u64) -> QueueParams {
+        QueueParams {
+            size: QUEUE_SIZE,
+            enable: true,
+            desc_addr: base,
+            avail_addr: base + 0x1000,
+            used_addr: base + 0x2000,
+        }
+    }
+
+    fn queue_resources(params: QueueParams) -> QueueResources {
+        QueueResources {
+            params,
+            notify: Interrupt::from_event(Event::new()),
+            event: Event::new(),
+            guest_memory: GuestMemory::empty(),
+        }
+    }
+
+    fn task_driver(driver: DefaultDriver) -> VmTaskDriver {
+        VmTaskDriverSource::new(SingleDriverBackend::new(driver)).simple()
+    }
+
+    #[test]
+    fn translate_within_one_region() {
+        let memory = test_memory();
+        let low = memory.regions[0].host_address();
+        let high = memory.regions[1].host_address();
+        let region_size = REGION_SIZE as u64;
+
+        assert_eq!(memory.translate(0x1000, 0x100).unwrap(), low + 0x1000);
+        assert_eq!(
+            memory.translate(region_size - 0x100, 0x100).unwrap(),
+            low + region_size - 0x100
+        );
+        assert_eq!(
+            memory.translate(HIGH_REGION + 0x10, 0x10).unwrap(),
+            high + 0x10
+        );
+
+        // Ranges crossing the end of a region, in the gap between regions,
+        // and wrapping the address space are rejected.
+        assert!(memory.translate(region_size - 0xff, 0x100).is_err());
+        assert!(memory.translate(region_size, 1).is_err());
+        assert!(memory.translate(HIGH_REGION - 1, 2).is_err());
+        assert!(memory.translate(u64::MAX, 2).is_err());
+
+        let table = memory.table();
+        assert_eq!(table.len(), 2);
+        assert_eq!(table[1].guest_address, HIGH_REGION);
+        assert_eq!(table[1].size, region_size);
+        assert_eq!(table[1].host_address, high);
+    }
+
+    #[test]
+    fn read_used_index() {
+        let memory = test_memory();
+        let params = queue_params(HIGH_REGION);
+        memory.regions[1]
+            .mapping
+            .write_at(0x2002, &5u16.to_le_bytes())
+            .unwrap();
+        assert_eq!(memory.read_used_index(&params).unwrap(), 5);
+
+        let params = QueueParams {
+            used_addr: REGION_SIZE as u64 - 2,
+            ..queue_params(0)
+        };
+        assert!(memory.read_used_index(&params).is_err());
+        let params = QueueParams {
+            used_addr: u64::MAX - 1,
+            ..queue_params(0)
+        };
+        assert!(memory.read_used_index(&params).is_err());
+    }
+
+    #[test]
+    fn split_ring_sizes() {
+        assert_eq!(ring_sizes(&queue_params(0)), (4096, 518, 2054));
+    }
+
+    #[async_test]
+    async fn start_queue_translates_rings(driver: DefaultDriver) {
+        let driver = task_driver(driver);
+        let (mut datapath, state) = test_datapath();
+        let features = modern_features();
+        let low = datapath.memory.as_ref().unwrap().regions[0].host_address();
+
+        datapath
+            .start_queue(
+                &driver,
+                RX_QUEUE,
+                queue_resources(queue_params(0)),
+                &features,
+                Some(QueueState {
+                    avail_index: 3,
+                    used_index: 3,
+                }),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            state.lock().started,
+            [StartedQueue {
+                index: RX_QUEUE,
+                size: QUEUE_SIZE,
+                base: 3,
+                desc_addr: low,
+                avail_addr: low + 0x1000,
+                used_addr: low + 0x2000,
+            }]
+        );
+
+        // Each ring must lie within a single region of RAM.
+        let crossing = [
+            QueueParams {
+                desc_addr: REGION_SIZE as u64 - 0x800,
+                ..queue_params(0x4000)
+            },
+            QueueParams {
+                avail_addr: HIGH_REGION - 0x100,
+                ..queue_params(0x4000)
+            },
+            QueueParams {
+                used_addr: REGION_SIZE as u64,
+                ..queue_params(0x4000)
+            },
+        ];
+        for params in crossing {
+            datapath
+                .start_queue(&driver, TX_QUEUE, queue_resources(params), &features, None)
+                .await
+                .unwrap_err();
+        }
+        assert_eq!(state.lock().started.len(), 1);
+
+        datapath
+            .start_queue(
+                &driver,
+                TX_QUEUE,
+                queue_resources(queue_params(HIGH_REGION)),
+                &features,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(state.lock().started.len(), 2);
+    }
+
+    #[async_test]
+    async fn queue_index_bounds(driver: DefaultDriver) {
+        let driver = task_driver(driver);
+        let (mut datapath, state) = test_datapath();
+        let features = modern_features();
+
+        let err = datapath
+            .start_queue(
+                &driver,
+                2,
+                queue_resources(queue_params(0)),
+                &features,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "invalid queue index 2");
+        assert!(datapath.stop_queue(2).is_none());
+        assert!(datapath.stop_queue(u16::MAX).is_none());
+
+        // Stopping a queue that was never started does not reach the kernel.
+        assert!(datapath.stop_queue(TX_QUEUE).is_none());
+        assert!(state.lock().stopped.is_empty());
+
+        datapath
+            .start_queue(
+                &driver,
+                RX_QUEUE,
+                queue_resources(queue_params(0)),
+                &features,
+                None,
+            )
+            .await
+            .unwrap();
+        let err = datapath
+            .start_queue(
+                &driver,
+                RX_QUEUE,
+                queue_resources(queue_params(0)),
+                &features,
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "queue 0 already active");
+
+        assert_eq!(
+            datapath.stop_queue(RX_QUEUE),
+            Some(QueueState {
+                avail_index: 7,
+                used_index: 0,
+            })
+        );
+        assert_eq!(state.lock().stopped, [RX_QUEUE]);
+    }
+
+    #[async_test]
+    async fn feature_negotiation(driver: DefaultDriver) {
+        let driver = task_driver(driver);
+        let (mut datapath, state) = test_datapath();
+        assert_eq!(
+            datapath.features().into_bits(),
+            modern_features().into_bits()
+        );
+
+        // Legacy virtio uses a different header size than the TAP device.
+        datapath
+            .start_queue(
+                &driver,
+                RX_QUEUE,
+                queue_resources(queue_params(0)),
+                &VirtioDeviceFeatures::new(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(state.lock().features, None);
+
+        let features = modern_features().with_device_specific_low(1 << 5);
+        datapath
+            .start_queue(
+                &driver,
+                RX_QUEUE,
+                queue_resources(queue_params(0)),
+                &features,
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(state.lock().features, Some(features.into_bits()));
+
+        // Both queues must use the same features.
+        datapath
+            .start_queue(
+                &driver,
+                TX_QUEUE,
+                queue_resources(queue_params(HIGH_REGION)),
+                &modern_features(),
+                None,
+            )
+            .await
+            .unwrap_err();
+
+        // A reset allows the guest to renegotiate.
+        datapath.reset();
+        assert_eq!(state.lock().stopped, [RX_QUEUE]);
+        datapath
+            .start_queue(
+                &driver,
+                TX_QUEUE,
+                queue_resources(queue_params(HIGH_REGION)),
+                &modern_features(),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(state.lock().features, Some(modern_features().into_bits()));
+    }
+
+    #[async_test]
+    async fn device_offers_a_single_queue_pair(driver: DefaultDriver) {
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver));
+        let mac = MacAddress::new([0x00, 0x15, 0x5d, 0xaa, 0xbb, 0xcc]);
+        let endpoint = || {
+            let (backend, _) = test_backend(modern_features().into_bits());
+            Box::new(VhostEndpoint {
+                vhost: Some(Box::new(backend)),
+            })
+        };
+
+        let err = Device::builder()
+            .max_queues(2)
+            .build(&driver_source, endpoint(), mac)
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "vhost-net supports a single queue pair, but 2 were requested"
+        );
+
+        Device::builder()
+            .max_queues(1)
+            .build(&driver_source, endpoint(), mac)
+            .unwrap();
+
+        // Without a limit, a single pair and no control queue are offered,
+        // along with only the ring features the kernel implements.
+        let device = Device::builder()
+            .build(&driver_source, endpoint(), mac)
+            .unwrap();
+        let traits = device.traits();
+        assert_eq!(traits.max_queues, 2);
+        assert!(traits.device_features.ring_event_idx());
+        assert!(!traits.device_features.ring_packed());
+        assert!(!traits.device_features.ring_indirect_desc());
+    }
+}
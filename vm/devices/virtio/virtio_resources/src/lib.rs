@@ -112,6 +112,128 @@ impl ResourceId<VirtioDeviceHandle> for VirtioRngHandle {
     }
 }
 
+pub mod balloon {
+    use mesh::MeshPayload;
+    use mesh::rpc::Rpc;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    #[derive(MeshPayload)]
+    pub struct VirtioBalloonHandle {
+        /// Allow the guest to deflate the balloon under memory pressure
+        /// rather than running out of memory.
+        pub deflate_on_oom: bool,
+        /// Let the guest report free pages so the host can reclaim them.
+        pub free_page_reporting: bool,
+        /// Host requests to resize the balloon or query its state.
+        pub control: mesh::Receiver<BalloonRequest>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioBalloonHandle {
+        const ID: &'static str = "virtio-balloon";
+    }
+
+    #[derive(MeshPayload)]
+    pub enum BalloonRequest {
+        /// Sets the amount of guest memory, in bytes, the guest should hand
+        /// back to the host. Rounded down to whole 4KiB pages.
+        SetTarget(Rpc<u64, ()>),
+        /// Gets the balloon's state, refreshing the guest's statistics first
+        /// if possible.
+        Query(Rpc<(), BalloonStatus>),
+    }
+
+    #[derive(Debug, Clone, MeshPayload)]
+    pub struct BalloonStatus {
+        /// The requested balloon size, in bytes.
+        pub target: u64,
+        /// The balloon size reported by the guest, in bytes.
+        pub actual: u64,
+        /// Bytes of free memory the guest has reported and the host has
+        /// released, cumulative since the device was created.
+        pub reported: u64,
+        /// The guest's most recent memory statistics, if it has sent any.
+        pub stats: Option<BalloonStats>,
+    }
+
+    /// Guest memory statistics. Each field is `None` if the guest did not
+    /// report it. Memory sizes are in bytes.
+    #[derive(Debug, Clone, Default, MeshPayload)]
+    pub struct BalloonStats {
+        pub swap_in: Option<u64>,
+        pub swap_out: Option<u64>,
+        pub major_faults: Option<u64>,
+        pub minor_faults: Option<u64>,
+        pub free_memory: Option<u64>,
+        pub total_memory: Option<u64>,
+        pub available_memory: Option<u64>,
+        pub disk_caches: Option<u64>,
+        pub hugetlb_allocations: Option<u64>,
+        pub hugetlb_failures: Option<u64>,
+    }
+}
+
+pub mod input {
+    use mesh::MeshPayload;
+    use vm_resource::Resource;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::KeyboardInputHandleKind;
+    use vm_resource::kind::MouseInputHandleKind;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    /// A virtio keyboard.
+    #[derive(MeshPayload)]
+    pub struct VirtioKeyboardHandle {
+        pub source: Resource<KeyboardInputHandleKind>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioKeyboardHandle {
+        const ID: &'static str = "virtio-keyboard";
+    }
+
+    /// A virtio mouse, reporting relative motion.
+    #[derive(MeshPayload)]
+    pub struct VirtioMouseHandle {
+        pub source: Resource<MouseInputHandleKind>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioMouseHandle {
+        const ID: &'static str = "virtio-mouse";
+    }
+
+    /// A virtio tablet, reporting absolute pointer positions.
+    #[derive(MeshPayload)]
+    pub struct VirtioTabletHandle {
+        pub source: Resource<MouseInputHandleKind>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioTabletHandle {
+        const ID: &'static str = "virtio-tablet";
+    }
+}
+
+pub mod gpu {
+    use mesh::MeshPayload;
+    use vm_resource::Resource;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::FramebufferHandleKind;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    /// A 2D virtio GPU, displaying the guest's scanout in the framebuffer.
+    #[derive(MeshPayload)]
+    pub struct VirtioGpuHandle {
+        /// The framebuffer to display the scanout in.
+        pub framebuffer: Resource<FramebufferHandleKind>,
+        /// Channel for reporting the regions the guest flushes, typically to
+        /// the VNC worker.
+        pub dirt_send: Option<mesh::Sender<Vec<video_core::DirtyRect>>>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioGpuHandle {
+        const ID: &'static str = "virtio-gpu";
+    }
+}
+
 pub mod blk {
     use mesh::MeshPayload;
     use vm_resource::Resource;
@@ -123,6 +245,8 @@ pub mod blk {
     pub struct VirtioBlkHandle {
         pub disk: Resource<DiskHandleKind>,
         pub read_only: bool,
+        /// The number of request queues, or `None` for one.
+        pub num_queues: Option<u16>,
     }
 
     impl ResourceId<VirtioDeviceHandle> for VirtioBlkHandle {
@@ -130,6 +254,30 @@ impl ResourceId<VirtioDeviceHandle> for VirtioBlkHandle {
     }
 }
 
+pub mod scsi {
+    use mesh::MeshPayload;
+    use storvsp_resources::ScsiControllerRequest;
+    use storvsp_resources::ScsiDeviceAndPath;
+    use vm_resource::ResourceId;
+    use vm_resource::kind::VirtioDeviceHandle;
+
+    /// A virtio SCSI controller, with LUNs from the same SCSI device
+    /// resources as storvsp.
+    #[derive(MeshPayload)]
+    pub struct VirtioScsiHandle {
+        /// The initial LUNs. Each path's `path` field must be zero.
+        pub devices: Vec<ScsiDeviceAndPath>,
+        /// Channel for adding and removing LUNs at runtime.
+        pub requests: Option<mesh::Receiver<ScsiControllerRequest>>,
+        /// The number of request queues, or `None` for one.
+        pub num_queues: Option<u16>,
+    }
+
+    impl ResourceId<VirtioDeviceHandle> for VirtioScsiHandle {
+        const ID: &'static str = "virtio-scsi";
+    }
+}
+
 pub mod net {
     use mesh::MeshPayload;
     use net_backend_resources::mac_address::MacAddress;
@@ -0,0 +1,1077 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio SCSI device.
+//!
+//! Implements the virtio SCSI host device (device ID 8) as specified in the
+//! VIRTIO 1.3 specification, §5.6 "SCSI Host Device". Commands on the request
+//! queues are dispatched to [`AsyncScsiDisk`] implementations, such as the
+//! disks and DVDs from `scsidisk`, so guests without Hyper-V drivers get the
+//! same SCSI emulation as storvsp.
+//!
+//! The controller has a single channel, so LUNs are addressed by
+//! [`ScsiPath`]s whose `path` is zero. LUNs can be added and removed while
+//! the guest is running; the guest is told with transport reset events on the
+//! event queue (`VIRTIO_SCSI_F_HOTPLUG`).
+//!
+//! As in storvsp, the controller answers REPORT LUNS itself, and the reset
+//! task management functions are no-ops. In-flight commands cannot be
+//! cancelled, so aborts are rejected.
+
+#![forbid(unsafe_code)]
+
+pub mod resolver;
+mod spec;
+#[cfg(test)]
+mod tests;
+
+use anyhow::Context as _;
+use futures::StreamExt;
+use futures_concurrency::future::Race as _;
+use guestmem::GuestMemory;
+use guestmem::GuestMemoryError;
+use guestmem::MemoryWrite;
+use guestmem::ranges::PagedRange;
+use inspect::Inspect;
+use inspect::InspectMut;
+use inspect_counters::Counter;
+use pal_async::wait::PolledWait;
+use parking_lot::RwLock;
+use scsi_buffers::RequestBuffers;
+use scsi_core::AsyncScsiDisk;
+use scsi_core::Request;
+use scsi_core::ScsiResult;
+use scsi_defs::AdditionalSenseCode;
+use scsi_defs::ScsiOp;
+use scsi_defs::ScsiStatus;
+use scsi_defs::SenseData;
+use scsi_defs::SenseKey;
+use scsi_defs::srb::SRB_FLAGS_DATA_IN;
+use scsi_defs::srb::SRB_FLAGS_DATA_OUT;
+use scsi_defs::srb::SrbStatus;
+use spec::*;
+use std::collections::HashMap;
+use std::collections::VecDeque;
+use std::collections::hash_map;
+use std::future::Future;
+use std::future::pending;
+use std::future::poll_fn;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::Context;
+use std::task::Poll;
+use storvsp_resources::ScsiPath;
+use task_control::AsyncRun;
+use task_control::Cancelled;
+use task_control::InspectTaskMut;
+use task_control::StopTask;
+use task_control::TaskControl;
+use thiserror::Error;
+use unicycle::FuturesUnordered;
+use virtio::DeviceTraits;
+use virtio::DeviceTraitsSharedMemory;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::VirtioQueue;
+use virtio::VirtioQueueCallbackWork;
+use virtio::queue::QueueState;
+use virtio::regions::DataRegion;
+use virtio::regions::data_regions;
+use virtio::regions::try_build_gpn_list;
+use virtio::spec::VirtioDeviceFeatures;
+use vmcore::vm_task::VmTaskDriver;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+/// The maximum number of request queues.
+const MAX_REQUEST_QUEUES: u16 = 16;
+
+/// The maximum number of commands in flight on each request queue.
+const MAX_IO_DEPTH: usize = 64;
+
+/// The maximum number of data segments per command. Each command also uses a
+/// descriptor for the request and one for the response.
+const SEG_MAX: u32 = virtio::DEFAULT_QUEUE_SIZE as u32 - 2;
+
+/// The largest transfer, in 512-byte sectors. This also bounds the bounce
+/// buffer used for descriptor chains that are not page aligned.
+const MAX_SECTORS: u32 = 0x4000;
+
+/// The highest target and LUN numbers. [`ScsiPath`] has a byte for each.
+const MAX_TARGET: u32 = 255;
+const MAX_LUN: u32 = 255;
+
+/// The largest CDB and sense sizes the driver may configure.
+const MAX_CONFIGURABLE_SIZE: u32 = 256;
+
+/// The maximum number of hotplug events held while the guest has not posted
+/// event buffers. Past this, the guest is told it missed events and rescans
+/// the whole bus.
+const MAX_PENDING_EVENTS: usize = 64;
+
+/// A virtio SCSI controller.
+pub struct VirtioScsiDevice {
+    driver: VmTaskDriver,
+    controller: Arc<ControllerState>,
+    sizes: Sizes,
+    control: TaskControl<ControlWorker, ControlQueues>,
+    requests: Vec<TaskControl<RequestWorker, RequestQueue>>,
+}
+
+/// A handle for adding and removing the LUNs of a [`VirtioScsiDevice`].
+#[derive(Clone)]
+pub struct VirtioScsiController {
+    state: Arc<ControllerState>,
+}
+
+/// An error adding or removing a LUN.
+#[derive(Debug, Error)]
+pub enum PathError {
+    /// There is already a LUN at the path.
+    #[error("SCSI path {0} is already in use")]
+    InUse(ScsiPath),
+    /// There is no LUN at the path.
+    #[error("SCSI path {0} is not in use")]
+    NotInUse(ScsiPath),
+    /// The path is on a channel other than zero.
+    #[error("SCSI path {0} is not on channel 0")]
+    InvalidChannel(ScsiPath),
+}
+
+impl VirtioScsiController {
+    /// Adds `disk` at `path`, and tells the guest to scan for it.
+    pub fn attach(&self, path: ScsiPath, disk: Arc<dyn AsyncScsiDisk>) -> Result<(), PathError> {
+        if path.path != 0 {
+            return Err(PathError::InvalidChannel(path));
+        }
+        match self.state.disks.write().entry(path) {
+            hash_map::Entry::Occupied(_) => return Err(PathError::InUse(path)),
+            hash_map::Entry::Vacant(entry) => entry.insert(disk),
+        };
+        self.state.notify(path, VIRTIO_SCSI_EVT_RESET_RESCAN);
+        Ok(())
+    }
+
+    /// Removes the disk at `path`, and tells the guest it is gone.
+    pub fn remove(&self, path: ScsiPath) -> Result<(), PathError> {
+        if self.state.disks.write().remove(&path).is_none() {
+            return Err(PathError::NotInUse(path));
+        }
+        self.state.notify(path, VIRTIO_SCSI_EVT_RESET_REMOVED);
+        Ok(())
+    }
+}
+
+/// The LUNs, shared between the device, its workers, and
+/// [`VirtioScsiController`]s.
+struct ControllerState {
+    disks: RwLock<HashMap<ScsiPath, Arc<dyn AsyncScsiDisk>>>,
+    events: mesh::Sender<Event>,
+}
+
+impl ControllerState {
+    fn disk(&self, path: &ScsiPath) -> Option<Arc<dyn AsyncScsiDisk>> {
+        self.disks.read().get(path).cloned()
+    }
+
+    /// Returns the sorted LUNs of `target`.
+    fn luns(&self, target: u8) -> Vec<u8> {
+        let mut luns = self
+            .disks
+            .read()
+            .keys()
+            .filter(|path| path.target == target)
+            .map(|path| path.lun)
+            .collect::<Vec<_>>();
+        luns.sort_unstable();
+        luns
+    }
+
+    fn notify(&self, path: ScsiPath, reason: u32) {
+        self.events.send(Event {
+            event: VIRTIO_SCSI_T_TRANSPORT_RESET,
+            lun: lun_address(path),
+            reason,
+        });
+    }
+}
+
+/// The CDB and sense sizes, which the driver may change through the config
+/// space. They set the layout of each command's request and response.
+#[derive(Debug, Copy, Clone, Inspect)]
+struct Sizes {
+    cdb: usize,
+    sense: usize,
+}
+
+impl Default for Sizes {
+    fn default() -> Self {
+        Self {
+            cdb: VIRTIO_SCSI_CDB_DEFAULT_SIZE,
+            sense: VIRTIO_SCSI_SENSE_DEFAULT_SIZE,
+        }
+    }
+}
+
+impl Sizes {
+    fn request_len(&self) -> usize {
+        size_of::<CmdReqHeader>() + self.cdb
+    }
+
+    fn response_len(&self) -> usize {
+        size_of::<CmdRespHeader>() + self.sense
+    }
+}
+
+/// Returns the single-level LUN address of `path`, using flat space
+/// addressing as the Linux and Windows drivers do.
+fn lun_address(path: ScsiPath) -> [u8; 8] {
+    [
+        LUN_ADDRESS_SINGLE_LEVEL,
+        path.target,
+        LUN_ADDRESS_FLAT_SPACE,
+        path.lun,
+        0,
+        0,
+        0,
+        0,
+    ]
+}
+
+/// Parses a single-level LUN address into a target and LUN.
+fn parse_lun_address(lun: [u8; 8]) -> Option<(u8, u16)> {
+    if lun[0] != LUN_ADDRESS_SINGLE_LEVEL || lun[4..] != [0; 4] {
+        return None;
+    }
+    let id = match lun[2] & 0xc0 {
+        0 if lun[2] == 0 => lun[3].into(),
+        LUN_ADDRESS_FLAT_SPACE => u16::from_be_bytes([lun[2] & 0x3f, lun[3]]),
+        _ => return None,
+    };
+    Some((lun[1], id))
+}
+
+impl VirtioScsiDevice {
+    /// Creates a new virtio SCSI controller with `num_queues` request queues,
+    /// clamped to `1..=16`, and no LUNs.
+    pub fn new(driver_source: &VmTaskDriverSource, num_queues: u16) -> Self {
+        let (send, recv) = mesh::channel();
+        let controller = Arc::new(ControllerState {
+            disks: Default::default(),
+            events: send,
+        });
+        let requests = (0..num_queues.clamp(1, MAX_REQUEST_QUEUES))
+            .map(|_| {
+                TaskControl::new(RequestWorker {
+                    controller: controller.clone(),
+                    stats: Default::default(),
+                    ios: FuturesUnordered::new(),
+                })
+            })
+            .collect();
+        Self {
+            driver: driver_source.simple(),
+            controller,
+            sizes: Sizes::default(),
+            control: TaskControl::new(ControlWorker {
+                events: recv,
+                pending: VecDeque::new(),
+                events_missed: false,
+            }),
+            requests,
+        }
+    }
+
+    /// Returns a handle for adding and removing LUNs.
+    pub fn controller(&self) -> VirtioScsiController {
+        VirtioScsiController {
+            state: self.controller.clone(),
+        }
+    }
+}
+
+impl InspectMut for VirtioScsiDevice {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>) {
+        let mut resp = req.respond();
+        for (path, disk) in self.controller.disks.read().iter() {
+            resp.child(&format!("disks/{}", path), |req| disk.inspect(req));
+        }
+        resp.field("sizes", self.sizes)
+            .field_mut("control", &mut self.control)
+            .fields_mut("requests", self.requests.iter_mut().enumerate());
+    }
+}
+
+impl VirtioDevice for VirtioScsiDevice {
+    fn traits(&self) -> DeviceTraits {
+        DeviceTraits {
+            device_id: virtio::spec::VirtioDeviceType::SCSI,
+            device_features: VirtioDeviceFeatures::new()
+                .with_device_specific_low(VIRTIO_SCSI_F_HOTPLUG)
+                .with_ring_event_idx(true)
+                .with_ring_indirect_desc(true)
+                .with_ring_packed(true),
+            max_queues: REQUEST_QUEUE_BASE + self.requests.len() as u16,
+            device_register_length: CONFIG_LEN,
+            shared_memory: DeviceTraitsSharedMemory::default(),
+        }
+    }
+
+    async fn read_registers_u32(&mut self, offset: u16) -> u32 {
+        match offset {
+            CONFIG_NUM_QUEUES => self.requests.len() as u32,
+            CONFIG_SEG_MAX => SEG_MAX,
+            CONFIG_MAX_SECTORS => MAX_SECTORS,
+            CONFIG_CMD_PER_LUN => MAX_IO_DEPTH as u32,
+            CONFIG_EVENT_INFO_SIZE => size_of::<Event>() as u32,
+            CONFIG_SENSE_SIZE => self.sizes.sense as u32,
+            CONFIG_CDB_SIZE => self.sizes.cdb as u32,
+            // `max_channel` is zero.
+            CONFIG_MAX_CHANNEL_TARGET => MAX_TARGET << 16,
+            CONFIG_MAX_LUN => MAX_LUN,
+            _ => 0,
+        }
+    }
+
+    async fn write_registers_u32(&mut self, offset: u16, val: u32) {
+        match offset {
+            CONFIG_SENSE_SIZE if val <= MAX_CONFIGURABLE_SIZE => self.sizes.sense = val as usize,
+            CONFIG_CDB_SIZE if val <= MAX_CONFIGURABLE_SIZE => self.sizes.cdb = val as usize,
+            _ => tracelimit::warn_ratelimited!(offset, val, "invalid virtio-scsi config write"),
+        }
+    }
+
+    async fn start_queue(
+        &mut self,
+        idx: u16,
+        resources: QueueResources,
+        features: &VirtioDeviceFeatures,
+        initial_state: Option<QueueState>,
+    ) -> anyhow::Result<()> {
+        let queue_event = PolledWait::new(&self.driver, resources.event)
+            .context("failed to create polled wait")?;
+        let queue = VirtioQueue::new(
+            *features,
+            resources.params,
+            resources.guest_memory.clone(),
+            resources.notify,
+            queue_event,
+            initial_state,
+        )
+        .context("failed to create virtio queue")?;
+
+        match idx {
+            CONTROL_QUEUE | EVENT_QUEUE => {
+                if self.control.has_state() {
+                    self.control.stop().await;
+                } else {
+                    self.control.insert(
+                        &self.driver,
+                        "virtio-scsi-control",
+                        ControlQueues {
+                            mem: resources.guest_memory,
+                            controlq: None,
+                            eventq: None,
+                            hotplug: false,
+                        },
+                    );
+                }
+                let (worker, state) = self.control.get_mut();
+                let state = state.unwrap();
+                if idx == EVENT_QUEUE {
+                    // The guest scans the bus after it starts the event queue,
+                    // so it will find the LUNs attached before now without
+                    // being told.
+                    worker.clear_events();
+                    state.hotplug = features.device_specific_low() & VIRTIO_SCSI_F_HOTPLUG != 0;
+                    state.eventq = Some(queue);
+                } else {
+                    state.controlq = Some(queue);
+                }
+                self.control.start();
+            }
+            _ => {
+                let worker = &mut self.requests[(idx - REQUEST_QUEUE_BASE) as usize];
+                worker.insert(
+                    &self.driver,
+                    format!("virtio-scsi-request-{}", idx - REQUEST_QUEUE_BASE),
+                    RequestQueue {
+                        queue,
+                        mem: resources.guest_memory,
+                        sizes: self.sizes,
+                    },
+                );
+                worker.start();
+            }
+        }
+        Ok(())
+    }
+
+    async fn stop_queue(&mut self, idx: u16) -> Option<QueueState> {
+        match idx {
+            CONTROL_QUEUE | EVENT_QUEUE => {
+                if !self.control.has_state() {
+                    return None;
+                }
+                self.control.stop().await;
+                let state = self.control.state_mut().unwrap();
+                let queue = if idx == EVENT_QUEUE {
+                    state.eventq.take()
+                } else {
+                    state.controlq.take()
+                };
+                if state.controlq.is_none() && state.eventq.is_none() {
+                    self.control.remove();
+                } else {
+                    self.control.start();
+                }
+                queue.map(|q| q.queue_state())
+            }
+            _ => {
+                let worker = &mut self.requests[(idx - REQUEST_QUEUE_BASE) as usize];
+                if !worker.has_state() {
+                    return None;
+                }
+                worker.stop().await;
+                // Complete the commands that were in flight when the worker
+                // stopped before giving up the queue.
+                let (task, state) = worker.get_mut();
+                let queue = &mut state.unwrap().queue;
+                poll_fn(|cx| task.poll_drain(queue, cx)).await;
+                Some(worker.remove().queue.queue_state())
+            }
+        }
+    }
+
+    async fn reset(&mut self) {
+        self.sizes = Sizes::default();
+        self.control.task_mut().clear_events();
+    }
+}
+
+/// Processes the control and event queues.
+#[derive(InspectMut)]
+struct ControlWorker {
+    #[inspect(skip)]
+    events: mesh::Receiver<Event>,
+    #[inspect(with = "VecDeque::len")]
+    pending: VecDeque<Event>,
+    events_missed: bool,
+}
+
+impl ControlWorker {
+    /// Drops the undelivered hotplug events.
+    fn clear_events(&mut self) {
+        while self.events.try_recv().is_ok() {}
+        self.pending.clear();
+        self.events_missed = false;
+    }
+}
+
+#[derive(InspectMut)]
+struct ControlQueues {
+    #[inspect(skip)]
+    mem: GuestMemory,
+    controlq: Option<VirtioQueue>,
+    eventq: Option<VirtioQueue>,
+    /// Whether the guest negotiated `VIRTIO_SCSI_F_HOTPLUG`.
+    hotplug: bool,
+}
+
+impl InspectTaskMut<ControlQueues> for ControlWorker {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>, state: Option<&mut ControlQueues>) {
+        req.respond().merge(self).merge(state);
+    }
+}
+
+impl AsyncRun<ControlQueues> for ControlWorker {
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        state: &mut ControlQueues,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async {
+            if let Err(err) = self.process(state).await {
+                tracelimit::error_ratelimited!(
+                    err = &err as &dyn std::error::Error,
+                    "virtio-scsi control queue error"
+                );
+            }
+        })
+        .await
+    }
+}
+
+impl ControlWorker {
+    async fn process(&mut self, state: &mut ControlQueues) -> Result<(), std::io::Error> {
+        enum Next {
+            Control(Result<VirtioQueueCallbackWork, std::io::Error>),
+            Buffer(Result<VirtioQueueCallbackWork, std::io::Error>),
+            Hotplug(Event),
+        }
+
+        let Self {
+            events,
+            pending: queued,
+            events_missed,
+        } = self;
+        let ControlQueues {
+            mem,
+            controlq,
+            eventq,
+            hotplug,
+        } = state;
+        loop {
+            let control = async {
+                match controlq {
+                    Some(queue) => Next::Control(next_work(queue).await),
+                    None => pending().await,
+                }
+            };
+            // Only take buffers from the guest when there is something to put
+            // in them.
+            let buffer = async {
+                match eventq {
+                    Some(queue) if !queued.is_empty() => Next::Buffer(next_work(queue).await),
+                    _ => pending().await,
+                }
+            };
+            let hotplug_event = async {
+                match events.next().await {
+                    Some(event) => Next::Hotplug(event),
+                    None => pending().await,
+                }
+            };
+
+            let next = (control, buffer, hotplug_event).race().await;
+            match next {
+                Next::Control(work) => {
+                    let work = work?;
+                    let len = match handle_control(mem, &work) {
+                        Ok(len) => len,
+                        Err(err) => {
+                            tracelimit::warn_ratelimited!(
+                                error = &err as &dyn std::error::Error,
+                                "virtio-scsi control request failed"
+                            );
+                            0
+                        }
+                    };
+                    controlq.as_mut().unwrap().complete(work, len);
+                }
+                Next::Buffer(work) => {
+                    let work = work?;
+                    let mut event = queued.pop_front().unwrap();
+                    if std::mem::take(events_missed) {
+                        event.event |= VIRTIO_SCSI_T_EVENTS_MISSED;
+                    }
+                    let len = match work.write(mem, event.as_bytes()) {
+                        Ok(()) => size_of::<Event>() as u32,
+                        Err(err) => {
+                            tracelimit::warn_ratelimited!(
+                                error = &err as &dyn std::error::Error,
+                                "failed to write virtio-scsi event"
+                            );
+                            0
+                        }
+                    };
+                    eventq.as_mut().unwrap().complete(work, len);
+                }
+                Next::Hotplug(event) => {
+                    if !*hotplug {
+                        // The guest cannot be told, so it will find out the
+                        // next time it scans the bus.
+                    } else if queued.len() >= MAX_PENDING_EVENTS {
+                        *events_missed = true;
+                    } else {
+                        queued.push_back(event);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// A failed control queue request.
+#[derive(Debug, Error)]
+enum ControlError {
+    #[error("unsupported control request type {0}")]
+    Unsupported(u32),
+    #[error("request too short")]
+    TooShort,
+    #[error("guest memory access failed")]
+    Memory(#[source] GuestMemoryError),
+    #[error("failed to write response")]
+    Write(#[source] virtio::VirtioWriteError),
+}
+
+/// Handles a control queue request, returning the length of the response.
+fn handle_control(mem: &GuestMemory, work: &VirtioQueueCallbackWork) -> Result<u32, ControlError> {
+    // The task management request is the largest.
+    let mut request = [0; size_of::<CtrlTmfReq>()];
+    let len = work.read(mem, &mut request).map_err(ControlError::Memory)?;
+    let request = &request[..len];
+    let ty = u32::read_from_prefix(request)
+        .map_err(|_| ControlError::TooShort)?
+        .0;
+    let response = match ty {
+        VIRTIO_SCSI_T_TMF => {
+            let (tmf, _) =
+                CtrlTmfReq::read_from_prefix(request).map_err(|_| ControlError::TooShort)?;
+            let response = match tmf.subtype {
+                VIRTIO_SCSI_T_TMF_I_T_NEXUS_RESET | VIRTIO_SCSI_T_TMF_LOGICAL_UNIT_RESET => {
+                    VIRTIO_SCSI_S_FUNCTION_COMPLETE
+                }
+                _ => VIRTIO_SCSI_S_FUNCTION_REJECTED,
+            };
+            vec![response]
+        }
+        VIRTIO_SCSI_T_AN_QUERY | VIRTIO_SCSI_T_AN_SUBSCRIBE => {
+            CtrlAnReq::read_from_prefix(request).map_err(|_| ControlError::TooShort)?;
+            // No asynchronous notifications are supported.
+            CtrlAnResp {
+                event_actual: 0,
+                response: VIRTIO_SCSI_S_OK,
+            }
+            .as_bytes()
+            .to_vec()
+        }
+        ty => return Err(ControlError::Unsupported(ty)),
+    };
+    work.write(mem, &response).map_err(ControlError::Write)?;
+    Ok(response.len() as u32)
+}
+
+/// Processes a request queue.
+///
+/// The in-flight commands live here rather than in [`RequestQueue`] so that
+/// they survive the task being stopped, and can be drained before the queue
+/// is removed.
+#[derive(InspectMut)]
+struct RequestWorker {
+    #[inspect(skip)]
+    controller: Arc<ControllerState>,
+    stats: RequestStats,
+    #[inspect(with = "FuturesUnordered::len")]
+    ios: FuturesUnordered<Pin<Box<dyn Future<Output = IoCompletion> + Send>>>,
+}
+
+#[derive(InspectMut)]
+struct RequestQueue {
+    queue: VirtioQueue,
+    #[inspect(skip)]
+    mem: GuestMemory,
+    sizes: Sizes,
+}
+
+#[derive(Inspect, Default)]
+struct RequestStats {
+    commands: Counter,
+    bounced: Counter,
+    failed: Counter,
+}
+
+/// A finished command, returned to the worker to complete its descriptor.
+struct IoCompletion {
+    work: VirtioQueueCallbackWork,
+    len: u32,
+    bounced: bool,
+    failed: bool,
+}
+
+impl InspectTaskMut<RequestQueue> for RequestWorker {
+    fn inspect_mut(&mut self, req: inspect::Request<'_>, state: Option<&mut RequestQueue>) {
+        req.respond().merge(self).merge(state);
+    }
+}
+
+impl AsyncRun<RequestQueue> for RequestWorker {
+    async fn run(
+        &mut self,
+        stop: &mut StopTask<'_>,
+        state: &mut RequestQueue,
+    ) -> Result<(), Cancelled> {
+        stop.until_stopped(async {
+            // Set once the queue can no longer produce work. In-flight
+            // commands still drain so their descriptors are completed.
+            let mut queue_done = false;
+
+            while !(queue_done && self.ios.is_empty()) {
+                enum Next {
+                    Work(Result<VirtioQueueCallbackWork, std::io::Error>),
+                    Completed(IoCompletion),
+                }
+
+                let next = poll_fn(|cx| {
+                    if let Poll::Ready(Some(completion)) = self.ios.poll_next_unpin(cx) {
+                        return Poll::Ready(Next::Completed(completion));
+                    }
+                    if !queue_done && self.ios.len() < MAX_IO_DEPTH {
+                        if let Poll::Ready(item) = state.queue.poll_next_unpin(cx) {
+                            let item = item.expect("virtio queue stream never ends");
+                            return Poll::Ready(Next::Work(item));
+                        }
+                    }
+                    Poll::Pending
+                })
+                .await;
+
+                match next {
+                    Next::Work(Ok(work)) => {
+                        let controller = self.controller.clone();
+                        let mem = state.mem.clone();
+                        let sizes = state.sizes;
+                        self.ios.push(Box::pin(async move {
+                            process_request(&controller, &mem, sizes, work).await
+                        }));
+                    }
+                    Next::Work(Err(err)) => {
+                        tracelimit::error_ratelimited!(
+                            error = &err as &dyn std::error::Error,
+                            "error reading from virtio-scsi request queue, stopping worker"
+                        );
+                        queue_done = true;
+                    }
+                    Next::Completed(completion) => self.finish(&mut state.queue, completion),
+                }
+            }
+        })
+        .await
+    }
+}
+
+impl RequestWorker {
+    fn finish(&mut self, queue: &mut VirtioQueue, completion: IoCompletion) {
+        queue.complete(completion.work, completion.len);
+        self.stats.commands.increment();
+        if completion.bounced {
+            self.stats.bounced.increment();
+        }
+        if completion.failed {
+            self.stats.failed.increment();
+        }
+    }
+
+    /// Polls the in-flight commands to completion.
+    fn poll_drain(&mut self, queue: &mut VirtioQueue, cx: &mut Context<'_>) -> Poll<()> {
+        loop {
+            match self.ios.poll_next_unpin(cx) {
+                Poll::Ready(Some(completion)) => self.finish(queue, completion),
+                Poll::Ready(None) => return Poll::Ready(()),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// A command that could not be dispatched, reported to the guest in the
+/// response's `response` field.
+#[derive(Debug, Error)]
+enum RequestError {
+    #[error("request too short")]
+    TooShort,
+    #[error("bidirectional commands are not supported")]
+    Bidirectional,
+    #[error("transfer of {0:#x} bytes is too large")]
+    TooLarge(u64),
+    #[error("invalid data buffer")]
+    InvalidBuffer,
+    #[error("no such target")]
+    BadTarget,
+    #[error("guest memory access failed")]
+    Memory(#[source] GuestMemoryError),
+}
+
+/// The outcome of a dispatched command.
+struct Executed {
+    result: ScsiResult,
+    /// The length of the command's data buffer.
+    data_len: usize,
+    /// Whether data was written to the guest.
+    data_in: bool,
+    bounced: bool,
+}
+
+/// Processes a command, writing its response.
+async fn process_request(
+    controller: &ControllerState,
+    mem: &GuestMemory,
+    sizes: Sizes,
+    work: VirtioQueueCallbackWork,
+) -> IoCompletion {
+    let response_len = sizes.response_len();
+    if work.get_payload_length(true) < response_len as u64 {
+        tracelimit::warn_ratelimited!("virtio-scsi response buffer too short");
+        return IoCompletion {
+            work,
+            len: 0,
+            bounced: false,
+            failed: true,
+        };
+    }
+
+    let mut response = vec![0; response_len];
+    let (len, bounced, failed) = match execute(controller, mem, sizes, &work).await {
+        Ok(executed) => {
+            let result = &executed.result;
+            let tx = result.tx.min(executed.data_len);
+            let sense = result.sense_data.as_ref().map_or(&[][..], |s| s.as_bytes());
+            let sense = &sense[..sense.len().min(sizes.sense)];
+            let header = CmdRespHeader {
+                sense_len: sense.len() as u32,
+                resid: (executed.data_len - tx) as u32,
+                status_qualifier: 0,
+                status: result.scsi_status.0,
+                response: VIRTIO_SCSI_S_OK,
+            };
+            header.write_to_prefix(&mut response).unwrap();
+            response[size_of::<CmdRespHeader>()..][..sense.len()].copy_from_slice(sense);
+            let data_len = if executed.data_in { tx } else { 0 };
+            (
+                (response_len + data_len) as u32,
+                executed.bounced,
+                result.scsi_status != ScsiStatus::GOOD,
+            )
+        }
+        Err(err) => {
+            let response_code = match err {
+                // Expected while the guest scans the bus.
+                RequestError::BadTarget => VIRTIO_SCSI_S_BAD_TARGET,
+                _ => {
+                    tracelimit::warn_ratelimited!(
+                        error = &err as &dyn std::error::Error,
+                        "virtio-scsi command failed"
+                    );
+                    VIRTIO_SCSI_S_FAILURE
+                }
+            };
+            CmdRespHeader {
+                response: response_code,
+                ..Default::default()
+            }
+            .write_to_prefix(&mut response)
+            .unwrap();
+            (response_len as u32, false, true)
+        }
+    };
+    if let Err(err) = work.write(mem, &response) {
+        tracelimit::warn_ratelimited!(
+            error = &err as &dyn std::error::Error,
+            "failed to write virtio-scsi response"
+        );
+    }
+    IoCompletion {
+        work,
+        len,
+        bounced,
+        failed,
+    }
+}
+
+/// Dispatches a command to its LUN.
+async fn execute(
+    controller: &ControllerState,
+    mem: &GuestMemory,
+    sizes: Sizes,
+    work: &VirtioQueueCallbackWork,
+) -> Result<Executed, RequestError> {
+    let request_len = sizes.request_len();
+    let mut request = vec![0; request_len];
+    if work.read(mem, &mut request).map_err(RequestError::Memory)? < request_len {
+        return Err(RequestError::TooShort);
+    }
+    let (header, cdb_bytes) = CmdReqHeader::read_from_prefix(&request).unwrap();
+    let mut cdb = [0; 16];
+    let cdb_len = cdb_bytes.len().min(cdb.len());
+    cdb[..cdb_len].copy_from_slice(&cdb_bytes[..cdb_len]);
+
+    let data_out = work.get_payload_length(false) - request_len as u64;
+    let data_in = work.get_payload_length(true) - sizes.response_len() as u64;
+    let (is_read, data_len, skip) = match (data_out, data_in) {
+        (0, len) => (true, len, sizes.response_len() as u64),
+        (len, 0) => (false, len, request_len as u64),
+        _ => return Err(RequestError::Bidirectional),
+    };
+    if data_len > MAX_SECTORS as u64 * 512 {
+        return Err(RequestError::TooLarge(data_len));
+    }
+    let data_len = data_len as usize;
+
+    let (target, lun) = parse_lun_address(header.lun).ok_or(RequestError::BadTarget)?;
+    let luns = controller.luns(target);
+    if luns.is_empty() {
+        return Err(RequestError::BadTarget);
+    }
+
+    let regions = data_regions(&work.payload, is_read, skip, data_len as u64).collect::<Vec<_>>();
+    let (mut bounce, gpns, offset) = match try_build_gpn_list(&regions) {
+        _ if data_len == 0 => (None, Vec::new(), 0),
+        Some((gpns, offset, _)) => (None, gpns, offset),
+        None => {
+            let mut bounce = GuestMemory::allocate(data_len);
+            if !is_read {
+                copy_from_guest(mem, &regions, bounce.inner_buf_mut().unwrap())
+                    .map_err(RequestError::Memory)?;
+            }
+            let gpns = (0..data_len.div_ceil(guestmem::PAGE_SIZE) as u64).collect();
+            (Some(bounce), gpns, 0)
+        }
+    };
+    let range = PagedRange::new(offset, data_len, &gpns).ok_or(RequestError::InvalidBuffer)?;
+    let buffers = RequestBuffers::new(bounce.as_ref().unwrap_or(mem), range, is_read);
+
+    let op = ScsiOp(cdb[0]);
+    let disk = u8::try_from(lun).ok().and_then(|lun| {
+        controller.disk(&ScsiPath {
+            path: 0,
+            target,
+            lun,
+        })
+    });
+    let result = if op == ScsiOp::REPORT_LUNS {
+        report_luns(&luns, &buffers)
+    } else if let Some(disk) = disk {
+        let srb_flags = match (data_len, is_read) {
+            (0, _) => 0,
+            (_, true) => SRB_FLAGS_DATA_IN,
+            (_, false) => SRB_FLAGS_DATA_OUT,
+        };
+        disk.execute_scsi(&buffers, &Request { cdb, srb_flags })
+            .await
+    } else if op == ScsiOp::INQUIRY {
+        inquiry_not_present(&cdb, &buffers, lun != 0)
+    } else {
+        check_condition(AdditionalSenseCode::INVALID_LUN)
+    };
+
+    let bounced = bounce.is_some();
+    if let Some(bounce) = &mut bounce {
+        if is_read {
+            let tx = result.tx.min(data_len);
+            copy_to_guest(mem, &regions, &bounce.inner_buf_mut().unwrap()[..tx])
+                .map_err(RequestError::Memory)?;
+        }
+    }
+
+    Ok(Executed {
+        result,
+        data_len,
+        data_in: is_read,
+        bounced,
+    })
+}
+
+fn check_condition(code: AdditionalSenseCode) -> ScsiResult {
+    ScsiResult {
+        scsi_status: ScsiStatus::CHECK_CONDITION,
+        srb_status: SrbStatus::INVALID_REQUEST,
+        tx: 0,
+        sense_data: Some(SenseData::new(SenseKey::ILLEGAL_REQUEST, code, 0)),
+    }
+}
+
+fn good(tx: usize) -> ScsiResult {
+    ScsiResult {
+        scsi_status: ScsiStatus::GOOD,
+        srb_status: SrbStatus::SUCCESS,
+        tx,
+        sense_data: None,
+    }
+}
+
+/// Answers REPORT LUNS with the target's LUNs.
+fn report_luns(luns: &[u8], buffers: &RequestBuffers<'_>) -> ScsiResult {
+    const HEADER_SIZE: usize = size_of::<scsi_defs::LunList>();
+    if buffers.len() < HEADER_SIZE {
+        return good(0);
+    }
+    let mut data = scsi_defs::LunList {
+        length: (luns.len() as u32 * 8).into(),
+        reserved: [0; 4],
+    }
+    .as_bytes()
+    .to_vec();
+    for &lun in luns {
+        let mut entry = [0; 8];
+        entry[..2].copy_from_slice(&u16::from(lun).to_be_bytes());
+        data.extend_from_slice(&entry);
+    }
+    let tx = buffers.len().min(data.len());
+    match buffers.writer().write(&data[..tx]) {
+        Ok(()) => good(tx),
+        Err(_) => check_condition(AdditionalSenseCode::INVALID_CDB),
+    }
+}
+
+/// Answers INQUIRY for a LUN with no device, on a target that has others.
+fn inquiry_not_present(
+    cdb: &[u8; 16],
+    buffers: &RequestBuffers<'_>,
+    nonzero_lun: bool,
+) -> ScsiResult {
+    const LOGICAL_UNIT_NOT_PRESENT_DEVICE: u8 = 0x7f;
+
+    let (cdb, _) = scsi_defs::CdbInquiry::read_from_prefix(cdb).unwrap();
+    let allocation_length = cdb.allocation_length.get() as usize;
+    if buffers.len() < allocation_length
+        || allocation_length < size_of::<scsi_defs::InquiryDataHeader>()
+        // VPD pages cannot be reported for a LUN that does not exist.
+        || cdb.flags.vpd()
+        || cdb.page_code != 0
+    {
+        return check_condition(AdditionalSenseCode::INVALID_CDB);
+    }
+
+    let mut data = scsidisk::INQUIRY_DATA_TEMPLATE;
+    data.header.device_type = LOGICAL_UNIT_NOT_PRESENT_DEVICE;
+    if nonzero_lun {
+        // These are only reported for LUN 0.
+        data.vendor_id = [0; 8];
+        data.product_id = [0; 16];
+        data.product_revision_level = [0; 4];
+    }
+    let tx = allocation_length.min(size_of::<scsi_defs::InquiryData>());
+    match buffers.writer().write(&data.as_bytes()[..tx]) {
+        Ok(()) => good(tx),
+        Err(_) => check_condition(AdditionalSenseCode::INVALID_CDB),
+    }
+}
+
+/// Reads the guest's data regions into `buf`.
+fn copy_from_guest(
+    mem: &GuestMemory,
+    regions: &[DataRegion],
+    mut buf: &mut [u8],
+) -> Result<(), GuestMemoryError> {
+    for region in regions {
+        let (this, rest) = buf.split_at_mut(region.len as usize);
+        mem.read_at(region.addr, this)?;
+        buf = rest;
+    }
+    Ok(())
+}
+
+/// Writes `buf` to the guest's data regions, stopping at the end of `buf`.
+fn copy_to_guest(
+    mem: &GuestMemory,
+    regions: &[DataRegion],
+    mut buf: &[u8],
+) -> Result<(), GuestMemoryError> {
+    for region in regions {
+        if buf.is_empty() {
+            break;
+        }
+        let (this, rest) = buf.split_at(buf.len().min(region.len as usize));
+        mem.write_at(region.addr, this)?;
+        buf = rest;
+    }
+    Ok(())
+}
+
+async fn next_work(queue: &mut VirtioQueue) -> Result<VirtioQueueCallbackWork, std::io::Error> {
+    match queue.next().await {
+        Some(work) => work,
+        None => pending().await,
+    }
+}
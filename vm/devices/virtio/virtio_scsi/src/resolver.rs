@@ -0,0 +1,149 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Resource resolver for the virtio SCSI device.
+
+use crate::ControllerState;
+use crate::PathError;
+use crate::VirtioScsiController;
+use crate::VirtioScsiDevice;
+use anyhow::Context as _;
+use async_trait::async_trait;
+use futures::StreamExt;
+use pal_async::task::Spawn;
+use scsi_core::ResolveScsiDeviceHandleParams;
+use std::sync::Arc;
+use std::sync::Weak;
+use storvsp_resources::ScsiControllerRequest;
+use storvsp_resources::ScsiDeviceAndPath;
+use storvsp_resources::ScsiPath;
+use thiserror::Error;
+use virtio::resolve::ResolvedVirtioDevice;
+use virtio::resolve::VirtioResolveInput;
+use virtio_resources::scsi::VirtioScsiHandle;
+use vm_resource::AsyncResolveResource;
+use vm_resource::ResolveError;
+use vm_resource::ResourceResolver;
+use vm_resource::declare_static_async_resolver;
+use vm_resource::kind::VirtioDeviceHandle;
+use vmcore::vm_task::VmTaskDriverSource;
+
+/// The default number of request queues.
+const DEFAULT_NUM_QUEUES: u16 = 1;
+
+/// Resolver for the virtio SCSI device.
+pub struct VirtioScsiResolver;
+
+declare_static_async_resolver! {
+    VirtioScsiResolver,
+    (VirtioDeviceHandle, VirtioScsiHandle),
+}
+
+/// An error returned by [`VirtioScsiResolver`].
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A device could not be attached.
+    #[error(transparent)]
+    Path(PathError),
+    /// A device could not be resolved.
+    #[error("failed to resolve scsi device at {path}")]
+    Device {
+        /// The device's path.
+        path: ScsiPath,
+        /// The resolve error.
+        #[source]
+        source: ResolveError,
+    },
+}
+
+#[async_trait]
+impl AsyncResolveResource<VirtioDeviceHandle, VirtioScsiHandle> for VirtioScsiResolver {
+    type Output = ResolvedVirtioDevice;
+    type Error = Error;
+
+    async fn resolve(
+        &self,
+        resolver: &ResourceResolver,
+        resource: VirtioScsiHandle,
+        input: VirtioResolveInput<'_>,
+    ) -> Result<Self::Output, Self::Error> {
+        let device = VirtioScsiDevice::new(
+            input.driver_source,
+            resource.num_queues.unwrap_or(DEFAULT_NUM_QUEUES),
+        );
+        let controller = device.controller();
+
+        for ScsiDeviceAndPath { path, device } in resource.devices {
+            let device = resolver
+                .resolve(
+                    device,
+                    ResolveScsiDeviceHandleParams {
+                        driver_source: input.driver_source,
+                    },
+                )
+                .await
+                .map_err(|err| Error::Device { path, source: err })?;
+
+            controller.attach(path, device.0).map_err(Error::Path)?;
+        }
+
+        if let Some(requests) = resource.requests {
+            input
+                .driver_source
+                .simple()
+                .spawn(
+                    "virtio-scsi-requests",
+                    handle_requests(
+                        input.driver_source.clone(),
+                        Arc::downgrade(&controller.state),
+                        resolver.clone(),
+                        requests,
+                    ),
+                )
+                .detach();
+        }
+
+        Ok(device.into())
+    }
+}
+
+async fn handle_requests(
+    driver_source: VmTaskDriverSource,
+    state: Weak<ControllerState>,
+    resolver: ResourceResolver,
+    mut requests: mesh::Receiver<ScsiControllerRequest>,
+) {
+    while let Some(req) = requests.next().await {
+        match req {
+            ScsiControllerRequest::AddDevice(rpc) => {
+                rpc.handle_failable(async |ScsiDeviceAndPath { path, device }| {
+                    let device = resolver
+                        .resolve(
+                            device,
+                            ResolveScsiDeviceHandleParams {
+                                driver_source: &driver_source,
+                            },
+                        )
+                        .await
+                        .context("failed to resolve media")?;
+
+                    if let Some(state) = state.upgrade() {
+                        VirtioScsiController { state }
+                            .attach(path, device.0)
+                            .context("failed to attach device")?;
+                    }
+                    anyhow::Ok(())
+                })
+                .await
+            }
+            ScsiControllerRequest::RemoveDevice(rpc) => rpc.handle_failable_sync(|path| {
+                if let Some(state) = state.upgrade() {
+                    VirtioScsiController { state }
+                        .remove(path)
+                        .context("failed to remove device")?;
+                }
+                anyhow::Ok(())
+            }),
+        }
+    }
+}
@@ -0,0 +1,125 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Virtio SCSI spec constants and structures (spec §5.6).
+
+use zerocopy::FromBytes;
+use zerocopy::Immutable;
+use zerocopy::IntoBytes;
+use zerocopy::KnownLayout;
+
+// Feature bits.
+pub const VIRTIO_SCSI_F_HOTPLUG: u32 = 1 << 1;
+
+/// The CDB and sense sizes until the driver configures others.
+pub const VIRTIO_SCSI_CDB_DEFAULT_SIZE: usize = 32;
+pub const VIRTIO_SCSI_SENSE_DEFAULT_SIZE: usize = 96;
+
+/// Config space offsets. Every field is le32 except `max_channel` and
+/// `max_target`, which share a dword.
+pub const CONFIG_NUM_QUEUES: u16 = 0;
+pub const CONFIG_SEG_MAX: u16 = 4;
+pub const CONFIG_MAX_SECTORS: u16 = 8;
+pub const CONFIG_CMD_PER_LUN: u16 = 12;
+pub const CONFIG_EVENT_INFO_SIZE: u16 = 16;
+pub const CONFIG_SENSE_SIZE: u16 = 20;
+pub const CONFIG_CDB_SIZE: u16 = 24;
+pub const CONFIG_MAX_CHANNEL_TARGET: u16 = 28;
+pub const CONFIG_MAX_LUN: u16 = 32;
+pub const CONFIG_LEN: u32 = 36;
+
+/// The queue indexes. Request queues start at [`REQUEST_QUEUE_BASE`].
+pub const CONTROL_QUEUE: u16 = 0;
+pub const EVENT_QUEUE: u16 = 1;
+pub const REQUEST_QUEUE_BASE: u16 = 2;
+
+// Request response codes.
+pub const VIRTIO_SCSI_S_OK: u8 = 0;
+pub const VIRTIO_SCSI_S_BAD_TARGET: u8 = 3;
+pub const VIRTIO_SCSI_S_FAILURE: u8 = 9;
+
+// Task management function response codes.
+pub const VIRTIO_SCSI_S_FUNCTION_COMPLETE: u8 = 0;
+pub const VIRTIO_SCSI_S_FUNCTION_REJECTED: u8 = 11;
+
+// Control queue request types.
+pub const VIRTIO_SCSI_T_TMF: u32 = 0;
+pub const VIRTIO_SCSI_T_AN_QUERY: u32 = 1;
+pub const VIRTIO_SCSI_T_AN_SUBSCRIBE: u32 = 2;
+
+// Task management function subtypes, of which only the resets are handled.
+pub const VIRTIO_SCSI_T_TMF_I_T_NEXUS_RESET: u32 = 4;
+pub const VIRTIO_SCSI_T_TMF_LOGICAL_UNIT_RESET: u32 = 5;
+
+// Event types.
+pub const VIRTIO_SCSI_T_TRANSPORT_RESET: u32 = 1;
+pub const VIRTIO_SCSI_T_EVENTS_MISSED: u32 = 0x8000_0000;
+
+// Transport reset event reasons.
+pub const VIRTIO_SCSI_EVT_RESET_RESCAN: u32 = 1;
+pub const VIRTIO_SCSI_EVT_RESET_REMOVED: u32 = 2;
+
+/// The first byte of every single-level LUN address.
+pub const LUN_ADDRESS_SINGLE_LEVEL: u8 = 1;
+
+/// The flat space addressing method, in the top bits of the LUN's first byte
+/// (SAM-5 §4.7.7).
+pub const LUN_ADDRESS_FLAT_SPACE: u8 = 0x40;
+
+/// `virtio_scsi_req_cmd`, up to the CDB.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct CmdReqHeader {
+    pub lun: [u8; 8],
+    pub id: u64,
+    pub task_attr: u8,
+    pub prio: u8,
+    pub crn: u8,
+}
+
+/// `virtio_scsi_resp_cmd`, up to the sense data.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, Default, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct CmdRespHeader {
+    pub sense_len: u32,
+    pub resid: u32,
+    pub status_qualifier: u16,
+    pub status: u8,
+    pub response: u8,
+}
+
+/// `virtio_scsi_ctrl_tmf`, the readable part.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct CtrlTmfReq {
+    pub ty: u32,
+    pub subtype: u32,
+    pub lun: [u8; 8],
+    pub id: u64,
+}
+
+/// `virtio_scsi_ctrl_an`, the readable part.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct CtrlAnReq {
+    pub ty: u32,
+    pub lun: [u8; 8],
+    pub event_requested: u32,
+}
+
+/// `virtio_scsi_ctrl_an`, the writable part.
+#[repr(C, packed)]
+#[derive(Debug, Copy, Clone, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct CtrlAnResp {
+    pub event_actual: u32,
+    pub response: u8,
+}
+
+/// `virtio_scsi_event`.
+#[repr(C)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, IntoBytes, Immutable, KnownLayout, FromBytes)]
+pub struct Event {
+    pub event: u32,
+    pub lun: [u8; 8],
+    pub reason: u32,
+}
@@ -0,0 +1,483 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! Tests for the virtio SCSI device.
+
+use crate::PathError;
+use crate::VirtioScsiController;
+use crate::VirtioScsiDevice;
+use crate::spec::*;
+use guestmem::GuestMemory;
+use pal_async::DefaultDriver;
+use pal_async::async_test;
+use pal_event::Event as PalEvent;
+use std::sync::Arc;
+use storvsp_resources::ScsiPath;
+use virtio::QueueResources;
+use virtio::VirtioDevice;
+use virtio::queue::QueueParams;
+use virtio::spec::VirtioDeviceFeatures;
+use virtio::spec::queue::DescriptorFlags;
+use virtio::test_helpers::init_avail_ring;
+use virtio::test_helpers::init_used_ring;
+use virtio::test_helpers::make_available;
+use virtio::test_helpers::wait_for_used;
+use virtio::test_helpers::write_descriptor;
+use vmcore::interrupt::Interrupt;
+use vmcore::vm_task::SingleDriverBackend;
+use vmcore::vm_task::VmTaskDriverSource;
+use zerocopy::FromBytes;
+use zerocopy::IntoBytes;
+
+const QUEUE_SIZE: u16 = 16;
+const QUEUE_BASE: [u64; 3] = [0x0000, 0x4000, 0x8000];
+const AVAIL_OFFSET: u64 = 0x1000;
+const USED_OFFSET: u64 = 0x2000;
+const REQUEST_ADDR: u64 = 0x10000;
+const DATA_OUT_ADDR: u64 = 0x11000;
+const RESPONSE_ADDR: u64 = 0x12000;
+const DATA_IN_ADDR: u64 = 0x14000;
+const TOTAL_MEM_SIZE: usize = 0x20000;
+
+const REQUEST_QUEUE: usize = REQUEST_QUEUE_BASE as usize;
+const RESPONSE_LEN: usize = size_of::<CmdRespHeader>() + VIRTIO_SCSI_SENSE_DEFAULT_SIZE;
+
+const CHECK_CONDITION: u8 = 2;
+
+struct Queue {
+    event: PalEvent,
+    interrupt: PalEvent,
+    avail_idx: u16,
+    used_idx: u16,
+}
+
+struct TestHarness {
+    device: VirtioScsiDevice,
+    controller: VirtioScsiController,
+    mem: GuestMemory,
+    driver: DefaultDriver,
+    queues: Vec<Queue>,
+}
+
+/// A response to a SCSI command.
+struct Response {
+    header: CmdRespHeader,
+    sense: Vec<u8>,
+    data: Vec<u8>,
+}
+
+fn disk() -> Arc<scsidisk::SimpleScsiDisk> {
+    Arc::new(scsidisk::SimpleScsiDisk::new(
+        disklayer_ram::ram_disk(1024 * 1024, false).unwrap(),
+        Default::default(),
+    ))
+}
+
+fn path(target: u8, lun: u8) -> ScsiPath {
+    ScsiPath {
+        path: 0,
+        target,
+        lun,
+    }
+}
+
+/// The address of `lun` on `target`, as the Linux driver encodes it.
+fn lun(target: u8, lun: u8) -> [u8; 8] {
+    [1, target, 0x40, lun, 0, 0, 0, 0]
+}
+
+impl TestHarness {
+    /// Creates a device with a disk at 0:0:0 and starts its queues.
+    async fn new(driver: &DefaultDriver) -> Self {
+        let driver_source = VmTaskDriverSource::new(SingleDriverBackend::new(driver.clone()));
+        let mut device = VirtioScsiDevice::new(&driver_source, 1);
+        let controller = device.controller();
+        controller.attach(path(0, 0), disk()).unwrap();
+
+        let mem = GuestMemory::allocate(TOTAL_MEM_SIZE);
+        let features = VirtioDeviceFeatures::new().with_device_specific_low(VIRTIO_SCSI_F_HOTPLUG);
+        let mut queues = Vec::new();
+        for (idx, base) in QUEUE_BASE.into_iter().enumerate() {
+            init_avail_ring(&mem, base + AVAIL_OFFSET);
+            init_used_ring(&mem, base + USED_OFFSET);
+            let queue = Queue {
+                event: PalEvent::new(),
+                interrupt: PalEvent::new(),
+                avail_idx: 0,
+                used_idx: 0,
+            };
+            device
+                .start_queue(
+                    idx as u16,
+                    QueueResources {
+                        params: QueueParams {
+                            size: QUEUE_SIZE,
+                            enable: true,
+                            desc_addr: base,
+                            avail_addr: base + AVAIL_OFFSET,
+                            used_addr: base + USED_OFFSET,
+                        },
+                        notify: Interrupt::from_event(queue.interrupt.clone()),
+                        event: queue.event.clone(),
+                        guest_memory: mem.clone(),
+                    },
+                    &features,
+                    None,
+                )
+                .await
+                .unwrap();
+            queues.push(queue);
+        }
+
+        Self {
+            device,
+            controller,
+            mem,
+            driver: driver.clone(),
+            queues,
+        }
+    }
+
+    /// Posts a descriptor chain of `(addr, len, writable)` buffers on queue
+    /// `idx`.
+    fn post(&mut self, idx: usize, chain: &[(u64, u32, bool)]) {
+        let base = QUEUE_BASE[idx];
+        for (i, &(addr, len, writable)) in chain.iter().enumerate() {
+            let i = i as u16;
+            write_descriptor(
+                &self.mem,
+                base,
+                i,
+                addr,
+                len,
+                DescriptorFlags::new()
+                    .with_next(usize::from(i) + 1 < chain.len())
+                    .with_write(writable),
+                i + 1,
+            );
+        }
+        let queue = &mut self.queues[idx];
+        make_available(
+            &self.mem,
+            base + AVAIL_OFFSET,
+            QUEUE_SIZE,
+            0,
+            &mut queue.avail_idx,
+        );
+        queue.event.signal();
+    }
+
+    /// Waits for the device to complete a descriptor chain on queue `idx`,
+    /// and returns the length it wrote.
+    async fn wait(&mut self, idx: usize) -> u32 {
+        let queue = &mut self.queues[idx];
+        let (_, len) = wait_for_used(
+            &self.driver,
+            &queue.interrupt,
+            &self.mem,
+            QUEUE_BASE[idx] + USED_OFFSET,
+            QUEUE_SIZE,
+            &mut queue.used_idx,
+        )
+        .await;
+        len
+    }
+
+    /// Sends a control queue request and returns the response.
+    async fn control(&mut self, request: &[u8], response_len: u32) -> Vec<u8> {
+        self.mem.write_at(REQUEST_ADDR, request).unwrap();
+        self.post(
+            CONTROL_QUEUE.into(),
+            &[
+                (REQUEST_ADDR, request.len() as u32, false),
+                (RESPONSE_ADDR, response_len, true),
+            ],
+        );
+        let len = self.wait(CONTROL_QUEUE.into()).await;
+        let mut response = vec![0; len as usize];
+        self.mem.read_at(RESPONSE_ADDR, &mut response).unwrap();
+        response
+    }
+
+    /// Posts an event buffer and waits for the device to fill it.
+    async fn event(&mut self) -> Event {
+        self.post(
+            EVENT_QUEUE.into(),
+            &[(RESPONSE_ADDR, size_of::<Event>() as u32, true)],
+        );
+        let len = self.wait(EVENT_QUEUE.into()).await;
+        assert_eq!(len as usize, size_of::<Event>());
+        let mut event = [0; size_of::<Event>()];
+        self.mem.read_at(RESPONSE_ADDR, &mut event).unwrap();
+        Event::read_from_bytes(&event).unwrap()
+    }
+
+    /// Sends `cdb` to `lun`, with `data_out` or a data-in buffer of
+    /// `data_in_len` bytes. If `bounce`, the data-in buffer shares a
+    /// descriptor with the response, so it is not page aligned.
+    async fn command(
+        &mut self,
+        lun: [u8; 8],
+        cdb: &[u8],
+        data_out: &[u8],
+        data_in_len: u32,
+        bounce: bool,
+    ) -> Response {
+        let mut request = [0; size_of::<CmdReqHeader>() + VIRTIO_SCSI_CDB_DEFAULT_SIZE];
+        CmdReqHeader {
+            lun,
+            id: 1,
+            task_attr: 0,
+            prio: 0,
+            crn: 0,
+        }
+        .write_to_prefix(&mut request)
+        .unwrap();
+        request[size_of::<CmdReqHeader>()..][..cdb.len()].copy_from_slice(cdb);
+        self.mem.write_at(REQUEST_ADDR, &request).unwrap();
+        self.mem.write_at(DATA_OUT_ADDR, data_out).unwrap();
+
+        let mut chain = vec![(REQUEST_ADDR, request.len() as u32, false)];
+        if !data_out.is_empty() {
+            chain.push((DATA_OUT_ADDR, data_out.len() as u32, false));
+        }
+        let data_in_addr = if bounce {
+            chain.push((RESPONSE_ADDR, RESPONSE_LEN as u32 + data_in_len, true));
+            RESPONSE_ADDR + RESPONSE_LEN as u64
+        } else {
+            chain.push((RESPONSE_ADDR, RESPONSE_LEN as u32, true));
+            if data_in_len != 0 {
+                chain.push((DATA_IN_ADDR, data_in_len, true));
+            }
+            DATA_IN_ADDR
+        };
+        self.post(REQUEST_QUEUE, &chain);
+        let len = self.wait(REQUEST_QUEUE).await as usize;
+        assert!(len >= RESPONSE_LEN);
+
+        let mut response = [0; RESPONSE_LEN];
+        self.mem.read_at(RESPONSE_ADDR, &mut response).unwrap();
+        let (header, sense) = CmdRespHeader::read_from_prefix(&response).unwrap();
+        let mut data = vec![0; len - RESPONSE_LEN];
+        self.mem.read_at(data_in_addr, &mut data).unwrap();
+        Response {
+            header,
+            sense: sense[..header.sense_len as usize].to_vec(),
+            data,
+        }
+    }
+}
+
+#[async_test]
+async fn config_space(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+    let device = &mut harness.device;
+    assert_eq!(device.traits().max_queues, 3);
+    assert_eq!(device.read_registers_u32(CONFIG_NUM_QUEUES).await, 1);
+    assert_eq!(device.read_registers_u32(CONFIG_EVENT_INFO_SIZE).await, 16);
+    assert_eq!(device.read_registers_u32(CONFIG_SENSE_SIZE).await, 96);
+    assert_eq!(device.read_registers_u32(CONFIG_CDB_SIZE).await, 32);
+    assert_eq!(
+        device.read_registers_u32(CONFIG_MAX_CHANNEL_TARGET).await,
+        255 << 16
+    );
+    assert_eq!(device.read_registers_u32(CONFIG_MAX_LUN).await, 255);
+
+    device.write_registers_u32(CONFIG_SENSE_SIZE, 32).await;
+    device.write_registers_u32(CONFIG_CDB_SIZE, 0x1000).await;
+    assert_eq!(device.read_registers_u32(CONFIG_SENSE_SIZE).await, 32);
+    assert_eq!(device.read_registers_u32(CONFIG_CDB_SIZE).await, 32);
+
+    // The transport stops the queues before resetting the device.
+    for idx in 0..3 {
+        assert!(device.stop_queue(idx).await.is_some());
+        assert!(device.stop_queue(idx).await.is_none());
+    }
+    device.reset().await;
+    assert_eq!(device.read_registers_u32(CONFIG_SENSE_SIZE).await, 96);
+}
+
+#[async_test]
+async fn inquiry(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+    let response = harness
+        .command(lun(0, 0), &[0x12, 0, 0, 0, 36, 0], &[], 36, false)
+        .await;
+    assert_eq!(response.header.response, VIRTIO_SCSI_S_OK);
+    assert_eq!(response.header.status, 0);
+    assert_eq!(response.header.resid, 0);
+    assert_eq!(response.data.len(), 36);
+    // A direct access block device.
+    assert_eq!(response.data[0], 0);
+}
+
+#[async_test]
+async fn write_read(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+    let data = (0..1024).map(|i| i as u8).collect::<Vec<_>>();
+
+    // WRITE(10) of two sectors at LBA 4.
+    let response = harness
+        .command(
+            lun(0, 0),
+            &[0x2a, 0, 0, 0, 0, 4, 0, 0, 2, 0],
+            &data,
+            0,
+            false,
+        )
+        .await;
+    assert_eq!(response.header.status, 0);
+    assert!(response.data.is_empty());
+
+    // READ(10) them back, through both the direct and bounce paths.
+    for bounce in [false, true] {
+        let response = harness
+            .command(
+                lun(0, 0),
+                &[0x28, 0, 0, 0, 0, 4, 0, 0, 2, 0],
+                &[],
+                1024,
+                bounce,
+            )
+            .await;
+        assert_eq!(response.header.status, 0);
+        assert_eq!(response.data, data);
+    }
+}
+
+#[async_test]
+async fn missing_luns(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+
+    // Targets without LUNs do not exist.
+    let response = harness.command(lun(1, 0), &[0; 6], &[], 0, false).await;
+    assert_eq!(response.header.response, VIRTIO_SCSI_S_BAD_TARGET);
+
+    // Missing LUNs on a target that exists report themselves as not present.
+    let response = harness
+        .command(lun(0, 1), &[0x12, 0, 0, 0, 36, 0], &[], 36, false)
+        .await;
+    assert_eq!(response.header.response, VIRTIO_SCSI_S_OK);
+    assert_eq!(response.data[0], 0x7f);
+
+    // And fail other commands.
+    let response = harness.command(lun(0, 1), &[0; 6], &[], 0, false).await;
+    assert_eq!(response.header.response, VIRTIO_SCSI_S_OK);
+    assert_eq!(response.header.status, CHECK_CONDITION);
+    // ILLEGAL REQUEST, LOGICAL UNIT NOT SUPPORTED.
+    assert_eq!(response.sense[2] & 0xf, 5);
+    assert_eq!(response.sense[12], 0x25);
+}
+
+#[async_test]
+async fn report_luns(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+    harness.controller.attach(path(0, 3), disk()).unwrap();
+    harness.controller.attach(path(1, 5), disk()).unwrap();
+
+    let response = harness
+        .command(
+            lun(0, 0),
+            &[0xa0, 0, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0],
+            &[],
+            64,
+            false,
+        )
+        .await;
+    assert_eq!(response.header.status, 0);
+    assert_eq!(response.header.resid, 64 - 24);
+    assert_eq!(
+        response.data,
+        [
+            [0, 0, 0, 16, 0, 0, 0, 0],
+            [0, 0, 0, 0, 0, 0, 0, 0],
+            [0, 3, 0, 0, 0, 0, 0, 0]
+        ]
+        .as_flattened()
+    );
+}
+
+#[async_test]
+async fn hotplug(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+
+    assert!(matches!(
+        harness.controller.attach(path(0, 0), disk()),
+        Err(PathError::InUse(_))
+    ));
+    assert!(matches!(
+        harness.controller.attach(
+            ScsiPath {
+                path: 1,
+                target: 0,
+                lun: 0
+            },
+            disk()
+        ),
+        Err(PathError::InvalidChannel(_))
+    ));
+
+    harness.controller.attach(path(2, 1), disk()).unwrap();
+    assert_eq!(
+        harness.event().await,
+        Event {
+            event: VIRTIO_SCSI_T_TRANSPORT_RESET,
+            lun: lun(2, 1),
+            reason: VIRTIO_SCSI_EVT_RESET_RESCAN,
+        }
+    );
+    let response = harness
+        .command(lun(2, 1), &[0x12, 0, 0, 0, 36, 0], &[], 36, false)
+        .await;
+    assert_eq!(response.data[0], 0);
+
+    harness.controller.remove(path(2, 1)).unwrap();
+    assert_eq!(
+        harness.event().await,
+        Event {
+            event: VIRTIO_SCSI_T_TRANSPORT_RESET,
+            lun: lun(2, 1),
+            reason: VIRTIO_SCSI_EVT_RESET_REMOVED,
+        }
+    );
+    let response = harness.command(lun(2, 1), &[0; 6], &[], 0, false).await;
+    assert_eq!(response.header.response, VIRTIO_SCSI_S_BAD_TARGET);
+
+    assert!(matches!(
+        harness.controller.remove(path(2, 1)),
+        Err(PathError::NotInUse(_))
+    ));
+}
+
+#[async_test]
+async fn task_management(driver: DefaultDriver) {
+    let mut harness = TestHarness::new(&driver).await;
+    let tmf = |subtype| CtrlTmfReq {
+        ty: VIRTIO_SCSI_T_TMF,
+        subtype,
+        lun: lun(0, 0),
+        id: 0,
+    };
+
+    let response = harness
+        .control(tmf(VIRTIO_SCSI_T_TMF_LOGICAL_UNIT_RESET).as_bytes(), 1)
+        .await;
+    assert_eq!(response, [VIRTIO_SCSI_S_FUNCTION_COMPLETE]);
+
+    // ABORT TASK.
+    let response = harness.control(tmf(0).as_bytes(), 1).await;
+    assert_eq!(response, [VIRTIO_SCSI_S_FUNCTION_REJECTED]);
+
+    let response = harness
+        .control(
+            CtrlAnReq {
+                ty: VIRTIO_SCSI_T_AN_QUERY,
+                lun: lun(0, 0),
+                event_requested: 0xff,
+            }
+            .as_bytes(),
+            5,
+        )
+        .await;
+    assert_eq!(response, [0, 0, 0, 0, VIRTIO_SCSI_S_OK]);
+}
@@ -25,9 +25,11 @@
 pub const VIRTIO_BLK_F_FLUSH: u32 = 1 << 9;
 /// Device exports information on optimal I/O alignment.
 pub const VIRTIO_BLK_F_TOPOLOGY: u32 = 1 << 10;
+/// Device supports more than one request queue; the count is in `num_queues`.
+pub const VIRTIO_BLK_F_MQ: u32 = 1 << 12;
 /// Device can support discard command.
 pub const VIRTIO_BLK_F_DISCARD: u32 = 1 << 13;
-/// Device can support write zeroes command (not currently advertised).
+/// Device can support write zeroes command.
 pub const VIRTIO_BLK_F_WRITE_ZEROES: u32 = 1 << 14;
 
 // Request types (spec §5.2.6).
@@ -79,8 +81,7 @@ pub struct VirtioBlkConfig {
     /// (valid if VIRTIO_BLK_F_CONFIG_WCE, which we don't negotiate).
     pub writeback: u8,
     pub unused0: u8,
-    /// Number of request queues (valid if VIRTIO_BLK_F_MQ,
-    /// which we don't negotiate). Set to 1.
+    /// Number of request queues (valid if VIRTIO_BLK_F_MQ).
     pub num_queues: u16,
     /// Maximum number of 512-byte sectors in a single discard segment
     /// (valid if VIRTIO_BLK_F_DISCARD).
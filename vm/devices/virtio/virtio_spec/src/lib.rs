@@ -115,7 +115,11 @@ pub enum VirtioDeviceType: u16 {
         BLK = 2,
         CONSOLE = 3,
         RNG = 4,
+        BALLOON = 5,
+        SCSI = 8,
         P9 = 9,
+        GPU = 16,
+        INPUT = 18,
         VSOCK = 19,
         FS = 26,
         PMEM = 27,
@@ -47,6 +47,7 @@ mod ioctl {
     #[cfg(target_arch = "x86_64")]
     ioctl_readwrite!(kvm_get_supported_hv_cpuid, KVMIO, 0xc1, kvm_cpuid2);
     ioctl_write_int_bad!(kvm_create_vcpu, request_code_none!(KVMIO, 0x41));
+    ioctl_write_ptr!(kvm_get_dirty_log, KVMIO, 0x42, kvm_dirty_log);
     ioctl_write_ptr!(
         kvm_set_user_memory_region,
         KVMIO,
@@ -248,6 +249,8 @@ pub enum Error {
     SignalMsi(#[source] nix::Error),
     #[error("SetMemoryRegion")]
     SetMemoryRegion(#[source] nix::Error),
+    #[error("GetDirtyLog")]
+    GetDirtyLog(#[source] nix::Error),
     #[error("SetMemoryAttributes")]
     SetMemoryAttributes(#[source] nix::Error),
     #[error("CreateGuestMemfd")]
@@ -898,6 +901,9 @@ pub fn request_msi(&self, msi: &kvm_msi) -> Result<()> {
 
     /// Sets or clears a userspace memory slot.
     ///
+    /// If `log_dirty` is set, KVM tracks guest writes to the slot, which can be
+    /// retrieved with [`Self::get_dirty_log`].
+    ///
     /// # Safety
     ///
     /// If `size` is nonzero, `data..data + size` must be a valid userspace
@@ -911,10 +917,16 @@ pub unsafe fn set_user_memory_region(
         size: usize,
         addr: u64,
         readonly: bool,
+        log_dirty: bool,
     ) -> Result<()> {
         let region = kvm_userspace_memory_region {
             slot,
-            flags: if readonly { KVM_MEM_READONLY } else { 0 },
+            flags: if readonly { KVM_MEM_READONLY } else { 0 }
+                | if log_dirty {
+                    KVM_MEM_LOG_DIRTY_PAGES
+                } else {
+                    0
+                },
             guest_phys_addr: addr,
             memory_size: size as u64,
             userspace_addr: data as usize as u64,
@@ -970,6 +982,32 @@ pub unsafe fn set_user_memory_region2(
         Ok(())
     }
 
+    /// Retrieves and clears the dirty page bitmap for `slot`, which must have
+    /// been registered with dirty logging enabled.
+    ///
+    /// Bit `n` of `bitmap` is set if the host page at offset `n * page_size`
+    /// within the slot was written since the last call.
+    ///
+    /// # Safety
+    ///
+    /// `bitmap` must have at least one bit for each host page in the slot,
+    /// since KVM writes the whole bitmap without checking its length.
+    pub unsafe fn get_dirty_log(&self, slot: u32, bitmap: &mut [u64]) -> Result<()> {
+        let log = kvm_dirty_log {
+            slot,
+            padding1: 0,
+            __bindgen_anon_1: kvm_dirty_log__bindgen_ty_1 {
+                dirty_bitmap: bitmap.as_mut_ptr().cast(),
+            },
+        };
+        // SAFETY: the caller guarantees that `bitmap` is large enough for the
+        // slot.
+        unsafe {
+            ioctl::kvm_get_dirty_log(self.vm.as_raw_fd(), &log).map_err(Error::GetDirtyLog)?;
+        }
+        Ok(())
+    }
+
     pub fn create_guest_memfd(&self, size: u64) -> Result<File> {
         let mut guest_memfd = kvm_create_guest_memfd {
             size,
@@ -71,6 +71,7 @@ enum GuestMemoryOperation {
     Lock,
     Subrange,
     Probe,
+    Discard,
 }
 
 impl std::fmt::Display for GuestMemoryOperation {
@@ -83,6 +84,7 @@ fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
             GuestMemoryOperation::Lock => "lock",
             GuestMemoryOperation::Subrange => "subrange",
             GuestMemoryOperation::Probe => "probe",
+            GuestMemoryOperation::Discard => "discard",
         })
     }
 }
@@ -1836,6 +1838,34 @@ fn fill_at_inner(&self, gpa: u64, val: u8, len: usize) -> Result<(), GuestMemory
         )
     }
 
+    /// Releases the host memory backing `gpa..gpa+len`, for memory the guest
+    /// has promised not to touch (e.g. pages handed to a balloon device).
+    ///
+    /// Only whole host pages are released, and afterwards they read as zero.
+    /// Returns `Ok(false)` without doing anything if the memory is not mapped
+    /// into this process or the platform cannot release it.
+    pub fn discard_at(&self, gpa: u64, len: u64) -> Result<bool, GuestMemoryError> {
+        self.with_op(Some((gpa, len)), GuestMemoryOperation::Discard, || {
+            let Some(mapping) = self.mapping_range(AccessType::Write, gpa, len as usize)? else {
+                return Ok(false);
+            };
+            #[cfg(unix)]
+            {
+                // SAFETY: `mapping_range` guarantees the range is inside the
+                // reserved VA range, and the caller guarantees the contents
+                // are no longer needed.
+                unsafe { sparse_mmap::unix::discard(mapping, len as usize) }
+                    .map_err(|err| GuestMemoryBackingError::other(gpa, err))?;
+                Ok(true)
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = mapping;
+                Ok(false)
+            }
+        })
+    }
+
     /// Reads from guest memory into `dest..dest+len`.
     ///
     /// # Safety
@@ -2940,6 +2970,24 @@ fn test_fault() {
         gm.write_plain::<u8>(PAGE_SIZE64 * 3 - 1, &0).unwrap_err();
     }
 
+    #[cfg(unix)]
+    #[test]
+    fn test_discard() {
+        let mapping = create_test_mapping();
+        let gm = GuestMemory::new("test", mapping);
+        gm.fill_at(0, 0xaa, SIZE_1MB).unwrap();
+
+        // Use 64K alignment so that whole host pages are covered on any host.
+        assert!(gm.discard_at(0x10000, 0x10000).unwrap());
+        assert_eq!(gm.read_plain::<u8>(0x10000).unwrap(), 0);
+        assert_eq!(gm.read_plain::<u8>(0x1ffff).unwrap(), 0);
+        assert_eq!(gm.read_plain::<u8>(0xffff).unwrap(), 0xaa);
+        assert_eq!(gm.read_plain::<u8>(0x20000).unwrap(), 0xaa);
+
+        gm.discard_at(4 * SIZE_1MB as u64, PAGE_SIZE as u64)
+            .unwrap_err();
+    }
+
     #[cfg(feature = "bitmap")]
     #[test]
     fn test_zero_length_access_at_offset_zero() {
@@ -314,6 +314,16 @@ unsafe fn io_uring_submit(
         unsafe { self.inner.driver().io_uring_submit(sqe) }
     }
 
+    #[cfg(target_os = "linux")]
+    unsafe fn io_uring_submit_fixed<'a>(
+        &'a self,
+        sqe: pal_async::io_uring::Entry,
+        buf: &'a pal_async::io_uring::FixedBuffer,
+    ) -> Pin<Box<dyn Future<Output = std::io::Result<i32>> + Send + 'a>> {
+        // SAFETY: passthru from caller
+        unsafe { self.inner.driver().io_uring_submit_fixed(sqe, buf) }
+    }
+
     #[cfg(target_os = "macos")]
     fn new_dyn_process_wait(
         &self,
@@ -583,6 +593,30 @@ unsafe fn io_uring_submit(
             })
         }
 
+        #[cfg(target_os = "linux")]
+        unsafe fn io_uring_submit_fixed<'a>(
+            &'a self,
+            sqe: pal_async::io_uring::Entry,
+            buf: &'a pal_async::io_uring::FixedBuffer,
+        ) -> std::pin::Pin<Box<dyn Future<Output = std::io::Result<i32>> + Send + 'a>> {
+            use pal_async::io_uring::{IoUringDriver, IoUringSubmit};
+            Box::pin(async move {
+                // As in `io_uring_submit`, clone the current driver so that
+                // the fixed buffer is registered with the same ring the SQE
+                // is submitted to.
+                let driver = CURRENT_DRIVER.with(|cell| cell.borrow(|driver| driver.cloned()));
+                let driver = driver.as_ref().unwrap_or(&self.default);
+                // SAFETY: passthru from caller
+                unsafe {
+                    driver
+                        .io_uring_submitter()
+                        .ok_or(std::io::ErrorKind::Unsupported)?
+                        .submit_fixed(sqe, buf)
+                        .await
+                }
+            })
+        }
+
         #[cfg(target_os = "macos")]
         fn new_dyn_process_wait(
             &self,
@@ -158,6 +158,12 @@ pub async fn handle_gdb(&mut self, req: DebugRequest) {
                 })
                 .await
             }
+            DebugRequest::TranslateGva(rpc) => {
+                rpc.handle_failable(async |(vp, gva)| {
+                    self.vp_set.translate_gva(VpIndex::new(vp), gva).await
+                })
+                .await
+            }
         }
     }
 }
@@ -1084,6 +1084,17 @@ pub async fn write_virtual_memory(
             .await
             .map_err(RunnerGoneError)?
     }
+
+    pub async fn translate_gva(&self, vp: VpIndex, gva: u64) -> anyhow::Result<u64> {
+        self.vps[vp.index() as usize]
+            .send
+            .call(
+                |x| VpEvent::State(StateEvent::Debug(DebugEvent::TranslateGva(x))),
+                gva,
+            )
+            .await
+            .map_err(RunnerGoneError)?
+    }
 }
 
 #[derive(Debug)]
@@ -1115,6 +1126,7 @@ enum DebugEvent {
     GetVpState(Rpc<(), anyhow::Result<Box<DebuggerVpState>>>),
     ReadVirtualMemory(Rpc<(u64, usize), anyhow::Result<Vec<u8>>>),
     WriteVirtualMemory(Rpc<(u64, Vec<u8>), anyhow::Result<()>>),
+    TranslateGva(Rpc<u64, anyhow::Result<u64>>),
 }
 
 /// An object used to dispatch a virtual processor.
@@ -1385,6 +1397,16 @@ fn state_event(&mut self, vp: &mut dyn ControlVp, event: StateEvent) {
                     )?;
                     Ok(())
                 }),
+                DebugEvent::TranslateGva(rpc) => rpc.handle_sync(|gva| {
+                    vp_state::translate_gva(
+                        self.inner.vtl_guest_memory[0]
+                            .as_ref()
+                            .context("no guest memory for vtl0")?,
+                        vp.debug(),
+                        Vtl::Vtl0,
+                        gva,
+                    )
+                }),
             },
         }
     }
@@ -1398,7 +1420,7 @@ mod vp_state {
     use hvdef::Vtl;
     use vmm_core_defs::debug_rpc::DebuggerVpState;
 
-    fn translate_gva(
+    pub(super) fn translate_gva(
         guest_memory: &GuestMemory,
         debug: &mut dyn DebugVp,
         vtl: Vtl,
@@ -427,6 +427,11 @@ fn supports_vtl_scrub(&self) -> Option<&dyn ScrubVtl<Error = <Self as Hv1>::Erro
         None
     }
 
+    /// Returns a trait object to track guest writes to memory, if supported.
+    fn supports_dirty_log(&self) -> Option<&dyn DirtyLog<Error = <Self as Hv1>::Error>> {
+        None
+    }
+
     /// Returns an interface for registering MMIO doorbells for this partition.
     ///
     /// Not all partitions support this.
@@ -541,6 +546,48 @@ pub trait ScrubVtl {
     fn scrub(&self, vtl: Vtl) -> Result<(), Self::Error>;
 }
 
+/// Extension trait for tracking which guest pages have been written.
+///
+/// Only writes made by the guest's processors are tracked. Writes made by the
+/// host to guest memory, such as by emulated devices, are not; callers that
+/// need a complete view of modified memory must account for those separately
+/// (for example, by rescanning memory while the VM is paused).
+///
+/// This is implemented by the KVM and mshv backends. WHP only tracks writes to
+/// ranges mapped with dirty tracking, which its memory mappers do not yet
+/// support, and Hypervisor.framework has no equivalent.
+///
+/// Ranges are in guest physical addresses and must be aligned to
+/// [`hvdef::HV_PAGE_SIZE`]. Backends may track memory at a coarser granularity
+/// than requested, so enabling or disabling tracking for one range may affect
+/// neighboring ranges that share a mapping.
+pub trait DirtyLog {
+    type Error: std::error::Error;
+
+    /// Starts tracking writes to the guest RAM in `range`.
+    ///
+    /// Pages that are not mapped to RAM are ignored.
+    fn enable_dirty_log(&self, range: MemoryRange) -> Result<(), Self::Error>;
+
+    /// Stops tracking writes to the guest RAM in `range`.
+    fn disable_dirty_log(&self, range: MemoryRange) -> Result<(), Self::Error>;
+
+    /// Fetches and clears the dirty bitmap for `range`.
+    ///
+    /// On return, bit `n % 64` of `bitmap[n / 64]` is set if the page at
+    /// `range.start() + n * HV_PAGE_SIZE` was written since tracking was
+    /// enabled or since the page's bit was last fetched. `bitmap` must have at
+    /// least one bit for each page in `range`; any further bits are cleared.
+    ///
+    /// Backends may conservatively report pages that were not written, for
+    /// example if a mapping changed while tracking was enabled.
+    fn get_and_clear_dirty_bitmap(
+        &self,
+        range: MemoryRange,
+        bitmap: &mut [u64],
+    ) -> Result<(), Self::Error>;
+}
+
 /// Provides access to partition state for save, restore, and reset.
 ///
 /// This is not part of [`Partition`] because some scenarios do not require such
@@ -891,6 +891,12 @@ fn supports_reset(
         None
     }
 
+    fn supports_dirty_log(
+        &self,
+    ) -> Option<&dyn virt::DirtyLog<Error = <Self as virt::Hv1>::Error>> {
+        Some(self)
+    }
+
     fn caps(&self) -> &PartitionCapabilities {
         &self.inner.caps
     }
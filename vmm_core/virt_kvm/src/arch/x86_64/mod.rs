@@ -726,6 +726,10 @@ fn supports_reset(&self) -> Option<&dyn ResetPartition<Error = Self::Error>> {
         Some(self)
     }
 
+    fn supports_dirty_log(&self) -> Option<&dyn virt::DirtyLog<Error = Self::Error>> {
+        Some(self)
+    }
+
     fn supports_initial_page_acceptance(
         &self,
     ) -> Option<&dyn virt::AcceptInitialPages<Error = <Self as Hv1>::Error>> {
@@ -8,8 +8,10 @@
 //! selects the appropriate backing when a range is mapped, validates private
 //! launch ranges, and discards stale contents when ownership changes.
 
+use crate::KvmError;
 use crate::KvmPartition;
 use crate::KvmPartitionInner;
+use hvdef::HV_PAGE_SIZE;
 use inspect::Inspect;
 use memory_range::MemoryRange;
 #[cfg(guest_arch = "x86_64")]
@@ -37,6 +39,10 @@ pub enum MemoryError {
     DiscardPrivateBacking(#[source] std::io::Error),
     #[error("unsupported isolation configuration: {0}")]
     UnsupportedIsolationConfiguration(&'static str),
+    #[error("dirty page logging is not supported for guest_memfd memory slots")]
+    DirtyLogGuestMemfd,
+    #[error("dirty bitmap is too small for the requested range")]
+    DirtyBitmapTooSmall,
 }
 
 #[derive(Debug, Inspect)]
@@ -46,6 +52,11 @@ pub(crate) struct KvmMemoryRange {
     range: MemoryRange,
     guest_memfd_offset: Option<u64>,
     private_attributes_set: bool,
+    readonly: bool,
+    /// Dirty bits retrieved from KVM but not yet reported, one per host page,
+    /// if dirty page logging is enabled for the slot.
+    #[inspect(rename = "dirty_log", with = "Option::is_some")]
+    dirty_log: Option<Vec<u64>>,
 }
 
 unsafe impl Sync for KvmMemoryRange {}
@@ -187,6 +198,13 @@ unsafe fn map_region(
             state.ranges.push(None);
         }
         let slot_to_use = slot_to_use.unwrap();
+        // Keep logging writes to a moved slot, since the caller may still be
+        // tracking the range.
+        let log_dirty = !readonly
+            && matches!(backing, KvmMemoryBacking::Userspace)
+            && state.ranges[slot_to_use]
+                .as_ref()
+                .is_some_and(|range| range.dirty_log.is_some());
         if let Some(existing_range) = &state.ranges[slot_to_use] {
             if existing_range.guest_memfd_offset.is_some()
                 && existing_range.range.len() != size as u64
@@ -218,6 +236,7 @@ unsafe fn map_region(
                         size,
                         addr,
                         readonly,
+                        log_dirty,
                     )?
                 };
                 (None, false)
@@ -262,6 +281,9 @@ unsafe fn map_region(
             range,
             guest_memfd_offset,
             private_attributes_set,
+            readonly,
+            // The whole slot may have new contents, so report all of it.
+            dirty_log: log_dirty.then(|| vec![!0; dirty_log_words(size as u64)]),
         });
         Ok(())
     }
@@ -307,12 +329,104 @@ unsafe fn clear_slot(&self, slot: usize, guest_memfd_backed: bool) -> Result<(),
         } else {
             // SAFETY: the caller ensures clearing this slot is valid.
             unsafe {
-                self.kvm
-                    .set_user_memory_region(slot as u32, std::ptr::null_mut(), 0, 0, false)
+                self.kvm.set_user_memory_region(
+                    slot as u32,
+                    std::ptr::null_mut(),
+                    0,
+                    0,
+                    false,
+                    false,
+                )
             }
         }
     }
 
+    /// Enables or disables dirty page logging for the writable slots
+    /// overlapping `range`.
+    pub(crate) fn set_dirty_log(
+        &self,
+        range: MemoryRange,
+        enable: bool,
+    ) -> Result<(), MemoryError> {
+        let mut state = self.memory.lock();
+        for (slot, entry) in state.ranges.iter_mut().enumerate() {
+            let Some(kvm_range) = entry else { continue };
+            if !kvm_range.range.overlaps(&range)
+                || kvm_range.readonly
+                || kvm_range.dirty_log.is_some() == enable
+            {
+                continue;
+            }
+            if kvm_range.guest_memfd_offset.is_some() {
+                return Err(MemoryError::DirtyLogGuestMemfd);
+            }
+            // SAFETY: this re-registers the slot's existing mapping, which the
+            // caller of `map_region` keeps valid while it is mapped.
+            unsafe {
+                self.kvm.set_user_memory_region(
+                    slot as u32,
+                    kvm_range.host_addr,
+                    kvm_range.range.len() as usize,
+                    kvm_range.range.start(),
+                    false,
+                    enable,
+                )?;
+            }
+            kvm_range.dirty_log = enable.then(|| vec![0; dirty_log_words(kvm_range.range.len())]);
+        }
+        Ok(())
+    }
+
+    /// Fetches and clears the dirty bits for `range`, one per 4KiB page.
+    ///
+    /// KVM reports dirty pages at host page granularity, and retrieving a
+    /// slot's log clears it for the whole slot, so bits for the parts of a
+    /// slot outside `range` are kept until they are requested. Writable slots
+    /// without logging enabled are reported as entirely dirty.
+    pub(crate) fn get_and_clear_dirty_bitmap(
+        &self,
+        range: MemoryRange,
+        bitmap: &mut [u64],
+    ) -> Result<(), MemoryError> {
+        if (bitmap.len() as u64) * 64 < range.page_count_4k() {
+            return Err(MemoryError::DirtyBitmapTooSmall);
+        }
+        bitmap.fill(0);
+        let host_page_size = host_page_size();
+        let mut state = self.memory.lock();
+        for (slot, entry) in state.ranges.iter_mut().enumerate() {
+            let Some(kvm_range) = entry else { continue };
+            let overlap = kvm_range.range.intersection(&range);
+            if overlap.is_empty() || kvm_range.readonly {
+                continue;
+            }
+            let first_bit = ((overlap.start() - range.start()) / HV_PAGE_SIZE) as usize;
+            let Some(log) = &mut kvm_range.dirty_log else {
+                for n in first_bit..first_bit + overlap.page_count_4k() as usize {
+                    bitmap[n / 64] |= 1 << (n % 64);
+                }
+                continue;
+            };
+
+            let mut fetched = vec![0; log.len()];
+            // SAFETY: `fetched` has a bit for each host page in the slot.
+            unsafe { self.kvm.get_dirty_log(slot as u32, &mut fetched)? };
+            for (log, fetched) in log.iter_mut().zip(&fetched) {
+                *log |= fetched;
+            }
+
+            take_dirty_bits(
+                log,
+                host_page_size,
+                overlap.start() - kvm_range.range.start(),
+                overlap.len(),
+                bitmap,
+                first_bit,
+            );
+        }
+        Ok(())
+    }
+
     /// Applies a guest-requested SNP shared/private state change.
     ///
     /// `page_count` is always expressed in 4-KiB pages by
@@ -586,6 +700,26 @@ fn memory_mapper(&self, vtl: hvdef::Vtl) -> Arc<dyn virt::PartitionMemoryMap> {
     }
 }
 
+impl virt::DirtyLog for KvmPartition {
+    type Error = KvmError;
+
+    fn enable_dirty_log(&self, range: MemoryRange) -> Result<(), KvmError> {
+        Ok(self.inner.set_dirty_log(range, true)?)
+    }
+
+    fn disable_dirty_log(&self, range: MemoryRange) -> Result<(), KvmError> {
+        Ok(self.inner.set_dirty_log(range, false)?)
+    }
+
+    fn get_and_clear_dirty_bitmap(
+        &self,
+        range: MemoryRange,
+        bitmap: &mut [u64],
+    ) -> Result<(), KvmError> {
+        Ok(self.inner.get_and_clear_dirty_bitmap(range, bitmap)?)
+    }
+}
+
 // TODO: figure out a better abstraction that works for both KVM and WHP.
 impl virt::PartitionMemoryMap for KvmPartitionInner {
     unsafe fn map_range(
@@ -632,6 +766,44 @@ fn unmap_range(&self, addr: u64, size: u64) -> anyhow::Result<()> {
     }
 }
 
+fn host_page_size() -> u64 {
+    // SAFETY: sysconf has no safety requirements.
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as u64 }
+}
+
+/// Moves the dirty bits for `len` bytes at `slot_offset` within a slot from
+/// `log`, which has one bit per host page, into `bitmap` starting at
+/// `first_bit`, with one bit per 4KiB page.
+///
+/// Only host pages entirely within the range are cleared in `log`, so that the
+/// rest of a partially covered page is still reported later.
+fn take_dirty_bits(
+    log: &mut [u64],
+    host_page_size: u64,
+    slot_offset: u64,
+    len: u64,
+    bitmap: &mut [u64],
+    first_bit: usize,
+) {
+    for i in 0..len / HV_PAGE_SIZE {
+        let host_page = ((slot_offset + i * HV_PAGE_SIZE) / host_page_size) as usize;
+        if log[host_page / 64] & (1 << (host_page % 64)) != 0 {
+            let n = first_bit + i as usize;
+            bitmap[n / 64] |= 1 << (n % 64);
+        }
+    }
+    let clear_start = slot_offset.div_ceil(host_page_size);
+    let clear_end = (slot_offset + len) / host_page_size;
+    for host_page in clear_start as usize..clear_end as usize {
+        log[host_page / 64] &= !(1 << (host_page % 64));
+    }
+}
+
+/// Returns the number of words in a dirty bitmap for a slot of `len` bytes.
+fn dirty_log_words(len: u64) -> usize {
+    len.div_ceil(host_page_size()).div_ceil(64) as usize
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -748,6 +920,8 @@ fn private_memory_range_resolves_hva_offset() {
             range: range(0x1000, 0x5000),
             guest_memfd_offset: Some(0),
             private_attributes_set: true,
+            readonly: false,
+            dirty_log: None,
         })];
 
         let resolved = private_memory_range_from_slots(range(0x3000, 0x5000), &slots).unwrap();
@@ -765,6 +939,8 @@ fn private_memory_range_rejects_non_private_or_non_guest_memfd_slots() {
             range: range(0x1000, 0x5000),
             guest_memfd_offset: None,
             private_attributes_set: true,
+            readonly: false,
+            dirty_log: None,
         })];
         assert!(matches!(
             private_memory_range_from_slots(range(0x1000, 0x2000), &userspace_slots),
@@ -776,6 +952,8 @@ fn private_memory_range_rejects_non_private_or_non_guest_memfd_slots() {
             range: range(0x1000, 0x5000),
             guest_memfd_offset: Some(0),
             private_attributes_set: false,
+            readonly: false,
+            dirty_log: None,
         })];
         assert!(matches!(
             private_memory_range_from_slots(range(0x1000, 0x2000), &shared_slots),
@@ -796,12 +974,16 @@ fn guest_memfd_segments_cover_adjacent_unordered_slots() {
                 range: range(0x3000, 0x5000),
                 guest_memfd_offset: Some(0x8000),
                 private_attributes_set: false,
+                readonly: false,
+                dirty_log: None,
             }),
             Some(KvmMemoryRange {
                 host_addr: first_host_addr,
                 range: range(0x1000, 0x3000),
                 guest_memfd_offset: Some(0x4000),
                 private_attributes_set: true,
+                readonly: false,
+                dirty_log: None,
             }),
         ];
 
@@ -835,12 +1017,16 @@ fn guest_memfd_segments_reject_incomplete_coverage() {
                 range: range(0x1000, 0x2000),
                 guest_memfd_offset: Some(0),
                 private_attributes_set: true,
+                readonly: false,
+                dirty_log: None,
             }),
             Some(KvmMemoryRange {
                 host_addr: host_addr.wrapping_add(0x2000),
                 range: range(0x3000, 0x4000),
                 guest_memfd_offset: Some(0x2000),
                 private_attributes_set: true,
+                readonly: false,
+                dirty_log: None,
             }),
         ];
         assert!(matches!(
@@ -853,6 +1039,8 @@ fn guest_memfd_segments_reject_incomplete_coverage() {
             range: range(0x1000, 0x4000),
             guest_memfd_offset: None,
             private_attributes_set: false,
+            readonly: false,
+            dirty_log: None,
         })];
         assert!(matches!(
             guest_memfd_range_segments(range(0x1000, 0x4000), &userspace_slot),
@@ -871,12 +1059,16 @@ fn guest_memfd_segments_reject_overlapping_slots() {
                 range: range(0x1000, 0x3000),
                 guest_memfd_offset: Some(0),
                 private_attributes_set: true,
+                readonly: false,
+                dirty_log: None,
             }),
             Some(KvmMemoryRange {
                 host_addr: host_addr.wrapping_add(0x1000),
                 range: range(0x2000, 0x4000),
                 guest_memfd_offset: Some(0x1000),
                 private_attributes_set: true,
+                readonly: false,
+                dirty_log: None,
             }),
         ];
 
@@ -885,4 +1077,32 @@ fn guest_memfd_segments_reject_overlapping_slots() {
             Err(MemoryError::InvalidMapGpaRange)
         ));
     }
+
+    #[test]
+    fn dirty_bits_4k_host_pages() {
+        let mut log = vec![0b1010_0110, 1 << 63];
+        let mut bitmap = [0; 2];
+        // Take pages 1..5 and 127 of the slot into bits 60.. of the bitmap.
+        take_dirty_bits(&mut log, 0x1000, 0x1000, 0x4000, &mut bitmap, 60);
+        take_dirty_bits(&mut log, 0x1000, 127 * 0x1000, 0x1000, &mut bitmap, 0);
+        assert_eq!(bitmap, [(0b11 << 60) | 1, 0]);
+        assert_eq!(log, [0b1010_0000, 0]);
+    }
+
+    #[test]
+    fn dirty_bits_large_host_pages() {
+        // Host pages 0 and 2 of 16KiB are dirty.
+        let mut log = vec![0b101];
+        let mut bitmap = [0];
+        // Take 4KiB pages 2..10, which partially cover host pages 0 and 2.
+        take_dirty_bits(&mut log, 0x4000, 0x2000, 0x8000, &mut bitmap, 0);
+        assert_eq!(bitmap, [0b1100_0011]);
+        // Only the fully covered host page 1 was cleared.
+        assert_eq!(log, [0b101]);
+
+        let mut bitmap = [0];
+        take_dirty_bits(&mut log, 0x4000, 0, 0xc000, &mut bitmap, 0);
+        assert_eq!(bitmap, [0b1111_0000_1111]);
+        assert_eq!(log, [0]);
+    }
 }
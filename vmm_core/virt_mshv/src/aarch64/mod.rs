@@ -216,6 +216,10 @@ fn supports_reset(&self) -> Option<&dyn virt::ResetPartition<Error = Error>> {
         Some(self)
     }
 
+    fn supports_dirty_log(&self) -> Option<&dyn virt::DirtyLog<Error = Error>> {
+        Some(self)
+    }
+
     fn doorbell_registration(
         self: &Arc<Self>,
         _minimum_vtl: Vtl,
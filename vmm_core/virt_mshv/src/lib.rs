@@ -25,6 +25,7 @@
 use guestmem::GuestMemory;
 use hv1_emulator::message_queues::MessageQueues;
 use hvdef::HV_PAGE_SHIFT;
+use hvdef::HV_PAGE_SIZE;
 use hvdef::HvDeliverabilityNotificationsRegister;
 use hvdef::HvError;
 use hvdef::HvMessage;
@@ -35,6 +36,9 @@
 use hvdef::hypercall::HvRegisterAssoc;
 use inspect::Inspect;
 use inspect::InspectMut;
+use memory_range::MemoryRange;
+use mshv_bindings::MSHV_GPAP_ACCESS_OP_CLEAR;
+use mshv_bindings::MSHV_GPAP_ACCESS_OP_SET;
 use mshv_bindings::MSHV_SET_MEM_BIT_EXECUTABLE;
 use mshv_bindings::MSHV_SET_MEM_BIT_WRITABLE;
 use mshv_bindings::mshv_install_intercept;
@@ -690,6 +694,12 @@ enum ErrorInner {
     ResetState(#[source] Box<virt::state::StateError<Error>>),
     #[error("install intercept failed")]
     InstallIntercept(#[source] KernelError),
+    #[error("failed to update dirty page tracking")]
+    DirtyLog(#[source] KernelError),
+    #[error("failed to get dirty page state")]
+    GetDirtyLog(#[source] KernelError),
+    #[error("dirty bitmap too small for the range")]
+    DirtyBitmapTooSmall,
     #[cfg(guest_arch = "x86_64")]
     #[error("failed to register cpuid override")]
     RegisterCpuid(#[source] KernelError),
@@ -794,6 +804,105 @@ fn access_state(&self, vtl: Vtl) -> Self::StateAccess<'_> {
 #[derive(Debug, Default)]
 struct MshvMemoryRangeState {
     ranges: Vec<Option<mshv_user_mem_region>>,
+    /// The ranges with dirty page tracking enabled. The hypervisor tracks
+    /// writes to the whole partition while this is not empty.
+    dirty_log: Vec<MemoryRange>,
+}
+
+impl MshvMemoryRangeState {
+    /// Returns the writable regions, as guest physical address ranges.
+    fn writable_ranges(&self) -> impl Iterator<Item = MemoryRange> + '_ {
+        self.ranges.iter().flatten().filter_map(|region| {
+            (region.flags & set_bits!(u8, MSHV_SET_MEM_BIT_WRITABLE) != 0).then(|| {
+                let start = region.guest_pfn << HV_PAGE_SHIFT;
+                MemoryRange::new(start..start + region.size)
+            })
+        })
+    }
+}
+
+impl virt::DirtyLog for MshvPartition {
+    type Error = Error;
+
+    fn enable_dirty_log(&self, range: MemoryRange) -> Result<(), Error> {
+        let mut state = self.inner.memory.lock();
+        if state.dirty_log.is_empty() {
+            self.inner
+                .vmfd
+                .enable_dirty_page_tracking()
+                .map_err(|e| ErrorInner::DirtyLog(e.into()))?;
+        }
+        state.dirty_log.push(range);
+        Ok(())
+    }
+
+    fn disable_dirty_log(&self, range: MemoryRange) -> Result<(), Error> {
+        let mut state = self.inner.memory.lock();
+        let Some(index) = state.dirty_log.iter().position(|r| *r == range) else {
+            return Ok(());
+        };
+        if state.dirty_log.len() == 1 {
+            // The hypervisor requires the dirty bits to be set for all memory
+            // before tracking is disabled.
+            for region in state.writable_ranges() {
+                self.inner
+                    .vmfd
+                    .get_dirty_log(
+                        region.start() >> HV_PAGE_SHIFT,
+                        region.len() as usize,
+                        MSHV_GPAP_ACCESS_OP_SET as u8,
+                    )
+                    .map_err(|e| ErrorInner::DirtyLog(e.into()))?;
+            }
+            self.inner
+                .vmfd
+                .disable_dirty_page_tracking()
+                .map_err(|e| ErrorInner::DirtyLog(e.into()))?;
+        }
+        state.dirty_log.swap_remove(index);
+        Ok(())
+    }
+
+    fn get_and_clear_dirty_bitmap(
+        &self,
+        range: MemoryRange,
+        bitmap: &mut [u64],
+    ) -> Result<(), Error> {
+        if (bitmap.len() as u64) * 64 < range.page_count_4k() {
+            return Err(ErrorInner::DirtyBitmapTooSmall.into());
+        }
+        bitmap.fill(0);
+        let state = self.inner.memory.lock();
+        for region in state.writable_ranges() {
+            let overlap = region.intersection(&range);
+            if overlap.is_empty() {
+                continue;
+            }
+            let first_bit = ((overlap.start() - range.start()) / HV_PAGE_SIZE) as usize;
+            let page_count = overlap.page_count_4k() as usize;
+            if state.dirty_log.is_empty() {
+                // Without tracking, any page may have been written.
+                for n in first_bit..first_bit + page_count {
+                    bitmap[n / 64] |= 1 << (n % 64);
+                }
+                continue;
+            }
+            let dirty = self
+                .inner
+                .vmfd
+                .get_dirty_log(
+                    overlap.start() >> HV_PAGE_SHIFT,
+                    overlap.len() as usize,
+                    MSHV_GPAP_ACCESS_OP_CLEAR as u8,
+                )
+                .map_err(|e| ErrorInner::GetDirtyLog(e.into()))?;
+            for n in (0..page_count).filter(|n| dirty[n / 64] & (1 << (n % 64)) != 0) {
+                let bit = first_bit + n;
+                bitmap[bit / 64] |= 1 << (bit % 64);
+            }
+        }
+        Ok(())
+    }
 }
 
 impl virt::PartitionMemoryMapper for MshvPartition {
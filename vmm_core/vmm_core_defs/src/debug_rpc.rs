@@ -36,6 +36,9 @@ pub enum DebugRequest {
     ReadMemory(FailableRpc<(GuestAddress, usize), Vec<u8>>),
     /// Write to the specified GPA from the guest.
     WriteMemory(FailableRpc<(GuestAddress, Vec<u8>), ()>),
+    /// Translate the specified vp's virtual address to a physical address
+    /// through its current page tables.
+    TranslateGva(FailableRpc<(u32, u64), u64>),
 }
 
 /// Register state for a VP.
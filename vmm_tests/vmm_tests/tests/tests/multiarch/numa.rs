@@ -372,7 +372,9 @@ async fn pcie_device_numa_affinity(
                             )
                             .into_resource(),
                             read_only: false,
+                            zoned: None,
                         }],
+                        disk_pool: Vec::new(),
                         requests: None,
                     }
                     .into_resource(),
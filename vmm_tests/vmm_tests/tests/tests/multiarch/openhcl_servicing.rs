@@ -1373,6 +1373,7 @@ async fn nvme_fused_keepalive_servicing(
                         sector_size: None,
                     })
                     .into_resource(),
+                    zoned: None,
                 }],
                 fault_config,
                 enable_tdisp_tests: false,
@@ -1604,6 +1605,7 @@ async fn create_keepalive_test_config_default(
                                 sector_size: None,
                             })
                             .into_resource(),
+                            zoned: None,
                         }],
                         fault_config: fault_configuration,
                         enable_tdisp_tests: false,
@@ -1718,6 +1720,7 @@ async fn create_keepalive_test_config_custom(
                                 sector_size: None,
                             })
                             .into_resource(),
+                            zoned: None,
                         }],
                         fault_config: fault_configuration,
                         enable_tdisp_tests: false,
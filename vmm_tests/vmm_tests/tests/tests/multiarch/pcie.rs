@@ -365,6 +365,7 @@ async fn pcie_hotplug(
         msix_count: 2,
         max_io_queues: 1,
         namespaces: vec![],
+        disk_pool: Vec::new(),
         requests: None,
     });
     vm.add_pcie_device("s0rc0rp0".into(), nvme_resource).await?;
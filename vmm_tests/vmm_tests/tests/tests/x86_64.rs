@@ -238,6 +238,7 @@ async fn vpci_filter(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyhow::Res
                             msix_count: 1,
                             max_io_queues: 1,
                             namespaces: Vec::new(),
+                            disk_pool: Vec::new(),
                             requests: None,
                         }
                         .into_resource(),
@@ -359,6 +360,7 @@ async fn virtio_blk_device(config: PetriVmBuilder<OpenVmmPetriBackend>) -> anyho
                     VirtioBlkHandle {
                         disk: disk_resource,
                         read_only: false,
+                        num_queues: None,
                     }
                     .into_resource(),
                 ));
@@ -607,6 +609,7 @@ async fn snapshot_save_to_disk(
         vp_count: 2,
         page_size: 4096,
         architecture: "x86_64".to_string(),
+        parent: None,
     };
     openvmm_helpers::snapshot::write_snapshot(&snap_dir, &manifest, &saved_state_bytes, &mem_path)?;
 
@@ -72,7 +72,9 @@ pub(crate) fn new_test_vtl2_nvme_device(
                 nsid,
                 disk: layer.into_resource(),
                 read_only: false,
+                zoned: None,
             }],
+            disk_pool: Vec::new(),
             requests: None,
         }
         .into_resource(),
@@ -803,8 +805,10 @@ const fn disk_sectors(index: u32) -> u64 {
                                 })
                                 .into_resource(),
                                 read_only: false,
+                                zoned: None,
                             })
                             .collect(),
+                        disk_pool: Vec::new(),
                         requests: None,
                     }
                     .into_resource(),
@@ -6,6 +6,7 @@
 use gdbstub::common::Tid;
 use mesh::rpc::RpcSend;
 use std::num::NonZeroUsize;
+use std::time::Duration;
 use vmm_core_defs::debug_rpc::DebugRequest;
 use vmm_core_defs::debug_rpc::DebugStopReason;
 use vmm_core_defs::debug_rpc::GuestAddress;
@@ -24,22 +25,35 @@ pub struct VmProxy {
     req_chan: mesh::Sender<DebugRequest>,
     stop_chan: Option<mesh::OneshotReceiver<DebugStopReason>>,
 
+    inspect: Option<mesh::Sender<inspect::Deferred>>,
+
     pub vps: Box<[Vp]>,
     pub breakpoints: [Option<HardwareBreakpoint>; 4],
 }
 
 impl VmProxy {
-    pub fn new(req_chan: mesh::Sender<DebugRequest>, vp_count: u32) -> Self {
+    pub fn new(
+        req_chan: mesh::Sender<DebugRequest>,
+        vp_count: u32,
+        inspect: Option<mesh::Sender<inspect::Deferred>>,
+    ) -> Self {
         Self {
             req_chan,
+            inspect,
             vps: vec![Vp::default(); vp_count as usize].into(),
             stop_chan: None,
             breakpoints: [None; 4],
         }
     }
 
-    pub fn into_params(self) -> (mesh::Sender<DebugRequest>, u32) {
-        (self.req_chan, self.vps.len() as u32)
+    pub fn into_params(
+        self,
+    ) -> (
+        mesh::Sender<DebugRequest>,
+        u32,
+        Option<mesh::Sender<inspect::Deferred>>,
+    ) {
+        (self.req_chan, self.vps.len() as u32, self.inspect)
     }
 
     pub fn send_req(&mut self, req: DebugRequest) {
@@ -63,7 +77,7 @@ pub fn vp_to_tid(&self, vp: u32) -> Tid {
         NonZeroUsize::new(vp as usize + 1).unwrap()
     }
 
-    #[expect(dead_code)] // TODO: add monitor command to inspect physical memory?
+    /// Reads `data.len()` bytes from guest physical address `gpa`.
     fn read_guest_physical_memory(&mut self, gpa: u64, data: &mut [u8]) -> anyhow::Result<()> {
         let buf = block_on(self.req_chan.call_failable(
             DebugRequest::ReadMemory,
@@ -77,6 +91,43 @@ fn read_guest_physical_memory(&mut self, gpa: u64, data: &mut [u8]) -> anyhow::R
         Ok(())
     }
 
+    /// Writes `data` to guest physical address `gpa`.
+    fn write_guest_physical_memory(&mut self, gpa: u64, data: &[u8]) -> anyhow::Result<()> {
+        block_on(self.req_chan.call_failable(
+            DebugRequest::WriteMemory,
+            (GuestAddress::Gpa(gpa), data.to_vec()),
+        ))
+        .context("failed to write memory")?;
+        Ok(())
+    }
+
+    /// Translates guest VP `vp_index`'s virtual address `gva` to a physical
+    /// address.
+    fn translate_gva(&mut self, vp_index: u32, gva: u64) -> anyhow::Result<u64> {
+        block_on(
+            self.req_chan
+                .call_failable(DebugRequest::TranslateGva, (vp_index, gva)),
+        )
+        .context("failed to translate address")
+    }
+
+    /// Inspects the VM at `path`, to `depth` levels below it.
+    fn inspect(&mut self, path: &str, depth: usize) -> anyhow::Result<inspect::Node> {
+        let send = self.inspect.as_ref().context("inspect is not available")?;
+        let obj = inspect::adhoc_mut(|req| send.send(req.defer()));
+        let mut inspection = inspect::InspectionBuilder::new(path)
+            .depth(Some(depth))
+            .inspect(obj);
+        // Inspecting this worker from here would wait on itself, so bound the
+        // time spent waiting for the results.
+        let _ = block_on(
+            mesh::CancelContext::new()
+                .with_timeout(Duration::from_secs(1))
+                .until_cancelled(inspection.resolve()),
+        );
+        Ok(inspection.results())
+    }
+
     /// Reads `len` bytes from guest VP `vp_index`'s virtual address `gva`.
     fn read_guest_virtual_memory(
         &mut self,
@@ -12,6 +12,7 @@
 
 mod base;
 mod breakpoints;
+mod monitor;
 mod target_aarch64;
 mod target_i8086;
 mod target_x86_64_qemu;
@@ -147,6 +148,13 @@ fn support_breakpoints(
         Some(self)
     }
 
+    #[inline(always)]
+    fn support_monitor_cmd(
+        &mut self,
+    ) -> Option<gdbstub::target::ext::monitor_cmd::MonitorCmdOps<'_, Self>> {
+        Some(self)
+    }
+
     // We can rely on the GDB client overwrite the guest instruction stream when setting
     // software breakpoints. No need to reimplement that logic inside our stub.
     // NOTE: (8/20/2024) WinDbg's GDB client does not support this mode, and sents explicit sw breakpoint requests to the stub
@@ -0,0 +1,298 @@
+// Copyright (c) Microsoft Corporation.
+// Licensed under the MIT License.
+
+//! `monitor` commands, for VM state that GDB has no native commands for.
+
+use super::TargetArch;
+use super::VmTarget;
+use crate::gdb::VmProxy;
+use anyhow::Context;
+use futures::executor::block_on;
+use gdbstub::outputln;
+use gdbstub::target::ext::monitor_cmd::ConsoleOutput;
+use gdbstub::target::ext::monitor_cmd::MonitorCmd;
+use mesh::rpc::RpcSend;
+use std::fmt::Write;
+use vmm_core_defs::debug_rpc::DebugRequest;
+use vmm_core_defs::debug_rpc::DebuggerVpState;
+
+const HELP: &str = "\
+phys <gpa> [<len>]          read guest physical memory
+phys-write <gpa> <hex>      write bytes to guest physical memory
+translate <gva> [<vp>]      translate a virtual address through the page tables
+regs [<vp>]                 show control and system registers
+inspect [<path>] [<depth>]  inspect the VM
+Numbers are decimal, or hexadecimal with a 0x prefix. <vp> defaults to 0.";
+
+const DEFAULT_READ_LEN: u64 = 64;
+const MAX_READ_LEN: u64 = 4096;
+
+#[derive(Debug, PartialEq, Eq)]
+enum Command<'a> {
+    Help,
+    ReadPhys { gpa: u64, len: usize },
+    WritePhys { gpa: u64, data: Vec<u8> },
+    Translate { gva: u64, vp: u32 },
+    Registers { vp: u32 },
+    Inspect { path: &'a str, depth: usize },
+}
+
+fn parse_number(s: &str) -> anyhow::Result<u64> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .with_context(|| format!("invalid number: {s}"))
+}
+
+fn parse_hex_bytes(s: &str) -> anyhow::Result<Vec<u8>> {
+    let s = s.strip_prefix("0x").unwrap_or(s);
+    if s.is_empty() || s.len() % 2 != 0 {
+        anyhow::bail!("expected an even number of hex digits: {s}");
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|b| u8::from_str_radix(b, 16).ok())
+                .with_context(|| format!("invalid hex bytes: {s}"))
+        })
+        .collect()
+}
+
+fn parse_command(cmd: &str) -> anyhow::Result<Command<'_>> {
+    let words = cmd.split_whitespace().collect::<Vec<_>>();
+    let Some((&name, args)) = words.split_first() else {
+        return Ok(Command::Help);
+    };
+    let max_args = match name {
+        "help" => 0,
+        "phys" | "phys-write" | "translate" | "inspect" => 2,
+        "regs" => 1,
+        _ => anyhow::bail!("unknown command: {name}"),
+    };
+    if args.len() > max_args {
+        anyhow::bail!("too many arguments for {name}");
+    }
+    let required = |i: usize, what: &str| {
+        args.get(i)
+            .copied()
+            .with_context(|| format!("missing {what}"))
+    };
+    let optional = |i: usize| args.get(i).map(|s| parse_number(s)).transpose();
+    let vp = |i: usize| -> anyhow::Result<u32> {
+        optional(i)?
+            .unwrap_or(0)
+            .try_into()
+            .context("invalid vp index")
+    };
+
+    let command = match name {
+        "help" => Command::Help,
+        "phys" => {
+            let len = optional(1)?.unwrap_or(DEFAULT_READ_LEN);
+            if len > MAX_READ_LEN {
+                anyhow::bail!("length must be at most {MAX_READ_LEN:#x}");
+            }
+            Command::ReadPhys {
+                gpa: parse_number(required(0, "address")?)?,
+                len: len as usize,
+            }
+        }
+        "phys-write" => Command::WritePhys {
+            gpa: parse_number(required(0, "address")?)?,
+            data: parse_hex_bytes(required(1, "data")?)?,
+        },
+        "translate" => Command::Translate {
+            gva: parse_number(required(0, "address")?)?,
+            vp: vp(1)?,
+        },
+        "regs" => Command::Registers { vp: vp(0)? },
+        "inspect" => Command::Inspect {
+            path: args.first().copied().unwrap_or(""),
+            depth: optional(1)?.unwrap_or(0) as usize,
+        },
+        _ => unreachable!(),
+    };
+    Ok(command)
+}
+
+fn hex_dump(out: &mut ConsoleOutput<'_>, address: u64, data: &[u8]) {
+    for (i, line) in data.chunks(16).enumerate() {
+        let mut hex = String::new();
+        for b in line {
+            let _ = write!(hex, "{b:02x} ");
+        }
+        let ascii = line
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect::<String>();
+        outputln!(out, "{:016x}  {hex:<48} {ascii}", address + i as u64 * 16);
+    }
+}
+
+fn print_registers(out: &mut ConsoleOutput<'_>, state: &DebuggerVpState) {
+    match state {
+        DebuggerVpState::X86_64(state) => {
+            for (name, value) in [
+                ("rip", state.rip),
+                ("rflags", state.rflags),
+                ("cr0", state.cr0),
+                ("cr2", state.cr2),
+                ("cr3", state.cr3),
+                ("cr4", state.cr4),
+                ("cr8", state.cr8),
+                ("efer", state.efer),
+                ("kernel_gs_base", state.kernel_gs_base),
+            ] {
+                outputln!(out, "{name:<16}{value:#018x}");
+            }
+            for (name, seg) in [
+                ("cs", &state.cs),
+                ("ss", &state.ss),
+                ("ds", &state.ds),
+                ("es", &state.es),
+                ("fs", &state.fs),
+                ("gs", &state.gs),
+            ] {
+                outputln!(
+                    out,
+                    "{name:<16}selector={:#06x} base={:#018x} limit={:#010x} attributes={:#06x}",
+                    seg.selector,
+                    seg.base,
+                    seg.limit,
+                    seg.attributes
+                );
+            }
+        }
+        DebuggerVpState::Aarch64(state) => {
+            for (name, value) in [
+                ("pc", state.pc),
+                ("cpsr", state.cpsr),
+                ("sp_el0", state.sp_el0),
+                ("sp_el1", state.sp_el1),
+                ("sctlr_el1", state.sctlr_el1),
+                ("tcr_el1", state.tcr_el1),
+                ("ttbr0_el1", state.ttbr0_el1),
+                ("ttbr1_el1", state.ttbr1_el1),
+            ] {
+                outputln!(out, "{name:<16}{value:#018x}");
+            }
+        }
+    }
+}
+
+impl VmProxy {
+    fn run_monitor_command(
+        &mut self,
+        command: Command<'_>,
+        out: &mut ConsoleOutput<'_>,
+    ) -> anyhow::Result<()> {
+        match command {
+            Command::Help => outputln!(out, "{HELP}"),
+            Command::ReadPhys { gpa, len } => {
+                let mut data = vec![0; len];
+                self.read_guest_physical_memory(gpa, &mut data)?;
+                hex_dump(out, gpa, &data);
+            }
+            Command::WritePhys { gpa, data } => {
+                self.write_guest_physical_memory(gpa, &data)?;
+                outputln!(out, "wrote {} bytes at {gpa:#x}", data.len());
+            }
+            Command::Translate { gva, vp } => {
+                self.check_vp(vp)?;
+                let gpa = self.translate_gva(vp, gva)?;
+                outputln!(out, "{gva:#x} -> {gpa:#x}");
+            }
+            Command::Registers { vp } => {
+                self.check_vp(vp)?;
+                let state = block_on(self.req_chan.call_failable(DebugRequest::GetVpState, vp))
+                    .context("failed to get vp state")?;
+                print_registers(out, &state);
+            }
+            Command::Inspect { path, depth } => {
+                let node = self.inspect(path, depth)?;
+                outputln!(out, "{node:#}");
+            }
+        }
+        Ok(())
+    }
+
+    fn check_vp(&self, vp: u32) -> anyhow::Result<()> {
+        if vp as usize >= self.vps.len() {
+            anyhow::bail!("vp {vp} does not exist");
+        }
+        Ok(())
+    }
+}
+
+impl<T: TargetArch> MonitorCmd for VmTarget<'_, T> {
+    fn handle_monitor_cmd(
+        &mut self,
+        cmd: &[u8],
+        mut out: ConsoleOutput<'_>,
+    ) -> Result<(), Self::Error> {
+        // Errors are reported to the user rather than to gdbstub, which would
+        // treat them as fatal.
+        let result = std::str::from_utf8(cmd)
+            .context("command is not valid utf-8")
+            .and_then(parse_command)
+            .and_then(|command| self.0.run_monitor_command(command, &mut out));
+        if let Err(err) = result {
+            outputln!(out, "error: {err:#}");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Command;
+    use super::parse_command;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_command("").unwrap(), Command::Help);
+        assert_eq!(
+            parse_command("phys 0x1000").unwrap(),
+            Command::ReadPhys {
+                gpa: 0x1000,
+                len: 64
+            }
+        );
+        assert_eq!(
+            parse_command("phys-write 4096 0xdeadbeef").unwrap(),
+            Command::WritePhys {
+                gpa: 0x1000,
+                data: vec![0xde, 0xad, 0xbe, 0xef]
+            }
+        );
+        assert_eq!(
+            parse_command("translate 0xfffff80000000000 3").unwrap(),
+            Command::Translate {
+                gva: 0xfffff80000000000,
+                vp: 3
+            }
+        );
+        assert_eq!(parse_command("regs").unwrap(), Command::Registers { vp: 0 });
+        assert_eq!(
+            parse_command("inspect vm/chipset 2").unwrap(),
+            Command::Inspect {
+                path: "vm/chipset",
+                depth: 2
+            }
+        );
+
+        assert!(parse_command("phys").is_err());
+        assert!(parse_command("phys 0 0x1001").is_err());
+        assert!(parse_command("phys-write 0 abc").is_err());
+        assert!(parse_command("regs 0 1").is_err());
+        assert!(parse_command("bogus").is_err());
+    }
+}
@@ -18,6 +18,8 @@ pub struct DebuggerParameters<T> {
     pub req_chan: mesh::Sender<DebugRequest>,
     pub vp_count: u32,
     pub target_arch: TargetArch,
+    /// Channel for inspecting the VM from the `monitor inspect` command.
+    pub inspect: Option<mesh::Sender<inspect::Deferred>>,
 }
 
 #[derive(Debug, Copy, Clone, Protobuf)]